@@ -20,6 +20,9 @@ pub mod util;
 // ---------------------------------------------------------- //
 
 pub type PlayerId = u32;
+/// Persistent identity of a player, verified by the server when joining. Unlike [`PlayerId`], stays the same
+/// across sessions.
+pub type PlayerIdentity = String;
 pub type ServerId = u32;
 
 // ---------------------------------------------------------- //
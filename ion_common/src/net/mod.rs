@@ -1,13 +1,26 @@
 use std::fmt::{self, Display};
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
 use std::{collections::HashMap, fmt::Debug};
 
-use bincode::{Decode, Encode};
+use bincode::{Decode, Encode, config};
+use blake2::digest::Mac;
+use blake2::{Blake2s256, Blake2sMac256, Digest};
 
-use crate::{PlayerId, ServerId};
+use crate::{Instant, PlayerId, PlayerIdentity, ServerId};
 
+pub mod rate_limit;
 pub mod tcp_network_socket;
+pub mod tls;
+pub mod transport;
 pub mod udp_network_socket;
+pub mod ws_network_socket;
+
+// After this many failed attempts in a row, an IP is locked out until this long has passed since the last one
+const MAX_AUTH_FAILURES: u32 = 5;
+const AUTH_LOCKOUT: Duration = Duration::from_secs(60);
+// Lockouts that have passed are cleaned up once this many IPs have failed
+const MAX_AUTH_FAILURE_IPS: usize = 1024;
 
 // ---------------------------------------------------------- //
 // --------------- Player and Server types ------------------ //
@@ -18,6 +31,8 @@ pub struct NetworkPlayerInfo {
     pub id: PlayerId,
     pub name: String,
     pub addr: SocketAddr,
+    /// Set by the server when the player joins, if the server authenticates players.
+    pub identity: Option<PlayerIdentity>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Encode, Decode)]
@@ -25,11 +40,27 @@ pub struct NetworkServerInfo {
     pub id: ServerId,
     pub name: String,
     pub addr: SocketAddr,
+    /// Address of the server in the other address family, if it can be reached over both IPv4 and IPv6.
+    /// Dual stack clients try IPv6 first and fall back to IPv4 if the server does not answer.
+    pub alt_addr: Option<SocketAddr>,
     pub is_global: bool,
     pub has_password: bool,
     pub description: String,
     pub cur_player_count: u32,
     pub max_player_count: u32,
+    /// Coarse region of the server, such as "eu", set by the host from the address of the server. Servers the host
+    /// has no region for get the region of the host itself.
+    pub region: Option<String>,
+    /// Host name of the server that browser builds connect to its WebSocket bridge with over TLS, such as
+    /// `eu1.example.com`. The certificate of the bridge has to be for it, see
+    /// [`WsNetworkListener::with_tls`](ws_network_socket::WsNetworkListener::with_tls). Browsers connect to the address
+    /// instead if there is none.
+    pub ws_host: Option<String>,
+    /// Public key of the udp socket of the server, set by the server when it starts. Clients pin it, so that nobody
+    /// between them and the server can read or change their traffic, see
+    /// [`UdpNetworkSocket::pin_key`](udp_network_socket::UdpNetworkSocket::pin_key). This only helps if the info came
+    /// through a trusted channel, as whoever can change the info can change the key too.
+    pub network_key: Option<[u8; 32]>,
 }
 
 // ---------------------------------------------------------- //
@@ -48,15 +79,335 @@ where
 #[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
 pub enum SysMessage {
     SocketInfoReq,
-    SocketInfoRes { addr: SocketAddr },
+    SocketInfoRes {
+        addr: SocketAddr,
+    },
     ServerInfoReq,
-    ServerInfoResGlobal { servers: Vec<NetworkServerInfo> },
-    ServerInfoResLocal { server: NetworkServerInfo },
-    ServerInfoPost { server: NetworkServerInfo },
+    ServerInfoResGlobal {
+        servers: Vec<NetworkServerInfo>,
+    },
+    ServerInfoResLocal {
+        server: NetworkServerInfo,
+    },
+    ServerInfoPost {
+        server: NetworkServerInfo,
+    },
     ServerInfoDelete,
-    NatPunchRelay { to: SocketAddr },
-    NatPunchStart { to: SocketAddr },
+    /// Sent by registered servers now and then to stay in the server list, see [`ServerInfoPost`](Self::ServerInfoPost).
+    ServerHeartbeat,
+    /// Sent by the host to the alternative address a server posted, answered with a
+    /// [`ServerAltAddrConfirm`](Self::ServerAltAddrConfirm) over that address. The host lists the alternative address
+    /// only once it is confirmed, so that servers can't claim addresses that aren't theirs.
+    ServerAltAddrCheck {
+        cookie: u64,
+    },
+    ServerAltAddrConfirm {
+        cookie: u64,
+    },
+    NatPunchRelay {
+        to: SocketAddr,
+    },
+    NatPunchStart {
+        to: SocketAddr,
+    },
     NatPunchPing,
+    Ping,
+    Pong,
+    /// Asks the host for its region and the other hosts it shares the server list with, answered with a
+    /// [`HostInfoRes`](Self::HostInfoRes).
+    HostInfoReq,
+    HostInfoRes {
+        region: Option<String>,
+        peers: Vec<SocketAddr>,
+    },
+    /// Message from a host to the other hosts it shares the server list with.
+    HostPeerMessage {
+        message: SignedPeerMessage,
+    },
+    /// Sent by the host to the registered servers and queued players when it shuts down.
+    HostShutdown,
+    /// Asks the host whether it is running fine, answered with a [`HostStatusRes`](Self::HostStatusRes).
+    HostStatusReq,
+    HostStatusRes {
+        status: HostStatus,
+    },
+    /// Command from an operator of the host services, answered with a [`HostAdminRes`](Self::HostAdminRes).
+    HostAdminReq {
+        token: Vec<u8>,
+        command: HostAdminCommand,
+    },
+    HostAdminRes {
+        result: Result<String, String>,
+    },
+    /// Queues the player for a match, answered with a [`MatchQueued`](Self::MatchQueued) and later a
+    /// [`MatchFound`](Self::MatchFound).
+    MatchReq {
+        player: NetworkPlayerInfo,
+        criteria: MatchCriteria,
+    },
+    MatchCancel,
+    MatchQueued {
+        position: u32,
+    },
+    MatchFound {
+        assignment: MatchAssignment,
+    },
+    StatSubmit {
+        submission: StatSubmission,
+    },
+    /// Asks for the best players of the stat, answered with a [`LeaderboardRes`](Self::LeaderboardRes).
+    LeaderboardReq {
+        stat: String,
+        count: u32,
+    },
+    LeaderboardRes {
+        stat: String,
+        entries: Vec<LeaderboardEntry>,
+    },
+    /// Asks for all stats of the player, answered with a [`PlayerStatsRes`](Self::PlayerStatsRes).
+    PlayerStatsReq {
+        player: PlayerIdentity,
+    },
+    PlayerStatsRes {
+        player: PlayerIdentity,
+        stats: Vec<(String, i64)>,
+    },
+    /// Asks the host to relay traffic to the peer, answered to both peers with a [`RelayOpened`](Self::RelayOpened)
+    /// or to the asking one with a [`RelayRefused`](Self::RelayRefused).
+    RelayOpen {
+        peer: SocketAddr,
+    },
+    /// The token identifies the session in the relayed data, and is only known to the peers and the host.
+    RelayOpened {
+        token: u64,
+        peer: SocketAddr,
+    },
+    RelayRefused {
+        peer: SocketAddr,
+        reason: String,
+    },
+    /// Data to forward to the other peer of the session, or forwarded from it.
+    RelayData {
+        token: u64,
+        data: Vec<u8>,
+        reliable: bool,
+    },
+    /// Sent by either peer to end the session, and by the host to the other peer.
+    RelayClose {
+        token: u64,
+    },
+}
+
+/// Players are only matched with players that have the same criteria.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Encode, Decode)]
+pub struct MatchCriteria {
+    pub game_mode: String,
+    pub region: String,
+    pub skill_bucket: u32,
+    /// Players in each match.
+    pub match_size: u32,
+}
+
+/// Where the players of a match play, see [`SysMessage::MatchFound`].
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum MatchAssignment {
+    /// All players join the server.
+    Server { server: NetworkServerInfo },
+    /// This player hosts the match, and should start a server that the other players join.
+    /// The host service has already sent it a nat punch for each of the players.
+    Host { players: Vec<NetworkPlayerInfo> },
+    /// This player joins the player who hosts the match, at the address the host service sees for it.
+    Client { host: NetworkPlayerInfo },
+}
+
+/// Command for the host services, so that operators can intervene without restarting the host.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum HostAdminCommand {
+    ListServers,
+    /// Removes the server from the server list, until it posts its info again.
+    EvictServer {
+        addr: SocketAddr,
+    },
+    /// Lists the nat punch relays that are still being resent.
+    ListNatPunches,
+    /// Lists the traffic relay sessions with the data relayed in each.
+    ListRelays,
+    SetLimits {
+        max_requests_per_sec: u32,
+        max_request_burst: u32,
+        max_servers_per_ip: u32,
+    },
+}
+
+/// State of the host services, for load balancers and uptime monitors. Updated by the host about once a second.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct HostStatus {
+    pub version: String,
+    pub uptime_secs: u64,
+    pub region: Option<String>,
+    /// Whether the host is shutting down, and no longer accepts registrations.
+    pub is_draining: bool,
+    /// Servers registered at this host.
+    pub server_count: u32,
+    /// Servers registered at the peer hosts that this host lists too.
+    pub peer_server_count: u32,
+    pub queued_player_count: u32,
+    pub relay_session_count: u32,
+    /// Public key of the udp socket of the host, that admin clients pin, see
+    /// [`UdpNetworkSocket::pin_key`](udp_network_socket::UdpNetworkSocket::pin_key).
+    pub network_key: [u8; 32],
+}
+
+// ---------------------------------------------------------- //
+// ------------------------ Secrets ------------------------- //
+// ---------------------------------------------------------- //
+
+/// Whether the token is the secret, such as a password sent by a client.
+pub fn secret_matches(token: &[u8], secret: &[u8]) -> bool {
+    // Compares macs of the secrets, so the comparison takes the same time wherever they differ
+    let key: [u8; 32] = Blake2s256::digest(secret).into();
+    let mac = |secret: &[u8]| Blake2sMac256::new_from_slice(&key).unwrap().chain_update(secret);
+    mac(token).verify_slice(&mac(secret).finalize().into_bytes()).is_ok()
+}
+
+/// Failed attempts to authenticate, by IP, so that a password can't be guessed by trying many.
+#[derive(Default)]
+pub struct AuthLockout {
+    // Default HashMap is used instead of faster 'Map' from this crate, as the addresses are untrusted inputs
+    failures: HashMap<IpAddr, (u32, Instant)>,
+}
+
+impl AuthLockout {
+    pub fn new() -> Self {
+        Self {
+            failures: HashMap::new(),
+        }
+    }
+
+    /// Whether attempts from the IP should be refused without checking them.
+    pub fn is_locked_out(&self, ip: IpAddr, now: Instant) -> bool {
+        self.failures.get(&ip).is_some_and(|(failures, last_failure)| {
+            *failures >= MAX_AUTH_FAILURES && *last_failure + AUTH_LOCKOUT > now
+        })
+    }
+
+    /// Counts a failed attempt from the IP. Returns true if the IP got locked out by it.
+    pub fn record_failure(&mut self, ip: IpAddr, now: Instant) -> bool {
+        if self.failures.len() >= MAX_AUTH_FAILURE_IPS {
+            self.failures
+                .retain(|_, (_, last_failure)| *last_failure + AUTH_LOCKOUT > now);
+        }
+        let (failures, last_failure) = self.failures.entry(ip).or_insert((0, now));
+        // Failures long ago don't count towards a lockout
+        if *last_failure + AUTH_LOCKOUT <= now {
+            *failures = 0;
+        }
+        *failures += 1;
+        *last_failure = now;
+        *failures == MAX_AUTH_FAILURES
+    }
+
+    /// Forgets the failed attempts of the IP, after it has authenticated.
+    pub fn clear(&mut self, ip: IpAddr) {
+        self.failures.remove(&ip);
+    }
+}
+
+// ---------------------------------------------------------- //
+// ------------------------- Stats -------------------------- //
+// ---------------------------------------------------------- //
+
+/// Value of a stat for a player, signed with a key shared by the game servers and the host.
+/// Clients never get the key, so they can't submit stats for themselves.
+///
+/// The host keeps the highest value of each stat for each player, so sending the same submission again changes nothing.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct StatSubmission {
+    pub stat: String,
+    pub player: PlayerIdentity,
+    pub value: i64,
+    mac: [u8; 32],
+}
+
+impl StatSubmission {
+    pub fn new(key: &[u8], stat: &str, player: &str, value: i64) -> Self {
+        Self {
+            stat: stat.to_owned(),
+            player: player.to_owned(),
+            value,
+            mac: Self::mac(key, stat, player, value).finalize().into_bytes().into(),
+        }
+    }
+
+    /// Whether the submission was signed with the key and has not been changed since.
+    pub fn verify(&self, key: &[u8]) -> bool {
+        Self::mac(key, &self.stat, &self.player, self.value)
+            .verify_slice(&self.mac)
+            .is_ok()
+    }
+
+    fn mac(key: &[u8], stat: &str, player: &str, value: i64) -> Blake2sMac256 {
+        let key: [u8; 32] = Blake2s256::digest(key).into();
+        let signed = bincode::encode_to_vec((stat, player, value), config::standard()).unwrap();
+        Blake2sMac256::new_from_slice(&key).unwrap().chain_update(signed)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct LeaderboardEntry {
+    /// Players with the same value share a rank, starting from 1.
+    pub rank: u32,
+    pub player: PlayerIdentity,
+    pub value: i64,
+}
+
+// ---------------------------------------------------------- //
+// ---------------------- Peer hosts ------------------------ //
+// ---------------------------------------------------------- //
+
+/// Message between hosts that share the server list.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum PeerMessage {
+    /// Servers registered at the sending host.
+    ServerSync { servers: Vec<NetworkServerInfo> },
+    /// Nat punch relay for a server registered at the receiving host.
+    NatPunchForward { from: SocketAddr, to: SocketAddr },
+    /// The sending host is shutting down.
+    Shutdown,
+}
+
+/// [`PeerMessage`] signed with a secret shared by the hosts, as the source address of a UDP packet can be spoofed.
+/// The signature covers the time the message was sent, so that receivers can refuse replayed messages.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct SignedPeerMessage {
+    pub message: PeerMessage,
+    /// Unix time in milliseconds. Unique among the messages of the sender.
+    pub sent_at_ms: u64,
+    mac: [u8; 32],
+}
+
+impl SignedPeerMessage {
+    pub fn new(secret: &[u8], message: PeerMessage, sent_at_ms: u64) -> Self {
+        let mac = Self::mac(secret, &message, sent_at_ms).finalize().into_bytes().into();
+        Self {
+            message,
+            sent_at_ms,
+            mac,
+        }
+    }
+
+    /// Whether the message was signed with the secret and has not been changed since.
+    pub fn verify(&self, secret: &[u8]) -> bool {
+        Self::mac(secret, &self.message, self.sent_at_ms)
+            .verify_slice(&self.mac)
+            .is_ok()
+    }
+
+    fn mac(secret: &[u8], message: &PeerMessage, sent_at_ms: u64) -> Blake2sMac256 {
+        let key: [u8; 32] = Blake2s256::digest(secret).into();
+        let signed = bincode::encode_to_vec((message, sent_at_ms), config::standard()).unwrap();
+        Blake2sMac256::new_from_slice(&key).unwrap().chain_update(signed)
+    }
 }
 
 // ---------------------------------------------------------- //
@@ -97,11 +448,15 @@ pub struct HttpResponse {
 
 #[cfg(test)]
 mod tests {
-    use std::net::SocketAddr;
+    use std::net::{IpAddr, SocketAddr};
 
     use bincode::{Decode, Encode, config};
 
-    use crate::net::{NetworkPlayerInfo, NetworkServerInfo, SysMessage, UdpMessage};
+    use crate::Instant;
+    use crate::net::{
+        AUTH_LOCKOUT, AuthLockout, MAX_AUTH_FAILURES, NetworkPlayerInfo, NetworkServerInfo, PeerMessage,
+        SignedPeerMessage, StatSubmission, SysMessage, UdpMessage, secret_matches,
+    };
 
     #[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
     struct TestStruct {
@@ -109,12 +464,46 @@ mod tests {
         data: Vec<u16>,
     }
 
+    #[test]
+    fn secret_matches_only_the_same_secret() {
+        assert!(secret_matches(b"hunter2", b"hunter2"));
+        assert!(!secret_matches(b"hunter3", b"hunter2"));
+        assert!(!secret_matches(b"hunter", b"hunter2"));
+        assert!(!secret_matches(b"", b"hunter2"));
+    }
+
+    #[test]
+    fn repeated_failures_lock_the_ip_out_for_a_while() {
+        let ip = IpAddr::from([127, 0, 0, 1]);
+        let other_ip = IpAddr::from([127, 0, 0, 2]);
+        let now = Instant::now();
+        let mut lockout = AuthLockout::new();
+
+        for _ in 1..MAX_AUTH_FAILURES {
+            assert!(!lockout.record_failure(ip, now));
+            assert!(!lockout.is_locked_out(ip, now));
+        }
+        assert!(lockout.record_failure(ip, now));
+        assert!(lockout.is_locked_out(ip, now));
+        assert!(!lockout.is_locked_out(other_ip, now));
+        assert!(!lockout.is_locked_out(ip, now + AUTH_LOCKOUT));
+
+        // Failures after the lockout start counting again
+        assert!(!lockout.record_failure(ip, now + AUTH_LOCKOUT));
+        assert!(!lockout.is_locked_out(ip, now + AUTH_LOCKOUT));
+
+        lockout.record_failure(other_ip, now);
+        lockout.clear(other_ip);
+        assert!(lockout.failures.get(&other_ip).is_none());
+    }
+
     #[test]
     fn can_serialize_and_deserialize_network_player_info() {
         let player_info = NetworkPlayerInfo {
             id: 123,
             name: "test-name".to_owned(),
             addr: SocketAddr::from(([1, 1, 1, 1], 1234)),
+            identity: Some("test-identity".to_owned()),
         };
 
         let bytes = bincode::encode_to_vec(&player_info, config::standard()).unwrap();
@@ -129,11 +518,15 @@ mod tests {
             id: 123,
             name: "test-name".to_owned(),
             addr: SocketAddr::from(([1, 1, 1, 1], 1234)),
+            alt_addr: Some(SocketAddr::from(([1, 0, 0, 0, 0, 0, 0, 1], 1234))),
             is_global: false,
             has_password: false,
             description: "desc".to_owned(),
             cur_player_count: 3,
             max_player_count: 8,
+            region: Some("eu".to_owned()),
+            ws_host: None,
+            network_key: Some([7; 32]),
         };
 
         let bytes = bincode::encode_to_vec(&server_info, config::standard()).unwrap();
@@ -162,4 +555,26 @@ mod tests {
             UdpMessage::MpMessage(_) => panic!("Got wrong type"),
         }
     }
+
+    #[test]
+    fn stat_submissions_are_verified() {
+        let submission = StatSubmission::new(b"stats-key", "wins", "player", 12);
+        assert!(submission.verify(b"stats-key"));
+        assert!(!submission.verify(b"other-key"));
+
+        let mut changed = submission.clone();
+        changed.value = 13;
+        assert!(!changed.verify(b"stats-key"));
+    }
+
+    #[test]
+    fn peer_messages_are_verified() {
+        let signed = SignedPeerMessage::new(b"secret", PeerMessage::Shutdown, 1000);
+        assert!(signed.verify(b"secret"));
+        assert!(!signed.verify(b"other-secret"));
+
+        let mut changed = signed.clone();
+        changed.sent_at_ms = 2000;
+        assert!(!changed.verify(b"secret"));
+    }
 }
@@ -0,0 +1,110 @@
+use std::{
+    cell::{Cell, RefCell},
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use crate::Map;
+
+// Addresses that haven't sent anything for this long are forgotten
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Limits the requests of each IP address with a token bucket.
+/// IPv6 addresses are limited per /64 network, as a single host can usually pick any address within one.
+pub struct RateLimiter {
+    per_sec: Cell<f64>,
+    burst: Cell<f64>,
+    buckets: RefCell<Map<IpAddr, (Instant, f64)>>,
+    last_cleanup: Cell<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(per_sec: u32, burst: u32) -> Self {
+        Self {
+            per_sec: Cell::new(per_sec as f64),
+            burst: Cell::new(burst as f64),
+            buckets: RefCell::new(Map::default()),
+            last_cleanup: Cell::new(Instant::now()),
+        }
+    }
+
+    pub fn set_limits(&self, per_sec: u32, burst: u32) {
+        self.per_sec.set(per_sec as f64);
+        self.burst.set(burst as f64);
+    }
+
+    /// Takes a token from the bucket of the address, returning false if the bucket is empty.
+    pub fn allow(&self, ip: IpAddr, now: Instant) -> bool {
+        let mut buckets = self.buckets.borrow_mut();
+        if self.last_cleanup.get() + IDLE_TIMEOUT < now {
+            buckets.retain(|_, (updated, _)| *updated + IDLE_TIMEOUT > now);
+            self.last_cleanup.set(now);
+        }
+
+        let (updated, tokens) = buckets.entry(rate_limit_key(ip)).or_insert((now, self.burst.get()));
+        let elapsed = now.saturating_duration_since(*updated).as_secs_f64();
+        *tokens = (*tokens + elapsed * self.per_sec.get()).min(self.burst.get());
+        *updated = now;
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Whether the address could be the real source of a request.
+/// Spoofed requests often claim addresses that can't send anything, to get the answers sent to many hosts or nowhere.
+pub fn is_valid_source(addr: SocketAddr) -> bool {
+    let ip = addr.ip().to_canonical();
+    let is_broadcast = match ip {
+        IpAddr::V4(ip) => ip.is_broadcast(),
+        IpAddr::V6(_) => false,
+    };
+    addr.port() != 0 && !ip.is_unspecified() && !ip.is_multicast() && !is_broadcast
+}
+
+fn rate_limit_key(ip: IpAddr) -> IpAddr {
+    match ip.to_canonical() {
+        IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from_bits(ip.to_bits() & !(u64::MAX as u128))),
+        ip => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_are_limited_per_address() {
+        let limiter = RateLimiter::new(10, 3);
+        let now = Instant::now();
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let other_ip: IpAddr = "10.0.0.2".parse().unwrap();
+
+        assert!((0..3).all(|_| limiter.allow(ip, now)));
+        assert!(!limiter.allow(ip, now));
+        assert!(limiter.allow(other_ip, now));
+
+        // Tokens come back over time
+        assert!(limiter.allow(ip, now + Duration::from_millis(100)));
+        assert!(!limiter.allow(ip, now + Duration::from_millis(100)));
+
+        // Addresses of the same IPv6 network share a bucket
+        let ip_v6: IpAddr = "2001:db8::1".parse().unwrap();
+        let other_ip_v6: IpAddr = "2001:db8::2".parse().unwrap();
+        assert!((0..3).all(|_| limiter.allow(ip_v6, now)));
+        assert!(!limiter.allow(other_ip_v6, now));
+    }
+
+    #[test]
+    fn invalid_sources_are_detected() {
+        assert!(is_valid_source("10.0.0.1:3000".parse().unwrap()));
+        assert!(is_valid_source("[::ffff:10.0.0.1]:3000".parse().unwrap()));
+        assert!(!is_valid_source("10.0.0.1:0".parse().unwrap()));
+        assert!(!is_valid_source("0.0.0.0:3000".parse().unwrap()));
+        assert!(!is_valid_source("255.255.255.255:3000".parse().unwrap()));
+        assert!(!is_valid_source("[::ffff:224.0.0.1]:3000".parse().unwrap()));
+    }
+}
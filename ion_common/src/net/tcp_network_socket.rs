@@ -1,43 +1,80 @@
-use std::io::{self, Write};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
 use std::net::{SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
 
+use super::tls::{TlsClientConfig, TlsStream};
 use super::{HttpRequest, HttpResponse};
 
-pub struct TcpNetworkSocket {}
+// Applies to connecting and to each read and write
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+// Whole response, however slowly the server sends it
+const HTTP_RESPONSE_TIMEOUT: Duration = Duration::from_secs(120);
+const HTTP_MAX_RESPONSE_LEN: usize = 256 * 1024 * 1024;
+
+pub struct TcpNetworkSocket {
+    // Host name the server must have a certificate for, and the authorities the certificate is checked against
+    tls: Option<(String, TlsClientConfig)>,
+}
 
 impl TcpNetworkSocket {
+    /// Sends requests unencrypted, for servers on a private network.
     pub fn new() -> Self {
-        Self {}
+        Self { tls: None }
+    }
+
+    /// Sends requests over TLS, to a server that has a certificate for the host name.
+    pub fn with_tls(server_name: &str, tls: TlsClientConfig) -> Self {
+        Self {
+            tls: Some((server_name.to_owned(), tls)),
+        }
     }
 
-    pub fn send_http_request(
-        &self,
-        to: SocketAddr,
-        request: HttpRequest,
-    ) -> io::Result<HttpResponse> {
-        let mut socket = TcpStream::connect(to)?;
-        let request_bytes = self.http_request_to_bytes(request);
+    /// Sends the request and waits for the whole response.
+    /// The url of the request is the target on the server, such as `/saves`. The connection is closed afterwards.
+    /// Chunked responses are not supported, so the server must send `Content-Length` or close the connection.
+    /// Responses larger than 256 MiB, or that take longer than two minutes in total, fail.
+    pub fn send_http_request(&self, to: SocketAddr, request: HttpRequest) -> io::Result<HttpResponse> {
+        let socket = TcpStream::connect_timeout(&to, HTTP_TIMEOUT)?;
+        socket.set_read_timeout(Some(HTTP_TIMEOUT))?;
+        socket.set_write_timeout(Some(HTTP_TIMEOUT))?;
+        let mut socket = match &self.tls {
+            Some((server_name, tls)) => TlsStream::connect(socket, server_name, tls)?,
+            None => TlsStream::Plain(socket),
+        };
+        let request_bytes = self.http_request_to_bytes(to, request);
         socket.write_all(&request_bytes)?;
+        socket.flush()?;
 
-        todo!()
+        let response_bytes = read_http_response(&mut socket, Instant::now() + HTTP_RESPONSE_TIMEOUT)?;
+        parse_http_response(&response_bytes)
     }
 
-    fn http_request_to_bytes(&self, request: HttpRequest) -> Vec<u8> {
+    fn http_request_to_bytes(&self, to: SocketAddr, request: HttpRequest) -> Vec<u8> {
         let mut request_str = String::new();
         request_str.push_str(&request.method.to_string());
+        request_str.push(' ');
         request_str.push_str(&request.url);
-        request_str.push_str("HTTP/1.1\r\n");
+        request_str.push_str(" HTTP/1.1\r\n");
 
         // Ensure Host header is present
         if !request.headers.contains_key("Host") {
-            request_str.push_str(&format!("Host: {}\r\n", request.url));
+            match &self.tls {
+                Some((server_name, _)) => request_str.push_str(&format!("Host: {}:{}\r\n", server_name, to.port())),
+                None => request_str.push_str(&format!("Host: {}\r\n", to)),
+            }
         }
 
         // Ensure Content-Length header is present
-        if request.body.len() > 0 && !request.headers.contains_key("Content-Length") {
+        if !request.headers.contains_key("Content-Length") {
             request_str.push_str(&format!("Content-Length: {}\r\n", request.body.len()));
         }
 
+        // The response is read until the server closes the connection
+        if !request.headers.contains_key("Connection") {
+            request_str.push_str("Connection: close\r\n");
+        }
+
         // Add other headers
         for (key, value) in &request.headers {
             request_str.push_str(&format!("{}: {}\r\n", key, value));
@@ -51,3 +88,178 @@ impl TcpNetworkSocket {
         request_bytes
     }
 }
+
+/// Reads until the server closes the connection, failing if the response grows too large or the deadline passes.
+fn read_http_response(socket: &mut impl Read, deadline: Instant) -> io::Result<Vec<u8>> {
+    let mut response_bytes = Vec::new();
+    let mut buffer = [0; 16 * 1024];
+    loop {
+        if Instant::now() > deadline {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "HTTP response took too long"));
+        }
+        let read = match socket.read(&mut buffer) {
+            Ok(0) => return Ok(response_bytes),
+            // Servers often close TLS connections without telling, the length of the body is checked if they send it
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(response_bytes),
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        if response_bytes.len() + read > HTTP_MAX_RESPONSE_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "HTTP response is too large"));
+        }
+        response_bytes.extend_from_slice(&buffer[..read]);
+    }
+}
+
+fn parse_http_response(bytes: &[u8]) -> io::Result<HttpResponse> {
+    let invalid =
+        |reason: &str| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid HTTP response: {}", reason));
+
+    let header_end = bytes
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| invalid("no end of headers"))?;
+    let head = String::from_utf8_lossy(&bytes[..header_end]);
+    let mut lines = head.split("\r\n");
+
+    let status_code = lines
+        .next()
+        .and_then(|status_line| status_line.split_whitespace().nth(1))
+        .and_then(|status_code| status_code.parse().ok())
+        .ok_or_else(|| invalid("no status code"))?;
+
+    let mut headers = HashMap::new();
+    for line in lines {
+        let (key, value) = line.split_once(':').ok_or_else(|| invalid("malformed header"))?;
+        headers.insert(key.trim().to_owned(), value.trim().to_owned());
+    }
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    };
+    if header("Transfer-Encoding").is_some_and(|encoding| encoding.eq_ignore_ascii_case("chunked")) {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Chunked HTTP responses are not supported",
+        ));
+    }
+
+    let mut body = bytes[header_end + 4..].to_vec();
+    if let Some(content_length) = header("Content-Length") {
+        let content_length: usize = content_length
+            .parse()
+            .map_err(|_| invalid("malformed content length"))?;
+        if body.len() < content_length {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "HTTP response body was cut short",
+            ));
+        }
+        body.truncate(content_length);
+    }
+
+    Ok(HttpResponse {
+        status_code,
+        headers,
+        body,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::Read;
+    use std::net::SocketAddr;
+    use std::time::{Duration, Instant};
+
+    use super::{HTTP_MAX_RESPONSE_LEN, TcpNetworkSocket, parse_http_response, read_http_response};
+    use crate::net::tls::tests::test_certificate;
+    use crate::net::tls::{TlsClientConfig, TlsStream};
+    use crate::net::{HttpMethod, HttpRequest};
+
+    #[test]
+    fn http_request_has_request_line_and_required_headers() {
+        let request = HttpRequest {
+            url: "/saves/a".to_owned(),
+            method: HttpMethod::POST,
+            headers: HashMap::from([("X-Test".to_owned(), "1".to_owned())]),
+            body: b"body".to_vec(),
+        };
+        let bytes = TcpNetworkSocket::new().http_request_to_bytes(SocketAddr::from(([127, 0, 0, 1], 80)), request);
+        let text = String::from_utf8(bytes).unwrap();
+
+        assert!(text.starts_with("POST /saves/a HTTP/1.1\r\n"));
+        assert!(text.contains("Host: 127.0.0.1:80\r\n"));
+        assert!(text.contains("Content-Length: 4\r\n"));
+        assert!(text.contains("X-Test: 1\r\n"));
+        assert!(text.ends_with("\r\n\r\nbody"));
+    }
+
+    #[test]
+    fn http_response_is_parsed() {
+        let response =
+            parse_http_response(b"HTTP/1.1 404 Not Found\r\ncontent-length: 5\r\nX-A: b\r\n\r\nmissing").unwrap();
+        assert_eq!(response.status_code, 404);
+        assert_eq!(response.headers.get("X-A").map(String::as_str), Some("b"));
+        assert_eq!(response.body, b"missi");
+
+        assert!(parse_http_response(b"HTTP/1.1 200 OK\r\n").is_err());
+        assert!(parse_http_response(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nshort").is_err());
+    }
+
+    #[test]
+    fn http_response_reading_is_bounded() {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut response: &[u8] = b"HTTP/1.1 200 OK\r\n\r\nbody";
+        assert_eq!(read_http_response(&mut response, deadline).unwrap().len(), 23);
+
+        let mut endless = std::io::repeat(b'a');
+        assert!(read_http_response(&mut endless, Instant::now() - Duration::from_secs(1)).is_err());
+
+        let mut too_large = std::io::repeat(b'a').take(HTTP_MAX_RESPONSE_LEN as u64 + 1);
+        assert!(read_http_response(&mut too_large, deadline).is_err());
+    }
+
+    #[test]
+    fn http_requests_are_sent_over_tls() {
+        let (certificate, cert_file) = test_certificate("http_client");
+        let server_config = certificate.load().unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut stream = TlsStream::accept(stream, Some(&server_config)).unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let len = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..len]);
+            }
+            std::io::Write::write_all(&mut stream, b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok").unwrap();
+            std::io::Write::flush(&mut stream).unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let socket = TcpNetworkSocket::with_tls("localhost", TlsClientConfig::new(Some(&cert_file)).unwrap());
+        let request = HttpRequest {
+            url: "/saves".to_owned(),
+            method: HttpMethod::GET,
+            headers: HashMap::new(),
+            body: Vec::new(),
+        };
+        let response = socket.send_http_request(addr, request).unwrap();
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.body, b"ok");
+        assert!(
+            server
+                .join()
+                .unwrap()
+                .contains(&format!("Host: localhost:{}\r\n", addr.port()))
+        );
+
+        std::fs::remove_dir_all(cert_file.parent().unwrap()).unwrap();
+    }
+}
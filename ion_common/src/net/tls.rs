@@ -0,0 +1,289 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::Arc;
+
+#[cfg(not(target_arch = "wasm32"))]
+use rustls::pki_types::pem::PemObject;
+#[cfg(not(target_arch = "wasm32"))]
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+#[cfg(not(target_arch = "wasm32"))]
+use rustls::{ClientConfig, ClientConnection, RootCertStore, ServerConfig, ServerConnection, StreamOwned};
+
+// ---------------------------------------------------------- //
+// ---------------------- Certificates ---------------------- //
+// ---------------------------------------------------------- //
+
+/// Certificate chain and private key that a TCP endpoint terminates TLS with, both as PEM files, such as the
+/// `fullchain.pem` and `privkey.pem` of Let's Encrypt.
+///
+/// Clients check the certificate against the host name they connect to, so the endpoint has to be reached by a host
+/// name the certificate is for, not by its IP address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsCertificate {
+    pub cert_file: PathBuf,
+    pub key_file: PathBuf,
+}
+
+impl TlsCertificate {
+    pub fn new(cert_file: impl Into<PathBuf>, key_file: impl Into<PathBuf>) -> Self {
+        Self {
+            cert_file: cert_file.into(),
+            key_file: key_file.into(),
+        }
+    }
+
+    /// Reads the certificate and the key, failing if either is missing or invalid. Endpoints load the certificate
+    /// once when they start, so a renewed one is taken into use on restart. Not supported on wasm.
+    pub fn load(&self) -> io::Result<TlsServerConfig> {
+        #[cfg(target_arch = "wasm32")]
+        return Err(tls_unsupported());
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let certs = read_certs(&self.cert_file)?;
+            let key =
+                PrivateKeyDer::from_pem_file(&self.key_file).map_err(|err| invalid_tls_file(&self.key_file, err))?;
+            let config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .map_err(io::Error::other)?
+                .with_no_client_auth()
+                .with_single_cert(certs, key)
+                .map_err(|err| invalid_tls_file(&self.cert_file, err))?;
+            Ok(TlsServerConfig {
+                config: Arc::new(config),
+            })
+        }
+    }
+}
+
+/// Loaded [`TlsCertificate`] that connections are accepted with, see [`TlsStream::accept`].
+#[derive(Clone)]
+pub struct TlsServerConfig {
+    #[cfg(not(target_arch = "wasm32"))]
+    config: Arc<ServerConfig>,
+    #[cfg(target_arch = "wasm32")]
+    never: std::convert::Infallible,
+}
+
+/// Certificate authorities that connections to servers are checked against, see [`TlsStream::connect`].
+#[derive(Clone)]
+pub struct TlsClientConfig {
+    #[cfg(not(target_arch = "wasm32"))]
+    config: Arc<ClientConfig>,
+}
+
+impl TlsClientConfig {
+    /// Trusts the usual public certificate authorities, and the certificates in the PEM file of `extra_roots` if one
+    /// is given, such as a private authority or a self-signed certificate. Not supported on wasm.
+    pub fn new(extra_roots: Option<&Path>) -> io::Result<Self> {
+        #[cfg(target_arch = "wasm32")]
+        return Err(tls_unsupported());
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let mut roots = RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+            };
+            if let Some(extra_roots) = extra_roots {
+                for cert in read_certs(extra_roots)? {
+                    roots.add(cert).map_err(|err| invalid_tls_file(extra_roots, err))?;
+                }
+            }
+            let config = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .map_err(io::Error::other)?
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+            Ok(Self {
+                config: Arc::new(config),
+            })
+        }
+    }
+}
+
+// ---------------------------------------------------------- //
+// ------------------------- Streams ------------------------ //
+// ---------------------------------------------------------- //
+
+/// TCP stream that is encrypted with TLS, or plain. Reads and writes go through TLS once the handshake, which
+/// happens on the first read or write, has completed.
+pub enum TlsStream {
+    Plain(TcpStream),
+    #[cfg(not(target_arch = "wasm32"))]
+    Server(Box<StreamOwned<ServerConnection, TcpStream>>),
+    #[cfg(not(target_arch = "wasm32"))]
+    Client(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl TlsStream {
+    /// Wraps an accepted connection in TLS if the endpoint has a config, and leaves it plain otherwise.
+    pub fn accept(stream: TcpStream, config: Option<&TlsServerConfig>) -> io::Result<Self> {
+        match config {
+            #[cfg(not(target_arch = "wasm32"))]
+            Some(config) => {
+                let connection = ServerConnection::new(config.config.clone()).map_err(io::Error::other)?;
+                Ok(TlsStream::Server(Box::new(StreamOwned::new(connection, stream))))
+            }
+            #[cfg(target_arch = "wasm32")]
+            Some(config) => match config.never {},
+            None => Ok(TlsStream::Plain(stream)),
+        }
+    }
+
+    /// Wraps a connection to a server in TLS, checking that the server has a certificate for the host name.
+    pub fn connect(stream: TcpStream, server_name: &str, config: &TlsClientConfig) -> io::Result<Self> {
+        #[cfg(target_arch = "wasm32")]
+        return Err(tls_unsupported());
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let server_name = ServerName::try_from(server_name.to_owned())
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+            let connection = ClientConnection::new(config.config.clone(), server_name).map_err(io::Error::other)?;
+            Ok(TlsStream::Client(Box::new(StreamOwned::new(connection, stream))))
+        }
+    }
+
+    /// The underlying TCP stream, for setting timeouts and shutting down.
+    pub fn tcp_stream(&self) -> &TcpStream {
+        match self {
+            TlsStream::Plain(stream) => stream,
+            #[cfg(not(target_arch = "wasm32"))]
+            TlsStream::Server(stream) => &stream.sock,
+            #[cfg(not(target_arch = "wasm32"))]
+            TlsStream::Client(stream) => &stream.sock,
+        }
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        !matches!(self, TlsStream::Plain(_))
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            TlsStream::Plain(stream) => stream.read(buf),
+            #[cfg(not(target_arch = "wasm32"))]
+            TlsStream::Server(stream) => stream.read(buf),
+            #[cfg(not(target_arch = "wasm32"))]
+            TlsStream::Client(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            TlsStream::Plain(stream) => stream.write(buf),
+            #[cfg(not(target_arch = "wasm32"))]
+            TlsStream::Server(stream) => stream.write(buf),
+            #[cfg(not(target_arch = "wasm32"))]
+            TlsStream::Client(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            TlsStream::Plain(stream) => stream.flush(),
+            #[cfg(not(target_arch = "wasm32"))]
+            TlsStream::Server(stream) => stream.flush(),
+            #[cfg(not(target_arch = "wasm32"))]
+            TlsStream::Client(stream) => stream.flush(),
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn tls_unsupported() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "TLS is not supported on wasm")
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn invalid_tls_file(path: &Path, reason: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid TLS file {:?}: {}", path, reason),
+    )
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|err| invalid_tls_file(path, err))?;
+    if certs.is_empty() {
+        return Err(invalid_tls_file(path, "no certificates"));
+    }
+    Ok(certs)
+}
+
+// ---------------------------------------------------------- //
+// ------------------------- Tests -------------------------- //
+// ---------------------------------------------------------- //
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    /// Writes a self-signed certificate for `localhost` to temporary files, returning it and its certificate file
+    /// for clients to trust.
+    pub(crate) fn test_certificate(name: &str) -> (TlsCertificate, PathBuf) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        let dir = std::env::temp_dir().join(format!("ion_tls_test_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cert_file = dir.join("cert.pem");
+        let key_file = dir.join("key.pem");
+        std::fs::write(&cert_file, cert.cert.pem()).unwrap();
+        std::fs::write(&key_file, cert.key_pair.serialize_pem()).unwrap();
+        (TlsCertificate::new(&cert_file, &key_file), cert_file)
+    }
+
+    #[test]
+    fn clients_talk_to_servers_with_trusted_certificates() {
+        let (certificate, cert_file) = test_certificate("trusted");
+        let server_config = certificate.load().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = std::thread::spawn(move || {
+            for _ in 0..2 {
+                let (stream, _) = listener.accept().unwrap();
+                let mut stream = TlsStream::accept(stream, Some(&server_config)).unwrap();
+                let mut buf = [0; 5];
+                if stream.read_exact(&mut buf).is_ok() {
+                    stream.write_all(&buf).unwrap();
+                    stream.flush().unwrap();
+                }
+            }
+        });
+
+        let config = TlsClientConfig::new(Some(&cert_file)).unwrap();
+        let mut client = TlsStream::connect(TcpStream::connect(addr).unwrap(), "localhost", &config).unwrap();
+        assert!(client.is_encrypted());
+        client.write_all(b"hello").unwrap();
+        let mut echoed = [0; 5];
+        client.read_exact(&mut echoed).unwrap();
+        assert_eq!(&echoed, b"hello");
+
+        // Without the certificate among the trusted ones, the handshake fails
+        let config = TlsClientConfig::new(None).unwrap();
+        let mut client = TlsStream::connect(TcpStream::connect(addr).unwrap(), "localhost", &config).unwrap();
+        assert!(client.write_all(b"hello").and_then(|_| client.flush()).is_err());
+        server.join().unwrap();
+
+        std::fs::remove_dir_all(cert_file.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn invalid_certificate_files_are_refused() {
+        let missing = TlsCertificate::new("/nonexistent/cert.pem", "/nonexistent/key.pem");
+        assert!(missing.load().is_err());
+        assert!(TlsClientConfig::new(Some(Path::new("/nonexistent/cert.pem"))).is_err());
+    }
+}
@@ -0,0 +1,165 @@
+use std::fmt::Debug;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use bincode::{Decode, Encode, config::Configuration};
+
+use super::udp_network_socket::{TrafficStats, UdpNetworkSocket};
+
+const BINCODE_CONFIG: Configuration = bincode::config::standard();
+
+// ---------------------------------------------------------- //
+// ----------------------- Transport ------------------------ //
+// ---------------------------------------------------------- //
+
+/// Connection multiplayer clients and servers send their messages over.
+///
+/// Native builds use [`UdpNetworkSocket`] by default. Browsers can't send raw UDP, so browser builds use
+/// [`WsNetworkSocket`](super::ws_network_socket::WsNetworkSocket), which connects to the WebSocket bridge of the server.
+/// Games can provide their own as a [`PacketTransport`].
+pub trait NetworkTransport<T>: Send + Sync
+where
+    T: 'static + Debug + Send + Encode + Decode<()>,
+{
+    /// Sends the message, resending it until acked or the timeout passes. Zero timeout sends it only once.
+    /// Transports that are reliable by themselves ignore the timeout.
+    fn send(&self, addr: SocketAddr, msg: T, timeout: Duration);
+
+    fn try_recv_all(&self) -> Vec<(SocketAddr, T)>;
+
+    fn latency_of(&self, addr: SocketAddr) -> Option<Duration>;
+
+    fn traffic_stats_of(&self, addr: SocketAddr) -> Option<TrafficStats>;
+
+    /// Local ip other players see this end at, if the transport knows it.
+    fn local_ip_addr(&self) -> Option<IpAddr>;
+}
+
+impl<T> NetworkTransport<T> for UdpNetworkSocket<T>
+where
+    T: 'static + Debug + Send + Encode + Decode<()>,
+{
+    fn send(&self, addr: SocketAddr, msg: T, timeout: Duration) {
+        UdpNetworkSocket::send(self, addr, msg, timeout);
+    }
+
+    fn try_recv_all(&self) -> Vec<(SocketAddr, T)> {
+        UdpNetworkSocket::try_recv_all(self)
+    }
+
+    fn latency_of(&self, addr: SocketAddr) -> Option<Duration> {
+        UdpNetworkSocket::latency_of(self, addr)
+    }
+
+    fn traffic_stats_of(&self, addr: SocketAddr) -> Option<TrafficStats> {
+        UdpNetworkSocket::traffic_stats_of(self, addr)
+    }
+
+    fn local_ip_addr(&self) -> Option<IpAddr> {
+        UdpNetworkSocket::local_ip_addr(self)
+    }
+}
+
+/// Transport provided by a game, for example on top of Steam Networking Sockets or a custom relay.
+///
+/// Messages are handed over already encoded. Peers are identified by socket addresses, so transports with other kinds of
+/// ids must map them to addresses of their own, the same way on every peer. Discovery and NAT traversal are up to the
+/// transport, as the host services only work over udp.
+pub trait PacketTransport: Send + Sync {
+    /// Sends the payload to the peer. Unreliable payloads may be lost, reliable ones must arrive, but in any order.
+    fn send(&self, addr: SocketAddr, payload: Vec<u8>, reliable: bool);
+
+    fn try_recv_all(&self) -> Vec<(SocketAddr, Vec<u8>)>;
+
+    /// One way latency to the peer.
+    fn latency_of(&self, _addr: SocketAddr) -> Option<Duration> {
+        None
+    }
+
+    fn traffic_stats_of(&self, _addr: SocketAddr) -> Option<TrafficStats> {
+        None
+    }
+
+    fn local_ip_addr(&self) -> Option<IpAddr> {
+        None
+    }
+}
+
+impl<T> NetworkTransport<T> for Box<dyn PacketTransport>
+where
+    T: 'static + Debug + Send + Encode + Decode<()>,
+{
+    fn send(&self, addr: SocketAddr, msg: T, timeout: Duration) {
+        let payload = bincode::encode_to_vec(msg, BINCODE_CONFIG).unwrap();
+        PacketTransport::send(&**self, addr, payload, !timeout.is_zero());
+    }
+
+    /// Payloads that can't be decoded are dropped.
+    fn try_recv_all(&self) -> Vec<(SocketAddr, T)> {
+        PacketTransport::try_recv_all(&**self)
+            .into_iter()
+            .filter_map(|(addr, payload)| {
+                let (msg, _) = bincode::decode_from_slice(&payload, BINCODE_CONFIG).ok()?;
+                Some((addr, msg))
+            })
+            .collect()
+    }
+
+    fn latency_of(&self, addr: SocketAddr) -> Option<Duration> {
+        PacketTransport::latency_of(&**self, addr)
+    }
+
+    fn traffic_stats_of(&self, addr: SocketAddr) -> Option<TrafficStats> {
+        PacketTransport::traffic_stats_of(&**self, addr)
+    }
+
+    fn local_ip_addr(&self) -> Option<IpAddr> {
+        PacketTransport::local_ip_addr(&**self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+    struct SimpleMessage {
+        count: u32,
+    }
+
+    /// Delivers everything back to the sender.
+    #[derive(Default)]
+    struct LoopbackTransport {
+        sent: Mutex<Vec<(SocketAddr, Vec<u8>, bool)>>,
+    }
+
+    impl PacketTransport for LoopbackTransport {
+        fn send(&self, addr: SocketAddr, payload: Vec<u8>, reliable: bool) {
+            self.sent.lock().unwrap().push((addr, payload, reliable));
+        }
+
+        fn try_recv_all(&self) -> Vec<(SocketAddr, Vec<u8>)> {
+            let mut sent = self.sent.lock().unwrap();
+            let mut received: Vec<_> = sent.drain(..).map(|(addr, payload, _)| (addr, payload)).collect();
+            received.push((SocketAddr::from(([127, 0, 0, 1], 1)), vec![255; 3]));
+            received
+        }
+    }
+
+    #[test]
+    fn packet_transports_carry_encoded_messages() {
+        let transport: Box<dyn PacketTransport> = Box::new(LoopbackTransport::default());
+        let addr = SocketAddr::from(([127, 0, 0, 1], 2));
+        NetworkTransport::send(&transport, addr, SimpleMessage { count: 1 }, Duration::from_secs(1));
+        NetworkTransport::send(&transport, addr, SimpleMessage { count: 2 }, Duration::ZERO);
+
+        // Garbage from the transport is dropped
+        let received: Vec<(SocketAddr, SimpleMessage)> = NetworkTransport::try_recv_all(&transport);
+        assert_eq!(
+            received,
+            vec![(addr, SimpleMessage { count: 1 }), (addr, SimpleMessage { count: 2 })]
+        );
+    }
+}
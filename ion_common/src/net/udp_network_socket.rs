@@ -1,5 +1,5 @@
 use core::panic;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::net::AddrParseError;
 use std::process::{Command, Stdio};
 use std::str::FromStr;
@@ -10,7 +10,7 @@ use std::{
     collections::VecDeque,
     fmt::Debug,
     io,
-    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
     sync::{
         Arc, RwLock,
         atomic::{AtomicBool, AtomicU64, Ordering},
@@ -22,7 +22,13 @@ use std::{
 use bincode::{Decode, Encode, config::Configuration, error::DecodeError};
 
 use crate::Map;
+use crate::log_warn;
 use crate::math::rand::Rng;
+use crate::net::rate_limit::{RateLimiter, is_valid_source};
+
+use self::encryption::Encryption;
+
+mod encryption;
 
 #[cfg(not(target_arch = "wasm32"))]
 use crate::util::native_spin_sleep;
@@ -33,9 +39,19 @@ use crate::util::native_spin_sleep;
 
 const PROTOCOL_ID: u32 = 1225163695;
 
-const MAX_UDP_PAYLOAD: usize = 1128;
-// Should fit into a single MTU in most modern networks
-const MSG_FRAGMENT_SIZE: usize = 1024;
+// Largest datagram that fits into a 1500 byte Ethernet MTU over IPv4
+const MAX_UDP_PAYLOAD: usize = 1472;
+// Room for the frame headers and encryption around the data of a fragment
+const FRAME_OVERHEAD: usize = 64;
+const MAX_FRAGMENT_SIZE: usize = MAX_UDP_PAYLOAD - FRAME_OVERHEAD;
+// Used until the path to the address has been probed. Fits into a single MTU in most modern networks
+const DEFAULT_FRAGMENT_SIZE: usize = 1024;
+// Fragment sizes probed on the path to each address. The smallest fits into the smallest datagram every IPv4 host must
+// accept, and the larger ones fill IPv6 datagrams of 1280 and 1500 byte MTUs
+const MTU_PROBE_FRAGMENT_SIZES: [usize; 4] =
+    [508 - FRAME_OVERHEAD, DEFAULT_FRAGMENT_SIZE, 1280 - 48 - FRAME_OVERHEAD, 1500 - 48 - FRAME_OVERHEAD];
+const MTU_PROBE_ATTEMPTS: u32 = 3;
+const MTU_PROBE_INTERVAL: Duration = Duration::from_secs(1);
 // 1 KiBiByte
 const MSG_MAX_TOTAL_SIZE: usize = 250 * 1024 * 1024;
 // 250 MiBiBytes
@@ -43,6 +59,8 @@ const MSG_BUFFER_SIZE: usize = 512; // Number of both incoming and outgoing mess
 
 const MIN_ACK_TIMEOUT: Duration = Duration::from_millis(50);
 const MAX_ACK_TIMEOUT: Duration = Duration::from_millis(1000);
+// Times a message is sent to an address that has never acked anything, when the sources are limited
+const MAX_UNVERIFIED_SENDS: u32 = 2;
 
 const BINCODE_CONFIG: Configuration = bincode::config::standard();
 
@@ -61,21 +79,66 @@ where
     msg_out_sender: SyncSender<(SocketAddr, T, Duration)>,
     msg_in_receiver: Mutex<Receiver<(SocketAddr, T)>>,
     address_latencies: Arc<RwLock<Map<IpAddr, AtomicU64>>>,
+    traffic_stats: Arc<Mutex<Map<SocketAddr, TrafficStats>>>,
+    source_limiter: Arc<Mutex<Option<RateLimiter>>>,
+    public_key: [u8; 32],
+    pinned_keys: Arc<Mutex<HashMap<SocketAddr, [u8; 32]>>>,
+    session_addrs: Arc<RwLock<HashSet<SocketAddr>>>,
 }
 
 impl<T> UdpNetworkSocket<T>
 where
     T: 'static + Debug + Send + Encode + Decode<()>,
 {
+    /// Creates a socket bound to the address. Sockets bound to an IPv6 address reach IPv4 peers too, through
+    /// IPv4-mapped addresses, where the platform allows it. Peers are always reported with their plain IPv4 address.
+    ///
+    /// If the host has no IPv6, sockets bound to the unspecified IPv6 address fall back to IPv4.
     pub fn new(bind_addr: SocketAddr) -> Self {
+        Self::with_encryption(bind_addr, false, Encryption::generate_keypair())
+    }
+
+    /// Creates a socket like [`UdpNetworkSocket::new`], with the private key instead of a generated one. Keeping the
+    /// key across restarts keeps the [`UdpNetworkSocket::public_key`] the same, so that peers can pin it for good.
+    /// New keys come from [`generate_private_key`].
+    pub fn with_private_key(bind_addr: SocketAddr, private_key: [u8; 32]) -> Self {
+        let public_key = Encryption::public_key_of(&private_key);
+        Self::with_encryption(bind_addr, false, (private_key.to_vec(), public_key))
+    }
+
+    /// Creates a socket that encrypts everything it sends to other addresses, except broadcasts.
+    ///
+    /// Each address gets its own session keys from a handshake before the first message is sent.
+    /// Any socket answers handshakes, and after that encrypts its replies to that address too.
+    ///
+    /// The sessions keep the traffic private from anyone listening, but they don't authenticate the peer, so anyone on
+    /// the path can sit in the middle and read or change everything. Sessions with addresses whose key has been pinned
+    /// with [`UdpNetworkSocket::pin_key`] are authenticated.
+    pub fn new_encrypted(bind_addr: SocketAddr) -> Self {
+        Self::with_encryption(bind_addr, true, Encryption::generate_keypair())
+    }
+
+    fn with_encryption(bind_addr: SocketAddr, encrypt_outgoing: bool, keypair: (Vec<u8>, [u8; 32])) -> Self {
         // log_info!("Starting up udp network socket");
 
         let (msg_in_sender, msg_in_receiver) = mpsc::sync_channel::<(SocketAddr, T)>(MSG_BUFFER_SIZE);
         let (msg_out_sender, msg_out_receiver) = mpsc::sync_channel::<(SocketAddr, T, Duration)>(MSG_BUFFER_SIZE);
 
         let address_latencies = Arc::new(RwLock::new(Map::default()));
-
-        let socket = Arc::new(UdpSocket::bind(bind_addr).unwrap());
+        let traffic_stats = Arc::new(Mutex::new(Map::default()));
+        let source_limiter = Arc::new(Mutex::new(None));
+        let (private_key, public_key) = keypair;
+        let pinned_keys = Arc::new(Mutex::new(HashMap::default()));
+        let session_addrs = Arc::new(RwLock::new(HashSet::default()));
+
+        let socket = UdpSocket::bind(bind_addr)
+            .or_else(|err| match bind_addr.ip() {
+                IpAddr::V6(ip) if ip.is_unspecified() => UdpSocket::bind((Ipv4Addr::UNSPECIFIED, bind_addr.port())),
+                _ => Err(err),
+            })
+            .unwrap();
+        let socket_addr = SocketAddr::new(socket.local_addr().unwrap().ip(), bind_addr.port());
+        let socket = Arc::new(socket);
         let socket_on = Arc::new(AtomicBool::new(true));
 
         socket.set_nonblocking(true).unwrap();
@@ -86,16 +149,25 @@ where
             msg_in_sender,
             msg_out_receiver,
             address_latencies.clone(),
+            traffic_stats.clone(),
+            source_limiter.clone(),
+            Encryption::new(private_key, pinned_keys.clone(), session_addrs.clone()),
+            encrypt_outgoing,
         );
 
         Self {
             socket,
             socket_on,
-            socket_addr: bind_addr,
+            socket_addr,
             socket_handle: Some(socket_handle),
             msg_out_sender,
             msg_in_receiver: Mutex::new(msg_in_receiver),
             address_latencies,
+            traffic_stats,
+            source_limiter,
+            public_key,
+            pinned_keys,
+            session_addrs,
         }
     }
 
@@ -165,6 +237,45 @@ where
             .map(|latency_ms| Duration::from_millis(latency_ms.load(Ordering::Relaxed)))
     }
 
+    /// Traffic to and from the address since the socket was created, or none if nothing has been sent or received.
+    pub fn traffic_stats_of(&self, addr: SocketAddr) -> Option<TrafficStats> {
+        self.traffic_stats.lock().unwrap().get(&addr).copied()
+    }
+
+    /// Limits the frames taken from each IP address, for sockets open to anyone. Frames beyond the limit, and frames
+    /// from addresses that can't be real sources, are dropped before they are acked or answered. Messages to addresses
+    /// that have never acked anything are only sent [`MAX_UNVERIFIED_SENDS`] times, instead of until their timeout.
+    ///
+    /// Together these keep spoofed frames from turning the socket into a flood towards the address they claim.
+    pub fn limit_sources(&self, frames_per_sec: u32, frame_burst: u32) {
+        let mut source_limiter = self.source_limiter.lock().unwrap();
+        match source_limiter.as_ref() {
+            Some(limiter) => limiter.set_limits(frames_per_sec, frame_burst),
+            None => *source_limiter = Some(RateLimiter::new(frames_per_sec, frame_burst)),
+        }
+    }
+
+    /// Public key the socket proves its identity with to peers that have pinned it. Generated for each socket, unless
+    /// the socket is created [`with_private_key`](UdpNetworkSocket::with_private_key).
+    pub fn public_key(&self) -> [u8; 32] {
+        self.public_key
+    }
+
+    /// Talks to the address only over sessions that the socket there authenticates with the key, so that nobody in
+    /// the middle can read or change the traffic. Messages to the address are encrypted from then on, and plain frames
+    /// and handshakes from it are dropped. The key is the [`UdpNetworkSocket::public_key`] of the socket at the address.
+    ///
+    /// The pinning side always starts the handshake, so two sockets must not pin each other.
+    pub fn pin_key(&self, addr: SocketAddr, public_key: [u8; 32]) {
+        self.pinned_keys.lock().unwrap().insert(addr, public_key);
+    }
+
+    /// Whether the socket has an encrypted session with the address. Once it has, only encrypted frames are taken from
+    /// the address.
+    pub fn has_session(&self, addr: SocketAddr) -> bool {
+        self.session_addrs.read().unwrap().contains(&addr)
+    }
+
     pub fn enable_broadcast(&self) {
         self.socket.set_broadcast(true).unwrap();
     }
@@ -194,13 +305,13 @@ where
         self.socket.local_addr().unwrap().ip().is_loopback()
     }
 
-    pub fn local_ip_addr(&self) -> Option<IpAddr> {
-        let is_loopback = match self.socket_addr {
-            SocketAddr::V4(addr) => *addr.ip().octets().first().unwrap() == 127,
-            SocketAddr::V6(_) => panic!("Loopback ipv6 not supported"),
-        };
+    /// Whether the socket reaches both IPv4 and IPv6 peers.
+    pub fn is_dual_stack(&self) -> bool {
+        self.socket_addr.is_ipv6() && cfg!(not(target_os = "windows"))
+    }
 
-        if is_loopback {
+    pub fn local_ip_addr(&self) -> Option<IpAddr> {
+        if self.socket_addr.ip().is_loopback() {
             Some(self.socket_addr.ip())
         } else {
             #[cfg(target_os = "macos")]
@@ -221,12 +332,17 @@ where
     // ---------------- Private implementation ------------------ //
     // ---------------------------------------------------------- //
 
+    #[allow(clippy::too_many_arguments)]
     fn build_network_thread(
         socket: Arc<UdpSocket>,
         socket_on: Arc<AtomicBool>,
         msg_in_sender: SyncSender<(SocketAddr, T)>,
         msg_out_receiver: Receiver<(SocketAddr, T, Duration)>,
         address_latencies: Arc<RwLock<Map<IpAddr, AtomicU64>>>,
+        traffic_stats: Arc<Mutex<Map<SocketAddr, TrafficStats>>>,
+        source_limiter: Arc<Mutex<Option<RateLimiter>>>,
+        mut encryption: Encryption,
+        encrypt_outgoing: bool,
     ) -> JoinHandle<()> {
         thread::Builder::new()
             .name("udp_network_socket".to_owned())
@@ -236,6 +352,7 @@ where
 
                 let mut inc_data_buf = [0; MAX_UDP_PAYLOAD];
                 let mut inc_fragment_buf = HashMap::default();
+                let mut path_mtus = HashMap::default();
 
                 let mut waiting_acks: HashMap<u64, SingleFrameAckDetails> = HashMap::default();
                 let mut waiting_multiframe_acks: HashMap<u64, MultiFrameAckDetails> = HashMap::default();
@@ -244,27 +361,42 @@ where
                 let mut send_multiframe_queue = VecDeque::new();
 
                 let mut rng = Rng::new(None);
+                let is_ipv6_socket = socket.local_addr().unwrap().is_ipv6();
 
                 move || {
                     while socket_on.load(Ordering::Relaxed) {
                         // Send frames
-                        Self::execute_frame_sends(&socket, &mut send_queue, &mut send_multiframe_queue);
+                        Self::execute_frame_sends(
+                            &socket,
+                            is_ipv6_socket,
+                            &mut encryption,
+                            encrypt_outgoing,
+                            &mut rng,
+                            &mut send_queue,
+                            &mut send_multiframe_queue,
+                            &traffic_stats,
+                        );
 
                         // Receive frames
                         Self::execute_frame_receives(
                             &socket,
+                            &mut encryption,
                             &mut inc_data_buf,
                             &mut inc_fragment_buf,
+                            &mut path_mtus,
                             &mut waiting_acks,
                             &mut waiting_multiframe_acks,
                             &mut send_queue,
                             &msg_in_sender,
                             address_latencies.clone(),
+                            &traffic_stats,
+                            &source_limiter,
                         );
 
                         // Take in messages
                         Self::process_msg_sends(
                             &mut rng,
+                            &mut path_mtus,
                             &msg_out_receiver,
                             &mut waiting_acks,
                             &mut waiting_multiframe_acks,
@@ -274,17 +406,24 @@ where
                         );
 
                         // Check waiting acks
+                        let limit_unverified = source_limiter.lock().unwrap().is_some();
                         Self::process_msg_resends(
+                            limit_unverified,
                             &mut waiting_acks,
                             &mut waiting_multiframe_acks,
                             &mut send_queue,
                             &mut send_multiframe_queue,
                             address_latencies.clone(),
+                            &traffic_stats,
                         );
 
+                        // Probe how large fragments get through to each address
+                        Self::process_mtu_probes(&mut rng, &mut path_mtus, &mut send_queue);
+
                         // Clean up old broken transactions from inc_fragment_buf
                         let now = Instant::now();
-                        inc_fragment_buf.retain(|_, (timestamp, _, _)| *timestamp + Duration::from_secs(60) > now);
+                        inc_fragment_buf.retain(|_, (timestamp, _, _, _)| *timestamp + Duration::from_secs(60) > now);
+                        encryption.expire_incomplete(now);
 
                         // Don't hot loop on non-windows platforms
                         // On windows we need to hot loop to keep the latency small
@@ -336,10 +475,47 @@ where
         }
     }
 
+    /// Encodes the frame for sending. Unicast frames are encrypted if this socket encrypts its traffic, if the other
+    /// end has set up a session, or if its key is pinned. Returns the handshake to send instead, if the frame has to
+    /// wait for a session.
+    fn encode_frame(
+        encryption: &mut Encryption,
+        encrypt_outgoing: bool,
+        rng: &mut Rng,
+        addr: SocketAddr,
+        frame: NetworkFrame,
+    ) -> Option<Vec<u8>> {
+        let is_handshake = matches!(
+            frame.frame_body,
+            FrameBody::HandshakeInit { .. } | FrameBody::HandshakeResponse { .. }
+        );
+        if is_handshake
+            || !is_unicast(addr)
+            || !(encrypt_outgoing || encryption.has_session(addr) || encryption.is_pinned(addr))
+        {
+            return Some(frame.into());
+        }
+
+        match encryption.seal(addr, frame.clone()) {
+            Some((nonce, data)) => Some(NetworkFrame::new(nonce, FrameBody::Encrypted { data }).into()),
+            None => encryption
+                .queue(addr, frame, rng.gen_u64())
+                .map(|(handshake_id, pinned, data)| {
+                    NetworkFrame::new(handshake_id, FrameBody::HandshakeInit { pinned, data }).into()
+                }),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
     fn execute_frame_sends(
         socket: &UdpSocket,
+        is_ipv6_socket: bool,
+        encryption: &mut Encryption,
+        encrypt_outgoing: bool,
+        rng: &mut Rng,
         send_queue: &mut VecDeque<(SocketAddr, NetworkFrame)>,
         send_multiframe_queue: &mut VecDeque<(SocketAddr, NetworkFrame)>,
+        traffic_stats: &Mutex<Map<SocketAddr, TrafficStats>>,
     ) {
         let mut singleframe_to_send = send_queue.pop_front();
         let mut multiframe_to_send = send_multiframe_queue.pop_front();
@@ -351,15 +527,24 @@ where
                 //     frame.frame_id,
                 //     addr
                 // );
-                let frame_byte_vec: Vec<u8> = frame.clone().into();
-                match socket.send_to(&frame_byte_vec, addr) {
-                    Ok(_) => {}
+                let Some(frame_byte_vec) = Self::encode_frame(encryption, encrypt_outgoing, rng, addr, frame.clone())
+                else {
+                    continue;
+                };
+                match socket.send_to(&frame_byte_vec, to_socket_family(addr, is_ipv6_socket)) {
+                    Ok(_) if frame.frame_body.is_mtu_probe() => {}
+                    Ok(len) => traffic_stats.lock().unwrap().entry(addr).or_default().record_sent(len),
                     Err(err) => match err.kind() {
                         io::ErrorKind::WouldBlock => {
                             send_queue.push_front((addr, frame));
                             break;
                         }
-                        _ => panic!("Error sending udp frame to {:?}: {:?}", addr, err),
+                        // Probes larger than the known path MTU may be refused right away
+                        _ if frame.frame_body.is_mtu_probe() => {}
+                        // Unreachable addresses fail right away, and must not take down the socket
+                        _ => {
+                            log_warn!("Dropped udp frame to {:?}: {:?}", addr, err);
+                        }
                     },
                 }
             }
@@ -370,15 +555,20 @@ where
                 //     frame.frame_id,
                 //     addr
                 // );
-                let frame_byte_vec: Vec<u8> = frame.clone().into();
-                match socket.send_to(&frame_byte_vec, addr) {
-                    Ok(_) => {}
+                let Some(frame_byte_vec) = Self::encode_frame(encryption, encrypt_outgoing, rng, addr, frame.clone())
+                else {
+                    continue;
+                };
+                match socket.send_to(&frame_byte_vec, to_socket_family(addr, is_ipv6_socket)) {
+                    Ok(len) => traffic_stats.lock().unwrap().entry(addr).or_default().record_sent(len),
                     Err(err) => match err.kind() {
                         io::ErrorKind::WouldBlock => {
                             send_multiframe_queue.push_front((addr, frame));
                             break;
                         }
-                        _ => panic!("Error sending udp frame: {:?}", err),
+                        _ => {
+                            log_warn!("Dropped udp frame to {:?}: {:?}", addr, err);
+                        }
                     },
                 }
             }
@@ -392,19 +582,82 @@ where
         }
     }
 
-    #[allow(clippy::too_many_arguments)]
+    #[allow(clippy::too_many_arguments, clippy::type_complexity)]
     fn execute_frame_receives(
         socket: &UdpSocket,
+        encryption: &mut Encryption,
         inc_data_buf: &mut [u8; MAX_UDP_PAYLOAD],
-        inc_fragment_buf: &mut HashMap<u64, (Instant, Vec<bool>, Vec<u8>)>,
+        inc_fragment_buf: &mut HashMap<u64, (Instant, usize, Vec<bool>, Vec<u8>)>,
+        path_mtus: &mut HashMap<SocketAddr, PathMtu>,
         waiting_acks: &mut HashMap<u64, SingleFrameAckDetails>,
         waiting_multiframe_acks: &mut HashMap<u64, MultiFrameAckDetails>,
         send_queue: &mut VecDeque<(SocketAddr, NetworkFrame)>,
         msg_in_sender: &SyncSender<(SocketAddr, T)>,
         address_latencies: Arc<RwLock<Map<IpAddr, AtomicU64>>>,
+        traffic_stats: &Mutex<Map<SocketAddr, TrafficStats>>,
+        source_limiter: &Mutex<Option<RateLimiter>>,
     ) {
         while let Ok((recv_size, from_addr)) = socket.recv_from(inc_data_buf) {
+            let from_addr = SocketAddr::new(from_addr.ip().to_canonical(), from_addr.port());
+            // Not held while receiving, as handing the message over can wait for the user of the socket
+            let is_allowed = source_limiter
+                .lock()
+                .unwrap()
+                .as_ref()
+                .is_none_or(|limiter| is_valid_source(from_addr) && limiter.allow(from_addr.ip(), Instant::now()));
+            if !is_allowed {
+                continue;
+            }
             if let Some((id, frame_body)) = Self::parse_frame(&inc_data_buf[0..recv_size]) {
+                let record_received = || {
+                    traffic_stats
+                        .lock()
+                        .unwrap()
+                        .entry(from_addr)
+                        .or_default()
+                        .record_received(recv_size)
+                };
+                // Probes are only known once decrypted
+                if !matches!(frame_body, FrameBody::Encrypted { .. }) && !frame_body.is_mtu_probe() {
+                    record_received();
+                }
+                let (id, frame_body) = match frame_body {
+                    FrameBody::HandshakeInit { pinned, data } => {
+                        if let Some(response) = encryption.accept(from_addr, id, pinned, &data) {
+                            let response_frame = NetworkFrame::new(id, FrameBody::HandshakeResponse { data: response });
+                            send_queue.push_back((from_addr, response_frame));
+                        }
+                        continue;
+                    }
+                    FrameBody::HandshakeResponse { data } => {
+                        let pending_frames = encryption.complete(from_addr, id, &data);
+                        send_queue.extend(pending_frames.into_iter().map(|frame| (from_addr, frame)));
+                        continue;
+                    }
+                    FrameBody::Encrypted { data } => {
+                        let Some((plaintext, pending_frames)) = encryption.open(from_addr, id, &data) else {
+                            record_received();
+                            continue;
+                        };
+                        send_queue.extend(pending_frames.into_iter().map(|frame| (from_addr, frame)));
+                        match Self::parse_frame(&plaintext) {
+                            Some((id, frame_body)) if !frame_body.is_transport() => {
+                                if !frame_body.is_mtu_probe() {
+                                    record_received();
+                                }
+                                (id, frame_body)
+                            }
+                            _ => {
+                                record_received();
+                                continue;
+                            }
+                        }
+                    }
+                    // Once there is a session, or the key is pinned, only encrypted frames are accepted from the address
+                    _ if encryption.has_session(from_addr) || encryption.is_pinned(from_addr) => continue,
+                    frame_body => (id, frame_body),
+                };
+
                 match frame_body {
                     FrameBody::SingleFrameMessage { data } => {
                         // log_trc!("Received SingleFrameMessage from {:?}", from_addr);
@@ -425,16 +678,20 @@ where
                     FrameBody::MultiFrameMessageBegin {
                         total_fragments,
                         total_size,
+                        fragment_size,
                     } => {
                         // log_trc!("Received MultiFrameMessageBegin from {:?}", from_addr);
                         if total_size < MSG_MAX_TOTAL_SIZE
                             && total_fragments > 1
-                            && total_size <= total_fragments * MSG_FRAGMENT_SIZE
-                            && total_size > (total_fragments - 1) * MSG_FRAGMENT_SIZE
+                            && (1..=MAX_FRAGMENT_SIZE).contains(&fragment_size)
+                            && total_fragments
+                                .checked_mul(fragment_size)
+                                .is_some_and(|max_size| total_size <= max_size)
+                            && total_size > (total_fragments - 1) * fragment_size
                         {
                             let fragment_vec = vec![false; total_fragments];
                             let data_vec = vec![0_u8; total_size];
-                            inc_fragment_buf.insert(id, (Instant::now(), fragment_vec, data_vec));
+                            inc_fragment_buf.insert(id, (Instant::now(), fragment_size, fragment_vec, data_vec));
                         } else {
                             // log_warn!(
                             //     "Received UDP MultiFrameMessageBegin with total size larger than max size. total_size: {}",
@@ -450,18 +707,23 @@ where
                         // );
                         inc_fragment_buf
                             .entry(id)
-                            .and_modify(|(timestamp, fragment_vec, data_vec)| {
-                                *timestamp = Instant::now();
-                                fragment_vec[fragment_id] = true;
-                                let fragment_start_i = fragment_id * MSG_FRAGMENT_SIZE;
-                                let fragment_end_i = fragment_id * MSG_FRAGMENT_SIZE + data.len();
-
-                                data_vec[fragment_start_i..fragment_end_i].copy_from_slice(&data);
+                            .and_modify(|(timestamp, fragment_size, fragment_vec, data_vec)| {
+                                // Checked first, as the id comes from the sender and could overflow the start index
+                                if fragment_id >= fragment_vec.len() || data.len() > *fragment_size {
+                                    return;
+                                }
+                                let fragment_start_i = fragment_id * *fragment_size;
+                                let fragment_end_i = fragment_start_i + data.len();
+                                if fragment_end_i <= data_vec.len() {
+                                    *timestamp = Instant::now();
+                                    fragment_vec[fragment_id] = true;
+                                    data_vec[fragment_start_i..fragment_end_i].copy_from_slice(&data);
+                                }
                             });
                     }
                     FrameBody::MultiFrameMessageEnd => {
                         // log_trc!("Received MultiFrameMessageEnd from {:?}", from_addr);
-                        if let Some((timestamp, fragment_vec, data_vec)) = inc_fragment_buf.remove(&id) {
+                        if let Some((timestamp, fragment_size, fragment_vec, data_vec)) = inc_fragment_buf.remove(&id) {
                             let missing_fragments: Vec<usize> = fragment_vec
                                 .iter()
                                 .enumerate()
@@ -478,7 +740,7 @@ where
                                     msg_in_sender.send((from_addr, msg)).unwrap();
                                 }
                             } else {
-                                inc_fragment_buf.insert(id, (timestamp, fragment_vec, data_vec));
+                                inc_fragment_buf.insert(id, (timestamp, fragment_size, fragment_vec, data_vec));
                                 let missing_fragments: Vec<_> = missing_fragments[0..min(missing_fragments.len(), 200)]
                                     .iter()
                                     .copied()
@@ -501,21 +763,42 @@ where
                             ack_details.missing_frames = missing_fragments;
                         });
                     }
+                    FrameBody::MtuProbe { fragment_size, padding } => {
+                        if padding.len() == fragment_size {
+                            let ack_frame = NetworkFrame::new(id, FrameBody::MtuProbeAck { fragment_size });
+                            send_queue.push_back((from_addr, ack_frame));
+                        }
+                    }
+                    FrameBody::MtuProbeAck { fragment_size } => {
+                        if let Some(path_mtu) = path_mtus.get_mut(&from_addr) {
+                            path_mtu.probe_acked(fragment_size);
+                        }
+                    }
+                    FrameBody::HandshakeInit { .. }
+                    | FrameBody::HandshakeResponse { .. }
+                    | FrameBody::Encrypted { .. } => {}
                 }
             }
         }
     }
 
     fn process_msg_resends(
+        limit_unverified: bool,
         waiting_acks: &mut HashMap<u64, SingleFrameAckDetails>,
         waiting_multiframe_acks: &mut HashMap<u64, MultiFrameAckDetails>,
         send_queue: &mut VecDeque<(SocketAddr, NetworkFrame)>,
         send_multiframe_queue: &mut VecDeque<(SocketAddr, NetworkFrame)>,
         address_latencies: Arc<RwLock<Map<IpAddr, AtomicU64>>>,
+        traffic_stats: &Mutex<Map<SocketAddr, TrafficStats>>,
     ) {
         let now = Instant::now();
+        let mut traffic_stats = traffic_stats.lock().unwrap();
         waiting_acks.retain(|_msg_id, ack_details| {
             let retain = now < ack_details.timeout_at;
+            // Messages sent without a timeout are never resent, so they don't count as lost
+            if !retain && ack_details.sent_count > 1 {
+                traffic_stats.entry(ack_details.addr).or_default().lost_messages += 1;
+            }
             if !retain {
                 // log_warn!(
                 //     "Failed to send singleframe message {} to {:?}",
@@ -528,6 +811,7 @@ where
         waiting_multiframe_acks.retain(|_, ack_details| {
             let retain = now < ack_details.timeout_at;
             if !retain {
+                traffic_stats.entry(ack_details.addr).or_default().lost_messages += 1;
                 // log_warn!(
                 //     "Failed to send multiframe message {} to {:?}",
                 //     ack_details.msg_id,
@@ -540,18 +824,23 @@ where
         waiting_acks.iter_mut().for_each(|(_msg_id, ack_details)| {
             if now > ack_details.next_resend_at {
                 // log_dbg!("Resending msg {} to {}", _msg_id, ack_details.addr);
-                let latency = address_latencies
+                // Latency is only known for addresses that have acked something
+                let known_latency = address_latencies
                     .read()
                     .unwrap()
                     .get(&ack_details.addr.ip())
-                    .map(|latency_ms| Duration::from_millis(latency_ms.load(Ordering::Relaxed)))
-                    .unwrap_or(Duration::from_millis(100));
+                    .map(|latency_ms| Duration::from_millis(latency_ms.load(Ordering::Relaxed)));
+                if limit_unverified && known_latency.is_none() && ack_details.sent_count >= MAX_UNVERIFIED_SENDS {
+                    return;
+                }
+                let latency = known_latency.unwrap_or(Duration::from_millis(100));
                 let next_send = now
                     + (5 * ack_details.sent_count * latency)
                         .max(MIN_ACK_TIMEOUT)
                         .min(MAX_ACK_TIMEOUT);
                 ack_details.sent_count += 1;
                 ack_details.next_resend_at = next_send;
+                traffic_stats.entry(ack_details.addr).or_default().resends += 1;
                 send_queue.push_back((ack_details.addr, ack_details.frame.clone()));
             }
         });
@@ -563,6 +852,7 @@ where
                 //     ack_details.msg_id,
                 //     ack_details.addr
                 // );
+                traffic_stats.entry(ack_details.addr).or_default().resends += ack_details.missing_frames.len() as u64;
                 ack_details.missing_frames.iter().for_each(|frame_i| {
                     send_multiframe_queue
                         .push_back((ack_details.addr, ack_details.all_frames[*frame_i as usize].clone()));
@@ -576,8 +866,10 @@ where
         });
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn process_msg_sends(
         rng: &mut Rng,
+        path_mtus: &mut HashMap<SocketAddr, PathMtu>,
         msg_out_receiver: &Receiver<(SocketAddr, T, Duration)>,
         waiting_acks: &mut HashMap<u64, SingleFrameAckDetails>,
         waiting_multiframe_acks: &mut HashMap<u64, MultiFrameAckDetails>,
@@ -592,12 +884,18 @@ where
             let data = bincode::encode_to_vec(msg, BINCODE_CONFIG).unwrap();
             let data_len = data.len();
 
-            let is_unicast = match addr.ip() {
-                IpAddr::V4(addr) => !addr.is_multicast() && !addr.is_broadcast(),
-                IpAddr::V6(addr) => !addr.is_multicast(),
+            let is_unicast = is_unicast(addr);
+            let fragment_size = if is_unicast {
+                path_mtus
+                    .entry(addr)
+                    .or_insert_with(|| PathMtu::new(Instant::now()))
+                    .fragment_size
+            } else {
+                // Broadcasts stay in the local network, so they are not probed
+                DEFAULT_FRAGMENT_SIZE
             };
 
-            if data_len < MSG_FRAGMENT_SIZE {
+            if data_len < fragment_size {
                 let frame = NetworkFrame::new(id, FrameBody::SingleFrameMessage { data });
 
                 if is_unicast {
@@ -625,7 +923,7 @@ where
             } else if data_len < MSG_MAX_TOTAL_SIZE && is_unicast {
                 let now = Instant::now();
                 let fragment_frames: Vec<_> = data
-                    .chunks(MSG_FRAGMENT_SIZE)
+                    .chunks(fragment_size)
                     .enumerate()
                     .map(|(i, data)| {
                         NetworkFrame::new(
@@ -643,6 +941,7 @@ where
                     FrameBody::MultiFrameMessageBegin {
                         total_fragments: fragment_frames.len(),
                         total_size: data_len,
+                        fragment_size,
                     },
                 );
                 let end_frame = NetworkFrame::new(id, FrameBody::MultiFrameMessageEnd);
@@ -669,6 +968,23 @@ where
         }
     }
 
+    fn process_mtu_probes(
+        rng: &mut Rng,
+        path_mtus: &mut HashMap<SocketAddr, PathMtu>,
+        send_queue: &mut VecDeque<(SocketAddr, NetworkFrame)>,
+    ) {
+        let now = Instant::now();
+        for (addr, path_mtu) in path_mtus.iter_mut() {
+            for fragment_size in path_mtu.probes_due(now) {
+                let probe = FrameBody::MtuProbe {
+                    fragment_size,
+                    padding: vec![0; fragment_size],
+                };
+                send_queue.push_back((*addr, NetworkFrame::new(rng.gen_u64(), probe)));
+            }
+        }
+    }
+
     fn update_latency_estimate(
         addr: SocketAddr,
         new_latency: Duration,
@@ -741,15 +1057,90 @@ where
 // ---------------- Supporting data types ------------------- //
 // ---------------------------------------------------------- //
 
+/// New private key for [`UdpNetworkSocket::with_private_key`].
+pub fn generate_private_key() -> [u8; 32] {
+    Encryption::generate_keypair().0.try_into().unwrap()
+}
+
+/// Bind address that accepts both IPv4 and IPv6 peers on the port. Windows makes IPv6 sockets IPv6 only, so there
+/// it binds IPv4 only.
+pub fn dual_stack_bind_addr(port: u16) -> SocketAddr {
+    if cfg!(target_os = "windows") {
+        SocketAddr::from((Ipv4Addr::UNSPECIFIED, port))
+    } else {
+        SocketAddr::from((Ipv6Addr::UNSPECIFIED, port))
+    }
+}
+
+/// IPv6 sockets send to IPv4 peers through IPv4-mapped addresses.
+fn to_socket_family(addr: SocketAddr, is_ipv6_socket: bool) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V4(ip) if is_ipv6_socket => SocketAddr::new(IpAddr::V6(ip.to_ipv6_mapped()), addr.port()),
+        _ => addr,
+    }
+}
+
+fn is_unicast(addr: SocketAddr) -> bool {
+    match addr.ip() {
+        IpAddr::V4(addr) => !addr.is_multicast() && !addr.is_broadcast(),
+        IpAddr::V6(addr) => !addr.is_multicast(),
+    }
+}
+
 #[derive(Clone, Encode, Decode)]
 enum FrameBody {
-    SingleFrameMessage { data: Vec<u8> },
+    SingleFrameMessage {
+        data: Vec<u8>,
+    },
     SingleFrameMessageAck,
-    MultiFrameMessageBegin { total_fragments: usize, total_size: usize },
-    MultiFrameMessageFragment { fragment_id: usize, data: Vec<u8> },
+    MultiFrameMessageBegin {
+        total_fragments: usize,
+        total_size: usize,
+        fragment_size: usize,
+    },
+    MultiFrameMessageFragment {
+        fragment_id: usize,
+        data: Vec<u8>,
+    },
     MultiFrameMessageEnd,
     MultiFrameMessageAck,
-    MultiFrameMessageAckFail { missing_fragments: Vec<u32> },
+    MultiFrameMessageAckFail {
+        missing_fragments: Vec<u32>,
+    },
+    // Padded to the size of a fragment frame with the given fragment size
+    MtuProbe {
+        fragment_size: usize,
+        padding: Vec<u8>,
+    },
+    MtuProbeAck {
+        fragment_size: usize,
+    },
+    // Frame id is the handshake id. Pinned handshakes authenticate the responder with the key the initiator has pinned
+    HandshakeInit {
+        pinned: bool,
+        data: Vec<u8>,
+    },
+    HandshakeResponse {
+        data: Vec<u8>,
+    },
+    // Frame id is the nonce. Data is another frame, encrypted with the session keys
+    Encrypted {
+        data: Vec<u8>,
+    },
+}
+
+impl FrameBody {
+    /// Whether the frame sets up or carries encryption, instead of carrying messages.
+    fn is_transport(&self) -> bool {
+        matches!(
+            self,
+            Self::HandshakeInit { .. } | Self::HandshakeResponse { .. } | Self::Encrypted { .. }
+        )
+    }
+
+    fn is_mtu_probe(&self) -> bool {
+        matches!(self, Self::MtuProbe { .. } | Self::MtuProbeAck { .. })
+    }
 }
 
 impl Debug for FrameBody {
@@ -762,6 +1153,11 @@ impl Debug for FrameBody {
             Self::MultiFrameMessageEnd => write!(f, "MultiFrameMessageEnd"),
             Self::MultiFrameMessageAck => write!(f, "MultiFrameMessageAck"),
             Self::MultiFrameMessageAckFail { .. } => write!(f, "MultiFrameMessageAckFail"),
+            Self::MtuProbe { .. } => write!(f, "MtuProbe"),
+            Self::MtuProbeAck { .. } => write!(f, "MtuProbeAck"),
+            Self::HandshakeInit { .. } => write!(f, "HandshakeInit"),
+            Self::HandshakeResponse { .. } => write!(f, "HandshakeResponse"),
+            Self::Encrypted { .. } => write!(f, "Encrypted"),
         }
     }
 }
@@ -808,6 +1204,43 @@ impl TryFrom<&[u8]> for NetworkFrame {
     }
 }
 
+/// Traffic between a socket and a single address.
+///
+/// Packets and bytes are counted as they go over the wire, including acks, resends and encryption handshakes.
+/// Path MTU probes are left out, as unanswered probes are expected and would look like packet loss.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrafficStats {
+    pub packets_sent: u64,
+    pub packets_received: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Packets sent again because the other end did not ack them in time.
+    pub resends: u64,
+    /// Reliable messages that were given up on, because they were not acked before their timeout.
+    pub lost_messages: u64,
+}
+
+impl TrafficStats {
+    /// Estimated share of packets lost on the way, from 0.0 to 1.0.
+    pub fn packet_loss(&self) -> f32 {
+        if self.packets_sent == 0 {
+            0.0
+        } else {
+            (self.resends as f32 / self.packets_sent as f32).min(1.0)
+        }
+    }
+
+    fn record_sent(&mut self, bytes: usize) {
+        self.packets_sent += 1;
+        self.bytes_sent += bytes as u64;
+    }
+
+    fn record_received(&mut self, bytes: usize) {
+        self.packets_received += 1;
+        self.bytes_received += bytes as u64;
+    }
+}
+
 struct SingleFrameAckDetails {
     sent_at_original: Instant,
     sent_count: u32,
@@ -817,6 +1250,46 @@ struct SingleFrameAckDetails {
     frame: NetworkFrame,
 }
 
+/// Fragment size to use on the path to an address. Datagrams larger than the path MTU are often dropped without a
+/// trace instead of being fragmented on the way, so the largest probed size that got through is used once known.
+struct PathMtu {
+    fragment_size: usize,
+    largest_acked: Option<usize>,
+    probes_sent: u32,
+    next_probe_at: Instant,
+}
+
+impl PathMtu {
+    fn new(now: Instant) -> Self {
+        Self {
+            fragment_size: DEFAULT_FRAGMENT_SIZE,
+            largest_acked: None,
+            probes_sent: 0,
+            next_probe_at: now,
+        }
+    }
+
+    /// Fragment sizes to probe now. Probes can be lost like any datagram, so unanswered ones are sent a few times.
+    fn probes_due(&mut self, now: Instant) -> Vec<usize> {
+        if self.probes_sent >= MTU_PROBE_ATTEMPTS || now < self.next_probe_at {
+            return Vec::new();
+        }
+        self.probes_sent += 1;
+        self.next_probe_at = now + MTU_PROBE_INTERVAL;
+        MTU_PROBE_FRAGMENT_SIZES
+            .into_iter()
+            .filter(|fragment_size| Some(*fragment_size) > self.largest_acked)
+            .collect()
+    }
+
+    fn probe_acked(&mut self, fragment_size: usize) {
+        if MTU_PROBE_FRAGMENT_SIZES.contains(&fragment_size) && Some(fragment_size) > self.largest_acked {
+            self.largest_acked = Some(fragment_size);
+            self.fragment_size = fragment_size;
+        }
+    }
+}
+
 struct MultiFrameAckDetails {
     msg_id: u64,
     timeout_at: Instant,
@@ -845,7 +1318,11 @@ mod tests {
     use bincode::{Decode, Encode};
 
     use crate::math::rand::Rng;
-    use crate::net::udp_network_socket::{MAX_UDP_PAYLOAD, UdpNetworkSocket};
+    use crate::net::udp_network_socket::{
+        BINCODE_CONFIG, DEFAULT_FRAGMENT_SIZE, FrameBody, MAX_UDP_PAYLOAD, MAX_UNVERIFIED_SENDS,
+        MTU_PROBE_FRAGMENT_SIZES, MTU_PROBE_INTERVAL, NetworkFrame, PathMtu, UdpNetworkSocket, dual_stack_bind_addr,
+        generate_private_key,
+    };
 
     fn catch_unwind_silent<F: FnOnce() -> R + panic::UnwindSafe, R>(f: F) -> thread::Result<R> {
         let prev_hook = panic::take_hook();
@@ -958,6 +1435,49 @@ mod tests {
         assert!(resp.is_none());
     }
 
+    #[test]
+    fn receiving_invalid_fragments_is_silently_ignored() {
+        let addr1 = SocketAddr::from(([127, 0, 0, 1], 3029));
+        let addr2 = SocketAddr::from(([127, 0, 0, 1], 3030));
+
+        let socket1: UdpNetworkSocket<SimpleMessage> = UdpNetworkSocket::new(addr1);
+        let custom_socket = UdpSocket::bind(addr2).unwrap();
+
+        let frames = [
+            FrameBody::MultiFrameMessageBegin {
+                total_fragments: usize::MAX,
+                total_size: 100,
+                fragment_size: 64,
+            },
+            FrameBody::MultiFrameMessageBegin {
+                total_fragments: 2,
+                total_size: 100,
+                fragment_size: 64,
+            },
+            FrameBody::MultiFrameMessageFragment {
+                fragment_id: usize::MAX,
+                data: vec![1; 64],
+            },
+            FrameBody::MultiFrameMessageFragment {
+                fragment_id: 2,
+                data: vec![1; 64],
+            },
+        ];
+        for frame_body in frames {
+            let frame = NetworkFrame::new(1, frame_body);
+            custom_socket.send_to(&Vec::<u8>::from(frame), addr1).unwrap();
+        }
+
+        // The socket still works
+        let data = bincode::encode_to_vec(SimpleMessage::SomeData(1), BINCODE_CONFIG).unwrap();
+        let frame = NetworkFrame::new(2, FrameBody::SingleFrameMessage { data });
+        custom_socket.send_to(&Vec::<u8>::from(frame), addr1).unwrap();
+        assert_eq!(
+            socket1.try_recv_timeout(Duration::from_secs(1)),
+            Some((addr2, SimpleMessage::SomeData(1)))
+        );
+    }
+
     #[test]
     fn not_getting_ack_triggers_message_resend_and_timeout() {
         let addr1 = SocketAddr::from(([127, 0, 0, 1], 3007));
@@ -1020,6 +1540,142 @@ mod tests {
         assert!(socket1.latency_of(addr2).unwrap() < Duration::from_millis(10));
     }
 
+    #[test]
+    fn traffic_stats_count_packets_and_resends() {
+        let addr1 = SocketAddr::from(([127, 0, 0, 1], 3020));
+        let addr2 = SocketAddr::from(([127, 0, 0, 1], 3021));
+        let addr3 = SocketAddr::from(([127, 0, 0, 1], 3022));
+
+        let socket1: UdpNetworkSocket<SimpleMessage> = UdpNetworkSocket::new(addr1);
+        let socket2: UdpNetworkSocket<SimpleMessage> = UdpNetworkSocket::new(addr2);
+        let _silent_socket = UdpSocket::bind(addr3).unwrap();
+
+        for i in 0..10 {
+            socket1.send(addr2, SimpleMessage::SomeData(i), Duration::from_secs(1));
+            assert!(socket2.try_recv_timeout(Duration::from_secs(1)).is_some());
+        }
+        sleep(Duration::from_millis(50));
+
+        let stats = socket1.traffic_stats_of(addr2).unwrap();
+        assert_eq!(stats.packets_sent, 10);
+        assert_eq!(stats.packets_received, 10);
+        assert!(stats.bytes_sent > 0 && stats.bytes_received > 0);
+        assert_eq!(socket2.traffic_stats_of(addr1).unwrap().packets_received, 10);
+
+        // Nothing is acked by a plain socket, so the message is resent until it is lost
+        socket1.send(addr3, SimpleMessage::SomeData(0), Duration::from_millis(500));
+        sleep(Duration::from_millis(700));
+        let stats = socket1.traffic_stats_of(addr3).unwrap();
+        assert!(stats.resends > 0);
+        assert_eq!(stats.packets_sent, stats.resends + 1);
+        assert_eq!(stats.lost_messages, 1);
+        assert!(stats.packet_loss() > 0.5);
+    }
+
+    #[test]
+    fn encrypted_sockets_exchange_messages() {
+        let addr1 = SocketAddr::from(([127, 0, 0, 1], 3014));
+        let addr2 = SocketAddr::from(([127, 0, 0, 1], 3015));
+        let addr3 = SocketAddr::from(([127, 0, 0, 1], 3016));
+
+        let socket1: UdpNetworkSocket<SimpleMessage> = UdpNetworkSocket::new_encrypted(addr1);
+        let socket2: UdpNetworkSocket<SimpleMessage> = UdpNetworkSocket::new_encrypted(addr2);
+        let socket3: UdpNetworkSocket<SimpleMessage> = UdpNetworkSocket::new(addr3);
+
+        let mut msg_vec: Vec<u8> = vec![0; 21964];
+        Rng::new(None).fill_random_bytes(msg_vec.as_mut_slice());
+        let msg = SimpleMessage::LotsOfBytes(msg_vec);
+
+        // Both encrypting sockets start a handshake at the same time
+        socket1.send(addr2, SimpleMessage::SomeData(1), Duration::from_secs(5));
+        socket2.send(addr1, msg.clone(), Duration::from_secs(5));
+        assert_eq!(
+            socket2.try_recv_timeout(Duration::from_secs(5)),
+            Some((addr1, SimpleMessage::SomeData(1)))
+        );
+        assert_eq!(socket1.try_recv_timeout(Duration::from_secs(5)), Some((addr2, msg)));
+
+        // Plain socket answers the handshake and replies encrypted
+        socket1.send(addr3, SimpleMessage::SomeData(2), Duration::from_secs(5));
+        assert_eq!(
+            socket3.try_recv_timeout(Duration::from_secs(5)),
+            Some((addr1, SimpleMessage::SomeData(2)))
+        );
+        socket3.send(addr1, SimpleMessage::SomeData(3), Duration::from_secs(5));
+        assert_eq!(
+            socket1.try_recv_timeout(Duration::from_secs(5)),
+            Some((addr3, SimpleMessage::SomeData(3)))
+        );
+    }
+
+    #[test]
+    fn pinned_sockets_talk_only_to_the_key_holder() {
+        let addr1 = SocketAddr::from(([127, 0, 0, 1], 3026));
+        let addr2 = SocketAddr::from(([127, 0, 0, 1], 3027));
+        let addr3 = SocketAddr::from(([127, 0, 0, 1], 3028));
+
+        let socket1: UdpNetworkSocket<SimpleMessage> = UdpNetworkSocket::new_encrypted(addr1);
+        let socket2: UdpNetworkSocket<SimpleMessage> =
+            UdpNetworkSocket::with_private_key(addr2, generate_private_key());
+        let socket3: UdpNetworkSocket<SimpleMessage> = UdpNetworkSocket::new(addr3);
+        socket1.pin_key(addr2, socket2.public_key());
+        socket1.pin_key(addr3, socket1.public_key());
+
+        socket1.send(addr2, SimpleMessage::SomeData(1), Duration::from_secs(5));
+        assert_eq!(
+            socket2.try_recv_timeout(Duration::from_secs(5)),
+            Some((addr1, SimpleMessage::SomeData(1)))
+        );
+        socket2.send(addr1, SimpleMessage::SomeData(2), Duration::from_secs(5));
+        assert_eq!(
+            socket1.try_recv_timeout(Duration::from_secs(5)),
+            Some((addr2, SimpleMessage::SomeData(2)))
+        );
+        assert!(socket1.has_session(addr2) && socket2.has_session(addr1));
+
+        // The socket at the address doesn't have the pinned key, so nothing gets through either way
+        socket1.send(addr3, SimpleMessage::SomeData(3), Duration::from_secs(1));
+        socket3.send(addr1, SimpleMessage::SomeData(4), Duration::from_secs(1));
+        assert_eq!(socket3.try_recv_timeout(Duration::from_secs(2)), None);
+        assert_eq!(socket1.try_recv_timeout(Duration::from_secs(1)), None);
+        assert!(!socket1.has_session(addr3));
+    }
+
+    #[test]
+    fn encrypted_messages_are_not_sent_in_plaintext() {
+        let addr1 = SocketAddr::from(([127, 0, 0, 1], 3017));
+        let addr2 = SocketAddr::from(([127, 0, 0, 1], 3018));
+        let sniffer_addr = SocketAddr::from(([127, 0, 0, 1], 3019));
+
+        let socket1: UdpNetworkSocket<SimpleMessage> = UdpNetworkSocket::new_encrypted(addr1);
+        let socket2: UdpNetworkSocket<SimpleMessage> = UdpNetworkSocket::new(addr2);
+        let sniffer = UdpSocket::bind(sniffer_addr).unwrap();
+        sniffer.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+
+        let secret = b"very secret chat message".to_vec();
+        socket1.send(
+            addr2,
+            SimpleMessage::LotsOfBytes(secret.clone()),
+            Duration::from_secs(5),
+        );
+        socket1.send(
+            sniffer_addr,
+            SimpleMessage::LotsOfBytes(secret.clone()),
+            Duration::from_secs(1),
+        );
+
+        assert_eq!(
+            socket2.try_recv_timeout(Duration::from_secs(5)),
+            Some((addr1, SimpleMessage::LotsOfBytes(secret.clone())))
+        );
+
+        // Nothing readable is sent before the other end has answered the handshake
+        let mut buf = [0; MAX_UDP_PAYLOAD];
+        while let Ok(len) = sniffer.recv(&mut buf) {
+            assert!(!buf[..len].windows(secret.len()).any(|window| window == secret));
+        }
+    }
+
     #[test]
     fn dropping_udp_network_socket_frees_the_bound_addr() {
         let addr = SocketAddr::from(([127, 0, 0, 1], 3011));
@@ -1045,6 +1701,51 @@ mod tests {
         println!("{:?}", ip);
     }
 
+    #[test]
+    fn path_mtu_is_probed_for_the_largest_fragment_size() {
+        let now = Instant::now();
+        let mut path_mtu = PathMtu::new(now);
+        assert_eq!(path_mtu.fragment_size, DEFAULT_FRAGMENT_SIZE);
+        assert_eq!(path_mtu.probes_due(now), MTU_PROBE_FRAGMENT_SIZES.to_vec());
+        assert!(path_mtu.probes_due(now).is_empty());
+
+        // Only the smallest probe got through, so the path is narrower than the default and only larger ones are resent
+        path_mtu.probe_acked(MTU_PROBE_FRAGMENT_SIZES[0]);
+        path_mtu.probe_acked(MTU_PROBE_FRAGMENT_SIZES[0] + 1);
+        assert_eq!(path_mtu.fragment_size, MTU_PROBE_FRAGMENT_SIZES[0]);
+        assert_eq!(
+            path_mtu.probes_due(now + MTU_PROBE_INTERVAL),
+            MTU_PROBE_FRAGMENT_SIZES[1..].to_vec()
+        );
+
+        path_mtu.probe_acked(MTU_PROBE_FRAGMENT_SIZES[2]);
+        path_mtu.probe_acked(MTU_PROBE_FRAGMENT_SIZES[1]);
+        assert_eq!(path_mtu.fragment_size, MTU_PROBE_FRAGMENT_SIZES[2]);
+
+        // Probing stops after a few attempts
+        path_mtu.probes_due(now + MTU_PROBE_INTERVAL * 2);
+        assert!(path_mtu.probes_due(now + MTU_PROBE_INTERVAL * 3).is_empty());
+    }
+
+    #[test]
+    fn dual_stack_sockets_exchange_messages_with_ipv4_sockets() {
+        let addr1 = dual_stack_bind_addr(3104);
+        let addr2 = SocketAddr::from(([127, 0, 0, 1], 3105));
+        let socket1: UdpNetworkSocket<SimpleMessage> = UdpNetworkSocket::new_encrypted(addr1);
+        let socket2: UdpNetworkSocket<SimpleMessage> = UdpNetworkSocket::new(addr2);
+
+        socket1.send(addr2, SimpleMessage::SomeData(1), Duration::from_secs(1));
+        let (from_addr, msg) = socket2.try_recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(msg, SimpleMessage::SomeData(1));
+
+        // Peers are seen with their plain ipv4 address, not the ipv4-mapped one
+        socket2.send(from_addr, SimpleMessage::SomeData(2), Duration::from_secs(1));
+        assert_eq!(
+            socket1.try_recv_timeout(Duration::from_secs(5)),
+            Some((addr2, SimpleMessage::SomeData(2)))
+        );
+    }
+
     #[test]
     fn sending_valid_huge_multiframe_messages_succeeds() {
         let addr1 = SocketAddr::from(([127, 0, 0, 1], 3101));
@@ -1066,4 +1767,53 @@ mod tests {
         assert!(resp.is_some());
         assert_eq!(resp.unwrap().1, msg);
     }
+
+    #[test]
+    fn limited_sockets_do_not_answer_floods() {
+        let addr1 = SocketAddr::from(([127, 0, 0, 1], 3023));
+        let addr2 = SocketAddr::from(([127, 0, 0, 1], 3024));
+        let silent_addr = SocketAddr::from(([127, 0, 0, 1], 3025));
+
+        let socket1: UdpNetworkSocket<SimpleMessage> = UdpNetworkSocket::new(addr1);
+        socket1.limit_sources(1, 5);
+        let custom_socket = UdpSocket::bind(addr2).unwrap();
+        custom_socket
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        let silent_socket = UdpSocket::bind(silent_addr).unwrap();
+        silent_socket
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+
+        // Frames beyond the burst are neither acked nor received
+        for id in 0..20 {
+            let data = bincode::encode_to_vec(SimpleMessage::SomeData(id), BINCODE_CONFIG).unwrap();
+            let frame = NetworkFrame::new(id as u64, FrameBody::SingleFrameMessage { data });
+            custom_socket.send_to(&Vec::<u8>::from(frame), addr1).unwrap();
+        }
+        let mut buf = [0; MAX_UDP_PAYLOAD];
+        let mut acks = 0;
+        while let Ok(len) = custom_socket.recv(&mut buf) {
+            let frame = UdpNetworkSocket::<SimpleMessage>::parse_frame(&buf[..len]);
+            if matches!(frame, Some((_, FrameBody::SingleFrameMessageAck))) {
+                acks += 1;
+            }
+        }
+        assert_eq!(acks, 5);
+        assert_eq!(socket1.try_recv_all().len(), 5);
+
+        // Addresses that have never acked anything get only a few resends
+        socket1.send(silent_addr, SimpleMessage::SomeData(0), Duration::from_secs(2));
+        let mut sends = 0;
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(2) {
+            if let Ok(len) = silent_socket.recv(&mut buf) {
+                let frame = UdpNetworkSocket::<SimpleMessage>::parse_frame(&buf[..len]);
+                if matches!(frame, Some((_, FrameBody::SingleFrameMessage { .. }))) {
+                    sends += 1;
+                }
+            }
+        }
+        assert_eq!(sends, MAX_UNVERIFIED_SENDS);
+    }
 }
@@ -0,0 +1,593 @@
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use snow::params::DHChoice;
+use snow::resolvers::{CryptoResolver, DefaultResolver};
+use snow::{Builder, HandshakeState, StatelessTransportState};
+
+use super::NetworkFrame;
+
+// ---------------------------------------------------------- //
+// ----------------------- Constants ------------------------ //
+// ---------------------------------------------------------- //
+
+/// Both peers generate ephemeral keys for every session. This keeps traffic private from anyone listening,
+/// but does not authenticate the peers, so anyone on the path between them can sit in the middle.
+const NOISE_PARAMS: &str = "Noise_NN_25519_ChaChaPoly_BLAKE2s";
+/// Used when the initiator has pinned the static key of the responder. The responder proves it has the private key,
+/// so nobody in the middle can read or change the traffic.
+const NOISE_PARAMS_PINNED: &str = "Noise_NK_25519_ChaChaPoly_BLAKE2s";
+
+const HANDSHAKE_RESEND_INTERVAL: Duration = Duration::from_millis(250);
+// Frames waiting for the handshake to finish, per address. Oldest are dropped first, and resent later if needed.
+const MAX_PENDING_FRAMES: usize = 1024;
+const MAX_HANDSHAKE_MSG_SIZE: usize = 256;
+// Handshakes and unconfirmed sessions are dropped if they don't finish in this time
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// Anyone can start handshakes from spoofed addresses, so the unconfirmed sessions are limited
+const MAX_UNCONFIRMED_SESSIONS_PER_IP: usize = 8;
+const MAX_UNCONFIRMED_SESSIONS: usize = 4096;
+// How far behind the newest nonce a frame may arrive. Older frames are dropped, as they could be replayed.
+const REPLAY_WINDOW: u64 = 1024;
+
+// ---------------------------------------------------------- //
+// ------------------- Encrypted sessions ------------------- //
+// ---------------------------------------------------------- //
+
+struct Session {
+    transport: StatelessTransportState,
+    // Pinned key of the peer that authenticated the session, none if the session is not authenticated
+    remote_key: Option<[u8; 32]>,
+    next_nonce: u64,
+    handshake_id: u64,
+    started_at: Instant,
+    // Response sent to the peer that started the handshake, resent if the peer didn't receive it
+    response: Option<Vec<u8>>,
+    replay_window: ReplayWindow,
+    // Frames waiting for the session to be confirmed
+    pending_frames: Vec<NetworkFrame>,
+}
+
+struct Handshake {
+    id: u64,
+    state: HandshakeState,
+    remote_key: Option<[u8; 32]>,
+    init_msg: Vec<u8>,
+    started_at: Instant,
+    init_sent_at: Option<Instant>,
+    pending_frames: Vec<NetworkFrame>,
+}
+
+/// Encrypted sessions of a socket, one per remote address.
+///
+/// A session is set up with a Noise handshake the first time a frame is sent to an address. Frames sent during the
+/// handshake are held back until it finishes. The nonce of each encrypted frame travels as its frame id, so frames
+/// can be decrypted in any order.
+///
+/// Sessions started by the other side stay unconfirmed until the first authentic frame arrives in them, as anyone can
+/// send a handshake from any address. Only confirmed sessions are used, and an unconfirmed session replaces the
+/// confirmed one with the address once it is confirmed, for example when the peer has restarted.
+///
+/// Sessions are not authenticated, unless the key of the address is pinned. Then only sessions from own handshakes
+/// that the peer answered with the private key are used, and handshakes started by the address are refused.
+pub(super) struct Encryption {
+    private_key: Vec<u8>,
+    pinned_keys: Arc<Mutex<HashMap<SocketAddr, [u8; 32]>>>,
+    // Addresses with a confirmed session, shared with the socket
+    session_addrs: Arc<RwLock<HashSet<SocketAddr>>>,
+    sessions: HashMap<SocketAddr, Session>,
+    unconfirmed_sessions: HashMap<SocketAddr, Session>,
+    handshakes: HashMap<SocketAddr, Handshake>,
+}
+
+impl Encryption {
+    pub(super) fn new(
+        private_key: Vec<u8>,
+        pinned_keys: Arc<Mutex<HashMap<SocketAddr, [u8; 32]>>>,
+        session_addrs: Arc<RwLock<HashSet<SocketAddr>>>,
+    ) -> Self {
+        Self {
+            private_key,
+            pinned_keys,
+            session_addrs,
+            sessions: HashMap::default(),
+            unconfirmed_sessions: HashMap::default(),
+            handshakes: HashMap::default(),
+        }
+    }
+
+    /// New static key pair of a socket, as the private and the public key.
+    pub(super) fn generate_keypair() -> (Vec<u8>, [u8; 32]) {
+        let keypair = Self::builder(false).generate_keypair().unwrap();
+        (keypair.private, keypair.public.try_into().unwrap())
+    }
+
+    /// Public key of a static private key.
+    pub(super) fn public_key_of(private_key: &[u8; 32]) -> [u8; 32] {
+        let mut dh = DefaultResolver.resolve_dh(&DHChoice::Curve25519).unwrap();
+        dh.set(private_key);
+        dh.pubkey().try_into().unwrap()
+    }
+
+    pub(super) fn has_session(&self, addr: SocketAddr) -> bool {
+        self.sessions.contains_key(&addr)
+    }
+
+    pub(super) fn is_pinned(&self, addr: SocketAddr) -> bool {
+        self.pinned_key(addr).is_some()
+    }
+
+    /// Encrypts the frame for the address. Returns the nonce and encrypted frame, or none if there is no session yet.
+    pub(super) fn seal(&mut self, addr: SocketAddr, frame: NetworkFrame) -> Option<(u64, Vec<u8>)> {
+        let pinned_key = self.pinned_key(addr);
+        let session = self
+            .sessions
+            .get_mut(&addr)
+            .filter(|session| session.is_authenticated_as(pinned_key))?;
+        let plaintext: Vec<u8> = frame.into();
+        let mut ciphertext = vec![0; plaintext.len() + 16];
+        let nonce = session.next_nonce;
+        session.next_nonce += 1;
+        let len = session
+            .transport
+            .write_message(nonce, &plaintext, &mut ciphertext)
+            .ok()?;
+        ciphertext.truncate(len);
+        Some((nonce, ciphertext))
+    }
+
+    /// Decrypts a frame received from the address. Returns none if there is no session, or the frame is not authentic
+    /// or has been received already.
+    ///
+    /// A frame that is authentic in the unconfirmed session confirms it. The frames that were waiting for the session
+    /// are then returned too.
+    pub(super) fn open(
+        &mut self,
+        addr: SocketAddr,
+        nonce: u64,
+        ciphertext: &[u8],
+    ) -> Option<(Vec<u8>, Vec<NetworkFrame>)> {
+        let pinned_key = self.pinned_key(addr);
+        if let Some(plaintext) = self
+            .sessions
+            .get_mut(&addr)
+            .filter(|session| session.is_authenticated_as(pinned_key))
+            .and_then(|session| session.open(nonce, ciphertext))
+        {
+            return Some((plaintext, Vec::new()));
+        }
+
+        let plaintext = self
+            .unconfirmed_sessions
+            .get_mut(&addr)
+            .filter(|session| session.is_authenticated_as(pinned_key))
+            .and_then(|session| session.open(nonce, ciphertext))?;
+        let mut session = self.unconfirmed_sessions.remove(&addr).unwrap();
+        let pending_frames = std::mem::take(&mut session.pending_frames);
+        self.sessions.insert(addr, session);
+        self.session_addrs.write().unwrap().insert(addr);
+        Some((plaintext, pending_frames))
+    }
+
+    /// Holds the frame back until the session with the address is set up. Returns the handshake id, whether the
+    /// handshake authenticates the peer with its pinned key, and the message to send, if the handshake should be
+    /// started or resent.
+    pub(super) fn queue(
+        &mut self,
+        addr: SocketAddr,
+        frame: NetworkFrame,
+        handshake_id: u64,
+    ) -> Option<(u64, bool, Vec<u8>)> {
+        let pinned_key = self.pinned_key(addr);
+        // The peer has started a session already, and sends its first frame in it soon
+        if let Some(session) = self
+            .unconfirmed_sessions
+            .get_mut(&addr)
+            .filter(|session| session.is_authenticated_as(pinned_key))
+        {
+            push_pending(&mut session.pending_frames, frame);
+            return None;
+        }
+
+        // The key was pinned after the handshake was started, so it is started again with the key
+        let mut pending_frames = Vec::new();
+        if self
+            .handshakes
+            .get(&addr)
+            .is_some_and(|handshake| handshake.remote_key != pinned_key)
+        {
+            pending_frames = self.handshakes.remove(&addr).unwrap().pending_frames;
+        }
+
+        let now = Instant::now();
+        let handshake = self.handshakes.entry(addr).or_insert_with(|| {
+            let mut state = match pinned_key {
+                Some(key) => Self::builder(true).remote_public_key(&key).build_initiator(),
+                None => Self::builder(false).build_initiator(),
+            }
+            .unwrap();
+            let mut init_msg = vec![0; MAX_HANDSHAKE_MSG_SIZE];
+            let len = state.write_message(&[], &mut init_msg).unwrap();
+            init_msg.truncate(len);
+            Handshake {
+                id: handshake_id,
+                state,
+                remote_key: pinned_key,
+                init_msg,
+                started_at: now,
+                init_sent_at: None,
+                pending_frames,
+            }
+        });
+        push_pending(&mut handshake.pending_frames, frame);
+
+        if handshake
+            .init_sent_at
+            .is_none_or(|sent_at| sent_at + HANDSHAKE_RESEND_INTERVAL < now)
+        {
+            handshake.init_sent_at = Some(now);
+            Some((handshake.id, handshake.remote_key.is_some(), handshake.init_msg.clone()))
+        } else {
+            None
+        }
+    }
+
+    /// Responds to a handshake started by the address, with an unconfirmed session. Returns the response message, or
+    /// none if the handshake is refused. Pinned handshakes are answered with the private key of this side.
+    ///
+    /// If both sides start a handshake at the same time, the pinned one is completed, and otherwise the one with the
+    /// larger id. Handshakes from addresses that have a pinned key are refused, as they don't authenticate the peer.
+    pub(super) fn accept(&mut self, addr: SocketAddr, id: u64, pinned: bool, init_msg: &[u8]) -> Option<Vec<u8>> {
+        if self.is_pinned(addr) {
+            return None;
+        }
+        if let Some(session) = self
+            .sessions
+            .get(&addr)
+            .into_iter()
+            .chain(self.unconfirmed_sessions.get(&addr))
+            .find(|session| session.handshake_id == id)
+        {
+            return session.response.clone();
+        }
+        // A pinned handshake wins, as the peer would refuse an unpinned one anyway
+        if !pinned && self.handshakes.get(&addr).is_some_and(|own| own.id > id) {
+            return None;
+        }
+        if !self.unconfirmed_sessions.contains_key(&addr) {
+            let from_ip = self
+                .unconfirmed_sessions
+                .keys()
+                .filter(|unconfirmed| unconfirmed.ip() == addr.ip())
+                .count();
+            if from_ip >= MAX_UNCONFIRMED_SESSIONS_PER_IP || self.unconfirmed_sessions.len() >= MAX_UNCONFIRMED_SESSIONS
+            {
+                return None;
+            }
+        }
+
+        let mut state = if pinned {
+            Self::builder(true)
+                .local_private_key(&self.private_key)
+                .build_responder()
+        } else {
+            Self::builder(false).build_responder()
+        }
+        .ok()?;
+        let mut payload = vec![0; MAX_HANDSHAKE_MSG_SIZE];
+        state.read_message(init_msg, &mut payload).ok()?;
+        let mut response = vec![0; MAX_HANDSHAKE_MSG_SIZE];
+        let len = state.write_message(&[], &mut response).ok()?;
+        response.truncate(len);
+
+        let transport = state.into_stateless_transport_mode().ok()?;
+        let mut session = Session::new(transport, None, id, Some(response.clone()));
+        // The own handshake lost, so its frames wait for this session instead
+        if let Some(own) = self.handshakes.remove(&addr) {
+            session.pending_frames = own.pending_frames;
+        }
+        self.unconfirmed_sessions.insert(addr, session);
+        Some(response)
+    }
+
+    /// Finishes a handshake this side started. Returns the frames that were waiting for the session.
+    pub(super) fn complete(&mut self, addr: SocketAddr, id: u64, response: &[u8]) -> Vec<NetworkFrame> {
+        let Some(handshake) = self.handshakes.get_mut(&addr).filter(|own| own.id == id) else {
+            return Vec::new();
+        };
+        let mut payload = vec![0; MAX_HANDSHAKE_MSG_SIZE];
+        if handshake.state.read_message(response, &mut payload).is_err() {
+            return Vec::new();
+        }
+
+        let handshake = self.handshakes.remove(&addr).unwrap();
+        match handshake.state.into_stateless_transport_mode() {
+            Ok(transport) => {
+                // The response is authentic, so the session is confirmed right away
+                self.sessions
+                    .insert(addr, Session::new(transport, handshake.remote_key, id, None));
+                self.session_addrs.write().unwrap().insert(addr);
+                handshake.pending_frames
+            }
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Drops the handshakes and unconfirmed sessions that have not finished in time, with the frames waiting for them.
+    pub(super) fn expire_incomplete(&mut self, now: Instant) {
+        self.handshakes
+            .retain(|_, handshake| handshake.started_at + HANDSHAKE_TIMEOUT > now);
+        self.unconfirmed_sessions
+            .retain(|_, session| session.started_at + HANDSHAKE_TIMEOUT > now);
+    }
+
+    fn pinned_key(&self, addr: SocketAddr) -> Option<[u8; 32]> {
+        self.pinned_keys.lock().unwrap().get(&addr).copied()
+    }
+
+    fn builder(pinned: bool) -> Builder<'static> {
+        let params = if pinned { NOISE_PARAMS_PINNED } else { NOISE_PARAMS };
+        Builder::new(params.parse().unwrap())
+    }
+}
+
+impl Session {
+    fn new(
+        transport: StatelessTransportState,
+        remote_key: Option<[u8; 32]>,
+        handshake_id: u64,
+        response: Option<Vec<u8>>,
+    ) -> Self {
+        Self {
+            transport,
+            remote_key,
+            next_nonce: 0,
+            handshake_id,
+            started_at: Instant::now(),
+            response,
+            replay_window: ReplayWindow::new(),
+            pending_frames: Vec::new(),
+        }
+    }
+
+    /// Whether the session can be used with a peer that has the pinned key, if any.
+    fn is_authenticated_as(&self, pinned_key: Option<[u8; 32]>) -> bool {
+        pinned_key.is_none_or(|key| self.remote_key == Some(key))
+    }
+
+    fn open(&mut self, nonce: u64, ciphertext: &[u8]) -> Option<Vec<u8>> {
+        if !self.replay_window.is_new(nonce) {
+            return None;
+        }
+        let mut plaintext = vec![0; ciphertext.len()];
+        let len = self.transport.read_message(nonce, ciphertext, &mut plaintext).ok()?;
+        plaintext.truncate(len);
+        // Only authentic frames move the window, so forged ones can't push real ones out of it
+        self.replay_window.mark(nonce);
+        Some(plaintext)
+    }
+}
+
+fn push_pending(pending_frames: &mut Vec<NetworkFrame>, frame: NetworkFrame) {
+    if pending_frames.len() >= MAX_PENDING_FRAMES {
+        pending_frames.remove(0);
+    }
+    pending_frames.push(frame);
+}
+
+// ---------------------------------------------------------- //
+// --------------------- Replay window ---------------------- //
+// ---------------------------------------------------------- //
+
+/// Nonces received in a session, within [`REPLAY_WINDOW`] of the newest one.
+struct ReplayWindow {
+    // One above the newest nonce received
+    next: u64,
+    // Bit for each nonce in the window, indexed by the nonce modulo the window size
+    received: [u64; (REPLAY_WINDOW / 64) as usize],
+}
+
+impl ReplayWindow {
+    fn new() -> Self {
+        Self {
+            next: 0,
+            received: [0; (REPLAY_WINDOW / 64) as usize],
+        }
+    }
+
+    fn is_new(&self, nonce: u64) -> bool {
+        if nonce >= self.next {
+            return true;
+        }
+        nonce.saturating_add(REPLAY_WINDOW) >= self.next && !self.bit(nonce)
+    }
+
+    fn mark(&mut self, nonce: u64) {
+        if nonce >= self.next {
+            if nonce - self.next >= REPLAY_WINDOW {
+                self.received = [0; (REPLAY_WINDOW / 64) as usize];
+            } else {
+                // The bits of the skipped nonces belonged to nonces that have now left the window
+                for skipped in self.next..nonce {
+                    self.set_bit(skipped, false);
+                }
+            }
+            self.next = nonce.saturating_add(1);
+        }
+        self.set_bit(nonce, true);
+    }
+
+    fn bit(&self, nonce: u64) -> bool {
+        let index = nonce % REPLAY_WINDOW;
+        self.received[(index / 64) as usize] & (1 << (index % 64)) != 0
+    }
+
+    fn set_bit(&mut self, nonce: u64, value: bool) {
+        let index = nonce % REPLAY_WINDOW;
+        let word = &mut self.received[(index / 64) as usize];
+        if value {
+            *word |= 1 << (index % 64);
+        } else {
+            *word &= !(1 << (index % 64));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use super::{Encryption, HANDSHAKE_TIMEOUT, HashMap, MAX_UNCONFIRMED_SESSIONS_PER_IP, REPLAY_WINDOW, ReplayWindow};
+    use crate::net::udp_network_socket::{FrameBody, NetworkFrame};
+
+    fn frame(data: u8) -> NetworkFrame {
+        NetworkFrame::new(0, FrameBody::SingleFrameMessage { data: vec![data] })
+    }
+
+    fn new_encryption() -> Encryption {
+        let (private_key, _) = Encryption::generate_keypair();
+        Encryption::new(private_key, Default::default(), Default::default())
+    }
+
+    fn data_of(plaintext: &[u8]) -> Vec<u8> {
+        match NetworkFrame::try_from(plaintext).unwrap().frame_body {
+            FrameBody::SingleFrameMessage { data } => data,
+            body => panic!("Unexpected frame {:?}", body),
+        }
+    }
+
+    /// Sets up a session from the initiator to the responder, confirmed on both sides.
+    fn connect(
+        initiator: &mut Encryption,
+        initiator_addr: SocketAddr,
+        responder: &mut Encryption,
+        responder_addr: SocketAddr,
+        handshake_id: u64,
+    ) {
+        let (id, pinned, init_msg) = initiator.queue(responder_addr, frame(1), handshake_id).unwrap();
+        let response = responder.accept(initiator_addr, id, pinned, &init_msg).unwrap();
+        let pending_frames = initiator.complete(responder_addr, id, &response);
+        assert_eq!(pending_frames.len(), 1);
+
+        let (nonce, ciphertext) = initiator.seal(responder_addr, frame(1)).unwrap();
+        let (plaintext, _) = responder.open(initiator_addr, nonce, &ciphertext).unwrap();
+        assert_eq!(data_of(&plaintext), vec![1]);
+        assert!(responder.has_session(initiator_addr));
+    }
+
+    #[test]
+    fn replayed_frames_are_dropped() {
+        let addr1 = SocketAddr::from(([127, 0, 0, 1], 1));
+        let addr2 = SocketAddr::from(([127, 0, 0, 1], 2));
+        let mut encryption1 = new_encryption();
+        let mut encryption2 = new_encryption();
+        connect(&mut encryption1, addr1, &mut encryption2, addr2, 7);
+
+        let (nonce, ciphertext) = encryption1.seal(addr2, frame(2)).unwrap();
+        assert!(encryption2.open(addr1, nonce, &ciphertext).is_some());
+        assert!(encryption2.open(addr1, nonce, &ciphertext).is_none());
+    }
+
+    #[test]
+    fn established_session_is_replaced_only_once_the_new_one_is_confirmed() {
+        let addr1 = SocketAddr::from(([127, 0, 0, 1], 1));
+        let addr2 = SocketAddr::from(([127, 0, 0, 1], 2));
+        let mut encryption1 = new_encryption();
+        let mut encryption2 = new_encryption();
+        connect(&mut encryption1, addr1, &mut encryption2, addr2, 7);
+
+        // Anyone can send a handshake from the address, but it doesn't take the session over
+        let mut spoofer = new_encryption();
+        let (id, pinned, init_msg) = spoofer.queue(addr2, frame(3), 8).unwrap();
+        assert!(encryption2.accept(addr1, id, pinned, &init_msg).is_some());
+        let (nonce, ciphertext) = encryption1.seal(addr2, frame(4)).unwrap();
+        let (plaintext, _) = encryption2.open(addr1, nonce, &ciphertext).unwrap();
+        assert_eq!(data_of(&plaintext), vec![4]);
+
+        // A restarted peer takes the session over with its first frame
+        let mut restarted = new_encryption();
+        connect(&mut restarted, addr1, &mut encryption2, addr2, 9);
+        let (nonce, ciphertext) = encryption1.seal(addr2, frame(5)).unwrap();
+        assert!(encryption2.open(addr1, nonce, &ciphertext).is_none());
+    }
+
+    #[test]
+    fn pinned_peers_must_prove_their_key() {
+        let addr1 = SocketAddr::from(([127, 0, 0, 1], 1));
+        let addr2 = SocketAddr::from(([127, 0, 0, 1], 2));
+        let (private_key, public_key) = Encryption::generate_keypair();
+        assert_eq!(
+            Encryption::public_key_of(&private_key.clone().try_into().unwrap()),
+            public_key
+        );
+        let pinned_keys = Arc::new(Mutex::new(HashMap::from([(addr2, public_key)])));
+        let pinned_encryption = || {
+            Encryption::new(
+                Encryption::generate_keypair().0,
+                pinned_keys.clone(),
+                Default::default(),
+            )
+        };
+        let mut encryption2 = Encryption::new(private_key, Default::default(), Default::default());
+
+        // Someone in the middle can't answer the handshake without the private key
+        let mut encryption1 = pinned_encryption();
+        let mut middle = new_encryption();
+        let (id, pinned, init_msg) = encryption1.queue(addr2, frame(1), 7).unwrap();
+        assert!(pinned);
+        assert!(middle.accept(addr1, id, pinned, &init_msg).is_none());
+        let (unpinned_id, pinned, init_msg) = new_encryption().queue(addr2, frame(1), id).unwrap();
+        let response = middle.accept(addr1, unpinned_id, pinned, &init_msg).unwrap();
+        assert!(encryption1.complete(addr2, id, &response).is_empty());
+        assert!(!encryption1.has_session(addr2));
+
+        // Nor start a session of its own from the address
+        let (id, pinned, init_msg) = new_encryption().queue(addr1, frame(2), 9).unwrap();
+        assert!(encryption1.accept(addr2, id, pinned, &init_msg).is_none());
+
+        let mut encryption1 = pinned_encryption();
+        connect(&mut encryption1, addr1, &mut encryption2, addr2, 7);
+        let (nonce, ciphertext) = encryption2.seal(addr1, frame(3)).unwrap();
+        let (plaintext, _) = encryption1.open(addr2, nonce, &ciphertext).unwrap();
+        assert_eq!(data_of(&plaintext), vec![3]);
+    }
+
+    #[test]
+    fn unconfirmed_sessions_are_limited_and_expire() {
+        let addr = SocketAddr::from(([127, 0, 0, 1], 1));
+        let mut encryption = new_encryption();
+        for port in 0..=MAX_UNCONFIRMED_SESSIONS_PER_IP as u16 {
+            let mut initiator = new_encryption();
+            let (id, pinned, init_msg) = initiator.queue(addr, frame(1), 1).unwrap();
+            let from_addr = SocketAddr::from(([127, 0, 0, 2], port));
+            let accepted = encryption.accept(from_addr, id, pinned, &init_msg);
+            assert_eq!(accepted.is_some(), (port as usize) < MAX_UNCONFIRMED_SESSIONS_PER_IP);
+            assert!(!encryption.has_session(from_addr));
+        }
+        assert_eq!(encryption.unconfirmed_sessions.len(), MAX_UNCONFIRMED_SESSIONS_PER_IP);
+
+        encryption.queue(addr, frame(1), 1).unwrap();
+        encryption.expire_incomplete(Instant::now() + HANDSHAKE_TIMEOUT + Duration::from_secs(1));
+        assert!(encryption.unconfirmed_sessions.is_empty());
+        assert!(encryption.handshakes.is_empty());
+    }
+
+    #[test]
+    fn replay_window_accepts_each_recent_nonce_once() {
+        let mut window = ReplayWindow::new();
+        for nonce in [5, 3, 4, 0, REPLAY_WINDOW + 2] {
+            assert!(window.is_new(nonce));
+            window.mark(nonce);
+            assert!(!window.is_new(nonce));
+        }
+        // Too old to tell whether it was received
+        assert!(!window.is_new(1));
+        assert!(window.is_new(REPLAY_WINDOW + 1));
+        assert!(!window.is_new(5));
+        assert!(window.is_new(REPLAY_WINDOW * 3));
+    }
+}
@@ -0,0 +1,847 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bincode::{Decode, Encode, config::Configuration};
+
+use crate::net::tls::{TlsServerConfig, TlsStream};
+
+#[cfg(target_arch = "wasm32")]
+pub use self::browser::WsNetworkSocket;
+
+// ---------------------------------------------------------- //
+// ----------------------- Constants ------------------------ //
+// ---------------------------------------------------------- //
+
+// Appended to the key of the client when accepting a handshake, see RFC 6455
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const MAX_HANDSHAKE_SIZE: usize = 8 * 1024;
+// Browsers only send small messages, such as actions and join requests
+const MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+// Connections are refused while this many are open or handshaking, as each one has its own thread
+const MAX_CONNECTIONS: usize = 256;
+// The whole handshake has to arrive in this time
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+// Clients send keepalives, so a connection that sends nothing for this long is gone
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+// Sends to a browser that doesn't read fail after this, instead of blocking the sender
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+// TLS connections are read and written through one lock, which reads give up this often so that sends get their turn
+const TLS_READ_INTERVAL: Duration = Duration::from_millis(10);
+const MSG_BUFFER_SIZE: usize = 512; // Number of incoming messages that can be buffered before the backpressure kicks in
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+
+const BINCODE_CONFIG: Configuration = bincode::config::standard();
+
+// ---------------------------------------------------------- //
+// ---------------------- Ws listener ----------------------- //
+// ---------------------------------------------------------- //
+
+/// Accepts WebSocket connections from browser builds, so that a native server can talk to them next to its udp socket.
+///
+/// Each connection is identified by the address of its TCP peer, and every message is sent bincode encoded in
+/// one binary frame. Public servers should encrypt the connections, either with a certificate of their own, see
+/// [`WsNetworkListener::with_tls`], or behind a proxy that terminates TLS, see [`WsNetworkListener::with_tls_proxies`].
+pub struct WsNetworkListener<T>
+where
+    T: 'static + Debug + Send + Encode + Decode<()>,
+{
+    local_addr: SocketAddr,
+    listener_on: Arc<AtomicBool>,
+    // Default HashMap is used instead of faster 'Map' from this crate, as peer addresses are untrusted inputs
+    connections: Arc<Mutex<HashMap<SocketAddr, Connection>>>,
+    msg_in_receiver: Mutex<Receiver<(SocketAddr, T)>>,
+}
+
+struct Connection {
+    writer: ConnectionWriter,
+    /// Whether the browser connected over TLS, to the listener or to the proxy in front of it.
+    is_secure: bool,
+}
+
+/// Plain connections are written through a clone of the stream, while the connection thread reads the original.
+/// TLS connections have one session for both, so they share it.
+enum ConnectionWriter {
+    Plain(TcpStream),
+    Tls(Arc<Mutex<TlsStream>>),
+}
+
+impl ConnectionWriter {
+    fn write_frame(&self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        match self {
+            ConnectionWriter::Plain(stream) => write_frame(&mut &*stream, opcode, payload),
+            ConnectionWriter::Tls(stream) => {
+                let mut stream = stream.lock().unwrap();
+                write_frame(&mut *stream, opcode, payload)?;
+                stream.flush()
+            }
+        }
+    }
+
+    fn shutdown(&self) {
+        match self {
+            ConnectionWriter::Plain(stream) => stream.shutdown(Shutdown::Both).ok(),
+            ConnectionWriter::Tls(stream) => stream.lock().unwrap().tcp_stream().shutdown(Shutdown::Both).ok(),
+        };
+    }
+}
+
+impl<T> WsNetworkListener<T>
+where
+    T: 'static + Debug + Send + Encode + Decode<()>,
+{
+    pub fn new(bind_addr: SocketAddr) -> io::Result<Self> {
+        Self::bind(bind_addr, None, Vec::new())
+    }
+
+    /// Creates a listener that terminates TLS itself, so that every connection is secure, see
+    /// [`WsNetworkListener::is_secure`]. Browsers have to connect by a host name the certificate is for.
+    pub fn with_tls(bind_addr: SocketAddr, tls: TlsServerConfig) -> io::Result<Self> {
+        Self::bind(bind_addr, Some(tls), Vec::new())
+    }
+
+    /// Creates a listener behind proxies that terminate TLS for it. Connections from the proxies whose handshake says
+    /// `X-Forwarded-Proto: https` are secure, see [`WsNetworkListener::is_secure`]. Anyone else could send the header
+    /// too, so it is ignored from other addresses.
+    pub fn with_tls_proxies(bind_addr: SocketAddr, tls_proxies: Vec<IpAddr>) -> io::Result<Self> {
+        Self::bind(bind_addr, None, tls_proxies)
+    }
+
+    fn bind(bind_addr: SocketAddr, tls: Option<TlsServerConfig>, tls_proxies: Vec<IpAddr>) -> io::Result<Self> {
+        let listener = TcpListener::bind(bind_addr)?;
+        listener.set_nonblocking(true)?;
+        let local_addr = listener.local_addr()?;
+
+        let listener_on = Arc::new(AtomicBool::new(true));
+        let connections = Arc::new(Mutex::new(HashMap::new()));
+        let (msg_in_sender, msg_in_receiver) = mpsc::sync_channel(MSG_BUFFER_SIZE);
+
+        thread::Builder::new().name("ws_network_listener".to_owned()).spawn({
+            let listener_on = listener_on.clone();
+            let connections = connections.clone();
+            let connection_count = Arc::new(AtomicUsize::new(0));
+            move || {
+                while listener_on.load(Ordering::Relaxed) {
+                    match listener.accept() {
+                        // Dropping the stream closes the connection
+                        Ok(_) if connection_count.load(Ordering::Relaxed) >= MAX_CONNECTIONS => {}
+                        Ok((stream, addr)) => Self::spawn_connection_thread(
+                            stream,
+                            addr,
+                            tls.clone(),
+                            tls_proxies.contains(&addr.ip().to_canonical()),
+                            connections.clone(),
+                            connection_count.clone(),
+                            msg_in_sender.clone(),
+                        ),
+                        Err(_) => thread::sleep(Duration::from_millis(10)),
+                    }
+                }
+            }
+        })?;
+
+        Ok(Self {
+            local_addr,
+            listener_on,
+            connections,
+            msg_in_receiver: Mutex::new(msg_in_receiver),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn is_connected(&self, addr: SocketAddr) -> bool {
+        self.connections.lock().unwrap().contains_key(&addr)
+    }
+
+    /// Whether the browser connected from the address is encrypted, up to the listener or the TLS proxy it came
+    /// through.
+    pub fn is_secure(&self, addr: SocketAddr) -> bool {
+        self.connections
+            .lock()
+            .unwrap()
+            .get(&addr)
+            .is_some_and(|connection| connection.is_secure)
+    }
+
+    /// Sends the message to the browser connected from the address. Returns false if there is no such connection.
+    pub fn send(&self, addr: SocketAddr, msg: T) -> bool {
+        let mut connections = self.connections.lock().unwrap();
+        let Some(connection) = connections.get_mut(&addr) else {
+            return false;
+        };
+        let payload = bincode::encode_to_vec(msg, BINCODE_CONFIG).unwrap();
+        if connection.writer.write_frame(OPCODE_BINARY, &payload).is_err() {
+            connections.remove(&addr);
+        }
+        true
+    }
+
+    pub fn try_recv_all(&self) -> Vec<(SocketAddr, T)> {
+        self.msg_in_receiver.lock().unwrap().try_iter().collect()
+    }
+
+    fn spawn_connection_thread(
+        stream: TcpStream,
+        addr: SocketAddr,
+        tls: Option<TlsServerConfig>,
+        is_tls_proxy: bool,
+        connections: Arc<Mutex<HashMap<SocketAddr, Connection>>>,
+        connection_count: Arc<AtomicUsize>,
+        msg_in_sender: SyncSender<(SocketAddr, T)>,
+    ) {
+        connection_count.fetch_add(1, Ordering::Relaxed);
+        // If the thread can't be spawned, the stream is dropped and the browser sees the connection fail
+        let spawned = thread::Builder::new().name("ws_network_connection".to_owned()).spawn({
+            let connection_count = connection_count.clone();
+            move || {
+                let accepted = stream
+                    .set_nonblocking(false)
+                    .and_then(|_| stream.set_nodelay(true))
+                    .and_then(|_| stream.set_write_timeout(Some(WRITE_TIMEOUT)))
+                    .and_then(|_| TlsStream::accept(stream, tls.as_ref()))
+                    .and_then(|mut stream| {
+                        let is_forwarded_https = accept_handshake(&mut stream)?;
+                        Ok((stream, is_forwarded_https))
+                    });
+                if let Ok((stream, is_forwarded_https)) = accepted {
+                    let is_secure = stream.is_encrypted() || (is_tls_proxy && is_forwarded_https);
+                    match stream {
+                        TlsStream::Plain(mut stream) => {
+                            let writer = stream.try_clone().map(ConnectionWriter::Plain);
+                            if let Ok(writer) = writer
+                                && stream.set_read_timeout(Some(IDLE_TIMEOUT)).is_ok()
+                            {
+                                connections
+                                    .lock()
+                                    .unwrap()
+                                    .insert(addr, Connection { writer, is_secure });
+                                Self::receive_messages(&mut stream, addr, &msg_in_sender);
+                            }
+                        }
+                        stream => {
+                            let stream = Arc::new(Mutex::new(stream));
+                            let writer = ConnectionWriter::Tls(stream.clone());
+                            connections
+                                .lock()
+                                .unwrap()
+                                .insert(addr, Connection { writer, is_secure });
+                            Self::receive_messages(&mut SharedTlsReader::new(stream), addr, &msg_in_sender);
+                        }
+                    }
+                    if let Some(Connection { writer, .. }) = connections.lock().unwrap().remove(&addr) {
+                        writer.write_frame(OPCODE_CLOSE, &[]).ok();
+                        writer.shutdown();
+                    }
+                }
+                connection_count.fetch_sub(1, Ordering::Relaxed);
+            }
+        });
+        if spawned.is_err() {
+            connection_count.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<T> WsNetworkListener<T>
+where
+    T: 'static + Debug + Send + Encode + Decode<()>,
+{
+    /// Passes the messages of the connection on until it is closed or fails.
+    fn receive_messages(stream: &mut impl Read, addr: SocketAddr, msg_in_sender: &SyncSender<(SocketAddr, T)>) {
+        while let Ok(Some(payload)) = read_message(stream) {
+            if let Ok((msg, _)) = bincode::decode_from_slice(&payload, BINCODE_CONFIG)
+                && msg_in_sender.send((addr, msg)).is_err()
+            {
+                break;
+            }
+        }
+    }
+}
+
+/// Reads a TLS connection that is written from other threads too. Each read holds the lock for at most
+/// [`TLS_READ_INTERVAL`], and fails once nothing has arrived for [`IDLE_TIMEOUT`].
+struct SharedTlsReader {
+    stream: Arc<Mutex<TlsStream>>,
+    last_read: Instant,
+}
+
+impl SharedTlsReader {
+    fn new(stream: Arc<Mutex<TlsStream>>) -> Self {
+        Self {
+            stream,
+            last_read: Instant::now(),
+        }
+    }
+}
+
+impl Read for SharedTlsReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let result = {
+                let mut stream = self.stream.lock().unwrap();
+                stream.tcp_stream().set_read_timeout(Some(TLS_READ_INTERVAL))?;
+                stream.read(buf)
+            };
+            match result {
+                Ok(len) => {
+                    self.last_read = Instant::now();
+                    return Ok(len);
+                }
+                Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                    if self.last_read.elapsed() > IDLE_TIMEOUT {
+                        return Err(err);
+                    }
+                    // Lets a waiting sender take the lock before reading again
+                    thread::yield_now();
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl<T> Drop for WsNetworkListener<T>
+where
+    T: 'static + Debug + Send + Encode + Decode<()>,
+{
+    fn drop(&mut self) {
+        self.listener_on.store(false, Ordering::Relaxed);
+        for (_, connection) in self.connections.lock().unwrap().drain() {
+            connection.writer.shutdown();
+        }
+    }
+}
+
+// ---------------------------------------------------------- //
+// ------------------- WebSocket protocol ------------------- //
+// ---------------------------------------------------------- //
+
+/// Reads the HTTP upgrade request of the client, and answers that the connection is now a WebSocket.
+/// Fails if the request, and the TLS handshake before it, don't arrive within [`HANDSHAKE_TIMEOUT`]. Returns whether
+/// the request says it was forwarded from https, which only proxies can be trusted with.
+fn accept_handshake(stream: &mut TlsStream) -> io::Result<bool> {
+    let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, reason.to_owned());
+
+    let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.ends_with(b"\r\n\r\n") {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "Handshake timed out"));
+        }
+        stream.tcp_stream().set_read_timeout(Some(remaining))?;
+        let len = stream.read(&mut buf)?;
+        if len == 0 || request.len() + len > MAX_HANDSHAKE_SIZE {
+            return Err(invalid("Invalid handshake"));
+        }
+        request.extend_from_slice(&buf[..len]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let header = |name: &str| {
+        request
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim())
+    };
+    let key = header("Sec-WebSocket-Key").ok_or_else(|| invalid("Missing WebSocket key"))?;
+    let is_forwarded_https = header("X-Forwarded-Proto").is_some_and(|proto| proto.eq_ignore_ascii_case("https"));
+
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        handshake_accept_key(key)
+    );
+    stream.write_all(response.as_bytes())?;
+    stream.flush()?;
+    Ok(is_forwarded_https)
+}
+
+fn handshake_accept_key(key: &str) -> String {
+    base64(&sha1(format!("{}{}", key, HANDSHAKE_GUID).as_bytes()))
+}
+
+/// Reads frames until a whole message has arrived. Returns none when the client closes the connection.
+/// Pings are not answered, as browsers don't send them.
+fn read_message(stream: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut message = Vec::new();
+    loop {
+        let mut header = [0; 2];
+        stream.read_exact(&mut header)?;
+        let is_final = header[0] & 0x80 != 0;
+        let opcode = header[0] & 0x0f;
+        let is_masked = header[1] & 0x80 != 0;
+        let len = match header[1] & 0x7f {
+            126 => {
+                let mut len = [0; 2];
+                stream.read_exact(&mut len)?;
+                u16::from_be_bytes(len) as usize
+            }
+            127 => {
+                let mut len = [0; 8];
+                stream.read_exact(&mut len)?;
+                u64::from_be_bytes(len) as usize
+            }
+            len => len as usize,
+        };
+        if len > MAX_MESSAGE_SIZE - message.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "WebSocket message too large",
+            ));
+        }
+
+        let mut mask = [0; 4];
+        if is_masked {
+            stream.read_exact(&mut mask)?;
+        }
+        let mut payload = vec![0; len];
+        stream.read_exact(&mut payload)?;
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+
+        match opcode {
+            OPCODE_CLOSE => return Ok(None),
+            OPCODE_CONTINUATION | OPCODE_BINARY => {
+                message.append(&mut payload);
+                if is_final {
+                    return Ok(Some(message));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Writes a single unmasked frame, as servers send them.
+fn write_frame(stream: &mut impl Write, opcode: u8, payload: &[u8]) -> io::Result<()> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len if len < 126 => frame.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    stream.write_all(&frame)
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    let mut padded = data.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in padded.chunks(64) {
+        let mut words = [0u32; 80];
+        for (word, bytes) in words.iter_mut().zip(block.chunks(4)) {
+            *word = u32::from_be_bytes(bytes.try_into().unwrap());
+        }
+        for i in 16..80 {
+            words[i] = (words[i - 3] ^ words[i - 8] ^ words[i - 14] ^ words[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, word) in words.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (value, add) in state.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(add);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (bytes, value) in digest.chunks_mut(4).zip(state) {
+        bytes.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], chunk.get(1).copied().unwrap_or(0), chunk.get(2).copied().unwrap_or(0)];
+        let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+// ---------------------------------------------------------- //
+// -------------------- Browser socket ---------------------- //
+// ---------------------------------------------------------- //
+
+#[cfg(target_arch = "wasm32")]
+mod browser {
+    use std::collections::VecDeque;
+    use std::fmt::Debug;
+    use std::net::{IpAddr, SocketAddr};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use bincode::{Decode, Encode};
+    use js_sys::{ArrayBuffer, Uint8Array};
+    use wasm_bindgen::JsCast;
+    use wasm_bindgen::JsValue;
+    use wasm_bindgen::closure::Closure;
+    use web_sys::{BinaryType, MessageEvent, WebSocket};
+
+    use crate::log_warn;
+    use crate::net::transport::NetworkTransport;
+    use crate::net::udp_network_socket::TrafficStats;
+
+    use super::BINCODE_CONFIG;
+
+    /// Connection of a browser build to the WebSocket bridge of a server, see [`super::WsNetworkListener`].
+    ///
+    /// The browser opens the connection in the background. Messages sent before it is open are queued.
+    pub struct WsNetworkSocket<T>
+    where
+        T: 'static + Debug + Send + Encode + Decode<()>,
+    {
+        server_addr: SocketAddr,
+        socket: WebSocket,
+        outgoing: Arc<Mutex<VecDeque<Vec<u8>>>>,
+        incoming: Arc<Mutex<VecDeque<T>>>,
+        _on_open: Closure<dyn FnMut(JsValue)>,
+        _on_message: Closure<dyn FnMut(MessageEvent)>,
+    }
+
+    // SAFETY: Browser builds run without shared memory, so the socket and its callbacks are only ever used from the
+    // thread that created them.
+    unsafe impl<T> Send for WsNetworkSocket<T> where T: 'static + Debug + Send + Encode + Decode<()> {}
+    unsafe impl<T> Sync for WsNetworkSocket<T> where T: 'static + Debug + Send + Encode + Decode<()> {}
+
+    impl<T> WsNetworkSocket<T>
+    where
+        T: 'static + Debug + Send + Encode + Decode<()>,
+    {
+        /// Connects to the bridge of the server, which listens on the same port as the udp socket of the server.
+        /// With a host name the connection is made over TLS to the host, which the certificate of the bridge has to be
+        /// for. Without one, pages served over https connect to the address with TLS too, as browsers refuse
+        /// unencrypted WebSockets from them.
+        pub fn connect(server_addr: SocketAddr, ws_host: Option<&str>) -> Self {
+            let url = match ws_host {
+                Some(ws_host) => format!("wss://{}:{}", ws_host, server_addr.port()),
+                None => {
+                    let is_https = web_sys::window()
+                        .and_then(|window| window.location().protocol().ok())
+                        .is_some_and(|protocol| protocol == "https:");
+                    let scheme = if is_https { "wss" } else { "ws" };
+                    format!("{}://{}", scheme, server_addr)
+                }
+            };
+            let socket = WebSocket::new(&url).expect("Invalid WebSocket address");
+            socket.set_binary_type(BinaryType::Arraybuffer);
+
+            let outgoing = Arc::new(Mutex::new(VecDeque::<Vec<u8>>::new()));
+            let incoming = Arc::new(Mutex::new(VecDeque::new()));
+
+            let on_open = Closure::wrap(Box::new({
+                let socket = socket.clone();
+                let outgoing = outgoing.clone();
+                move |_event: JsValue| {
+                    for payload in outgoing.lock().unwrap().drain(..) {
+                        socket.send_with_u8_array(&payload).ok();
+                    }
+                }
+            }) as Box<dyn FnMut(JsValue)>);
+            socket.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+
+            let on_message = Closure::wrap(Box::new({
+                let incoming = incoming.clone();
+                move |event: MessageEvent| {
+                    let Ok(buffer) = event.data().dyn_into::<ArrayBuffer>() else {
+                        return;
+                    };
+                    match bincode::decode_from_slice(&Uint8Array::new(&buffer).to_vec(), BINCODE_CONFIG) {
+                        Ok((msg, _)) => incoming.lock().unwrap().push_back(msg),
+                        Err(err) => {
+                            log_warn!("Invalid WebSocket message: {}", err);
+                        }
+                    }
+                }
+            }) as Box<dyn FnMut(MessageEvent)>);
+            socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+            Self {
+                server_addr,
+                socket,
+                outgoing,
+                incoming,
+                _on_open: on_open,
+                _on_message: on_message,
+            }
+        }
+    }
+
+    impl<T> NetworkTransport<T> for WsNetworkSocket<T>
+    where
+        T: 'static + Debug + Send + Encode + Decode<()>,
+    {
+        fn send(&self, _addr: SocketAddr, msg: T, _timeout: Duration) {
+            let payload = bincode::encode_to_vec(msg, BINCODE_CONFIG).unwrap();
+            if self.socket.ready_state() == WebSocket::OPEN {
+                self.socket.send_with_u8_array(&payload).ok();
+            } else {
+                self.outgoing.lock().unwrap().push_back(payload);
+            }
+        }
+
+        fn try_recv_all(&self) -> Vec<(SocketAddr, T)> {
+            let mut incoming = self.incoming.lock().unwrap();
+            incoming.drain(..).map(|msg| (self.server_addr, msg)).collect()
+        }
+
+        fn latency_of(&self, _addr: SocketAddr) -> Option<Duration> {
+            None
+        }
+
+        fn traffic_stats_of(&self, _addr: SocketAddr) -> Option<TrafficStats> {
+            None
+        }
+
+        fn local_ip_addr(&self) -> Option<IpAddr> {
+            None
+        }
+    }
+
+    impl<T> Drop for WsNetworkSocket<T>
+    where
+        T: 'static + Debug + Send + Encode + Decode<()>,
+    {
+        fn drop(&mut self) {
+            self.socket.set_onopen(None);
+            self.socket.set_onmessage(None);
+            self.socket.close().ok();
+        }
+    }
+}
+
+// ---------------------------------------------------------- //
+// ------------------------- Tests -------------------------- //
+// ---------------------------------------------------------- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::tls::TlsClientConfig;
+    use crate::net::tls::tests::test_certificate;
+
+    #[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+    struct SimpleMessage {
+        text: String,
+    }
+
+    #[test]
+    fn handshake_and_frames_follow_the_websocket_protocol() {
+        // Example from RFC 6455
+        assert_eq!(
+            handshake_accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+
+        // Clients mask their frames, and may split messages into several frames
+        let mask = [1, 2, 3, 4];
+        let masked = |bytes: &[u8]| {
+            bytes
+                .iter()
+                .enumerate()
+                .map(|(i, b)| b ^ mask[i % 4])
+                .collect::<Vec<_>>()
+        };
+        let mut frames = vec![OPCODE_BINARY, 0x80 | 3];
+        frames.extend_from_slice(&mask);
+        frames.extend(masked(b"abc"));
+        frames.extend([0x80 | OPCODE_CONTINUATION, 0x80 | 2]);
+        frames.extend_from_slice(&mask);
+        frames.extend(masked(b"de"));
+        frames.extend([0x80 | OPCODE_CLOSE, 0]);
+
+        let mut stream = io::Cursor::new(frames);
+        assert_eq!(read_message(&mut stream).unwrap(), Some(b"abcde".to_vec()));
+        assert_eq!(read_message(&mut stream).unwrap(), None);
+
+        let mut written = Vec::new();
+        write_frame(&mut written, OPCODE_BINARY, &[7; 300]).unwrap();
+        assert_eq!(written[..4], [0x80 | OPCODE_BINARY, 126, 1, 44]);
+        assert_eq!(read_message(&mut io::Cursor::new(written)).unwrap(), Some(vec![7; 300]));
+    }
+
+    #[test]
+    fn oversized_messages_are_refused() {
+        let mut frames = vec![OPCODE_BINARY, 127];
+        frames.extend_from_slice(&(MAX_MESSAGE_SIZE as u64).to_be_bytes());
+        frames.extend(vec![0; MAX_MESSAGE_SIZE]);
+        frames.extend([0x80 | OPCODE_CONTINUATION, 1, 0]);
+        assert!(read_message(&mut io::Cursor::new(frames)).is_err());
+
+        let mut frames = vec![0x80 | OPCODE_BINARY, 127];
+        frames.extend_from_slice(&u64::MAX.to_be_bytes());
+        assert!(read_message(&mut io::Cursor::new(frames)).is_err());
+    }
+
+    #[test]
+    fn listener_exchanges_messages_with_browsers() {
+        let listener: WsNetworkListener<SimpleMessage> =
+            WsNetworkListener::new(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let mut client = TcpStream::connect(listener.local_addr()).unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nUpgrade: websocket\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n")
+            .unwrap();
+        let mut response = [0; 129];
+        client.read_exact(&mut response).unwrap();
+        assert!(String::from_utf8_lossy(&response).contains("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+
+        let msg = SimpleMessage {
+            text: "hello".to_owned(),
+        };
+        write_frame(
+            &mut client,
+            OPCODE_BINARY,
+            &bincode::encode_to_vec(&msg, BINCODE_CONFIG).unwrap(),
+        )
+        .unwrap();
+        let mut received = Vec::new();
+        for _ in 0..100 {
+            received.extend(listener.try_recv_all());
+            if !received.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let client_addr = client.local_addr().unwrap();
+        assert_eq!(received, vec![(client_addr, msg.clone())]);
+
+        assert!(listener.send(client_addr, msg.clone()));
+        let payload = read_message(&mut client).unwrap().unwrap();
+        let (echoed, _): (SimpleMessage, usize) = bincode::decode_from_slice(&payload, BINCODE_CONFIG).unwrap();
+        assert_eq!(echoed, msg);
+    }
+
+    #[test]
+    fn listener_terminates_tls() {
+        let (certificate, cert_file) = test_certificate("ws_listener");
+        let listener: WsNetworkListener<SimpleMessage> =
+            WsNetworkListener::with_tls(SocketAddr::from(([127, 0, 0, 1], 0)), certificate.load().unwrap()).unwrap();
+        let client_config = TlsClientConfig::new(Some(&cert_file)).unwrap();
+        let tcp_client = TcpStream::connect(listener.local_addr()).unwrap();
+        let client_addr = tcp_client.local_addr().unwrap();
+        let mut client = TlsStream::connect(tcp_client, "localhost", &client_config).unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nUpgrade: websocket\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n")
+            .unwrap();
+        client.flush().unwrap();
+        let mut response = [0; 129];
+        client.read_exact(&mut response).unwrap();
+        assert!(String::from_utf8_lossy(&response).contains("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+
+        let msg = SimpleMessage {
+            text: "hello".to_owned(),
+        };
+        write_frame(
+            &mut client,
+            OPCODE_BINARY,
+            &bincode::encode_to_vec(&msg, BINCODE_CONFIG).unwrap(),
+        )
+        .unwrap();
+        client.flush().unwrap();
+        let mut received = Vec::new();
+        for _ in 0..100 {
+            received.extend(listener.try_recv_all());
+            if !received.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(received, vec![(client_addr, msg.clone())]);
+        assert!(listener.is_secure(client_addr));
+
+        // Sends get through while the connection thread is waiting to read
+        assert!(listener.send(client_addr, msg.clone()));
+        let payload = read_message(&mut client).unwrap().unwrap();
+        let (echoed, _): (SimpleMessage, usize) = bincode::decode_from_slice(&payload, BINCODE_CONFIG).unwrap();
+        assert_eq!(echoed, msg);
+
+        std::fs::remove_dir_all(cert_file.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn only_tls_proxies_make_connections_secure() {
+        let connect = |listener: &WsNetworkListener<SimpleMessage>, headers: &str| {
+            let mut client = TcpStream::connect(listener.local_addr()).unwrap();
+            let request = format!(
+                "GET / HTTP/1.1\r\nUpgrade: websocket\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n{}\r\n",
+                headers
+            );
+            client.write_all(request.as_bytes()).unwrap();
+            let client_addr = client.local_addr().unwrap();
+            for _ in 0..100 {
+                if listener.is_connected(client_addr) {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            assert!(listener.is_connected(client_addr));
+            (client, client_addr)
+        };
+        let bind_addr = SocketAddr::from(([127, 0, 0, 1], 0));
+
+        let listener = WsNetworkListener::with_tls_proxies(bind_addr, vec![IpAddr::from([127, 0, 0, 1])]).unwrap();
+        let (_client, client_addr) = connect(&listener, "X-Forwarded-Proto: https\r\n");
+        assert!(listener.is_secure(client_addr));
+        let (_client, client_addr) = connect(&listener, "");
+        assert!(!listener.is_secure(client_addr));
+
+        // The header means nothing from others than the proxies
+        let listener = WsNetworkListener::new(bind_addr).unwrap();
+        let (_client, client_addr) = connect(&listener, "X-Forwarded-Proto: https\r\n");
+        assert!(!listener.is_secure(client_addr));
+    }
+}
@@ -0,0 +1,1146 @@
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::{DateTime, LogLevel};
+
+/// A trait for converting Rust config structs into a standardized text format and back.
+///
+/// The `Config` trait enables serialization and deserialization of configuration structs
+/// to/from a human-readable key-value text format. This format supports nested structures,
+/// arrays, and various data types including primitives, strings,
+/// network addresses, file paths, etc. Support for custom types can be added by implementing the `Config` trait.
+///
+/// # Format
+///
+/// The generated format uses sections (denoted by `[section.name]`) for nested structures
+/// and key-value pairs for individual fields:
+///
+/// ```text
+/// simple_field = 42
+/// string_field = "hello world"
+/// boolean_field = true
+/// float_field = 123.456
+/// array_field = [1,2,3]
+///
+/// [nested_section]
+/// inner_field = "value"
+///
+/// [deeply.nested.section]
+/// deep_field = 123
+/// ```
+///
+/// Configs can be written as TOML or JSON too, see [`ConfigFormat`].
+///
+/// # Supported Types
+///
+/// Out of the box, the trait supports:
+/// - All primitive numeric types (`u8`, `i32`, `f64`, etc.)
+/// - Strings (`String`, `&'static str`)
+/// - Booleans
+/// - Network types (`SocketAddr`, `IpAddr`, etc.)
+/// - File paths (`PathBuf`)
+/// - Date/time (`DateTime`)
+/// - Durations (`Duration`), as seconds
+/// - Log levels (`LogLevel`)
+/// - Vectors of supported types
+/// - Optional types (`Option<T>`), with `None` written as `none`
+pub trait Config {
+    /// Encodes this object's data into a key-value table.
+    #[rustfmt::skip]
+    fn encode_kv_table(&self, path: &str, table: &mut BTreeMap<String, String>);
+
+    /// Decodes an object from a key-value table.
+    #[rustfmt::skip]
+    fn decode_kv_table(path: &str, table: &BTreeMap<String, String>) -> Result<Self, ConfigParseError> where Self: Sized;
+}
+
+/// Error type for configuration parsing failures.
+///
+/// This enum represents the different ways that configuration parsing can fail
+/// when using the `Config` trait to deserialize text data.
+#[derive(Debug, Clone)]
+pub enum ConfigParseError {
+    /// A field's value could not be parsed to the expected type.
+    InvalidFieldType(String),
+
+    /// The input text has invalid syntax.
+    InvalidSyntax(String),
+
+    /// A required field is missing from the input.
+    MissingData(String),
+}
+
+/// Text format of configs. Every format holds the same key-value table, so any config can be written in any of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConfigFormat {
+    /// The format described in [`Config`].
+    #[default]
+    Ion,
+
+    /// TOML, for editing by hand. Values TOML has no type for, such as socket addresses, are written as strings.
+    /// Only the subset of TOML that configs are written in can be read: no inline tables or multi-line values. `None` is
+    /// written as an empty inline table, as TOML has no null.
+    Toml,
+
+    /// JSON, for external tooling. Sections are nested objects, and values JSON has no type for are written as strings.
+    Json,
+}
+
+impl ConfigFormat {
+    pub const ALL: [ConfigFormat; 3] = [ConfigFormat::Ion, ConfigFormat::Toml, ConfigFormat::Json];
+
+    /// File extension of configs written in the format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ConfigFormat::Ion => "conf",
+            ConfigFormat::Toml => "toml",
+            ConfigFormat::Json => "json",
+        }
+    }
+}
+
+pub fn config_to_string(config: &dyn Config) -> String {
+    let mut table = BTreeMap::new();
+    config.encode_kv_table("", &mut table);
+    kv_table_to_string(&table)
+}
+
+/// Fields of a config text that the config type doesn't have, for example ones written by a newer version of the game.
+/// See [`config_from_string_keeping_unknown`].
+pub type UnknownConfigFields = BTreeMap<String, String>;
+
+pub fn config_to_string_as(config: &dyn Config, format: ConfigFormat) -> String {
+    config_to_string_keeping_unknown(config, &UnknownConfigFields::new(), format)
+}
+
+/// Same as [`config_to_string_as`], but writes the unknown fields of the config too, so that they survive the config
+/// being re-exported.
+pub fn config_to_string_keeping_unknown(
+    config: &dyn Config,
+    unknown_fields: &UnknownConfigFields,
+    format: ConfigFormat,
+) -> String {
+    let mut table = unknown_fields.clone();
+    config.encode_kv_table("", &mut table);
+    match format {
+        ConfigFormat::Ion => kv_table_to_string(&table),
+        // The sections and keys of TOML are the same as in the Ion format, only the values differ
+        // TOML has no null, so `None` is written as an empty inline table
+        ConfigFormat::Toml => kv_table_to_string(
+            &table
+                .iter()
+                .map(|(key, value)| match value.as_str() {
+                    NONE_VALUE => (key.clone(), "{}".to_owned()),
+                    _ => (key.clone(), ConfigValue::from_ion(value).to_text()),
+                })
+                .collect(),
+        ),
+        ConfigFormat::Json => kv_table_to_json(&table),
+    }
+}
+
+/// Decodes a config from the text, see [`config_from_string`].
+pub fn config_from_string_as<T: Config + Default>(string: &str, format: ConfigFormat) -> Result<T, ConfigParseError> {
+    Ok(config_from_string_keeping_unknown(string, format)?.0)
+}
+
+/// Same as [`config_from_string_as`], but also returns the fields of the text that the config doesn't have, for
+/// writing them back with [`config_to_string_keeping_unknown`].
+pub fn config_from_string_keeping_unknown<T: Config + Default>(
+    string: &str,
+    format: ConfigFormat,
+) -> Result<(T, UnknownConfigFields), ConfigParseError> {
+    let kv_table = match format {
+        ConfigFormat::Ion => kv_table_from_string(string)?,
+        ConfigFormat::Toml => kv_table_from_toml(string)?,
+        ConfigFormat::Json => kv_table_from_json(string)?,
+    };
+
+    let mut full_table = BTreeMap::new();
+    T::default().encode_kv_table("", &mut full_table);
+    merge_kv_table(&mut full_table, kv_table.clone());
+    let config = T::decode_kv_table("", &full_table)?;
+
+    // Whatever the decoded config doesn't write back wasn't read from the text either
+    let mut known_table = BTreeMap::new();
+    config.encode_kv_table("", &mut known_table);
+    let unknown_fields = kv_table
+        .into_iter()
+        .filter(|(key, _)| !known_table.contains_key(key))
+        .collect();
+    Ok((config, unknown_fields))
+}
+
+fn kv_table_to_string(table: &BTreeMap<String, String>) -> String {
+    let mut kv_vec: Vec<_> = table.iter().collect();
+    kv_vec.sort_by_key(|x| x.0);
+    kv_vec.sort_by_key(|x| x.0.contains('.'));
+
+    let mut string_builder = String::new();
+    let mut current_path = "";
+
+    for (key, value) in kv_vec {
+        let path_end = key.rfind('.').map(|i| i + 1).unwrap_or(0);
+        let path = &key[0..path_end.saturating_sub(1)];
+
+        if path != current_path {
+            string_builder.push_str(format!("\n[{}]\n", path).as_str());
+            current_path = path;
+        }
+
+        string_builder.push_str(format!("{} = {}\n", &key[path_end..], value).as_str());
+    }
+
+    string_builder
+}
+
+/// Decodes a config from the text. Fields missing from the text are taken from the default config, so that configs
+/// written before a field was added still decode, and fields the config doesn't have are ignored.
+///
+/// Optional fields that are `None` are written as `none`, so that they aren't taken from the default config either.
+pub fn config_from_string<T: Config + Default>(string: &str) -> Result<T, ConfigParseError> {
+    config_from_string_as(string, ConfigFormat::Ion)
+}
+
+/// Decodes a config from the text, taking the fields missing from the text from the given config.
+pub fn config_update_from_string<T: Config>(config: &T, string: &str) -> Result<T, ConfigParseError> {
+    let mut kv_table = BTreeMap::new();
+    config.encode_kv_table("", &mut kv_table);
+    merge_kv_table(&mut kv_table, kv_table_from_string(string)?);
+    T::decode_kv_table("", &kv_table)
+}
+
+/// Overwrites the fields of the table with those of `overrides`. An optional field that is `None` on one side and a
+/// section on the other is replaced as a whole, so that the keys of both don't end up in the table.
+fn merge_kv_table(table: &mut BTreeMap<String, String>, overrides: BTreeMap<String, String>) {
+    for key in overrides.keys() {
+        let section = format!("{}.", key);
+        table.retain(|existing, _| !existing.starts_with(&section) && !key.starts_with(&format!("{}.", existing)));
+    }
+    table.extend(overrides);
+}
+
+fn kv_table_from_string(string: &str) -> Result<BTreeMap<String, String>, ConfigParseError> {
+    let mut kv_table = BTreeMap::new();
+    let mut current_path = "";
+
+    for line in string
+        .split('\n')
+        .filter(|line| !line.is_empty() && !line.starts_with("//"))
+    {
+        if line.starts_with('[') {
+            current_path = &line[1..(line.len() - 1)];
+        } else {
+            let line_vec: Vec<_> = line.splitn(2, '=').collect();
+            if line_vec.len() != 2 {
+                return Err(ConfigParseError::InvalidSyntax(format!("Invalid line: {}", line)));
+            }
+
+            let key = line_vec[0].trim();
+            let value = line_vec[1].trim();
+
+            let mut path = current_path.to_owned();
+            if !path.is_empty() {
+                path.push('.');
+            }
+            path.push_str(key);
+
+            kv_table.insert(path, value.to_owned());
+        }
+    }
+
+    Ok(kv_table)
+}
+
+// ---------------------------------------------------------- //
+// ----------------- Config implementations ----------------- //
+// ---------------------------------------------------------- //
+
+macro_rules! config_basic_impl {
+    ($ty:ty) => {
+        impl Config for $ty {
+            fn encode_kv_table(&self, name: &str, table: &mut BTreeMap<String, String>) {
+                table.insert(name.to_owned(), self.to_string());
+            }
+
+            fn decode_kv_table(name: &str, table: &BTreeMap<String, String>) -> Result<Self, ConfigParseError> {
+                if let Some(val) = table.get(name) {
+                    #[allow(irrefutable_let_patterns)]
+                    if let Ok(val) = unquoted(val).parse() {
+                        Ok(val)
+                    } else {
+                        Err(ConfigParseError::InvalidFieldType(name.to_string()))
+                    }
+                } else {
+                    Err(ConfigParseError::MissingData(name.to_string()))
+                }
+            }
+        }
+    };
+}
+
+macro_rules! config_vec_impl {
+    ($ty:ty) => {
+        impl Config for Vec<$ty> {
+            fn encode_kv_table(&self, name: &str, table: &mut BTreeMap<String, String>) {
+                let str_vec = self
+                    .iter()
+                    .map(|item| {
+                        let mut tmp_map = BTreeMap::new();
+                        item.encode_kv_table("tmp", &mut tmp_map);
+                        tmp_map.remove("tmp").unwrap()
+                    })
+                    .collect::<Vec<String>>();
+
+                table.insert(name.to_owned(), format!("[{}]", str_vec.join(",")));
+            }
+
+            fn decode_kv_table(name: &str, table: &BTreeMap<String, String>) -> Result<Self, ConfigParseError>
+            where
+                Self: Sized,
+            {
+                if let Some(val) = table.get(name) {
+                    if !val.starts_with('[') || !val.ends_with(']') {
+                        return Err(ConfigParseError::InvalidFieldType(name.to_string()));
+                    }
+                    val[1..(val.len() - 1)]
+                        .split(',')
+                        .filter(|item| !item.trim().is_empty())
+                        .map(|item| {
+                            let mut tmp_map = BTreeMap::new();
+                            tmp_map.insert("tmp".to_owned(), item.trim().to_owned());
+                            <$ty>::decode_kv_table("tmp", &tmp_map)
+                        })
+                        .collect()
+                } else {
+                    Err(ConfigParseError::MissingData(name.to_string()))
+                }
+            }
+        }
+    };
+}
+
+config_basic_impl!(u8);
+config_basic_impl!(u16);
+config_basic_impl!(u32);
+config_basic_impl!(u64);
+config_basic_impl!(u128);
+config_basic_impl!(i8);
+config_basic_impl!(i16);
+config_basic_impl!(i32);
+config_basic_impl!(i64);
+config_basic_impl!(i128);
+config_basic_impl!(f32);
+config_basic_impl!(f64);
+config_basic_impl!(usize);
+config_basic_impl!(bool);
+config_basic_impl!(SocketAddr);
+config_basic_impl!(IpAddr);
+config_basic_impl!(Ipv4Addr);
+config_basic_impl!(Ipv6Addr);
+
+config_vec_impl!(u8);
+config_vec_impl!(u16);
+config_vec_impl!(u32);
+config_vec_impl!(u64);
+config_vec_impl!(u128);
+config_vec_impl!(i8);
+config_vec_impl!(i16);
+config_vec_impl!(i32);
+config_vec_impl!(i64);
+config_vec_impl!(i128);
+config_vec_impl!(f32);
+config_vec_impl!(f64);
+config_vec_impl!(usize);
+config_vec_impl!(bool);
+config_vec_impl!(SocketAddr);
+config_vec_impl!(IpAddr);
+config_vec_impl!(Ipv4Addr);
+config_vec_impl!(Ipv6Addr);
+config_vec_impl!(String);
+config_vec_impl!(&'static str);
+
+impl Config for PathBuf {
+    fn encode_kv_table(&self, name: &str, table: &mut BTreeMap<String, String>) {
+        table.insert(name.to_owned(), format!("{:?}", self.display()));
+    }
+
+    fn decode_kv_table(name: &str, table: &BTreeMap<String, String>) -> Result<Self, ConfigParseError> {
+        if let Some(val) = table.get(name) {
+            if !val.starts_with('"') {
+                return Err(ConfigParseError::InvalidFieldType(name.to_string()));
+            }
+
+            if !val.ends_with('"') {
+                return Err(ConfigParseError::InvalidFieldType(name.to_string()));
+            }
+
+            match val[1..(val.len() - 1)].to_owned().parse() {
+                Ok(res) => Ok(res),
+                Err(_) => Err(ConfigParseError::InvalidFieldType(name.to_string())),
+            }
+        } else {
+            Err(ConfigParseError::MissingData(format!("Missing field: {}", name)))
+        }
+    }
+}
+
+impl Config for &'static str {
+    fn encode_kv_table(&self, name: &str, table: &mut BTreeMap<String, String>) {
+        table.insert(name.to_owned(), format!("\"{}\"", self));
+    }
+
+    fn decode_kv_table(name: &str, table: &BTreeMap<String, String>) -> Result<Self, ConfigParseError> {
+        if let Some(val) = table.get(name) {
+            if !val.starts_with('"') {
+                return Err(ConfigParseError::InvalidFieldType(name.to_string()));
+            }
+
+            if !val.ends_with('"') {
+                return Err(ConfigParseError::InvalidFieldType(name.to_string()));
+            }
+
+            let string_to_forget = val[1..(val.len() - 1)].to_owned();
+            Ok(Box::leak(string_to_forget.into_boxed_str()))
+        } else {
+            Err(ConfigParseError::MissingData(format!("Missing field: {}", name)))
+        }
+    }
+}
+
+impl Config for String {
+    fn encode_kv_table(&self, name: &str, table: &mut BTreeMap<String, String>) {
+        table.insert(name.to_owned(), format!("\"{}\"", self));
+    }
+
+    fn decode_kv_table(name: &str, table: &BTreeMap<String, String>) -> Result<Self, ConfigParseError> {
+        if let Some(val) = table.get(name) {
+            if !val.starts_with('"') {
+                return Err(ConfigParseError::InvalidFieldType(name.to_string()));
+            }
+
+            if !val.ends_with('"') {
+                return Err(ConfigParseError::InvalidFieldType(name.to_string()));
+            }
+
+            Ok(val[1..(val.len() - 1)].to_owned())
+        } else {
+            Err(ConfigParseError::MissingData(format!("Missing field: {}", name)))
+        }
+    }
+}
+
+/// Value that `None` is written as.
+const NONE_VALUE: &str = "none";
+
+impl<T: Config> Config for Option<T> {
+    fn encode_kv_table(&self, name: &str, table: &mut BTreeMap<String, String>) {
+        if let Some(value) = self {
+            value.encode_kv_table(name, table);
+        } else {
+            table.insert(name.to_owned(), NONE_VALUE.to_owned());
+        }
+    }
+    fn decode_kv_table(name: &str, table: &BTreeMap<String, String>) -> Result<Self, ConfigParseError> {
+        let section = format!("{}.", name);
+        match table.get(name).map(String::as_str) {
+            Some(NONE_VALUE) => Ok(None),
+            Some(_) => Ok(Some(T::decode_kv_table(name, table)?)),
+            None if table.keys().any(|key| key.starts_with(&section)) => Ok(Some(T::decode_kv_table(name, table)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Config for DateTime {
+    fn encode_kv_table(&self, name: &str, table: &mut BTreeMap<String, String>) {
+        table.insert(name.to_owned(), self.format_iso8601());
+    }
+
+    fn decode_kv_table(name: &str, table: &BTreeMap<String, String>) -> Result<Self, ConfigParseError> {
+        if let Some(val) = table.get(name) {
+            unquoted(val)
+                .parse()
+                .map_err(|_| ConfigParseError::InvalidFieldType(name.to_string()))
+        } else {
+            Err(ConfigParseError::MissingData(format!("Missing field: {}", name)))
+        }
+    }
+}
+
+impl Config for Duration {
+    fn encode_kv_table(&self, name: &str, table: &mut BTreeMap<String, String>) {
+        table.insert(name.to_owned(), self.as_secs_f64().to_string());
+    }
+
+    fn decode_kv_table(name: &str, table: &BTreeMap<String, String>) -> Result<Self, ConfigParseError> {
+        if let Some(val) = table.get(name) {
+            unquoted(val)
+                .parse()
+                .ok()
+                .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                .ok_or_else(|| ConfigParseError::InvalidFieldType(name.to_string()))
+        } else {
+            Err(ConfigParseError::MissingData(format!("Missing field: {}", name)))
+        }
+    }
+}
+
+impl Config for LogLevel {
+    fn encode_kv_table(&self, name: &str, table: &mut BTreeMap<String, String>) {
+        table.insert(name.to_owned(), format!("\"{:?}\"", self));
+    }
+
+    fn decode_kv_table(name: &str, table: &BTreeMap<String, String>) -> Result<Self, ConfigParseError> {
+        match table.get(name).map(|val| val.as_str()) {
+            Some("\"Error\"") => Ok(LogLevel::Error),
+            Some("\"Warning\"") => Ok(LogLevel::Warning),
+            Some("\"Info\"") => Ok(LogLevel::Info),
+            Some("\"Debug\"") => Ok(LogLevel::Debug),
+            Some("\"Trace\"") => Ok(LogLevel::Trace),
+            Some(_) => Err(ConfigParseError::InvalidFieldType(name.to_string())),
+            None => Err(ConfigParseError::MissingData(format!("Missing field: {}", name))),
+        }
+    }
+}
+
+/// Values read from TOML or JSON are strings where those formats have no type for them, see [`ConfigFormat`].
+fn unquoted(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+// ---------------------------------------------------------- //
+// ------------------ TOML and JSON formats ----------------- //
+// ---------------------------------------------------------- //
+
+/// Value of the key-value table, split by its syntax in the Ion format.
+#[derive(Debug, Clone, PartialEq)]
+enum ConfigValue {
+    /// Number, boolean or any other unquoted value.
+    Token(String),
+    Str(String),
+    Array(Vec<ConfigValue>),
+}
+
+impl ConfigValue {
+    fn from_ion(value: &str) -> Self {
+        if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+            ConfigValue::Str(value[1..(value.len() - 1)].to_owned())
+        } else if value.starts_with('[') && value.ends_with(']') {
+            ConfigValue::Array(
+                value[1..(value.len() - 1)]
+                    .split(',')
+                    .filter(|item| !item.trim().is_empty())
+                    .map(|item| ConfigValue::from_ion(item.trim()))
+                    .collect(),
+            )
+        } else {
+            ConfigValue::Token(value.to_owned())
+        }
+    }
+
+    fn to_ion(&self) -> String {
+        match self {
+            ConfigValue::Token(token) => token.clone(),
+            ConfigValue::Str(string) => format!("\"{}\"", string),
+            ConfigValue::Array(items) => format!("[{}]", items.iter().map(Self::to_ion).collect::<Vec<_>>().join(",")),
+        }
+    }
+
+    /// Writes the value as TOML or JSON, which share the syntax of strings, numbers, booleans and arrays.
+    fn to_text(&self) -> String {
+        match self {
+            ConfigValue::Token(token) if is_number_or_bool(token) => token.clone(),
+            ConfigValue::Token(string) | ConfigValue::Str(string) => quote_string(string),
+            ConfigValue::Array(items) => {
+                format!("[{}]", items.iter().map(Self::to_text).collect::<Vec<_>>().join(", "))
+            }
+        }
+    }
+}
+
+fn is_number_or_bool(token: &str) -> bool {
+    token == "true"
+        || token == "false"
+        || (token.starts_with(|c: char| c.is_ascii_digit() || c == '-')
+            && token
+                .chars()
+                .all(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E'))
+            && token.parse::<f64>().is_ok())
+}
+
+fn quote_string(string: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in string.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn kv_table_to_json(table: &BTreeMap<String, String>) -> String {
+    enum JsonNode {
+        Value(String),
+        Object(BTreeMap<String, JsonNode>),
+    }
+
+    fn write_object(object: &BTreeMap<String, JsonNode>, indent: usize, out: &mut String) {
+        out.push('{');
+        for (i, (key, node)) in object.iter().enumerate() {
+            out.push_str(if i == 0 { "\n" } else { ",\n" });
+            out.push_str(&"  ".repeat(indent + 1));
+            out.push_str(&quote_string(key));
+            out.push_str(": ");
+            match node {
+                JsonNode::Value(value) => out.push_str(value),
+                JsonNode::Object(object) => write_object(object, indent + 1, out),
+            }
+        }
+        if !object.is_empty() {
+            out.push('\n');
+            out.push_str(&"  ".repeat(indent));
+        }
+        out.push('}');
+    }
+
+    // Sections become nested objects
+    let mut root = BTreeMap::new();
+    for (key, value) in table {
+        let mut object = &mut root;
+        let mut segments = key.split('.').peekable();
+        while let Some(segment) = segments.next() {
+            if segments.peek().is_none() {
+                let value = if value == NONE_VALUE {
+                    "null".to_owned()
+                } else {
+                    ConfigValue::from_ion(value).to_text()
+                };
+                object.insert(segment.to_owned(), JsonNode::Value(value));
+                break;
+            }
+            let node = object
+                .entry(segment.to_owned())
+                .or_insert_with(|| JsonNode::Object(BTreeMap::new()));
+            let JsonNode::Object(inner) = node else {
+                break;
+            };
+            object = inner;
+        }
+    }
+
+    let mut json = String::new();
+    write_object(&root, 0, &mut json);
+    json.push('\n');
+    json
+}
+
+fn kv_table_from_toml(string: &str) -> Result<BTreeMap<String, String>, ConfigParseError> {
+    let mut kv_table = BTreeMap::new();
+    let mut current_path = String::new();
+
+    for line in string.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            let section = line.split('#').next().unwrap_or_default().trim();
+            if section.starts_with("[[") || !section.ends_with(']') {
+                return Err(ConfigParseError::InvalidSyntax(format!("Invalid section: {}", line)));
+            }
+            current_path = section[1..(section.len() - 1)].trim().to_owned();
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(ConfigParseError::InvalidSyntax(format!("Invalid line: {}", line)));
+        };
+        let mut reader = ValueReader::new(value);
+        reader.skip_whitespace();
+        let value = if reader.peek() == Some('{') {
+            // The only inline table is the empty one that `None` is written as
+            reader.next();
+            reader.skip_whitespace();
+            reader.expect('}')?;
+            NONE_VALUE.to_owned()
+        } else {
+            reader.read_value()?.to_ion()
+        };
+        reader.skip_whitespace();
+        if !reader.is_at_end() && reader.peek() != Some('#') {
+            return Err(ConfigParseError::InvalidSyntax(format!("Invalid line: {}", line)));
+        }
+
+        let key = key.trim().trim_matches('"');
+        let path = if current_path.is_empty() {
+            key.to_owned()
+        } else {
+            format!("{}.{}", current_path, key)
+        };
+        kv_table.insert(path, value);
+    }
+
+    Ok(kv_table)
+}
+
+fn kv_table_from_json(string: &str) -> Result<BTreeMap<String, String>, ConfigParseError> {
+    fn read_object(
+        reader: &mut ValueReader,
+        path: &str,
+        kv_table: &mut BTreeMap<String, String>,
+    ) -> Result<(), ConfigParseError> {
+        reader.expect('{')?;
+        reader.skip_whitespace();
+        if reader.peek() == Some('}') {
+            reader.next();
+            return Ok(());
+        }
+        loop {
+            reader.skip_whitespace();
+            let key = reader.read_string()?;
+            let path = if path.is_empty() {
+                key
+            } else {
+                format!("{}.{}", path, key)
+            };
+            reader.skip_whitespace();
+            reader.expect(':')?;
+            reader.skip_whitespace();
+            if reader.peek() == Some('{') {
+                read_object(reader, &path, kv_table)?;
+            } else {
+                match reader.read_value()? {
+                    ConfigValue::Token(token) if token == "null" => {
+                        kv_table.insert(path, NONE_VALUE.to_owned());
+                    }
+                    value => {
+                        kv_table.insert(path, value.to_ion());
+                    }
+                }
+            }
+            reader.skip_whitespace();
+            match reader.next() {
+                Some(',') => continue,
+                Some('}') => return Ok(()),
+                _ => return Err(ConfigParseError::InvalidSyntax("Expected ',' or '}'".to_owned())),
+            }
+        }
+    }
+
+    let mut kv_table = BTreeMap::new();
+    let mut reader = ValueReader::new(string);
+    reader.skip_whitespace();
+    read_object(&mut reader, "", &mut kv_table)?;
+    reader.skip_whitespace();
+    if !reader.is_at_end() {
+        return Err(ConfigParseError::InvalidSyntax(
+            "Trailing data after the config".to_owned(),
+        ));
+    }
+    Ok(kv_table)
+}
+
+/// Reads the values of TOML and JSON.
+struct ValueReader<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl<'a> ValueReader<'a> {
+    fn new(text: &'a str) -> Self {
+        Self {
+            chars: text.chars().peekable(),
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        self.chars.next()
+    }
+
+    fn is_at_end(&mut self) -> bool {
+        self.peek().is_none()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ConfigParseError> {
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            c => Err(ConfigParseError::InvalidSyntax(format!(
+                "Expected '{}', found {:?}",
+                expected, c
+            ))),
+        }
+    }
+
+    fn read_value(&mut self) -> Result<ConfigValue, ConfigParseError> {
+        match self.peek() {
+            Some('"') => Ok(ConfigValue::Str(self.read_string()?)),
+            Some('\'') => {
+                // TOML literal strings have no escapes
+                self.next();
+                let mut string = String::new();
+                loop {
+                    match self.next() {
+                        Some('\'') => return Ok(ConfigValue::Str(string)),
+                        Some(c) => string.push(c),
+                        None => return Err(ConfigParseError::InvalidSyntax("Unterminated string".to_owned())),
+                    }
+                }
+            }
+            Some('[') => {
+                self.next();
+                let mut items = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    if self.peek() == Some(']') {
+                        self.next();
+                        return Ok(ConfigValue::Array(items));
+                    }
+                    items.push(self.read_value()?);
+                    self.skip_whitespace();
+                    match self.next() {
+                        Some(',') => {}
+                        Some(']') => return Ok(ConfigValue::Array(items)),
+                        _ => return Err(ConfigParseError::InvalidSyntax("Expected ',' or ']'".to_owned())),
+                    }
+                }
+            }
+            _ => {
+                let mut token = String::new();
+                while let Some(c) = self.peek() {
+                    if c.is_whitespace() || matches!(c, ',' | ']' | '}' | '#') {
+                        break;
+                    }
+                    token.push(c);
+                    self.next();
+                }
+                if token.is_empty() {
+                    return Err(ConfigParseError::InvalidSyntax("Missing value".to_owned()));
+                }
+                Ok(ConfigValue::Token(token))
+            }
+        }
+    }
+
+    fn read_string(&mut self) -> Result<String, ConfigParseError> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(string),
+                Some('\\') => {
+                    let escaped = match self.next() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some(c @ ('u' | 'U')) => {
+                            let len = if c == 'u' { 4 } else { 8 };
+                            let hex: String = (0..len).filter_map(|_| self.next()).collect();
+                            u32::from_str_radix(&hex, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| {
+                                    ConfigParseError::InvalidSyntax(format!("Invalid escape \\{}{}", c, hex))
+                                })?
+                        }
+                        c => return Err(ConfigParseError::InvalidSyntax(format!("Invalid escape {:?}", c))),
+                    };
+                    string.push(escaped);
+                }
+                Some(c) => string.push(c),
+                None => return Err(ConfigParseError::InvalidSyntax("Unterminated string".to_owned())),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::path::PathBuf;
+
+    use derive_engine::Config;
+
+    use crate::util::config::{
+        ConfigFormat, ConfigParseError, config_from_string, config_from_string_as, config_from_string_keeping_unknown,
+        config_to_string, config_to_string_as, config_to_string_keeping_unknown, config_update_from_string,
+    };
+
+    #[derive(Debug, Clone, Default, Config, PartialEq)]
+    struct Inner {
+        value_1: u64,
+        value_2: String,
+    }
+
+    #[derive(Debug, Clone, Default, Config, PartialEq)]
+    struct Other {
+        id: u32,
+        name: String,
+        inner_inner: Inner,
+    }
+
+    #[derive(Debug, Clone, Config, PartialEq)]
+    struct TestStruct {
+        str: String,
+        num: u64,
+        vector: Vec<u64>,
+        vector2: Vec<&'static str>,
+        nested: Inner,
+        other: Other,
+        boolean: bool,
+        addr: SocketAddr,
+        path: PathBuf,
+        en: EnumTest,
+        op1: Option<u32>,
+        op2: Option<String>,
+        op3: Option<Inner>,
+        float: f64,
+        time: DateTime,
+    }
+
+    #[derive(Debug, Clone, Config, PartialEq)]
+    struct Logging {
+        timeout: Duration,
+        level: LogLevel,
+    }
+
+    impl Default for Logging {
+        fn default() -> Self {
+            Self {
+                timeout: Duration::from_secs(1),
+                level: LogLevel::Info,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Config, PartialEq)]
+    enum EnumTest {
+        Opt1,
+        Opt2,
+    }
+
+    impl Default for TestStruct {
+        fn default() -> Self {
+            gen_test_struct()
+        }
+    }
+
+    fn gen_test_struct() -> TestStruct {
+        TestStruct {
+            str: "TestValue".to_string(),
+            num: 345,
+            vector: vec![1, 2, 3],
+            vector2: vec!["one", "two"],
+            boolean: true,
+            float: 0.67353,
+            time: DateTime::now(),
+            en: EnumTest::Opt2,
+            op1: None,
+            op2: Some("inside\t_option".to_owned()),
+            op3: Some(Inner {
+                value_1: 5,
+                value_2: "OPTIONAL".to_string(),
+            }),
+            addr: SocketAddr::from(([192, 168, 1, 112], 6767)),
+            path: PathBuf::from("/home/test/asd.txt"),
+            nested: Inner {
+                value_1: 234234,
+                value_2: "INNER TEXT".to_string(),
+            },
+            other: Other {
+                id: 923,
+                name: "Bob".to_owned(),
+                inner_inner: Inner {
+                    value_1: 123,
+                    value_2: "INNER INNER TEXT".to_string(),
+                },
+            },
+        }
+    }
+
+    #[test]
+    fn encoding_and_decoding_config_produces_identical_result() {
+        let original = gen_test_struct();
+        let encoded = config_to_string(&original);
+        println!("{}", encoded);
+        let decoded: TestStruct = config_from_string(&encoded).unwrap();
+        assert_eq!(original, decoded);
+    }
+
+    #[test]
+    fn encoding_and_decoding_config_works_in_every_format() {
+        let mut original = gen_test_struct();
+        original.op1 = Some(7);
+        original.nested.value_2 = "quote \" and \\ # not a comment".to_string();
+        for format in ConfigFormat::ALL {
+            let encoded = config_to_string_as(&original, format);
+            let decoded: TestStruct = config_from_string_as(&encoded, format).unwrap();
+            assert_eq!(original, decoded, "{:?}:\n{}", format, encoded);
+        }
+    }
+
+    #[test]
+    fn hand_written_toml_and_json_are_read() {
+        let toml = "# Logging\ntimeout = 0.5 # seconds\nlevel = 'Debug'\n";
+        let json = "{ \"timeout\": 0.5, \"level\": \"Debug\", \"extra\": null }";
+        for (string, format) in [(toml, ConfigFormat::Toml), (json, ConfigFormat::Json)] {
+            let decoded: Logging = config_from_string_as(string, format).unwrap();
+            assert_eq!(
+                decoded,
+                Logging {
+                    timeout: Duration::from_millis(500),
+                    level: LogLevel::Debug
+                }
+            );
+        }
+
+        let inner: Inner =
+            config_from_string_as("{\"value_1\": 1, \"value_2\": \"\\u00e4\"}", ConfigFormat::Json).unwrap();
+        assert_eq!(inner.value_2, "\u{e4}");
+        assert!(matches!(
+            config_from_string_as::<Inner>("{\"value_1\": 1,", ConfigFormat::Json),
+            Err(ConfigParseError::InvalidSyntax(_))
+        ));
+        assert!(matches!(
+            config_from_string_as::<Inner>("value_1 = 1 2", ConfigFormat::Toml),
+            Err(ConfigParseError::InvalidSyntax(_))
+        ));
+    }
+
+    #[test]
+    fn updating_config_keeps_missing_fields() {
+        let original = gen_test_struct();
+        let updated: TestStruct =
+            config_update_from_string(&original, "num = 12\n[nested]\nvalue_2 = \"NEW\"").unwrap();
+        assert_eq!(updated.num, 12);
+        assert_eq!(updated.nested.value_2, "NEW");
+        assert_eq!(updated.nested.value_1, original.nested.value_1);
+        assert_eq!(updated.other, original.other);
+
+        let decoded: Logging = config_from_string("timeout = 0.5\nlevel = \"Debug\"").unwrap();
+        assert_eq!(
+            decoded,
+            Logging {
+                timeout: Duration::from_millis(500),
+                level: LogLevel::Debug
+            }
+        );
+    }
+
+    #[test]
+    fn optional_fields_can_be_cleared() {
+        let mut original = gen_test_struct();
+        original.op2 = None;
+        original.op3 = None;
+        for format in ConfigFormat::ALL {
+            let encoded = config_to_string_as(&original, format);
+            let decoded: TestStruct = config_from_string_as(&encoded, format).unwrap();
+            assert_eq!(decoded.op2, None, "{:?}:\n{}", format, encoded);
+            assert_eq!(decoded.op3, None, "{:?}:\n{}", format, encoded);
+        }
+
+        let updated: TestStruct =
+            config_update_from_string(&original, "[op3]\nvalue_1 = 1\nvalue_2 = \"SET\"").unwrap();
+        assert_eq!(
+            updated.op3,
+            Some(Inner {
+                value_1: 1,
+                value_2: "SET".to_string(),
+            })
+        );
+        assert_eq!(updated.op2, None);
+    }
+
+    #[test]
+    fn invalid_syntax_fails_correctly() {
+        let test_file = "val1 = 123\n val2-45";
+        let decoded: Result<TestStruct, _> = config_from_string(test_file);
+
+        match decoded {
+            Ok(_) => panic!("Should not succeed"),
+            Err(err) => match err {
+                ConfigParseError::InvalidFieldType(_) => panic!("Wrong error type {:?}", &err),
+                ConfigParseError::InvalidSyntax(_) => {}
+                ConfigParseError::MissingData(_) => panic!("Wrong error type {:?}", &err),
+            },
+        }
+    }
+
+    #[test]
+    fn invalid_field_type_fails_correctly() {
+        let test_file = "value_1 = 123\nvalue_2 = 456";
+        let decoded: Result<Inner, _> = config_from_string(test_file);
+
+        match decoded {
+            Ok(_) => panic!("Should not succeed"),
+            Err(err) => match err {
+                ConfigParseError::InvalidFieldType(_) => {}
+                ConfigParseError::InvalidSyntax(_) => panic!("Wrong error type {:?}", &err),
+                ConfigParseError::MissingData(_) => panic!("Wrong error type {:?}", &err),
+            },
+        }
+    }
+
+    #[test]
+    fn missing_fields_are_filled_from_default() {
+        let test_file = "value_1 = 123\n value_4 = \"test\"";
+        let decoded: Inner = config_from_string(test_file).unwrap();
+        assert_eq!(
+            decoded,
+            Inner {
+                value_1: 123,
+                value_2: String::new(),
+            }
+        );
+
+        let decoded: Logging = config_from_string("level = \"Debug\"").unwrap();
+        assert_eq!(decoded.timeout, Logging::default().timeout);
+        assert_eq!(decoded.level, LogLevel::Debug);
+    }
+
+    #[test]
+    fn unknown_fields_survive_re_export() {
+        let test_file = "value_1 = 123\nvalue_2 = \"old\"\nadded_later = [1,2]\n[new_section]\nflag = true\n";
+        let (mut decoded, unknown_fields) =
+            config_from_string_keeping_unknown::<Inner>(test_file, ConfigFormat::Ion).unwrap();
+        assert_eq!(decoded.value_1, 123);
+        assert_eq!(
+            unknown_fields.keys().collect::<Vec<_>>(),
+            ["added_later", "new_section.flag"]
+        );
+
+        decoded.value_2 = "new".to_string();
+        for format in ConfigFormat::ALL {
+            let re_exported = config_to_string_keeping_unknown(&decoded, &unknown_fields, format);
+            let (re_imported, re_imported_unknown) =
+                config_from_string_keeping_unknown::<Inner>(&re_exported, format).unwrap();
+            assert_eq!(re_imported, decoded);
+            assert_eq!(re_imported_unknown, unknown_fields, "{:?}:\n{}", format, re_exported);
+        }
+    }
+}
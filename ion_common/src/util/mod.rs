@@ -1,6 +1,8 @@
 use std::time::Duration;
 
+pub mod config;
 pub(crate) mod log;
+pub mod signals;
 pub(crate) mod time;
 
 /// A sub-millisecond accurate sleep function.
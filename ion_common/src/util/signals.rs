@@ -0,0 +1,31 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static SHUTDOWN_SIGNAL_RECEIVED: AtomicBool = AtomicBool::new(false);
+
+/// Installs handlers for SIGINT and SIGTERM, so that supervised deployments (systemd, containers) can shut down
+/// gracefully. Receiving the signal only sets the returned flag, which is also polled with
+/// [`shutdown_signal_received`]. A second signal terminates the process immediately, in case the shutdown gets stuck.
+///
+/// Only supported on unix platforms. On other platforms the flag is never set.
+pub fn register_shutdown_signals() -> &'static AtomicBool {
+    #[cfg(unix)]
+    {
+        extern "C" fn on_signal(signal: libc::c_int) {
+            if SHUTDOWN_SIGNAL_RECEIVED.swap(true, Ordering::SeqCst) {
+                unsafe { libc::_exit(128 + signal) };
+            }
+        }
+
+        let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        unsafe {
+            libc::signal(libc::SIGINT, handler);
+            libc::signal(libc::SIGTERM, handler);
+        }
+    }
+    &SHUTDOWN_SIGNAL_RECEIVED
+}
+
+/// Returns true if the process has received a shutdown signal.
+pub fn shutdown_signal_received() -> bool {
+    SHUTDOWN_SIGNAL_RECEIVED.load(Ordering::SeqCst)
+}
@@ -2,21 +2,29 @@ use std::ops::Deref;
 
 use proc_macro::TokenStream;
 
-use quote::quote;
+use proc_macro_crate::{FoundCrate, crate_name};
+use quote::{format_ident, quote};
 use syn::Type;
 
 pub fn impl_config(ast: &syn::DeriveInput) -> TokenStream {
     let name = &ast.ident;
 
-    let import = if "ion_engine" == std::env::var("CARGO_PKG_NAME").unwrap() {
-        quote!(
-            use crate::util::config::*;
-        )
-    } else {
-        quote!(
-            use ion_engine::util::config::*;
-        )
+    // The engine re-exports the config from ion_common, so crates that depend only on ion_common use it from there
+    let config_crate = crate_name("ion_engine")
+        .or_else(|_| crate_name("ion_common"))
+        .expect("Config can only be derived in crates that depend on ion_engine or ion_common");
+    let config_path = match config_crate {
+        FoundCrate::Itself => quote!(crate::util::config),
+        FoundCrate::Name(name) => {
+            let name = format_ident!("{}", name);
+            quote!(#name::util::config)
+        }
     };
+    // The trait is named by its path, so that it can be derived for types that are named Config too
+    let import = quote!(
+        use #config_path::*;
+        use #config_path::Config as _;
+    );
 
     match &ast.data {
         syn::Data::Struct(data) => {
@@ -79,7 +87,7 @@ pub fn impl_config(ast: &syn::DeriveInput) -> TokenStream {
 
             let output = quote! {
                 #import
-                impl Config for #name {
+                impl #config_path::Config for #name {
                     fn encode_kv_table(&self, path: &str, table: &mut std::collections::BTreeMap<String, String>) {
                         let name_key = if !path.is_empty() { format!("{}.", path) } else { "".to_owned()  };
                         #( #encode_fields )*
@@ -134,7 +142,7 @@ pub fn impl_config(ast: &syn::DeriveInput) -> TokenStream {
 
             let output = quote! {
                 #import
-                impl Config for #name {
+                impl #config_path::Config for #name {
                     fn encode_kv_table(&self, path: &str, table: &mut std::collections::BTreeMap<String, String>) {
                         match self {
                             #( #encode_fields )*
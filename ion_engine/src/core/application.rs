@@ -1,11 +1,15 @@
-use core::panic;
-use std::sync::mpsc::Sender;
+use std::{io, path::PathBuf, sync::mpsc::Sender};
 
 use ion_common::log_info;
-use winit::{application::ApplicationHandler, event::WindowEvent, event_loop::EventLoop};
+use winit::{
+    application::ApplicationHandler,
+    event::{DeviceEvent, WindowEvent},
+    event_loop::EventLoop,
+};
 
 use crate::{
     core::{coordinates::Position, world::CommandType},
+    files::{asset_error::AssetError, cloud_sync::SaveSyncStatus},
     gfx::renderer::Renderer,
     input::Input,
     util,
@@ -51,12 +55,28 @@ pub enum ApplicationEvent {
     /// [`persisted`]: https://developer.mozilla.org/en-US/docs/Web/API/PageTransitionEvent/persisted
     /// [`bfcache`]: https://web.dev/bfcache/
     Suspended,
+
+    /// Emitted when an autosave has been written, see [`AutosaveConstants`](super::AutosaveConstants).
+    AutosaveCompleted { save_name: String },
+
+    /// Emitted when writing an autosave failed, see [`AutosaveConstants`](super::AutosaveConstants).
+    /// The earlier autosaves are left as they were.
+    AutosaveFailed { save_name: String, error: io::Error },
+
+    /// Emitted when a file in the watched directories was created, modified or removed, see
+    /// [`Constants::file_watch_interval`](super::Constants::file_watch_interval). Configs exported by the game
+    /// are reported too.
+    FileChanged(PathBuf),
+
+    /// Emitted for each save synced with the cloud, see [`Files::sync_saves`](crate::files::Files::sync_saves).
+    SaveSynced { save_name: String, status: SaveSyncStatus },
 }
 
 pub(crate) fn run_render_loop<F, C>(
     constants: Constants,
     input: Input<C>,
     app_event_sender: Sender<ApplicationEvent>,
+    asset_error_sender: Sender<AssetError>,
     on_render_frame: F,
 ) where
     F: FnMut(&mut Renderer) -> bool + 'static,
@@ -72,6 +92,7 @@ pub(crate) fn run_render_loop<F, C>(
         renderer: None,
         input,
         app_event_sender,
+        asset_error_sender,
         on_render_frame,
     };
 
@@ -110,6 +131,7 @@ where
     renderer: Option<Renderer>,
     input: Input<C>,
     app_event_sender: Sender<ApplicationEvent>,
+    asset_error_sender: Sender<AssetError>,
 
     on_render_frame: F,
 }
@@ -143,7 +165,12 @@ where
                     .expect("Must succeed in appending canvas to document body.");
             }
 
-            self.renderer = Some(Renderer::new(&self.constants, window, event_loop));
+            self.renderer = Some(Renderer::new(
+                &self.constants,
+                window,
+                event_loop,
+                self.asset_error_sender.clone(),
+            ));
         } else {
             self.app_event_sender.send(ApplicationEvent::Resumed).unwrap();
         }
@@ -153,6 +180,17 @@ where
         self.app_event_sender.send(ApplicationEvent::Suspended).unwrap();
     }
 
+    fn device_event(
+        &mut self,
+        _event_loop: &winit::event_loop::ActiveEventLoop,
+        _device_id: winit::event::DeviceId,
+        event: DeviceEvent,
+    ) {
+        if let DeviceEvent::MouseMotion { delta } = event {
+            self.input.handle_mouse_motion_event(delta);
+        }
+    }
+
     fn window_event(
         &mut self,
         event_loop: &winit::event_loop::ActiveEventLoop,
@@ -160,14 +198,26 @@ where
         event: winit::event::WindowEvent,
     ) {
         let renderer = self.renderer.as_mut().unwrap();
+        let window = renderer
+            .window
+            .clone()
+            .expect("Renderer of the application must have a window");
+
+        if window.id() != window_id {
+            if !renderer.secondary_window_event(window_id, &event) {
+                log_info!("Ignoring event of an unknown window {:?}", window_id);
+            }
+            return;
+        }
+
         let used_by_ui = renderer.ui_event_process(&event);
 
         match event {
             WindowEvent::Resized(_) => {
-                renderer.resize_window(renderer.window.inner_size().into(), None);
+                renderer.resize_window(window.inner_size().into(), None);
             }
             WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
-                renderer.resize_window(renderer.window.inner_size().into(), Some(scale_factor as f32));
+                renderer.resize_window(window.inner_size().into(), Some(scale_factor as f32));
             }
             WindowEvent::KeyboardInput { .. } => {
                 if !used_by_ui {
@@ -189,12 +239,20 @@ where
                 let loc = renderer.camera().pos_to_loc(pos);
                 self.input.handle_mouse_move_event(loc, pos);
             }
+            WindowEvent::Touch(touch) if !used_by_ui => {
+                let resolution = renderer.window_resolution();
+                let pos = Position::from_physical_position(touch.location, resolution);
+                let aspect_ratio = resolution.width as f32 / resolution.height.max(1) as f32;
+                self.input.handle_touch_event(touch.id, touch.phase, pos, aspect_ratio);
+            }
             WindowEvent::RedrawRequested => {
+                self.input.poll_gamepads();
                 if !(self.on_render_frame)(renderer) {
                     event_loop.exit();
                 }
 
-                renderer.window.request_redraw();
+                renderer.create_pending_resources(event_loop);
+                window.request_redraw();
             }
             WindowEvent::Focused(focused) => {
                 if focused {
@@ -208,9 +266,5 @@ where
             }
             _ => {}
         }
-
-        if renderer.window.id() != window_id {
-            panic!("Window ID mismatch: {:?} != {:?}", renderer.window.id(), window_id);
-        }
     }
 }
@@ -0,0 +1,127 @@
+use std::time::Duration;
+
+use ion_common::{Instant, log_info, log_warn};
+
+use crate::files::{FileTask, Files};
+
+use super::{AutosaveConstants, application::ApplicationEvent, universe::Universe, world::WorldType};
+
+// ---------------------------------------------------------- //
+// ------------------------ Autosaves ----------------------- //
+// ---------------------------------------------------------- //
+
+/// Schedules the autosaves of [`AutosaveConstants`] on the universe thread.
+pub(crate) struct Autosave {
+    constants: Option<AutosaveConstants>,
+    running_time: Duration,
+    last_update: Option<Instant>,
+    next_slot: u32,
+    pending: Option<(String, FileTask<()>)>,
+}
+
+impl Autosave {
+    pub(crate) fn new(constants: Option<AutosaveConstants>) -> Self {
+        Self {
+            constants,
+            running_time: Duration::ZERO,
+            last_update: None,
+            next_slot: 0,
+            pending: None,
+        }
+    }
+
+    /// Starts an autosave if one is due, and reports the one in progress once it finishes.
+    /// Must be called between universe frames, with the universe unlocked, as the universe is saved as it is.
+    pub(crate) fn update<W: WorldType>(&mut self, universe: &Universe<W>, files: &Files) -> Option<ApplicationEvent> {
+        let finished = self.poll_pending();
+        let is_running = universe.is_running() && !universe.is_playing_replay();
+        if let Some(save_name) = self.next_due(Instant::now(), is_running) {
+            let task = universe.export_save_async(files, &save_name);
+            self.pending = Some((save_name, task));
+        }
+        finished
+    }
+
+    /// Returns the save to write if an autosave is due. Only time spent running counts towards the interval.
+    fn next_due(&mut self, now: Instant, is_running: bool) -> Option<String> {
+        let constants = self.constants.as_ref()?;
+        let last_update = self.last_update.replace(now);
+        if !is_running {
+            return None;
+        }
+        if let Some(last_update) = last_update {
+            self.running_time += now.duration_since(last_update).unwrap_or_default();
+        }
+        // A slow disk delays the next autosave instead of queueing them up
+        if self.running_time < constants.interval || self.pending.is_some() {
+            return None;
+        }
+        self.running_time = Duration::ZERO;
+        let slot = self.next_slot;
+        self.next_slot = (slot + 1) % constants.slots.max(1);
+        Some(constants.slot_save_name(slot))
+    }
+
+    fn poll_pending(&mut self) -> Option<ApplicationEvent> {
+        let result = self.pending.as_ref()?.1.try_join()?;
+        let (save_name, _) = self.pending.take().unwrap();
+        Some(match result {
+            Ok(()) => {
+                log_info!("Autosaved universe to {}", save_name);
+                ApplicationEvent::AutosaveCompleted { save_name }
+            }
+            Err(error) => {
+                log_warn!("Failed to autosave universe to {}: {}", save_name, error);
+                ApplicationEvent::AutosaveFailed { save_name, error }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_autosaves_rotate_through_slots_while_running() {
+        let mut autosave = Autosave::new(Some(AutosaveConstants {
+            interval: Duration::from_secs(10),
+            slots: 2,
+            save_name: "autosave",
+        }));
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+
+        assert_eq!(autosave.next_due(at(0), true), None);
+        assert_eq!(autosave.next_due(at(9), true), None);
+        assert_eq!(autosave.next_due(at(10), true), Some("autosave_0".to_string()));
+
+        // Paused time doesn't count
+        assert_eq!(autosave.next_due(at(15), true), None);
+        assert_eq!(autosave.next_due(at(100), false), None);
+        assert_eq!(autosave.next_due(at(104), true), None);
+        assert_eq!(autosave.next_due(at(105), true), Some("autosave_1".to_string()));
+
+        assert_eq!(autosave.next_due(at(115), true), Some("autosave_0".to_string()));
+    }
+
+    #[test]
+    fn test_autosave_waits_for_pending_save() {
+        let mut autosave = Autosave::new(Some(AutosaveConstants {
+            interval: Duration::from_secs(1),
+            slots: 3,
+            save_name: "autosave",
+        }));
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+
+        autosave.pending = Some(("autosave_0".to_string(), FileTask::finished(Ok(()))));
+        assert_eq!(autosave.next_due(at(0), true), None);
+        assert_eq!(autosave.next_due(at(5), true), None);
+        assert!(matches!(
+            autosave.poll_pending(),
+            Some(ApplicationEvent::AutosaveCompleted { save_name }) if save_name == "autosave_0"
+        ));
+        assert_eq!(autosave.next_due(at(6), true), Some("autosave_0".to_string()));
+    }
+}
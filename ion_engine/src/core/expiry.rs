@@ -0,0 +1,190 @@
+use std::collections::BTreeMap;
+
+use bincode::{Decode, Encode};
+
+use super::FrameId;
+
+// ---------------------------------------------------------- //
+// ---------------------- Expiry queue ---------------------- //
+// ---------------------------------------------------------- //
+
+/// World defined category of an expiring object, for example decals, corpses or dropped items.
+pub type ExpiryKind = u16;
+
+/// World defined identifier of an expiring object. Only needs to be unique within its [`ExpiryKind`].
+pub type ExpiryObjectId = u64;
+
+/// Object whose expiry frame has been reached. Handed to [`WorldType::on_objects_expired`](super::world::WorldType::on_objects_expired).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Encode, Decode)]
+pub struct ExpiredObject {
+    pub kind: ExpiryKind,
+    pub id: ExpiryObjectId,
+    /// Frame the object was scheduled to expire on. May be earlier than the current frame if the budget was exceeded.
+    pub expires_on: FrameId,
+}
+
+/// Frame-budgeted queue of objects that should be removed from the world on a given frame.
+///
+/// Worlds schedule short-lived objects (decals, corpses, dropped items etc.) here instead of scanning
+/// for them every frame. The engine drains the queue after each universe frame and hands the expired objects
+/// to the world in a single batch. At most `budget_per_frame` objects are expired per frame; the rest are
+/// carried over to the following frames in expiry order. The queue is part of the world state, so it must be
+/// serialized with the world to keep expiry deterministic across save/load and multiplayer.
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct ExpiryQueue {
+    budget_per_frame: usize,
+    buckets: BTreeMap<FrameId, Vec<(ExpiryKind, ExpiryObjectId)>>,
+    scheduled: BTreeMap<(ExpiryKind, ExpiryObjectId), FrameId>,
+}
+
+impl ExpiryQueue {
+    /// Creates an empty queue that expires at most `budget_per_frame` objects per frame.
+    pub fn new(budget_per_frame: usize) -> Self {
+        assert!(budget_per_frame > 0, "Expiry budget must be greater than zero");
+        Self {
+            budget_per_frame,
+            buckets: BTreeMap::new(),
+            scheduled: BTreeMap::new(),
+        }
+    }
+
+    pub fn budget_per_frame(&self) -> usize {
+        self.budget_per_frame
+    }
+
+    pub fn set_budget_per_frame(&mut self, budget_per_frame: usize) {
+        assert!(budget_per_frame > 0, "Expiry budget must be greater than zero");
+        self.budget_per_frame = budget_per_frame;
+    }
+
+    /// Schedules an object to expire on the given frame.
+    /// If the object is already scheduled, its expiry frame is replaced.
+    pub fn schedule(&mut self, kind: ExpiryKind, id: ExpiryObjectId, expires_on: FrameId) {
+        self.cancel(kind, id);
+        self.scheduled.insert((kind, id), expires_on);
+        self.buckets.entry(expires_on).or_default().push((kind, id));
+    }
+
+    /// Removes an object from the queue, for example when it was destroyed before expiring.
+    /// Returns true if the object was scheduled.
+    pub fn cancel(&mut self, kind: ExpiryKind, id: ExpiryObjectId) -> bool {
+        let Some(expires_on) = self.scheduled.remove(&(kind, id)) else {
+            return false;
+        };
+        let bucket = self.buckets.get_mut(&expires_on).unwrap();
+        bucket.retain(|entry| *entry != (kind, id));
+        if bucket.is_empty() {
+            self.buckets.remove(&expires_on);
+        }
+        true
+    }
+
+    /// Returns the frame the object is scheduled to expire on, if any.
+    pub fn expires_on(&self, kind: ExpiryKind, id: ExpiryObjectId) -> Option<FrameId> {
+        self.scheduled.get(&(kind, id)).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.scheduled.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scheduled.is_empty()
+    }
+
+    /// Removes and returns objects that expire on or before the given frame, up to the frame budget.
+    /// Objects are returned ordered by expiry frame, and by scheduling order within a frame.
+    pub(crate) fn drain_expired(&mut self, frame: FrameId) -> Vec<ExpiredObject> {
+        let mut expired = Vec::new();
+        while expired.len() < self.budget_per_frame {
+            let Some(mut bucket) = self.buckets.first_entry() else {
+                break;
+            };
+            let expires_on = *bucket.key();
+            if expires_on > frame {
+                break;
+            }
+
+            let take = (self.budget_per_frame - expired.len()).min(bucket.get().len());
+            for (kind, id) in bucket.get_mut().drain(..take) {
+                self.scheduled.remove(&(kind, id));
+                expired.push(ExpiredObject { kind, id, expires_on });
+            }
+            if bucket.get().is_empty() {
+                bucket.remove();
+            }
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drain_returns_only_expired_in_order() {
+        let mut queue = ExpiryQueue::new(10);
+        queue.schedule(0, 1, 5);
+        queue.schedule(1, 2, 3);
+        queue.schedule(0, 3, 8);
+
+        assert!(queue.drain_expired(2).is_empty());
+
+        let expired = queue.drain_expired(5);
+        assert_eq!(expired.iter().map(|e| e.id).collect::<Vec<_>>(), vec![2, 1]);
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.expires_on(0, 3), Some(8));
+    }
+
+    #[test]
+    fn drain_respects_budget_and_carries_over() {
+        let mut queue = ExpiryQueue::new(2);
+        for id in 0..5 {
+            queue.schedule(0, id, 1);
+        }
+
+        assert_eq!(
+            queue.drain_expired(1).iter().map(|e| e.id).collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+        assert_eq!(
+            queue.drain_expired(2).iter().map(|e| e.id).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+
+        let expired = queue.drain_expired(3);
+        assert_eq!(
+            expired,
+            vec![ExpiredObject {
+                kind: 0,
+                id: 4,
+                expires_on: 1
+            }]
+        );
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn reschedule_and_cancel() {
+        let mut queue = ExpiryQueue::new(10);
+        queue.schedule(0, 1, 5);
+        queue.schedule(0, 1, 10);
+        queue.schedule(1, 1, 5);
+
+        assert_eq!(queue.len(), 2);
+        assert_eq!(
+            queue.drain_expired(5),
+            vec![ExpiredObject {
+                kind: 1,
+                id: 1,
+                expires_on: 5
+            }]
+        );
+
+        assert!(queue.cancel(0, 1));
+        assert!(!queue.cancel(0, 1));
+        assert!(queue.drain_expired(10).is_empty());
+        assert!(queue.is_empty());
+    }
+}
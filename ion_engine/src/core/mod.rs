@@ -1,24 +1,35 @@
 use std::{
     collections::BTreeMap,
+    io,
     net::SocketAddr,
     path::PathBuf,
     sync::{Arc, atomic::AtomicBool, mpsc::Receiver},
+    time::Duration,
 };
 
-use ion_common::{PlayerId, net::NetworkPlayerInfo};
+use bincode::{Decode, Encode};
+use ion_common::{Map, PlayerId, net::NetworkPlayerInfo};
 use universe::Universe;
-use world::WorldType;
+use world::{WorldId, WorldType};
 
 use crate::{
     core::application::ApplicationEvent,
-    files::Files,
-    gfx::{GfxFrameData, renderer::Renderer},
+    files::{Files, asset_error::AssetError, storage::StorageBackend},
+    gfx::{
+        GfxFrameData,
+        gfx_config::{GfxAdapterSelection, GfxBackend},
+        renderer::Renderer,
+    },
     input::input_state::InputState,
-    net::NetworkEvent,
+    net::{Network, NetworkEvent},
+    util::config::ConfigFormat,
 };
 
 pub mod application;
+pub(crate) mod autosave;
 pub mod coordinates;
+pub mod expiry;
+mod replay;
 pub mod universe;
 pub mod world;
 
@@ -73,13 +84,36 @@ pub struct RenderFrameProps<'a, W: WorldType> {
 
     /// Receiver for network events. These include multiplayer events like updates about joining progress or players leaving etc.
     pub network_events: &'a Receiver<NetworkEvent>,
+
+    /// Receiver for asset errors. These include texture decode failures, missing files, atlas overflows etc.
+    /// The failing assets are skipped, so these are mostly useful for showing warnings during development.
+    pub asset_errors: &'a Receiver<AssetError>,
+}
+
+/// Handle to the engine for dedicated servers, see [`crate::run_headless`].
+/// Each universe frame, a function for the frame is called with this as an argument.
+pub struct HeadlessFrameProps<'a, W: WorldType> {
+    /// Engine shutdown handle. Set to false for graceful shutdown.
+    pub engine_running: Arc<AtomicBool>,
+
+    /// Universe module. Allows loading in, starting and stopping the universe.
+    pub universe: &'a Universe<W>,
+
+    /// Network module. Allows starting the multiplayer server.
+    pub network: &'a Network<W>,
+
+    /// Files module. Access to the filesystem.
+    pub files: &'a Files,
+
+    /// Receiver for network events. These include multiplayer events like players joining or leaving.
+    pub network_events: &'a Receiver<NetworkEvent>,
 }
 
 pub struct UniverseFrameProps<'a, W: WorldType> {
     // Handle to universe-level data
     pub universe_data: &'a W::UniverseDataType,
 
-    // Multiplayer updates
+    // Multiplayer updates. These include players moving to or from this world, see `Network::mp_move_player`.
     pub players_joining: &'a [NetworkPlayerInfo],
     pub players_leaving: &'a [PlayerId],
 
@@ -87,6 +121,14 @@ pub struct UniverseFrameProps<'a, W: WorldType> {
     pub actions: &'a BTreeMap<PlayerId, Vec<W::ActionType>>,
 }
 
+/// Player that moved between worlds on a frame, without joining or leaving the universe.
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub(crate) struct PlayerMove {
+    pub player_info: NetworkPlayerInfo,
+    pub left_worlds: Vec<WorldId>,
+    pub joined_worlds: Vec<WorldId>,
+}
+
 // ---------------------------------------------------------- //
 // -------------- Global constant data formats -------------- //
 // ---------------------------------------------------------- //
@@ -96,6 +138,95 @@ pub struct Constants {
     pub app_name: &'static str,
     pub gfx: GfxConstants,
     pub net: Option<NetworkConstants>,
+    pub saves: SaveConstants,
+    /// Format configs are exported in, unless another is given, see [`Files::export_config`].
+    pub config_format: ConfigFormat,
+    /// Where configs, saves and replays are kept. If `None`, the file system on native platforms and the browser
+    /// storage on wasm, see [`StorageBackend`].
+    pub storage: Option<Arc<dyn StorageBackend>>,
+    /// Autosaving of the running universe. If `None`, the universe is only saved when the game asks for it.
+    pub autosave: Option<AutosaveConstants>,
+    /// How often the asset, config and mod directories are checked for changed files, reported with
+    /// [`ApplicationEvent::FileChanged`](application::ApplicationEvent::FileChanged) for hot reloading.
+    /// Native only. Meant for development, as every check lists the directories.
+    pub file_watch_interval: Option<Duration>,
+}
+
+/// How often the running universe is saved automatically, and where.
+///
+/// The saves rotate through `slots` saves named `{save_name}_{slot}`, so that a save broken by a crash mid-write
+/// still leaves the earlier ones. Time only counts while the universe is running, and replays are never autosaved.
+/// Each finished autosave is reported with [`ApplicationEvent::AutosaveCompleted`] or
+/// [`ApplicationEvent::AutosaveFailed`].
+#[derive(Debug, Clone)]
+pub struct AutosaveConstants {
+    pub interval: Duration,
+    pub slots: u32,
+    pub save_name: &'static str,
+}
+
+impl AutosaveConstants {
+    /// Name of the save written to the slot.
+    pub fn slot_save_name(&self, slot: u32) -> String {
+        format!("{}_{}", self.save_name, slot)
+    }
+}
+
+/// Upgrades the files of a save by one version, see [`SaveConstants::with_migration`].
+pub type SaveMigration = fn(&mut Map<String, Vec<u8>>) -> Result<(), io::Error>;
+
+/// Version of the save format, and how older saves are brought up to it.
+///
+/// Every save written by [`Files::export_save`] is tagged with the version. When a save of an older version is
+/// imported, the migrations from its version on are run on its files in order, so that the game only ever decodes
+/// saves of the current version. Saves from before versioning are version 0.
+#[derive(Debug, Clone, Default)]
+pub struct SaveConstants {
+    /// Bumped whenever the saved data changes in a way older code can't read, with a migration from the previous one.
+    pub version: u32,
+    migrations: Map<u32, SaveMigration>,
+}
+
+impl SaveConstants {
+    pub fn new(version: u32) -> Self {
+        Self {
+            version,
+            migrations: Map::default(),
+        }
+    }
+
+    /// Registers a migration that upgrades saves of `from_version` to `from_version + 1`.
+    pub fn with_migration(mut self, from_version: u32, migration: SaveMigration) -> Self {
+        assert!(
+            from_version < self.version,
+            "Migrations must be from versions before the current one"
+        );
+        self.migrations.insert(from_version, migration);
+        self
+    }
+
+    /// Runs the migrations from `version` to the current version on the files.
+    pub(crate) fn migrate(&self, version: u32, files: &mut Map<String, Vec<u8>>) -> Result<(), io::Error> {
+        if version > self.version {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Save version {} is newer than the supported version {}",
+                    version, self.version
+                ),
+            ));
+        }
+        for from_version in version..self.version {
+            let migration = self.migrations.get(&from_version).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("No migration from save version {}", from_version),
+                )
+            })?;
+            migration(files)?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -104,12 +235,46 @@ pub struct NetworkConstants {
     pub bind_addr: SocketAddr,
     /// Address to connect to for server host service
     pub host_addr: SocketAddr,
+    /// Keepalive, disconnect and join timeouts of multiplayer connections
+    pub timeouts: NetworkTimeouts,
+    /// Version of the game. Clients can only join servers running the same version.
+    pub app_version: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NetworkTimeouts {
+    /// Interval of keepalive messages sent over otherwise quiet connections
+    pub keepalive_interval: Duration,
+    /// Time without any messages after which the other end of a connection is considered gone
+    pub disconnect_timeout: Duration,
+    /// Time a client waits for its join to complete. Servers wait twice this long so that clients give up first.
+    pub join_timeout: Duration,
+    /// Interval at which an unanswered join handshake is sent again
+    pub join_retry_interval: Duration,
+    /// Number of times an unanswered join handshake is sent again before giving up
+    pub join_retries: u32,
+}
+
+impl Default for NetworkTimeouts {
+    fn default() -> Self {
+        Self {
+            keepalive_interval: Duration::from_secs(1),
+            disconnect_timeout: Duration::from_secs(15),
+            join_timeout: Duration::from_secs(30),
+            join_retry_interval: Duration::from_secs(5),
+            join_retries: 3,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct GfxConstants {
     /// Path to texture asset directory
     pub asset_path: PathBuf,
+    /// Paths to asset archives read in addition to the asset directory, created with
+    /// [`AssetArchive::pack`](crate::files::asset_archive::AssetArchive::pack).
+    /// Later archives take priority over earlier ones, and all archives over the asset directory.
+    pub asset_archives: Vec<PathBuf>,
     /// Camera angle in degrees from vertical (0 = top-down, 90 = side view)
     pub camera_angle_deg: f32,
     /// Number of pixels per world unit for rendering, measured on screen x-axis
@@ -118,4 +283,8 @@ pub struct GfxConstants {
     pub height_units_total: f32,
     /// Zero height level scaled to 0-1 range (e.g. 0.25 means zero is 25% up from min height)
     pub height_scaled_zero: f32,
+    /// Graphics API of the renderer. Ignored on wasm, which always uses WebGL.
+    pub backend: GfxBackend,
+    /// Graphics adapter of the renderer. Falls back to `Auto` if the adapter is not found or doesn't support the window.
+    pub adapter: GfxAdapterSelection,
 }
@@ -0,0 +1,141 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+
+use bincode::{Decode, Encode, config};
+use ion_common::net::NetworkPlayerInfo;
+use ion_common::{Map, PlayerId};
+
+use super::world::{ActionType, WorldId};
+use super::{FrameId, PlayerMove};
+
+// Bumped whenever the replay format changes, so that old replays are rejected instead of misread
+const REPLAY_FORMAT_VERSION: u32 = 2;
+
+// ---------------------------------------------------------- //
+// ------------------------- Replays ------------------------ //
+// ---------------------------------------------------------- //
+
+/// Synchronized actions of a single universe frame.
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub(crate) struct ReplayFrame<C: ActionType> {
+    pub frame: FrameId,
+    pub players_joined: Vec<NetworkPlayerInfo>,
+    pub players_left: Vec<PlayerId>,
+    pub players_moved: Vec<PlayerMove>,
+    pub actions: Map<WorldId, BTreeMap<PlayerId, Vec<C>>>,
+}
+
+/// State of the universe when recording started, and the stateful actions of every frame since.
+///
+/// Executing the frames on the initial state reproduces the recorded session, as long as the worlds are
+/// deterministic. Stateless actions are not recorded, as they only affect the local state of the recording player.
+#[derive(Debug, Clone, Encode, Decode)]
+pub(crate) struct Replay<C: ActionType> {
+    version: u32,
+    pub start_frame: FrameId,
+    pub universe_data: Vec<u8>,
+    pub worlds_data: Vec<Vec<u8>>,
+    pub frames: Vec<ReplayFrame<C>>,
+}
+
+impl<C: ActionType> Replay<C> {
+    pub(crate) fn new(start_frame: FrameId, universe_data: Vec<u8>, worlds_data: Vec<Vec<u8>>) -> Self {
+        Self {
+            version: REPLAY_FORMAT_VERSION,
+            start_frame,
+            universe_data,
+            worlds_data,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Records the stateful actions of the frame. A frame that was already recorded replaces the earlier recording
+    /// of it and all frames after it, as rollback clients execute frames again with corrected actions.
+    pub(crate) fn record(&mut self, mut frame: ReplayFrame<C>) {
+        if frame.frame < self.start_frame {
+            return;
+        }
+        while self.frames.last().is_some_and(|last| last.frame >= frame.frame) {
+            self.frames.pop();
+        }
+        for players in frame.actions.values_mut() {
+            for actions in players.values_mut() {
+                actions.retain(|action| action.is_stateful());
+            }
+        }
+        self.frames.push(frame);
+    }
+
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        bincode::encode_to_vec(self, config::standard()).unwrap()
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, io::Error> {
+        let invalid = |reason: String| io::Error::new(io::ErrorKind::InvalidData, reason);
+        let (replay, _): (Self, usize) = bincode::decode_from_slice(bytes, config::standard())
+            .map_err(|err| invalid(format!("Replay could not be decoded: {}", err)))?;
+        if replay.version != REPLAY_FORMAT_VERSION {
+            return Err(invalid(format!("Unsupported replay version {}", replay.version)));
+        }
+        Ok(replay)
+    }
+}
+
+/// Whether the universe is recording or playing back a replay.
+pub(crate) enum ReplayState<C: ActionType> {
+    Recording(Replay<C>),
+    Playing(VecDeque<ReplayFrame<C>>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Encode, Decode)]
+    enum TestAction {
+        Move(i32),
+        Look(i32),
+    }
+
+    impl ActionType for TestAction {
+        fn is_stateful(&self) -> bool {
+            matches!(self, Self::Move(_))
+        }
+    }
+
+    fn frame(frame: FrameId, actions: Vec<TestAction>) -> ReplayFrame<TestAction> {
+        let mut players = BTreeMap::new();
+        players.insert(1, actions);
+        let mut worlds = Map::default();
+        worlds.insert(0, players);
+        ReplayFrame {
+            frame,
+            players_joined: Vec::new(),
+            players_left: Vec::new(),
+            players_moved: Vec::new(),
+            actions: worlds,
+        }
+    }
+
+    #[test]
+    fn replays_record_stateful_actions_and_round_trip() {
+        let mut replay = Replay::new(10, vec![1, 2, 3], vec![vec![4]]);
+        replay.record(frame(9, vec![TestAction::Move(0)]));
+        replay.record(frame(10, vec![TestAction::Move(1), TestAction::Look(5)]));
+        replay.record(frame(11, vec![TestAction::Move(2)]));
+        replay.record(frame(12, vec![TestAction::Move(3)]));
+
+        // Frames executed again replace the earlier recordings
+        replay.record(frame(11, vec![TestAction::Move(-2)]));
+
+        let decoded = Replay::<TestAction>::from_bytes(&replay.to_bytes()).unwrap();
+        assert_eq!(decoded.start_frame, 10);
+        assert_eq!(decoded.universe_data, vec![1, 2, 3]);
+        assert_eq!(
+            decoded.frames,
+            vec![frame(10, vec![TestAction::Move(1)]), frame(11, vec![TestAction::Move(-2)])]
+        );
+
+        assert!(Replay::<TestAction>::from_bytes(&[1, 2, 3]).is_err());
+    }
+}
@@ -1,9 +1,11 @@
+use std::collections::BTreeMap;
 use std::sync::atomic::AtomicU64;
 use std::sync::mpsc;
 use std::sync::mpsc::{Receiver, Sender};
 use std::time::Duration;
 use std::{
     fmt::Debug,
+    io,
     sync::{
         Mutex, MutexGuard,
         atomic::{AtomicBool, AtomicU32, Ordering},
@@ -11,12 +13,15 @@ use std::{
 };
 
 use ion_common::net::{NetworkPlayerInfo, NetworkServerInfo};
-use ion_common::{Map, log_error, log_info};
+use ion_common::{Map, PlayerId, log_error, log_info};
 
+use crate::files::{FileTask, Files, save_info::SaveMetadata};
 use crate::input::input_state::InputState;
+use crate::net::AdminCommand;
 
 use super::{
-    DEFAULT_UPS, FrameId,
+    DEFAULT_UPS, FrameId, PlayerMove,
+    replay::{Replay, ReplayFrame, ReplayState},
     world::{ActionType, WorldId, WorldType},
 };
 
@@ -60,6 +65,25 @@ pub trait UniverseDataType: 'static + Send + Sync {
     /// over the network. The implementation receives a lock to all worlds
     /// to include their state in the serialized data.
     fn as_bytes(&self, worlds: &MutexGuard<Map<WorldId, Self::WorldType>>) -> Vec<u8>;
+
+    /// Returns the details stored alongside saves of the universe, shown when saves are listed without importing
+    /// them, see [`Files::list_saves`]. By default, nothing is stored.
+    fn save_metadata(&self) -> SaveMetadata {
+        SaveMetadata::default()
+    }
+
+    /// Handles an admin command sent to the server over the remote console, see [`crate::net::RconClient`].
+    ///
+    /// Only called on the server, between universe frames, for the commands the engine doesn't carry out itself.
+    /// Changes made to the worlds here are not seen by clients, so they must be synced by other means.
+    /// Returns the reply shown to the admin, or why the command failed. By default all commands are refused.
+    fn on_admin_command(
+        &self,
+        command: &AdminCommand,
+        _worlds: &mut MutexGuard<Map<WorldId, Self::WorldType>>,
+    ) -> Result<String, String> {
+        Err(format!("Unsupported command: {:?}", command))
+    }
 }
 
 // ---------------------------------------------------------- //
@@ -112,8 +136,11 @@ pub struct Universe<W: WorldType> {
     active_frame: AtomicU64,
     active_world_id: AtomicU32,
     universe_frame_time: AtomicU64,
+    frame_pacing: AtomicU64,
     universe_data: Mutex<Option<W::UniverseDataType>>,
     worlds_data: Mutex<Map<WorldId, W>>,
+    shutdown_save: Mutex<Option<String>>,
+    replay: Mutex<Option<ReplayState<W::ActionType>>>,
 
     action_sender: Sender<ActionMessage<W::ActionType>>,
     action_receiver: Mutex<Receiver<ActionMessage<W::ActionType>>>,
@@ -129,8 +156,11 @@ impl<W: WorldType> Universe<W> {
             active_frame: AtomicU64::new(0),
             active_world_id: AtomicU32::new(u32::MAX),
             universe_frame_time: AtomicU64::new(1_000_000_000 / DEFAULT_UPS),
+            frame_pacing: AtomicU64::new(1.0f64.to_bits()),
             universe_data: Mutex::new(None),
             worlds_data: Mutex::new(Map::default()),
+            shutdown_save: Mutex::new(None),
+            replay: Mutex::new(None),
             action_sender,
             action_receiver: Mutex::new(action_receiver),
         }
@@ -204,6 +234,17 @@ impl<W: WorldType> Universe<W> {
             .store(1_000_000_000 / target_ups as u64, Ordering::Relaxed);
     }
 
+    /// Returns the time the universe thread actually takes for each frame.
+    /// Multiplayer clients tick slightly faster or slower than the universe speed to stay in time with the server.
+    pub fn paced_frame_time(&self) -> Duration {
+        self.universe_frame_time()
+            .div_f64(f64::from_bits(self.frame_pacing.load(Ordering::Relaxed)))
+    }
+
+    pub(crate) fn set_frame_pacing(&self, pacing: f64) {
+        self.frame_pacing.store(pacing.to_bits(), Ordering::Relaxed);
+    }
+
     // ---------------------------------------------------------- //
     // ------------------- World management --------------------- //
     // ---------------------------------------------------------- //
@@ -249,6 +290,7 @@ impl<W: WorldType> Universe<W> {
         let mut worlds_data_lock = self.worlds_data.lock().unwrap();
         worlds_data_lock.clear();
         universe_data_lock.take();
+        self.replay.lock().unwrap().take();
 
         drop(universe_data_lock);
         drop(worlds_data_lock);
@@ -256,6 +298,7 @@ impl<W: WorldType> Universe<W> {
         self.pause();
         self.active_world_id.store(u32::MAX, Ordering::SeqCst);
         self.active_frame.store(0, Ordering::SeqCst);
+        self.set_frame_pacing(1.0);
     }
 
     /// Adds a single world to the universe.
@@ -340,6 +383,172 @@ impl<W: WorldType> Universe<W> {
         self.active_world_id.store(u32::MAX, Ordering::Release);
     }
 
+    // ---------------------------------------------------------- //
+    // ------------------------ Saving -------------------------- //
+    // ---------------------------------------------------------- //
+
+    /// Serializes universe data and all worlds to save files, in the format expected by [`Files::export_save`].
+    /// Each world is stored under its name and universe data under `"universe"`.
+    /// Returns `None` if no universe is loaded.
+    /// NOTE: This blocks the universe thread.
+    pub fn save_files(&self) -> Option<Vec<(String, Vec<u8>)>> {
+        let universe_data_lock = self.universe_data.lock().unwrap();
+        let worlds_data_lock = self.worlds_data.lock().unwrap();
+
+        Some(Self::build_save_files(universe_data_lock.as_ref()?, &worlds_data_lock))
+    }
+
+    /// Returns the details stored alongside saves of the universe, see [`UniverseDataType::save_metadata`].
+    /// Empty if no universe is loaded.
+    pub fn save_metadata(&self) -> SaveMetadata {
+        self.universe_data
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|universe_data| universe_data.save_metadata())
+            .unwrap_or_default()
+    }
+
+    /// Same as [`Universe::save_files`], for callers that already hold the locks.
+    pub(crate) fn build_save_files(
+        universe_data: &W::UniverseDataType,
+        worlds: &MutexGuard<Map<WorldId, W>>,
+    ) -> Vec<(String, Vec<u8>)> {
+        let mut save_files: Vec<_> = worlds
+            .values()
+            .map(|world| (world.name().to_string(), world.as_bytes()))
+            .collect();
+        save_files.push(("universe".to_string(), universe_data.as_bytes(worlds)));
+        save_files
+    }
+
+    /// Serializes the universe and writes it to the given save.
+    pub fn export_save(&self, files: &Files, save_name: &str) -> Result<(), io::Error> {
+        log_info!("Exporting universe to save {}", save_name);
+        let save_files = self
+            .save_files()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No universe loaded"))?;
+        files.export_save(save_name, save_files, self.save_metadata())
+    }
+
+    /// Same as [`Universe::export_save`], but writes the save in the background, see [`Files::export_save_async`].
+    /// Serializing still blocks the universe thread.
+    pub fn export_save_async(&self, files: &Files, save_name: &str) -> FileTask<()> {
+        log_info!("Exporting universe to save {} in the background", save_name);
+        match self.save_files() {
+            Some(save_files) => files.export_save_async(save_name, save_files, self.save_metadata()),
+            None => FileTask::finished(Err(io::Error::new(io::ErrorKind::NotFound, "No universe loaded"))),
+        }
+    }
+
+    /// Sets the save that is written when the process receives a shutdown signal (SIGINT or SIGTERM).
+    /// Mainly meant for dedicated servers. If `None`, the universe is not saved on shutdown.
+    pub fn set_shutdown_save(&self, save_name: Option<&str>) {
+        *self.shutdown_save.lock().unwrap() = save_name.map(|name| name.to_string());
+    }
+
+    /// Returns the save that is written when the process receives a shutdown signal.
+    pub fn shutdown_save(&self) -> Option<String> {
+        self.shutdown_save.lock().unwrap().clone()
+    }
+
+    // ---------------------------------------------------------- //
+    // ------------------------ Replays ------------------------- //
+    // ---------------------------------------------------------- //
+
+    /// Starts recording a replay of the universe from the active frame on.
+    /// Replaces any replay being recorded, and the recording stops if the universe is unloaded.
+    /// NOTE: This blocks the universe thread.
+    pub fn start_replay_recording(&self) -> Result<(), io::Error> {
+        let universe_data_lock = self.universe_data.lock().unwrap();
+        let worlds_data_lock = self.worlds_data.lock().unwrap();
+        let universe_data = universe_data_lock
+            .as_ref()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No universe loaded"))?;
+
+        log_info!("Recording replay from frame {}", self.active_frame());
+        let replay = Replay::new(
+            self.active_frame(),
+            universe_data.as_bytes(&worlds_data_lock),
+            worlds_data_lock.values().map(|world| world.as_bytes()).collect(),
+        );
+        *self.replay.lock().unwrap() = Some(ReplayState::Recording(replay));
+        Ok(())
+    }
+
+    /// Stops recording and writes the replay with the given name.
+    pub fn stop_replay_recording(&self, files: &Files, replay_name: &str) -> Result<(), io::Error> {
+        let mut replay_lock = self.replay.lock().unwrap();
+        let Some(ReplayState::Recording(replay)) =
+            replay_lock.take_if(|state| matches!(state, ReplayState::Recording(_)))
+        else {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "No replay is being recorded"));
+        };
+        drop(replay_lock);
+        files.export_replay(replay_name, replay.to_bytes())
+    }
+
+    /// Loads the universe from the start of the replay with the given name, and plays back the recorded actions.
+    ///
+    /// Like [`Universe::load_universe`], the universe starts paused. It is loaded without an active player, so only
+    /// the recorded players act. When the replay ends the universe is paused, after which it runs normally.
+    pub fn load_replay(&self, files: &Files, replay_name: &str) -> Result<(), io::Error> {
+        let replay = Replay::<W::ActionType>::from_bytes(&files.import_replay(replay_name)?)?;
+        let worlds = replay
+            .worlds_data
+            .iter()
+            .map(|world_data| W::from_bytes(world_data, None))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Replay contains an invalid world"))?;
+        let universe_data = W::UniverseDataType::from_bytes(&replay.universe_data, None, None);
+
+        self.load_universe(universe_data, worlds, Some(replay.start_frame));
+        *self.replay.lock().unwrap() = Some(ReplayState::Playing(replay.frames.into()));
+        Ok(())
+    }
+
+    pub fn is_recording_replay(&self) -> bool {
+        matches!(*self.replay.lock().unwrap(), Some(ReplayState::Recording(_)))
+    }
+
+    pub fn is_playing_replay(&self) -> bool {
+        matches!(*self.replay.lock().unwrap(), Some(ReplayState::Playing(_)))
+    }
+
+    /// Adds the actions of an executed frame to the replay, if one is being recorded.
+    pub(crate) fn record_replay_frame(
+        &self,
+        frame: FrameId,
+        players_joined: &[NetworkPlayerInfo],
+        players_left: &[PlayerId],
+        players_moved: &[PlayerMove],
+        actions: &Map<WorldId, BTreeMap<PlayerId, Vec<W::ActionType>>>,
+    ) {
+        if let Some(ReplayState::Recording(replay)) = &mut *self.replay.lock().unwrap() {
+            replay.record(ReplayFrame {
+                frame,
+                players_joined: players_joined.to_vec(),
+                players_left: players_left.to_vec(),
+                players_moved: players_moved.to_vec(),
+                actions: actions.clone(),
+            });
+        }
+    }
+
+    /// Takes the next frame of the replay being played back. Returns none and stops playback when it has ended.
+    pub(crate) fn next_replay_frame(&self) -> Option<ReplayFrame<W::ActionType>> {
+        let mut replay_lock = self.replay.lock().unwrap();
+        let Some(ReplayState::Playing(frames)) = &mut *replay_lock else {
+            return None;
+        };
+        let frame = frames.pop_front();
+        if frame.is_none() {
+            log_info!("Replay ended on frame {}", self.active_frame());
+            replay_lock.take();
+        }
+        frame
+    }
+
     // ---------------------------------------------------------- //
     // ----------------- Low level management ------------------- //
     // ---------------------------------------------------------- //
@@ -616,12 +825,14 @@ mod tests {
             _cached: &[ChunkLocation],
         ) -> (GfxGlobalData, GfxSpriteData, GfxDebugData) {
             use crate::core::coordinates::Location;
+            use crate::gfx::CameraFollow;
             use ion_common::Map;
             (
                 GfxGlobalData {
                     frame: 0,
                     camera_loc: Location::new(0.0, 0.0),
                     camera_scale: 1.0,
+                    camera_follow: CameraFollow::Lock,
                     lighting_ambient: 0.5,
                     lighting_sun: 1.0,
                     post_bloom: 0.0,
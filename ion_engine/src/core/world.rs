@@ -1,6 +1,7 @@
 use std::fmt::Debug;
 
 use bincode::{Decode, Encode};
+use blake2::{Blake2s256, Digest};
 use ion_common::net::NetworkPlayerInfo;
 use std::hash::Hash;
 
@@ -9,6 +10,7 @@ use crate::gfx::{GfxDebugData, GfxGlobalData, GfxSpriteData};
 use crate::input::input_state::InputState;
 
 use super::coordinates::ChunkLocation;
+use super::expiry::{ExpiredObject, ExpiryQueue};
 use super::universe::UniverseDataType;
 use super::{FrameId, RenderFrameProps};
 
@@ -24,7 +26,14 @@ pub type WorldId = u32;
 /// Commands represent high-level user intentions like "move forward", "attack", or "open menu".
 /// They are generated by the input system when specific key combinations are pressed,
 /// and are then processed by the world to generate specific [`ActionType`] instances.
-pub trait CommandType: 'static + Debug + Send + Sync + Clone + Copy + Eq + Hash + Encode + Decode<()> {}
+pub trait CommandType: 'static + Debug + Send + Sync + Clone + Copy + Eq + Hash + Encode + Decode<()> {
+    /// Input context the command belongs to, for example gameplay or a menu.
+    /// Commands in different contexts may share keys without their bindings conflicting.
+    /// Which contexts are active is controlled with the bind context stack of the input state.
+    fn bind_context(&self) -> u32 {
+        0
+    }
+}
 
 /// Game actions that modify world state and are synchronized across the network.
 ///
@@ -120,6 +129,62 @@ pub trait WorldType: 'static + Sized + Debug + Send {
     /// the network in multiplayer scenarios.
     fn as_bytes(&self) -> Vec<u8>;
 
+    /// Captures the logical state of this world for rollback multiplayer, see [`NetSyncMode::Rollback`].
+    ///
+    /// Called before every frame that is executed with predicted actions, so it should be fast.
+    /// Defaults to [`WorldType::as_bytes`].
+    ///
+    /// [`NetSyncMode::Rollback`]: crate::net::NetSyncMode::Rollback
+    fn snapshot(&self) -> Vec<u8> {
+        self.as_bytes()
+    }
+
+    /// Restores the logical state captured with [`WorldType::snapshot`], so that frames can be executed again.
+    ///
+    /// After restoring, executing the same frames with the same actions must result in the same state as before.
+    /// Only needed for rollback multiplayer; the default implementation panics.
+    fn restore_snapshot(&mut self, _snapshot: &[u8]) {
+        panic!(
+            "World {} does not support restoring snapshots required by rollback",
+            self.id()
+        );
+    }
+
+    /// Hash of the logical state of this world, compared between the server and clients to detect desyncs.
+    ///
+    /// In lockstep multiplayer, clients send their checksums to the server once a second. When they differ, both
+    /// the server and the client get a [`NetworkEvent::Desync`] with the player whose worlds differ. It is reported
+    /// once per player, as the session can't recover from it.
+    ///
+    /// Must be the same on every machine that has executed the same frames. Defaults to a hash of
+    /// [`WorldType::snapshot`], which can be overridden to leave out state that is allowed to differ.
+    ///
+    /// [`NetworkEvent::Desync`]: crate::net::NetworkEvent::Desync
+    fn checksum(&self) -> u64 {
+        let hash = Blake2s256::digest(self.snapshot());
+        u64::from_le_bytes(hash[..8].try_into().unwrap())
+    }
+
+    /// Captures the part of the world state the player is allowed to see, for snapshot multiplayer.
+    /// See [`NetSyncMode::Snapshot`].
+    ///
+    /// Called on the server for every connected player once per snapshot interval. Filtering out what the player
+    /// doesn't need keeps the snapshots small. Defaults to [`WorldType::snapshot`].
+    ///
+    /// [`NetSyncMode::Snapshot`]: crate::net::NetSyncMode::Snapshot
+    fn interest_snapshot(&self, _player: &NetworkPlayerInfo) -> Vec<u8> {
+        self.snapshot()
+    }
+
+    /// Updates the world on a client from the snapshots streamed by the server in snapshot multiplayer.
+    ///
+    /// Called before every frame with the two snapshots the frame is between, and `t` from 0.0 to 1.0 telling
+    /// how far from the previous to the next snapshot the frame is. Both are the same if the next snapshot is late.
+    /// The default implementation restores the previous snapshot without interpolating.
+    fn apply_server_snapshot(&mut self, previous: &[u8], _next: &[u8], _t: f32) {
+        self.restore_snapshot(previous);
+    }
+
     /// Builds stateful actions from user input that modify game state.
     ///
     /// Stateful actions are synchronized across the network in multiplayer games
@@ -148,6 +213,20 @@ pub trait WorldType: 'static + Sized + Debug + Send {
     /// It processes all actions for this frame and updates the world state accordingly.
     fn execute_on_universe_frame(&mut self, props: UniverseFrameProps<Self>);
 
+    /// Returns the expiry queue of this world, if it uses one.
+    ///
+    /// The engine drains the queue after every universe frame and passes the expired objects
+    /// to [`WorldType::on_objects_expired`] in a single batch. See [`ExpiryQueue`] for details.
+    fn expiry_queue(&mut self) -> Option<&mut ExpiryQueue> {
+        None
+    }
+
+    /// Called after the universe frame with all objects whose expiry frame has been reached.
+    ///
+    /// This is part of the universe frame, so the same determinism rules as for
+    /// [`WorldType::execute_on_universe_frame`] apply. Never called with an empty slice.
+    fn on_objects_expired(&mut self, _frame: FrameId, _expired: &[ExpiredObject]) {}
+
     /// Builds optimized render data for the graphics system.
     ///
     /// This method is called once per render frame for the active world only.
@@ -0,0 +1,502 @@
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use bincode::{Decode, Encode, config};
+use ion_common::{Map, log_info};
+
+use crate::files::file_helpers::{list_files, load_resource};
+
+/// Magic bytes at the start of every asset archive.
+const ARCHIVE_MAGIC: [u8; 8] = *b"IONPAK\0\0";
+/// Version of the archive layout. Archives of other versions are rejected.
+const ARCHIVE_VERSION: u32 = 1;
+/// Files are stored uncompressed unless compressing saves at least this fraction of their size.
+/// Already compressed formats such as PNG rarely do, and stored files can be read partially.
+const MIN_COMPRESSION_GAIN: f32 = 0.05;
+
+// ---------------------------------------------------------- //
+// ---------------------- Asset archive --------------------- //
+// ---------------------------------------------------------- //
+
+/// A single file of packed assets, so that shipped games don't need thousands of loose asset files.
+///
+/// The archive starts with an index of the packed files, followed by their contents. Each file is compressed
+/// with zlib if it makes the file smaller. Archives are created with [`AssetArchive::pack`] and listed in
+/// [`GfxConstants::asset_archives`](crate::core::GfxConstants::asset_archives).
+///
+/// Files are named by their path relative to the packed directory, with `/` as separator.
+pub struct AssetArchive {
+    path: PathBuf,
+    entries: Map<String, ArchiveEntry>,
+    data: ArchiveData,
+}
+
+#[derive(Debug, Clone, Encode, Decode)]
+struct ArchiveEntry {
+    name: String,
+    /// Offset from the start of the file contents.
+    offset: u64,
+    stored_size: u64,
+    compressed: bool,
+}
+
+enum ArchiveData {
+    /// Contents are read from the archive file when needed.
+    File { data_start: u64 },
+    /// Whole archive is kept in memory. Used on wasm, where files can't be read partially.
+    Memory { bytes: Vec<u8>, data_start: u64 },
+}
+
+impl AssetArchive {
+    /// Opens an archive and reads its index. File contents are read only when requested.
+    /// On wasm the whole archive is downloaded, so this can't be called from the main thread.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let (entries, data) = if cfg!(not(target_arch = "wasm32")) {
+            let mut file = File::open(&path)?;
+            let archive_len = file.metadata()?.len();
+            let (entries, data_start) = Self::read_index(&mut file, archive_len)?;
+            (entries, ArchiveData::File { data_start })
+        } else {
+            let bytes = load_resource(&path)?;
+            let (entries, data_start) = Self::read_index(&mut Cursor::new(&bytes), bytes.len() as u64)?;
+            (entries, ArchiveData::Memory { bytes, data_start })
+        };
+
+        let entries = entries.into_iter().map(|entry| (entry.name.clone(), entry)).collect();
+        Ok(Self { path, entries, data })
+    }
+
+    /// Packs all files of the directory and its subdirectories into an archive at `archive_path`.
+    /// Returns the number of packed files.
+    pub fn pack<P: AsRef<Path>, Q: AsRef<Path>>(source_dir: P, archive_path: Q) -> io::Result<usize> {
+        let source_dir = source_dir.as_ref();
+        let mut files = list_files(source_dir, None)?
+            .into_iter()
+            .map(|(path, _)| {
+                let name = entry_name(path.strip_prefix(source_dir).unwrap()).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid file name {:?}", path))
+                })?;
+                Ok((name, path))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        files.sort();
+
+        let mut entries = Vec::with_capacity(files.len());
+        let mut contents = Vec::new();
+        for (name, path) in files {
+            let bytes = std::fs::read(&path)?;
+            let compressed_bytes = miniz_oxide::deflate::compress_to_vec_zlib(&bytes, 6);
+            let compressed = (compressed_bytes.len() as f32) < bytes.len() as f32 * (1.0 - MIN_COMPRESSION_GAIN);
+            let stored = if compressed { compressed_bytes } else { bytes };
+            entries.push(ArchiveEntry {
+                name,
+                offset: contents.len() as u64,
+                stored_size: stored.len() as u64,
+                compressed,
+            });
+            contents.extend_from_slice(&stored);
+        }
+
+        let index = bincode::encode_to_vec(&entries, config::standard())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+
+        if let Some(parent) = archive_path.as_ref().parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = io::BufWriter::new(File::create(archive_path.as_ref())?);
+        file.write_all(&ARCHIVE_MAGIC)?;
+        file.write_all(&ARCHIVE_VERSION.to_le_bytes())?;
+        file.write_all(&(index.len() as u64).to_le_bytes())?;
+        file.write_all(&index)?;
+        file.write_all(&contents)?;
+        file.flush()?;
+
+        log_info!("Packed {} files to {:?}", entries.len(), archive_path.as_ref());
+        Ok(entries.len())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.contains_key(name)
+    }
+
+    /// Names of all files in the archive, in no particular order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
+    }
+
+    /// Reads and decompresses a file of the archive.
+    pub fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        let entry = self.entry(name)?;
+        let stored = self.read_stored(entry, entry.stored_size)?;
+        if entry.compressed {
+            miniz_oxide::inflate::decompress_to_vec_zlib(&stored).map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Failed to decompress {} from {:?}: {:?}", name, self.path, err),
+                )
+            })
+        } else {
+            Ok(stored)
+        }
+    }
+
+    /// Reads at most `len` bytes from the start of a file, for example to read an image header.
+    /// Only uncompressed files can be read partially, compressed ones are decompressed whole.
+    pub fn read_prefix(&self, name: &str, len: usize) -> io::Result<Vec<u8>> {
+        let entry = self.entry(name)?;
+        if entry.compressed {
+            let mut bytes = self.read(name)?;
+            bytes.truncate(len);
+            Ok(bytes)
+        } else {
+            self.read_stored(entry, entry.stored_size.min(len as u64))
+        }
+    }
+
+    fn entry(&self, name: &str) -> io::Result<&ArchiveEntry> {
+        self.entries.get(name).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("File {} not found in {:?}", name, self.path),
+            )
+        })
+    }
+
+    /// Reads `len` bytes from the start of the stored contents of an entry. `len` is at most the stored size,
+    /// and entries were checked to be within the archive when it was opened.
+    fn read_stored(&self, entry: &ArchiveEntry, len: u64) -> io::Result<Vec<u8>> {
+        match &self.data {
+            ArchiveData::File { data_start } => {
+                let mut file = File::open(&self.path)?;
+                file.seek(SeekFrom::Start(data_start + entry.offset))?;
+                let mut bytes = vec![0; len as usize];
+                file.read_exact(&mut bytes)?;
+                Ok(bytes)
+            }
+            ArchiveData::Memory { bytes, data_start } => {
+                let start = (data_start + entry.offset) as usize;
+                bytes
+                    .get(start..start + len as usize)
+                    .map(<[u8]>::to_vec)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "Archive is truncated"))
+            }
+        }
+    }
+
+    /// Reads the header and index of an archive that is `archive_len` bytes long.
+    /// Returns the entries and the offset of the file contents.
+    ///
+    /// Fails if the index or any of the files would extend past the end of the archive.
+    fn read_index<R: Read>(reader: &mut R, archive_len: u64) -> io::Result<(Vec<ArchiveEntry>, u64)> {
+        let mut header = [0u8; 20];
+        reader.read_exact(&mut header)?;
+        if header[0..8] != ARCHIVE_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not an asset archive"));
+        }
+        let version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        if version != ARCHIVE_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported asset archive version {}", version),
+            ));
+        }
+        let index_len = u64::from_le_bytes(header[12..20].try_into().unwrap());
+        let data_start = (header.len() as u64)
+            .checked_add(index_len)
+            .filter(|data_start| *data_start <= archive_len)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Asset archive index is truncated"))?;
+
+        let mut index = vec![0; index_len as usize];
+        reader.read_exact(&mut index)?;
+        let (entries, _): (Vec<ArchiveEntry>, _) = bincode::decode_from_slice(&index, config::standard())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+
+        let data_len = archive_len - data_start;
+        for entry in &entries {
+            if entry
+                .offset
+                .checked_add(entry.stored_size)
+                .is_none_or(|end| end > data_len)
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("File {} extends past the end of the asset archive", entry.name),
+                ));
+            }
+        }
+
+        Ok((entries, data_start))
+    }
+}
+
+// ---------------------------------------------------------- //
+// ---------------------- Asset source ---------------------- //
+// ---------------------------------------------------------- //
+
+/// Assets of the asset directory together with the asset archives and the asset directories of mods.
+///
+/// Files are addressed by their path in the asset directory, also when they are read from an archive or a mod.
+/// Archives take priority over the directory, and later archives over earlier ones, so that a patch archive
+/// can replace single files of the base archive. Mods take priority over both, later mods over earlier ones.
+pub(crate) struct AssetSource {
+    asset_path: PathBuf,
+    archives: Vec<AssetArchive>,
+    mod_dirs: Vec<PathBuf>,
+}
+
+/// Where a file of an [`AssetSource`] is read from.
+enum ResolvedFile<'a> {
+    Archive(&'a AssetArchive, String),
+    File(PathBuf),
+}
+
+impl AssetSource {
+    pub(crate) fn new(asset_path: PathBuf, archives: Vec<AssetArchive>, mod_dirs: Vec<PathBuf>) -> Self {
+        Self {
+            asset_path,
+            archives,
+            mod_dirs,
+        }
+    }
+
+    pub(crate) fn asset_path(&self) -> &Path {
+        &self.asset_path
+    }
+
+    pub(crate) fn has_archives(&self) -> bool {
+        !self.archives.is_empty()
+    }
+
+    /// Paths of the archived and mod files with one of the given extensions.
+    /// These replace the files of the same path in the asset directory.
+    pub(crate) fn override_files(&self, extensions: &[&str]) -> Vec<PathBuf> {
+        let mod_names = self.mod_dirs.iter().flat_map(|mod_dir| {
+            list_files(mod_dir, None)
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(move |(path, _)| entry_name(path.strip_prefix(mod_dir).ok()?))
+        });
+        let mut names: Vec<_> = self
+            .archives
+            .iter()
+            .flat_map(|archive| archive.names().map(str::to_owned))
+            .chain(mod_names)
+            .filter(|name| {
+                Path::new(name)
+                    .extension()
+                    .is_some_and(|ext| extensions.iter().any(|allowed| ext == *allowed))
+            })
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        names.into_iter().map(|name| self.asset_path.join(name)).collect()
+    }
+
+    pub(crate) fn load(&self, path: &Path) -> io::Result<Vec<u8>> {
+        match self.resolve(path) {
+            ResolvedFile::Archive(archive, name) => archive.read(&name),
+            ResolvedFile::File(path) => load_resource(path),
+        }
+    }
+
+    /// Reads at most `len` bytes from the start of the file.
+    pub(crate) fn load_prefix(&self, path: &Path, len: usize) -> io::Result<Vec<u8>> {
+        match self.resolve(path) {
+            ResolvedFile::Archive(archive, name) => archive.read_prefix(&name, len),
+            ResolvedFile::File(path) if cfg!(not(target_arch = "wasm32")) => {
+                let mut bytes = Vec::with_capacity(len);
+                File::open(path)?.take(len as u64).read_to_end(&mut bytes)?;
+                Ok(bytes)
+            }
+            ResolvedFile::File(path) => {
+                let mut bytes = load_resource(path)?;
+                bytes.truncate(len);
+                Ok(bytes)
+            }
+        }
+    }
+
+    /// The last mod that has the file, or the archive with the highest priority that has it, or the asset directory.
+    fn resolve(&self, path: &Path) -> ResolvedFile<'_> {
+        if let Ok(relative_path) = path.strip_prefix(&self.asset_path) {
+            let mod_file = self
+                .mod_dirs
+                .iter()
+                .rev()
+                .map(|mod_dir| mod_dir.join(relative_path))
+                .find(|mod_file| mod_file.is_file());
+            if let Some(mod_file) = mod_file {
+                return ResolvedFile::File(mod_file);
+            }
+
+            if let Some(name) = entry_name(relative_path)
+                && let Some(archive) = self.archives.iter().rev().find(|archive| archive.contains(&name))
+            {
+                return ResolvedFile::Archive(archive, name);
+            }
+        }
+        ResolvedFile::File(path.to_path_buf())
+    }
+}
+
+/// Name of a file in an archive from its relative path.
+fn entry_name(relative_path: &Path) -> Option<String> {
+    let parts = relative_path
+        .components()
+        .map(|component| component.as_os_str().to_str())
+        .collect::<Option<Vec<_>>>()?;
+    Some(parts.join("/"))
+}
+
+// ---------------------------------------------------------- //
+// ------------------------- Tests -------------------------- //
+// ---------------------------------------------------------- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::file_helpers::TmpFolder;
+
+    #[test]
+    fn packed_files_can_be_read_back() {
+        let folder = TmpFolder::create("target/tmp/asset_archive_pack_test");
+        let assets = folder.path().join("assets");
+        std::fs::create_dir_all(assets.join("sub")).unwrap();
+        let repetitive = vec![7u8; 1000];
+        let random: Vec<u8> = (0..1000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        std::fs::write(assets.join("repetitive.bin"), &repetitive).unwrap();
+        std::fs::write(assets.join("sub").join("random.bin"), &random).unwrap();
+
+        let archive_path = folder.path().join("assets.pak");
+        assert_eq!(AssetArchive::pack(&assets, &archive_path).unwrap(), 2);
+        assert!(std::fs::metadata(&archive_path).unwrap().len() < 2000);
+
+        let archive = AssetArchive::open(&archive_path).unwrap();
+        let mut names: Vec<_> = archive.names().collect();
+        names.sort();
+        assert_eq!(names, vec!["repetitive.bin", "sub/random.bin"]);
+        assert_eq!(archive.read("repetitive.bin").unwrap(), repetitive);
+        assert_eq!(archive.read("sub/random.bin").unwrap(), random);
+        assert_eq!(archive.read_prefix("repetitive.bin", 10).unwrap(), &repetitive[..10]);
+        assert_eq!(archive.read_prefix("sub/random.bin", 10).unwrap(), &random[..10]);
+        assert_eq!(archive.read("missing.bin").unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        std::fs::write(&archive_path, b"not an archive at all").unwrap();
+        assert_eq!(
+            AssetArchive::open(&archive_path).err().unwrap().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn archives_with_out_of_bounds_contents_are_rejected() {
+        let folder = TmpFolder::create("target/tmp/asset_archive_bounds_test");
+        let archive_path = folder.path().join("assets.pak");
+        let write_archive = |index_len: Option<u64>, entries: &[ArchiveEntry], contents: &[u8]| {
+            let index = bincode::encode_to_vec(entries, config::standard()).unwrap();
+            let index_len = index_len.unwrap_or(index.len() as u64);
+            let mut bytes = ARCHIVE_MAGIC.to_vec();
+            bytes.extend_from_slice(&ARCHIVE_VERSION.to_le_bytes());
+            bytes.extend_from_slice(&index_len.to_le_bytes());
+            bytes.extend_from_slice(&index);
+            bytes.extend_from_slice(contents);
+            std::fs::write(&archive_path, bytes).unwrap();
+        };
+        let entry = |offset: u64, stored_size: u64| ArchiveEntry {
+            name: "a.bin".to_owned(),
+            offset,
+            stored_size,
+            compressed: false,
+        };
+        let open_error = || AssetArchive::open(&archive_path).err().unwrap().kind();
+
+        write_archive(None, &[entry(0, 4)], b"abcd");
+        assert_eq!(
+            AssetArchive::open(&archive_path).unwrap().read("a.bin").unwrap(),
+            b"abcd"
+        );
+
+        write_archive(Some(u64::MAX - 4), &[entry(0, 4)], b"abcd");
+        assert_eq!(open_error(), io::ErrorKind::InvalidData);
+        write_archive(Some(1000), &[entry(0, 4)], b"abcd");
+        assert_eq!(open_error(), io::ErrorKind::InvalidData);
+        write_archive(None, &[entry(2, 4)], b"abcd");
+        assert_eq!(open_error(), io::ErrorKind::InvalidData);
+        write_archive(None, &[entry(u64::MAX, 4)], b"abcd");
+        assert_eq!(open_error(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn later_archives_take_priority_over_earlier_ones_and_the_directory() {
+        let folder = TmpFolder::create("target/tmp/asset_source_priority_test");
+        let pack = |name: &str, files: &[(&str, &str)]| {
+            let dir = folder.path().join(name);
+            std::fs::create_dir_all(&dir).unwrap();
+            for (file, content) in files {
+                std::fs::write(dir.join(file), content).unwrap();
+            }
+            let archive_path = folder.path().join(format!("{}.pak", name));
+            AssetArchive::pack(&dir, &archive_path).unwrap();
+            AssetArchive::open(&archive_path).unwrap()
+        };
+        let base = pack("base", &[("a.png", "base a"), ("b.png", "base b")]);
+        let patch = pack("patch", &[("b.png", "patch b"), ("c.txt", "patch c")]);
+
+        let asset_path = folder.path().join("loose");
+        std::fs::create_dir_all(&asset_path).unwrap();
+        std::fs::write(asset_path.join("a.png"), "loose a").unwrap();
+        std::fs::write(asset_path.join("d.png"), "loose d").unwrap();
+        let source = AssetSource::new(asset_path.clone(), vec![base, patch], Vec::new());
+
+        assert_eq!(
+            source.override_files(&["png"]),
+            vec![asset_path.join("a.png"), asset_path.join("b.png")]
+        );
+        assert_eq!(source.load(&asset_path.join("a.png")).unwrap(), b"base a");
+        assert_eq!(source.load(&asset_path.join("b.png")).unwrap(), b"patch b");
+        assert_eq!(source.load(&asset_path.join("d.png")).unwrap(), b"loose d");
+        assert_eq!(source.load_prefix(&asset_path.join("d.png"), 5).unwrap(), b"loose");
+        assert!(source.load(&asset_path.join("e.png")).is_err());
+    }
+
+    #[test]
+    fn mods_take_priority_over_archives() {
+        let folder = TmpFolder::create("target/tmp/asset_source_mods_test");
+        let base_dir = folder.path().join("base");
+        std::fs::create_dir_all(&base_dir).unwrap();
+        std::fs::write(base_dir.join("a.png"), "base a").unwrap();
+        std::fs::write(base_dir.join("b.png"), "base b").unwrap();
+        AssetArchive::pack(&base_dir, folder.path().join("base.pak")).unwrap();
+        let base = AssetArchive::open(folder.path().join("base.pak")).unwrap();
+
+        let mod_1 = folder.path().join("mod_1");
+        let mod_2 = folder.path().join("mod_2");
+        std::fs::create_dir_all(mod_1.join("sub")).unwrap();
+        std::fs::create_dir_all(&mod_2).unwrap();
+        std::fs::write(mod_1.join("a.png"), "mod 1 a").unwrap();
+        std::fs::write(mod_1.join("sub").join("c.png"), "mod 1 c").unwrap();
+        std::fs::write(mod_2.join("a.png"), "mod 2 a").unwrap();
+
+        let asset_path = folder.path().join("loose");
+        let source = AssetSource::new(asset_path.clone(), vec![base], vec![mod_1, mod_2]);
+
+        assert_eq!(
+            source.override_files(&["png"]),
+            vec![asset_path.join("a.png"), asset_path.join("b.png"), asset_path.join("sub").join("c.png")]
+        );
+        assert_eq!(source.load(&asset_path.join("a.png")).unwrap(), b"mod 2 a");
+        assert_eq!(source.load(&asset_path.join("b.png")).unwrap(), b"base b");
+        assert_eq!(
+            source.load_prefix(&asset_path.join("sub").join("c.png"), 5).unwrap(),
+            b"mod 1"
+        );
+    }
+}
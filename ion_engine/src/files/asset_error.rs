@@ -0,0 +1,66 @@
+use std::{fmt, path::PathBuf, sync::mpsc::Sender};
+
+use ion_common::log_error;
+
+// ---------------------------------------------------------- //
+// ----------------------- Asset error ---------------------- //
+// ---------------------------------------------------------- //
+
+/// Problem with an asset that was skipped while loading or inserting it.
+///
+/// Asset errors don't stop the engine: the offending asset is left out and sprites using it are rendered
+/// as missing. Errors are logged and delivered to the game through `RenderFrameProps::asset_errors`
+/// as they occur, so that they can be shown as in-game warnings during development.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssetError {
+    /// File or directory could not be read.
+    MissingFile { path: PathBuf, reason: String },
+
+    /// Texture used by a gfx bundle was not found in the loaded assets.
+    MissingTexture { name: String },
+
+    /// File was read but its contents could not be decoded.
+    DecodeFailed { path: PathBuf, reason: String },
+
+    /// File name or contents use a format that is not supported, for example an unknown layout tag.
+    UnsupportedFormat { path: PathBuf, reason: String },
+
+    /// Texture does not fit to a texture sheet or to the dynamic atlas.
+    AtlasOverflow { name: String, dimensions: (u32, u32) },
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssetError::MissingFile { path, reason } => write!(f, "Missing file {:?}: {}", path, reason),
+            AssetError::MissingTexture { name } => write!(f, "Missing texture {}", name),
+            AssetError::DecodeFailed { path, reason } => write!(f, "Failed to decode {:?}: {}", path, reason),
+            AssetError::UnsupportedFormat { path, reason } => write!(f, "Unsupported format in {:?}: {}", path, reason),
+            AssetError::AtlasOverflow { name, dimensions } => write!(
+                f,
+                "Texture {} ({}x{}) does not fit to the atlas",
+                name, dimensions.0, dimensions.1
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AssetError {}
+
+/// Sending end of the asset error stream. Cheap to clone, so it can be handed to loader threads.
+#[derive(Debug, Clone)]
+pub(crate) struct AssetDiagnostics {
+    sender: Sender<AssetError>,
+}
+
+impl AssetDiagnostics {
+    pub fn new(sender: Sender<AssetError>) -> Self {
+        Self { sender }
+    }
+
+    /// Logs the error and delivers it to the game.
+    pub fn report(&self, error: AssetError) {
+        log_error!("Asset error: {}", error);
+        self.sender.send(error).ok();
+    }
+}
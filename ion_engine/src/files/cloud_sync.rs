@@ -0,0 +1,469 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::{fs, io};
+
+use bincode::{Decode, Encode, config};
+use blake2::{Blake2s256, Digest};
+use ion_common::net::tcp_network_socket::TcpNetworkSocket;
+use ion_common::net::tls::TlsClientConfig;
+use ion_common::net::{HttpMethod, HttpRequest, HttpResponse};
+use ion_common::{DateTime, Map, log_info, log_warn};
+
+use crate::core::application::ApplicationEvent;
+use crate::files::storage::is_plain_file_name;
+use crate::files::{FileTask, Files, file_paths};
+
+// Hashes of the saves as of their last sync, in the saves directory whatever the storage backend
+const SYNC_STATE_FILE: &str = ".sync_state";
+
+const SAVE_HASH_HEADER: &str = "X-Save-Hash";
+const SAVE_MODIFIED_HEADER: &str = "X-Save-Modified";
+
+/// HTTPS endpoint that keeps the saves of the player, see [`Files::set_cloud_sync`].
+///
+/// The endpoint stores saves by name under `path`, and answers:
+/// - `GET {path}`: one line per save, `{name}\t{hash}\t{modified}`, with the modification time in unix milliseconds
+/// - `GET {path}/{name}`: the save as uploaded, or 404 if there is no such save
+/// - `POST {path}/{name}`: stores the save of the body, with the hash and modification time in the `X-Save-Hash`
+///   and `X-Save-Modified` headers
+/// - `DELETE {path}/{name}`: deletes the save
+///
+/// Save names are percent-encoded, and must be valid file names once decoded. The body of a save is opaque to the
+/// endpoint. Requests are always sent over TLS, as they carry the saves and the headers, such as authorization.
+///
+/// ```no_run
+/// # use ion_engine::files::cloud_sync::CloudSyncEndpoint;
+/// let endpoint = CloudSyncEndpoint {
+///     addr: "203.0.113.1:443".parse().unwrap(),
+///     server_name: "saves.example.com".to_owned(),
+///     root_cert_file: None,
+///     path: "/saves/player_1".to_owned(),
+///     headers: Default::default(),
+/// };
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct CloudSyncEndpoint {
+    pub addr: SocketAddr,
+    /// Host name of the endpoint, which its certificate has to be for.
+    pub server_name: String,
+    /// PEM file of certificates that are trusted besides the public certificate authorities, such as the
+    /// self-signed certificate of a private endpoint.
+    pub root_cert_file: Option<PathBuf>,
+    /// Path of the saves on the server, such as `/saves/player_1`.
+    pub path: String,
+    /// Sent with every request, for example for authorization.
+    pub headers: HashMap<String, String>,
+}
+
+/// Result of syncing a save, see [`Files::sync_saves`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum SaveSyncStatus {
+    /// The local and the cloud save are the same.
+    UpToDate,
+    /// The local save was uploaded, replacing the cloud save if there was one.
+    Uploaded,
+    /// The cloud save was downloaded, replacing the local save if there was one.
+    Downloaded,
+    /// Both saves have changed since the last sync. Neither is replaced until the conflict is resolved with
+    /// [`Files::resolve_save_conflict`].
+    Conflict {
+        local_modified: Option<DateTime>,
+        remote_modified: Option<DateTime>,
+    },
+    Failed {
+        reason: String,
+    },
+}
+
+/// Which save to keep when resolving a conflict, see [`Files::resolve_save_conflict`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncSide {
+    Local,
+    Remote,
+}
+
+/// Save as stored by the endpoint. The files keep their save headers, so that they are written back as they were.
+#[derive(Encode, Decode)]
+struct SaveBundle {
+    save_info: Vec<u8>,
+    files: Vec<(String, Vec<u8>)>,
+}
+
+struct RemoteSave {
+    hash: String,
+    modified: Option<DateTime>,
+}
+
+impl Files {
+    /// Sets the endpoint to sync saves with, or disables syncing with `None`.
+    /// Saves are only synced when [`Files::sync_saves`] is called, so games usually sync at startup and after saving.
+    pub fn set_cloud_sync(&self, endpoint: Option<CloudSyncEndpoint>) {
+        *self.cloud_sync.lock().unwrap() = endpoint;
+    }
+
+    /// Syncs every local and cloud save with the endpoint of [`Files::set_cloud_sync`].
+    ///
+    /// Saves are compared by a hash of their files. A save that only changed on one side since the last sync is
+    /// copied to the other side, and a save that changed on both sides is left as a conflict. Saves deleted locally
+    /// are downloaded again, use [`Files::delete_cloud_save`] to delete them from the endpoint too.
+    ///
+    /// The status of each save is returned and reported with
+    /// [`ApplicationEvent::SaveSynced`](crate::core::application::ApplicationEvent::SaveSynced). Saves that fail to
+    /// sync don't stop the others. Not supported on wasm.
+    pub fn sync_saves(&self) -> io::Result<Vec<(String, SaveSyncStatus)>> {
+        let endpoint = self.cloud_sync_endpoint()?;
+        log_info!("Syncing saves with {}{}", endpoint.addr, endpoint.path);
+
+        let remote_saves = self.list_cloud_saves(&endpoint)?;
+        let mut save_names: Vec<String> = self.storage.list_saves()?;
+        save_names.extend(remote_saves.keys().cloned());
+        save_names.sort();
+        save_names.dedup();
+
+        let mut sync_state = self.read_sync_state();
+        let mut statuses = Vec::with_capacity(save_names.len());
+        for save_name in save_names {
+            let status = self
+                .sync_save(&endpoint, &save_name, remote_saves.get(&save_name), &mut sync_state)
+                .unwrap_or_else(|err| SaveSyncStatus::Failed {
+                    reason: err.to_string(),
+                });
+            self.report_save_synced(&save_name, &status);
+            statuses.push((save_name, status));
+        }
+        self.write_sync_state(&sync_state)?;
+        Ok(statuses)
+    }
+
+    /// Same as [`Files::sync_saves`], but syncs in the background after the other background operations.
+    pub fn sync_saves_async(&self) -> FileTask<Vec<(String, SaveSyncStatus)>> {
+        self.run_in_background(|this| this.sync_saves())
+    }
+
+    /// Resolves a [`SaveSyncStatus::Conflict`] by replacing the save of the other side with the save of `keep`.
+    pub fn resolve_save_conflict(&self, save_name: &str, keep: SyncSide) -> io::Result<SaveSyncStatus> {
+        let endpoint = self.cloud_sync_endpoint()?;
+        log_info!(
+            "Resolving sync conflict of save '{}' by keeping the {:?} save",
+            save_name,
+            keep
+        );
+
+        let mut sync_state = self.read_sync_state();
+        let hash = match keep {
+            SyncSide::Local => self.upload_save(&endpoint, save_name)?,
+            SyncSide::Remote => self.download_save(&endpoint, save_name)?,
+        };
+        sync_state.insert(save_name.to_owned(), hash);
+        self.write_sync_state(&sync_state)?;
+
+        let status = match keep {
+            SyncSide::Local => SaveSyncStatus::Uploaded,
+            SyncSide::Remote => SaveSyncStatus::Downloaded,
+        };
+        self.report_save_synced(save_name, &status);
+        Ok(status)
+    }
+
+    /// Deletes a save from the endpoint of [`Files::set_cloud_sync`]. The local save is kept.
+    pub fn delete_cloud_save(&self, save_name: &str) -> io::Result<()> {
+        let endpoint = self.cloud_sync_endpoint()?;
+        log_warn!("Deleting cloud save '{}'", save_name);
+
+        send_sync_request(
+            &endpoint,
+            HttpMethod::DELETE,
+            Some(save_name),
+            Vec::new(),
+            HashMap::new(),
+        )?;
+        let mut sync_state = self.read_sync_state();
+        sync_state.remove(save_name);
+        self.write_sync_state(&sync_state)
+    }
+
+    /// Only called from [`crate::run`], as only the render loop takes application events.
+    pub(crate) fn set_app_event_sender(&self, app_event_sender: std::sync::mpsc::Sender<ApplicationEvent>) {
+        *self.app_event_sender.lock().unwrap() = Some(app_event_sender);
+    }
+
+    fn sync_save(
+        &self,
+        endpoint: &CloudSyncEndpoint,
+        save_name: &str,
+        remote: Option<&RemoteSave>,
+        sync_state: &mut Map<String, String>,
+    ) -> io::Result<SaveSyncStatus> {
+        let local_hash = self.local_save_hash(save_name)?;
+        let synced_hash = sync_state.get(save_name);
+
+        let (status, hash) = match (local_hash, remote) {
+            (Some(local_hash), Some(remote)) if local_hash == remote.hash => (SaveSyncStatus::UpToDate, local_hash),
+            (Some(_), None) => (SaveSyncStatus::Uploaded, self.upload_save(endpoint, save_name)?),
+            (None, Some(_)) => (SaveSyncStatus::Downloaded, self.download_save(endpoint, save_name)?),
+            (Some(_), Some(remote)) if synced_hash == Some(&remote.hash) => {
+                (SaveSyncStatus::Uploaded, self.upload_save(endpoint, save_name)?)
+            }
+            (Some(local_hash), Some(_)) if synced_hash == Some(&local_hash) => {
+                (SaveSyncStatus::Downloaded, self.download_save(endpoint, save_name)?)
+            }
+            (Some(_), Some(remote)) => {
+                let local_modified = self.read_save_info(save_name).ok().and_then(|info| info.modified);
+                let status = SaveSyncStatus::Conflict {
+                    local_modified,
+                    remote_modified: remote.modified,
+                };
+                return Ok(status);
+            }
+            (None, None) => unreachable!("Synced saves exist locally or in the cloud"),
+        };
+        sync_state.insert(save_name.to_owned(), hash);
+        Ok(status)
+    }
+
+    /// Uploads the local save, returning its hash.
+    fn upload_save(&self, endpoint: &CloudSyncEndpoint, save_name: &str) -> io::Result<String> {
+        log_info!("Uploading save '{}'", save_name);
+        let files = self.storage.read_save(save_name)?;
+        let hash = hash_save_files(&files);
+        let save_info = self.read_save_info(save_name)?;
+        let modified = save_info
+            .modified
+            .map(|modified| modified.as_unix_timestamp_ms())
+            .unwrap_or(0);
+
+        let bundle = SaveBundle {
+            save_info: bincode::encode_to_vec(&save_info, config::standard()).unwrap(),
+            files: sorted_files(files),
+        };
+        let body = bincode::encode_to_vec(&bundle, config::standard()).unwrap();
+        let headers = HashMap::from([
+            (SAVE_HASH_HEADER.to_owned(), hash.clone()),
+            (SAVE_MODIFIED_HEADER.to_owned(), modified.to_string()),
+        ]);
+        send_sync_request(endpoint, HttpMethod::POST, Some(save_name), body, headers)?;
+        Ok(hash)
+    }
+
+    /// Replaces the local save with the cloud save, returning its hash.
+    fn download_save(&self, endpoint: &CloudSyncEndpoint, save_name: &str) -> io::Result<String> {
+        log_info!("Downloading save '{}'", save_name);
+        let response = send_sync_request(endpoint, HttpMethod::GET, Some(save_name), Vec::new(), HashMap::new())?;
+        let (bundle, _) = bincode::decode_from_slice::<SaveBundle, _>(&response.body, config::standard())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid cloud save: {}", err)))?;
+
+        let files: Map<String, Vec<u8>> = bundle.files.into_iter().collect();
+        let hash = hash_save_files(&files);
+        self.storage
+            .write_save(save_name, sorted_files(files), bundle.save_info)?;
+        Ok(hash)
+    }
+
+    fn list_cloud_saves(&self, endpoint: &CloudSyncEndpoint) -> io::Result<Map<String, RemoteSave>> {
+        let response = send_sync_request(endpoint, HttpMethod::GET, None, Vec::new(), HashMap::new())?;
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid cloud save list");
+
+        let mut remote_saves = Map::default();
+        for line in String::from_utf8_lossy(&response.body).lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut fields = line.split('\t');
+            let (Some(name), Some(hash), Some(modified)) = (fields.next(), fields.next(), fields.next()) else {
+                return Err(invalid());
+            };
+            let modified: u64 = modified.trim().parse().map_err(|_| invalid())?;
+            let remote_save = RemoteSave {
+                hash: hash.to_owned(),
+                modified: (modified > 0).then(|| DateTime::from_unix_timestamp_ms(modified)),
+            };
+            remote_saves.insert(decode_save_name(name).ok_or_else(invalid)?, remote_save);
+        }
+        Ok(remote_saves)
+    }
+
+    fn local_save_hash(&self, save_name: &str) -> io::Result<Option<String>> {
+        match self.storage.read_save(save_name) {
+            Ok(files) => Ok(Some(hash_save_files(&files))),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn cloud_sync_endpoint(&self) -> io::Result<CloudSyncEndpoint> {
+        if cfg!(target_arch = "wasm32") {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Cloud sync is not supported on wasm",
+            ));
+        }
+        self.cloud_sync
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "No cloud sync endpoint set"))
+    }
+
+    fn report_save_synced(&self, save_name: &str, status: &SaveSyncStatus) {
+        match status {
+            SaveSyncStatus::Conflict { .. } => {
+                log_warn!("Save '{}' has changed both locally and in the cloud", save_name);
+            }
+            SaveSyncStatus::Failed { reason } => {
+                log_warn!("Syncing save '{}' failed: {}", save_name, reason);
+            }
+            _ => {
+                log_info!("Synced save '{}': {:?}", save_name, status);
+            }
+        }
+        if let Some(app_event_sender) = self.app_event_sender.lock().unwrap().as_ref() {
+            let _ = app_event_sender.send(ApplicationEvent::SaveSynced {
+                save_name: save_name.to_owned(),
+                status: status.clone(),
+            });
+        }
+    }
+
+    fn read_sync_state(&self) -> Map<String, String> {
+        fs::read(file_paths::save_dir(&self.app_name, None).join(SYNC_STATE_FILE))
+            .ok()
+            .and_then(|bytes| bincode::decode_from_slice::<Vec<(String, String)>, _>(&bytes, config::standard()).ok())
+            .map(|(sync_state, _)| sync_state.into_iter().collect())
+            .unwrap_or_default()
+    }
+
+    fn write_sync_state(&self, sync_state: &Map<String, String>) -> io::Result<()> {
+        let mut sync_state: Vec<_> = sync_state
+            .iter()
+            .map(|(name, hash)| (name.clone(), hash.clone()))
+            .collect();
+        sync_state.sort();
+        let bytes = bincode::encode_to_vec(&sync_state, config::standard()).unwrap();
+        fs::create_dir_all(file_paths::save_dir(&self.app_name, None))?;
+        fs::write(file_paths::save_dir(&self.app_name, None).join(SYNC_STATE_FILE), bytes)
+    }
+}
+
+fn send_sync_request(
+    endpoint: &CloudSyncEndpoint,
+    method: HttpMethod,
+    save_name: Option<&str>,
+    body: Vec<u8>,
+    headers: HashMap<String, String>,
+) -> io::Result<HttpResponse> {
+    let path = endpoint.path.trim_end_matches('/');
+    let url = match save_name {
+        Some(save_name) => format!("{}/{}", path, encode_save_name(save_name)),
+        None => path.to_owned(),
+    };
+    let request = HttpRequest {
+        url: url.clone(),
+        method: method.clone(),
+        headers: endpoint.headers.clone().into_iter().chain(headers).collect(),
+        body,
+    };
+
+    let tls = TlsClientConfig::new(endpoint.root_cert_file.as_deref())?;
+    let response = TcpNetworkSocket::with_tls(&endpoint.server_name, tls).send_http_request(endpoint.addr, request)?;
+    match response.status_code {
+        200..=299 => Ok(response),
+        404 => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} {} was not found", method, url),
+        )),
+        status_code => Err(io::Error::other(format!(
+            "Cloud sync endpoint answered {} to {} {}",
+            status_code, method, url
+        ))),
+    }
+}
+
+/// Hashes the names and contents of the files, so that the same save has the same hash on every device.
+fn hash_save_files(files: &Map<String, Vec<u8>>) -> String {
+    let mut hasher = Blake2s256::new();
+    for (file_name, file_content) in sorted_files(files.clone()) {
+        hasher.update((file_name.len() as u64).to_le_bytes());
+        hasher.update(file_name.as_bytes());
+        hasher.update((file_content.len() as u64).to_le_bytes());
+        hasher.update(&file_content);
+    }
+    hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn sorted_files(files: Map<String, Vec<u8>>) -> Vec<(String, Vec<u8>)> {
+    let mut files: Vec<_> = files.into_iter().collect();
+    files.sort_by(|(a, _), (b, _)| a.cmp(b));
+    files
+}
+
+fn encode_save_name(save_name: &str) -> String {
+    save_name
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn decode_save_name(encoded: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut encoded_bytes = encoded.bytes();
+    while let Some(byte) = encoded_bytes.next() {
+        if byte == b'%' {
+            let hex = [encoded_bytes.next()?, encoded_bytes.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes)
+        .ok()
+        .filter(|save_name| is_plain_file_name(save_name))
+}
+
+// ---------------------------------------------------------- //
+// ------------------------- Tests -------------------------- //
+// ---------------------------------------------------------- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_names_survive_percent_encoding() {
+        for save_name in ["autosave_0", "My save (2)", "tallennus ä ö", "100%"] {
+            let encoded = encode_save_name(save_name);
+            assert!(
+                encoded
+                    .bytes()
+                    .all(|byte| byte.is_ascii_alphanumeric() || b"-_.~%".contains(&byte))
+            );
+            assert_eq!(decode_save_name(&encoded).as_deref(), Some(save_name));
+        }
+        assert_eq!(decode_save_name("bad%2"), None);
+    }
+
+    #[test]
+    fn save_names_that_leave_the_save_directory_are_rejected() {
+        for encoded in ["..", "%2E%2E", "..%2F..%2Fconfig", "a%2Fb", "a%5Cb", "%2Fetc", "", "."] {
+            assert_eq!(decode_save_name(encoded), None, "{}", encoded);
+        }
+    }
+
+    #[test]
+    fn save_hash_depends_on_names_and_contents() {
+        let files = |entries: &[(&str, &[u8])]| -> Map<String, Vec<u8>> {
+            entries
+                .iter()
+                .map(|(name, content)| (name.to_string(), content.to_vec()))
+                .collect()
+        };
+        let hash = hash_save_files(&files(&[("a", b"1"), ("b", b"2")]));
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, hash_save_files(&files(&[("b", b"2"), ("a", b"1")])));
+        assert_ne!(hash, hash_save_files(&files(&[("a", b"1"), ("b", b"3")])));
+        assert_ne!(hash, hash_save_files(&files(&[("a1", b""), ("b", b"2")])));
+    }
+}
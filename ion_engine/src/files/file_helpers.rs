@@ -479,39 +479,44 @@ pub fn js_object_to_files_map(js_value: &JsValue) -> Result<Map<String, Vec<u8>>
     Ok(files)
 }
 
+/// A tmp folder for tests, that deletes itself when dropping the struct.
+/// Left over contents from an earlier run that didn't finish are deleted when creating it.
+#[cfg(test)]
+pub(crate) struct TmpFolder {
+    path: PathBuf,
+}
+
+#[cfg(test)]
+impl TmpFolder {
+    pub(crate) fn create(path: &str) -> Self {
+        let path = PathBuf::from(path);
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self { path }
+    }
+
+    pub(crate) fn path(&self) -> PathBuf {
+        self.path.clone()
+    }
+}
+
+#[cfg(test)]
+impl Drop for TmpFolder {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
 // ---------------------------------------------------------- //
 // ------------------------- Tests -------------------------- //
 // ---------------------------------------------------------- //
 
 #[cfg(test)]
 mod tests {
-    use crate::files::file_helpers::{list_dirs, list_files};
+    use crate::files::file_helpers::{TmpFolder, list_dirs, list_files};
     use std::ffi::OsStr;
     use std::path::PathBuf;
 
-    // A tmp folder that deletes itself when dropping the struct
-    struct TmpFolder {
-        path: PathBuf,
-    }
-
-    impl TmpFolder {
-        pub fn create(path: &str) -> Self {
-            let path = PathBuf::from(path);
-            std::fs::create_dir_all(path.clone()).unwrap();
-            Self { path }
-        }
-
-        pub fn path(&self) -> PathBuf {
-            self.path.clone()
-        }
-    }
-
-    impl Drop for TmpFolder {
-        fn drop(&mut self) {
-            std::fs::remove_dir_all(self.path.clone()).unwrap();
-        }
-    }
-
     #[test]
     fn listing_files_in_directory_works() {
         let folder = TmpFolder::create("target/tmp/file_list_test");
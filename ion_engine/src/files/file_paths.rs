@@ -47,6 +47,18 @@ pub fn cache_dir(app_name: &str) -> PathBuf {
     base.join(cache_path)
 }
 
+pub fn replay_dir(app_name: &str) -> PathBuf {
+    let base = game_data_dir(app_name);
+    let replay_path = PathBuf::from("replays/");
+    base.join(replay_path)
+}
+
+pub fn mods_dir(app_name: &str) -> PathBuf {
+    let base = game_data_dir(app_name);
+    let mods_path = PathBuf::from("mods/");
+    base.join(mods_path)
+}
+
 pub fn save_dir(app_name: &str, save_name: Option<&str>) -> PathBuf {
     let base = game_data_dir(app_name);
     let save_path = if let Some(save_name) = save_name {
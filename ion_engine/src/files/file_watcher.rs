@@ -0,0 +1,124 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::time::{Duration, SystemTime};
+
+use ion_common::{Map, log_info};
+
+use crate::core::application::ApplicationEvent;
+use crate::files::file_helpers::list_files;
+use crate::util::concurrency::spawn_thread;
+
+// ---------------------------------------------------------- //
+// ---------------------- File watcher ---------------------- //
+// ---------------------------------------------------------- //
+
+/// Finds the files changed in the watched directories since the last poll.
+///
+/// Directories are scanned instead of subscribing to the notifications of the platform, which works the same on every
+/// platform without extra dependencies, but takes time with very large directories.
+pub(crate) struct FileWatcher {
+    dirs: Vec<PathBuf>,
+    stamps: Map<PathBuf, FileStamp>,
+}
+
+/// Changes when a file is written, at least on file systems that keep modification times.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FileStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+impl FileWatcher {
+    pub(crate) fn new(dirs: Vec<PathBuf>) -> Self {
+        let stamps = Self::scan(&dirs);
+        Self { dirs, stamps }
+    }
+
+    /// Files created, modified or removed since the last poll, in path order.
+    pub(crate) fn poll(&mut self) -> Vec<PathBuf> {
+        let stamps = Self::scan(&self.dirs);
+        let mut changed: Vec<_> = stamps
+            .iter()
+            .filter(|(path, stamp)| self.stamps.get(*path) != Some(stamp))
+            .map(|(path, _)| path.clone())
+            .chain(self.stamps.keys().filter(|path| !stamps.contains_key(*path)).cloned())
+            .collect();
+        changed.sort();
+        self.stamps = stamps;
+        changed
+    }
+
+    /// Directories that don't exist are skipped, and picked up once they are created.
+    fn scan(dirs: &[PathBuf]) -> Map<PathBuf, FileStamp> {
+        dirs.iter()
+            .flat_map(|dir| list_files(dir, None).unwrap_or_default())
+            .map(|(path, metadata)| {
+                let stamp = FileStamp {
+                    modified: metadata.modified().ok(),
+                    len: metadata.len(),
+                };
+                (path, stamp)
+            })
+            .collect()
+    }
+}
+
+/// Polls the directories at the interval on a thread of its own, sending [`ApplicationEvent::FileChanged`] for each
+/// changed file. Stops with the engine.
+pub(crate) fn spawn_file_watcher(
+    dirs: Vec<PathBuf>,
+    interval: Duration,
+    app_event_sender: Sender<ApplicationEvent>,
+    engine_running: Arc<AtomicBool>,
+) {
+    log_info!("Watching for file changes in {:?}", dirs);
+    spawn_thread(Some("File watcher"), move || {
+        let mut watcher = FileWatcher::new(dirs);
+        while engine_running.load(Ordering::Relaxed) {
+            std::thread::sleep(interval);
+            for path in watcher.poll() {
+                log_info!("File changed: {:?}", path);
+                if app_event_sender.send(ApplicationEvent::FileChanged(path)).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+}
+
+// ---------------------------------------------------------- //
+// ------------------------- Tests -------------------------- //
+// ---------------------------------------------------------- //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn created_modified_and_removed_files_are_found() {
+        let dir = PathBuf::from("target/tmp/file_watcher_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("kept.txt"), "kept").unwrap();
+        std::fs::write(dir.join("modified.txt"), "old").unwrap();
+        std::fs::write(dir.join("removed.txt"), "removed").unwrap();
+
+        let mut watcher = FileWatcher::new(vec![dir.clone(), dir.join("missing")]);
+        assert!(watcher.poll().is_empty());
+
+        // Length changes even if the modification time doesn't, on file systems with coarse timestamps
+        std::fs::write(dir.join("modified.txt"), "modified").unwrap();
+        std::fs::write(dir.join("sub").join("created.txt"), "created").unwrap();
+        std::fs::remove_file(dir.join("removed.txt")).unwrap();
+
+        assert_eq!(
+            watcher.poll(),
+            vec![dir.join("modified.txt"), dir.join("removed.txt"), dir.join("sub").join("created.txt")]
+        );
+        assert!(watcher.poll().is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
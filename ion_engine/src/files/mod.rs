@@ -1,14 +1,34 @@
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex, mpsc};
 use std::{fs, io};
 
-use ion_common::{Map, log_info, log_warn};
-
-use crate::core::Constants;
-use crate::files::file_helpers::{list_dirs, list_files};
-use crate::util::config::{Config, ConfigParseError, config_from_string, config_to_string};
-
+use bincode::config;
+use ion_common::{DateTime, Map, log_info, log_warn};
+
+use crate::core::application::ApplicationEvent;
+use crate::core::{Constants, SaveConstants};
+use crate::files::cloud_sync::CloudSyncEndpoint;
+use crate::files::file_helpers::list_dirs;
+use crate::files::mods::{MOD_CONFIG_DIR, MOD_SETTINGS_CONFIG, ModInfo, ModSettings};
+use crate::files::save_info::{SaveInfo, SaveMetadata};
+use crate::files::storage::{BrowserStorage, NativeStorage, StorageBackend, config_file_in};
+use crate::util::config::{
+    Config, ConfigFormat, ConfigParseError, UnknownConfigFields, config_from_string_as,
+    config_from_string_keeping_unknown, config_to_string_keeping_unknown,
+};
+
+pub mod asset_archive;
+pub mod asset_error;
+pub mod cloud_sync;
 pub mod file_helpers;
 pub mod file_paths;
+pub(crate) mod file_watcher;
+pub mod mods;
+pub mod save_info;
+pub mod storage;
+
+// Starts every save file, followed by the save version. Files without it are from before save versioning.
+const SAVE_HEADER_MAGIC: &[u8; 8] = b"IONSAVE\0";
 
 /// Cross-platform file system abstraction for the game engine.
 ///
@@ -16,25 +36,85 @@ pub mod file_paths;
 ///
 /// ## Platform Differences
 ///
-/// - **Native platforms**: Uses the actual file system with platform-specific directories
-/// - **WASM/Browser**: Uses browser local storage for configs and IndexedDB for save games
+/// Configs, saves and replays are kept by a [`StorageBackend`], see [`Constants::storage`]. By default:
+/// - **Native platforms**: Uses the actual file system with platform-specific directories, see [`NativeStorage`]
+/// - **WASM/Browser**: Uses browser local storage for configs and IndexedDB for save games, see [`BrowserStorage`]
+///
+/// Logs and mods are always in the game data directory on native platforms.
+///
+/// ## Background IO
+///
+/// Saves and replays can be written in the background with the `_async` methods, so that big saves don't stall the
+/// calling thread. They return a [`FileTask`] to poll for the result. Background operations run one at a time in the
+/// order they were started, so a save that is imported after being exported has the exported contents. Other methods
+/// don't wait for them.
+///
+/// ## Mods
+///
+/// Mods are folders in the mods directory of the game data, see [`Files::list_mods`]. Enabled mods are looked up
+/// before the base game: configs in their [`MOD_CONFIG_DIR`](mods::MOD_CONFIG_DIR) replace the configs of the
+/// config directory, and textures in their [`MOD_TEXTURE_DIR`](mods::MOD_TEXTURE_DIR) are loaded by
+/// [`TextureAssets::include_mods`](crate::gfx::textures::texture_assets::TextureAssets::include_mods).
+/// Mods are not supported on wasm.
+///
+/// ## Cloud saves
+///
+/// Saves can be synced with an HTTP endpoint of the game, see [`Files::set_cloud_sync`] and [`Files::sync_saves`].
+/// Cloud saves are not supported on wasm.
+#[derive(Clone)]
 pub struct Files {
     app_name: String,
+    saves: Arc<SaveConstants>,
+    config_format: ConfigFormat,
+    storage: Arc<dyn StorageBackend>,
+    /// Fields of the imported configs that the config types don't have, written back when the configs are exported.
+    unknown_config_fields: Arc<Mutex<Map<String, UnknownConfigFields>>>,
+    cloud_sync: Arc<Mutex<Option<CloudSyncEndpoint>>>,
+    app_event_sender: Arc<Mutex<Option<mpsc::Sender<ApplicationEvent>>>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    io_jobs: mpsc::Sender<IoJob>,
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+type IoJob = Box<dyn FnOnce() + Send>;
+
 impl Files {
     pub fn new(constants: &Constants) -> Self {
         if cfg!(not(target_arch = "wasm32")) {
-            fs::create_dir_all(file_paths::save_dir(constants.app_name, None))
-                .unwrap_or_else(|_| panic!("Dir create error {:?}", file_paths::save_dir(constants.app_name, None)));
-            fs::create_dir_all(file_paths::config_dir(constants.app_name))
-                .unwrap_or_else(|_| panic!("Dir create error {:?}", file_paths::config_dir(constants.app_name)));
             fs::create_dir_all(file_paths::log_dir(constants.app_name))
                 .unwrap_or_else(|_| panic!("Dir create error {:?}", file_paths::log_dir(constants.app_name)));
+            fs::create_dir_all(file_paths::mods_dir(constants.app_name))
+                .unwrap_or_else(|_| panic!("Dir create error {:?}", file_paths::mods_dir(constants.app_name)));
         }
 
+        let storage: Arc<dyn StorageBackend> = match &constants.storage {
+            Some(storage) => storage.clone(),
+            None if cfg!(target_arch = "wasm32") => Arc::new(BrowserStorage::new(constants.app_name)),
+            None => Arc::new(NativeStorage::new(constants.app_name)),
+        };
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let io_jobs = {
+            let (io_jobs, io_job_receiver) = mpsc::channel::<IoJob>();
+            // Stops once every clone of the files is dropped
+            crate::util::concurrency::spawn_thread(Some("File IO"), move || {
+                for job in io_job_receiver {
+                    job();
+                }
+            });
+            io_jobs
+        };
+
         Self {
             app_name: constants.app_name.to_string(),
+            saves: Arc::new(constants.saves.clone()),
+            config_format: constants.config_format,
+            storage,
+            unknown_config_fields: Arc::new(Mutex::new(Map::default())),
+            cloud_sync: Arc::new(Mutex::new(None)),
+            app_event_sender: Arc::new(Mutex::new(None)),
+            #[cfg(not(target_arch = "wasm32"))]
+            io_jobs,
         }
     }
 
@@ -42,208 +122,454 @@ impl Files {
     /// ⚠️ **WARNING**: This will delete absolutely everything.
     pub fn delete_all_data(&self) -> Result<(), io::Error> {
         log_warn!("Deleting all application data");
-
-        #[cfg(target_arch = "wasm32")]
-        {
-            // Delete all localStorage data
-            for key in file_helpers::list_local_storage()? {
-                file_helpers::delete_local_storage(&key)?;
-            }
-            // Delete all IndexedDB saves
-            file_helpers::clear_store_indexeddb(&self.app_name, "saves")?;
-            Ok(())
-        }
-        #[cfg(not(target_arch = "wasm32"))]
-        {
-            fs::remove_dir_all(file_paths::game_data_dir(&self.app_name))
-        }
+        self.storage.delete_all()
     }
 
-    /// Imports a configuration file from storage.
-    pub fn import_config<T: Config>(&self, config_name: &str) -> Result<T, ConfigParseError> {
+    /// Imports a configuration file from storage, in whichever format it was exported in.
+    /// If the config exists in several formats, the one of [`Constants::config_format`] is preferred.
+    ///
+    /// If an enabled mod has the config, the config of the last such mod is imported instead. Exporting the config
+    /// still writes it to the config directory, so mods should only provide configs that players don't edit.
+    ///
+    /// Fields missing from the config are taken from the default config. Fields the config doesn't have are kept, and
+    /// written back when the config is exported, so that configs of other versions of the game survive.
+    pub fn import_config<T: Config + Default>(&self, config_name: &str) -> Result<T, ConfigParseError> {
         log_info!("Importing config '{}'", config_name);
-        let encoded = if cfg!(target_arch = "wasm32") {
-            let storage_key = format!("{}_{}_config", self.app_name, config_name);
-            file_helpers::read_local_storage(&storage_key)
-                .map_err(|_| ConfigParseError::MissingData(format!("Missing config '{}'", config_name)))?
-        } else {
-            let config_dir = file_paths::config_dir(&self.app_name);
-            let config_file = config_dir.join(config_name.replace(".conf", "").to_string() + ".conf");
-            fs::read_to_string(config_file.clone())
-                .map_err(|_| ConfigParseError::MissingData(format!("Missing file '{:?}'", config_file)))?
+        let Some((encoded, format)) = self
+            .read_mod_config(config_name)
+            .or_else(|| self.read_config(config_name))
+        else {
+            return Err(ConfigParseError::MissingData(format!(
+                "Missing config '{}'",
+                config_name
+            )));
         };
 
-        config_from_string(&encoded)
+        let (config, unknown_fields) = config_from_string_keeping_unknown(&encoded, format)?;
+        if !unknown_fields.is_empty() {
+            log_info!(
+                "Config '{}' has fields unknown to this version: {:?}",
+                config_name,
+                unknown_fields.keys()
+            );
+        }
+        self.unknown_config_fields
+            .lock()
+            .unwrap()
+            .insert(config_name.to_owned(), unknown_fields);
+        Ok(config)
+    }
+
+    /// Reads a config from the config storage, in the preferred format if it exists in several.
+    fn read_config(&self, config_name: &str) -> Option<(String, ConfigFormat)> {
+        self.config_formats().find_map(|format| {
+            let encoded = self.storage.read_config(config_name, format).ok();
+            encoded.map(|encoded| (encoded, format))
+        })
+    }
+
+    /// Reads a config from the last enabled mod that has it.
+    fn read_mod_config(&self, config_name: &str) -> Option<(String, ConfigFormat)> {
+        self.enabled_mod_dirs().iter().rev().find_map(|mod_dir| {
+            self.config_formats().find_map(|format| {
+                let path = config_file_in(&mod_dir.join(MOD_CONFIG_DIR), config_name, format);
+                let encoded = fs::read_to_string(&path).ok()?;
+                log_info!("Config '{}' is provided by mod {:?}", config_name, mod_dir);
+                Some((encoded, format))
+            })
+        })
+    }
+
+    /// Config formats in the order they are looked for, the preferred one first.
+    fn config_formats(&self) -> impl Iterator<Item = ConfigFormat> {
+        let preferred = self.config_format;
+        std::iter::once(preferred).chain(ConfigFormat::ALL.into_iter().filter(move |format| *format != preferred))
     }
 
-    /// Exports a configuration file to storage.
+    /// Exports a configuration file to storage, in the format of [`Constants::config_format`].
     pub fn export_config(&self, config_name: &str, config: &dyn Config) -> io::Result<()> {
-        log_info!("Exporting config '{}'", config_name);
-        let encoded = config_to_string(config);
+        self.export_config_as(config_name, config, self.config_format)
+    }
 
-        if cfg!(target_arch = "wasm32") {
-            let storage_key = format!("{}_{}_config", self.app_name, config_name);
-            file_helpers::write_local_storage(&storage_key, &encoded)
-        } else {
-            let config_dir = file_paths::config_dir(&self.app_name);
-            let config_file = config_dir.join(config_name.replace(".conf", "").to_string() + ".conf");
-            fs::write(config_file, encoded)
+    /// Exports a configuration file to storage in the given format, replacing the config in any other format.
+    pub fn export_config_as(&self, config_name: &str, config: &dyn Config, format: ConfigFormat) -> io::Result<()> {
+        log_info!("Exporting config '{}' as {:?}", config_name, format);
+        let encoded = {
+            let unknown_config_fields = self.unknown_config_fields.lock().unwrap();
+            let unknown_fields = unknown_config_fields.get(config_name).cloned().unwrap_or_default();
+            config_to_string_keeping_unknown(config, &unknown_fields, format)
+        };
+
+        // Otherwise the config could be imported from an outdated copy in a preferred format
+        for other_format in ConfigFormat::ALL
+            .into_iter()
+            .filter(|other_format| *other_format != format)
+        {
+            match self.storage.delete_config(config_name, other_format) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                _ => {}
+            }
         }
+
+        self.storage.write_config(config_name, format, &encoded)
     }
 
-    /// Deletes a specific configuration from storage.
+    /// Deletes a specific configuration from storage, in every format.
     pub fn delete_config(&self, config_name: &str) -> Result<(), io::Error> {
         log_warn!("Deleting config '{}'", config_name);
-        if cfg!(target_arch = "wasm32") {
-            let storage_key = format!("{}_{}_config", self.app_name, config_name);
-            file_helpers::delete_local_storage(&storage_key)
-        } else {
-            let config_dir = file_paths::config_dir(&self.app_name);
-            let config_file = config_dir.join(config_name.replace(".conf", "").to_string() + ".conf");
-            fs::remove_file(config_file)
+        let results: Vec<_> = ConfigFormat::ALL
+            .into_iter()
+            .map(|format| self.storage.delete_config(config_name, format))
+            .collect();
+        if results.iter().any(Result::is_ok) {
+            return Ok(());
         }
+        results.into_iter().find(|result| result.is_err()).unwrap()
     }
 
     /// Deletes all configuration files from storage.
     /// ⚠️ **WARNING**: This will delete ALL configuration data!
     pub fn delete_all_configs(&self) -> Result<(), io::Error> {
         log_warn!("Deleting all configs");
-        if cfg!(target_arch = "wasm32") {
-            for key in file_helpers::list_local_storage()? {
-                if key.ends_with("_config") {
-                    file_helpers::delete_local_storage(&key)?;
-                }
-            }
-            Ok(())
-        } else {
-            fs::remove_dir_all(file_paths::config_dir(&self.app_name))
-        }
+        self.storage.delete_all_configs()
     }
 
-    /// Exports save game data to storage.
-    /// - **Native platforms**: Uses the file system with backup and restore functionality
-    /// - **WASM/Browser**: Uses IndexedDB for persistent storage
-    pub fn export_save(&self, name: &str, files: Vec<(String, Vec<u8>)>) -> Result<(), io::Error> {
+    /// Exports save game data to storage, tagged with the save version of [`SaveConstants`].
+    /// The metadata is stored alongside the save, see [`Files::list_saves`].
+    /// If writing the save fails, the previous save of the name is kept.
+    pub fn export_save(
+        &self,
+        name: &str,
+        files: Vec<(String, Vec<u8>)>,
+        metadata: SaveMetadata,
+    ) -> Result<(), io::Error> {
         log_info!("Exporting save '{}'", name);
+        let now = DateTime::now();
+        let save_info = SaveInfo {
+            name: name.to_owned(),
+            created: self
+                .read_save_info(name)
+                .ok()
+                .and_then(|info| info.created)
+                .or(Some(now)),
+            modified: Some(now),
+            size: files.iter().map(|(_, file_content)| file_content.len() as u64).sum(),
+            save_version: self.saves.version,
+            metadata,
+        };
+        let save_info = bincode::encode_to_vec(&save_info, config::standard()).unwrap();
+        let files = files
+            .into_iter()
+            .map(|(file_name, file_content)| (file_name, add_save_header(self.saves.version, file_content)))
+            .collect();
 
-        #[cfg(target_arch = "wasm32")]
-        {
-            // Convert Vec to Map for IndexedDB storage
-            let files_map: Map<String, Vec<u8>> = files.into_iter().collect();
-            let js_object = file_helpers::files_map_to_js_object(&files_map);
-            file_helpers::write_indexeddb(&self.app_name, "saves", name, &js_object)
-        }
-        #[cfg(not(target_arch = "wasm32"))]
-        {
-            let save_folder_path = file_paths::save_dir(&self.app_name, Some(name));
-            let save_folder_backup_path =
-                file_paths::save_dir(&self.app_name, Some(format!("{}_backup", name).as_str()));
-            let prev_save_exists = save_folder_path.is_dir();
-
-            // Backup previous save
-            if prev_save_exists {
-                fs::rename(&save_folder_path, &save_folder_backup_path)?;
-            }
+        self.storage.write_save(name, files, save_info)
+    }
 
-            fs::create_dir_all(&save_folder_path)?;
-            let save_result: Result<(), io::Error> = {
-                for (file_name, file_content) in files {
-                    let file_path = save_folder_path.clone().join(PathBuf::from(file_name));
-                    fs::write(save_folder_path.join(file_path), file_content)?;
-                }
-                Ok(())
-            };
+    /// Same as [`Files::export_save`], but writes the save in the background.
+    pub fn export_save_async(&self, name: &str, files: Vec<(String, Vec<u8>)>, metadata: SaveMetadata) -> FileTask<()> {
+        let name = name.to_owned();
+        self.run_in_background(move |this| this.export_save(&name, files, metadata))
+    }
 
-            if save_result.is_err() {
-                // Error while writing save, restore backup
-                fs::rename(&save_folder_backup_path, &save_folder_path)?;
-                save_result
-            } else {
-                if prev_save_exists {
-                    fs::remove_dir_all(save_folder_backup_path)?;
-                }
-                Ok(())
-            }
-        }
+    /// Same as [`Files::import_save`], but reads the save in the background.
+    pub fn import_save_async(&self, save_name: &str) -> FileTask<Map<String, Vec<u8>>> {
+        let save_name = save_name.to_owned();
+        self.run_in_background(move |this| this.import_save(&save_name))
     }
 
-    /// Imports save game data from storage.
-    /// - **Native platforms**: Reads from the file system
-    /// - **WASM/Browser**: Reads from IndexedDB
+    /// Imports save game data from storage. Saves of older versions are migrated to the current version with the
+    /// migrations of [`SaveConstants`], and saves of newer versions fail to import.
     pub fn import_save(&self, save_name: &str) -> Result<Map<String, Vec<u8>>, io::Error> {
         log_info!("Importing save '{}'", save_name);
+        let mut save_files = self.storage.read_save(save_name)?;
+        let version = strip_save_headers(&mut save_files)?;
+        let version = version.unwrap_or(self.saves.version);
+        if version != self.saves.version {
+            log_info!(
+                "Migrating save '{}' from version {} to {}",
+                save_name,
+                version,
+                self.saves.version
+            );
+        }
+        self.saves.migrate(version, &mut save_files)?;
+        Ok(save_files)
+    }
 
-        #[cfg(target_arch = "wasm32")]
-        {
-            let js_value = file_helpers::read_indexeddb(&self.app_name, "saves", save_name)?;
-            file_helpers::js_object_to_files_map(&js_value)
+    /// Deletes a save game from storage.
+    pub fn delete_save(&self, save_name: &str) -> Result<(), io::Error> {
+        log_warn!("Deleting save '{}'", save_name);
+        self.storage.delete_save(save_name)
+    }
+
+    /// Lists all available save games, with the info stored alongside them, so that the saves aren't read.
+    /// Saves written before save info was stored are read in full instead, until they are exported again.
+    pub fn list_saves(&self) -> Result<Vec<SaveInfo>, io::Error> {
+        self.storage
+            .list_saves()?
+            .iter()
+            .map(|save_name| self.read_save_info(save_name))
+            .collect()
+    }
+
+    fn read_save_info(&self, save_name: &str) -> Result<SaveInfo, io::Error> {
+        let save_info = self.storage.read_save_info(save_name).ok().and_then(|bytes| {
+            bincode::decode_from_slice::<SaveInfo, _>(&bytes, config::standard())
+                .ok()
+                .map(|(save_info, _)| save_info)
+        });
+        if let Some(save_info) = save_info {
+            // The save folder may have been renamed since
+            return Ok(SaveInfo {
+                name: save_name.to_owned(),
+                ..save_info
+            });
         }
-        #[cfg(not(target_arch = "wasm32"))]
-        {
-            let save_folder_path = file_paths::save_dir(&self.app_name, Some(save_name));
-            let mut save_files: Map<String, Vec<u8>> = Map::default();
-
-            for (file_path, _) in list_files(&save_folder_path, None)? {
-                let file_content = fs::read(&file_path)?;
-                let file_name = file_path
-                    .file_name()
-                    .unwrap()
-                    .to_os_string()
-                    .into_string()
-                    .map_err(|err| {
-                        io::Error::new(
-                            io::ErrorKind::InvalidInput,
-                            format!("Save file {:?} was not valid: {:?}", file_path, err),
-                        )
-                    })?;
-
-                save_files.insert(file_name, file_content);
+
+        // Saves from before save info was stored
+        let mut save_files = self.storage.read_save(save_name)?;
+        let save_version = strip_save_headers(&mut save_files)?.unwrap_or(self.saves.version);
+        let (created, modified) = self.storage.save_times(save_name)?;
+        Ok(SaveInfo {
+            name: save_name.to_owned(),
+            created,
+            modified,
+            size: save_files.values().map(|file_content| file_content.len() as u64).sum(),
+            save_version,
+            metadata: SaveMetadata::default(),
+        })
+    }
+
+    /// Exports a recorded replay to storage, replacing any replay with the same name.
+    pub fn export_replay(&self, replay_name: &str, replay: Vec<u8>) -> Result<(), io::Error> {
+        log_info!("Exporting replay '{}'", replay_name);
+        self.storage.write_replay(replay_name, replay)
+    }
+
+    /// Same as [`Files::export_replay`], but writes the replay in the background.
+    pub fn export_replay_async(&self, replay_name: &str, replay: Vec<u8>) -> FileTask<()> {
+        let replay_name = replay_name.to_owned();
+        self.run_in_background(move |this| this.export_replay(&replay_name, replay))
+    }
+
+    /// Imports a recorded replay from storage.
+    pub fn import_replay(&self, replay_name: &str) -> Result<Vec<u8>, io::Error> {
+        log_info!("Importing replay '{}'", replay_name);
+        self.storage.read_replay(replay_name)
+    }
+
+    pub fn delete_replay(&self, replay_name: &str) -> Result<(), io::Error> {
+        log_warn!("Deleting replay '{}'", replay_name);
+        self.storage.delete_replay(replay_name)
+    }
+
+    /// Lists all recorded replays.
+    pub fn list_replays(&self) -> Result<Vec<String>, io::Error> {
+        self.storage.list_replays()
+    }
+
+    /// Lists the mods in load order. Mods found for the first time are enabled, and loaded after the known ones.
+    /// Lists no mods on wasm.
+    pub fn list_mods(&self) -> Result<Vec<ModInfo>, io::Error> {
+        if cfg!(target_arch = "wasm32") {
+            return Ok(Vec::new());
+        }
+
+        let mut mod_dirs = match list_dirs(&file_paths::mods_dir(&self.app_name)) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            mod_dirs => mod_dirs?,
+        };
+        mod_dirs.sort();
+
+        let settings = self.mod_settings();
+        let mut mods: Vec<_> = mod_dirs
+            .into_iter()
+            .filter_map(|path| {
+                let name = path.file_name()?.to_str()?.to_owned();
+                Some(ModInfo {
+                    enabled: !settings.disabled.contains(&name),
+                    name,
+                    path,
+                })
+            })
+            .collect();
+        mods.sort_by_key(|mod_info| {
+            settings
+                .load_order
+                .iter()
+                .position(|name| *name == mod_info.name)
+                .unwrap_or(usize::MAX)
+        });
+        Ok(mods)
+    }
+
+    /// Enables or disables a mod. Takes effect the next time assets or configs are loaded.
+    pub fn set_mod_enabled(&self, mod_name: &str, enabled: bool) -> Result<(), io::Error> {
+        log_info!("Setting mod '{}' enabled: {}", mod_name, enabled);
+        let mods = self.list_mods()?;
+        Self::find_mod(&mods, mod_name)?;
+
+        let mut settings = Self::settings_of_mods(&mods);
+        settings.disabled.retain(|name| name != mod_name);
+        if !enabled {
+            settings.disabled.push(mod_name.to_owned());
+        }
+        self.export_config(MOD_SETTINGS_CONFIG, &settings)
+    }
+
+    /// Sets the load order of the mods, later mods overriding earlier ones.
+    /// Mods missing from the list are loaded after the listed ones, in their current order.
+    pub fn set_mod_load_order(&self, mod_names: &[&str]) -> Result<(), io::Error> {
+        log_info!("Setting mod load order: {:?}", mod_names);
+        let mods = self.list_mods()?;
+        for mod_name in mod_names {
+            Self::find_mod(&mods, mod_name)?;
+        }
+
+        let mut settings = Self::settings_of_mods(&mods);
+        settings.load_order.retain(|name| !mod_names.contains(&name.as_str()));
+        settings
+            .load_order
+            .splice(0..0, mod_names.iter().map(|name| name.to_string()));
+        self.export_config(MOD_SETTINGS_CONFIG, &settings)
+    }
+
+    /// Folders of the enabled mods in load order.
+    pub fn enabled_mod_dirs(&self) -> Vec<PathBuf> {
+        match self.list_mods() {
+            Ok(mods) => mods
+                .into_iter()
+                .filter(|mod_info| mod_info.enabled)
+                .map(|mod_info| mod_info.path)
+                .collect(),
+            Err(err) => {
+                log_warn!("Failed to list mods: {}", err);
+                Vec::new()
             }
+        }
+    }
+
+    /// Mod settings are read past the mods, as they decide which mods are enabled.
+    fn mod_settings(&self) -> ModSettings {
+        let Some((encoded, format)) = self.read_config(MOD_SETTINGS_CONFIG) else {
+            return ModSettings::default();
+        };
+        config_from_string_as(&encoded, format).unwrap_or_else(|err| {
+            log_warn!("Failed to read mod settings, using defaults: {:?}", err);
+            ModSettings::default()
+        })
+    }
 
-            Ok(save_files)
+    fn settings_of_mods(mods: &[ModInfo]) -> ModSettings {
+        ModSettings {
+            load_order: mods.iter().map(|mod_info| mod_info.name.clone()).collect(),
+            disabled: mods
+                .iter()
+                .filter(|mod_info| !mod_info.enabled)
+                .map(|mod_info| mod_info.name.clone())
+                .collect(),
         }
     }
 
-    /// Deletes a save game from storage.
-    /// - **Native platforms**: Removes from the file system
-    /// - **WASM/Browser**: Removes from IndexedDB
-    pub fn delete_save(&self, save_name: &str) -> Result<(), io::Error> {
-        log_warn!("Deleting save '{}'", save_name);
+    fn find_mod<'a>(mods: &'a [ModInfo], mod_name: &str) -> Result<&'a ModInfo, io::Error> {
+        mods.iter()
+            .find(|mod_info| mod_info.name == mod_name)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Mod '{}' not found", mod_name)))
+    }
+
+    /// Queues the operation for the IO thread.
+    /// On wasm it runs right away instead, as browser storage can only be reached from the main thread.
+    fn run_in_background<T: Send + 'static>(
+        &self,
+        operation: impl FnOnce(&Files) -> io::Result<T> + Send + 'static,
+    ) -> FileTask<T> {
+        let (sender, receiver) = mpsc::channel();
+        let files = self.clone();
+        let job = move || {
+            // The task may have been dropped already, if the caller doesn't care about the result
+            let _ = sender.send(operation(&files));
+        };
 
         #[cfg(target_arch = "wasm32")]
-        {
-            file_helpers::delete_indexeddb(&self.app_name, "saves", save_name)
-        }
+        job();
         #[cfg(not(target_arch = "wasm32"))]
-        {
-            let save_folder_path = file_paths::save_dir(&self.app_name, Some(save_name));
-            fs::remove_dir_all(save_folder_path)
+        self.io_jobs
+            .send(Box::new(job))
+            .expect("File IO thread must be running");
+
+        FileTask {
+            receiver: Mutex::new(receiver),
         }
     }
+}
 
-    /// Lists all available save games.
-    /// - **Native platforms**: Lists directories in the saves folder
-    /// - **WASM/Browser**: Lists saves stored in IndexedDB
-    pub fn list_saves(&self) -> Result<Vec<String>, io::Error> {
-        #[cfg(target_arch = "wasm32")]
-        {
-            file_helpers::list_keys_indexeddb(&self.app_name, "saves")
+fn add_save_header(version: u32, file_content: Vec<u8>) -> Vec<u8> {
+    let mut content = Vec::with_capacity(SAVE_HEADER_MAGIC.len() + 4 + file_content.len());
+    content.extend_from_slice(SAVE_HEADER_MAGIC);
+    content.extend_from_slice(&version.to_le_bytes());
+    content.extend(file_content);
+    content
+}
+
+/// Removes the headers from the save files, returning the version of the save. None if the save has no files.
+fn strip_save_headers(files: &mut Map<String, Vec<u8>>) -> Result<Option<u32>, io::Error> {
+    let mut version = None;
+    for (file_name, file_content) in files.iter_mut() {
+        let file_version = match file_content.strip_prefix(SAVE_HEADER_MAGIC) {
+            Some(rest) if rest.len() >= 4 => {
+                let file_version = u32::from_le_bytes(rest[..4].try_into().unwrap());
+                file_content.drain(..SAVE_HEADER_MAGIC.len() + 4);
+                file_version
+            }
+            _ => 0,
+        };
+        if version.is_some_and(|version| version != file_version) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Save file {} has version {} unlike the others", file_name, file_version),
+            ));
         }
-        #[cfg(not(target_arch = "wasm32"))]
-        {
-            let paths = list_dirs(&file_paths::save_dir(&self.app_name, None))?;
-            Ok(paths
-                .into_iter()
-                .map(|path| {
-                    path.file_name()
-                        .unwrap()
-                        .to_os_string()
-                        .into_string()
-                        .expect("Save file names must be valid unicode")
-                })
-                .collect())
+        version = Some(file_version);
+    }
+    Ok(version)
+}
+
+/// File operation running in the background, see [`Files::export_save_async`].
+/// Dropping the task doesn't cancel the operation.
+pub struct FileTask<T> {
+    // Behind a mutex so that tasks can be kept in state shared between threads
+    receiver: Mutex<mpsc::Receiver<io::Result<T>>>,
+}
+
+impl<T> FileTask<T> {
+    /// Task that has already finished with the result.
+    pub fn finished(result: io::Result<T>) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        sender.send(result).unwrap();
+        Self {
+            receiver: Mutex::new(receiver),
         }
     }
+
+    /// Returns `Some(result)` once the operation has finished, `None` while it is still running.
+    /// The result is only returned once, polling again after that returns an error.
+    pub fn try_join(&self) -> Option<io::Result<T>> {
+        match self.receiver.lock().unwrap().try_recv() {
+            Ok(result) => Some(result),
+            Err(mpsc::TryRecvError::Empty) => None,
+            Err(mpsc::TryRecvError::Disconnected) => Some(Err(io::Error::other("File operation was lost"))),
+        }
+    }
+
+    /// Blocks until the operation has finished and returns the result.
+    pub fn join(self) -> io::Result<T> {
+        self.receiver
+            .into_inner()
+            .unwrap()
+            .recv()
+            .unwrap_or_else(|_| Err(io::Error::other("File operation was lost")))
+    }
 }
 
 // ---------------------------------------------------------- //
@@ -253,12 +579,13 @@ impl Files {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::time::Duration;
+
     use crate::core::Constants;
-    use crate::util::config::Config;
     use derive_engine::Config;
 
     // Mock config for testing
-    #[derive(Debug, Clone, PartialEq, Config)]
+    #[derive(Debug, Clone, Default, PartialEq, Config)]
     struct TestConfig {
         value: i32,
         name: String,
@@ -271,6 +598,14 @@ mod tests {
 
     impl TestFilesGuard {
         fn new(test_name: &str) -> Self {
+            Self::with_saves(test_name, SaveConstants::default())
+        }
+
+        fn with_saves(test_name: &str, saves: SaveConstants) -> Self {
+            Self::with_storage(test_name, saves, None)
+        }
+
+        fn with_storage(test_name: &str, saves: SaveConstants, storage: Option<Arc<dyn StorageBackend>>) -> Self {
             let app_name = format!("ion_test_{}", test_name);
 
             // Create a mock Constants with the correct structure and unique app name
@@ -278,12 +613,20 @@ mod tests {
                 app_name: Box::leak(app_name.into_boxed_str()), // Convert to &'static str
                 gfx: crate::core::GfxConstants {
                     asset_path: std::path::PathBuf::from("test_assets"),
+                    asset_archives: Vec::new(),
                     camera_angle_deg: 45.0,
                     pixels_per_unit: 32.0,
                     height_units_total: 100.0,
                     height_scaled_zero: 0.5,
+                    backend: crate::gfx::gfx_config::GfxBackend::Auto,
+                    adapter: crate::gfx::gfx_config::GfxAdapterSelection::Auto,
                 },
                 net: None,
+                saves,
+                config_format: ConfigFormat::Ion,
+                storage,
+                autosave: None,
+                file_watch_interval: None,
             };
 
             let files = Files::new(&constants);
@@ -298,6 +641,8 @@ mod tests {
     impl Drop for TestFilesGuard {
         fn drop(&mut self) {
             let _ = self.files.delete_all_data();
+            // Logs and mods are in the game data directory whatever the storage backend
+            let _ = fs::remove_dir_all(file_paths::game_data_dir(&self.files.app_name));
         }
     }
 
@@ -337,6 +682,141 @@ mod tests {
         // Guard automatically cleans up when it goes out of scope
     }
 
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_export_config_in_other_formats() {
+        let guard = TestFilesGuard::new("export_config_in_other_formats");
+        let files = guard.files();
+
+        let test_config = TestConfig {
+            value: 7,
+            name: "toml".to_string(),
+        };
+        files
+            .export_config_as("test", &test_config, ConfigFormat::Toml)
+            .unwrap();
+        let config_dir = file_paths::config_dir(&files.app_name);
+        assert!(config_dir.join("test.toml").is_file());
+        assert_eq!(files.import_config::<TestConfig>("test").unwrap(), test_config);
+
+        // Exporting in another format replaces the config
+        let test_config = TestConfig {
+            value: 8,
+            name: "json".to_string(),
+        };
+        files
+            .export_config_as("test", &test_config, ConfigFormat::Json)
+            .unwrap();
+        assert!(!config_dir.join("test.toml").exists());
+        assert_eq!(files.import_config::<TestConfig>("test").unwrap(), test_config);
+
+        files.delete_config("test").unwrap();
+        assert!(files.import_config::<TestConfig>("test").is_err());
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_config_keeps_unknown_fields() {
+        let guard = TestFilesGuard::new("config_keeps_unknown_fields");
+        let files = guard.files();
+
+        // A config written by a newer version, without a field of this version
+        files.export_config("test", &TestConfig::default()).unwrap();
+        let config_file = file_paths::config_dir(&files.app_name).join("test.conf");
+        fs::write(&config_file, "value = 5\nfuture_field = \"kept\"\n").unwrap();
+
+        let mut imported = files.import_config::<TestConfig>("test").unwrap();
+        assert_eq!(
+            imported,
+            TestConfig {
+                value: 5,
+                name: String::new()
+            }
+        );
+
+        imported.value = 6;
+        files.export_config("test", &imported).unwrap();
+        let exported = fs::read_to_string(&config_file).unwrap();
+        assert!(exported.contains("future_field = \"kept\""), "{exported}");
+        assert_eq!(files.import_config::<TestConfig>("test").unwrap(), imported);
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_mods_can_be_enabled_and_ordered() {
+        let guard = TestFilesGuard::new("mods_can_be_enabled_and_ordered");
+        let files = guard.files();
+        let mods_dir = file_paths::mods_dir(&files.app_name);
+        for mod_name in ["b_mod", "a_mod", "c_mod"] {
+            fs::create_dir_all(mods_dir.join(mod_name)).unwrap();
+        }
+        let names = |files: &Files| {
+            files
+                .list_mods()
+                .unwrap()
+                .into_iter()
+                .map(|mod_info| (mod_info.name, mod_info.enabled))
+                .collect::<Vec<_>>()
+        };
+
+        // New mods are enabled and in name order
+        assert_eq!(
+            names(files),
+            vec![("a_mod".to_string(), true), ("b_mod".to_string(), true), ("c_mod".to_string(), true)]
+        );
+
+        files.set_mod_enabled("b_mod", false).unwrap();
+        files.set_mod_load_order(&["c_mod"]).unwrap();
+        fs::create_dir_all(mods_dir.join("0_mod")).unwrap();
+        assert_eq!(
+            names(files),
+            vec![
+                ("c_mod".to_string(), true),
+                ("a_mod".to_string(), true),
+                ("b_mod".to_string(), false),
+                ("0_mod".to_string(), true)
+            ]
+        );
+        assert_eq!(
+            files.enabled_mod_dirs(),
+            vec![mods_dir.join("c_mod"), mods_dir.join("a_mod"), mods_dir.join("0_mod")]
+        );
+
+        assert_eq!(
+            files.set_mod_enabled("missing_mod", true).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_mod_configs_replace_configs() {
+        let guard = TestFilesGuard::new("mod_configs_replace_configs");
+        let files = guard.files();
+        let mods_dir = file_paths::mods_dir(&files.app_name);
+        let write_mod_config = |mod_name: &str, value: i32| {
+            let config_dir = mods_dir.join(mod_name).join(mods::MOD_CONFIG_DIR);
+            fs::create_dir_all(&config_dir).unwrap();
+            fs::write(config_dir.join("test.conf"), format!("value = {value}\n")).unwrap();
+        };
+
+        let config = TestConfig {
+            value: 1,
+            name: "base".to_string(),
+        };
+        files.export_config("test", &config).unwrap();
+        write_mod_config("mod_a", 2);
+        write_mod_config("mod_b", 3);
+
+        // The last enabled mod wins
+        assert_eq!(files.import_config::<TestConfig>("test").unwrap().value, 3);
+        files.set_mod_load_order(&["mod_b", "mod_a"]).unwrap();
+        assert_eq!(files.import_config::<TestConfig>("test").unwrap().value, 2);
+        files.set_mod_enabled("mod_a", false).unwrap();
+        files.set_mod_enabled("mod_b", false).unwrap();
+        assert_eq!(files.import_config::<TestConfig>("test").unwrap(), config);
+    }
+
     #[test]
     #[cfg(not(target_arch = "wasm32"))]
     fn test_import_nonexistent_config() {
@@ -430,7 +910,7 @@ mod tests {
         ];
 
         // Export save
-        let export_result = files.export_save("test_save", save_files.clone());
+        let export_result = files.export_save("test_save", save_files.clone(), SaveMetadata::default());
         assert!(export_result.is_ok(), "Failed to export save: {:?}", export_result);
 
         // Import save
@@ -457,11 +937,13 @@ mod tests {
 
         // Create initial save
         let initial_files = vec![("data.txt".to_string(), b"initial".to_vec())];
-        files.export_save("backup_test", initial_files).unwrap();
+        files
+            .export_save("backup_test", initial_files, SaveMetadata::default())
+            .unwrap();
 
         // Create new save (should backup the old one)
         let new_files = vec![("data.txt".to_string(), b"updated".to_vec())];
-        let result = files.export_save("backup_test", new_files);
+        let result = files.export_save("backup_test", new_files, SaveMetadata::default());
         assert!(result.is_ok(), "Failed to export save with backup: {:?}", result);
 
         // Verify the save was updated
@@ -470,6 +952,82 @@ mod tests {
         // Guard automatically cleans up when it goes out of scope
     }
 
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_export_and_import_save_async() {
+        let guard = TestFilesGuard::new("export_and_import_save_async");
+        let files = guard.files();
+
+        let save_files = vec![("data.txt".to_string(), b"async".to_vec())];
+
+        // Operations run in order, so the import sees the export even though neither is waited for in between
+        let export_task = files.export_save_async("async_save", save_files, SaveMetadata::default());
+        let import_task = files.import_save_async("async_save");
+
+        let imported = import_task.join().unwrap();
+        assert_eq!(imported.get("data.txt").unwrap(), &b"async".to_vec());
+        assert!(export_task.try_join().unwrap().is_ok());
+        assert!(export_task.try_join().unwrap().is_err(), "Result is only returned once");
+
+        let missing_task = files.import_save_async("nonexistent_save");
+        assert!(missing_task.join().is_err(), "Should fail to import nonexistent save");
+        // Guard automatically cleans up when it goes out of scope
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_save_versions_are_migrated() {
+        let old_guard = TestFilesGuard::new("save_versions_are_migrated");
+        let old_files = old_guard.files();
+        old_files
+            .export_save(
+                "old_save",
+                vec![("data.txt".to_string(), b"v0".to_vec())],
+                SaveMetadata::default(),
+            )
+            .unwrap();
+
+        // Saves from before versioning have no header, and count as version 0
+        let legacy_dir = file_paths::save_dir(&old_files.app_name, Some("legacy_save"));
+        fs::create_dir_all(&legacy_dir).unwrap();
+        fs::write(legacy_dir.join("data.txt"), b"v0").unwrap();
+
+        let saves = SaveConstants::new(2)
+            .with_migration(0, |files| {
+                files.insert("added.txt".to_string(), b"v1".to_vec());
+                Ok(())
+            })
+            .with_migration(1, |files| {
+                let data = files.remove("data.txt").unwrap();
+                files.insert("renamed.txt".to_string(), data);
+                Ok(())
+            });
+        let new_guard = TestFilesGuard::with_saves("save_versions_are_migrated", saves);
+        let new_files = new_guard.files();
+
+        for save_name in ["old_save", "legacy_save"] {
+            let imported = new_files.import_save(save_name).unwrap();
+            assert_eq!(imported.len(), 2);
+            assert_eq!(imported.get("renamed.txt").unwrap(), &b"v0".to_vec());
+            assert_eq!(imported.get("added.txt").unwrap(), &b"v1".to_vec());
+        }
+
+        // Saves of the current version are not migrated, and newer ones can't be read
+        new_files
+            .export_save(
+                "new_save",
+                vec![("renamed.txt".to_string(), b"v2".to_vec())],
+                SaveMetadata::default(),
+            )
+            .unwrap();
+        let imported = new_files.import_save("new_save").unwrap();
+        assert_eq!(imported.get("renamed.txt").unwrap(), &b"v2".to_vec());
+        assert!(
+            old_files.import_save("new_save").is_err(),
+            "Newer saves should fail to import"
+        );
+    }
+
     #[test]
     #[cfg(not(target_arch = "wasm32"))]
     fn test_import_nonexistent_save() {
@@ -489,7 +1047,9 @@ mod tests {
 
         // Create a save to delete
         let save_files = vec![("test.dat".to_string(), vec![1, 2, 3])];
-        files.export_save("to_delete", save_files).unwrap();
+        files
+            .export_save("to_delete", save_files, SaveMetadata::default())
+            .unwrap();
 
         // Delete the save
         let delete_result = files.delete_save("to_delete");
@@ -523,15 +1083,15 @@ mod tests {
         let save2 = vec![("data2.txt".to_string(), b"save2".to_vec())];
         let save3 = vec![("data3.txt".to_string(), b"save3".to_vec())];
 
-        files.export_save("save_alpha", save1).unwrap();
-        files.export_save("save_beta", save2).unwrap();
-        files.export_save("save_gamma", save3).unwrap();
+        files.export_save("save_alpha", save1, SaveMetadata::default()).unwrap();
+        files.export_save("save_beta", save2, SaveMetadata::default()).unwrap();
+        files.export_save("save_gamma", save3, SaveMetadata::default()).unwrap();
 
         // List saves
         let saves_result = files.list_saves();
         assert!(saves_result.is_ok(), "Failed to list saves: {:?}", saves_result);
 
-        let mut saves = saves_result.unwrap();
+        let mut saves: Vec<String> = saves_result.unwrap().into_iter().map(|save| save.name).collect();
         saves.sort(); // Sort for consistent comparison
 
         assert_eq!(saves.len(), 3);
@@ -541,6 +1101,52 @@ mod tests {
         // Guard automatically cleans up when it goes out of scope
     }
 
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_list_saves_with_info() {
+        let guard = TestFilesGuard::with_saves("list_saves_with_info", SaveConstants::new(3));
+        let files = guard.files();
+
+        let metadata = SaveMetadata {
+            playtime: Duration::from_secs(3600),
+            app_version: "1.2.3".to_string(),
+            thumbnail: Some(b"\x89PNG".to_vec()),
+        };
+        let save_files = vec![("data.txt".to_string(), b"12345".to_vec())];
+        files
+            .export_save("info_save", save_files.clone(), SaveMetadata::default())
+            .unwrap();
+        let created = files.list_saves().unwrap()[0].created;
+        files.export_save("info_save", save_files, metadata.clone()).unwrap();
+
+        // Saves from before save info was stored are listed too
+        let legacy_dir = file_paths::save_dir(&files.app_name, Some("legacy_save"));
+        fs::create_dir_all(&legacy_dir).unwrap();
+        fs::write(legacy_dir.join("data.txt"), b"123").unwrap();
+
+        let mut saves = files.list_saves().unwrap();
+        saves.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(saves.len(), 2);
+
+        let save = &saves[0];
+        assert_eq!(save.name, "info_save");
+        assert_eq!(save.created, created, "Overwriting should keep the creation time");
+        assert!(save.modified >= save.created);
+        assert_eq!(save.size, 5);
+        assert_eq!(save.save_version, 3);
+        assert_eq!(save.metadata, metadata);
+
+        let legacy_save = &saves[1];
+        assert_eq!(legacy_save.name, "legacy_save");
+        assert!(legacy_save.modified.is_some());
+        assert_eq!(legacy_save.size, 3);
+        assert_eq!(legacy_save.save_version, 0);
+        assert_eq!(legacy_save.metadata, SaveMetadata::default());
+
+        // The save info isn't part of the save
+        assert_eq!(files.import_save("info_save").unwrap().len(), 1);
+    }
+
     #[test]
     #[cfg(not(target_arch = "wasm32"))]
     fn test_list_saves_empty() {
@@ -570,7 +1176,9 @@ mod tests {
         let save_files = vec![("test.dat".to_string(), vec![1, 2, 3])];
 
         files.export_config("test_config", &config).unwrap();
-        files.export_save("test_save", save_files).unwrap();
+        files
+            .export_save("test_save", save_files, SaveMetadata::default())
+            .unwrap();
 
         // Delete all data
         let result = files.delete_all_data();
@@ -611,7 +1219,7 @@ mod tests {
         let files = guard.files();
 
         // Export save with no files
-        let result = files.export_save("empty_save", vec![]);
+        let result = files.export_save("empty_save", vec![], SaveMetadata::default());
         assert!(result.is_ok(), "Should be able to create save with no files");
 
         // Import should succeed but return empty map
@@ -620,6 +1228,24 @@ mod tests {
         // Guard automatically cleans up when it goes out of scope
     }
 
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_save_names_outside_save_dir_are_rejected() {
+        let guard = TestFilesGuard::new("save_names_outside_save_dir");
+        let files = guard.files();
+
+        for save_name in ["..", "../escaped", "/tmp/escaped", "a/b"] {
+            let result = files.export_save(save_name, vec![], SaveMetadata::default());
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        }
+        for file_name in ["..", "../escaped.txt", "/tmp/escaped.txt", "sub/file.txt"] {
+            let save_files = vec![(file_name.to_string(), b"data".to_vec())];
+            let result = files.export_save("save", save_files, SaveMetadata::default());
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        }
+        assert!(files.list_saves().unwrap().is_empty());
+    }
+
     #[test]
     #[cfg(not(target_arch = "wasm32"))]
     fn test_save_with_large_files() {
@@ -630,7 +1256,7 @@ mod tests {
         let large_content = vec![0u8; 1024 * 1024];
         let save_files = vec![("large_file.dat".to_string(), large_content.clone())];
 
-        let export_result = files.export_save("large_save", save_files);
+        let export_result = files.export_save("large_save", save_files, SaveMetadata::default());
         assert!(export_result.is_ok(), "Should handle large files");
 
         let imported = files.import_save("large_save").unwrap();
@@ -648,12 +1274,16 @@ mod tests {
         // Create initial save with multiple files
         let initial_files =
             vec![("file1.txt".to_string(), b"version1".to_vec()), ("file2.txt".to_string(), b"data2".to_vec())];
-        files.export_save("overwrite_test", initial_files).unwrap();
+        files
+            .export_save("overwrite_test", initial_files, SaveMetadata::default())
+            .unwrap();
 
         // Overwrite with different files
         let new_files =
             vec![("file1.txt".to_string(), b"version2".to_vec()), ("file3.txt".to_string(), b"data3".to_vec())];
-        files.export_save("overwrite_test", new_files).unwrap();
+        files
+            .export_save("overwrite_test", new_files, SaveMetadata::default())
+            .unwrap();
 
         // Verify the save was completely replaced
         let imported = files.import_save("overwrite_test").unwrap();
@@ -663,4 +1293,335 @@ mod tests {
         assert!(imported.get("file2.txt").is_none(), "Old file should be gone");
         // Guard automatically cleans up when it goes out of scope
     }
+
+    /// Serves the cloud sync protocol from memory over TLS, for as long as the test runs. Returns the address and the
+    /// self-signed certificate of the server, which is for `localhost`.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn spawn_fake_cloud(test_name: &str) -> (std::net::SocketAddr, std::path::PathBuf) {
+        use std::io::{Read, Write};
+
+        use ion_common::net::tls::{TlsCertificate, TlsStream};
+
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        let cert_dir = std::env::temp_dir().join(format!("ion_test_{}_cert", test_name));
+        fs::create_dir_all(&cert_dir).unwrap();
+        let (cert_file, key_file) = (cert_dir.join("cert.pem"), cert_dir.join("key.pem"));
+        fs::write(&cert_file, cert.cert.pem()).unwrap();
+        fs::write(&key_file, cert.key_pair.serialize_pem()).unwrap();
+        let tls = TlsCertificate::new(&cert_file, &key_file).load().unwrap();
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut saves: Map<String, (String, String, Vec<u8>)> = Map::default();
+            for stream in listener.incoming().map(Result::unwrap) {
+                let mut stream = TlsStream::accept(stream, Some(&tls)).unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                let header_end = loop {
+                    let read = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..read]);
+                    if let Some(pos) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+                        break pos + 4;
+                    }
+                };
+                let head = String::from_utf8(request[..header_end].to_vec()).unwrap();
+                let header = |name: &str| {
+                    head.lines()
+                        .find_map(|line| line.split_once(": ").filter(|(key, _)| key.eq_ignore_ascii_case(name)))
+                        .map(|(_, value)| value.to_owned())
+                };
+                let content_length: usize = header("Content-Length").unwrap().parse().unwrap();
+                while request.len() < header_end + content_length {
+                    let read = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..read]);
+                }
+                let body = request[header_end..].to_vec();
+
+                let mut request_line = head.lines().next().unwrap().split(' ');
+                let (method, url) = (request_line.next().unwrap(), request_line.next().unwrap());
+                let save_name = url.strip_prefix("/saves/").map(str::to_owned);
+                let (status, response) = match (method, save_name) {
+                    ("GET", None) => {
+                        let list: String = saves
+                            .iter()
+                            .map(|(name, (hash, modified, _))| format!("{}\t{}\t{}\n", name, hash, modified))
+                            .collect();
+                        ("200 OK", list.into_bytes())
+                    }
+                    ("GET", Some(name)) => match saves.get(&name) {
+                        Some((_, _, save)) => ("200 OK", save.clone()),
+                        None => ("404 Not Found", Vec::new()),
+                    },
+                    ("POST", Some(name)) => {
+                        let hash = header("X-Save-Hash").unwrap();
+                        saves.insert(name, (hash, header("X-Save-Modified").unwrap(), body));
+                        ("200 OK", Vec::new())
+                    }
+                    ("DELETE", Some(name)) => {
+                        saves.remove(&name);
+                        ("200 OK", Vec::new())
+                    }
+                    _ => ("400 Bad Request", Vec::new()),
+                };
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\n\r\n",
+                    status,
+                    response.len()
+                );
+                let _ = stream.write_all(&response);
+                let _ = stream.flush();
+            }
+        });
+        (addr, cert_file)
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_sync_saves_between_devices() {
+        use crate::files::cloud_sync::{CloudSyncEndpoint, SaveSyncStatus, SyncSide};
+
+        let guard_a = TestFilesGuard::new("sync_saves_device_a");
+        let guard_b = TestFilesGuard::new("sync_saves_device_b");
+        let (device_a, device_b) = (guard_a.files(), guard_b.files());
+        let save = |files: &Files, content: &[u8]| {
+            files
+                .export_save(
+                    "my save",
+                    vec![("data.bin".to_string(), content.to_vec())],
+                    SaveMetadata::default(),
+                )
+                .unwrap();
+        };
+        let synced = |files: &Files| {
+            files
+                .sync_saves()
+                .unwrap()
+                .into_iter()
+                .map(|(_, status)| status)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(device_a.sync_saves().unwrap_err().kind(), io::ErrorKind::NotConnected);
+        let (addr, cert_file) = spawn_fake_cloud("sync_saves");
+        let endpoint = CloudSyncEndpoint {
+            addr,
+            server_name: "localhost".to_string(),
+            root_cert_file: Some(cert_file.clone()),
+            path: "/saves".to_string(),
+            headers: Default::default(),
+        };
+        device_a.set_cloud_sync(Some(endpoint.clone()));
+        device_b.set_cloud_sync(Some(endpoint));
+        let (event_sender, events) = mpsc::channel();
+        device_b.set_app_event_sender(event_sender);
+
+        save(device_a, b"first");
+        assert_eq!(synced(device_a), vec![SaveSyncStatus::Uploaded]);
+        assert_eq!(synced(device_b), vec![SaveSyncStatus::Downloaded]);
+        assert_eq!(
+            device_b.import_save("my save").unwrap().get("data.bin").unwrap(),
+            b"first"
+        );
+        assert_eq!(synced(device_b), vec![SaveSyncStatus::UpToDate]);
+        assert!(matches!(
+            events.try_iter().last(),
+            Some(ApplicationEvent::SaveSynced { save_name, status: SaveSyncStatus::UpToDate }) if save_name == "my save"
+        ));
+
+        // Both devices save before syncing again
+        save(device_a, b"second on a");
+        save(device_b, b"second on b");
+        assert_eq!(synced(device_a), vec![SaveSyncStatus::Uploaded]);
+        assert!(matches!(synced(device_b)[..], [SaveSyncStatus::Conflict { .. }]));
+        assert_eq!(
+            device_b.import_save("my save").unwrap().get("data.bin").unwrap(),
+            b"second on b"
+        );
+
+        assert_eq!(
+            device_b.resolve_save_conflict("my save", SyncSide::Remote).unwrap(),
+            SaveSyncStatus::Downloaded
+        );
+        assert_eq!(
+            device_b.import_save("my save").unwrap().get("data.bin").unwrap(),
+            b"second on a"
+        );
+        assert_eq!(synced(device_a), vec![SaveSyncStatus::UpToDate]);
+
+        save(device_b, b"third");
+        assert_eq!(synced(device_b), vec![SaveSyncStatus::Uploaded]);
+        assert_eq!(synced(device_a), vec![SaveSyncStatus::Downloaded]);
+
+        device_a.delete_cloud_save("my save").unwrap();
+        device_a.delete_save("my save").unwrap();
+        assert!(synced(device_a).is_empty());
+
+        // The endpoint isn't trusted without its certificate
+        device_a.set_cloud_sync(Some(CloudSyncEndpoint {
+            root_cert_file: None,
+            ..device_b.cloud_sync.lock().unwrap().clone().unwrap()
+        }));
+        assert!(device_a.sync_saves().is_err());
+        fs::remove_dir_all(cert_file.parent().unwrap()).unwrap();
+    }
+
+    /// Keeps everything in memory, like a backend of a platform without a file system would.
+    #[derive(Debug, Default)]
+    struct MemoryStorage {
+        configs: Mutex<Map<String, String>>,
+        saves: Mutex<Map<String, (Vec<(String, Vec<u8>)>, Vec<u8>)>>,
+        replays: Mutex<Map<String, Vec<u8>>>,
+    }
+
+    impl StorageBackend for MemoryStorage {
+        fn read_config(&self, config_name: &str, format: ConfigFormat) -> io::Result<String> {
+            let key = format!("{}.{}", config_name, format.extension());
+            self.configs
+                .lock()
+                .unwrap()
+                .get(&key)
+                .cloned()
+                .ok_or(io::ErrorKind::NotFound.into())
+        }
+
+        fn write_config(&self, config_name: &str, format: ConfigFormat, content: &str) -> io::Result<()> {
+            let key = format!("{}.{}", config_name, format.extension());
+            self.configs.lock().unwrap().insert(key, content.to_owned());
+            Ok(())
+        }
+
+        fn delete_config(&self, config_name: &str, format: ConfigFormat) -> io::Result<()> {
+            let key = format!("{}.{}", config_name, format.extension());
+            self.configs
+                .lock()
+                .unwrap()
+                .remove(&key)
+                .map(|_| ())
+                .ok_or(io::ErrorKind::NotFound.into())
+        }
+
+        fn delete_all_configs(&self) -> io::Result<()> {
+            self.configs.lock().unwrap().clear();
+            Ok(())
+        }
+
+        fn read_save(&self, save_name: &str) -> io::Result<Map<String, Vec<u8>>> {
+            let saves = self.saves.lock().unwrap();
+            let (files, _) = saves.get(save_name).ok_or(io::Error::from(io::ErrorKind::NotFound))?;
+            Ok(files.iter().cloned().collect())
+        }
+
+        fn read_save_info(&self, save_name: &str) -> io::Result<Vec<u8>> {
+            let saves = self.saves.lock().unwrap();
+            let (_, save_info) = saves.get(save_name).ok_or(io::Error::from(io::ErrorKind::NotFound))?;
+            Ok(save_info.clone())
+        }
+
+        fn write_save(&self, save_name: &str, files: Vec<(String, Vec<u8>)>, save_info: Vec<u8>) -> io::Result<()> {
+            self.saves
+                .lock()
+                .unwrap()
+                .insert(save_name.to_owned(), (files, save_info));
+            Ok(())
+        }
+
+        fn delete_save(&self, save_name: &str) -> io::Result<()> {
+            self.saves
+                .lock()
+                .unwrap()
+                .remove(save_name)
+                .map(|_| ())
+                .ok_or(io::ErrorKind::NotFound.into())
+        }
+
+        fn list_saves(&self) -> io::Result<Vec<String>> {
+            Ok(self.saves.lock().unwrap().keys().cloned().collect())
+        }
+
+        fn read_replay(&self, replay_name: &str) -> io::Result<Vec<u8>> {
+            self.replays
+                .lock()
+                .unwrap()
+                .get(replay_name)
+                .cloned()
+                .ok_or(io::ErrorKind::NotFound.into())
+        }
+
+        fn write_replay(&self, replay_name: &str, replay: Vec<u8>) -> io::Result<()> {
+            self.replays.lock().unwrap().insert(replay_name.to_owned(), replay);
+            Ok(())
+        }
+
+        fn delete_replay(&self, replay_name: &str) -> io::Result<()> {
+            self.replays
+                .lock()
+                .unwrap()
+                .remove(replay_name)
+                .map(|_| ())
+                .ok_or(io::ErrorKind::NotFound.into())
+        }
+
+        fn list_replays(&self) -> io::Result<Vec<String>> {
+            Ok(self.replays.lock().unwrap().keys().cloned().collect())
+        }
+
+        fn delete_all(&self) -> io::Result<()> {
+            self.configs.lock().unwrap().clear();
+            self.saves.lock().unwrap().clear();
+            self.replays.lock().unwrap().clear();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_custom_storage_backend() {
+        let storage = Arc::new(MemoryStorage::default());
+        let guard = TestFilesGuard::with_storage(
+            "custom_storage_backend",
+            SaveConstants::default(),
+            Some(storage.clone()),
+        );
+        let files = guard.files();
+
+        let config = TestConfig {
+            value: 7,
+            name: "memory".to_string(),
+        };
+        files.export_config_as("settings", &config, ConfigFormat::Toml).unwrap();
+        assert_eq!(files.import_config::<TestConfig>("settings").unwrap(), config);
+        assert!(storage.configs.lock().unwrap().contains_key("settings.toml"));
+
+        files
+            .export_save(
+                "slot",
+                vec![("data.bin".to_string(), vec![1, 2, 3])],
+                SaveMetadata::default(),
+            )
+            .unwrap();
+        assert_eq!(
+            files.import_save("slot").unwrap().get("data.bin").unwrap(),
+            &vec![1, 2, 3]
+        );
+        let saves = files.list_saves().unwrap();
+        assert_eq!(saves.len(), 1);
+        assert_eq!(saves[0].name, "slot");
+        assert_eq!(saves[0].size, 3);
+
+        files.export_replay_async("match", vec![4, 5]).join().unwrap();
+        assert_eq!(files.list_replays().unwrap(), vec!["match".to_string()]);
+        assert_eq!(files.import_replay("match").unwrap(), vec![4, 5]);
+
+        // Nothing is written to the file system
+        if cfg!(not(target_arch = "wasm32")) {
+            assert!(!file_paths::save_dir(&files.app_name, None).exists());
+            assert!(!file_paths::config_dir(&files.app_name).exists());
+        }
+
+        files.delete_save("slot").unwrap();
+        files.delete_all_data().unwrap();
+        assert!(files.list_saves().unwrap().is_empty());
+        assert!(files.import_config::<TestConfig>("settings").is_err());
+    }
 }
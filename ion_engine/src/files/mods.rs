@@ -0,0 +1,31 @@
+use std::path::PathBuf;
+
+use derive_engine::Config;
+
+/// Folder of a mod with textures, laid out like the texture asset directory.
+/// Textures of the mod replace the textures of the same path, and can add new ones.
+pub const MOD_TEXTURE_DIR: &str = "textures";
+
+/// Folder of a mod with configs, named like the configs of the config directory.
+pub const MOD_CONFIG_DIR: &str = "configs";
+
+/// Config with the load order of the mods and the disabled mods.
+pub(super) const MOD_SETTINGS_CONFIG: &str = "mods";
+
+/// A mod as listed by [`Files::list_mods`](super::Files::list_mods).
+///
+/// Mods are folders in the mods directory of the game data, named by the folder.
+/// Mods loaded later override the assets and configs of earlier mods.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModInfo {
+    pub name: String,
+    pub path: PathBuf,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Config)]
+pub(super) struct ModSettings {
+    /// Mods in load order. Mods found later are loaded after these.
+    pub load_order: Vec<String>,
+    pub disabled: Vec<String>,
+}
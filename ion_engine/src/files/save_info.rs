@@ -0,0 +1,34 @@
+use std::time::Duration;
+
+use bincode::{Decode, Encode};
+use ion_common::DateTime;
+
+/// Details the game stores with a save, so that load menus can show them without importing the save.
+/// Given by [`UniverseDataType::save_metadata`](crate::core::universe::UniverseDataType::save_metadata) when the
+/// universe is saved.
+#[derive(Debug, Clone, Default, PartialEq, Encode, Decode)]
+pub struct SaveMetadata {
+    /// Time played in the saved universe.
+    pub playtime: Duration,
+    /// Version of the game that wrote the save.
+    pub app_version: String,
+    /// Small picture of the save as PNG.
+    pub thumbnail: Option<Vec<u8>>,
+}
+
+/// A save as listed by [`Files::list_saves`](super::Files::list_saves).
+///
+/// Saves written before save info was stored have no metadata, and only the times the platform knows.
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub struct SaveInfo {
+    pub name: String,
+    /// When a save of this name was first written. Overwriting the save keeps it.
+    pub created: Option<DateTime>,
+    /// When the save was last written.
+    pub modified: Option<DateTime>,
+    /// Total size of the saved files in bytes.
+    pub size: u64,
+    /// Version of the save format, see [`SaveConstants`](crate::core::SaveConstants).
+    pub save_version: u32,
+    pub metadata: SaveMetadata,
+}
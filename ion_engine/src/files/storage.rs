@@ -0,0 +1,389 @@
+use std::ffi::OsStr;
+use std::fmt::Debug;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fs, io};
+
+use ion_common::{DateTime, Map};
+
+use crate::files::file_helpers::{self, list_dirs, list_files};
+use crate::files::file_paths;
+use crate::util::config::ConfigFormat;
+
+const REPLAY_EXTENSION: &str = "replay";
+
+// Holds the save info next to the save files, so that saves can be listed without reading them
+const SAVE_INFO_FILE: &str = ".save_info";
+
+/// Where [`Files`](super::Files) keeps configs, saves and replays, set with
+/// [`Constants::storage`](crate::core::Constants::storage).
+///
+/// The engine has [`NativeStorage`] and [`BrowserStorage`], one of which is used by default. Platforms with storage
+/// of their own, such as consoles or Steam Cloud, can be supported by implementing this for it.
+///
+/// Contents are stored as given: save headers, versions and save info are handled by [`Files`](super::Files).
+/// Reading or deleting something that doesn't exist fails with [`io::ErrorKind::NotFound`]. Backends are used from
+/// several threads, including the background IO thread of [`Files`](super::Files).
+pub trait StorageBackend: Debug + Send + Sync {
+    /// Reads the config written in the format.
+    fn read_config(&self, config_name: &str, format: ConfigFormat) -> io::Result<String>;
+
+    fn write_config(&self, config_name: &str, format: ConfigFormat, content: &str) -> io::Result<()>;
+
+    fn delete_config(&self, config_name: &str, format: ConfigFormat) -> io::Result<()>;
+
+    fn delete_all_configs(&self) -> io::Result<()>;
+
+    /// Reads the files of the save, without the save info.
+    fn read_save(&self, save_name: &str) -> io::Result<Map<String, Vec<u8>>>;
+
+    /// Reads the save info written with the save.
+    fn read_save_info(&self, save_name: &str) -> io::Result<Vec<u8>>;
+
+    /// Replaces the save, if there is one, with the files and the save info. A failed write should leave the
+    /// previous save as it was.
+    fn write_save(&self, save_name: &str, files: Vec<(String, Vec<u8>)>, save_info: Vec<u8>) -> io::Result<()>;
+
+    fn delete_save(&self, save_name: &str) -> io::Result<()>;
+
+    fn list_saves(&self) -> io::Result<Vec<String>>;
+
+    /// When the save was created and last written, for saves written before save info was stored.
+    fn save_times(&self, _save_name: &str) -> io::Result<(Option<DateTime>, Option<DateTime>)> {
+        Ok((None, None))
+    }
+
+    fn read_replay(&self, replay_name: &str) -> io::Result<Vec<u8>>;
+
+    /// Replaces the replay, if there is one.
+    fn write_replay(&self, replay_name: &str, replay: Vec<u8>) -> io::Result<()>;
+
+    fn delete_replay(&self, replay_name: &str) -> io::Result<()>;
+
+    fn list_replays(&self) -> io::Result<Vec<String>>;
+
+    /// Deletes everything the backend has stored for the application.
+    fn delete_all(&self) -> io::Result<()>;
+}
+
+// ---------------------------------------------------------- //
+// -------------------- Native storage ---------------------- //
+// ---------------------------------------------------------- //
+
+/// Keeps the data in the game data directory of the platform, see [`file_paths`]. Default on native platforms.
+///
+/// Configs are files in the config directory, saves are folders in the saves directory, and replays are files in
+/// the replays directory.
+#[derive(Debug)]
+pub struct NativeStorage {
+    app_name: String,
+}
+
+impl NativeStorage {
+    pub fn new(app_name: &str) -> Self {
+        fs::create_dir_all(file_paths::save_dir(app_name, None))
+            .unwrap_or_else(|_| panic!("Dir create error {:?}", file_paths::save_dir(app_name, None)));
+        fs::create_dir_all(file_paths::config_dir(app_name))
+            .unwrap_or_else(|_| panic!("Dir create error {:?}", file_paths::config_dir(app_name)));
+        fs::create_dir_all(file_paths::replay_dir(app_name))
+            .unwrap_or_else(|_| panic!("Dir create error {:?}", file_paths::replay_dir(app_name)));
+
+        Self {
+            app_name: app_name.to_owned(),
+        }
+    }
+
+    fn config_file(&self, config_name: &str, format: ConfigFormat) -> PathBuf {
+        config_file_in(&file_paths::config_dir(&self.app_name), config_name, format)
+    }
+
+    fn replay_path(&self, replay_name: &str) -> PathBuf {
+        file_paths::replay_dir(&self.app_name).join(format!("{}.{}", replay_name, REPLAY_EXTENSION))
+    }
+}
+
+/// Path of the config in the directory, in the format.
+pub(super) fn config_file_in(dir: &Path, config_name: &str, format: ConfigFormat) -> PathBuf {
+    let config_name = config_name.replace(".conf", "");
+    dir.join(format!("{}.{}", config_name, format.extension()))
+}
+
+/// Whether the name is a single plain path component, so that joining it to a directory stays in the directory.
+pub(super) fn is_plain_file_name(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    !name.contains(['/', '\\'])
+        && matches!(components.next(), Some(Component::Normal(component)) if component == OsStr::new(name))
+        && components.next().is_none()
+}
+
+fn check_plain_file_name(name: &str) -> io::Result<()> {
+    if is_plain_file_name(name) {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("'{}' is not a valid save or save file name", name),
+        ))
+    }
+}
+
+impl StorageBackend for NativeStorage {
+    fn read_config(&self, config_name: &str, format: ConfigFormat) -> io::Result<String> {
+        fs::read_to_string(self.config_file(config_name, format))
+    }
+
+    fn write_config(&self, config_name: &str, format: ConfigFormat, content: &str) -> io::Result<()> {
+        fs::write(self.config_file(config_name, format), content)
+    }
+
+    fn delete_config(&self, config_name: &str, format: ConfigFormat) -> io::Result<()> {
+        fs::remove_file(self.config_file(config_name, format))
+    }
+
+    fn delete_all_configs(&self) -> io::Result<()> {
+        fs::remove_dir_all(file_paths::config_dir(&self.app_name))
+    }
+
+    fn read_save(&self, save_name: &str) -> io::Result<Map<String, Vec<u8>>> {
+        let save_folder_path = file_paths::save_dir(&self.app_name, Some(save_name));
+        let mut save_files: Map<String, Vec<u8>> = Map::default();
+
+        for (file_path, _) in list_files(&save_folder_path, None)? {
+            if file_path.file_name() == Some(OsStr::new(SAVE_INFO_FILE)) {
+                continue;
+            }
+            let file_content = fs::read(&file_path)?;
+            let file_name = file_path
+                .file_name()
+                .unwrap()
+                .to_os_string()
+                .into_string()
+                .map_err(|err| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Save file {:?} was not valid: {:?}", file_path, err),
+                    )
+                })?;
+
+            save_files.insert(file_name, file_content);
+        }
+
+        Ok(save_files)
+    }
+
+    fn read_save_info(&self, save_name: &str) -> io::Result<Vec<u8>> {
+        fs::read(file_paths::save_dir(&self.app_name, Some(save_name)).join(SAVE_INFO_FILE))
+    }
+
+    fn write_save(&self, save_name: &str, files: Vec<(String, Vec<u8>)>, save_info: Vec<u8>) -> io::Result<()> {
+        check_plain_file_name(save_name)?;
+        for (file_name, _) in &files {
+            check_plain_file_name(file_name)?;
+        }
+
+        let save_folder_path = file_paths::save_dir(&self.app_name, Some(save_name));
+        let save_folder_backup_path =
+            file_paths::save_dir(&self.app_name, Some(format!("{}_backup", save_name).as_str()));
+        let prev_save_exists = save_folder_path.is_dir();
+
+        // Backup previous save
+        if prev_save_exists {
+            fs::rename(&save_folder_path, &save_folder_backup_path)?;
+        }
+
+        fs::create_dir_all(&save_folder_path)?;
+        let save_result: Result<(), io::Error> = {
+            for (file_name, file_content) in files.into_iter().chain([(SAVE_INFO_FILE.to_string(), save_info)]) {
+                let file_path = save_folder_path.clone().join(PathBuf::from(file_name));
+                fs::write(save_folder_path.join(file_path), file_content)?;
+            }
+            Ok(())
+        };
+
+        if save_result.is_err() {
+            // Error while writing save, restore backup
+            fs::rename(&save_folder_backup_path, &save_folder_path)?;
+            save_result
+        } else {
+            if prev_save_exists {
+                fs::remove_dir_all(save_folder_backup_path)?;
+            }
+            Ok(())
+        }
+    }
+
+    fn delete_save(&self, save_name: &str) -> io::Result<()> {
+        fs::remove_dir_all(file_paths::save_dir(&self.app_name, Some(save_name)))
+    }
+
+    fn list_saves(&self) -> io::Result<Vec<String>> {
+        let paths = list_dirs(&file_paths::save_dir(&self.app_name, None))?;
+        Ok(paths
+            .into_iter()
+            .map(|path| {
+                path.file_name()
+                    .unwrap()
+                    .to_os_string()
+                    .into_string()
+                    .expect("Save file names must be valid unicode")
+            })
+            .collect())
+    }
+
+    fn save_times(&self, save_name: &str) -> io::Result<(Option<DateTime>, Option<DateTime>)> {
+        let metadata = fs::metadata(file_paths::save_dir(&self.app_name, Some(save_name)))?;
+        let to_date_time = |time: SystemTime| {
+            let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+            DateTime::from_unix_timestamp_ms(since_epoch.as_millis() as u64)
+        };
+        Ok((
+            metadata.created().ok().map(to_date_time),
+            metadata.modified().ok().map(to_date_time),
+        ))
+    }
+
+    fn read_replay(&self, replay_name: &str) -> io::Result<Vec<u8>> {
+        fs::read(self.replay_path(replay_name))
+    }
+
+    fn write_replay(&self, replay_name: &str, replay: Vec<u8>) -> io::Result<()> {
+        fs::write(self.replay_path(replay_name), replay)
+    }
+
+    fn delete_replay(&self, replay_name: &str) -> io::Result<()> {
+        fs::remove_file(self.replay_path(replay_name))
+    }
+
+    fn list_replays(&self) -> io::Result<Vec<String>> {
+        let replay_extension = [OsStr::new(REPLAY_EXTENSION)];
+        let paths = list_files(file_paths::replay_dir(&self.app_name), Some(&replay_extension))?;
+        Ok(paths
+            .into_iter()
+            .map(|(path, _)| {
+                path.file_stem()
+                    .unwrap()
+                    .to_os_string()
+                    .into_string()
+                    .expect("Replay file names must be valid unicode")
+            })
+            .collect())
+    }
+
+    /// Deletes the whole game data directory, including logs and mods.
+    fn delete_all(&self) -> io::Result<()> {
+        fs::remove_dir_all(file_paths::game_data_dir(&self.app_name))
+    }
+}
+
+// ---------------------------------------------------------- //
+// -------------------- Browser storage --------------------- //
+// ---------------------------------------------------------- //
+
+/// Keeps configs in the local storage of the browser, and saves and replays in IndexedDB. Default on wasm, and
+/// only works there.
+#[derive(Debug)]
+pub struct BrowserStorage {
+    app_name: String,
+}
+
+impl BrowserStorage {
+    pub fn new(app_name: &str) -> Self {
+        Self {
+            app_name: app_name.to_owned(),
+        }
+    }
+
+    /// Configs in the Ion format keep the key they had before other formats.
+    fn config_storage_key(&self, config_name: &str, format: ConfigFormat) -> String {
+        match format {
+            ConfigFormat::Ion => format!("{}_{}_config", self.app_name, config_name),
+            format => format!("{}_{}.{}_config", self.app_name, config_name, format.extension()),
+        }
+    }
+}
+
+impl StorageBackend for BrowserStorage {
+    fn read_config(&self, config_name: &str, format: ConfigFormat) -> io::Result<String> {
+        file_helpers::read_local_storage(&self.config_storage_key(config_name, format))
+    }
+
+    fn write_config(&self, config_name: &str, format: ConfigFormat, content: &str) -> io::Result<()> {
+        file_helpers::write_local_storage(&self.config_storage_key(config_name, format), content)
+    }
+
+    fn delete_config(&self, config_name: &str, format: ConfigFormat) -> io::Result<()> {
+        file_helpers::delete_local_storage(&self.config_storage_key(config_name, format))
+    }
+
+    fn delete_all_configs(&self) -> io::Result<()> {
+        for key in file_helpers::list_local_storage()? {
+            if key.ends_with("_config") {
+                file_helpers::delete_local_storage(&key)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_save(&self, save_name: &str) -> io::Result<Map<String, Vec<u8>>> {
+        let js_value = file_helpers::read_indexeddb(&self.app_name, "saves", save_name)?;
+        file_helpers::js_object_to_files_map(&js_value)
+    }
+
+    fn read_save_info(&self, save_name: &str) -> io::Result<Vec<u8>> {
+        let js_value = file_helpers::read_indexeddb(&self.app_name, "save_info", save_name)?;
+        file_helpers::js_object_to_files_map(&js_value)?
+            .remove(SAVE_INFO_FILE)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Save info is empty"))
+    }
+
+    fn write_save(&self, save_name: &str, files: Vec<(String, Vec<u8>)>, save_info: Vec<u8>) -> io::Result<()> {
+        // Convert Vec to Map for IndexedDB storage
+        let files_map: Map<String, Vec<u8>> = files.into_iter().collect();
+        let js_object = file_helpers::files_map_to_js_object(&files_map);
+        file_helpers::write_indexeddb(&self.app_name, "saves", save_name, &js_object)?;
+
+        // Kept in a store of its own, so that listing saves doesn't read them
+        let info_map: Map<String, Vec<u8>> = [(SAVE_INFO_FILE.to_string(), save_info)].into_iter().collect();
+        let js_object = file_helpers::files_map_to_js_object(&info_map);
+        file_helpers::write_indexeddb(&self.app_name, "save_info", save_name, &js_object)
+    }
+
+    fn delete_save(&self, save_name: &str) -> io::Result<()> {
+        file_helpers::delete_indexeddb(&self.app_name, "saves", save_name)?;
+        file_helpers::delete_indexeddb(&self.app_name, "save_info", save_name)
+    }
+
+    fn list_saves(&self) -> io::Result<Vec<String>> {
+        file_helpers::list_keys_indexeddb(&self.app_name, "saves")
+    }
+
+    fn read_replay(&self, replay_name: &str) -> io::Result<Vec<u8>> {
+        let js_value = file_helpers::read_indexeddb(&self.app_name, "replays", replay_name)?;
+        file_helpers::js_object_to_files_map(&js_value)?
+            .remove("replay")
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Replay is empty"))
+    }
+
+    fn write_replay(&self, replay_name: &str, replay: Vec<u8>) -> io::Result<()> {
+        let files_map: Map<String, Vec<u8>> = [("replay".to_string(), replay)].into_iter().collect();
+        let js_object = file_helpers::files_map_to_js_object(&files_map);
+        file_helpers::write_indexeddb(&self.app_name, "replays", replay_name, &js_object)
+    }
+
+    fn delete_replay(&self, replay_name: &str) -> io::Result<()> {
+        file_helpers::delete_indexeddb(&self.app_name, "replays", replay_name)
+    }
+
+    fn list_replays(&self) -> io::Result<Vec<String>> {
+        file_helpers::list_keys_indexeddb(&self.app_name, "replays")
+    }
+
+    /// Deletes the whole local storage of the page, and the saves.
+    fn delete_all(&self) -> io::Result<()> {
+        for key in file_helpers::list_local_storage()? {
+            file_helpers::delete_local_storage(&key)?;
+        }
+        file_helpers::clear_store_indexeddb(&self.app_name, "saves")?;
+        file_helpers::clear_store_indexeddb(&self.app_name, "save_info")
+    }
+}
@@ -1,5 +1,5 @@
 use derive_engine::Config;
-use std::{collections::BTreeMap, time::Duration};
+use std::{collections::BTreeMap, ops::RangeInclusive, time::Duration};
 use winit::dpi::PhysicalSize;
 
 // ---------------------------------------------------------- //
@@ -11,9 +11,31 @@ pub struct GfxConfig {
     pub window_decorations: bool,
     pub window_transparent: bool,
     pub window_mode: WindowMode,
+    /// Name of the monitor used by the fullscreen window modes. `None`, or a monitor that is not connected,
+    /// uses the monitor the window is currently on.
+    pub fullscreen_monitor: Option<String>,
     pub frame_resolution: Resolution,
+    /// Renders at a multiple of `frame_resolution`, and downsamples to the window in the final pass.
+    pub supersampling: SupersamplingOpts,
     pub frame_rate_cap: Option<u32>,
     pub vsync: VsyncOpts,
+    /// Camera scale from which on chunks are rendered from pre-baked impostor textures
+    /// instead of their sprites. `None` disables impostors. Not supported on wasm.
+    pub lod_camera_scale: Option<f32>,
+    /// Bakes the shadows of chunked sprites into per-chunk lightmaps, which are rebaked only when the chunk changes.
+    /// Shadow animations of chunked sprites are frozen while enabled. Not supported on wasm.
+    pub baked_shadows: bool,
+    /// Screen space reflections on sprites marked reflective with `Sprite::with_reflectivity`. Not supported on wasm.
+    pub reflections: bool,
+    /// Multisample anti-aliasing of the gbuffer pass.
+    /// Unsupported modes fall back to `Off`, see [`super::renderer::Renderer::available_msaa_modes`].
+    pub msaa: MsaaOpts,
+    /// Anisotropic filtering of the linear render graph samplers.
+    pub anisotropy: AnisotropyOpts,
+    /// Color vision deficiency correction applied in the post pass.
+    pub color_filter: ColorFilterOpts,
+    /// Gamma, brightness and contrast calibration applied in the final pass.
+    pub display: DisplayAdjustments,
 }
 
 impl Default for GfxConfig {
@@ -22,17 +44,35 @@ impl Default for GfxConfig {
             window_decorations: true,
             window_transparent: false,
             window_mode: WindowMode::Windowed,
+            fullscreen_monitor: None,
             frame_resolution: Resolution {
                 width: 800,
                 height: 600,
             },
+            supersampling: SupersamplingOpts::Off,
             frame_rate_cap: Some(60),
             vsync: VsyncOpts::On,
+            lod_camera_scale: None,
+            baked_shadows: false,
+            reflections: false,
+            msaa: MsaaOpts::Off,
+            anisotropy: AnisotropyOpts::Off,
+            color_filter: ColorFilterOpts::Off,
+            display: DisplayAdjustments::default(),
         }
     }
 }
 
 impl GfxConfig {
+    /// Resolution of the render targets, which is `frame_resolution` scaled by supersampling.
+    pub fn render_resolution(&self) -> Resolution {
+        let scale = self.supersampling.scale();
+        Resolution {
+            width: (self.frame_resolution.width as f32 * scale).round() as u32,
+            height: (self.frame_resolution.height as f32 * scale).round() as u32,
+        }
+    }
+
     pub fn frame_time_cap(&self) -> Option<Duration> {
         self.frame_rate_cap
             .map(|frame_rate_cap| Duration::from_nanos(1_000_000_000u64 / frame_rate_cap as u64))
@@ -84,6 +124,116 @@ impl From<wgpu::PresentMode> for VsyncOpts {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Config)]
+pub enum MsaaOpts {
+    Off,
+    X2,
+    X4,
+}
+
+impl MsaaOpts {
+    pub fn sample_count(self) -> u32 {
+        match self {
+            MsaaOpts::Off => 1,
+            MsaaOpts::X2 => 2,
+            MsaaOpts::X4 => 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Config)]
+pub enum SupersamplingOpts {
+    Off,
+    X1_5,
+    X2,
+}
+
+impl SupersamplingOpts {
+    pub fn scale(self) -> f32 {
+        match self {
+            SupersamplingOpts::Off => 1.0,
+            SupersamplingOpts::X1_5 => 1.5,
+            SupersamplingOpts::X2 => 2.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Config)]
+pub enum AnisotropyOpts {
+    Off,
+    X2,
+    X4,
+    X8,
+    X16,
+}
+
+impl AnisotropyOpts {
+    pub fn clamp(self) -> u16 {
+        match self {
+            AnisotropyOpts::Off => 1,
+            AnisotropyOpts::X2 => 2,
+            AnisotropyOpts::X4 => 4,
+            AnisotropyOpts::X8 => 8,
+            AnisotropyOpts::X16 => 16,
+        }
+    }
+}
+
+/// Daltonization filters for color vision deficiencies.
+/// The frame is first simulated as seen with the deficiency, and the lost color information
+/// is then shifted into channels that are still distinguishable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Config)]
+pub enum ColorFilterOpts {
+    Off,
+    Protanopia,
+    Deuteranopia,
+    Tritanopia,
+}
+
+/// User display calibration, applied after tone mapping.
+/// Values outside of the documented ranges are clamped when applied.
+#[derive(Debug, Clone, Copy, PartialEq, Config)]
+pub struct DisplayAdjustments {
+    /// Gamma exponent in range `0.5..=3.0`. Values above 1.0 brighten the mid tones.
+    pub gamma: f32,
+    /// Offset added to all channels in range `-0.5..=0.5`.
+    pub brightness: f32,
+    /// Scale of the distance from mid gray in range `0.5..=2.0`.
+    pub contrast: f32,
+}
+
+impl DisplayAdjustments {
+    pub const GAMMA_RANGE: RangeInclusive<f32> = 0.5..=3.0;
+    pub const BRIGHTNESS_RANGE: RangeInclusive<f32> = -0.5..=0.5;
+    pub const CONTRAST_RANGE: RangeInclusive<f32> = 0.5..=2.0;
+
+    pub fn is_neutral(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn clamped(self) -> Self {
+        Self {
+            gamma: self.gamma.clamp(*Self::GAMMA_RANGE.start(), *Self::GAMMA_RANGE.end()),
+            brightness: self
+                .brightness
+                .clamp(*Self::BRIGHTNESS_RANGE.start(), *Self::BRIGHTNESS_RANGE.end()),
+            contrast: self
+                .contrast
+                .clamp(*Self::CONTRAST_RANGE.start(), *Self::CONTRAST_RANGE.end()),
+        }
+    }
+}
+
+impl Default for DisplayAdjustments {
+    fn default() -> Self {
+        Self {
+            gamma: 1.0,
+            brightness: 0.0,
+            contrast: 1.0,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum WindowMode {
     Windowed,
@@ -112,13 +262,23 @@ impl Config for WindowMode {
             Ok(WindowMode::Windowed)
         } else if value == "BorderlessFullscreen" {
             Ok(WindowMode::BorderlessFullscreen)
-        } else if value.starts_with("ExclusiveFullscreen(") {
-            let parts = value.split(',').collect::<Vec<&str>>();
-            Ok(WindowMode::ExclusiveFullscreen(VideoMode {
-                width: parts[0].parse().unwrap(),
-                height: parts[1].parse().unwrap(),
-                frame_rate: parts[2].parse().unwrap(),
-            }))
+        } else if let Some(params) = value
+            .strip_prefix("ExclusiveFullscreen(")
+            .and_then(|value| value.strip_suffix(')'))
+        {
+            let parts = params
+                .split(',')
+                .map(|part| part.trim().parse())
+                .collect::<Result<Vec<u32>, _>>()
+                .map_err(|_| ConfigParseError::InvalidFieldType(name.to_string()))?;
+            match parts[..] {
+                [width, height, frame_rate] => Ok(WindowMode::ExclusiveFullscreen(VideoMode {
+                    width,
+                    height,
+                    frame_rate,
+                })),
+                _ => Err(ConfigParseError::InvalidFieldType(name.to_string())),
+            }
         } else {
             Err(ConfigParseError::InvalidFieldType(name.to_string()))
         }
@@ -151,6 +311,66 @@ impl From<VideoMode> for PhysicalSize<u32> {
     }
 }
 
+/// Monitor connected to the system, as returned by [`super::renderer::Renderer::available_display_modes`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisplayMonitor {
+    /// Name for [`GfxConfig::fullscreen_monitor`]. `None` if the platform doesn't name monitors.
+    pub name: Option<String>,
+    pub resolution: Resolution,
+    pub frame_rate: Option<u32>,
+    /// Whether the window is currently on this monitor.
+    pub is_current: bool,
+    /// Modes that can be used with [`WindowMode::ExclusiveFullscreen`] on this monitor, largest first.
+    /// Empty on platforms that don't support exclusive fullscreen.
+    pub video_modes: Vec<VideoMode>,
+}
+
+/// Graphics API used by the renderer, see [`crate::core::GfxConstants::backend`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Config)]
+pub enum GfxBackend {
+    /// Primary backend of the platform: Vulkan, DX12 or Metal.
+    Auto,
+    Vulkan,
+    Dx12,
+    Metal,
+    Gl,
+}
+
+impl GfxBackend {
+    pub(crate) fn backends(self) -> wgpu::Backends {
+        match self {
+            GfxBackend::Auto => wgpu::Backends::PRIMARY,
+            GfxBackend::Vulkan => wgpu::Backends::VULKAN,
+            GfxBackend::Dx12 => wgpu::Backends::DX12,
+            GfxBackend::Metal => wgpu::Backends::METAL,
+            GfxBackend::Gl => wgpu::Backends::GL,
+        }
+    }
+}
+
+/// Graphics adapter used by the renderer, see [`crate::core::GfxConstants::adapter`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GfxAdapterSelection {
+    /// High performance adapter that supports the window.
+    Auto,
+    /// First adapter whose name contains the text, ignoring case.
+    Name(String),
+    /// Adapter at the index of [`super::renderer::Renderer::available_adapters`].
+    Index(usize),
+}
+
+/// Graphics adapter of the configured backend, as returned by [`super::renderer::Renderer::available_adapters`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GfxAdapter {
+    /// Index for [`GfxAdapterSelection::Index`].
+    pub index: usize,
+    pub name: String,
+    pub adapter_type: String,
+    pub backend: String,
+    /// Whether the renderer is using this adapter.
+    pub is_current: bool,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Config)]
 pub struct Resolution {
     pub width: u32,
@@ -186,3 +406,59 @@ impl Resolution {
         (self.width / fraction, self.height / fraction)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exclusive_fullscreen_window_mode_survives_encoding() {
+        let window_mode = WindowMode::ExclusiveFullscreen(VideoMode {
+            width: 2560,
+            height: 1440,
+            frame_rate: 144,
+        });
+
+        let mut table = BTreeMap::new();
+        window_mode.encode_kv_table("window_mode", &mut table);
+        assert_eq!(WindowMode::decode_kv_table("window_mode", &table).unwrap(), window_mode);
+
+        table.insert("window_mode".to_string(), "ExclusiveFullscreen(2560,1440)".to_string());
+        assert!(WindowMode::decode_kv_table("window_mode", &table).is_err());
+    }
+
+    #[test]
+    fn display_adjustments_are_clamped_to_ranges() {
+        let display = DisplayAdjustments {
+            gamma: 10.0,
+            brightness: -2.0,
+            contrast: 1.5,
+        }
+        .clamped();
+
+        assert_eq!(display.gamma, 3.0);
+        assert_eq!(display.brightness, -0.5);
+        assert_eq!(display.contrast, 1.5);
+        assert!(DisplayAdjustments::default().clamped().is_neutral());
+    }
+
+    #[test]
+    fn render_resolution_is_scaled_by_supersampling() {
+        let config = GfxConfig {
+            frame_resolution: Resolution {
+                width: 1281,
+                height: 720,
+            },
+            supersampling: SupersamplingOpts::X1_5,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            config.render_resolution(),
+            Resolution {
+                width: 1922,
+                height: 1080,
+            }
+        );
+    }
+}
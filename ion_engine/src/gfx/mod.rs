@@ -7,6 +7,7 @@ use bincode::{Decode, Encode};
 use derive_engine::RawData;
 use ion_common::Map;
 use renderer::gpu_data_types::{InstanceLight, InstanceSprite, LineVertex};
+use skeleton::{BoneTransform, Skeleton};
 use textures::{TextureId, TextureLayout};
 
 use crate::{
@@ -20,6 +21,7 @@ use crate::{
 
 pub mod gfx_config;
 pub mod renderer;
+pub mod skeleton;
 pub mod textures;
 
 // ---------------------------------------------------------- //
@@ -53,6 +55,7 @@ pub struct GfxGlobalData {
     pub frame: FrameId,
     pub camera_loc: Location,
     pub camera_scale: f32,
+    pub camera_follow: CameraFollow,
 
     pub lighting_ambient: f32,
     pub lighting_sun: f32,
@@ -102,6 +105,107 @@ pub struct GfxDebugData {
     pub debug_labels: Vec<(String, Location)>,
 }
 
+// ---------------------------------------------------------- //
+// ------------------------- Camera ------------------------- //
+// ---------------------------------------------------------- //
+
+/// Settings for trauma based camera shake. See [`renderer::Renderer::add_camera_trauma`].
+///
+/// Trauma is a value between 0.0 and 1.0 that decays over time.
+/// The camera is offset by smooth noise scaled by trauma squared, so small amounts of trauma barely shake the camera.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraShake {
+    /// Camera offset in world units at full trauma
+    pub max_offset: f32,
+    /// Speed of the shake, in noise samples per second
+    pub frequency: f32,
+    /// Trauma removed per second
+    pub decay: f32,
+}
+
+impl Default for CameraShake {
+    fn default() -> Self {
+        Self {
+            max_offset: 1.0,
+            frequency: 15.0,
+            decay: 1.0,
+        }
+    }
+}
+
+/// How the rendered view follows the camera location of the universe. Selected every frame in [`GfxGlobalData`].
+///
+/// Follow modes only move the view on the render thread. Sprites with `camera_follow` stay interpolated
+/// with the camera location itself, so a player sprite can move inside the view while the camera lags behind it.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CameraFollow {
+    /// View is locked to the camera location
+    #[default]
+    Lock,
+    /// View approaches the camera location exponentially. Rate is per second.
+    Lerp { rate: f32 },
+    /// View moves only when the camera location leaves a box around the view center. Half extents are in world units.
+    DeadZone { half_width: f32, half_height: f32 },
+    /// View leads the camera location in the direction it moves, by the given number of universe frames of movement.
+    /// Rate is per second, and smooths changes of direction.
+    LookAhead { frames: f32, rate: f32 },
+}
+
+/// Zoom settings of the camera. See [`renderer::Renderer::set_camera_zoom`].
+///
+/// The renderer clamps the camera scale of each frame to the allowed range, and eases the rendered scale towards it.
+/// [`CameraZoom::scroll`] calculates the camera location and scale for scroll input on the universe side.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraZoom {
+    pub min_scale: f32,
+    pub max_scale: f32,
+    /// Scale multiplier per scroll step
+    pub scroll_step: f32,
+    /// Rate of easing towards the camera scale, per second. Zero disables easing.
+    pub easing: f32,
+    /// Whether scrolling keeps the location under the cursor in place, instead of zooming to the camera center
+    pub zoom_to_cursor: bool,
+}
+
+impl Default for CameraZoom {
+    fn default() -> Self {
+        Self {
+            min_scale: 1.0,
+            max_scale: 100.0,
+            scroll_step: 1.15,
+            easing: 15.0,
+            zoom_to_cursor: true,
+        }
+    }
+}
+
+impl CameraZoom {
+    pub fn clamp_scale(&self, scale: f32) -> f32 {
+        scale.clamp(self.min_scale, self.max_scale)
+    }
+
+    /// New camera location and scale after scrolling. Positive scroll delta zooms out.
+    pub fn scroll(
+        &self,
+        camera_loc: Location,
+        camera_scale: f32,
+        scroll_delta: f32,
+        cursor_loc: Location,
+    ) -> (Location, f32) {
+        let scale = self.clamp_scale(camera_scale * self.scroll_step.powf(scroll_delta));
+        if !self.zoom_to_cursor {
+            return (camera_loc, scale);
+        }
+
+        let ratio = scale / camera_scale;
+        let loc = Location {
+            x: cursor_loc.x + (camera_loc.x - cursor_loc.x) * ratio,
+            y: cursor_loc.y + (camera_loc.y - cursor_loc.y) * ratio,
+        };
+        (loc, scale)
+    }
+}
+
 // ---------------------------------------------------------- //
 // ------------------------- Color -------------------------- //
 // ---------------------------------------------------------- //
@@ -186,12 +290,22 @@ impl Mul<f32> for Color {
 /// Contains the necessary information to render sprites, shadows, and lights that form a single graphical "object" like a tree, npc or a ground tile:
 /// - `id`: Id of the sprite bundle. Received from `TextureAssets::include_sprite_bundle`
 /// - `loc`: Location in the world
-/// - `anim_start`: Optional animation start frame. If Some(..) the sprite will play the animation, as if it was started on the given frame. This allows randomizing or setting the animation start time exactly. All animations will loop, unless specifically stopped.
+/// - `anim_start`: Optional animation start frame. If Some(..) the sprite will play the animation, as if it was started on the given frame. This allows randomizing or setting the animation start time exactly.
+/// - `anim_mode`: How the animation is played, see [`AnimMode`]. Animations loop by default.
+/// - `static_frame`: Animation frame that is shown when `anim_start` is None. Allows showing a single frame of an animation sheet.
+/// - `crossfade`: Optional crossfade from another bundle, see [`GfxCrossfade`].
+/// - `skeleton_anim`: Index of the played animation if the bundle is a [`Skeleton`]. Timing follows `anim_start` and `anim_mode`.
+/// - `palette`: Palette used by indexed color sprites of the bundle. Received from `TextureAssets::include_palette`. Defaults to the first palette.
 #[derive(Debug, Clone)]
 pub struct GfxRef {
     pub id: u32,
     pub loc: Location,
     pub anim_start: Option<u32>,
+    pub anim_mode: AnimMode,
+    pub static_frame: u32,
+    pub crossfade: Option<GfxCrossfade>,
+    pub skeleton_anim: u32,
+    pub palette: Option<u32>,
 }
 
 impl GfxRef {
@@ -200,6 +314,11 @@ impl GfxRef {
             id,
             loc,
             anim_start: None,
+            anim_mode: AnimMode::Loop,
+            static_frame: 0,
+            crossfade: None,
+            skeleton_anim: 0,
+            palette: None,
         }
     }
 
@@ -208,8 +327,137 @@ impl GfxRef {
             id,
             loc,
             anim_start: Some(anim_start),
+            anim_mode: AnimMode::Loop,
+            static_frame: 0,
+            crossfade: None,
+            skeleton_anim: 0,
+            palette: None,
+        }
+    }
+
+    pub fn new_anim_with_mode(id: u32, loc: Location, anim_start: u32, anim_mode: AnimMode) -> Self {
+        Self {
+            id,
+            loc,
+            anim_start: Some(anim_start),
+            anim_mode,
+            static_frame: 0,
+            crossfade: None,
+            skeleton_anim: 0,
+            palette: None,
         }
     }
+
+    pub fn new_static_frame(id: u32, loc: Location, static_frame: u32) -> Self {
+        Self {
+            id,
+            loc,
+            anim_start: None,
+            anim_mode: AnimMode::Loop,
+            static_frame,
+            crossfade: None,
+            skeleton_anim: 0,
+            palette: None,
+        }
+    }
+
+    /// Plays an animation of a skeleton bundle. See [`Skeleton::animation_index`].
+    pub fn new_skeleton_anim(id: u32, loc: Location, skeleton_anim: u32, anim_start: u32, anim_mode: AnimMode) -> Self {
+        Self {
+            id,
+            loc,
+            anim_start: Some(anim_start),
+            anim_mode,
+            static_frame: 0,
+            crossfade: None,
+            skeleton_anim,
+            palette: None,
+        }
+    }
+
+    /// Animation time in universe frames, interpolated with the render frame offset.
+    /// Shows the static frame when the animation is not playing.
+    pub(crate) fn anim_time(&self, frame: FrameId, frame_offset: f32) -> f32 {
+        match self.anim_start {
+            Some(start) => (frame as i64 - start as i64) as f32 + frame_offset,
+            None => self.static_frame as f32,
+        }
+    }
+
+    /// Fades this bundle in while fading out another one, for example when switching from a walk to an idle animation.
+    pub fn with_crossfade(mut self, crossfade: GfxCrossfade) -> Self {
+        self.crossfade = Some(crossfade);
+        self
+    }
+
+    /// Renders indexed color sprites of the bundle with the given palette, for example team colors or skin variants.
+    pub fn with_palette(mut self, palette: u32) -> Self {
+        self.palette = Some(palette);
+        self
+    }
+}
+
+/// Crossfade from another gfx bundle to the bundle of a [`GfxRef`].
+///
+/// Both bundles are rendered at the same location for `frames` frames, and the sprites are blended with
+/// ordered dithering in the gbuffer pass. Shadows and lights switch over at the halfway point.
+/// The fade progresses with the universe frame, so it should be used with dynamic gfx;
+/// cached chunk gfx would only be updated when the chunk is rebuilt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GfxCrossfade {
+    /// Id of the bundle that is faded out.
+    pub from_id: u32,
+    /// Animation start of the faded out bundle. See [`GfxRef`].
+    pub from_anim_start: Option<u32>,
+    /// Frame the crossfade started on.
+    pub start_frame: FrameId,
+    /// Length of the crossfade in frames.
+    pub frames: u32,
+}
+
+impl GfxCrossfade {
+    pub fn new(from_id: u32, from_anim_start: Option<u32>, start_frame: FrameId, frames: u32) -> Self {
+        Self {
+            from_id,
+            from_anim_start,
+            start_frame,
+            frames,
+        }
+    }
+
+    /// Progress of the crossfade in range `[0, 1]`.
+    pub fn progress(&self, frame: FrameId) -> f32 {
+        if self.frames == 0 {
+            return 1.0;
+        }
+        (frame.saturating_sub(self.start_frame) as f32 / self.frames as f32).min(1.0)
+    }
+
+    /// Packs the fade for the gbuffer shader: progress in the low 7 bits and the outgoing flag in the high bit.
+    /// Zero means that the sprite is not fading.
+    pub(crate) fn fade_bits(progress: f32, outgoing: bool) -> u32 {
+        let progress_bits = ((progress * 128.0).round() as u32).clamp(1, 127);
+        progress_bits | (outgoing as u32) << 7
+    }
+}
+
+/// Play mode of a sprite animation.
+///
+/// One-shot modes (`OnceHold` and `OnceHide`) count animation frames since `anim_start` with 8 bits on the gpu,
+/// so they replay after 256 animation frames. Replace the [`GfxRef`] with a static one when the animation is done
+/// if it stays on screen longer than that.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Encode, Decode)]
+pub enum AnimMode {
+    /// Animation starts over after the last frame.
+    #[default]
+    Loop = 0,
+    /// Animation is played once and then stays on the last frame.
+    OnceHold = 1,
+    /// Animation is played once and then the sprite is hidden.
+    OnceHide = 2,
+    /// Animation is played forwards and backwards in turns.
+    PingPong = 3,
 }
 
 /// A bundle of sprites, shadows and lights that form a single graphical "object" like a tree, npc or a ground tile.
@@ -219,6 +467,8 @@ pub struct GfxBundle {
     sprites: Vec<Sprite>,
     shadows: Vec<Sprite>,
     lights: Vec<GfxLight>,
+    distortions: Vec<Sprite>,
+    skeleton: Option<Skeleton>,
 }
 
 impl GfxBundle {
@@ -227,6 +477,8 @@ impl GfxBundle {
             sprites,
             shadows: Vec::new(),
             lights: Vec::new(),
+            distortions: Vec::new(),
+            skeleton: None,
         }
     }
 
@@ -235,6 +487,8 @@ impl GfxBundle {
             sprites,
             shadows,
             lights: Vec::new(),
+            distortions: Vec::new(),
+            skeleton: None,
         }
     }
 
@@ -243,6 +497,8 @@ impl GfxBundle {
             sprites,
             shadows: Vec::new(),
             lights,
+            distortions: Vec::new(),
+            skeleton: None,
         }
     }
 
@@ -251,25 +507,86 @@ impl GfxBundle {
             sprites,
             shadows,
             lights,
+            distortions: Vec::new(),
+            skeleton: None,
+        }
+    }
+
+    /// Bundle that is rendered as an animated skeleton. Sprites and shadows are attached to the skeleton slots.
+    pub fn new_skeleton(skeleton: Skeleton) -> Self {
+        Self {
+            sprites: Vec::new(),
+            shadows: Vec::new(),
+            lights: Vec::new(),
+            distortions: Vec::new(),
+            skeleton: Some(skeleton),
         }
     }
 
+    /// Adds distortion sprites that warp the scene behind them, for heat haze, explosions and portals.
+    ///
+    /// The red and green channels of the texture give the direction of the offset, with 0.5 as no offset,
+    /// and alpha gives its strength. At full strength, the scene is offset by up to 8 pixels.
+    /// Distortions move with animated textures. Not supported on wasm.
+    pub fn with_distortions(mut self, distortions: Vec<Sprite>) -> Self {
+        self.distortions = distortions;
+        self
+    }
+
+    /// Extracts instances of the bundle. `fade` is passed to the sprites, see [`GfxCrossfade::fade_bits`].
+    /// Shadows, lights and distortions are left out when `shadows_and_lights` is false.
+    /// Skeletons are posed at `anim_time`, see [`GfxRef::anim_time`].
+    #[allow(clippy::too_many_arguments)]
     fn extract_for_render(
         &self,
         render_camera: Option<&RenderCamera>,
         gfx_ref: &GfxRef,
+        anim_time: f32,
+        fade: u32,
+        shadows_and_lights: bool,
         sprites: &mut Vec<(InstanceSprite, TextureLayout, u8)>,
         shadows: &mut Vec<(InstanceSprite, TextureLayout)>,
         lights: &mut Vec<(InstanceLight, TextureLayout)>,
+        distortions: &mut Vec<(InstanceSprite, TextureLayout)>,
     ) {
+        if let Some(skeleton) = &self.skeleton {
+            let mut pose = Vec::with_capacity(skeleton.bones().len());
+            if !skeleton.pose(gfx_ref.skeleton_anim, anim_time, gfx_ref.anim_mode, &mut pose) {
+                return;
+            }
+
+            for slot in &skeleton.slots {
+                let mut instance = slot.sprite.as_posed_instance(render_camera, gfx_ref, &pose[slot.bone]);
+                let layout = slot
+                    .sprite
+                    .texture_id
+                    .map(|id| id.layout)
+                    .unwrap_or(TextureLayout::Square);
+                if slot.shadow {
+                    if shadows_and_lights {
+                        shadows.push((instance, layout));
+                    }
+                } else {
+                    instance.tex_sheet_indices |= fade << 24;
+                    sprites.push((instance, layout, slot.sprite.layer));
+                }
+            }
+        }
+
         for sprite in &self.sprites {
+            let mut instance = sprite.as_instance(render_camera, gfx_ref);
+            instance.tex_sheet_indices |= fade << 24;
             sprites.push((
-                sprite.as_instance(render_camera, gfx_ref),
+                instance,
                 sprite.texture_id.map(|id| id.layout).unwrap_or(TextureLayout::Square),
                 sprite.layer,
             ));
         }
 
+        if !shadows_and_lights {
+            return;
+        }
+
         for shadow in &self.shadows {
             shadows.push((
                 shadow.as_instance(render_camera, gfx_ref),
@@ -287,6 +604,16 @@ impl GfxBundle {
                     .unwrap_or(TextureLayout::Square),
             ));
         }
+
+        for distortion in &self.distortions {
+            distortions.push((
+                distortion.as_instance(render_camera, gfx_ref),
+                distortion
+                    .texture_id
+                    .map(|id| id.layout)
+                    .unwrap_or(TextureLayout::Square),
+            ));
+        }
     }
 }
 
@@ -327,6 +654,7 @@ pub enum SpriteTypeId {
     Normal = 10,
     Shadow = 20,
     Light = 30,
+    Distortion = 40,
 }
 
 impl SpriteTypeId {
@@ -335,6 +663,43 @@ impl SpriteTypeId {
     }
 }
 
+/// 9-slice definition of a [`Sprite`], in texture pixels.
+///
+/// The sprite is drawn with the given target size instead of the texture size. Corners defined by the insets
+/// keep their size, edges stretch along one axis and the center stretches along both axes.
+/// Useful for panels, walls and bars that need to be drawn in many sizes from a single texture.
+/// Slicing is done in the gbuffer and shadow shaders, so a 9-slice sprite is still a single instance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NineSlice {
+    pub width: u16,
+    pub height: u16,
+    pub left: u8,
+    pub right: u8,
+    pub top: u8,
+    pub bottom: u8,
+}
+
+impl NineSlice {
+    /// 9-slice with the same inset on every side.
+    pub fn new(width: u16, height: u16, inset: u8) -> Self {
+        Self {
+            width,
+            height,
+            left: inset,
+            right: inset,
+            top: inset,
+            bottom: inset,
+        }
+    }
+
+    fn pack(&self) -> [u32; 2] {
+        [
+            self.width.max(1) as u32 | (self.height.max(1) as u32) << 16,
+            self.left as u32 | (self.right as u32) << 8 | (self.top as u32) << 16 | (self.bottom as u32) << 24,
+        ]
+    }
+}
+
 /// A single renderable sprite texture
 #[derive(Debug, Clone)]
 pub struct Sprite {
@@ -350,6 +715,9 @@ pub struct Sprite {
     layer: u8,
     anim_fps: u32,
     camera_follow: bool,
+    nine_slice: Option<NineSlice>,
+    indexed_color: bool,
+    reflectivity: f32,
 }
 
 impl Sprite {
@@ -377,17 +745,73 @@ impl Sprite {
             layer,
             anim_fps: anim_fps.unwrap_or(0),
             camera_follow,
+            nine_slice: None,
+            indexed_color: false,
+            reflectivity: 0.0,
         }
     }
 
+    /// Renders the sprite as a 9-slice, see [`NineSlice`].
+    pub fn with_nine_slice(mut self, nine_slice: NineSlice) -> Self {
+        self.nine_slice = Some(nine_slice);
+        self
+    }
+
+    /// Renders the sprite with a palette selected by the [`GfxRef`].
+    /// Red channel of the color texture is the palette index, and alpha is used as is.
+    /// Indices are read without filtering, so indexed sprites should not be scaled down.
+    pub fn with_indexed_color(mut self) -> Self {
+        self.indexed_color = true;
+        self
+    }
+
+    /// Marks the sprite as a reflective surface, like wet ground, ice or a polished floor.
+    /// Reflectivity between 0.0 and 1.0 is how strongly sprites standing on it are mirrored.
+    /// Only has an effect when reflections are enabled in [`gfx_config::GfxConfig`].
+    pub fn with_reflectivity(mut self, reflectivity: f32) -> Self {
+        self.reflectivity = reflectivity.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Packs animation data to four bytes: flags (on bit and play mode), frame rate, start and frame count.
+    /// When the animation is off, the start byte holds the static frame instead.
+    /// One-shot modes store the start as a negative phase, so that the shader can count frames since the start.
+    fn pack_anim_data(gfx_ref: &GfxRef, anim_frame_rate: u32, anim_frame_count: u32) -> u32 {
+        let (anim_flags, anim_frame_start) = match gfx_ref.anim_start {
+            Some(start) => {
+                let start = match gfx_ref.anim_mode {
+                    AnimMode::OnceHold | AnimMode::OnceHide => (256 - (start / anim_frame_rate.max(1)) % 256) % 256,
+                    AnimMode::Loop | AnimMode::PingPong => start % 256,
+                };
+                (1 | (gfx_ref.anim_mode as u32) << 1, start)
+            }
+            None => (0, gfx_ref.static_frame.min(anim_frame_count - 1)),
+        };
+
+        anim_flags | (anim_frame_rate << 8) | (anim_frame_start << 16) | (anim_frame_count << 24)
+    }
+
+    /// Instance of a sprite attached to a skeleton bone. Sprite transform is relative to the bone.
+    fn as_posed_instance(
+        &self,
+        render_camera: Option<&RenderCamera>,
+        gfx_ref: &GfxRef,
+        bone: &BoneTransform,
+    ) -> InstanceSprite {
+        let mut instance = self.as_instance(render_camera, gfx_ref);
+        let posed_loc = bone.apply(self.loc);
+        instance.loc[0] += posed_loc.x - self.loc.x;
+        instance.loc[1] += posed_loc.y - self.loc.y;
+        instance.rot += bone.rot;
+        instance.scale *= bone.scale;
+        instance
+    }
+
     fn as_instance(&self, render_camera: Option<&RenderCamera>, gfx_ref: &GfxRef) -> InstanceSprite {
         debug_assert!(self.texture_id.is_some(), "Sprite has no texture id");
 
-        let anim_on: u32 = if gfx_ref.anim_start.is_some() { 1 } else { 0 };
-        let anim_frame_rate: u32 = self.anim_fps;
-        let anim_frame_start: u32 = gfx_ref.anim_start.unwrap_or(0);
         let anim_frame_count: u32 = self.texture_id.map(|id| id.frame_count as u32).unwrap_or(1);
-        let anim_data: u32 = anim_on | (anim_frame_rate << 8) | (anim_frame_start << 16) | (anim_frame_count << 24);
+        let anim_data = Self::pack_anim_data(gfx_ref, self.anim_fps, anim_frame_count);
 
         let offset_x = if self.camera_follow && render_camera.is_some() {
             render_camera.unwrap().interpolation_x()
@@ -414,15 +838,22 @@ impl Sprite {
             tex_coords_sizes: self.texture_id.map(|id| id.tex_coords_sizes).unwrap_or([1.0, 1.0]),
             tex_sheet_indices: self
                 .texture_id
-                .map(|id| id.tex_sheet_indices[0] | id.tex_sheet_indices[1] << 8)
+                .map(|id| (id.tex_sheet_indices[0] & 0xFF) | (id.tex_sheet_indices[1] & 0xFF) << 8)
                 .unwrap_or(0)
                 | self
                     .texture_mask_id
-                    .map(|mask_id| mask_id.tex_sheet_indices[0])
+                    .map(|mask_id| mask_id.tex_sheet_indices[0] & 0xFF)
                     .unwrap_or(255)
                     << 16
                 | 0 << 24,
             type_id: self.type_id.as_normalized_f32(),
+            nine_slice: self.nine_slice.map(|nine_slice| nine_slice.pack()).unwrap_or([0, 0]),
+            palette: if self.indexed_color {
+                gfx_ref.palette.unwrap_or(0) + 1
+            } else {
+                0
+            },
+            reflectivity: self.reflectivity,
         }
     }
 }
@@ -564,3 +995,234 @@ impl DebugShape for DebugArrow {
         vertices
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn anim_data_packs_modes_and_static_frames() {
+        let loc = Location { x: 0.0, y: 0.0 };
+
+        let looping = GfxRef::new_anim(0, loc, 300);
+        assert_eq!(Sprite::pack_anim_data(&looping, 4, 8), 1 | 4 << 8 | 44 << 16 | 8 << 24);
+
+        let once = GfxRef::new_anim_with_mode(0, loc, 40, AnimMode::OnceHide);
+        assert_eq!(
+            Sprite::pack_anim_data(&once, 4, 8),
+            (1 | 2 << 1) | 4 << 8 | 246 << 16 | 8 << 24
+        );
+
+        let ping_pong = GfxRef::new_anim_with_mode(0, loc, 0, AnimMode::PingPong);
+        assert_eq!(Sprite::pack_anim_data(&ping_pong, 4, 8) & 0xFF, 1 | 3 << 1);
+
+        let frame = GfxRef::new_static_frame(0, loc, 5);
+        assert_eq!(Sprite::pack_anim_data(&frame, 4, 8), 4 << 8 | 5 << 16 | 8 << 24);
+        let frame = GfxRef::new_static_frame(0, loc, 20);
+        assert_eq!(Sprite::pack_anim_data(&frame, 4, 8) >> 16 & 0xFF, 7);
+    }
+
+    #[test]
+    fn distortions_are_extracted_with_shadows_and_lights() {
+        let sprite = |type_id| {
+            let mut sprite = Sprite::new(
+                type_id,
+                "tex".to_string(),
+                None,
+                Location { x: 1.0, y: 2.0 },
+                0.0,
+                1.0,
+                0,
+                None,
+                false,
+            );
+            sprite.texture_id = Some(TextureId {
+                tex_coords: [0.0, 0.0],
+                tex_coords_sizes: [0.1, 0.1],
+                tex_sheet_indices: [0, 0],
+                layout: TextureLayout::Square,
+                frame_count: 1,
+            });
+            sprite
+        };
+        let bundle =
+            GfxBundle::new(vec![sprite(SpriteTypeId::Normal)]).with_distortions(vec![sprite(SpriteTypeId::Distortion)]);
+        let gfx_ref = GfxRef::new(0, Location { x: 10.0, y: 20.0 });
+
+        let mut sprites = Vec::new();
+        let mut shadows = Vec::new();
+        let mut lights = Vec::new();
+        let mut distortions = Vec::new();
+        for shadows_and_lights in [true, false] {
+            bundle.extract_for_render(
+                None,
+                &gfx_ref,
+                0.0,
+                0,
+                shadows_and_lights,
+                &mut sprites,
+                &mut shadows,
+                &mut lights,
+                &mut distortions,
+            );
+        }
+
+        assert_eq!(sprites.len(), 2);
+        assert_eq!(distortions.len(), 1);
+        assert_eq!(distortions[0].0.loc, [11.0, 22.0]);
+        assert_eq!(distortions[0].0.type_id, SpriteTypeId::Distortion.as_normalized_f32());
+    }
+
+    #[test]
+    fn sprites_without_normal_maps_leave_crossfade_byte_empty() {
+        let mut sprite = Sprite::new(
+            SpriteTypeId::Normal,
+            "tex".to_string(),
+            None,
+            Location { x: 0.0, y: 0.0 },
+            0.0,
+            1.0,
+            0,
+            None,
+            false,
+        );
+        sprite.texture_id = Some(TextureId {
+            tex_coords: [0.0, 0.0],
+            tex_coords_sizes: [0.1, 0.1],
+            tex_sheet_indices: [3, u32::MAX],
+            layout: TextureLayout::Square,
+            frame_count: 1,
+        });
+        let bundle = GfxBundle::new(vec![sprite]);
+        let gfx_ref = GfxRef::new(0, Location { x: 0.0, y: 0.0 });
+
+        let mut sprites = Vec::new();
+        bundle.extract_for_render(
+            None,
+            &gfx_ref,
+            0.0,
+            0,
+            false,
+            &mut sprites,
+            &mut Vec::new(),
+            &mut Vec::new(),
+            &mut Vec::new(),
+        );
+
+        let tex_sheet_indices = sprites[0].0.tex_sheet_indices;
+        assert_eq!(tex_sheet_indices >> 24, 0);
+        assert_eq!(tex_sheet_indices & 0xFF, 3);
+        assert_eq!(tex_sheet_indices >> 8 & 0xFF, 255);
+    }
+
+    #[test]
+    fn indexed_sprites_use_palette_of_gfx_ref() {
+        let mut sprite = Sprite::new(
+            SpriteTypeId::Normal,
+            "tex".to_string(),
+            None,
+            Location { x: 0.0, y: 0.0 },
+            0.0,
+            1.0,
+            0,
+            None,
+            false,
+        );
+        sprite.texture_id = Some(TextureId {
+            tex_coords: [0.0, 0.0],
+            tex_coords_sizes: [0.1, 0.1],
+            tex_sheet_indices: [0, 0],
+            layout: TextureLayout::Square,
+            frame_count: 1,
+        });
+        let gfx_ref = GfxRef::new(0, Location { x: 0.0, y: 0.0 });
+
+        assert_eq!(sprite.as_instance(None, &gfx_ref).palette, 0);
+
+        let sprite = sprite.with_indexed_color();
+        assert_eq!(sprite.as_instance(None, &gfx_ref).palette, 1);
+        assert_eq!(sprite.as_instance(None, &gfx_ref.with_palette(3)).palette, 4);
+    }
+
+    #[test]
+    fn reflectivity_is_clamped_and_passed_to_instance() {
+        let mut sprite = Sprite::new(
+            SpriteTypeId::Normal,
+            "tex".to_string(),
+            None,
+            Location { x: 0.0, y: 0.0 },
+            0.0,
+            1.0,
+            0,
+            None,
+            false,
+        )
+        .with_reflectivity(1.5);
+        sprite.texture_id = Some(TextureId {
+            tex_coords: [0.0, 0.0],
+            tex_coords_sizes: [0.1, 0.1],
+            tex_sheet_indices: [0, 0],
+            layout: TextureLayout::Square,
+            frame_count: 1,
+        });
+        let gfx_ref = GfxRef::new(0, Location { x: 0.0, y: 0.0 });
+
+        assert_eq!(sprite.as_instance(None, &gfx_ref).reflectivity, 1.0);
+    }
+
+    #[test]
+    fn nine_slice_packs_size_and_insets() {
+        let nine_slice = NineSlice {
+            width: 300,
+            height: 40,
+            left: 4,
+            right: 5,
+            top: 6,
+            bottom: 7,
+        };
+        assert_eq!(nine_slice.pack(), [300 | 40 << 16, 4 | 5 << 8 | 6 << 16 | 7 << 24]);
+        assert_eq!(NineSlice::new(0, 0, 2).pack()[0], 1 | 1 << 16);
+    }
+
+    #[test]
+    fn crossfade_progress_and_fade_bits() {
+        let crossfade = GfxCrossfade::new(1, None, 100, 10);
+        assert_eq!(crossfade.progress(90), 0.0);
+        assert_eq!(crossfade.progress(105), 0.5);
+        assert_eq!(crossfade.progress(200), 1.0);
+        assert_eq!(GfxCrossfade::new(1, None, 100, 0).progress(100), 1.0);
+
+        assert_eq!(GfxCrossfade::fade_bits(0.0, false), 1);
+        assert_eq!(GfxCrossfade::fade_bits(0.5, false), 64);
+        assert_eq!(GfxCrossfade::fade_bits(0.5, true), 64 | 0x80);
+        assert_eq!(GfxCrossfade::fade_bits(1.0, true), 127 | 0x80);
+    }
+
+    #[test]
+    fn scroll_zoom_keeps_cursor_in_place() {
+        let zoom = CameraZoom {
+            min_scale: 5.0,
+            max_scale: 20.0,
+            scroll_step: 2.0,
+            easing: 0.0,
+            zoom_to_cursor: true,
+        };
+
+        let (loc, scale) = zoom.scroll(Location::new(0.0, 0.0), 10.0, -1.0, Location::new(4.0, 2.0));
+        assert_eq!(scale, 5.0);
+        assert_eq!(loc, Location::new(2.0, 1.0));
+
+        let (loc, scale) = zoom.scroll(Location::new(0.0, 0.0), 10.0, 2.0, Location::new(4.0, 2.0));
+        assert_eq!(scale, 20.0);
+        assert_eq!(loc, Location::new(-4.0, -2.0));
+
+        let no_cursor = CameraZoom {
+            zoom_to_cursor: false,
+            ..zoom
+        };
+        assert_eq!(
+            no_cursor.scroll(Location::new(1.0, 1.0), 10.0, 1.0, Location::new(4.0, 2.0)),
+            (Location::new(1.0, 1.0), 20.0)
+        );
+    }
+}
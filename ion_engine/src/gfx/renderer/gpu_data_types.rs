@@ -1,4 +1,4 @@
-use std::mem;
+use std::{borrow::Cow, mem};
 
 use derive_engine::RawData;
 use wgpu::vertex_attr_array;
@@ -9,20 +9,79 @@ use crate::{gfx::textures::TextureLayout, util::casting::RawData};
 // ----------------------- GPU shaders ---------------------- //
 // ---------------------------------------------------------- //
 
-pub const SHADER_SCALE: &str = include_str!("shaders/shader_scale.wgsl");
-pub const SHADER_DEBUG: &str = include_str!("shaders/shader_line.wgsl");
-pub const SHADER_GBUF: &str = include_str!("shaders/shader_gbuf.wgsl");
-pub const SHADER_GBUF_WASM: &str = include_str!("shaders/shader_gbuf_wasm.wgsl");
-pub const SHADER_LIGHT: &str = include_str!("shaders/shader_light.wgsl");
-pub const SHADER_LIGHT_WASM: &str = include_str!("shaders/shader_light_wasm.wgsl");
-pub const SHADER_SHADOW: &str = include_str!("shaders/shader_shadow.wgsl");
-pub const SHADER_SHADOW_WASM: &str = include_str!("shaders/shader_shadow_wasm.wgsl");
-pub const SHADER_POST_1: &str = include_str!("shaders/shader_post_1.wgsl");
-pub const SHADER_POST_2: &str = include_str!("shaders/shader_post_2.wgsl");
-pub const SHADER_BLOOM_DS: &str = include_str!("shaders/shader_bloom_ds.wgsl");
-pub const SHADER_BLOOM_US: &str = include_str!("shaders/shader_bloom_us.wgsl");
-pub const SHADER_SSAO_RAW: &str = include_str!("shaders/shader_ssao_raw.wgsl");
-pub const SHADER_SSAO_BLUR: &str = include_str!("shaders/shader_ssao_blur.wgsl");
+/// WGSL shader embedded in the binary.
+/// In native debug builds, the shader is read from the shader directory when it exists, so it can be hot-reloaded.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ShaderSource {
+    #[cfg_attr(not(all(debug_assertions, not(target_arch = "wasm32"))), allow(dead_code))]
+    pub file_name: &'static str,
+    pub embedded: &'static str,
+}
+
+impl ShaderSource {
+    pub fn load(&self) -> Cow<'static, str> {
+        #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+        if let Some(source) = super::shader_hot_reload::read_shader(self.file_name) {
+            return Cow::Owned(source);
+        }
+
+        Cow::Borrowed(self.embedded)
+    }
+}
+
+macro_rules! shader_source {
+    ($file_name:literal) => {
+        ShaderSource {
+            file_name: $file_name,
+            embedded: include_str!(concat!("shaders/", $file_name)),
+        }
+    };
+}
+
+pub(crate) const SHADER_SCALE: ShaderSource = shader_source!("shader_scale.wgsl");
+pub(crate) const SHADER_DEBUG: ShaderSource = shader_source!("shader_line.wgsl");
+pub(crate) const SHADER_GBUF: ShaderSource = shader_source!("shader_gbuf.wgsl");
+pub(crate) const SHADER_GBUF_WASM: ShaderSource = shader_source!("shader_gbuf_wasm.wgsl");
+pub(crate) const SHADER_LIGHT: ShaderSource = shader_source!("shader_light.wgsl");
+pub(crate) const SHADER_LIGHT_WASM: ShaderSource = shader_source!("shader_light_wasm.wgsl");
+pub(crate) const SHADER_SHADOW: ShaderSource = shader_source!("shader_shadow.wgsl");
+pub(crate) const SHADER_SHADOW_WASM: ShaderSource = shader_source!("shader_shadow_wasm.wgsl");
+pub(crate) const SHADER_SHADOW_LIGHTMAP: ShaderSource = shader_source!("shader_shadow_lightmap.wgsl");
+pub(crate) const SHADER_DISTORTION: ShaderSource = shader_source!("shader_distortion.wgsl");
+pub(crate) const SHADER_SSR: ShaderSource = shader_source!("shader_ssr.wgsl");
+pub(crate) const SHADER_POST_1: ShaderSource = shader_source!("shader_post_1.wgsl");
+pub(crate) const SHADER_POST_2: ShaderSource = shader_source!("shader_post_2.wgsl");
+pub(crate) const SHADER_BLOOM_DS: ShaderSource = shader_source!("shader_bloom_ds.wgsl");
+pub(crate) const SHADER_BLOOM_US: ShaderSource = shader_source!("shader_bloom_us.wgsl");
+pub(crate) const SHADER_SSAO_RAW: ShaderSource = shader_source!("shader_ssao_raw.wgsl");
+pub(crate) const SHADER_SSAO_BLUR: ShaderSource = shader_source!("shader_ssao_blur.wgsl");
+pub(crate) const SHADER_IMPOSTOR: ShaderSource = shader_source!("shader_impostor.wgsl");
+pub(crate) const SHADER_MIPMAP: ShaderSource = shader_source!("shader_mipmap.wgsl");
+pub(crate) const SHADER_DEPTH_RESOLVE: ShaderSource = shader_source!("shader_depth_resolve.wgsl");
+
+#[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+pub(crate) const ALL_SHADERS: &[ShaderSource] = &[
+    SHADER_SCALE,
+    SHADER_DEBUG,
+    SHADER_GBUF,
+    SHADER_GBUF_WASM,
+    SHADER_LIGHT,
+    SHADER_LIGHT_WASM,
+    SHADER_SHADOW,
+    SHADER_SHADOW_WASM,
+    SHADER_SHADOW_LIGHTMAP,
+    SHADER_DISTORTION,
+    SHADER_SSR,
+    SHADER_POST_1,
+    SHADER_POST_2,
+    SHADER_BLOOM_DS,
+    SHADER_BLOOM_US,
+    SHADER_SSAO_RAW,
+    SHADER_SSAO_BLUR,
+    SHADER_IMPOSTOR,
+    SHADER_MIPMAP,
+    SHADER_DEPTH_RESOLVE,
+];
 
 // ---------------------------------------------------------- //
 // --------------- GPU-supported data models ---------------- //
@@ -80,6 +139,30 @@ impl LineVertex {
     }
 }
 
+#[repr(C)]
+#[derive(Debug, Clone, Copy, RawData)]
+pub(crate) struct ImpostorVertex {
+    /// Location of the vertex in world coordinates
+    pub world_loc: [f32; 2],
+    /// Texture coordinates in the baked impostor textures
+    pub tex_coords: [f32; 2],
+}
+
+const IMPOSTOR_VERTEX_ATTRIBUTES: [wgpu::VertexAttribute; 2] = vertex_attr_array![
+    0 => Float32x2,
+    1 => Float32x2,
+];
+
+impl ImpostorVertex {
+    pub fn buffer_layout<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<ImpostorVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &IMPOSTOR_VERTEX_ATTRIBUTES,
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy, RawData)]
 pub(crate) struct InstanceSprite {
@@ -104,9 +187,17 @@ pub(crate) struct InstanceSprite {
     /// Type id of the sprite
     /// Id between 0.0 and 1.0, so that it can be stored as color
     pub type_id: f32,
+    /// 9-slice target size (two u16 values) and insets (four u8 values) in pixels.
+    /// Zero if the sprite is not sliced.
+    pub nine_slice: [u32; 2],
+    /// Palette row of an indexed color sprite plus one.
+    /// Zero if the sprite is not indexed.
+    pub palette: u32,
+    /// How strongly the sprite reflects other sprites, see `Sprite::with_reflectivity`
+    pub reflectivity: f32,
 }
 
-const INSTANCE_SPRITE_ATTRIBUTES: [wgpu::VertexAttribute; 9] = vertex_attr_array![
+const INSTANCE_SPRITE_ATTRIBUTES: [wgpu::VertexAttribute; 12] = vertex_attr_array![
     6 => Float32x2,
     7 => Float32,
     8 => Float32,
@@ -116,6 +207,9 @@ const INSTANCE_SPRITE_ATTRIBUTES: [wgpu::VertexAttribute; 9] = vertex_attr_array
     12 => Float32x2,
     13 => Uint32,
     14 => Float32,
+    15 => Uint32x2,
+    5 => Uint32,
+    4 => Float32,
 ];
 
 impl InstanceSprite {
@@ -173,6 +267,23 @@ impl InstanceLight {
     }
 }
 
+/// Arguments for a single indexed draw in an indirect buffer.
+/// Layout must match `wgpu::util::DrawIndexedIndirectArgs`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, RawData)]
+pub(crate) struct GpuDrawIndexedIndirect {
+    /// Number of indices to draw
+    pub index_count: u32,
+    /// Number of instances to draw
+    pub instance_count: u32,
+    /// First index within the index buffer
+    pub first_index: u32,
+    /// Value added to the vertex index before indexing into the vertex buffer
+    pub base_vertex: i32,
+    /// Instance index of the first instance to draw
+    pub first_instance: u32,
+}
+
 // ---------------------------------------------------------- //
 // --------------- Vertex geometry generators --------------- //
 // ---------------------------------------------------------- //
@@ -1,48 +1,85 @@
-use std::{iter, sync::Arc};
+use std::{
+    iter,
+    path::Path,
+    sync::{Arc, mpsc::Sender},
+};
 
-use ion_common::log_info;
+use ion_common::{Map, log_info, log_warn};
+use offscreen_target::{OFFSCREEN_FORMAT, OffscreenTarget};
 use render_camera::RenderCamera;
 use render_globals::RenderGlobals;
-use render_graph::RenderGraph;
+use render_graph::{INDIRECT_DRAW_FEATURES, RenderGraph};
+use secondary_window::SecondaryWindow;
 
 use winit::{
     dpi::{PhysicalPosition, PhysicalSize},
     event::WindowEvent,
     event_loop::ActiveEventLoop,
-    window::Fullscreen,
+    window::{CursorGrabMode, CursorIcon, CustomCursor, CustomCursorSource, Fullscreen, Icon},
 };
 
 #[cfg(target_os = "macos")]
 use winit::platform::macos::WindowExtMacOS;
+#[cfg(target_os = "windows")]
+use winit::platform::windows::WindowExtWindows;
 
 use crate::{
     core::Constants,
+    files::{
+        asset_error::{AssetDiagnostics, AssetError},
+        file_helpers::load_resource,
+    },
     gfx::{GfxFrameMode, WASM_COMPATIBLE_RENDERING, renderer::render_ui::RenderUi},
-    util::concurrency::block_on,
+    input::CursorGrab,
+    util::{concurrency::block_on, system_info::SystemInfo},
 };
 
 use super::{
-    GfxFrameData,
-    gfx_config::{GfxConfig, Resolution, VsyncOpts, WindowMode},
-    textures::{texture_assets::TextureAssets, texture_loader::TextureLoader},
+    CameraShake, CameraZoom, GfxBundle, GfxFrameData,
+    gfx_config::{
+        DisplayAdjustments, DisplayMonitor, GfxAdapter, GfxAdapterSelection, GfxConfig, MsaaOpts, Resolution,
+        SupersamplingOpts, VideoMode, VsyncOpts, WindowMode,
+    },
+    textures::{
+        texture_assets::TextureAssets,
+        texture_atlas::{AtlasInsertError, RuntimeTexture},
+        texture_loader::TextureLoader,
+    },
 };
 
 pub(crate) mod gpu_data_types;
+pub(crate) mod offscreen_target;
 pub(crate) mod render_camera;
 pub(crate) mod render_globals;
 pub(crate) mod render_graph;
 pub(crate) mod render_helpers;
 pub(crate) mod render_ui;
+pub(crate) mod secondary_window;
+#[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+pub(crate) mod shader_hot_reload;
+
+pub use secondary_window::SecondaryWindowId;
+
+/// Where the renderer presents its frames.
+enum RenderOutput<'a> {
+    Window(Arc<winit::window::Window>, &'a ActiveEventLoop),
+    Offscreen(Resolution),
+}
 
 pub struct Renderer {
-    pub(crate) window: Arc<winit::window::Window>,
+    /// `None` for offscreen renderers.
+    pub(crate) window: Option<Arc<winit::window::Window>>,
 
+    instance: wgpu::Instance,
     #[allow(dead_code)]
     pub(crate) adapter: wgpu::Adapter,
-    pub(crate) surface: wgpu::Surface<'static>,
+    pub(crate) surface: Option<wgpu::Surface<'static>>,
+    offscreen_target: Option<OffscreenTarget>,
     pub(crate) device: wgpu::Device,
     pub(crate) queue: wgpu::Queue,
 
+    system_info: SystemInfo,
+
     surface_ready: bool,
     surface_config: wgpu::SurfaceConfiguration,
     surface_capabilities: wgpu::SurfaceCapabilities,
@@ -53,32 +90,77 @@ pub struct Renderer {
     constants: Constants,
     texture_loader: Option<TextureLoader>,
     texture_assets: Option<TextureAssets>,
+    asset_diagnostics: AssetDiagnostics,
+    #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+    shader_watcher: shader_hot_reload::ShaderWatcher,
 
     render_camera: RenderCamera,
     render_globals: RenderGlobals,
     render_graph: RenderGraph,
     render_ui: RenderUi,
+
+    secondary_windows: Map<SecondaryWindowId, SecondaryWindow>,
+    pending_secondary_windows: Vec<(SecondaryWindowId, String, Resolution)>,
+    next_secondary_window_id: u32,
+
+    pending_cursor: Option<CustomCursorSource>,
+    custom_cursor: Option<CustomCursor>,
+    ui_cursor_active: bool,
+    frame_cursor_icon: Option<egui::CursorIcon>,
 }
 
 impl Renderer {
-    pub fn new(constants: &Constants, window: winit::window::Window, event_loop: &ActiveEventLoop) -> Self {
-        let window = Arc::new(window);
+    pub fn new(
+        constants: &Constants,
+        window: winit::window::Window,
+        event_loop: &ActiveEventLoop,
+        asset_error_sender: Sender<AssetError>,
+    ) -> Self {
+        Self::create(
+            constants,
+            RenderOutput::Window(Arc::new(window), event_loop),
+            asset_error_sender,
+        )
+    }
+
+    /// Creates a renderer without a window, that renders into an offscreen target of the given resolution.
+    /// Frames are rendered and read back with [`Self::render_offscreen`], for example for screenshot tests,
+    /// thumbnails or map previews. Window related settings and methods have no effect. Not supported on wasm.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn new_offscreen(
+        constants: &Constants,
+        resolution: Resolution,
+        asset_error_sender: Sender<AssetError>,
+    ) -> Self {
+        let mut renderer = Self::create(constants, RenderOutput::Offscreen(resolution), asset_error_sender);
+        renderer.set_config(GfxConfig {
+            frame_resolution: resolution,
+            vsync: VsyncOpts::Off,
+            ..GfxConfig::default()
+        });
+        renderer
+    }
+
+    fn create(constants: &Constants, output: RenderOutput, asset_error_sender: Sender<AssetError>) -> Self {
+        let (window, event_loop, offscreen_resolution) = match output {
+            RenderOutput::Window(window, event_loop) => (Some(window), Some(event_loop), None),
+            RenderOutput::Offscreen(resolution) => (None, None, Some(resolution)),
+        };
+
         let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
-            #[cfg(not(target_arch = "wasm32"))]
-            backends: wgpu::Backends::PRIMARY,
-            #[cfg(target_arch = "wasm32")]
-            backends: wgpu::Backends::GL,
+            backends: Self::backends(constants),
             ..Default::default()
         });
 
-        let surface = instance.create_surface(window.clone()).unwrap();
+        let surface = window
+            .as_ref()
+            .map(|window| instance.create_surface(window.clone()).unwrap());
 
-        let adapter = block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
-            power_preference: wgpu::PowerPreference::HighPerformance,
-            compatible_surface: Some(&surface),
-            force_fallback_adapter: false,
-        }))
-        .unwrap();
+        let adapter = Self::select_adapter(&instance, surface.as_ref(), constants);
+
+        let system_info = SystemInfo::collect(&adapter.get_info());
+        system_info.set_as_crash_report_info();
+        log_info!("System info:\n{}", system_info);
 
         let features = if cfg!(target_arch = "wasm32") {
             wgpu::Features::default()
@@ -87,6 +169,8 @@ impl Renderer {
                 | wgpu::Features::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING
                 | wgpu::Features::POLYGON_MODE_LINE
                 | wgpu::Features::RG11B10UFLOAT_RENDERABLE
+                | (adapter.features() & INDIRECT_DRAW_FEATURES)
+                | (adapter.features() & wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
         };
 
         let limits = if cfg!(target_arch = "wasm32") {
@@ -111,49 +195,63 @@ impl Renderer {
         }))
         .unwrap();
 
-        let surface_capabilities = surface.get_capabilities(&adapter);
+        // Offscreen renderers have no surface, so their output is described with the surface config as well
+        let surface_capabilities = surface
+            .as_ref()
+            .map(|surface| surface.get_capabilities(&adapter))
+            .unwrap_or_default();
+        let (output_size, dpi_factor) = match &window {
+            Some(window) => (window.inner_size(), window.scale_factor() as f32),
+            None => (offscreen_resolution.unwrap().into(), 1.0),
+        };
+        let output_format = surface_capabilities
+            .formats
+            .first()
+            .copied()
+            .unwrap_or(OFFSCREEN_FORMAT);
 
         let surface_config = wgpu::SurfaceConfiguration {
             alpha_mode: wgpu::CompositeAlphaMode::Opaque,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface_capabilities.formats[0],
-            view_formats: vec![surface_capabilities.formats[0]],
-            width: window.inner_size().width,
-            height: window.inner_size().height,
-            present_mode: surface_capabilities.present_modes[0],
+            format: output_format,
+            view_formats: vec![output_format],
+            width: output_size.width,
+            height: output_size.height,
+            present_mode: surface_capabilities
+                .present_modes
+                .first()
+                .copied()
+                .unwrap_or(wgpu::PresentMode::AutoNoVsync),
             desired_maximum_frame_latency: 1,
         };
 
         let render_globals = RenderGlobals::new(
             &constants.gfx,
             &device,
-            window.inner_size(),
+            output_size,
             Self::maximum_texture_size(&device),
         );
-        let render_camera = RenderCamera::new(
-            &device,
-            window.inner_size().into(),
-            window.scale_factor() as f32,
-            &constants.gfx,
-        );
+        let render_camera = RenderCamera::new(&device, output_size.into(), dpi_factor, &constants.gfx);
 
         let render_graph = RenderGraph::new(&constants.gfx, &device);
-        let render_ui = RenderUi::new(
-            event_loop,
-            &window,
-            &device,
-            &surface_config,
-            window.inner_size(),
-            window.scale_factor() as f32,
-        );
+        let render_ui = match (&window, event_loop) {
+            (Some(window), Some(event_loop)) => {
+                RenderUi::new(event_loop, window, &device, &surface_config, output_size, dpi_factor)
+            }
+            _ => RenderUi::new_offscreen(&device, output_format, output_size),
+        };
+        let offscreen_target = offscreen_resolution.map(|resolution| OffscreenTarget::new(&device, resolution));
 
         Self {
+            surface_ready: offscreen_target.is_some(),
             window,
+            instance,
             adapter,
             surface,
+            offscreen_target,
             device,
             queue,
-            surface_ready: false,
+            system_info,
             surface_config,
             surface_capabilities,
             surface_view_descriptor: wgpu::TextureViewDescriptor::default(),
@@ -162,10 +260,22 @@ impl Renderer {
             config: GfxConfig::default(),
             texture_loader: None,
             texture_assets: None,
+            asset_diagnostics: AssetDiagnostics::new(asset_error_sender),
+            #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+            shader_watcher: shader_hot_reload::ShaderWatcher::new(),
             render_camera,
             render_globals,
             render_graph,
             render_ui,
+
+            secondary_windows: Map::default(),
+            pending_secondary_windows: Vec::new(),
+            next_secondary_window_id: 0,
+
+            pending_cursor: None,
+            custom_cursor: None,
+            ui_cursor_active: false,
+            frame_cursor_icon: None,
         }
     }
 
@@ -173,7 +283,12 @@ impl Renderer {
     // ------------------- Public interface --------------------- //
     // ---------------------------------------------------------- //
 
-    pub fn load_texture_assets(&mut self, texture_assets: TextureAssets) {
+    /// Info about the graphics adapter and the host system, collected when the renderer was created.
+    pub fn system_info(&self) -> &SystemInfo {
+        &self.system_info
+    }
+
+    pub fn load_texture_assets(&mut self, mut texture_assets: TextureAssets) {
         assert!(
             self.texture_loader.is_none(),
             "Only one texture load can be in progress at a time"
@@ -182,12 +297,33 @@ impl Renderer {
         let texture_loader = TextureLoader::new(
             &self.constants,
             texture_assets.required_textures(),
+            texture_assets.mod_texture_dirs(),
             Self::maximum_texture_size(&self.device),
+            self.asset_diagnostics.clone(),
         );
+        texture_assets.set_diagnostics(self.asset_diagnostics.clone());
         self.texture_loader = Some(texture_loader);
         self.texture_assets = Some(texture_assets);
     }
 
+    /// Inserts a texture to the dynamic atlas of the loaded texture assets.
+    /// Requires that `TextureAssets::reserve_dynamic_atlas` was called before loading and that loading has finished.
+    pub fn insert_runtime_texture(&mut self, texture: RuntimeTexture) -> Result<(), AtlasInsertError> {
+        match self.texture_assets.as_mut() {
+            Some(texture_assets) => texture_assets.insert_runtime_texture(&self.queue, texture),
+            None => Err(AtlasInsertError::AtlasNotReserved),
+        }
+    }
+
+    /// Adds a gfx bundle to the already submitted texture assets.
+    /// Returns the reference to the bundle, same as `TextureAssets::include_gfx_bundle`.
+    pub fn include_gfx_bundle(&mut self, gfx_bundle: GfxBundle) -> u32 {
+        self.texture_assets
+            .as_mut()
+            .expect("Texture assets must be submitted before including more gfx bundles")
+            .include_gfx_bundle(gfx_bundle)
+    }
+
     pub fn texture_assets_progress(&self) -> Option<f32> {
         self.texture_loader.as_ref().map(|loader| loader.progress())
     }
@@ -204,16 +340,118 @@ impl Renderer {
             .collect()
     }
 
+    /// MSAA modes supported for the gbuffer targets. Only `Off` is available on wasm.
+    pub fn available_msaa_modes(&self) -> Vec<MsaaOpts> {
+        if WASM_COMPATIBLE_RENDERING {
+            return vec![MsaaOpts::Off];
+        }
+
+        // Without adapter specific format features, only 4x is guaranteed to be supported
+        let adapter_specific = self
+            .device
+            .features()
+            .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+
+        [MsaaOpts::Off, MsaaOpts::X2, MsaaOpts::X4]
+            .into_iter()
+            .filter(|msaa| {
+                let sample_count = msaa.sample_count();
+                if sample_count == 1 {
+                    return true;
+                }
+                if !adapter_specific {
+                    return sample_count == 4;
+                }
+                [wgpu::TextureFormat::Bgra8UnormSrgb, wgpu::TextureFormat::Depth32Float]
+                    .into_iter()
+                    .all(|format| {
+                        self.adapter
+                            .get_texture_format_features(format)
+                            .flags
+                            .sample_count_supported(sample_count)
+                    })
+            })
+            .collect()
+    }
+
     pub fn dpi_factor(&self) -> f32 {
-        self.window.scale_factor() as f32
+        self.window.as_ref().map_or(1.0, |window| window.scale_factor() as f32)
     }
 
+    /// Resolution of the window, or of the target of an offscreen renderer.
     pub fn window_resolution(&self) -> Resolution {
-        self.window.inner_size().into()
+        match (&self.window, &self.offscreen_target) {
+            (Some(window), _) => window.inner_size().into(),
+            (None, Some(offscreen_target)) => offscreen_target.resolution(),
+            (None, None) => unreachable!("Renderer must have a window or an offscreen target"),
+        }
     }
 
+    /// Resolution of the current monitor. Offscreen renderers return the resolution of their target.
     pub fn monitor_resolution(&self) -> Resolution {
-        self.monitor_handle().size().into()
+        match &self.window {
+            Some(_) => self.monitor_handle().size().into(),
+            None => self.window_resolution(),
+        }
+    }
+
+    /// Connected monitors with their supported fullscreen video modes, for populating settings menus.
+    /// Empty for offscreen renderers.
+    pub fn available_display_modes(&self) -> Vec<DisplayMonitor> {
+        let Some(window) = &self.window else {
+            return Vec::new();
+        };
+        let current_monitor = window.current_monitor();
+
+        window
+            .available_monitors()
+            .map(|monitor| {
+                // Exclusive fullscreen is not supported on macos, and modes differing only by bit depth are merged
+                let mut video_modes: Vec<VideoMode> = if cfg!(target_os = "macos") {
+                    Vec::new()
+                } else {
+                    monitor.video_modes().map(VideoMode::from).collect()
+                };
+                video_modes.sort_by(|a, b| b.cmp(a));
+                video_modes.dedup();
+
+                DisplayMonitor {
+                    name: monitor.name(),
+                    resolution: monitor.size().into(),
+                    frame_rate: monitor.refresh_rate_millihertz().map(|rate| rate / 1000),
+                    is_current: current_monitor.as_ref() == Some(&monitor),
+                    video_modes,
+                }
+            })
+            .collect()
+    }
+
+    /// Adapters of the configured backend, for populating settings menus.
+    /// On wasm, only the adapter in use is returned.
+    pub fn available_adapters(&self) -> Vec<GfxAdapter> {
+        let current_info = self.adapter.get_info();
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let adapter_infos: Vec<_> = self
+            .instance
+            .enumerate_adapters(Self::backends(&self.constants))
+            .iter()
+            .map(|adapter| adapter.get_info())
+            .collect();
+        #[cfg(target_arch = "wasm32")]
+        let adapter_infos = vec![current_info.clone()];
+
+        adapter_infos
+            .into_iter()
+            .enumerate()
+            .map(|(index, info)| GfxAdapter {
+                index,
+                name: info.name.clone(),
+                adapter_type: format!("{:?}", info.device_type),
+                backend: info.backend.to_string(),
+                is_current: info == current_info,
+            })
+            .collect()
     }
 
     pub fn config(&self) -> &GfxConfig {
@@ -222,16 +460,55 @@ impl Renderer {
 
     pub fn set_config(&mut self, mut config: GfxConfig) {
         #[cfg(not(target_arch = "wasm32"))]
-        self.window.set_decorations(config.window_decorations);
-        #[cfg(not(target_arch = "wasm32"))]
-        self.window.set_transparent(config.window_transparent);
+        if let Some(window) = &self.window {
+            window.set_decorations(config.window_decorations);
+            window.set_transparent(config.window_transparent);
+        }
+
+        self.render_graph.set_lod_camera_scale(config.lod_camera_scale);
+        self.render_graph.set_baked_shadows(config.baked_shadows);
+        self.render_graph.set_reflections(config.reflections);
+        self.render_globals.set_color_filter(config.color_filter);
+        self.render_globals.set_display_adjustments(config.display);
+
+        if !self.available_msaa_modes().contains(&config.msaa) {
+            log_info!("MSAA mode {:?} not supported, falling back to Off", config.msaa);
+            config.msaa = MsaaOpts::Off;
+        }
+
+        let max_texture_dimension = self.device.limits().max_texture_dimension_2d;
+        let render_resolution = config.render_resolution();
+        if render_resolution.width > max_texture_dimension || render_resolution.height > max_texture_dimension {
+            log_info!(
+                "Supersampling {:?} exceeds the max texture size, falling back to Off",
+                config.supersampling
+            );
+            config.supersampling = SupersamplingOpts::Off;
+        }
+
+        // Render passes are created once textures are loaded.
+        // If they already exist, rebuild them with the new sample count and samplers.
+        let passes_changed = config.msaa != self.config.msaa || config.anisotropy != self.config.anisotropy;
+        if passes_changed && self.render_graph.render_graph_ready() {
+            self.render_graph.create_render_passes(
+                &self.device,
+                &self.queue,
+                &self.render_camera,
+                &self.render_globals,
+                self.texture_assets.as_ref().unwrap(),
+                &config,
+                &self.surface_config,
+            );
+        }
 
         self.surface_config.present_mode = config.vsync.into();
-        self.surface.configure(&self.device, &self.surface_config);
+        if let Some(surface) = &self.surface {
+            surface.configure(&self.device, &self.surface_config);
+        }
 
         if config.vsync != VsyncOpts::Off {
             #[cfg(not(target_arch = "wasm32"))]
-            {
+            if self.window.is_some() {
                 let monitor_refresh_rate = self.monitor_handle().refresh_rate_millihertz().unwrap() / 1000;
                 config.frame_rate_cap = Some(
                     config
@@ -246,53 +523,11 @@ impl Renderer {
         }
 
         #[cfg(not(target_arch = "wasm32"))]
-        match config.window_mode {
-            WindowMode::Windowed => {
-                #[cfg(target_os = "macos")]
-                self.window.set_simple_fullscreen(false);
-
-                self.window.set_fullscreen(None);
-                let physical_size: PhysicalSize<u32> = config.frame_resolution.into();
-                if let Some(new_size) = self.window.request_inner_size(physical_size) {
-                    self.resize_window(new_size.into(), None)
-                }
-            }
-            WindowMode::BorderlessFullscreen => {
-                #[cfg(target_os = "macos")]
-                self.window.set_simple_fullscreen(false);
-
-                self.window.set_fullscreen(Some(Fullscreen::Borderless(None)));
-            }
-            WindowMode::ExclusiveFullscreen(fullscreen_video_mode) => {
-                #[cfg(target_os = "macos")]
-                panic!(
-                    "Exclusive fullscreen not supported on macos. Mode attempted: {:?}",
-                    fullscreen_video_mode
-                );
-
-                #[cfg(not(target_os = "macos"))]
-                {
-                    let video_mode = self
-                        .monitor_handle()
-                        .video_modes()
-                        .find(|video_mode| {
-                            video_mode.size().width == fullscreen_video_mode.width
-                                && video_mode.size().height == fullscreen_video_mode.height
-                                && video_mode.refresh_rate_millihertz() == fullscreen_video_mode.frame_rate * 1000
-                        })
-                        .expect("Should find a video mode matching given render_config");
-                    self.window
-                        .set_fullscreen(Some(Fullscreen::Exclusive(video_mode.clone())));
-                }
-            }
-        }
-
-        #[cfg(not(target_arch = "wasm32"))]
-        if config.window_mode == WindowMode::Windowed {
-            self.set_window_position_center();
+        if self.window.is_some() {
+            self.apply_window_mode(&config);
         }
 
-        self.resize_renderer(config.frame_resolution);
+        self.resize_renderer(config.render_resolution());
         self.config = config;
     }
 
@@ -302,37 +537,236 @@ impl Renderer {
     /// Does nothing on native targets
     pub fn set_wasm_window_size(&mut self, _resolution: Resolution) {
         #[cfg(target_arch = "wasm32")]
-        let _ = self.window.request_inner_size(PhysicalSize::from(_resolution));
+        if let Some(window) = &self.window {
+            let _ = window.request_inner_size(PhysicalSize::from(_resolution));
+        }
     }
 
     pub fn set_window_position_center(&mut self) {
-        let own_size = self.window.outer_size();
+        let Some(window) = &self.window else {
+            return;
+        };
+        let own_size = window.outer_size();
         let monitor_size = self.monitor_handle().size();
 
         let target_x = (monitor_size.width.saturating_sub(own_size.width)) / 2;
         let target_y = (monitor_size.height.saturating_sub(own_size.height)) / 2;
 
-        self.window.set_outer_position(PhysicalPosition {
+        window.set_outer_position(PhysicalPosition {
             x: target_x,
             y: target_y,
         });
     }
 
+    /// Sets the title of the window, for example to include the name of the loaded save.
+    /// On wasm, this sets the title of the web page instead.
+    pub fn set_window_title(&mut self, title: &str) {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(window) = &self.window {
+            window.set_title(title);
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        if let Some(document) = ion_common::web_sys::window().and_then(|window| window.document()) {
+            document.set_title(title);
+        }
+    }
+
+    /// Sets the window and taskbar icon from an image file. Icons of 32x32 to 256x256 pixels work best.
+    ///
+    /// Supported on Windows and X11. On macOS the dock icon comes from the app bundle,
+    /// and on Wayland from the desktop entry of the app, so this does nothing there. Does nothing on wasm.
+    pub fn set_window_icon(&mut self, path: &Path) -> Result<(), AssetError> {
+        let image = Self::load_rgba_image(path)?;
+        let (width, height) = image.dimensions();
+        let icon = Icon::from_rgba(image.into_raw(), width, height).map_err(|err| AssetError::UnsupportedFormat {
+            path: path.to_path_buf(),
+            reason: err.to_string(),
+        })?;
+
+        let Some(window) = &self.window else {
+            return Ok(());
+        };
+
+        // Windows keeps separate small (title bar) and big (taskbar, alt-tab) icons
+        #[cfg(target_os = "windows")]
+        window.set_taskbar_icon(Some(icon.clone()));
+
+        window.set_window_icon(Some(icon));
+        Ok(())
+    }
+
+    /// Sets the OS cursor to an image file, with the click point at `hotspot` pixels from the top left corner.
+    /// Hardware cursors follow the mouse without the latency of a sprite cursor.
+    ///
+    /// The cursor is created at the end of the current render frame. While egui shows its own cursor,
+    /// for example over text fields, the custom cursor is replaced until egui returns to the default cursor.
+    pub fn set_cursor_image(&mut self, path: &Path, hotspot: (u16, u16)) -> Result<(), AssetError> {
+        let image = Self::load_rgba_image(path)?;
+        let (width, height) = image.dimensions();
+        let source = CustomCursor::from_rgba(image.into_raw(), width as u16, height as u16, hotspot.0, hotspot.1)
+            .map_err(|err| AssetError::UnsupportedFormat {
+                path: path.to_path_buf(),
+                reason: err.to_string(),
+            })?;
+
+        self.pending_cursor = Some(source);
+        Ok(())
+    }
+
+    /// Restores the default OS cursor.
+    pub fn reset_cursor_image(&mut self) {
+        self.pending_cursor = None;
+        self.custom_cursor = None;
+        if let Some(window) = &self.window {
+            window.set_cursor(CursorIcon::Default);
+        }
+    }
+
+    /// Confines or locks the OS cursor to the window. If the platform does not support the requested mode,
+    /// the other grabbing mode is tried instead. Returns the mode that is in effect.
+    /// Combine with `set_cursor_visible(false)` and `InputState::mouse_motion` for aiming or drag rotating.
+    pub fn set_cursor_grab(&mut self, grab: CursorGrab) -> CursorGrab {
+        let Some(window) = &self.window else {
+            return CursorGrab::None;
+        };
+
+        let attempts: &[CursorGrab] = match grab {
+            CursorGrab::None => &[CursorGrab::None],
+            CursorGrab::Confined => &[CursorGrab::Confined, CursorGrab::Locked],
+            CursorGrab::Locked => &[CursorGrab::Locked, CursorGrab::Confined],
+        };
+        for attempt in attempts {
+            let mode = match attempt {
+                CursorGrab::None => CursorGrabMode::None,
+                CursorGrab::Confined => CursorGrabMode::Confined,
+                CursorGrab::Locked => CursorGrabMode::Locked,
+            };
+            match window.set_cursor_grab(mode) {
+                Ok(()) => return *attempt,
+                Err(err) => {
+                    log_warn!("Cursor grab {:?} not available: {}", attempt, err);
+                }
+            }
+        }
+
+        window.set_cursor_grab(CursorGrabMode::None).ok();
+        CursorGrab::None
+    }
+
+    /// Shows a system cursor icon for the current frame, for example a hand over interactable objects.
+    /// `egui::CursorIcon::None` hides the cursor for the frame. Must be set again every frame to keep showing it.
+    ///
+    /// Cursors that egui shows for its own widgets, like text fields, take precedence.
+    pub fn set_frame_cursor_icon(&mut self, icon: egui::CursorIcon) {
+        self.frame_cursor_icon = Some(icon);
+    }
+
+    pub fn set_cursor_visible(&mut self, visible: bool) {
+        if let Some(window) = &self.window {
+            window.set_cursor_visible(visible);
+        }
+    }
+
+    /// Applies display calibration without reconfiguring the window or the surface,
+    /// so it can be driven live from calibration sliders. Also updated in [`Self::config`] for persisting.
+    pub fn set_display_adjustments(&mut self, display: DisplayAdjustments) {
+        self.config.display = display.clamped();
+        self.render_globals.set_display_adjustments(display);
+    }
+
     pub fn set_frame_mode(&mut self, frame_mode: GfxFrameMode) {
         self.render_globals.set_frame_mode(frame_mode);
     }
 
+    /// Renders a frame of a renderer created with [`Self::new_offscreen`], and returns it as an image.
+    /// Blocks until the gpu has finished the frame.
+    ///
+    /// Texture assets are loaded over several frames. Render without frame data until
+    /// [`Self::texture_assets_ready`] before rendering frame data.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn render_offscreen(&mut self, frame_data: Option<&GfxFrameData>) -> image::RgbaImage {
+        assert!(
+            self.offscreen_target.is_some(),
+            "Only offscreen renderers can render offscreen"
+        );
+
+        self.pre_render(frame_data);
+        self.ui_begin_pass();
+        self.render(frame_data);
+        self.post_render();
+
+        self.offscreen_target.as_ref().unwrap().read_image(&self.device)
+    }
+
+    /// Opens an additional OS window for tools such as level editors and debug dashboards.
+    /// The window only renders egui, built with the context from [`Self::secondary_window_ui`].
+    ///
+    /// The window is created at the end of the current render frame. Closing it from the title bar
+    /// closes it the same way as [`Self::close_secondary_window`]. Not supported on wasm.
+    pub fn open_secondary_window(&mut self, title: &str, resolution: Resolution) -> SecondaryWindowId {
+        let id = SecondaryWindowId(self.next_secondary_window_id);
+        self.next_secondary_window_id += 1;
+        self.pending_secondary_windows.push((id, title.to_string(), resolution));
+        id
+    }
+
+    pub fn close_secondary_window(&mut self, id: SecondaryWindowId) {
+        self.secondary_windows.remove(&id);
+        self.pending_secondary_windows
+            .retain(|(pending_id, _, _)| *pending_id != id);
+    }
+
+    /// Whether the window is open or about to be opened. `false` once it has been closed.
+    pub fn secondary_window_open(&self, id: SecondaryWindowId) -> bool {
+        self.secondary_windows.contains_key(&id)
+            || self
+                .pending_secondary_windows
+                .iter()
+                .any(|(pending_id, _, _)| *pending_id == id)
+    }
+
+    /// Egui context for building the ui of a secondary window this frame.
+    /// `None` if the window is not open yet, has been closed, or is minimized.
+    pub fn secondary_window_ui(&self, id: SecondaryWindowId) -> Option<egui::Context> {
+        self.secondary_windows
+            .get(&id)
+            .and_then(|secondary_window| secondary_window.ui_ctx())
+    }
+
     // ---------------------------------------------------------- //
     // ----------------- Internal implementation ---------------- //
     // ---------------------------------------------------------- //
 
+    /// Adds trauma to the camera shake. Total trauma is clamped between 0.0 and 1.0, and decays over time.
+    /// Shake is applied on the render thread only, so it doesn't affect the camera location of the universe.
+    pub fn add_camera_trauma(&mut self, trauma: f32) {
+        self.render_camera.add_trauma(trauma);
+    }
+
+    pub fn camera_trauma(&self) -> f32 {
+        self.render_camera.trauma()
+    }
+
+    pub fn set_camera_shake(&mut self, camera_shake: CameraShake) {
+        self.render_camera.set_shake(camera_shake);
+    }
+
+    /// Sets the allowed camera scale range and zoom easing.
+    /// Camera scale sent by the universe is clamped to the range on the render thread.
+    pub fn set_camera_zoom(&mut self, camera_zoom: CameraZoom) {
+        self.render_camera.set_zoom(camera_zoom);
+    }
+
     pub(crate) fn resize_window(&mut self, new_window_res: Resolution, new_dpi_factor: Option<f32>) {
         if new_window_res.width > 0 && new_window_res.height > 0 {
             log_info!("resize window: {:?}", new_window_res);
             self.surface_config.width = new_window_res.width;
             self.surface_config.height = new_window_res.height;
 
-            self.surface.configure(&self.device, &self.surface_config);
+            if let Some(surface) = &self.surface {
+                surface.configure(&self.device, &self.surface_config);
+            }
             self.surface_ready = true;
 
             self.render_camera.resize_window(new_window_res, new_dpi_factor);
@@ -354,19 +788,215 @@ impl Renderer {
     }
 
     pub(crate) fn ui_begin_pass(&mut self) -> egui::Context {
-        self.render_ui.ui_begin_pass(&self.window)
+        for secondary_window in self.secondary_windows.values_mut() {
+            secondary_window.ui_begin_pass();
+        }
+
+        self.render_ui.ui_begin_pass(self.window.as_deref())
+    }
+
+    /// Handles an event of a window that is not the main window. Returns `false` if the window is not known,
+    /// which can happen for events that were queued before the window was closed.
+    pub(crate) fn secondary_window_event(&mut self, window_id: winit::window::WindowId, event: &WindowEvent) -> bool {
+        let Some((&id, secondary_window)) = self
+            .secondary_windows
+            .iter_mut()
+            .find(|(_, secondary_window)| secondary_window.window.id() == window_id)
+        else {
+            return false;
+        };
+
+        if secondary_window.window_event(&self.device, event) {
+            self.close_secondary_window(id);
+        }
+        true
+    }
+
+    /// Creates the secondary windows and the cursor requested during the frame.
+    /// These can only be created from the event loop.
+    pub(crate) fn create_pending_resources(&mut self, event_loop: &ActiveEventLoop) {
+        if let Some(window) = &self.window
+            && let Some(source) = self.pending_cursor.take()
+        {
+            let cursor = event_loop.create_custom_cursor(source);
+            window.set_cursor(cursor.clone());
+            self.custom_cursor = Some(cursor);
+        }
+
+        for (id, title, resolution) in self.pending_secondary_windows.drain(..) {
+            if cfg!(target_arch = "wasm32") {
+                log_warn!("Secondary windows are not supported on wasm: {}", title);
+                continue;
+            }
+
+            let secondary_window = SecondaryWindow::new(
+                event_loop,
+                &self.instance,
+                &self.adapter,
+                &self.device,
+                &title,
+                resolution,
+            );
+            self.secondary_windows.insert(id, secondary_window);
+        }
     }
 
     pub(crate) fn ui_event_process(&mut self, event: &WindowEvent) -> bool {
-        self.render_ui.ui_event_process(&self.window, event)
+        match &self.window {
+            Some(window) => self.render_ui.ui_event_process(window, event),
+            None => false,
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn apply_window_mode(&mut self, config: &GfxConfig) {
+        match config.window_mode {
+            WindowMode::Windowed => {
+                #[cfg(target_os = "macos")]
+                self.window().set_simple_fullscreen(false);
+
+                self.window().set_fullscreen(None);
+                let physical_size: PhysicalSize<u32> = config.frame_resolution.into();
+                if let Some(new_size) = self.window().request_inner_size(physical_size) {
+                    self.resize_window(new_size.into(), None)
+                }
+            }
+            WindowMode::BorderlessFullscreen => {
+                #[cfg(target_os = "macos")]
+                self.window().set_simple_fullscreen(false);
+
+                let monitor = self.fullscreen_monitor_handle(config.fullscreen_monitor.as_deref());
+                self.window()
+                    .set_fullscreen(Some(Fullscreen::Borderless(Some(monitor))));
+            }
+            WindowMode::ExclusiveFullscreen(fullscreen_video_mode) => {
+                #[cfg(target_os = "macos")]
+                panic!(
+                    "Exclusive fullscreen not supported on macos. Mode attempted: {:?}",
+                    fullscreen_video_mode
+                );
+
+                #[cfg(not(target_os = "macos"))]
+                {
+                    // Compared as `VideoMode`, so that modes from `available_display_modes` always match
+                    let monitor = self.fullscreen_monitor_handle(config.fullscreen_monitor.as_deref());
+                    let video_mode = monitor
+                        .video_modes()
+                        .find(|video_mode| VideoMode::from(video_mode.clone()) == fullscreen_video_mode);
+
+                    match video_mode {
+                        Some(video_mode) => self.window().set_fullscreen(Some(Fullscreen::Exclusive(video_mode))),
+                        None => {
+                            log_warn!(
+                                "Video mode {:?} not available, using borderless fullscreen",
+                                fullscreen_video_mode
+                            );
+                            self.window()
+                                .set_fullscreen(Some(Fullscreen::Borderless(Some(monitor))));
+                        }
+                    }
+                }
+            }
+        }
+
+        if config.window_mode == WindowMode::Windowed {
+            self.set_window_position_center();
+        }
+    }
+
+    fn window(&self) -> &winit::window::Window {
+        self.window.as_ref().expect("Offscreen renderer doesn't have a window")
     }
 
     fn monitor_handle(&self) -> winit::monitor::MonitorHandle {
-        self.window
+        self.window()
             .current_monitor()
             .expect("Current monitor must be available")
     }
 
+    /// Monitor with the given name, or the current monitor if no name is given or no monitor matches it.
+    fn fullscreen_monitor_handle(&self, name: Option<&str>) -> winit::monitor::MonitorHandle {
+        let Some(name) = name else {
+            return self.monitor_handle();
+        };
+
+        self.window()
+            .available_monitors()
+            .find(|monitor| monitor.name().as_deref() == Some(name))
+            .unwrap_or_else(|| {
+                log_warn!("Monitor {} not found, using the current monitor", name);
+                self.monitor_handle()
+            })
+    }
+
+    fn load_rgba_image(path: &Path) -> Result<image::RgbaImage, AssetError> {
+        let bytes = load_resource(path).map_err(|err| AssetError::MissingFile {
+            path: path.to_path_buf(),
+            reason: err.to_string(),
+        })?;
+        let image = image::load_from_memory(&bytes).map_err(|err| AssetError::DecodeFailed {
+            path: path.to_path_buf(),
+            reason: err.to_string(),
+        })?;
+        Ok(image.into_rgba8())
+    }
+
+    fn backends(constants: &Constants) -> wgpu::Backends {
+        if cfg!(target_arch = "wasm32") {
+            wgpu::Backends::GL
+        } else {
+            constants.gfx.backend.backends()
+        }
+    }
+
+    /// Picks the adapter selected in the constants, or the high performance adapter if it is not available.
+    fn select_adapter(
+        instance: &wgpu::Instance,
+        surface: Option<&wgpu::Surface>,
+        constants: &Constants,
+    ) -> wgpu::Adapter {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let mut adapters = instance.enumerate_adapters(Self::backends(constants));
+            let selected = match &constants.gfx.adapter {
+                GfxAdapterSelection::Auto => None,
+                GfxAdapterSelection::Name(name) => {
+                    let name = name.to_lowercase();
+                    adapters
+                        .iter()
+                        .position(|adapter| adapter.get_info().name.to_lowercase().contains(&name))
+                }
+                GfxAdapterSelection::Index(index) => (*index < adapters.len()).then_some(*index),
+            };
+
+            match selected {
+                Some(index) if surface.is_none_or(|surface| adapters[index].is_surface_supported(surface)) => {
+                    return adapters.swap_remove(index);
+                }
+                Some(_) => {
+                    log_warn!(
+                        "Adapter {:?} doesn't support the window, using the default adapter",
+                        constants.gfx.adapter
+                    );
+                }
+                None if constants.gfx.adapter != GfxAdapterSelection::Auto => {
+                    log_warn!(
+                        "Adapter {:?} not found, using the default adapter",
+                        constants.gfx.adapter
+                    );
+                }
+                None => {}
+            }
+        }
+
+        block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            compatible_surface: surface,
+            force_fallback_adapter: false,
+        }))
+        .unwrap()
+    }
+
     fn maximum_texture_size(device: &wgpu::Device) -> u32 {
         if WASM_COMPATIBLE_RENDERING {
             device.limits().max_texture_dimension_2d.min(8192)
@@ -381,16 +1011,23 @@ impl Renderer {
 
     /// If frame data is provided, it will be used to update the camera and globals.
     /// If texture loading is in progress, it will be polled.
+    /// In native debug builds, modified shaders are hot-reloaded.
+    /// Pending dynamic atlas updates are flushed.
     pub(crate) fn pre_render(&mut self, frame_data: Option<&GfxFrameData>) {
         if let Some(frame_data) = frame_data {
             self.render_camera.update_location(&frame_data);
-            self.render_camera.update_scale(frame_data.global_data.camera_scale);
+            let delta_time = frame_data.timing_data.render_frame_duration.as_secs_f32();
+            self.render_camera
+                .update_scale(frame_data.global_data.camera_scale, delta_time);
+            self.render_camera
+                .update_follow(frame_data.global_data.camera_follow, delta_time);
+            self.render_camera.update_shake(delta_time);
             self.render_globals.update_globals(&frame_data.global_data);
 
             self.render_ui.ui_build_debug_labels(
                 &frame_data.debug_data.debug_labels,
                 &self.render_camera,
-                self.window.inner_size(),
+                self.window_resolution().into(),
                 self.dpi_factor(),
             );
         }
@@ -403,10 +1040,11 @@ impl Renderer {
                 .poll_loading(&self.device, &self.queue);
 
             if poll_complete {
-                self.texture_assets
-                    .as_mut()
-                    .unwrap()
-                    .take_finished_loader(&self.device, self.texture_loader.take().unwrap());
+                self.texture_assets.as_mut().unwrap().take_finished_loader(
+                    &self.device,
+                    &self.queue,
+                    self.texture_loader.take().unwrap(),
+                );
 
                 self.render_graph.create_render_passes(
                     &self.device,
@@ -419,9 +1057,31 @@ impl Renderer {
                 );
 
                 self.render_graph
-                    .create_render_targets(&self.device, self.config.frame_resolution);
+                    .create_render_targets(&self.device, self.config.render_resolution());
             }
         }
+
+        // Shaders are hot-reloaded by rebuilding the passes that use them. Texture assets are kept as they are.
+        #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+        if self.render_graph.render_graph_ready() {
+            let changed = self.shader_watcher.poll();
+            if !changed.is_empty() {
+                self.render_graph.reload_shaders(
+                    &changed,
+                    &self.device,
+                    &self.queue,
+                    &self.render_camera,
+                    &self.render_globals,
+                    self.texture_assets.as_ref().unwrap(),
+                    &self.config,
+                    &self.surface_config,
+                );
+            }
+        }
+
+        if let Some(texture_assets) = self.texture_assets.as_mut() {
+            texture_assets.flush_dynamic_atlas(&self.device, &self.queue);
+        }
     }
 
     pub(crate) fn render(&mut self, frame_data: Option<&GfxFrameData>) {
@@ -429,13 +1089,20 @@ impl Renderer {
             return;
         }
 
-        let surface_texture: wgpu::SurfaceTexture;
+        let surface_texture: Option<wgpu::SurfaceTexture>;
         let surface_view: wgpu::TextureView;
         let mut encoder: wgpu::CommandEncoder;
 
         {
-            surface_texture = self.surface.get_current_texture().unwrap();
-            surface_view = surface_texture.texture.create_view(&self.surface_view_descriptor);
+            surface_texture = self
+                .surface
+                .as_ref()
+                .map(|surface| surface.get_current_texture().unwrap());
+            surface_view = match (&surface_texture, &self.offscreen_target) {
+                (Some(surface_texture), _) => surface_texture.texture.create_view(&self.surface_view_descriptor),
+                (None, Some(offscreen_target)) => offscreen_target.create_view(),
+                (None, None) => unreachable!("Renderer must have a window or an offscreen target"),
+            };
             encoder = self.device.create_command_encoder(&self.command_encoder_descriptor);
         }
 
@@ -462,15 +1129,48 @@ impl Renderer {
             }
         }
 
-        self.render_ui
-            .render_ui(&self.window, &self.device, &self.queue, &mut encoder, &surface_view);
+        if let Some(icon) = self.frame_cursor_icon.take() {
+            self.render_ui.request_cursor_icon(icon);
+        }
+        self.render_ui.render_ui(
+            self.window.as_deref(),
+            &self.device,
+            &self.queue,
+            &mut encoder,
+            &surface_view,
+        );
+
+        // Egui resets the cursor to the default one when it stops showing its own cursor
+        if let Some(custom_cursor) = &self.custom_cursor
+            && let Some(window) = &self.window
+        {
+            let ui_cursor_active = self.render_ui.cursor_icon() != egui::CursorIcon::Default;
+            if self.ui_cursor_active && !ui_cursor_active {
+                window.set_cursor(custom_cursor.clone());
+            }
+            self.ui_cursor_active = ui_cursor_active;
+        }
+
+        if let Some(offscreen_target) = &self.offscreen_target {
+            offscreen_target.copy_to_readback(&mut encoder);
+        }
 
         self.queue.submit(iter::once(encoder.finish()));
 
-        surface_texture.present();
+        if let Some(surface_texture) = surface_texture {
+            surface_texture.present();
+        }
+
+        for secondary_window in self.secondary_windows.values_mut() {
+            secondary_window.render(&self.device, &self.queue);
+        }
     }
 
     pub(crate) fn post_render(&mut self) {
         self.render_ui.render_ui_cleanup();
+
+        for secondary_window in self.secondary_windows.values_mut() {
+            secondary_window.post_render();
+        }
     }
 }
@@ -0,0 +1,119 @@
+use std::sync::mpsc;
+
+use crate::gfx::gfx_config::Resolution;
+
+/// Format of the offscreen target. Same channel order as the images it is read back into.
+pub(super) const OFFSCREEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// Texture that a windowless renderer draws into instead of a surface, and that can be read back to the cpu.
+/// Used for screenshot tests, thumbnails and map previews.
+pub(super) struct OffscreenTarget {
+    texture: wgpu::Texture,
+    readback_buffer: wgpu::Buffer,
+    padded_bytes_per_row: u32,
+    resolution: Resolution,
+}
+
+impl OffscreenTarget {
+    pub(super) fn new(device: &wgpu::Device, resolution: Resolution) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("offscreen_target_texture"),
+            size: wgpu::Extent3d {
+                width: resolution.width,
+                height: resolution.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: OFFSCREEN_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        // Rows of a texture to buffer copy must be aligned, the padding is stripped when reading
+        let padded_bytes_per_row = Self::padded_bytes_per_row(resolution.width);
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("offscreen_target_readback_buffer"),
+            size: padded_bytes_per_row as u64 * resolution.height as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            texture,
+            readback_buffer,
+            padded_bytes_per_row,
+            resolution,
+        }
+    }
+
+    pub(super) fn resolution(&self) -> Resolution {
+        self.resolution
+    }
+
+    pub(super) fn create_view(&self) -> wgpu::TextureView {
+        self.texture.create_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+    /// Records a copy of the rendered frame to the readback buffer. Must be submitted before [`Self::read_image`].
+    pub(super) fn copy_to_readback(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer: &self.readback_buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.padded_bytes_per_row),
+                    rows_per_image: Some(self.resolution.height),
+                },
+            },
+            self.texture.size(),
+        );
+    }
+
+    /// Blocks until the gpu has finished the submitted frame, and returns it as an image.
+    pub(super) fn read_image(&self, device: &wgpu::Device) -> image::RgbaImage {
+        let buffer_slice = self.readback_buffer.slice(..);
+        let (sender, receiver) = mpsc::channel();
+        buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device
+            .poll(wgpu::PollType::Wait)
+            .expect("Gpu must finish the offscreen frame");
+        receiver
+            .recv()
+            .expect("Map callback must be called after waiting")
+            .expect("Offscreen readback buffer must be mappable");
+
+        let unpadded_bytes_per_row = self.resolution.width as usize * 4;
+        let mut pixels = Vec::with_capacity(unpadded_bytes_per_row * self.resolution.height as usize);
+        {
+            let mapped = buffer_slice.get_mapped_range();
+            for row in mapped.chunks(self.padded_bytes_per_row as usize) {
+                pixels.extend_from_slice(&row[..unpadded_bytes_per_row]);
+            }
+        }
+        self.readback_buffer.unmap();
+
+        image::RgbaImage::from_raw(self.resolution.width, self.resolution.height, pixels)
+            .expect("Readback must contain every pixel of the target")
+    }
+
+    fn padded_bytes_per_row(width: u32) -> u32 {
+        (width * 4).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rows_are_padded_to_copy_alignment() {
+        assert_eq!(OffscreenTarget::padded_bytes_per_row(64), 256);
+        assert_eq!(OffscreenTarget::padded_bytes_per_row(65), 512);
+        assert_eq!(OffscreenTarget::padded_bytes_per_row(1), 256);
+    }
+}
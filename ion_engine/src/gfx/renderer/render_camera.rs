@@ -2,10 +2,11 @@ use wgpu::util::DeviceExt;
 
 use crate::core::GfxConstants;
 use crate::core::coordinates::{Location, Position};
-use crate::gfx::GfxFrameData;
 use crate::gfx::gfx_config::Resolution;
+use crate::gfx::{CameraFollow, CameraShake, CameraZoom, GfxFrameData};
 use crate::util::casting::{RawData, any_as_bytes};
 use derive_engine::RawData;
+use ion_common::math::hash::FastHash;
 use ion_common::math::matrix::Matrix4x4;
 
 pub(crate) struct RenderCamera {
@@ -15,12 +16,17 @@ pub(crate) struct RenderCamera {
 
     render_loc: Location,
     real_loc: Location,
+    follow_loc: Location,
     scale: f32,
+    target_scale: f32,
+    zoom: CameraZoom,
+    zoom_anchor: Option<Location>,
     angle_cos: f32,
     angle_sin: f32,
     angle_tan: f32,
     last_real_change_x: f32,
     last_real_change_y: f32,
+    shake: Shake,
 
     scale_mat: Matrix4x4,
     rot_mat: Matrix4x4,
@@ -83,12 +89,17 @@ impl RenderCamera {
             camera_buffer,
             render_loc: Location { x: 0.0, y: 0.0 },
             real_loc: Location { x: 0.0, y: 0.0 },
+            follow_loc: Location { x: 0.0, y: 0.0 },
             scale: 10.0,
+            target_scale: 10.0,
+            zoom: CameraZoom::default(),
+            zoom_anchor: None,
             angle_cos,
             angle_sin,
             angle_tan,
             last_real_change_x: 0.0,
             last_real_change_y: 0.0,
+            shake: Shake::new(CameraShake::default()),
 
             rot_mat: Matrix4x4::new([
                 [std::f32::consts::FRAC_PI_4.cos(), std::f32::consts::FRAC_PI_4.sin(), 0.0, 0.0],
@@ -126,26 +137,109 @@ impl RenderCamera {
         self.render_loc = new_updated_loc;
     }
 
-    pub(crate) fn update_scale(&mut self, camera_scale: f32) {
-        self.scale = camera_scale;
+    /// Moves the view towards the interpolated camera location with the given follow mode.
+    /// Must be called after `update_location`.
+    pub(crate) fn update_follow(&mut self, follow: CameraFollow, delta_time: f32) {
+        let velocity = Location {
+            x: self.last_real_change_x,
+            y: self.last_real_change_y,
+        };
+        self.follow_loc = Self::calc_follow_loc(
+            follow,
+            self.follow_loc,
+            self.render_loc,
+            velocity,
+            self.scale,
+            delta_time,
+        );
+    }
+
+    /// Clamps the camera scale to the zoom range, and eases the rendered scale towards it.
+    /// Must be called after `update_location`.
+    pub(crate) fn update_scale(&mut self, camera_scale: f32, delta_time: f32) {
+        let target_scale = self.zoom.clamp_scale(camera_scale);
+        if target_scale != self.target_scale {
+            // Fixed point of the universe camera zoom. Easing zooms around it, so zooming to cursor stays smooth.
+            let ratio = target_scale / self.target_scale;
+            let prev_loc_x = self.real_loc.x - self.last_real_change_x;
+            let prev_loc_y = self.real_loc.y - self.last_real_change_y;
+            self.zoom_anchor = ((1.0 - ratio).abs() > 1e-4).then(|| Location {
+                x: (self.real_loc.x - ratio * prev_loc_x) / (1.0 - ratio),
+                y: (self.real_loc.y - ratio * prev_loc_y) / (1.0 - ratio),
+            });
+            self.target_scale = target_scale;
+        }
+
+        if self.zoom.easing > 0.0 {
+            self.scale += (self.target_scale - self.scale) * (1.0 - (-self.zoom.easing * delta_time).exp());
+        }
+        if self.zoom.easing <= 0.0 || (self.target_scale - self.scale).abs() < self.target_scale * 1e-3 {
+            self.scale = self.target_scale;
+            self.zoom_anchor = None;
+        }
+    }
+
+    pub(crate) fn set_zoom(&mut self, zoom: CameraZoom) {
+        self.zoom = zoom;
+    }
+
+    /// Advances the camera shake by the duration of the previous render frame.
+    pub(crate) fn update_shake(&mut self, delta_time: f32) {
+        self.shake.update(delta_time);
+    }
+
+    pub(crate) fn add_trauma(&mut self, trauma: f32) {
+        self.shake.trauma = (self.shake.trauma + trauma).clamp(0.0, 1.0);
+    }
+
+    pub(crate) fn trauma(&self) -> f32 {
+        self.shake.trauma
+    }
+
+    pub(crate) fn set_shake(&mut self, settings: CameraShake) {
+        self.shake.settings = settings;
     }
 
     pub(crate) fn write_to_gpu(&self, queue: &wgpu::Queue) {
-        let z_span = self.angle_sin * self.scale * self.window_aspect_ratio;
-        let z_edges = [16.0, 16.0 + z_span];
-        let gpu_camera = GpuCamera {
-            vp_mat: self.calc_vp_mat(None).raw(),
-            vp_mat_inv: self.calc_vp_mat(None).inverse().unwrap().raw(),
-            z_edges,
-            loc: [self.render_loc.x, self.render_loc.y],
-            scale: self.scale,
-            angle_cos: self.angle_cos,
-            angle_sin: self.angle_sin,
-            angle_tan: self.angle_tan,
-        };
+        let gpu_camera = self.gpu_camera(self.calc_vp_mat(None), self.view_loc());
         queue.write_buffer(&self.camera_buffer, 0, any_as_bytes(&gpu_camera));
     }
 
+    /// Creates a camera buffer and bind group for rendering into off-screen textures, such as chunk impostors.
+    pub(crate) fn create_bake_camera(&self, device: &wgpu::Device, label: &str) -> (wgpu::Buffer, wgpu::BindGroup) {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&format!("{}_camera_buffer", label)),
+            size: size_of::<GpuCamera>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.camera_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some(&format!("{}_camera_bind_group", label)),
+        });
+        (buffer, bind_group)
+    }
+
+    /// Writes a bake camera that views a square area centered on `center`, with the same angle as this camera.
+    pub(crate) fn write_bake_camera_to_gpu(
+        &self,
+        queue: &wgpu::Queue,
+        buffer: &wgpu::Buffer,
+        center: Location,
+        half_extent: f32,
+    ) {
+        let gpu_camera = self.gpu_camera(self.calc_bake_vp_mat(center, half_extent), center);
+        queue.write_buffer(buffer, 0, any_as_bytes(&gpu_camera));
+    }
+
+    pub(crate) fn scale(&self) -> f32 {
+        self.scale
+    }
+
     pub(crate) fn interpolation_x(&self) -> f32 {
         self.render_loc.x - self.real_loc.x
     }
@@ -197,21 +291,45 @@ impl RenderCamera {
 
     pub(crate) fn calc_vp_mat(&self, custom_scale: Option<f32>) -> Matrix4x4 {
         let scale = custom_scale.unwrap_or(self.scale) / self.window_aspect_ratio;
+        self.calc_ortho_vp_mat(self.view_loc(), scale * self.window_aspect_ratio, scale)
+    }
+
+    /// Location the camera is looking at, including follow mode, zoom easing and the shake offset.
+    /// These are not part of `render_loc`, so that they don't affect sprite interpolation.
+    fn view_loc(&self) -> Location {
+        let loc = match self.zoom_anchor {
+            Some(anchor) => {
+                let ratio = self.scale / self.target_scale;
+                Location {
+                    x: anchor.x + (self.follow_loc.x - anchor.x) * ratio,
+                    y: anchor.y + (self.follow_loc.y - anchor.y) * ratio,
+                }
+            }
+            None => self.follow_loc,
+        };
+        loc + self.shake.offset
+    }
 
-        let left = scale * -1. * self.window_aspect_ratio;
-        let right = scale * self.window_aspect_ratio;
-        let bottom = scale * -1.;
-        let top = scale;
+    /// View-projection matrix of a bake camera. The view is square, so bakes don't depend on the window size.
+    pub(crate) fn calc_bake_vp_mat(&self, center: Location, half_extent: f32) -> Matrix4x4 {
+        self.calc_ortho_vp_mat(center, half_extent, half_extent)
+    }
+
+    fn calc_ortho_vp_mat(&self, center: Location, half_width: f32, half_height: f32) -> Matrix4x4 {
+        let left = -half_width;
+        let right = half_width;
+        let bottom = -half_height;
+        let top = half_height;
 
         let rcp_width = 1.0 / (right - left);
-        let rcp_height = 1.0 / (scale - bottom);
+        let rcp_height = 1.0 / (top - bottom);
         let r = 1.0 / (0.0 - 1.0);
 
         let view = Matrix4x4::new([
             [1.0, 0.0, 0.0, 0.0],
             [0.0, 1.0, 0.0, 0.0],
             [0.0, 0.0, 1.0, 0.0],
-            [-self.render_loc.x, -self.render_loc.y, 0.0, 1.0],
+            [-center.x, -center.y, 0.0, 1.0],
         ]);
 
         // Orthographic right-hand projection matrix
@@ -234,6 +352,114 @@ impl RenderCamera {
     pub(crate) fn calc_camera_x_y_ratio(gfx_constants: &GfxConstants) -> f32 {
         gfx_constants.camera_angle_deg.to_radians().cos()
     }
+
+    /// Velocity is the camera movement per universe frame.
+    /// Targets more than a view width away are snapped to, so that teleports don't pan across the world.
+    fn calc_follow_loc(
+        follow: CameraFollow,
+        current: Location,
+        target: Location,
+        velocity: Location,
+        scale: f32,
+        delta_time: f32,
+    ) -> Location {
+        let ease = |to: Location, rate: f32| {
+            let t = 1.0 - (-rate * delta_time).exp();
+            Location {
+                x: current.x + (to.x - current.x) * t,
+                y: current.y + (to.y - current.y) * t,
+            }
+        };
+
+        let distance = ((target.x - current.x).powi(2) + (target.y - current.y).powi(2)).sqrt();
+        if distance > 2.0 * scale {
+            return target;
+        }
+
+        match follow {
+            CameraFollow::Lock => target,
+            CameraFollow::Lerp { rate } => ease(target, rate),
+            CameraFollow::DeadZone {
+                half_width,
+                half_height,
+            } => Location {
+                x: current.x.max(target.x - half_width).min(target.x + half_width),
+                y: current.y.max(target.y - half_height).min(target.y + half_height),
+            },
+            CameraFollow::LookAhead { frames, rate } => ease(
+                Location {
+                    x: target.x + velocity.x * frames,
+                    y: target.y + velocity.y * frames,
+                },
+                rate,
+            ),
+        }
+    }
+
+    fn gpu_camera(&self, vp_mat: Matrix4x4, loc: Location) -> GpuCamera {
+        let z_span = self.angle_sin * self.scale * self.window_aspect_ratio;
+        GpuCamera {
+            vp_mat: vp_mat.raw(),
+            vp_mat_inv: vp_mat.inverse().unwrap().raw(),
+            z_edges: [16.0, 16.0 + z_span],
+            loc: [loc.x, loc.y],
+            scale: self.scale,
+            angle_cos: self.angle_cos,
+            angle_sin: self.angle_sin,
+            angle_tan: self.angle_tan,
+        }
+    }
+}
+
+/// Trauma based camera shake state
+struct Shake {
+    settings: CameraShake,
+    trauma: f32,
+    time: f32,
+    offset: Location,
+}
+
+impl Shake {
+    fn new(settings: CameraShake) -> Self {
+        Self {
+            settings,
+            trauma: 0.0,
+            time: 0.0,
+            offset: Location { x: 0.0, y: 0.0 },
+        }
+    }
+
+    fn update(&mut self, delta_time: f32) {
+        self.trauma = (self.trauma - self.settings.decay * delta_time).max(0.0);
+        if self.trauma == 0.0 {
+            self.time = 0.0;
+            self.offset = Location { x: 0.0, y: 0.0 };
+            return;
+        }
+
+        self.time += delta_time;
+        let noise_time = self.time * self.settings.frequency;
+        let intensity = self.settings.max_offset * self.trauma * self.trauma;
+        self.offset = Location {
+            x: Self::noise(0, noise_time) * intensity,
+            y: Self::noise(1, noise_time) * intensity,
+        };
+    }
+
+    /// Smooth value noise between -1.0 and 1.0
+    fn noise(seed: u32, time: f32) -> f32 {
+        let lattice = |i: i32| {
+            let hash = FastHash::hash(&[seed.to_le_bytes(), i.to_le_bytes()].concat());
+            // High bits of the hash depend on all input bytes
+            (hash >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+        };
+
+        let i = time.floor();
+        let t = time - i;
+        let t = t * t * (3.0 - 2.0 * t);
+        let (a, b) = (lattice(i as i32), lattice(i as i32 + 1));
+        a + (b - a) * t
+    }
 }
 
 #[repr(C)]
@@ -248,3 +474,78 @@ struct GpuCamera {
     angle_sin: f32,
     angle_tan: f32,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shake_decays_with_trauma() {
+        let mut shake = Shake::new(CameraShake {
+            max_offset: 2.0,
+            frequency: 10.0,
+            decay: 1.0,
+        });
+
+        shake.trauma = 1.0;
+        let mut max_offset: f32 = 0.0;
+        for _ in 0..10 {
+            shake.update(0.05);
+            max_offset = max_offset.max(shake.offset.x.abs()).max(shake.offset.y.abs());
+        }
+        assert!((shake.trauma - 0.5).abs() < 1e-4);
+        assert!(max_offset > 0.0 && max_offset <= 2.0);
+
+        shake.update(1.0);
+        assert_eq!(shake.trauma, 0.0);
+        assert_eq!(shake.offset, Location { x: 0.0, y: 0.0 });
+    }
+
+    #[test]
+    fn follow_modes_move_view_towards_target() {
+        let origin = Location::new(0.0, 0.0);
+        let target = Location::new(4.0, -1.0);
+        let velocity = Location::new(0.5, 0.0);
+        let follow = |mode| RenderCamera::calc_follow_loc(mode, origin, target, velocity, 10.0, 0.1);
+
+        assert_eq!(follow(CameraFollow::Lock), target);
+
+        let lerped = follow(CameraFollow::Lerp { rate: 5.0 });
+        assert!(lerped.x > 0.0 && lerped.x < 4.0 && lerped.y < 0.0 && lerped.y > -1.0);
+
+        let dead_zone = follow(CameraFollow::DeadZone {
+            half_width: 3.0,
+            half_height: 3.0,
+        });
+        assert_eq!(dead_zone, Location::new(1.0, 0.0));
+
+        let look_ahead = RenderCamera::calc_follow_loc(
+            CameraFollow::LookAhead {
+                frames: 4.0,
+                rate: 1000.0,
+            },
+            origin,
+            target,
+            velocity,
+            10.0,
+            0.1,
+        );
+        assert!((look_ahead.x - 6.0).abs() < 1e-3);
+
+        let teleport = Location::new(100.0, 0.0);
+        let snapped =
+            RenderCamera::calc_follow_loc(CameraFollow::Lerp { rate: 1.0 }, origin, teleport, velocity, 10.0, 0.1);
+        assert_eq!(snapped, teleport);
+    }
+
+    #[test]
+    fn shake_noise_is_smooth_and_bounded() {
+        let mut prev = Shake::noise(0, 0.0);
+        for step in 1..1000 {
+            let value = Shake::noise(0, step as f32 * 0.01);
+            assert!((-1.0..=1.0).contains(&value));
+            assert!((value - prev).abs() < 0.1);
+            prev = value;
+        }
+    }
+}
@@ -4,7 +4,7 @@ use winit::dpi::PhysicalSize;
 use derive_engine::RawData;
 
 use crate::core::GfxConstants;
-use crate::gfx::gfx_config::{GfxConfig, Resolution};
+use crate::gfx::gfx_config::{ColorFilterOpts, DisplayAdjustments, GfxConfig, Resolution};
 use crate::gfx::{GfxFrameMode, GfxGlobalData};
 use crate::util::casting::{RawData, any_as_bytes, slice_as_bytes};
 
@@ -29,6 +29,8 @@ pub(crate) struct RenderGlobals {
     lighting_sun: f32,
 
     post_bloom: f32,
+    post_color_filter: ColorFilterOpts,
+    post_display: DisplayAdjustments,
 }
 
 impl RenderGlobals {
@@ -53,7 +55,7 @@ impl RenderGlobals {
         });
         let globals_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("globals_buffer"),
-            contents: slice_as_bytes(&[0; 16]),
+            contents: slice_as_bytes(&[0; 20]),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
         let globals_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
@@ -88,6 +90,8 @@ impl RenderGlobals {
             lighting_sun: 0.2,
 
             post_bloom: 0.1,
+            post_color_filter: default_config.color_filter,
+            post_display: default_config.display,
         }
     }
 
@@ -126,6 +130,14 @@ impl RenderGlobals {
         self.frame_mode = frame_mode;
     }
 
+    pub(crate) fn set_color_filter(&mut self, color_filter: ColorFilterOpts) {
+        self.post_color_filter = color_filter;
+    }
+
+    pub(crate) fn set_display_adjustments(&mut self, display: DisplayAdjustments) {
+        self.post_display = display.clamped();
+    }
+
     pub(crate) fn write_to_gpu(&self, queue: &wgpu::Queue) {
         let gpu_globals = GpuGlobals {
             frame: self.frame,
@@ -142,6 +154,10 @@ impl RenderGlobals {
             lighting_sun: self.lighting_sun,
             lighting_unused: 0.0,
             post_bloom: self.post_bloom,
+            post_color_filter: self.post_color_filter as u32,
+            post_gamma: self.post_display.gamma,
+            post_brightness: self.post_display.brightness,
+            post_contrast: self.post_display.contrast,
             padding: [0.0; 2],
         };
 
@@ -169,6 +185,10 @@ struct GpuGlobals {
     lighting_unused: f32,
 
     post_bloom: f32,
+    post_color_filter: u32,
+    post_gamma: f32,
+    post_brightness: f32,
+    post_contrast: f32,
 
     padding: [f32; 2],
 }
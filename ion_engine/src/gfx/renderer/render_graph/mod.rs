@@ -1,25 +1,33 @@
 use std::collections::VecDeque;
 
-use ion_common::Map;
+#[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+use ion_common::log_error;
+use ion_common::{Map, Set};
+use render_pass_distortion::RenderPassDistortion;
 use render_pass_final::RenderPassFinal;
 use render_pass_gbuf::RenderPassGBuf;
+use render_pass_impostor::RenderPassImpostor;
+use render_pass_msaa_resolve::RenderPassMsaaResolve;
 use render_pass_shadow::RenderPassShadow;
+use render_pass_shadow_lightmap::RenderPassShadowLightmap;
+use render_pass_ssr::RenderPassSsr;
 use wgpu::util::DeviceExt;
 
 use crate::{
     WASM_COMPATIBLE_RENDERING,
-    core::{GfxConstants, coordinates::ChunkLocation},
+    core::{FrameId, GfxConstants, coordinates::ChunkLocation},
     gfx::{
         GfxFrameData, GfxSpriteData,
         gfx_config::{GfxConfig, Resolution},
         renderer::{
-            gpu_data_types::{InstanceLight, InstanceSprite},
+            gpu_data_types::{GpuDrawIndexedIndirect, InstanceLight, InstanceSprite},
             render_graph::{
-                render_pass_bloom::RenderPassBloom, render_pass_light::RenderPassLight,
-                render_pass_post_1::RenderPassPost1, render_pass_post_2::RenderPassPost2,
-                render_pass_ssao::RenderPassSsao,
+                render_pass_bloom::RenderPassBloom, render_pass_distortion::DISTORTION_TARGET_FORMAT,
+                render_pass_light::RenderPassLight, render_pass_post_1::RenderPassPost1,
+                render_pass_post_2::RenderPassPost2, render_pass_ssao::RenderPassSsao,
+                render_pass_ssr::REFLECTION_TARGET_FORMAT,
             },
-            render_helpers::write_to_buffer,
+            render_helpers::{reserve_buffer, write_to_buffer},
         },
         textures::{
             Texture,
@@ -28,6 +36,16 @@ use crate::{
     },
     util::casting::slice_as_bytes,
 };
+#[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+use crate::{
+    gfx::renderer::gpu_data_types::{
+        SHADER_BLOOM_DS, SHADER_BLOOM_US, SHADER_DEBUG, SHADER_DEPTH_RESOLVE, SHADER_DISTORTION, SHADER_GBUF,
+        SHADER_GBUF_WASM, SHADER_IMPOSTOR, SHADER_LIGHT, SHADER_LIGHT_WASM, SHADER_POST_1, SHADER_POST_2, SHADER_SCALE,
+        SHADER_SHADOW, SHADER_SHADOW_LIGHTMAP, SHADER_SHADOW_WASM, SHADER_SSAO_BLUR, SHADER_SSAO_RAW, SHADER_SSR,
+        ShaderSource,
+    },
+    util::concurrency::block_on,
+};
 
 use super::{
     gpu_data_types::{build_index_vec, build_vertex_vec},
@@ -36,13 +54,23 @@ use super::{
 };
 
 mod render_pass_bloom;
+mod render_pass_distortion;
 mod render_pass_final;
 mod render_pass_gbuf;
+mod render_pass_impostor;
 mod render_pass_light;
+mod render_pass_msaa_resolve;
 mod render_pass_post_1;
 mod render_pass_post_2;
 mod render_pass_shadow;
+mod render_pass_shadow_lightmap;
 mod render_pass_ssao;
+mod render_pass_ssr;
+
+/// Device features required for batching all sprite draws of a pass into a single indirect submission.
+/// If the adapter lacks these, draw calls are submitted one by one.
+pub(super) const INDIRECT_DRAW_FEATURES: wgpu::Features =
+    wgpu::Features::MULTI_DRAW_INDIRECT.union(wgpu::Features::INDIRECT_FIRST_INSTANCE);
 
 pub(super) struct RenderGraph {
     vertex_buffer: wgpu::Buffer,
@@ -51,15 +79,25 @@ pub(super) struct RenderGraph {
     free_buffers: VecDeque<Buffers>,
     chunk_buffers: Map<ChunkLocation, Buffers>,
     dynamic_buffers: Buffers,
+    indirect_draws: Option<IndirectDraws>,
+    lod_camera_scale: Option<f32>,
+    baked_shadows: bool,
+    reflections: bool,
+    msaa_samples: u32,
 
     render_pass_gbuf: Option<RenderPassGBuf>,
+    render_pass_impostor: Option<RenderPassImpostor>,
+    render_pass_msaa_resolve: Option<RenderPassMsaaResolve>,
     render_pass_final: Option<RenderPassFinal>,
     render_pass_light: Option<RenderPassLight>,
     render_pass_shadow: Option<RenderPassShadow>,
+    render_pass_shadow_lightmap: Option<RenderPassShadowLightmap>,
     render_pass_ssao: Option<RenderPassSsao>,
     render_pass_post_1: Option<RenderPassPost1>,
     render_pass_post_2: Option<RenderPassPost2>,
     render_pass_bloom: Option<RenderPassBloom>,
+    render_pass_distortion: Option<RenderPassDistortion>,
+    render_pass_ssr: Option<RenderPassSsr>,
 
     target_color: Option<Texture>,
     target_normal: Option<Texture>,
@@ -70,6 +108,14 @@ pub(super) struct RenderGraph {
     target_bloom: Option<Texture>,
     target_post_1: Option<Texture>,
     target_post_2: Option<Texture>,
+    target_distortion: Option<Texture>,
+    target_reflection: Option<Texture>,
+
+    // Multisampled gbuffer targets, only present when MSAA is enabled
+    target_color_msaa: Option<Texture>,
+    target_normal_msaa: Option<Texture>,
+    target_height_id_msaa: Option<Texture>,
+    target_depth_msaa: Option<Texture>,
 
     linear_sampler: wgpu::Sampler,
     nearest_sampler: wgpu::Sampler,
@@ -92,78 +138,75 @@ impl RenderGraph {
             usage: wgpu::BufferUsages::INDEX,
         });
 
-        let linear_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Linear,
-            mipmap_filter: wgpu::FilterMode::Linear,
-            ..Default::default()
-        });
-
-        let nearest_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Nearest,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
-        });
-
-        let mirror_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::MirrorRepeat,
-            address_mode_v: wgpu::AddressMode::MirrorRepeat,
-            address_mode_w: wgpu::AddressMode::MirrorRepeat,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Linear,
-            mipmap_filter: wgpu::FilterMode::Linear,
-            ..Default::default()
-        });
+        let linear_sampler = build_sampler(device, wgpu::AddressMode::ClampToEdge, wgpu::FilterMode::Linear, 1);
+        let nearest_sampler = build_sampler(device, wgpu::AddressMode::ClampToEdge, wgpu::FilterMode::Nearest, 1);
+        let mirror_sampler = build_sampler(device, wgpu::AddressMode::MirrorRepeat, wgpu::FilterMode::Linear, 1);
 
         let dynamic_buffers = Buffers {
             color_buf: device.create_buffer(&wgpu::BufferDescriptor {
                 label: Some("dynamic_color_buffer"),
                 size: 128 * size_of::<InstanceSprite>() as u64,
-                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
                 mapped_at_creation: false,
             }),
             shadow_buf: device.create_buffer(&wgpu::BufferDescriptor {
                 label: Some("dynamic_shadow_buffer"),
                 size: 128 * size_of::<InstanceSprite>() as u64,
-                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
                 mapped_at_creation: false,
             }),
             light_buf: device.create_buffer(&wgpu::BufferDescriptor {
                 label: Some("dynamic_light_buffer"),
                 size: 128 * size_of::<InstanceLight>() as u64,
-                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }),
+            distortion_buf: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("dynamic_distortion_buffer"),
+                size: 128 * size_of::<InstanceSprite>() as u64,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
                 mapped_at_creation: false,
             }),
             color_draw_calls: Vec::new(),
             shadow_draw_calls: Vec::new(),
             light_draw_calls: Vec::new(),
+            distortion_draw_calls: Vec::new(),
             color_draw_calls_wasm: Vec::new(),
             shadow_draw_calls_wasm: Vec::new(),
             light_draw_calls_wasm: Vec::new(),
         };
 
+        let indirect_draws = if !WASM_COMPATIBLE_RENDERING && device.features().contains(INDIRECT_DRAW_FEATURES) {
+            Some(IndirectDraws::new(device))
+        } else {
+            None
+        };
+
         Self {
             vertex_buffer,
             index_buffer,
             render_pass_gbuf: None,
+            render_pass_impostor: None,
+            render_pass_msaa_resolve: None,
             render_pass_final: None,
             render_pass_light: None,
             render_pass_shadow: None,
+            render_pass_shadow_lightmap: None,
             render_pass_ssao: None,
             render_pass_post_1: None,
             render_pass_post_2: None,
             render_pass_bloom: None,
+            render_pass_distortion: None,
+            render_pass_ssr: None,
 
             free_buffers: VecDeque::new(),
             chunk_buffers: Map::default(),
             dynamic_buffers,
+            indirect_draws,
+            lod_camera_scale: None,
+            baked_shadows: false,
+            reflections: false,
+            msaa_samples: 1,
 
             target_color: None,
             target_normal: None,
@@ -174,6 +217,13 @@ impl RenderGraph {
             target_bloom: None,
             target_post_1: None,
             target_post_2: None,
+            target_distortion: None,
+            target_reflection: None,
+
+            target_color_msaa: None,
+            target_normal_msaa: None,
+            target_height_id_msaa: None,
+            target_depth_msaa: None,
 
             linear_sampler,
             nearest_sampler,
@@ -191,13 +241,46 @@ impl RenderGraph {
         gfx_config: &GfxConfig,
         surface_config: &wgpu::SurfaceConfiguration,
     ) {
+        // Samplers are bound when render targets are created, which always follows creating the passes
+        let anisotropy_clamp = gfx_config.anisotropy.clamp();
+        self.linear_sampler = build_sampler(
+            device,
+            wgpu::AddressMode::ClampToEdge,
+            wgpu::FilterMode::Linear,
+            anisotropy_clamp,
+        );
+        self.mirror_sampler = build_sampler(
+            device,
+            wgpu::AddressMode::MirrorRepeat,
+            wgpu::FilterMode::Linear,
+            anisotropy_clamp,
+        );
+
+        self.msaa_samples = if WASM_COMPATIBLE_RENDERING {
+            1
+        } else {
+            gfx_config.msaa.sample_count()
+        };
+
         self.render_pass_gbuf = Some(RenderPassGBuf::new(
             device,
             render_camera,
             render_globals,
             &texture_assets.bind_group_layout(),
+            self.msaa_samples,
         ));
 
+        if !WASM_COMPATIBLE_RENDERING {
+            self.render_pass_impostor = Some(RenderPassImpostor::new(
+                device,
+                render_camera,
+                render_globals,
+                self.msaa_samples,
+            ));
+        }
+        self.render_pass_msaa_resolve = (self.msaa_samples > 1).then(|| RenderPassMsaaResolve::new(device));
+        self.lod_camera_scale = gfx_config.lod_camera_scale;
+
         self.render_pass_light = Some(RenderPassLight::new(
             device,
             render_camera,
@@ -212,6 +295,12 @@ impl RenderGraph {
             &texture_assets.bind_group_layout(),
         ));
 
+        if !WASM_COMPATIBLE_RENDERING {
+            self.render_pass_shadow_lightmap =
+                Some(RenderPassShadowLightmap::new(device, render_camera, render_globals));
+        }
+        self.baked_shadows = gfx_config.baked_shadows;
+
         self.render_pass_ssao = Some(RenderPassSsao::new(
             device,
             queue,
@@ -224,6 +313,17 @@ impl RenderGraph {
         self.render_pass_post_2 = Some(RenderPassPost2::new(device, render_globals, render_camera));
         self.render_pass_bloom = Some(RenderPassBloom::new(device, render_camera, render_globals));
 
+        if !WASM_COMPATIBLE_RENDERING {
+            self.render_pass_distortion = Some(RenderPassDistortion::new(
+                device,
+                render_camera,
+                render_globals,
+                texture_assets.bind_group_layout(),
+            ));
+            self.render_pass_ssr = Some(RenderPassSsr::new(device, render_globals, render_camera));
+        }
+        self.reflections = gfx_config.reflections;
+
         self.render_pass_final = Some(RenderPassFinal::new(
             device,
             surface_config,
@@ -308,6 +408,62 @@ impl RenderGraph {
             1,
             "post_2_target",
         ));
+        // Cleared every frame by the distortion pass. Stays zero on wasm, where there is no distortion.
+        self.target_distortion = Some(Texture::new_from_empty(
+            device,
+            render_resolution,
+            DISTORTION_TARGET_FORMAT,
+            1,
+            "distortion_target",
+        ));
+        // Cleared every frame by the reflection pass. Stays zero on wasm, where there are no reflections.
+        self.target_reflection = Some(Texture::new_from_empty(
+            device,
+            render_resolution,
+            REFLECTION_TARGET_FORMAT,
+            1,
+            "reflection_target",
+        ));
+
+        if self.msaa_samples > 1 {
+            self.target_color_msaa = Some(Texture::new_multisampled(
+                device,
+                render_resolution,
+                ldr_target_format,
+                self.msaa_samples,
+                "color_target_msaa",
+            ));
+            self.target_normal_msaa = Some(Texture::new_multisampled(
+                device,
+                render_resolution,
+                ldr_target_format,
+                self.msaa_samples,
+                "normal_target_msaa",
+            ));
+            self.target_height_id_msaa = Some(Texture::new_multisampled(
+                device,
+                render_resolution,
+                ldr_target_format,
+                self.msaa_samples,
+                "height_id_target_msaa",
+            ));
+            self.target_depth_msaa = Some(Texture::new_multisampled(
+                device,
+                render_resolution,
+                wgpu::TextureFormat::Depth32Float,
+                self.msaa_samples,
+                "depth_target_msaa",
+            ));
+        } else {
+            self.target_color_msaa = None;
+            self.target_normal_msaa = None;
+            self.target_height_id_msaa = None;
+            self.target_depth_msaa = None;
+        }
+
+        if let Some(render_pass_msaa_resolve) = self.render_pass_msaa_resolve.as_ref() {
+            render_pass_msaa_resolve.set_render_graph(device, self);
+        }
 
         if let Some(render_pass_final) = self.render_pass_final.as_ref() {
             render_pass_final.set_render_graph(device, &self);
@@ -321,6 +477,10 @@ impl RenderGraph {
             render_pass_shadow.set_render_graph(device, &self);
         }
 
+        if let Some(render_pass_shadow_lightmap) = self.render_pass_shadow_lightmap.as_ref() {
+            render_pass_shadow_lightmap.set_render_graph(device, self);
+        }
+
         if let Some(render_pass_ssao) = self.render_pass_ssao.as_ref() {
             render_pass_ssao.set_render_graph(device, &self, render_resolution);
         }
@@ -336,6 +496,196 @@ impl RenderGraph {
         if let Some(render_pass_bloom) = self.render_pass_bloom.as_ref() {
             render_pass_bloom.set_render_graph(device, &self);
         }
+
+        if let Some(render_pass_ssr) = self.render_pass_ssr.as_ref() {
+            render_pass_ssr.set_render_graph(device, self);
+        }
+    }
+
+    /// Rebuilds the passes that use any of the changed shaders, and binds the render targets to them.
+    /// A pass that fails to build, for example because the shader no longer matches the pipeline layout, keeps its
+    /// old pipelines, so the game keeps running until the shader is fixed.
+    #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+    #[allow(clippy::too_many_arguments)]
+    pub fn reload_shaders(
+        &mut self,
+        changed: &[ShaderSource],
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        render_camera: &RenderCamera,
+        render_globals: &RenderGlobals,
+        texture_assets: &TextureAssets,
+        gfx_config: &GfxConfig,
+        surface_config: &wgpu::SurfaceConfiguration,
+    ) {
+        let render_resolution = gfx_config.render_resolution();
+        let uses = |shaders: &[ShaderSource]| {
+            shaders
+                .iter()
+                .any(|shader| changed.iter().any(|changed| changed.file_name == shader.file_name))
+        };
+
+        if uses(&[SHADER_GBUF, SHADER_GBUF_WASM])
+            && let Some(pass) = rebuild_pass(device, "gbuf", || {
+                RenderPassGBuf::new(
+                    device,
+                    render_camera,
+                    render_globals,
+                    texture_assets.bind_group_layout(),
+                    self.msaa_samples,
+                )
+            })
+        {
+            self.render_pass_gbuf = Some(pass);
+        }
+
+        if self.render_pass_impostor.is_some()
+            && uses(&[SHADER_IMPOSTOR])
+            && let Some(pass) = rebuild_pass(device, "impostor", || {
+                RenderPassImpostor::new(device, render_camera, render_globals, self.msaa_samples)
+            })
+        {
+            self.render_pass_impostor = Some(pass);
+        }
+
+        if self.render_pass_msaa_resolve.is_some()
+            && uses(&[SHADER_DEPTH_RESOLVE])
+            && let Some(pass) = rebuild_pass(device, "msaa_resolve", || {
+                let pass = RenderPassMsaaResolve::new(device);
+                pass.set_render_graph(device, self);
+                pass
+            })
+        {
+            self.render_pass_msaa_resolve = Some(pass);
+        }
+
+        if uses(&[SHADER_LIGHT, SHADER_LIGHT_WASM])
+            && let Some(pass) = rebuild_pass(device, "light", || {
+                let pass = RenderPassLight::new(
+                    device,
+                    render_camera,
+                    render_globals,
+                    texture_assets.bind_group_layout(),
+                );
+                pass.set_render_graph(device, self);
+                pass
+            })
+        {
+            self.render_pass_light = Some(pass);
+        }
+
+        if uses(&[SHADER_SHADOW, SHADER_SHADOW_WASM])
+            && let Some(pass) = rebuild_pass(device, "shadow", || {
+                let pass = RenderPassShadow::new(
+                    device,
+                    render_camera,
+                    render_globals,
+                    texture_assets.bind_group_layout(),
+                );
+                pass.set_render_graph(device, self);
+                pass
+            })
+        {
+            self.render_pass_shadow = Some(pass);
+        }
+
+        if self.render_pass_shadow_lightmap.is_some()
+            && uses(&[SHADER_SHADOW_LIGHTMAP])
+            && let Some(pass) = rebuild_pass(device, "shadow_lightmap", || {
+                let pass = RenderPassShadowLightmap::new(device, render_camera, render_globals);
+                pass.set_render_graph(device, self);
+                pass
+            })
+        {
+            self.render_pass_shadow_lightmap = Some(pass);
+        }
+
+        if uses(&[SHADER_SSAO_RAW, SHADER_SSAO_BLUR])
+            && let Some(pass) = rebuild_pass(device, "ssao", || {
+                let pass = RenderPassSsao::new(device, queue, render_camera, render_globals, gfx_config);
+                pass.set_render_graph(device, self, render_resolution);
+                pass
+            })
+        {
+            self.render_pass_ssao = Some(pass);
+        }
+
+        if uses(&[SHADER_POST_1])
+            && let Some(pass) = rebuild_pass(device, "post_1", || {
+                let pass = RenderPassPost1::new(device, render_globals, render_camera);
+                pass.set_render_graph(device, self);
+                pass
+            })
+        {
+            self.render_pass_post_1 = Some(pass);
+        }
+
+        if uses(&[SHADER_POST_2])
+            && let Some(pass) = rebuild_pass(device, "post_2", || {
+                let pass = RenderPassPost2::new(device, render_globals, render_camera);
+                pass.set_render_graph(device, self);
+                pass
+            })
+        {
+            self.render_pass_post_2 = Some(pass);
+        }
+
+        if uses(&[SHADER_BLOOM_DS, SHADER_BLOOM_US])
+            && let Some(pass) = rebuild_pass(device, "bloom", || {
+                let pass = RenderPassBloom::new(device, render_camera, render_globals);
+                pass.set_render_graph(device, self);
+                pass
+            })
+        {
+            self.render_pass_bloom = Some(pass);
+        }
+
+        if self.render_pass_distortion.is_some()
+            && uses(&[SHADER_DISTORTION])
+            && let Some(pass) = rebuild_pass(device, "distortion", || {
+                RenderPassDistortion::new(
+                    device,
+                    render_camera,
+                    render_globals,
+                    texture_assets.bind_group_layout(),
+                )
+            })
+        {
+            self.render_pass_distortion = Some(pass);
+        }
+
+        if self.render_pass_ssr.is_some()
+            && uses(&[SHADER_SSR])
+            && let Some(pass) = rebuild_pass(device, "ssr", || {
+                let pass = RenderPassSsr::new(device, render_globals, render_camera);
+                pass.set_render_graph(device, self);
+                pass
+            })
+        {
+            self.render_pass_ssr = Some(pass);
+        }
+
+        if uses(&[SHADER_SCALE, SHADER_DEBUG])
+            && let Some(pass) = rebuild_pass(device, "final", || {
+                let pass = RenderPassFinal::new(device, surface_config, render_globals, render_camera);
+                pass.set_render_graph(device, self);
+                pass
+            })
+        {
+            self.render_pass_final = Some(pass);
+        }
+    }
+
+    pub fn set_lod_camera_scale(&mut self, lod_camera_scale: Option<f32>) {
+        self.lod_camera_scale = lod_camera_scale;
+    }
+
+    pub fn set_baked_shadows(&mut self, baked_shadows: bool) {
+        self.baked_shadows = baked_shadows;
+    }
+
+    pub fn set_reflections(&mut self, reflections: bool) {
+        self.reflections = reflections;
     }
 
     pub fn render_graph_ready(&self) -> bool {
@@ -358,7 +708,14 @@ impl RenderGraph {
     ) {
         // Update buffers
         if WASM_COMPATIBLE_RENDERING {
-            self.update_buffers_wasm(device, queue, texture_assets, &gfx_frame_data.sprite_data);
+            self.update_buffers_wasm(
+                device,
+                queue,
+                texture_assets,
+                &gfx_frame_data.sprite_data,
+                gfx_frame_data.global_data.frame,
+                gfx_frame_data.timing_data.render_frame_offset,
+            );
         } else {
             self.update_buffers_native(
                 device,
@@ -366,7 +723,51 @@ impl RenderGraph {
                 render_camera,
                 texture_assets,
                 &gfx_frame_data.sprite_data,
+                gfx_frame_data.global_data.frame,
+                gfx_frame_data.timing_data.render_frame_offset,
+            );
+
+            let render_pass_impostor = self.render_pass_impostor.as_mut().unwrap();
+            render_pass_impostor.prepare(
+                device,
+                queue,
+                render_camera,
+                self.target_color.as_ref().unwrap().texture.width(),
+                self.lod_camera_scale,
+                &self.chunk_buffers,
+                &self.nearest_sampler,
             );
+
+            let render_pass_shadow_lightmap = self.render_pass_shadow_lightmap.as_mut().unwrap();
+            render_pass_shadow_lightmap.prepare(
+                device,
+                queue,
+                render_camera,
+                self.baked_shadows,
+                &self.chunk_buffers,
+                &self.linear_sampler,
+            );
+
+            if let Some(indirect_draws) = self.indirect_draws.as_mut() {
+                indirect_draws.update(
+                    device,
+                    queue,
+                    encoder,
+                    &self.chunk_buffers,
+                    &self.dynamic_buffers,
+                    render_pass_impostor.drawn_chunks(),
+                    render_pass_shadow_lightmap.drawn_chunks(),
+                );
+            }
+
+            self.render_pass_impostor
+                .as_ref()
+                .unwrap()
+                .bake(encoder, render_globals, self, texture_assets);
+            self.render_pass_shadow_lightmap
+                .as_ref()
+                .unwrap()
+                .bake(encoder, render_globals, self, texture_assets);
         }
 
         // Run all the render passes
@@ -375,6 +776,14 @@ impl RenderGraph {
             .unwrap()
             .render(encoder, render_camera, render_globals, &self, texture_assets);
 
+        if let Some(render_pass_impostor) = self.render_pass_impostor.as_ref() {
+            render_pass_impostor.render(encoder, render_camera, render_globals, self);
+        }
+
+        if let Some(render_pass_msaa_resolve) = self.render_pass_msaa_resolve.as_ref() {
+            render_pass_msaa_resolve.render(encoder, self);
+        }
+
         self.render_pass_light
             .as_ref()
             .unwrap()
@@ -385,6 +794,10 @@ impl RenderGraph {
             .unwrap()
             .render(encoder, render_camera, render_globals, &self, texture_assets);
 
+        if let Some(render_pass_shadow_lightmap) = self.render_pass_shadow_lightmap.as_ref() {
+            render_pass_shadow_lightmap.render(encoder, render_camera, render_globals, self);
+        }
+
         self.render_pass_post_1
             .as_ref()
             .unwrap()
@@ -395,11 +808,19 @@ impl RenderGraph {
             .unwrap()
             .render(encoder, render_camera, render_globals, &self);
 
+        if let Some(render_pass_ssr) = self.render_pass_ssr.as_ref() {
+            render_pass_ssr.render(encoder, render_camera, render_globals, self);
+        }
+
         self.render_pass_bloom
             .as_ref()
             .unwrap()
             .render(encoder, render_camera, render_globals);
 
+        if let Some(render_pass_distortion) = self.render_pass_distortion.as_ref() {
+            render_pass_distortion.render(encoder, render_camera, render_globals, self, texture_assets);
+        }
+
         self.render_pass_post_2
             .as_ref()
             .unwrap()
@@ -416,6 +837,41 @@ impl RenderGraph {
         );
     }
 
+    /// Color attachments of the gbuffer targets. With MSAA, sprites are drawn to the multisampled targets,
+    /// which are resolved to the regular targets at the end of each pass.
+    fn gbuf_color_attachments(&self) -> [Option<wgpu::RenderPassColorAttachment<'_>>; 3] {
+        [
+            gbuf_color_attachment(&self.target_color, &self.target_color_msaa),
+            gbuf_color_attachment(&self.target_normal, &self.target_normal_msaa),
+            gbuf_color_attachment(&self.target_height_id, &self.target_height_id_msaa),
+        ]
+    }
+
+    /// Depth target of the gbuffer passes. The multisampled depth is resolved by `RenderPassMsaaResolve`.
+    fn gbuf_depth_view(&self) -> &wgpu::TextureView {
+        &self
+            .target_depth_msaa
+            .as_ref()
+            .or(self.target_depth.as_ref())
+            .unwrap()
+            .texture_view
+    }
+
+    /// Whether the shadow sprites of the chunk are drawn from a baked lightmap this frame.
+    fn is_lightmap_chunk(&self, chunk: &ChunkLocation) -> bool {
+        self.render_pass_shadow_lightmap
+            .as_ref()
+            .is_some_and(|render_pass_shadow_lightmap| render_pass_shadow_lightmap.drawn_chunks().contains(chunk))
+    }
+
+    /// Whether the color sprites of the chunk are drawn from an impostor this frame.
+    fn is_impostor_chunk(&self, chunk: &ChunkLocation) -> bool {
+        self.render_pass_impostor
+            .as_ref()
+            .is_some_and(|render_pass_impostor| render_pass_impostor.drawn_chunks().contains(chunk))
+    }
+
+    #[allow(clippy::too_many_arguments)]
     fn update_buffers_native(
         &mut self,
         device: &wgpu::Device,
@@ -423,6 +879,8 @@ impl RenderGraph {
         render_camera: &RenderCamera,
         texture_assets: &TextureAssets,
         gfx_sprite_data: &GfxSpriteData,
+        frame: FrameId,
+        frame_offset: f32,
     ) {
         // Remove chunks that are no longer being rendered
         let chunks_to_remove: Vec<_> = self
@@ -440,35 +898,58 @@ impl RenderGraph {
         // Update chunk buffers that received new data
         for (chunk_location, chunk_data) in &gfx_sprite_data.chunked_gfx {
             if let Some(chunk_data) = chunk_data {
+                if let Some(render_pass_impostor) = self.render_pass_impostor.as_mut() {
+                    render_pass_impostor.invalidate(chunk_location);
+                }
+                if let Some(render_pass_shadow_lightmap) = self.render_pass_shadow_lightmap.as_mut() {
+                    render_pass_shadow_lightmap.invalidate(chunk_location);
+                }
+
                 let (
                     (instances_color, draw_calls_color),
                     (instances_shadow, draw_calls_shadow),
                     (instances_light, draw_calls_light),
-                ) = texture_assets.refs_to_draw_calls(&chunk_data, render_camera);
+                    (instances_distortion, draw_calls_distortion),
+                ) = texture_assets.refs_to_draw_calls(&chunk_data, render_camera, frame, frame_offset);
 
                 let buffers = self.chunk_buffers.entry(*chunk_location).or_insert_with(|| {
                     self.free_buffers.pop_front().unwrap_or_else(|| Buffers {
                         color_buf: device.create_buffer(&wgpu::BufferDescriptor {
                             label: Some("dynamic_color_buffer"),
                             size: 128 * size_of::<InstanceSprite>() as u64,
-                            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                            usage: wgpu::BufferUsages::VERTEX
+                                | wgpu::BufferUsages::COPY_DST
+                                | wgpu::BufferUsages::COPY_SRC,
                             mapped_at_creation: false,
                         }),
                         shadow_buf: device.create_buffer(&wgpu::BufferDescriptor {
                             label: Some("dynamic_shadow_buffer"),
                             size: 128 * size_of::<InstanceSprite>() as u64,
-                            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                            usage: wgpu::BufferUsages::VERTEX
+                                | wgpu::BufferUsages::COPY_DST
+                                | wgpu::BufferUsages::COPY_SRC,
                             mapped_at_creation: false,
                         }),
                         light_buf: device.create_buffer(&wgpu::BufferDescriptor {
                             label: Some("dynamic_light_buffer"),
                             size: 128 * size_of::<InstanceLight>() as u64,
-                            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                            usage: wgpu::BufferUsages::VERTEX
+                                | wgpu::BufferUsages::COPY_DST
+                                | wgpu::BufferUsages::COPY_SRC,
+                            mapped_at_creation: false,
+                        }),
+                        distortion_buf: device.create_buffer(&wgpu::BufferDescriptor {
+                            label: Some("dynamic_distortion_buffer"),
+                            size: 128 * size_of::<InstanceSprite>() as u64,
+                            usage: wgpu::BufferUsages::VERTEX
+                                | wgpu::BufferUsages::COPY_DST
+                                | wgpu::BufferUsages::COPY_SRC,
                             mapped_at_creation: false,
                         }),
                         color_draw_calls: Vec::new(),
                         shadow_draw_calls: Vec::new(),
                         light_draw_calls: Vec::new(),
+                        distortion_draw_calls: Vec::new(),
                         color_draw_calls_wasm: Vec::new(),
                         shadow_draw_calls_wasm: Vec::new(),
                         light_draw_calls_wasm: Vec::new(),
@@ -478,10 +959,12 @@ impl RenderGraph {
                 write_to_buffer(device, queue, &mut buffers.color_buf, &instances_color);
                 write_to_buffer(device, queue, &mut buffers.shadow_buf, &instances_shadow);
                 write_to_buffer(device, queue, &mut buffers.light_buf, &instances_light);
+                write_to_buffer(device, queue, &mut buffers.distortion_buf, &instances_distortion);
 
                 buffers.color_draw_calls = draw_calls_color;
                 buffers.shadow_draw_calls = draw_calls_shadow;
                 buffers.light_draw_calls = draw_calls_light;
+                buffers.distortion_draw_calls = draw_calls_distortion;
             }
         }
 
@@ -490,15 +973,23 @@ impl RenderGraph {
             (instances_color, draw_calls_color),
             (instances_shadow, draw_calls_shadow),
             (instances_light, draw_calls_light),
-        ) = texture_assets.refs_to_draw_calls(&gfx_sprite_data.dynamic_gfx, render_camera);
+            (instances_distortion, draw_calls_distortion),
+        ) = texture_assets.refs_to_draw_calls(&gfx_sprite_data.dynamic_gfx, render_camera, frame, frame_offset);
 
         write_to_buffer(device, queue, &mut self.dynamic_buffers.color_buf, &instances_color);
         write_to_buffer(device, queue, &mut self.dynamic_buffers.shadow_buf, &instances_shadow);
         write_to_buffer(device, queue, &mut self.dynamic_buffers.light_buf, &instances_light);
+        write_to_buffer(
+            device,
+            queue,
+            &mut self.dynamic_buffers.distortion_buf,
+            &instances_distortion,
+        );
 
         self.dynamic_buffers.color_draw_calls = draw_calls_color;
         self.dynamic_buffers.shadow_draw_calls = draw_calls_shadow;
         self.dynamic_buffers.light_draw_calls = draw_calls_light;
+        self.dynamic_buffers.distortion_draw_calls = draw_calls_distortion;
     }
 
     fn update_buffers_wasm(
@@ -507,6 +998,8 @@ impl RenderGraph {
         queue: &wgpu::Queue,
         texture_assets: &TextureAssets,
         gfx_sprite_data: &GfxSpriteData,
+        frame: FrameId,
+        frame_offset: f32,
     ) {
         // Remove chunks that are no longer being rendered
         let chunks_to_remove: Vec<_> = self
@@ -528,7 +1021,7 @@ impl RenderGraph {
                     (instances_color, draw_calls_color),
                     (instances_shadow, draw_calls_shadow),
                     (instances_light, draw_calls_light),
-                ) = texture_assets.refs_to_draw_calls_wasm(&chunk_data);
+                ) = texture_assets.refs_to_draw_calls_wasm(&chunk_data, frame, frame_offset);
 
                 let buffers = self.chunk_buffers.entry(*chunk_location).or_insert_with(|| {
                     self.free_buffers.pop_front().unwrap_or_else(|| Buffers {
@@ -550,9 +1043,17 @@ impl RenderGraph {
                             usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
                             mapped_at_creation: false,
                         }),
+                        // Distortions are not supported on wasm, so this stays empty
+                        distortion_buf: device.create_buffer(&wgpu::BufferDescriptor {
+                            label: Some("dynamic_distortion_buffer"),
+                            size: 128 * size_of::<InstanceSprite>() as u64,
+                            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                            mapped_at_creation: false,
+                        }),
                         color_draw_calls: Vec::new(),
                         shadow_draw_calls: Vec::new(),
                         light_draw_calls: Vec::new(),
+                        distortion_draw_calls: Vec::new(),
                         color_draw_calls_wasm: Vec::new(),
                         shadow_draw_calls_wasm: Vec::new(),
                         light_draw_calls_wasm: Vec::new(),
@@ -574,7 +1075,7 @@ impl RenderGraph {
             (instances_color, draw_calls_color),
             (instances_shadow, draw_calls_shadow),
             (instances_light, draw_calls_light),
-        ) = texture_assets.refs_to_draw_calls_wasm(&gfx_sprite_data.dynamic_gfx);
+        ) = texture_assets.refs_to_draw_calls_wasm(&gfx_sprite_data.dynamic_gfx, frame, frame_offset);
 
         write_to_buffer(device, queue, &mut self.dynamic_buffers.color_buf, &instances_color);
         write_to_buffer(device, queue, &mut self.dynamic_buffers.shadow_buf, &instances_shadow);
@@ -586,14 +1087,267 @@ impl RenderGraph {
     }
 }
 
+/// Anisotropic filtering requires all filters to be linear, so `anisotropy_clamp` is ignored for nearest samplers.
+fn build_sampler(
+    device: &wgpu::Device,
+    address_mode: wgpu::AddressMode,
+    filter: wgpu::FilterMode,
+    anisotropy_clamp: u16,
+) -> wgpu::Sampler {
+    device.create_sampler(&wgpu::SamplerDescriptor {
+        address_mode_u: address_mode,
+        address_mode_v: address_mode,
+        address_mode_w: address_mode,
+        mag_filter: filter,
+        min_filter: filter,
+        mipmap_filter: filter,
+        anisotropy_clamp: if filter == wgpu::FilterMode::Linear {
+            anisotropy_clamp
+        } else {
+            1
+        },
+        ..Default::default()
+    })
+}
+
+/// Builds a pass in a validation error scope. Returns none if building it failed, in which case the error is logged.
+#[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+fn rebuild_pass<P>(device: &wgpu::Device, name: &str, build: impl FnOnce() -> P) -> Option<P> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let pass = build();
+    match block_on(device.pop_error_scope()) {
+        Some(err) => {
+            log_error!("Failed to rebuild the {} pass, keeping the old one:\n{}", name, err);
+            None
+        }
+        None => Some(pass),
+    }
+}
+
+fn gbuf_color_attachment<'a>(
+    target: &'a Option<Texture>,
+    target_msaa: &'a Option<Texture>,
+) -> Option<wgpu::RenderPassColorAttachment<'a>> {
+    let target = &target.as_ref().unwrap().texture_view;
+    Some(wgpu::RenderPassColorAttachment {
+        view: target_msaa
+            .as_ref()
+            .map_or(target, |target_msaa| &target_msaa.texture_view),
+        resolve_target: target_msaa.as_ref().map(|_| target),
+        ops: wgpu::Operations {
+            load: wgpu::LoadOp::Load,
+            store: wgpu::StoreOp::Store,
+        },
+    })
+}
+
+/// Merged instance data and indirect draw arguments for all chunked and dynamic sprites.
+/// Chunk buffers are copied into a single instance buffer on the GPU every frame,
+/// so that each pass can render everything with one `multi_draw_indexed_indirect` call.
+struct IndirectDraws {
+    color: IndirectBatch,
+    shadow: IndirectBatch,
+    light: IndirectBatch,
+    distortion: IndirectBatch,
+}
+
+impl IndirectDraws {
+    fn new(device: &wgpu::Device) -> Self {
+        Self {
+            color: IndirectBatch::new(device, size_of::<InstanceSprite>() as u64, "indirect_color"),
+            shadow: IndirectBatch::new(device, size_of::<InstanceSprite>() as u64, "indirect_shadow"),
+            light: IndirectBatch::new(device, size_of::<InstanceLight>() as u64, "indirect_light"),
+            distortion: IndirectBatch::new(device, size_of::<InstanceSprite>() as u64, "indirect_distortion"),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        chunk_buffers: &Map<ChunkLocation, Buffers>,
+        dynamic_buffers: &Buffers,
+        impostor_chunks: &Set<ChunkLocation>,
+        lightmap_chunks: &Set<ChunkLocation>,
+    ) {
+        // Impostors replace only the color sprites of a chunk, and lightmaps only the shadow sprites
+        let no_chunks = Set::default();
+        self.color.update(
+            device,
+            queue,
+            encoder,
+            chunk_buffers,
+            dynamic_buffers,
+            impostor_chunks,
+            |buffers| (&buffers.color_buf, &buffers.color_draw_calls),
+        );
+        self.shadow.update(
+            device,
+            queue,
+            encoder,
+            chunk_buffers,
+            dynamic_buffers,
+            lightmap_chunks,
+            |buffers| (&buffers.shadow_buf, &buffers.shadow_draw_calls),
+        );
+        self.light.update(
+            device,
+            queue,
+            encoder,
+            chunk_buffers,
+            dynamic_buffers,
+            &no_chunks,
+            |buffers| (&buffers.light_buf, &buffers.light_draw_calls),
+        );
+        self.distortion.update(
+            device,
+            queue,
+            encoder,
+            chunk_buffers,
+            dynamic_buffers,
+            &no_chunks,
+            |buffers| (&buffers.distortion_buf, &buffers.distortion_draw_calls),
+        );
+    }
+}
+
+struct IndirectBatch {
+    instance_size: u64,
+    instance_buf: wgpu::Buffer,
+    indirect_buf: wgpu::Buffer,
+    draw_count: u32,
+}
+
+impl IndirectBatch {
+    fn new(device: &wgpu::Device, instance_size: u64, label: &str) -> Self {
+        Self {
+            instance_size,
+            instance_buf: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(&format!("{}_instance_buffer", label)),
+                size: 1024 * instance_size,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            indirect_buf: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(&format!("{}_indirect_buffer", label)),
+                size: 256 * size_of::<GpuDrawIndexedIndirect>() as u64,
+                usage: wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            draw_count: 0,
+        }
+    }
+
+    /// Copies the instances of all chunks and dynamic sprites into the merged instance buffer,
+    /// and builds the indirect arguments in the same order as the non-batched draw calls.
+    /// Chunks in `skip_chunks` are left out.
+    #[allow(clippy::too_many_arguments)]
+    fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        chunk_buffers: &Map<ChunkLocation, Buffers>,
+        dynamic_buffers: &Buffers,
+        skip_chunks: &Set<ChunkLocation>,
+        select: impl Fn(&Buffers) -> (&wgpu::Buffer, &Vec<DrawCall>),
+    ) {
+        // Draw calls of a buffer always cover a continuous range starting from zero
+        let instance_count =
+            |draw_calls: &Vec<DrawCall>| draw_calls.last().map(|call| call.draw_range.end).unwrap_or(0);
+
+        let mut chunks: Vec<_> = chunk_buffers
+            .iter()
+            .filter(|(chunk, _)| !skip_chunks.contains(chunk))
+            .collect();
+        chunks.sort_by_key(|(chunk, _)| **chunk);
+
+        let total_instances: u32 = chunks
+            .iter()
+            .map(|(_, buffers)| instance_count(select(buffers).1))
+            .sum::<u32>()
+            + instance_count(select(dynamic_buffers).1);
+
+        reserve_buffer(
+            device,
+            &mut self.instance_buf,
+            total_instances as u64 * self.instance_size,
+        );
+
+        let mut chunked_draws = Vec::new();
+        let mut first_instance = 0;
+        for (chunk, buffers) in chunks {
+            let (instance_buf, draw_calls) = select(buffers);
+            let count = instance_count(draw_calls);
+            if count > 0 {
+                encoder.copy_buffer_to_buffer(
+                    instance_buf,
+                    0,
+                    &self.instance_buf,
+                    first_instance as u64 * self.instance_size,
+                    count as u64 * self.instance_size,
+                );
+                chunked_draws.extend(draw_calls.iter().map(|draw_call| (chunk, draw_call, first_instance)));
+                first_instance += count;
+            }
+        }
+
+        chunked_draws.sort_by_key(|(chunk, draw_call, _)| (draw_call.layer, **chunk));
+
+        let (instance_buf, dynamic_draw_calls) = select(dynamic_buffers);
+        let dynamic_count = instance_count(dynamic_draw_calls);
+        if dynamic_count > 0 {
+            encoder.copy_buffer_to_buffer(
+                instance_buf,
+                0,
+                &self.instance_buf,
+                first_instance as u64 * self.instance_size,
+                dynamic_count as u64 * self.instance_size,
+            );
+        }
+
+        let indirect_args: Vec<_> = chunked_draws
+            .into_iter()
+            .map(|(_, draw_call, base)| (draw_call, base))
+            .chain(dynamic_draw_calls.iter().map(|draw_call| (draw_call, first_instance)))
+            .map(|(draw_call, base)| {
+                let index_range = draw_call.layout.draw_range();
+                GpuDrawIndexedIndirect {
+                    index_count: index_range.end - index_range.start,
+                    instance_count: draw_call.draw_range.end - draw_call.draw_range.start,
+                    first_index: index_range.start,
+                    base_vertex: 0,
+                    first_instance: base + draw_call.draw_range.start,
+                }
+            })
+            .collect();
+
+        write_to_buffer(device, queue, &mut self.indirect_buf, &indirect_args);
+        self.draw_count = indirect_args.len() as u32;
+    }
+
+    /// Executes all draws of this batch with a single indirect call.
+    /// Assumes that all render pass bindings are set, except for the instance buffer.
+    fn execute(&self, render_pass: &mut wgpu::RenderPass) {
+        if self.draw_count > 0 {
+            render_pass.set_vertex_buffer(1, self.instance_buf.slice(..));
+            render_pass.multi_draw_indexed_indirect(&self.indirect_buf, 0, self.draw_count);
+        }
+    }
+}
+
 struct Buffers {
     color_buf: wgpu::Buffer,
     shadow_buf: wgpu::Buffer,
     light_buf: wgpu::Buffer,
+    distortion_buf: wgpu::Buffer,
 
     color_draw_calls: Vec<DrawCall>,
     shadow_draw_calls: Vec<DrawCall>,
     light_draw_calls: Vec<DrawCall>,
+    distortion_draw_calls: Vec<DrawCall>,
 
     color_draw_calls_wasm: Vec<DrawCallWasm>,
     shadow_draw_calls_wasm: Vec<DrawCallWasm>,
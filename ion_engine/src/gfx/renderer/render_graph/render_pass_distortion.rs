@@ -0,0 +1,141 @@
+use crate::{
+    build_shader,
+    core::coordinates::ChunkLocation,
+    gfx::{
+        renderer::{
+            RenderGraph,
+            gpu_data_types::{InstanceSprite, SHADER_DISTORTION, Vertex},
+            render_camera::RenderCamera,
+            render_globals::RenderGlobals,
+            render_helpers::build_render_pipeline,
+        },
+        textures::texture_assets::TextureAssets,
+    },
+};
+
+/// Screen space offsets of the distortion sprites. Signed, so that the offsets of overlapping sprites can cancel out.
+pub(super) const DISTORTION_TARGET_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg16Float;
+
+/// Renders distortion sprites into offsets that warp the scene in the second post pass.
+/// Only supported by the native renderer.
+pub(super) struct RenderPassDistortion {
+    render_pipeline: wgpu::RenderPipeline,
+}
+
+impl RenderPassDistortion {
+    pub(super) fn new(
+        device: &wgpu::Device,
+        render_camera: &RenderCamera,
+        render_globals: &RenderGlobals,
+        asset_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("render_pipeline_layout_distortion"),
+            bind_group_layouts: &[
+                &render_globals.globals_bind_group_layout,
+                &render_camera.camera_bind_group_layout,
+                asset_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let shader = build_shader!(device, SHADER_DISTORTION);
+
+        let render_pipeline = build_render_pipeline(
+            device,
+            &render_pipeline_layout,
+            &shader,
+            &[Vertex::buffer_layout(), InstanceSprite::buffer_layout()],
+            &[Some(wgpu::ColorTargetState {
+                format: DISTORTION_TARGET_FORMAT,
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::One,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent::REPLACE,
+                }),
+                write_mask: wgpu::ColorWrites::RED | wgpu::ColorWrites::GREEN,
+            })],
+            None,
+            "render_pipeline_distortion",
+        );
+
+        Self { render_pipeline }
+    }
+
+    pub(super) fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        render_camera: &RenderCamera,
+        render_globals: &RenderGlobals,
+        render_graph: &RenderGraph,
+        texture_assets: &TextureAssets,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("render_pass_distortion"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &render_graph.target_distortion.as_ref().unwrap().texture_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            ..Default::default()
+        });
+
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(0, &render_globals.globals_bind_group, &[]);
+        render_pass.set_bind_group(1, &render_camera.camera_bind_group, &[]);
+        render_pass.set_bind_group(2, texture_assets.bind_group(), &[]);
+
+        render_pass.set_vertex_buffer(0, render_graph.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(render_graph.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+
+        if let Some(indirect_draws) = render_graph.indirect_draws.as_ref() {
+            indirect_draws.distortion.execute(&mut render_pass);
+        } else {
+            self.execute_draw_calls_native(&mut render_pass, render_graph);
+        }
+    }
+
+    /// Executes draw calls for the native renderer.
+    /// Assumes that all render pass bindings are set, except for the instance buffer.
+    fn execute_draw_calls_native(&self, render_pass: &mut wgpu::RenderPass, render_graph: &RenderGraph) {
+        // ------------------ Chunked sprite rendering ------------------ //
+
+        let mut all_chunked_draw_calls = render_graph
+            .chunk_buffers
+            .iter()
+            .flat_map(|(chunk, buffers)| {
+                buffers
+                    .distortion_draw_calls
+                    .iter()
+                    .map(move |draw_call| (chunk, draw_call))
+            })
+            .collect::<Vec<_>>();
+
+        all_chunked_draw_calls.sort_by_key(|(chunk, draw_call)| (draw_call.layer, *chunk));
+
+        let mut prev_chunk: Option<ChunkLocation> = None;
+        for (chunk, draw_call) in all_chunked_draw_calls {
+            if prev_chunk.is_none() || prev_chunk.unwrap() != *chunk {
+                prev_chunk = Some(*chunk);
+                let buffers = render_graph.chunk_buffers.get(chunk).unwrap();
+                render_pass.set_vertex_buffer(1, buffers.distortion_buf.slice(..));
+            }
+
+            render_pass.draw_indexed(draw_call.layout.draw_range(), 0, draw_call.draw_range.clone());
+        }
+
+        // ------------------ Dynamic sprite rendering ------------------ //
+        render_pass.set_vertex_buffer(1, render_graph.dynamic_buffers.distortion_buf.slice(..));
+
+        for draw_call in render_graph.dynamic_buffers.distortion_draw_calls.iter() {
+            render_pass.draw_indexed(draw_call.layout.draw_range(), 0, draw_call.draw_range.clone());
+        }
+    }
+}
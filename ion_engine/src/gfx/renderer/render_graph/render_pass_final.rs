@@ -37,7 +37,7 @@ impl RenderPassFinal {
 
         let render_pipeline_layout_final = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("render_pipeline_layout_final"),
-            bind_group_layouts: &[&source_tex_bind_group_layout],
+            bind_group_layouts: &[&source_tex_bind_group_layout, &render_globals.globals_bind_group_layout],
             push_constant_ranges: &[],
         });
 
@@ -170,6 +170,7 @@ impl RenderPassFinal {
         });
 
         render_pass.set_bind_group(0, render_sources.as_ref().unwrap(), &[]);
+        render_pass.set_bind_group(1, &render_globals.globals_bind_group, &[]);
         render_pass.set_pipeline(&self.render_pipeline_final);
         render_pass.draw(0..6, 0..1);
 
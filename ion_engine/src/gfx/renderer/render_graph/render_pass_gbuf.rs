@@ -7,7 +7,7 @@ use crate::{
             gpu_data_types::{InstanceSprite, SHADER_GBUF, SHADER_GBUF_WASM, Vertex},
             render_camera::RenderCamera,
             render_globals::RenderGlobals,
-            render_helpers::build_render_pipeline,
+            render_helpers::{build_render_pipeline, build_render_pipeline_multisampled},
         },
         textures::texture_assets::TextureAssets,
     },
@@ -15,6 +15,8 @@ use crate::{
 
 pub struct RenderPassGBuf {
     render_pipeline: wgpu::RenderPipeline,
+    /// Single-sampled variant of the pipeline for baking impostors. `None` if the main pipeline is single-sampled.
+    bake_render_pipeline: Option<wgpu::RenderPipeline>,
 }
 
 impl RenderPassGBuf {
@@ -23,6 +25,7 @@ impl RenderPassGBuf {
         render_camera: &RenderCamera,
         render_globals: &RenderGlobals,
         asset_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
     ) -> Self {
         let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("render_pipeline_layout_gbuf"),
@@ -46,60 +49,80 @@ impl RenderPassGBuf {
             wgpu::TextureFormat::Bgra8UnormSrgb
         };
 
-        let render_pipeline = build_render_pipeline(
-            device,
-            &render_pipeline_layout,
-            &shader,
-            &[Vertex::buffer_layout(), InstanceSprite::buffer_layout()],
-            &[
-                Some(wgpu::ColorTargetState {
-                    format: target_format,
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent {
-                            src_factor: wgpu::BlendFactor::SrcAlpha,
-                            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                            operation: wgpu::BlendOperation::Add,
-                        },
-                        alpha: wgpu::BlendComponent::OVER,
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
+        let targets = [
+            Some(wgpu::ColorTargetState {
+                format: target_format,
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::SrcAlpha,
+                        dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent::OVER,
                 }),
-                Some(wgpu::ColorTargetState {
-                    format: target_format,
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent {
-                            src_factor: wgpu::BlendFactor::SrcAlpha,
-                            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                            operation: wgpu::BlendOperation::Add,
-                        },
-                        alpha: wgpu::BlendComponent::OVER,
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
+                write_mask: wgpu::ColorWrites::ALL,
+            }),
+            Some(wgpu::ColorTargetState {
+                format: target_format,
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::SrcAlpha,
+                        dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent::OVER,
                 }),
-                Some(wgpu::ColorTargetState {
-                    format: target_format,
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent {
-                            src_factor: wgpu::BlendFactor::SrcAlpha,
-                            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-                            operation: wgpu::BlendOperation::Add,
-                        },
-                        alpha: wgpu::BlendComponent::OVER,
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
+                write_mask: wgpu::ColorWrites::ALL,
+            }),
+            Some(wgpu::ColorTargetState {
+                format: target_format,
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::SrcAlpha,
+                        dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent::OVER,
                 }),
-            ],
-            Some(wgpu::DepthStencilState {
-                format: wgpu::TextureFormat::Depth32Float,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::LessEqual,
-                stencil: wgpu::StencilState::default(),
-                bias: wgpu::DepthBiasState::default(),
+                write_mask: wgpu::ColorWrites::ALL,
             }),
+        ];
+
+        let depth_stencil = Some(wgpu::DepthStencilState {
+            format: wgpu::TextureFormat::Depth32Float,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        });
+
+        let render_pipeline = build_render_pipeline_multisampled(
+            device,
+            &render_pipeline_layout,
+            &shader,
+            &[Vertex::buffer_layout(), InstanceSprite::buffer_layout()],
+            &targets,
+            depth_stencil.clone(),
+            sample_count,
             "render_pipeline_gbuf",
         );
 
-        Self { render_pipeline }
+        let bake_render_pipeline = (sample_count > 1).then(|| {
+            build_render_pipeline(
+                device,
+                &render_pipeline_layout,
+                &shader,
+                &[Vertex::buffer_layout(), InstanceSprite::buffer_layout()],
+                &targets,
+                depth_stencil,
+                "render_pipeline_gbuf_bake",
+            )
+        });
+
+        Self {
+            render_pipeline,
+            bake_render_pipeline,
+        }
     }
 
     pub fn render(
@@ -112,34 +135,9 @@ impl RenderPassGBuf {
     ) {
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("render_pass_gbuf"),
-            color_attachments: &[
-                Some(wgpu::RenderPassColorAttachment {
-                    view: &render_graph.target_color.as_ref().unwrap().texture_view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Load,
-                        store: wgpu::StoreOp::Store,
-                    },
-                }),
-                Some(wgpu::RenderPassColorAttachment {
-                    view: &render_graph.target_normal.as_ref().unwrap().texture_view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Load,
-                        store: wgpu::StoreOp::Store,
-                    },
-                }),
-                Some(wgpu::RenderPassColorAttachment {
-                    view: &render_graph.target_height_id.as_ref().unwrap().texture_view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Load,
-                        store: wgpu::StoreOp::Store,
-                    },
-                }),
-            ],
+            color_attachments: &render_graph.gbuf_color_attachments(),
             depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                view: &render_graph.target_depth.as_ref().unwrap().texture_view,
+                view: render_graph.gbuf_depth_view(),
                 depth_ops: Some(wgpu::Operations {
                     load: wgpu::LoadOp::Clear(1.0),
                     store: wgpu::StoreOp::Store,
@@ -160,7 +158,41 @@ impl RenderPassGBuf {
             self.execute_draw_calls_wasm(&mut render_pass, render_graph, texture_assets);
         } else {
             render_pass.set_bind_group(2, texture_assets.bind_group(), &[]);
-            self.execute_draw_calls_native(&mut render_pass, render_graph);
+            if let Some(indirect_draws) = render_graph.indirect_draws.as_ref() {
+                indirect_draws.color.execute(&mut render_pass);
+            } else {
+                self.execute_draw_calls_native(&mut render_pass, render_graph);
+            }
+        }
+    }
+
+    /// Renders the color sprites of a single chunk with a custom camera. Used for baking chunk impostors.
+    /// Only supported by the native renderer.
+    pub(super) fn render_chunk(
+        &self,
+        render_pass: &mut wgpu::RenderPass,
+        camera_bind_group: &wgpu::BindGroup,
+        render_globals: &RenderGlobals,
+        render_graph: &RenderGraph,
+        texture_assets: &TextureAssets,
+        chunk: &ChunkLocation,
+    ) {
+        let buffers = render_graph.chunk_buffers.get(chunk).unwrap();
+
+        render_pass.set_pipeline(self.bake_render_pipeline.as_ref().unwrap_or(&self.render_pipeline));
+        render_pass.set_bind_group(0, &render_globals.globals_bind_group, &[]);
+        render_pass.set_bind_group(1, camera_bind_group, &[]);
+        render_pass.set_bind_group(2, texture_assets.bind_group(), &[]);
+
+        render_pass.set_vertex_buffer(0, render_graph.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, buffers.color_buf.slice(..));
+        render_pass.set_index_buffer(render_graph.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+
+        let mut draw_calls: Vec<_> = buffers.color_draw_calls.iter().collect();
+        draw_calls.sort_by_key(|draw_call| draw_call.layer);
+
+        for draw_call in draw_calls {
+            render_pass.draw_indexed(draw_call.layout.draw_range(), 0, draw_call.draw_range.clone());
         }
     }
 
@@ -172,6 +204,7 @@ impl RenderPassGBuf {
         let mut all_chunked_draw_calls = render_graph
             .chunk_buffers
             .iter()
+            .filter(|(chunk, _)| !render_graph.is_impostor_chunk(chunk))
             .flat_map(|(chunk, buffers)| buffers.color_draw_calls.iter().map(move |draw_call| (chunk, draw_call)))
             .collect::<Vec<_>>();
 
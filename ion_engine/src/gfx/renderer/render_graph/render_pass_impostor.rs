@@ -0,0 +1,422 @@
+use ion_common::{Map, Set, math::matrix::Matrix4x4};
+
+use crate::{
+    build_shader,
+    core::{
+        CHUNK_SIZE,
+        coordinates::{ChunkLocation, Location},
+    },
+    gfx::{
+        gfx_config::Resolution,
+        renderer::{
+            gpu_data_types::{ImpostorVertex, SHADER_IMPOSTOR},
+            render_camera::RenderCamera,
+            render_globals::RenderGlobals,
+            render_helpers::{build_render_pipeline_multisampled, build_tex_bind_group_layout, write_to_buffer},
+        },
+        textures::{Texture, texture_assets::TextureAssets},
+    },
+};
+
+use super::{Buffers, RenderGraph};
+
+/// Half of the side of the square area that is baked into an impostor, in camera view units.
+/// Leaves room above the chunk for sprites that extend past the chunk, such as trees and buildings.
+const IMPOSTOR_HALF_EXTENT: f32 = CHUNK_SIZE as f32 * 0.75;
+const IMPOSTOR_MIN_RESOLUTION: u32 = 64;
+const IMPOSTOR_MAX_RESOLUTION: u32 = 512;
+/// Chunks that don't have an impostor yet are baked gradually, and rendered from sprites in the meantime.
+const IMPOSTOR_BAKES_PER_FRAME: usize = 4;
+
+/// Level-of-detail rendering for distant chunks.
+///
+/// When the camera scale reaches `GfxConfig::lod_camera_scale`, the color sprites of each chunk are baked once
+/// into low-res gbuffer textures with the gbuffer pipeline, and the chunk is then drawn as a single textured quad.
+/// Impostors are rebaked when the chunk receives new data, and dropped when the camera zooms back in.
+/// Shadows and lights of impostor chunks are still rendered from sprites, and sprite animations are frozen
+/// to the frame they were baked on.
+///
+/// Only supported by the native renderer.
+pub(super) struct RenderPassImpostor {
+    render_pipeline: wgpu::RenderPipeline,
+    source_tex_bind_group_layout: wgpu::BindGroupLayout,
+
+    bake_cameras: Vec<(wgpu::Buffer, wgpu::BindGroup)>,
+    bake_depth: Option<Texture>,
+    bake_resolution: u32,
+    pending_bakes: Vec<ChunkLocation>,
+
+    impostors: Map<ChunkLocation, Impostor>,
+    drawn_chunks: Set<ChunkLocation>,
+    draw_order: Vec<ChunkLocation>,
+    vertex_buffer: wgpu::Buffer,
+}
+
+struct Impostor {
+    target_color: Texture,
+    target_normal: Texture,
+    target_height_id: Texture,
+    bind_group: wgpu::BindGroup,
+    vertices: [ImpostorVertex; 6],
+}
+
+impl RenderPassImpostor {
+    pub(super) fn new(
+        device: &wgpu::Device,
+        render_camera: &RenderCamera,
+        render_globals: &RenderGlobals,
+        sample_count: u32,
+    ) -> Self {
+        let source_tex_bind_group_layout =
+            build_tex_bind_group_layout(device, 3, false, "source_tex_bind_group_layout_impostor");
+
+        let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("render_pipeline_layout_impostor"),
+            bind_group_layouts: &[
+                &render_globals.globals_bind_group_layout,
+                &render_camera.camera_bind_group_layout,
+                &source_tex_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let shader = build_shader!(device, SHADER_IMPOSTOR);
+
+        // Baked targets are already blended over a transparent background, so they are premultiplied
+        let target_state = Some(wgpu::ColorTargetState {
+            format: wgpu::TextureFormat::Bgra8UnormSrgb,
+            blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+            write_mask: wgpu::ColorWrites::ALL,
+        });
+
+        let render_pipeline = build_render_pipeline_multisampled(
+            device,
+            &render_pipeline_layout,
+            &shader,
+            &[ImpostorVertex::buffer_layout()],
+            &[target_state.clone(), target_state.clone(), target_state],
+            Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            sample_count,
+            "render_pipeline_impostor",
+        );
+
+        let bake_cameras = (0..IMPOSTOR_BAKES_PER_FRAME)
+            .map(|i| render_camera.create_bake_camera(device, &format!("impostor_bake_{}", i)))
+            .collect();
+
+        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("impostor_vertex_buffer"),
+            size: 64 * 6 * size_of::<ImpostorVertex>() as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            render_pipeline,
+            source_tex_bind_group_layout,
+
+            bake_cameras,
+            bake_depth: None,
+            bake_resolution: 0,
+            pending_bakes: Vec::new(),
+
+            impostors: Map::default(),
+            drawn_chunks: Set::default(),
+            draw_order: Vec::new(),
+            vertex_buffer,
+        }
+    }
+
+    /// Chunks that are drawn from impostors this frame. Their color sprites must not be drawn by the gbuffer pass.
+    pub(super) fn drawn_chunks(&self) -> &Set<ChunkLocation> {
+        &self.drawn_chunks
+    }
+
+    /// Drops the impostor of a chunk, so that it is rebaked from the new chunk data.
+    pub(super) fn invalidate(&mut self, chunk: &ChunkLocation) {
+        self.impostors.remove(chunk);
+    }
+
+    /// Selects the chunks drawn from impostors this frame, and prepares bakes for chunks that have no impostor yet.
+    /// Must be called after chunk buffers are updated, and before the gbuffer pass.
+    #[allow(clippy::too_many_arguments)]
+    pub(super) fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        render_camera: &RenderCamera,
+        render_width: u32,
+        lod_camera_scale: Option<f32>,
+        chunk_buffers: &Map<ChunkLocation, Buffers>,
+        sampler: &wgpu::Sampler,
+    ) {
+        self.pending_bakes.clear();
+        self.drawn_chunks.clear();
+        self.draw_order.clear();
+
+        let Some(lod_camera_scale) = lod_camera_scale.filter(|&scale| render_camera.scale() >= scale) else {
+            self.impostors.clear();
+            return;
+        };
+
+        let bake_resolution = Self::calc_bake_resolution(render_width, lod_camera_scale);
+        if bake_resolution != self.bake_resolution {
+            self.impostors.clear();
+            self.bake_resolution = bake_resolution;
+            self.bake_depth = Some(Texture::new_from_empty(
+                device,
+                Resolution {
+                    width: bake_resolution,
+                    height: bake_resolution,
+                },
+                wgpu::TextureFormat::Depth32Float,
+                1,
+                "impostor_bake_depth",
+            ));
+        }
+
+        self.impostors.retain(|chunk, _| chunk_buffers.contains_key(chunk));
+
+        let mut chunks: Vec<_> = chunk_buffers
+            .iter()
+            .filter(|(_, buffers)| !buffers.color_draw_calls.is_empty())
+            .map(|(chunk, _)| *chunk)
+            .collect();
+        chunks.sort();
+
+        let mut vertices = Vec::with_capacity(chunks.len() * 6);
+        for chunk in chunks {
+            if !self.impostors.contains_key(&chunk) {
+                if self.pending_bakes.len() >= IMPOSTOR_BAKES_PER_FRAME {
+                    continue;
+                }
+
+                let center = Location::from(chunk) + Location::new(CHUNK_SIZE as f32 / 2.0, CHUNK_SIZE as f32 / 2.0);
+                let (camera_buffer, _) = &self.bake_cameras[self.pending_bakes.len()];
+                render_camera.write_bake_camera_to_gpu(queue, camera_buffer, center, IMPOSTOR_HALF_EXTENT);
+
+                let vertices = Self::calc_vertices(render_camera.calc_bake_vp_mat(center, IMPOSTOR_HALF_EXTENT));
+                let impostor = self.create_impostor(device, sampler, vertices);
+                self.impostors.insert(chunk, impostor);
+                self.pending_bakes.push(chunk);
+            }
+
+            vertices.extend_from_slice(&self.impostors[&chunk].vertices);
+            self.drawn_chunks.insert(chunk);
+            self.draw_order.push(chunk);
+        }
+
+        write_to_buffer(device, queue, &mut self.vertex_buffer, &vertices);
+    }
+
+    /// Bakes the chunks selected in `prepare` into their impostor textures.
+    pub(super) fn bake(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        render_globals: &RenderGlobals,
+        render_graph: &RenderGraph,
+        texture_assets: &TextureAssets,
+    ) {
+        for (chunk, (_, camera_bind_group)) in self.pending_bakes.iter().zip(&self.bake_cameras) {
+            let impostor = &self.impostors[chunk];
+            let clear = wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT);
+
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("render_pass_impostor_bake"),
+                color_attachments: &[
+                    color_attachment(&impostor.target_color, clear),
+                    color_attachment(&impostor.target_normal, clear),
+                    color_attachment(&impostor.target_height_id, clear),
+                ],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.bake_depth.as_ref().unwrap().texture_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Discard,
+                    }),
+                    stencil_ops: None,
+                }),
+                ..Default::default()
+            });
+
+            render_graph.render_pass_gbuf.as_ref().unwrap().render_chunk(
+                &mut render_pass,
+                camera_bind_group,
+                render_globals,
+                render_graph,
+                texture_assets,
+                chunk,
+            );
+        }
+    }
+
+    /// Draws the impostors into the gbuffer. Must run after the gbuffer pass, since that clears the depth.
+    pub(super) fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        render_camera: &RenderCamera,
+        render_globals: &RenderGlobals,
+        render_graph: &RenderGraph,
+    ) {
+        if self.draw_order.is_empty() {
+            return;
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("render_pass_impostor"),
+            color_attachments: &render_graph.gbuf_color_attachments(),
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: render_graph.gbuf_depth_view(),
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            ..Default::default()
+        });
+
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(0, &render_globals.globals_bind_group, &[]);
+        render_pass.set_bind_group(1, &render_camera.camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+
+        for (i, chunk) in self.draw_order.iter().enumerate() {
+            let first_vertex = i as u32 * 6;
+            render_pass.set_bind_group(2, &self.impostors[chunk].bind_group, &[]);
+            render_pass.draw(first_vertex..first_vertex + 6, 0..1);
+        }
+    }
+
+    fn create_impostor(
+        &self,
+        device: &wgpu::Device,
+        sampler: &wgpu::Sampler,
+        vertices: [ImpostorVertex; 6],
+    ) -> Impostor {
+        let resolution = Resolution {
+            width: self.bake_resolution,
+            height: self.bake_resolution,
+        };
+        let format = wgpu::TextureFormat::Bgra8UnormSrgb;
+
+        let target_color = Texture::new_from_empty(device, resolution, format, 1, "impostor_color");
+        let target_normal = Texture::new_from_empty(device, resolution, format, 1, "impostor_normal");
+        let target_height_id = Texture::new_from_empty(device, resolution, format, 1, "impostor_height_id");
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.source_tex_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&target_color.texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&target_normal.texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&target_height_id.texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+            label: Some("impostor_bind_group"),
+        });
+
+        Impostor {
+            target_color,
+            target_normal,
+            target_height_id,
+            bind_group,
+            vertices,
+        }
+    }
+
+    /// Impostors are baked at the pixel density of the camera at the LOD threshold,
+    /// so they are never magnified on screen.
+    fn calc_bake_resolution(render_width: u32, lod_camera_scale: f32) -> u32 {
+        let pixels_per_unit = render_width as f32 / (2.0 * lod_camera_scale);
+        ((2.0 * IMPOSTOR_HALF_EXTENT * pixels_per_unit).ceil() as u32)
+            .clamp(IMPOSTOR_MIN_RESOLUTION, IMPOSTOR_MAX_RESOLUTION)
+    }
+
+    /// World space quad covered by a bake, as two triangles.
+    /// Sprites are flat in the world plane, so projecting the bake back onto it reproduces the sprites exactly.
+    pub(super) fn calc_vertices(bake_vp_mat: Matrix4x4) -> [ImpostorVertex; 6] {
+        let vp_mat_inv = bake_vp_mat
+            .inverse()
+            .expect("view_projection matrix should always be invertible");
+
+        let vertex = |clip_x: f32, clip_y: f32| {
+            let world_loc = vp_mat_inv * [clip_x, clip_y, 0.0, 1.0];
+            ImpostorVertex {
+                world_loc: [world_loc[0], world_loc[1]],
+                tex_coords: [(clip_x + 1.0) * 0.5, (1.0 - clip_y) * 0.5],
+            }
+        };
+
+        let top_left = vertex(-1.0, 1.0);
+        let top_right = vertex(1.0, 1.0);
+        let bottom_left = vertex(-1.0, -1.0);
+        let bottom_right = vertex(1.0, -1.0);
+
+        [top_left, bottom_left, bottom_right, top_left, bottom_right, top_right]
+    }
+}
+
+fn color_attachment(texture: &Texture, load: wgpu::LoadOp<wgpu::Color>) -> Option<wgpu::RenderPassColorAttachment<'_>> {
+    Some(wgpu::RenderPassColorAttachment {
+        view: &texture.texture_view,
+        resolve_target: None,
+        ops: wgpu::Operations {
+            load,
+            store: wgpu::StoreOp::Store,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bake_resolution_matches_threshold_pixel_density() {
+        // 960 px over 80 units at the threshold gives 12 px per unit
+        assert_eq!(RenderPassImpostor::calc_bake_resolution(960, 40.0), 288);
+        assert_eq!(
+            RenderPassImpostor::calc_bake_resolution(1920, 10.0),
+            IMPOSTOR_MAX_RESOLUTION
+        );
+        assert_eq!(
+            RenderPassImpostor::calc_bake_resolution(100, 40.0),
+            IMPOSTOR_MIN_RESOLUTION
+        );
+    }
+
+    #[test]
+    fn impostor_quad_maps_bake_corners_to_texture_corners() {
+        let vertices = RenderPassImpostor::calc_vertices(Matrix4x4::new([
+            [0.5, 0.0, 0.0, 0.0],
+            [0.0, 0.25, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]));
+
+        let top_left = vertices[0];
+        let bottom_right = vertices[2];
+        assert_eq!(top_left.world_loc, [-2.0, 4.0]);
+        assert_eq!(top_left.tex_coords, [0.0, 0.0]);
+        assert_eq!(bottom_right.world_loc, [2.0, -4.0]);
+        assert_eq!(bottom_right.tex_coords, [1.0, 1.0]);
+    }
+}
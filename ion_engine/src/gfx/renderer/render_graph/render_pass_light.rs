@@ -137,7 +137,11 @@ impl RenderPassLight {
             self.execute_draw_calls_wasm(&mut render_pass, render_graph, texture_assets);
         } else {
             render_pass.set_bind_group(2, texture_assets.bind_group(), &[]);
-            self.execute_draw_calls_native(&mut render_pass, render_graph);
+            if let Some(indirect_draws) = render_graph.indirect_draws.as_ref() {
+                indirect_draws.light.execute(&mut render_pass);
+            } else {
+                self.execute_draw_calls_native(&mut render_pass, render_graph);
+            }
         }
     }
 
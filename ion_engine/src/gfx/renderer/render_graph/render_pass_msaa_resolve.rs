@@ -0,0 +1,100 @@
+use std::cell::RefCell;
+
+use crate::{
+    build_shader,
+    gfx::renderer::{gpu_data_types::SHADER_DEPTH_RESOLVE, render_helpers::build_render_pipeline},
+};
+
+use super::RenderGraph;
+
+/// Resolves the multisampled gbuffer depth into the regular depth target.
+/// Color targets are resolved by the gbuffer passes themselves, but depth textures can't be resolved automatically.
+pub(super) struct RenderPassMsaaResolve {
+    render_pipeline: wgpu::RenderPipeline,
+
+    source_tex_bind_group_layout: wgpu::BindGroupLayout,
+    source_tex_bind_group: RefCell<Option<wgpu::BindGroup>>,
+}
+
+impl RenderPassMsaaResolve {
+    pub(super) fn new(device: &wgpu::Device) -> Self {
+        let source_tex_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: true,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Depth,
+                },
+                count: None,
+            }],
+            label: Some("source_tex_bind_group_layout_msaa_resolve"),
+        });
+
+        let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("render_pipeline_layout_msaa_resolve"),
+            bind_group_layouts: &[&source_tex_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let shader = build_shader!(device, SHADER_DEPTH_RESOLVE);
+
+        let render_pipeline = build_render_pipeline(
+            device,
+            &render_pipeline_layout,
+            &shader,
+            &[],
+            &[],
+            Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            "render_pipeline_msaa_resolve",
+        );
+
+        Self {
+            render_pipeline,
+
+            source_tex_bind_group_layout,
+            source_tex_bind_group: RefCell::new(None),
+        }
+    }
+
+    pub fn set_render_graph(&self, device: &wgpu::Device, render_graph: &RenderGraph) {
+        *self.source_tex_bind_group.borrow_mut() = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.source_tex_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(
+                    &render_graph.target_depth_msaa.as_ref().unwrap().texture_view,
+                ),
+            }],
+            label: Some("render_targets_bind_group_msaa_resolve"),
+        }));
+    }
+
+    pub(super) fn render(&self, encoder: &mut wgpu::CommandEncoder, render_graph: &RenderGraph) {
+        let render_sources = self.source_tex_bind_group.borrow();
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("render_pass_msaa_resolve"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &render_graph.target_depth.as_ref().unwrap().texture_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            ..Default::default()
+        });
+
+        render_pass.set_bind_group(0, render_sources.as_ref().unwrap(), &[]);
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -20,7 +20,7 @@ pub(super) struct RenderPassPost2 {
 impl RenderPassPost2 {
     pub(super) fn new(device: &wgpu::Device, render_globals: &RenderGlobals, render_camera: &RenderCamera) -> Self {
         let source_tex_bind_group_layout =
-            build_tex_bind_group_layout(device, 2, false, "source_tex_bind_group_layout_post_2");
+            build_tex_bind_group_layout(device, 4, false, "source_tex_bind_group_layout_post_2");
 
         let render_pipeline_layout_post_2 = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("render_pipeline_layout_post_2"),
@@ -80,6 +80,18 @@ impl RenderPassPost2 {
                 },
                 wgpu::BindGroupEntry {
                     binding: 2,
+                    resource: wgpu::BindingResource::TextureView(
+                        &render_graph.target_distortion.as_ref().unwrap().texture_view,
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(
+                        &render_graph.target_reflection.as_ref().unwrap().texture_view,
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
                     resource: wgpu::BindingResource::Sampler(&render_graph.linear_sampler),
                 },
             ],
@@ -9,6 +9,7 @@ use crate::{
             gpu_data_types::{InstanceSprite, SHADER_SHADOW, SHADER_SHADOW_WASM, Vertex},
             render_camera::RenderCamera,
             render_globals::RenderGlobals,
+            render_graph::render_pass_shadow_lightmap::SHADOW_LIGHTMAP_FORMAT,
             render_helpers::{build_render_pipeline, build_tex_bind_group_layout},
         },
         textures::texture_assets::TextureAssets,
@@ -17,6 +18,8 @@ use crate::{
 
 pub struct RenderPassShadow {
     render_pipeline: wgpu::RenderPipeline,
+    /// Pipeline for baking chunk shadow lightmaps. Only supported by the native renderer.
+    bake_render_pipeline: Option<wgpu::RenderPipeline>,
 
     source_tex_bind_group_layout: wgpu::BindGroupLayout,
     source_tex_bind_group: RefCell<Option<wgpu::BindGroup>>,
@@ -74,8 +77,44 @@ impl RenderPassShadow {
             "render_pipeline_shadow",
         );
 
+        let bake_render_pipeline = (!WASM_COMPATIBLE_RENDERING).then(|| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("render_pipeline_shadow_bake"),
+                layout: Some(&render_pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    compilation_options: Default::default(),
+                    buffers: &[Vertex::buffer_layout(), InstanceSprite::buffer_layout()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some("fs_bake"),
+                    compilation_options: Default::default(),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: SHADOW_LIGHTMAP_FORMAT,
+                        blend: Some(wgpu::BlendState {
+                            color: wgpu::BlendComponent {
+                                src_factor: wgpu::BlendFactor::One,
+                                dst_factor: wgpu::BlendFactor::One,
+                                operation: wgpu::BlendOperation::Max,
+                            },
+                            alpha: wgpu::BlendComponent::REPLACE,
+                        }),
+                        write_mask: wgpu::ColorWrites::RED,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        });
+
         Self {
             render_pipeline,
+            bake_render_pipeline,
 
             source_tex_bind_group_layout,
             source_tex_bind_group: RefCell::new(None),
@@ -135,7 +174,39 @@ impl RenderPassShadow {
             self.execute_draw_calls_wasm(&mut render_pass, render_graph, texture_assets);
         } else {
             render_pass.set_bind_group(2, texture_assets.bind_group(), &[]);
-            self.execute_draw_calls_native(&mut render_pass, render_graph);
+            if let Some(indirect_draws) = render_graph.indirect_draws.as_ref() {
+                indirect_draws.shadow.execute(&mut render_pass);
+            } else {
+                self.execute_draw_calls_native(&mut render_pass, render_graph);
+            }
+        }
+    }
+
+    /// Bakes the shadow sprites of a single chunk into a lightmap with a custom camera.
+    /// Only supported by the native renderer.
+    pub(super) fn render_chunk(
+        &self,
+        render_pass: &mut wgpu::RenderPass,
+        camera_bind_group: &wgpu::BindGroup,
+        render_globals: &RenderGlobals,
+        render_graph: &RenderGraph,
+        texture_assets: &TextureAssets,
+        chunk: &ChunkLocation,
+    ) {
+        let buffers = render_graph.chunk_buffers.get(chunk).unwrap();
+
+        render_pass.set_pipeline(self.bake_render_pipeline.as_ref().unwrap());
+        render_pass.set_bind_group(0, &render_globals.globals_bind_group, &[]);
+        render_pass.set_bind_group(1, camera_bind_group, &[]);
+        render_pass.set_bind_group(2, texture_assets.bind_group(), &[]);
+        render_pass.set_bind_group(3, self.source_tex_bind_group.borrow().as_ref().unwrap(), &[]);
+
+        render_pass.set_vertex_buffer(0, render_graph.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, buffers.shadow_buf.slice(..));
+        render_pass.set_index_buffer(render_graph.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+
+        for draw_call in &buffers.shadow_draw_calls {
+            render_pass.draw_indexed(draw_call.layout.draw_range(), 0, draw_call.draw_range.clone());
         }
     }
 
@@ -147,6 +218,7 @@ impl RenderPassShadow {
         let mut all_chunked_draw_calls = render_graph
             .chunk_buffers
             .iter()
+            .filter(|(chunk, _)| !render_graph.is_lightmap_chunk(chunk))
             .flat_map(|(chunk, buffers)| {
                 buffers
                     .shadow_draw_calls
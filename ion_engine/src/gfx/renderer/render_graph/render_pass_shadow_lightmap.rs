@@ -0,0 +1,329 @@
+use std::cell::RefCell;
+
+use ion_common::{Map, Set};
+
+use crate::{
+    build_shader,
+    core::{
+        CHUNK_SIZE,
+        coordinates::{ChunkLocation, Location},
+    },
+    gfx::{
+        gfx_config::Resolution,
+        renderer::{
+            gpu_data_types::{ImpostorVertex, SHADER_SHADOW_LIGHTMAP},
+            render_camera::RenderCamera,
+            render_globals::RenderGlobals,
+            render_helpers::{build_render_pipeline, build_tex_bind_group_layout, write_to_buffer},
+        },
+        textures::{Texture, texture_assets::TextureAssets},
+    },
+};
+
+use super::{Buffers, RenderGraph, render_pass_impostor::RenderPassImpostor};
+
+pub(super) const SHADOW_LIGHTMAP_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+/// Half of the side of the square area that is baked into a lightmap, in camera view units.
+/// Leaves room around the chunk for shadows that extend past it.
+const LIGHTMAP_HALF_EXTENT: f32 = CHUNK_SIZE as f32 * 0.75;
+/// Shadows are soft and low-contrast, so a fixed resolution is enough at all camera scales.
+const LIGHTMAP_RESOLUTION: u32 = 256;
+/// Chunks that don't have a lightmap yet are baked gradually, and their shadows are rendered from sprites
+/// in the meantime.
+const LIGHTMAP_BAKES_PER_FRAME: usize = 4;
+
+/// Baked shadows for chunked sprites.
+///
+/// The shadow sprites of each chunk are rendered once into a lightmap texture, and the chunk shadows are then
+/// drawn as a single textured quad. Lightmaps are rebaked when the chunk receives new data.
+/// Dynamic sprites are still rendered by the shadow pass every frame, and shadow animations of chunked sprites
+/// are frozen to the frame they were baked on.
+///
+/// Only supported by the native renderer.
+pub(super) struct RenderPassShadowLightmap {
+    render_pipeline: wgpu::RenderPipeline,
+    lightmap_bind_group_layout: wgpu::BindGroupLayout,
+
+    source_tex_bind_group_layout: wgpu::BindGroupLayout,
+    source_tex_bind_group: RefCell<Option<wgpu::BindGroup>>,
+
+    bake_cameras: Vec<(wgpu::Buffer, wgpu::BindGroup)>,
+    pending_bakes: Vec<ChunkLocation>,
+
+    lightmaps: Map<ChunkLocation, Lightmap>,
+    drawn_chunks: Set<ChunkLocation>,
+    draw_order: Vec<ChunkLocation>,
+    vertex_buffer: wgpu::Buffer,
+}
+
+struct Lightmap {
+    target: Texture,
+    bind_group: wgpu::BindGroup,
+    vertices: [ImpostorVertex; 6],
+}
+
+impl RenderPassShadowLightmap {
+    pub(super) fn new(device: &wgpu::Device, render_camera: &RenderCamera, render_globals: &RenderGlobals) -> Self {
+        let lightmap_bind_group_layout =
+            build_tex_bind_group_layout(device, 1, false, "lightmap_bind_group_layout_shadow_lightmap");
+        let source_tex_bind_group_layout =
+            build_tex_bind_group_layout(device, 1, false, "source_tex_bind_group_layout_shadow_lightmap");
+
+        let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("render_pipeline_layout_shadow_lightmap"),
+            bind_group_layouts: &[
+                &render_globals.globals_bind_group_layout,
+                &render_camera.camera_bind_group_layout,
+                &lightmap_bind_group_layout,
+                &source_tex_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let shader = build_shader!(device, SHADER_SHADOW_LIGHTMAP);
+
+        // Blends the same way as the shadow pass, so overlapping lightmaps and sprite shadows combine correctly
+        let render_pipeline = build_render_pipeline(
+            device,
+            &render_pipeline_layout,
+            &shader,
+            &[ImpostorVertex::buffer_layout()],
+            &[Some(wgpu::ColorTargetState {
+                format: wgpu::TextureFormat::Rgba16Float,
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::One,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Max,
+                    },
+                    alpha: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::One,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Max,
+                    },
+                }),
+                write_mask: wgpu::ColorWrites::ALPHA,
+            })],
+            None,
+            "render_pipeline_shadow_lightmap",
+        );
+
+        let bake_cameras = (0..LIGHTMAP_BAKES_PER_FRAME)
+            .map(|i| render_camera.create_bake_camera(device, &format!("lightmap_bake_{}", i)))
+            .collect();
+
+        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("lightmap_vertex_buffer"),
+            size: 64 * 6 * size_of::<ImpostorVertex>() as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            render_pipeline,
+            lightmap_bind_group_layout,
+
+            source_tex_bind_group_layout,
+            source_tex_bind_group: RefCell::new(None),
+
+            bake_cameras,
+            pending_bakes: Vec::new(),
+
+            lightmaps: Map::default(),
+            drawn_chunks: Set::default(),
+            draw_order: Vec::new(),
+            vertex_buffer,
+        }
+    }
+
+    pub fn set_render_graph(&self, device: &wgpu::Device, render_graph: &RenderGraph) {
+        *self.source_tex_bind_group.borrow_mut() = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.source_tex_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(
+                        &render_graph.target_height_id.as_ref().unwrap().texture_view,
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&render_graph.nearest_sampler),
+                },
+            ],
+            label: Some("render_targets_bind_group_shadow_lightmap"),
+        }));
+    }
+
+    /// Chunks whose shadows are drawn from lightmaps this frame. Their shadow sprites must not be drawn
+    /// by the shadow pass.
+    pub(super) fn drawn_chunks(&self) -> &Set<ChunkLocation> {
+        &self.drawn_chunks
+    }
+
+    /// Drops the lightmap of a chunk, so that it is rebaked from the new chunk data.
+    pub(super) fn invalidate(&mut self, chunk: &ChunkLocation) {
+        self.lightmaps.remove(chunk);
+    }
+
+    /// Selects the chunks drawn from lightmaps this frame, and prepares bakes for chunks that have no lightmap yet.
+    /// Must be called after chunk buffers are updated, and before the shadow pass.
+    pub(super) fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        render_camera: &RenderCamera,
+        baked_shadows: bool,
+        chunk_buffers: &Map<ChunkLocation, Buffers>,
+        sampler: &wgpu::Sampler,
+    ) {
+        self.pending_bakes.clear();
+        self.drawn_chunks.clear();
+        self.draw_order.clear();
+
+        if !baked_shadows {
+            self.lightmaps.clear();
+            return;
+        }
+
+        self.lightmaps.retain(|chunk, _| chunk_buffers.contains_key(chunk));
+
+        let mut chunks: Vec<_> = chunk_buffers
+            .iter()
+            .filter(|(_, buffers)| !buffers.shadow_draw_calls.is_empty())
+            .map(|(chunk, _)| *chunk)
+            .collect();
+        chunks.sort();
+
+        let mut vertices = Vec::with_capacity(chunks.len() * 6);
+        for chunk in chunks {
+            if !self.lightmaps.contains_key(&chunk) {
+                if self.pending_bakes.len() >= LIGHTMAP_BAKES_PER_FRAME {
+                    continue;
+                }
+
+                let center = Location::from(chunk) + Location::new(CHUNK_SIZE as f32 / 2.0, CHUNK_SIZE as f32 / 2.0);
+                let (camera_buffer, _) = &self.bake_cameras[self.pending_bakes.len()];
+                render_camera.write_bake_camera_to_gpu(queue, camera_buffer, center, LIGHTMAP_HALF_EXTENT);
+
+                let vertices =
+                    RenderPassImpostor::calc_vertices(render_camera.calc_bake_vp_mat(center, LIGHTMAP_HALF_EXTENT));
+                let lightmap = self.create_lightmap(device, sampler, vertices);
+                self.lightmaps.insert(chunk, lightmap);
+                self.pending_bakes.push(chunk);
+            }
+
+            vertices.extend_from_slice(&self.lightmaps[&chunk].vertices);
+            self.drawn_chunks.insert(chunk);
+            self.draw_order.push(chunk);
+        }
+
+        write_to_buffer(device, queue, &mut self.vertex_buffer, &vertices);
+    }
+
+    /// Bakes the chunks selected in `prepare` into their lightmaps.
+    pub(super) fn bake(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        render_globals: &RenderGlobals,
+        render_graph: &RenderGraph,
+        texture_assets: &TextureAssets,
+    ) {
+        for (chunk, (_, camera_bind_group)) in self.pending_bakes.iter().zip(&self.bake_cameras) {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("render_pass_shadow_lightmap_bake"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &self.lightmaps[chunk].target.texture_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                ..Default::default()
+            });
+
+            render_graph.render_pass_shadow.as_ref().unwrap().render_chunk(
+                &mut render_pass,
+                camera_bind_group,
+                render_globals,
+                render_graph,
+                texture_assets,
+                chunk,
+            );
+        }
+    }
+
+    /// Draws the lightmaps into the shadow target. Must run after the gbuffer, since shadows are masked by height.
+    pub(super) fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        render_camera: &RenderCamera,
+        render_globals: &RenderGlobals,
+        render_graph: &RenderGraph,
+    ) {
+        if self.draw_order.is_empty() {
+            return;
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("render_pass_shadow_lightmap"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &render_graph.target_light_shadow.as_ref().unwrap().texture_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        });
+
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(0, &render_globals.globals_bind_group, &[]);
+        render_pass.set_bind_group(1, &render_camera.camera_bind_group, &[]);
+        render_pass.set_bind_group(3, self.source_tex_bind_group.borrow().as_ref().unwrap(), &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+
+        for (i, chunk) in self.draw_order.iter().enumerate() {
+            let first_vertex = i as u32 * 6;
+            render_pass.set_bind_group(2, &self.lightmaps[chunk].bind_group, &[]);
+            render_pass.draw(first_vertex..first_vertex + 6, 0..1);
+        }
+    }
+
+    fn create_lightmap(
+        &self,
+        device: &wgpu::Device,
+        sampler: &wgpu::Sampler,
+        vertices: [ImpostorVertex; 6],
+    ) -> Lightmap {
+        let resolution = Resolution {
+            width: LIGHTMAP_RESOLUTION,
+            height: LIGHTMAP_RESOLUTION,
+        };
+        let target = Texture::new_from_empty(device, resolution, SHADOW_LIGHTMAP_FORMAT, 1, "shadow_lightmap");
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.lightmap_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&target.texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+            label: Some("shadow_lightmap_bind_group"),
+        });
+
+        Lightmap {
+            target,
+            bind_group,
+            vertices,
+        }
+    }
+}
@@ -180,7 +180,7 @@ impl RenderPassSsao {
             "render_pipeline_ssao_blur",
         );
 
-        let ssao_dims = gfx_config.frame_resolution;
+        let ssao_dims = gfx_config.render_resolution();
         let render_tex_ssao_raw = Texture::new_from_empty(device, ssao_dims, target_format, 1, "render_tex_ssao_raw");
         let render_tex_ssao_blur = Texture::new_from_empty(device, ssao_dims, target_format, 1, "render_tex_ssao_blur");
 
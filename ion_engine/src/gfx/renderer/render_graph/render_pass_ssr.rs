@@ -0,0 +1,121 @@
+use std::cell::RefCell;
+
+use crate::build_shader;
+use crate::gfx::renderer::gpu_data_types::SHADER_SSR;
+use crate::gfx::renderer::render_camera::RenderCamera;
+use crate::gfx::renderer::render_globals::RenderGlobals;
+use wgpu::CommandEncoder;
+
+use crate::gfx::renderer::render_helpers::{build_render_pipeline, build_tex_bind_group_layout};
+
+use super::RenderGraph;
+
+/// Reflections of the lit scene, with the reflection strength in alpha. Blended over the scene in the second post pass.
+pub(super) const REFLECTION_TARGET_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// Screen space reflections of sprites on reflective surfaces of the gbuffer.
+/// Only supported by the native renderer.
+pub(super) struct RenderPassSsr {
+    render_pipeline_ssr: wgpu::RenderPipeline,
+
+    source_tex_bind_group_layout: wgpu::BindGroupLayout,
+    source_tex_bind_group: RefCell<Option<wgpu::BindGroup>>,
+}
+
+impl RenderPassSsr {
+    pub(super) fn new(device: &wgpu::Device, render_globals: &RenderGlobals, render_camera: &RenderCamera) -> Self {
+        let source_tex_bind_group_layout =
+            build_tex_bind_group_layout(device, 2, false, "source_tex_bind_group_layout_ssr");
+
+        let render_pipeline_layout_ssr = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("render_pipeline_layout_ssr"),
+            bind_group_layouts: &[
+                &render_globals.globals_bind_group_layout,
+                &render_camera.camera_bind_group_layout,
+                &source_tex_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let shader_ssr = build_shader!(device, SHADER_SSR);
+
+        let render_pipeline_ssr = build_render_pipeline(
+            device,
+            &render_pipeline_layout_ssr,
+            &shader_ssr,
+            &[],
+            &[Some(wgpu::ColorTargetState {
+                format: REFLECTION_TARGET_FORMAT,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            None,
+            "render_pipeline_ssr",
+        );
+
+        Self {
+            render_pipeline_ssr,
+
+            source_tex_bind_group_layout,
+            source_tex_bind_group: RefCell::new(None),
+        }
+    }
+
+    pub fn set_render_graph(&self, device: &wgpu::Device, render_graph: &RenderGraph) {
+        *self.source_tex_bind_group.borrow_mut() = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.source_tex_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(
+                        &render_graph.target_post_1.as_ref().unwrap().texture_view,
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(
+                        &render_graph.target_height_id.as_ref().unwrap().texture_view,
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&render_graph.linear_sampler),
+                },
+            ],
+            label: Some("render_targets_bind_group_ssr"),
+        }));
+    }
+
+    /// Renders the reflections, or only clears the target when reflections are disabled.
+    pub(super) fn render(
+        &self,
+        encoder: &mut CommandEncoder,
+        render_camera: &RenderCamera,
+        render_globals: &RenderGlobals,
+        render_graph: &RenderGraph,
+    ) {
+        let render_sources = self.source_tex_bind_group.borrow();
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("render_pass_ssr"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &render_graph.target_reflection.as_ref().unwrap().texture_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        });
+
+        if !render_graph.reflections {
+            return;
+        }
+
+        render_pass.set_bind_group(0, &render_globals.globals_bind_group, &[]);
+        render_pass.set_bind_group(1, &render_camera.camera_bind_group, &[]);
+        render_pass.set_bind_group(2, render_sources.as_ref().unwrap(), &[]);
+        render_pass.set_pipeline(&self.render_pipeline_ssr);
+        render_pass.draw(0..6, 0..1);
+    }
+}
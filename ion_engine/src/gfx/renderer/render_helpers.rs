@@ -6,6 +6,7 @@ use crate::util::casting::{RawData, slice_as_bytes};
 /// If the buffer is too small, a new buffer will be created.
 /// If the buffer is already the large enough, it will be written to.
 /// The buffer will nevers shrink in size.
+/// A recreated buffer keeps the usages of the original buffer.
 pub(super) fn write_to_buffer<T: RawData>(
     device: &wgpu::Device,
     queue: &wgpu::Queue,
@@ -16,22 +17,36 @@ pub(super) fn write_to_buffer<T: RawData>(
         *buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: None,
             contents: slice_as_bytes(&instances),
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            usage: buffer.usage(),
         });
     } else {
         queue.write_buffer(buffer, 0, slice_as_bytes(instances));
     }
 }
 
+/// Makes sure that the buffer is at least of the given size in bytes.
+/// If the buffer is too small, a new empty buffer with the same usages will be created.
+/// Contents of the buffer are not preserved when it grows.
+pub(super) fn reserve_buffer(device: &wgpu::Device, buffer: &mut wgpu::Buffer, size: u64) {
+    if buffer.size() < size {
+        *buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: size.next_power_of_two(),
+            usage: buffer.usage(),
+            mapped_at_creation: false,
+        });
+    }
+}
+
 /// Builds a shader module from a given shader constant.
-/// The first argument is a wgpu::Device, the second is a string literal of the shader.
+/// The first argument is a wgpu::Device, the second is a `ShaderSource` constant of the shader.
 #[macro_export]
 macro_rules! build_shader {
     ($device:expr, $shader_const:ident) => {{
         let label = stringify!($shader_const).to_lowercase();
         $device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some(&label),
-            source: wgpu::ShaderSource::Wgsl($shader_const.into()),
+            source: wgpu::ShaderSource::Wgsl($shader_const.load()),
         })
     }};
 }
@@ -100,6 +115,21 @@ pub(super) fn build_render_pipeline(
     targets: &[Option<wgpu::ColorTargetState>],
     depth_stencil: Option<wgpu::DepthStencilState>,
     label: &str,
+) -> wgpu::RenderPipeline {
+    build_render_pipeline_multisampled(device, layout, shader, sources, targets, depth_stencil, 1, label)
+}
+
+/// Builds a render pipeline like `build_render_pipeline`, for targets with the given sample count.
+#[allow(clippy::too_many_arguments)]
+pub(super) fn build_render_pipeline_multisampled(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    sources: &[VertexBufferLayout],
+    targets: &[Option<wgpu::ColorTargetState>],
+    depth_stencil: Option<wgpu::DepthStencilState>,
+    sample_count: u32,
+    label: &str,
 ) -> wgpu::RenderPipeline {
     device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
         label: Some(label),
@@ -127,7 +157,7 @@ pub(super) fn build_render_pipeline(
         },
         depth_stencil,
         multisample: wgpu::MultisampleState {
-            count: 1,
+            count: sample_count,
             mask: !0,
             alpha_to_coverage_enabled: false,
         },
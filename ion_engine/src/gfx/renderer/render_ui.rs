@@ -16,11 +16,14 @@ use super::render_camera::RenderCamera;
 // ---------------------------------------------------------- //
 
 pub(super) struct RenderUi {
-    winit_state: egui_winit::State,
+    ctx: egui::Context,
+    /// Input and platform output of the window. `None` for offscreen rendering, which has no input.
+    winit_state: Option<egui_winit::State>,
     wgpu_renderer: egui_wgpu::Renderer,
     viewport_info: ViewportInfo,
     screen_descriptor: ScreenDescriptor,
     latest_texture_delta: egui::TexturesDelta,
+    latest_cursor_icon: egui::CursorIcon,
 }
 
 impl RenderUi {
@@ -34,7 +37,7 @@ impl RenderUi {
     ) -> Self {
         let ctx = egui::Context::default();
         let state = egui_winit::State::new(
-            ctx,
+            ctx.clone(),
             ViewportId::ROOT,
             event_loop,
             Some(ui_dpi_factor),
@@ -51,11 +54,32 @@ impl RenderUi {
         update_viewport_info(&mut viewport_info, state.egui_ctx(), window, true);
 
         Self {
-            winit_state: state,
+            ctx,
+            winit_state: Some(state),
             wgpu_renderer: renderer,
             viewport_info,
             screen_descriptor,
             latest_texture_delta: egui::TexturesDelta::default(),
+            latest_cursor_icon: egui::CursorIcon::Default,
+        }
+    }
+
+    pub(super) fn new_offscreen(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        ui_size: PhysicalSize<u32>,
+    ) -> Self {
+        Self {
+            ctx: egui::Context::default(),
+            winit_state: None,
+            wgpu_renderer: egui_wgpu::Renderer::new(device, format, None, 1, false),
+            viewport_info: ViewportInfo::default(),
+            screen_descriptor: ScreenDescriptor {
+                size_in_pixels: [ui_size.width, ui_size.height],
+                pixels_per_point: 1.0,
+            },
+            latest_texture_delta: egui::TexturesDelta::default(),
+            latest_cursor_icon: egui::CursorIcon::Default,
         }
     }
 
@@ -67,15 +91,35 @@ impl RenderUi {
     }
 
     pub(crate) fn ui_event_process(&mut self, native_window: &winit::window::Window, event: &WindowEvent) -> bool {
-        self.winit_state.on_window_event(native_window, event).consumed
+        self.winit_state
+            .as_mut()
+            .is_some_and(|winit_state| winit_state.on_window_event(native_window, event).consumed)
     }
 
-    pub(crate) fn ui_begin_pass(&mut self, native_window: &winit::window::Window) -> egui::Context {
-        let context = self.winit_state.egui_ctx().clone();
+    pub(super) fn ui_ctx(&self) -> egui::Context {
+        self.ctx.clone()
+    }
 
-        update_viewport_info(&mut self.viewport_info, &context, native_window, false);
+    pub(crate) fn ui_begin_pass(&mut self, native_window: Option<&winit::window::Window>) -> egui::Context {
+        let context = self.ctx.clone();
 
-        let data = self.winit_state.take_egui_input(native_window);
+        let data = match (self.winit_state.as_mut(), native_window) {
+            (Some(winit_state), Some(native_window)) => {
+                update_viewport_info(&mut self.viewport_info, &context, native_window, false);
+                winit_state.take_egui_input(native_window)
+            }
+            _ => {
+                let [width, height] = self.screen_descriptor.size_in_pixels;
+                let pixels_per_point = self.screen_descriptor.pixels_per_point;
+                egui::RawInput {
+                    screen_rect: Some(egui::Rect::from_min_size(
+                        egui::Pos2::ZERO,
+                        egui::vec2(width as f32, height as f32) / pixels_per_point,
+                    )),
+                    ..Default::default()
+                }
+            }
+        };
 
         context.begin_pass(data);
         context
@@ -88,7 +132,7 @@ impl RenderUi {
         window_res: PhysicalSize<u32>,
         dpi_scale: f32,
     ) {
-        let ctx = self.winit_state.egui_ctx();
+        let ctx = &self.ctx;
         let prev_style = ctx.style();
 
         ctx.set_style(self.debug_label_style());
@@ -116,7 +160,7 @@ impl RenderUi {
     #[allow(clippy::too_many_arguments)]
     pub(super) fn render_ui(
         &mut self,
-        native_window: &winit::window::Window,
+        native_window: Option<&winit::window::Window>,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         encoder: &mut CommandEncoder,
@@ -137,7 +181,7 @@ impl RenderUi {
             })
             .forget_lifetime();
 
-        let context = self.winit_state.egui_ctx().clone();
+        let context = self.ctx.clone();
         let egui::FullOutput {
             platform_output,
             textures_delta,
@@ -145,8 +189,11 @@ impl RenderUi {
             pixels_per_point,
             ..
         } = context.end_pass();
-        self.winit_state.handle_platform_output(native_window, platform_output);
-        let primitives = self.winit_state.egui_ctx().tessellate(shapes, pixels_per_point);
+        self.latest_cursor_icon = platform_output.cursor_icon;
+        if let (Some(winit_state), Some(native_window)) = (self.winit_state.as_mut(), native_window) {
+            winit_state.handle_platform_output(native_window, platform_output);
+        }
+        let primitives = context.tessellate(shapes, pixels_per_point);
         for (id, image_delta) in &textures_delta.set {
             self.wgpu_renderer.update_texture(device, queue, *id, image_delta);
         }
@@ -158,6 +205,18 @@ impl RenderUi {
         self.latest_texture_delta = textures_delta;
     }
 
+    /// Shows the cursor for the current pass, unless a widget already shows its own cursor.
+    pub(super) fn request_cursor_icon(&self, icon: egui::CursorIcon) {
+        if self.ctx.output(|output| output.cursor_icon) == egui::CursorIcon::Default {
+            self.ctx.set_cursor_icon(icon);
+        }
+    }
+
+    /// Cursor that egui requested on the latest rendered frame.
+    pub(super) fn cursor_icon(&self) -> egui::CursorIcon {
+        self.latest_cursor_icon
+    }
+
     pub(super) fn render_ui_cleanup(&mut self) {
         for id in &self.latest_texture_delta.free {
             self.wgpu_renderer.free_texture(id);
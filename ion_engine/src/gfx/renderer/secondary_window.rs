@@ -0,0 +1,151 @@
+use std::{iter, sync::Arc};
+
+use winit::{event::WindowEvent, event_loop::ActiveEventLoop};
+
+use crate::gfx::gfx_config::Resolution;
+
+use super::render_ui::RenderUi;
+
+/// Identifies a secondary window opened with [`super::Renderer::open_secondary_window`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SecondaryWindowId(pub(super) u32);
+
+/// Additional OS window that only renders egui, for tools such as level editors and debug dashboards.
+/// Shares the wgpu device with the main window, but has its own surface and egui context.
+pub(super) struct SecondaryWindow {
+    pub(super) window: Arc<winit::window::Window>,
+    surface: wgpu::Surface<'static>,
+    surface_config: wgpu::SurfaceConfiguration,
+    render_ui: RenderUi,
+    ui_pass_active: bool,
+}
+
+impl SecondaryWindow {
+    pub(super) fn new(
+        event_loop: &ActiveEventLoop,
+        instance: &wgpu::Instance,
+        adapter: &wgpu::Adapter,
+        device: &wgpu::Device,
+        title: &str,
+        resolution: Resolution,
+    ) -> Self {
+        let window = Arc::new(
+            event_loop
+                .create_window(
+                    winit::window::WindowAttributes::default()
+                        .with_title(title)
+                        .with_inner_size(winit::dpi::PhysicalSize::<u32>::from(resolution)),
+                )
+                .expect("Window creation must succeed"),
+        );
+
+        let surface = instance.create_surface(window.clone()).unwrap();
+        let surface_capabilities = surface.get_capabilities(adapter);
+
+        let surface_config = wgpu::SurfaceConfiguration {
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: surface_capabilities.formats[0],
+            view_formats: vec![surface_capabilities.formats[0]],
+            width: window.inner_size().width.max(1),
+            height: window.inner_size().height.max(1),
+            present_mode: wgpu::PresentMode::AutoNoVsync,
+            desired_maximum_frame_latency: 1,
+        };
+        surface.configure(device, &surface_config);
+
+        let render_ui = RenderUi::new(
+            event_loop,
+            &window,
+            device,
+            &surface_config,
+            window.inner_size(),
+            window.scale_factor() as f32,
+        );
+
+        Self {
+            window,
+            surface,
+            surface_config,
+            render_ui,
+            ui_pass_active: false,
+        }
+    }
+
+    /// Egui context of the window. Valid for building ui between `ui_begin_pass` and `render`.
+    pub(super) fn ui_ctx(&self) -> Option<egui::Context> {
+        self.ui_pass_active.then(|| self.render_ui.ui_ctx())
+    }
+
+    /// Handles events of this window. Returns `true` if the window should be closed.
+    pub(super) fn window_event(&mut self, device: &wgpu::Device, event: &WindowEvent) -> bool {
+        self.render_ui.ui_event_process(&self.window, event);
+
+        match event {
+            WindowEvent::Resized(_) => self.resize(device, None),
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => self.resize(device, Some(*scale_factor as f32)),
+            WindowEvent::CloseRequested => return true,
+            _ => {}
+        }
+
+        false
+    }
+
+    /// Minimized windows are not rendered, so ui is not built for them either.
+    pub(super) fn ui_begin_pass(&mut self) {
+        let size = self.window.inner_size();
+        self.ui_pass_active = size.width > 0 && size.height > 0;
+        if self.ui_pass_active {
+            self.render_ui.ui_begin_pass(Some(&self.window));
+        }
+    }
+
+    pub(super) fn render(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if !self.ui_pass_active {
+            return;
+        }
+        self.ui_pass_active = false;
+
+        let surface_texture = self.surface.get_current_texture().unwrap();
+        let surface_view = surface_texture
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("secondary_window_encoder"),
+        });
+
+        // Ui is drawn on top of the previous surface contents, so it needs to be cleared first
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("render_pass_secondary_window_clear"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &surface_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        });
+
+        self.render_ui
+            .render_ui(Some(&self.window), device, queue, &mut encoder, &surface_view);
+
+        queue.submit(iter::once(encoder.finish()));
+        surface_texture.present();
+    }
+
+    pub(super) fn post_render(&mut self) {
+        self.render_ui.render_ui_cleanup();
+    }
+
+    fn resize(&mut self, device: &wgpu::Device, new_dpi_factor: Option<f32>) {
+        let new_window_res: Resolution = self.window.inner_size().into();
+        if new_window_res.width > 0 && new_window_res.height > 0 {
+            self.surface_config.width = new_window_res.width;
+            self.surface_config.height = new_window_res.height;
+            self.surface.configure(device, &self.surface_config);
+            self.render_ui.resize_window(new_window_res, new_dpi_factor);
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use std::{
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+
+use ion_common::{log_error, log_info};
+use wgpu::naga;
+
+use super::gpu_data_types::{ALL_SHADERS, ShaderSource};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Directory the shaders are read from in debug builds.
+/// Defaults to the engine shader sources, and can be overridden with the `ION_SHADER_DIR` environment variable.
+pub(crate) fn shader_dir() -> PathBuf {
+    std::env::var_os("ION_SHADER_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/src/gfx/renderer/shaders")))
+}
+
+/// Reads a shader from the shader directory. `None` if the file doesn't exist, e.g. when running outside the source tree.
+pub(crate) fn read_shader(file_name: &str) -> Option<String> {
+    fs::read_to_string(shader_dir().join(file_name)).ok()
+}
+
+/// Polls the shader directory for modified WGSL files.
+/// Changed shaders are validated before they are reported, so a typo doesn't crash the game mid-iteration.
+/// Errors that only show up when the pipeline is built are caught by the render graph, see
+/// [`RenderGraph::reload_shaders`](super::render_graph::RenderGraph::reload_shaders).
+pub(crate) struct ShaderWatcher {
+    modified: Vec<Option<SystemTime>>,
+    last_poll: Instant,
+}
+
+impl ShaderWatcher {
+    pub(crate) fn new() -> Self {
+        Self {
+            modified: Self::modified_times(),
+            last_poll: Instant::now(),
+        }
+    }
+
+    /// Returns the shaders that changed since the last poll and are valid.
+    /// The render passes using them must then be rebuilt to pick up the new sources.
+    pub(crate) fn poll(&mut self) -> Vec<ShaderSource> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return Vec::new();
+        }
+        self.last_poll = Instant::now();
+
+        let modified = Self::modified_times();
+        let changed = ALL_SHADERS
+            .iter()
+            .zip(modified.iter().zip(&self.modified))
+            .filter(|(_, (modified, previous))| modified != previous)
+            .map(|(shader, _)| *shader)
+            .filter(|shader| match validate(&shader.load()) {
+                Ok(()) => {
+                    log_info!("Shader {} changed, rebuilding the passes using it", shader.file_name);
+                    true
+                }
+                Err(err) => {
+                    log_error!("Shader {} failed to reload:\n{}", shader.file_name, err);
+                    false
+                }
+            })
+            .collect();
+        self.modified = modified;
+        changed
+    }
+
+    fn modified_times() -> Vec<Option<SystemTime>> {
+        let dir = shader_dir();
+        ALL_SHADERS
+            .iter()
+            .map(|shader| {
+                fs::metadata(dir.join(shader.file_name))
+                    .and_then(|meta| meta.modified())
+                    .ok()
+            })
+            .collect()
+    }
+}
+
+fn validate(source: &str) -> Result<(), String> {
+    let module = naga::front::wgsl::parse_str(source).map_err(|err| err.emit_to_string(source))?;
+    naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+        .validate(&module)
+        .map_err(|err| err.emit_to_string(source))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn embedded_shaders_are_valid() {
+        for shader in ALL_SHADERS {
+            assert!(validate(shader.embedded).is_ok(), "{} is invalid", shader.file_name);
+        }
+    }
+
+    #[test]
+    fn invalid_shader_is_rejected() {
+        assert!(validate("fn broken( {").is_err());
+    }
+}
@@ -0,0 +1,37 @@
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var depth_msaa: texture_depth_multisampled_2d;
+
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    // Single triangle that covers the whole screen
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+
+struct OutputBuffers {
+  @builtin(frag_depth) depth: f32,
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> OutputBuffers {
+    let coords = vec2<i32>(in.position.xy);
+
+    // The nearest sample wins, like it would without multisampling
+    var depth = 1.0;
+    for (var i = 0; i < i32(textureNumSamples(depth_msaa)); i++) {
+        depth = min(depth, textureLoad(depth_msaa, coords, i));
+    }
+
+    var output: OutputBuffers;
+    output.depth = depth;
+    return output;
+}
@@ -16,6 +16,10 @@ struct GlobalsUniform {
     lighting_unused: f32,
 
     post_bloom: f32,
+    post_color_filter: u32,
+    post_gamma: f32,
+    post_brightness: f32,
+    post_contrast: f32,
 
     _padding1: f32,
     _padding2: f32,
@@ -46,6 +50,11 @@ struct VertexOutput {
     @location(3) tex_coords_mask: vec2<f32>,
     @location(4) tex_sheet_indices: u32,
     @location(5) type_id: f32,
+    @location(6) slice_uv: vec2<f32>,
+    @location(7) nine_slice: vec2<u32>,
+    @location(8) slice_source_size: vec2<f32>,
+    @location(9) palette: u32,
+    @location(10) reflectivity: f32,
 }
 
 struct InstanceInput {
@@ -58,6 +67,9 @@ struct InstanceInput {
     @location(12) tex_coords_sizes: vec2<f32>,
     @location(13) tex_sheet_indices: u32,
     @location(14) type_id: f32,
+    @location(15) nine_slice: vec2<u32>,
+    @location(5) palette: u32,
+    @location(4) reflectivity: f32,
 };
 
 @group(0) @binding(0)
@@ -69,6 +81,8 @@ var<uniform> camera: CameraUniform;
 var tex: binding_array<texture_2d<f32>>;
 @group(2) @binding(1)
 var tex_sampler: sampler;
+@group(2) @binding(2)
+var tex_palette: texture_2d<f32>;
 
 
 fn unpack4u8u32(packed: u32) -> vec4<u32> {
@@ -79,15 +93,82 @@ fn unpack4u8u32(packed: u32) -> vec4<u32> {
     return vec4<u32>(a, b, c, d);
 }
 
+// Returns the current animation frame, or the frame count if the sprite is hidden after a one-shot animation.
+fn calc_anim_frame(anim_flags: u32, anim_frame_rate: u32, anim_frame_start: u32, anim_frame_count: u32) -> u32 {
+    let anim_on = anim_flags & 1u;
+    let anim_mode = anim_flags >> 1u;
+
+    if anim_on == 0u {
+        return anim_frame_start;
+    }
+
+    let anim_step = globals.frame / anim_frame_rate + anim_frame_start;
+
+    if anim_mode == 1u || anim_mode == 2u {
+        // One-shot modes: start is stored as a negative phase, so the low byte counts frames since the start
+        let elapsed = anim_step & 0xFFu;
+        if elapsed < anim_frame_count {
+            return elapsed;
+        }
+        return select(anim_frame_count - 1u, anim_frame_count, anim_mode == 2u);
+    }
+
+    if anim_mode == 3u && anim_frame_count > 1u {
+        let period = 2u * anim_frame_count - 2u;
+        let step = anim_step % period;
+        return select(period - step, step, step < anim_frame_count);
+    }
+
+    return anim_step % anim_frame_count;
+}
+
+// Maps a coordinate on a 9-slice sprite to the source texture. Both are in range [0, 1] over the sprite.
+// Corners keep their size, while edges and the center stretch to fill the target size.
+fn nine_slice_uv(uv: vec2<f32>, nine_slice: vec2<u32>, source_size: vec2<f32>) -> vec2<f32> {
+    let target_px = vec2<f32>(f32(nine_slice.x & 0xFFFFu), f32(nine_slice.x >> 16u));
+    let source_px = source_size * globals.tex_sheet_size;
+    let insets = vec4<f32>(unpack4u8u32(nine_slice.y));
+    let start = insets.xz;
+    let end = insets.yw;
+
+    let pos = uv * target_px;
+    let stretched = start + (pos - start) * (source_px - start - end) / max(target_px - start - end, vec2<f32>(1.0));
+    let from_end = source_px - (target_px - pos);
+    let mapped = select(select(stretched, from_end, pos > target_px - end), pos, pos < start);
+    return mapped / source_px;
+}
+
+// Inverse of the srgb decoding done when sampling the texture sheets, so that palette indices are exact.
+fn linear_to_srgb(linear: f32) -> f32 {
+    if linear <= 0.0031308 {
+        return linear * 12.92;
+    }
+    return 1.055 * pow(linear, 1.0 / 2.4) - 0.055;
+}
+
+// Ordered dithering threshold in range (0, 1) from a 4x4 bayer matrix. Used for crossfading sprites.
+fn dither_threshold(pixel: vec2<f32>) -> f32 {
+    var bayer = array<u32, 16>(0u, 8u, 2u, 10u, 12u, 4u, 14u, 6u, 3u, 11u, 1u, 9u, 15u, 7u, 13u, 5u);
+    let x = u32(pixel.x) % 4u;
+    let y = u32(pixel.y) % 4u;
+    return (f32(bayer[y * 4u + x]) + 0.5) / 16.0;
+}
+
 @vertex
 fn vs_main(
     vertex: VertexInput,
     instance: InstanceInput,
     @builtin(vertex_index) vertex_index: u32,
 ) -> VertexOutput {
-    let scale_factor = instance.scale * (instance.tex_coords_sizes.x / (globals.pixels_per_unit / globals.tex_sheet_size));
+    // 9-slice sprites use the target size for the geometry and the source size for texture coordinates
+    var geometry_sizes = instance.tex_coords_sizes;
+    if instance.nine_slice.x != 0u {
+        geometry_sizes = vec2<f32>(f32(instance.nine_slice.x & 0xFFFFu), f32(instance.nine_slice.x >> 16u)) / globals.tex_sheet_size;
+    }
+
+    let scale_factor = instance.scale * (geometry_sizes.x / (globals.pixels_per_unit / globals.tex_sheet_size));
     let anim_data = unpack4u8u32(instance.anim_data);
-    let anim_on = anim_data.x;
+    let anim_flags = anim_data.x;
     let anim_frame_rate = anim_data.y;
     let anim_frame_start = anim_data.z;
     let anim_frame_count = anim_data.w;
@@ -101,10 +182,10 @@ fn vs_main(
 
     if vertex.tex_layout_flags.x == u32(1) && vertex.tex_layout_flags.y == u32(1) {
         // Ortographic layouts, top 2 vertices
-        position_vertical_fix = (instance.tex_coords_sizes.y / instance.tex_coords_sizes.x - 1.0) * scale_factor;
+        position_vertical_fix = (geometry_sizes.y / geometry_sizes.x - 1.0) * scale_factor;
     } else if vertex.tex_layout_flags.x == u32(3) {
         // IsometricHex layouts, Top 3 vertices
-        position_vertical_fix = (instance.tex_coords_sizes.y / camera.angle_cos / instance.tex_coords_sizes.x - 1.0) * scale_factor;
+        position_vertical_fix = (geometry_sizes.y / camera.angle_cos / geometry_sizes.x - 1.0) * scale_factor;
         if vertex_index == u32(9) || vertex_index == u32(10) {
             text_coords_vertical_fix = camera.angle_cos / 2.0 * instance.tex_coords_sizes.x;
         }
@@ -122,16 +203,26 @@ fn vs_main(
     // -------------------- Vertex position --------------------- //
     // ---------------------------------------------------------- //
 
+    // Sprites are rotated in the world plane around the sprite location
+    let vertex_offset = vec2<f32>(
+        vertex.position.x * scale_factor + position_vertical_fix,
+        vertex.position.y * scale_factor + position_vertical_fix,
+    );
+    let rot_sin = sin(instance.rotation);
+    let rot_cos = cos(instance.rotation);
+
     var position = vertex.position;
-    position.x = vertex.position.x * scale_factor + position_vertical_fix + instance.location.x;
-    position.y = vertex.position.y * scale_factor + position_vertical_fix + instance.location.y;
+    position.x = vertex_offset.x * rot_cos - vertex_offset.y * rot_sin + instance.location.x;
+    position.y = vertex_offset.x * rot_sin + vertex_offset.y * rot_cos + instance.location.y;
 
     // ---------------------------------------------------------- //
     // ------------------ Texture coordinates ------------------- //
     // ---------------------------------------------------------- //
 
     
-    var anim_cur_frame = anim_on * ((globals.frame / anim_frame_rate + anim_frame_start) % anim_frame_count);
+    let anim_frame = calc_anim_frame(anim_flags, anim_frame_rate, anim_frame_start, anim_frame_count);
+    let anim_hidden = anim_frame >= anim_frame_count;
+    var anim_cur_frame = min(anim_frame, anim_frame_count - 1u);
     let anim_frame_size_x = instance.tex_coords_sizes.x + 1.0 / globals.tex_sheet_size;
     let anim_frame_size_y = instance.tex_coords_sizes.y + 1.0 / globals.tex_sheet_size;
 
@@ -167,12 +258,21 @@ fn vs_main(
     var out: VertexOutput;
 
     out.clip_pos = camera.vp_mat * vec4<f32>(position, 1.0);
+    if anim_hidden {
+        // Collapse the sprite outside of the clip volume
+        out.clip_pos = vec4<f32>(0.0, 0.0, -1.0, 1.0);
+    }
     out.world_loc = vec2<f32>(position.x, position.y);
     out.scale = instance.scale;
     out.tex_coords = tex_coords;
     out.tex_coords_mask = tex_coords_mask;
     out.tex_sheet_indices = instance.tex_sheet_indices;
     out.type_id = instance.type_id;
+    out.slice_uv = vertex.tex_coord_offset;
+    out.nine_slice = instance.nine_slice;
+    out.slice_source_size = instance.tex_coords_sizes;
+    out.palette = instance.palette;
+    out.reflectivity = instance.reflectivity;
     return out;
 }
 
@@ -200,8 +300,36 @@ fn fs_main(in: VertexOutput) -> OutputBuffers {
     let has_nh_data = tex_sheet_index_nh != 255u;
     let has_mask_data = tex_sheet_index_mask != 255u;
 
-    let base_color = textureSample(tex[tex_sheet_indices.x], tex_sampler, in.tex_coords);
-    let normal_height = textureSample(tex[tex_sheet_index_nh], tex_sampler, in.tex_coords);    
+    // Remap 9-slice coordinates. Offset is the same for the color and the mask.
+    var tex_coords = in.tex_coords;
+    var tex_coords_mask = in.tex_coords_mask;
+    if in.nine_slice.x != 0u {
+        let slice_offset = (nine_slice_uv(in.slice_uv, in.nine_slice, in.slice_source_size) - in.slice_uv) * in.slice_source_size;
+        tex_coords += slice_offset;
+        tex_coords_mask += slice_offset;
+    }
+
+    var base_color = textureSample(tex[tex_sheet_indices.x], tex_sampler, tex_coords);
+
+    // Indexed color: red channel selects the color from the palette row, and alpha is kept as is
+    if in.palette != 0u {
+        let sheet_size = vec2<f32>(textureDimensions(tex[tex_sheet_indices.x]));
+        let index_sample = textureLoad(tex[tex_sheet_indices.x], vec2<i32>(tex_coords * sheet_size), 0);
+        let palette_index = i32(round(linear_to_srgb(index_sample.r) * 255.0));
+        let palette_color = textureLoad(tex_palette, vec2<i32>(palette_index, i32(in.palette - 1u)), 0);
+        base_color = vec4<f32>(palette_color.rgb, base_color.a);
+    }
+    let normal_height = textureSample(tex[tex_sheet_index_nh], tex_sampler, tex_coords);    
+
+    // Crossfade: incoming and outgoing sprites discard complementary pixels
+    let crossfade = tex_sheet_indices.w;
+    if crossfade != 0u {
+        let crossfade_progress = f32(crossfade & 0x7Fu) / 128.0;
+        let crossfade_outgoing = (crossfade & 0x80u) != 0u;
+        if (dither_threshold(in.clip_pos.xy) < crossfade_progress) == crossfade_outgoing {
+            discard;
+        }
+    }
 
     var output: OutputBuffers;
 
@@ -224,7 +352,7 @@ fn fs_main(in: VertexOutput) -> OutputBuffers {
     // Check mask
     var mask_alpha: f32;
     if has_mask_data {
-        let mask_sample = textureSample(tex[tex_sheet_index_mask], tex_sampler, in.tex_coords_mask);
+        let mask_sample = textureSample(tex[tex_sheet_index_mask], tex_sampler, tex_coords_mask);
         mask_alpha = mask_sample.r;
         if in.type_id == TYPE_OCEAN {
             frag_height = min_height * (1.0 - mask_alpha);
@@ -248,7 +376,7 @@ fn fs_main(in: VertexOutput) -> OutputBuffers {
     if has_nh_data {
         if normal_height.x > 0.1 || normal_height.y > 0.1 || normal_height.z > 0.1 {
             output.normal = vec4<f32>(normal_height.xyz, mask_alpha);
-            output.height_id = vec4<f32>(height, 0.0, in.type_id, 1.0);
+            output.height_id = vec4<f32>(height, in.reflectivity, in.type_id, 1.0);
         } else {
             output.normal = vec4<f32>(0.0, 0.0, 0.0, 0.0);
             output.height_id = vec4<f32>(0.0, 0.0, 0.0, 0.0);
@@ -256,7 +384,7 @@ fn fs_main(in: VertexOutput) -> OutputBuffers {
     } else {
         if base_color.a > 0.3 {
             output.normal = vec4<f32>(0.0, 0.0, 1.0, mask_alpha);
-            output.height_id = vec4<f32>(height, 0.0, in.type_id, 1.0);
+            output.height_id = vec4<f32>(height, in.reflectivity, in.type_id, 1.0);
         } else {
             output.normal = vec4<f32>(0.0, 0.0, 0.0, 0.0);
             output.height_id = vec4<f32>(0.0, 0.0, 0.0, 0.0);
@@ -0,0 +1,114 @@
+struct GlobalsUniform {
+    frame: u32,
+    frame_mode: u32,
+    frame_res_x: u32,
+    frame_res_y: u32,
+    window_res_x: u32,
+    window_res_y: u32,
+
+    tex_sheet_size: f32,
+    pixels_per_unit: f32,
+    height_units_total: f32,
+    height_scaled_zero: f32,
+
+    lighting_ambient: f32,
+    lighting_sun: f32,
+    lighting_unused: f32,
+
+    post_bloom: f32,
+    post_color_filter: u32,
+    post_gamma: f32,
+    post_brightness: f32,
+    post_contrast: f32,
+
+    _padding1: f32,
+    _padding2: f32,
+}
+
+struct CameraUniform {
+    vp_mat: mat4x4<f32>,
+    vp_mat_inv: mat4x4<f32>,
+    z_edges: vec2<f32>,
+    loc: vec2<f32>,
+    scale: f32,
+    angle_cos: f32,
+    angle_sin: f32,
+    angle_tan: f32,
+}
+
+struct VertexInput {
+    @location(0) world_loc: vec2<f32>,
+    @location(1) tex_coords: vec2<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_pos: vec4<f32>,
+    @location(0) world_loc: vec2<f32>,
+    @location(1) tex_coords: vec2<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> globals: GlobalsUniform;
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(2) @binding(0)
+var tex_color: texture_2d<f32>;
+@group(2) @binding(1)
+var tex_normal: texture_2d<f32>;
+@group(2) @binding(2)
+var tex_height_id: texture_2d<f32>;
+@group(2) @binding(3)
+var tex_sampler: sampler;
+
+
+@vertex
+fn vs_main(vertex: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_pos = camera.vp_mat * vec4<f32>(vertex.world_loc, 0.0, 1.0);
+    out.world_loc = vertex.world_loc;
+    out.tex_coords = vertex.tex_coords;
+    return out;
+}
+
+
+struct OutputBuffers {
+  @builtin(frag_depth) depth: f32,
+  @location(0) color: vec4<f32>,
+  @location(1) normal: vec4<f32>,
+  @location(2) height_id: vec4<f32>,
+}
+
+
+@fragment
+fn fs_main(in: VertexOutput) -> OutputBuffers {
+    let color = textureSample(tex_color, tex_sampler, in.tex_coords);
+    let normal = textureSample(tex_normal, tex_sampler, in.tex_coords);
+    let height_id = textureSample(tex_height_id, tex_sampler, in.tex_coords);
+
+    // Impostors of neighbouring chunks overlap, so empty pixels must not cover anything
+    if color.a <= 0.0 {
+        discard;
+    }
+
+    // Depth is recalculated from the baked height the same way as in the gbuffer pass,
+    // so that impostors are sorted correctly against dynamic sprites.
+    let min_height = globals.height_units_total * globals.height_scaled_zero * -1.0;
+    let frag_height = height_id.x * globals.height_units_total + min_height;
+
+    let screen_y_coord = in.clip_pos.y / f32(globals.frame_res_y);
+    let camera_height = camera.z_edges.x + (camera.z_edges.y - camera.z_edges.x) * (1.0 - screen_y_coord);
+    let camera_x_y_offset = camera_height * camera.angle_tan / 1.414;
+    let frag_x_y_offset = frag_height * camera.angle_tan / 1.414;
+    let camera_coords = vec3<f32>(in.world_loc.x - camera_x_y_offset, in.world_loc.y - camera_x_y_offset, camera_height);
+    let frag_coords = vec3<f32>(in.world_loc.x - frag_x_y_offset, in.world_loc.y - frag_x_y_offset, frag_height);
+
+    let max_view_distance = (camera.z_edges.y - min_height) / camera.angle_sin;
+
+    var output: OutputBuffers;
+    output.color = color;
+    output.normal = normal;
+    output.height_id = height_id;
+    output.depth = min(distance(camera_coords, frag_coords) / max_view_distance, 1.0);
+    return output;
+}
@@ -0,0 +1,54 @@
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    // Single triangle that covers the whole target mip level
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+fn load_quad(position: vec4<f32>) -> array<vec4<f32>, 4> {
+    let coords = vec2<i32>(position.xy) * 2;
+    return array<vec4<f32>, 4>(
+        textureLoad(t_source, coords, 0),
+        textureLoad(t_source, coords + vec2<i32>(1, 0), 0),
+        textureLoad(t_source, coords + vec2<i32>(0, 1), 0),
+        textureLoad(t_source, coords + vec2<i32>(1, 1), 0),
+    );
+}
+
+// Plain 2x2 box filter. Used for data sheets, where alpha is not coverage.
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let quad = load_quad(in.position);
+    return (quad[0] + quad[1] + quad[2] + quad[3]) * 0.25;
+}
+
+// 2x2 box filter with colors weighted by alpha.
+// Fully transparent texels usually have black or garbage color, which would otherwise bleed into sprite edges.
+@fragment
+fn fs_color(in: VertexOutput) -> @location(0) vec4<f32> {
+    let quad = load_quad(in.position);
+
+    var color = vec3<f32>(0.0);
+    var alpha = 0.0;
+    for (var i = 0; i < 4; i++) {
+        color += quad[i].rgb * quad[i].a;
+        alpha += quad[i].a;
+    }
+
+    if (alpha <= 0.0) {
+        return vec4<f32>((quad[0].rgb + quad[1].rgb + quad[2].rgb + quad[3].rgb) * 0.25, 0.0);
+    }
+
+    return vec4<f32>(color / alpha, alpha * 0.25);
+}
@@ -16,6 +16,10 @@ struct GlobalsUniform {
     lighting_unused: f32,
 
     post_bloom: f32,
+    post_color_filter: u32,
+    post_gamma: f32,
+    post_brightness: f32,
+    post_contrast: f32,
 
     _padding1: f32,
     _padding2: f32,
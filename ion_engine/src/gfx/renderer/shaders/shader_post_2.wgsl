@@ -16,6 +16,10 @@ struct GlobalsUniform {
     lighting_unused: f32,
 
     post_bloom: f32,
+    post_color_filter: u32,
+    post_gamma: f32,
+    post_brightness: f32,
+    post_contrast: f32,
 
     _padding1: f32,
     _padding2: f32,
@@ -83,13 +87,71 @@ var tex_post: texture_2d<f32>;
 @group(2) @binding(1)
 var tex_bloom: texture_2d<f32>;
 @group(2) @binding(2)
+var tex_distortion: texture_2d<f32>;
+@group(2) @binding(3)
+var tex_reflection: texture_2d<f32>;
+@group(2) @binding(4)
 var linear_sampler: sampler;
 
+// Color vision deficiency simulation matrices (Machado et al. 2009, full severity), given as rows.
+// Multiplied as `color * matrix`, so the constructor columns act as rows.
+fn simulate_color_deficiency(color: vec3<f32>, color_filter: u32) -> vec3<f32> {
+    switch color_filter {
+        case 1u : {
+            return color * mat3x3<f32>(
+                vec3<f32>(0.152286, 1.052583, -0.204868),
+                vec3<f32>(0.114503, 0.786281, 0.099216),
+                vec3<f32>(-0.003882, -0.048116, 1.051998),
+            );
+        }
+        case 2u : {
+            return color * mat3x3<f32>(
+                vec3<f32>(0.367322, 0.860646, -0.227968),
+                vec3<f32>(0.280085, 0.672501, 0.047413),
+                vec3<f32>(-0.011820, 0.042940, 0.968881),
+            );
+        }
+        case 3u : {
+            return color * mat3x3<f32>(
+                vec3<f32>(1.255528, -0.076749, -0.178779),
+                vec3<f32>(-0.078411, 0.930809, 0.147602),
+                vec3<f32>(0.004733, 0.691367, 0.303900),
+            );
+        }
+        default : {
+            return color;
+        }
+    }
+}
+
+// Daltonization: the color difference lost to the deficiency is redistributed into channels that remain visible.
+fn correct_color_deficiency(color: vec3<f32>, color_filter: u32) -> vec3<f32> {
+    let error = color - simulate_color_deficiency(color, color_filter);
+
+    var shift: vec3<f32>;
+    if (color_filter == 3u) {
+        shift = vec3<f32>(error.r + 0.7 * error.b, error.g + 0.7 * error.b, 0.0);
+    } else {
+        shift = vec3<f32>(0.0, 0.7 * error.r + error.g, 0.7 * error.r + error.b);
+    }
+
+    return clamp(color + shift, vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let exposure = 1.0;
-    let bloom = textureSampleLevel(tex_bloom, linear_sampler, in.tex_coords, 0.0);
-    let color = textureSampleLevel(tex_post, linear_sampler, in.tex_coords, 0.0);
+
+    // Distortion sprites warp the scene by offsetting where it is sampled from
+    let distortion = textureSampleLevel(tex_distortion, linear_sampler, in.tex_coords, 0.0).xy;
+    let tex_coords = in.tex_coords + distortion;
+
+    let bloom = textureSampleLevel(tex_bloom, linear_sampler, tex_coords, 0.0);
+    let scene = textureSampleLevel(tex_post, linear_sampler, tex_coords, 0.0);
+
+    // Reflections are blended over the lit scene before tone mapping
+    let reflection = textureSampleLevel(tex_reflection, linear_sampler, tex_coords, 0.0);
+    let color = vec4<f32>(mix(scene.rgb, reflection.rgb, reflection.a), scene.a);
 
     var to_be_mapped: vec3<f32>;
     var do_mapping: bool;
@@ -112,6 +174,9 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     var tonemapped: vec3<f32>;
     if (do_mapping) {
         tonemapped = vec3<f32>(1.0, 1.0, 1.0) - exp(- to_be_mapped.xyz * exposure);
+        if (globals.post_color_filter != 0u) {
+            tonemapped = correct_color_deficiency(tonemapped, globals.post_color_filter);
+        }
     } else {
         tonemapped = to_be_mapped.xyz;
     }
@@ -1,3 +1,30 @@
+struct GlobalsUniform {
+    frame: u32,
+    frame_mode: u32,
+    frame_res_x: u32,
+    frame_res_y: u32,
+    window_res_x: u32,
+    window_res_y: u32,
+
+    tex_sheet_size: f32,
+    pixels_per_unit: f32,
+    height_units_total: f32,
+    height_scaled_zero: f32,
+
+    lighting_ambient: f32,
+    lighting_sun: f32,
+    lighting_unused: f32,
+
+    post_bloom: f32,
+    post_color_filter: u32,
+    post_gamma: f32,
+    post_brightness: f32,
+    post_contrast: f32,
+
+    _padding1: f32,
+    _padding2: f32,
+}
+
 struct VertexOutput {
     @builtin(position) position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
@@ -45,10 +72,44 @@ fn vs_main(
 var t_post: texture_2d<f32>;
 @group(0) @binding(1)
 var s_linear: sampler;
+@group(1) @binding(0)
+var<uniform> globals: GlobalsUniform;
+
+// User display calibration, applied to the tone mapped color before the surface sRGB encoding.
+fn adjust_display(color: vec3<f32>) -> vec3<f32> {
+    let gamma_corrected = pow(max(color, vec3<f32>(0.0)), vec3<f32>(1.0 / globals.post_gamma));
+    let contrasted = (gamma_corrected - 0.5) * globals.post_contrast + 0.5;
+    return clamp(contrasted + globals.post_brightness, vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// Supersampled frames are downsampled with a 3x3 tent of bilinear taps spread over the window pixel footprint.
+// A single bilinear tap would only average 2x2 source texels, which aliases at fractional scales.
+fn sample_post(tex_coords: vec2<f32>) -> vec4<f32> {
+    let source_res = vec2<f32>(f32(globals.frame_res_x), f32(globals.frame_res_y));
+    let target_res = vec2<f32>(f32(globals.window_res_x), f32(globals.window_res_y));
+    let ratio = source_res / target_res;
+
+    if (ratio.x <= 1.0 && ratio.y <= 1.0) {
+        return textureSampleLevel(t_post, s_linear, tex_coords, 0.0);
+    }
+
+    let step = 0.5 * max(ratio, vec2<f32>(1.0)) / source_res;
+    let weights = vec3<f32>(0.25, 0.5, 0.25);
+
+    var color = vec4<f32>(0.0);
+    for (var y = 0; y < 3; y++) {
+        for (var x = 0; x < 3; x++) {
+            let offset = vec2<f32>(f32(x - 1), f32(y - 1)) * step;
+            color += textureSampleLevel(t_post, s_linear, tex_coords + offset, 0.0) * weights[x] * weights[y];
+        }
+    }
+    return color;
+}
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_post, s_linear, in.tex_coords);
+    let color = sample_post(in.tex_coords);
+    return vec4<f32>(adjust_display(color.rgb), color.a);
 }
 
 
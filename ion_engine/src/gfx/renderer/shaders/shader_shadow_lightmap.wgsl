@@ -0,0 +1,90 @@
+struct GlobalsUniform {
+    frame: u32,
+    frame_mode: u32,
+    frame_res_x: u32,
+    frame_res_y: u32,
+    window_res_x: u32,
+    window_res_y: u32,
+
+    tex_sheet_size: f32,
+    pixels_per_unit: f32,
+    height_units_total: f32,
+    height_scaled_zero: f32,
+
+    lighting_ambient: f32,
+    lighting_sun: f32,
+    lighting_unused: f32,
+
+    post_bloom: f32,
+    post_color_filter: u32,
+    post_gamma: f32,
+    post_brightness: f32,
+    post_contrast: f32,
+
+    _padding1: f32,
+    _padding2: f32,
+}
+
+struct CameraUniform {
+    vp_mat: mat4x4<f32>,
+    vp_mat_inv: mat4x4<f32>,
+    z_edges: vec2<f32>,
+    loc: vec2<f32>,
+    scale: f32,
+    angle_cos: f32,
+    angle_sin: f32,
+    angle_tan: f32,
+}
+
+struct VertexInput {
+    @location(0) world_loc: vec2<f32>,
+    @location(1) tex_coords: vec2<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_pos: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> globals: GlobalsUniform;
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(2) @binding(0)
+var tex_lightmap: texture_2d<f32>;
+@group(2) @binding(1)
+var tex_lightmap_sampler: sampler;
+
+@group(3) @binding(0)
+var tex_height: texture_2d<f32>;
+@group(3) @binding(1)
+var tex_height_sampler: sampler;
+
+
+@vertex
+fn vs_main(vertex: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_pos = camera.vp_mat * vec4<f32>(vertex.world_loc, 0.0, 1.0);
+    out.tex_coords = vertex.tex_coords;
+    return out;
+}
+
+
+// Shadows are masked to the ground the same way as in the shadow pass, so baked and sprite shadows match.
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let screen_x_coord = in.clip_pos.x / f32(globals.frame_res_x);
+    let screen_y_coord = in.clip_pos.y / f32(globals.frame_res_y);
+
+    let shadow_alpha = textureSample(tex_lightmap, tex_lightmap_sampler, in.tex_coords).r;
+    let height_id = textureSample(tex_height, tex_height_sampler, vec2<f32>(screen_x_coord, screen_y_coord));
+    let height = height_id.x;
+
+    let height_distance = abs(height - globals.height_scaled_zero);
+    let max_distance = 0.02; // 2% away from ground level
+    let height_alpha = 1.0 - smoothstep(0.0, max_distance, height_distance);
+    let sun_alpha = max(min(globals.lighting_sun / 2.0, 1.0), 0.5);
+
+    return vec4<f32>(0.0, 0.0, 0.0, shadow_alpha * height_alpha * sun_alpha);
+}
@@ -0,0 +1,144 @@
+struct GlobalsUniform {
+    frame: u32,
+    frame_mode: u32,
+    frame_res_x: u32,
+    frame_res_y: u32,
+    window_res_x: u32,
+    window_res_y: u32,
+
+    tex_sheet_size: f32,
+    pixels_per_unit: f32,
+    height_units_total: f32,
+    height_scaled_zero: f32,
+
+    lighting_ambient: f32,
+    lighting_sun: f32,
+    lighting_unused: f32,
+
+    post_bloom: f32,
+    post_color_filter: u32,
+    post_gamma: f32,
+    post_brightness: f32,
+    post_contrast: f32,
+
+    _padding1: f32,
+    _padding2: f32,
+}
+
+struct CameraUniform {
+    vp_mat: mat4x4<f32>,
+    vp_mat_inv: mat4x4<f32>,
+    z_edges: vec2<f32>,
+    loc: vec2<f32>,
+    scale: f32,
+    angle_cos: f32,
+    angle_sin: f32,
+    angle_tan: f32,
+}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32,) -> VertexOutput {
+    var out: VertexOutput;
+
+    switch (i32(vertex_index)) {
+        case 0 : {
+            out.position = vec4<f32>(1.0, 1.0, 1.0, 1.0);
+            out.tex_coords = vec2<f32>(1.0, 0.0);
+        }
+        case 1 : {
+            out.position = vec4<f32>(- 1.0, 1.0, 1.0, 1.0);
+            out.tex_coords = vec2<f32>(0.0, 0.0);
+        }
+        case 2 : {
+            out.position = vec4<f32>(- 1.0, - 1.0, 1.0, 1.0);
+            out.tex_coords = vec2<f32>(0.0, 1.0);
+        }
+        case 3 : {
+            out.position = vec4<f32>(1.0, 1.0, 1.0, 1.0);
+            out.tex_coords = vec2<f32>(1.0, 0.0);
+        }
+        case 4 : {
+            out.position = vec4<f32>(- 1.0, - 1.0, 1.0, 1.0);
+            out.tex_coords = vec2<f32>(0.0, 1.0);
+        }
+        case 5 : {
+            out.position = vec4<f32>(1.0, - 1.0, 1.0, 1.0);
+            out.tex_coords = vec2<f32>(1.0, 1.0);
+        }
+        default : { }
+    }
+
+    return out;
+}
+
+@group(0) @binding(0)
+var<uniform> globals: GlobalsUniform;
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Fragment shader
+@group(2) @binding(0)
+var tex_post: texture_2d<f32>;
+@group(2) @binding(1)
+var tex_height_id: texture_2d<f32>;
+@group(2) @binding(2)
+var linear_sampler: sampler;
+
+// March distance in world units, along the diagonal that points up on the screen
+const MAX_DISTANCE: f32 = 8.0;
+const MAX_STEPS: i32 = 48;
+
+fn world_to_tex_coords(world_loc: vec2<f32>) -> vec2<f32> {
+    let clip = camera.vp_mat * vec4<f32>(world_loc, 0.0, 1.0);
+    return vec2<f32>(clip.x * 0.5 + 0.5, 0.5 - clip.y * 0.5);
+}
+
+fn load_height_id(tex_coords: vec2<f32>) -> vec4<f32> {
+    let size = vec2<f32>(textureDimensions(tex_height_id));
+    return textureLoad(tex_height_id, vec2<i32>(tex_coords * size), 0);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let surface = load_height_id(in.tex_coords);
+    let reflectivity = surface.y;
+    if reflectivity < 0.01 {
+        return vec4<f32>(0.0, 0.0, 0.0, 0.0);
+    }
+
+    let surface_height = (surface.x - globals.height_scaled_zero) * globals.height_units_total;
+    let clip_coords = vec4<f32>(in.tex_coords.x * 2.0 - 1.0, 1.0 - in.tex_coords.y * 2.0, 0.0, 1.0);
+    let world_loc = (camera.vp_mat_inv * clip_coords).xy;
+
+    // A sprite pixel at height h is drawn h * tan(angle) up the screen diagonal from where it touches the ground,
+    // so its mirror image is twice that distance away from the pixel itself.
+    let screen_up = vec2<f32>(1.0, 1.0) / sqrt(2.0);
+    let step_length = MAX_DISTANCE / f32(MAX_STEPS);
+    for (var i = 1; i <= MAX_STEPS; i++) {
+        let march_distance = f32(i) * step_length;
+        let tex_coords = world_to_tex_coords(world_loc + screen_up * march_distance);
+        if any(tex_coords < vec2<f32>(0.0)) || any(tex_coords > vec2<f32>(1.0)) {
+            break;
+        }
+
+        let candidate = load_height_id(tex_coords);
+        if candidate.w == 0.0 {
+            continue;
+        }
+
+        let height = (candidate.x - globals.height_scaled_zero) * globals.height_units_total - surface_height;
+        let mirror_distance = 2.0 * height * camera.angle_tan;
+        if height > 0.0 && abs(mirror_distance - march_distance) <= step_length {
+            let color = textureSampleLevel(tex_post, linear_sampler, tex_coords, 0.0).rgb;
+            let fade = 1.0 - march_distance / MAX_DISTANCE;
+            return vec4<f32>(color, reflectivity * fade);
+        }
+    }
+
+    return vec4<f32>(0.0, 0.0, 0.0, 0.0);
+}
@@ -0,0 +1,341 @@
+use std::f32::consts::PI;
+
+use crate::core::coordinates::Location;
+
+use super::{AnimMode, Sprite};
+
+// ---------------------------------------------------------- //
+// --------------------- Bone transforms -------------------- //
+// ---------------------------------------------------------- //
+
+/// Location, rotation (radians, counter-clockwise) and uniform scale of a bone.
+/// Bone transforms are relative to the parent bone, or to the gfx ref location for root bones.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoneTransform {
+    pub loc: Location,
+    pub rot: f32,
+    pub scale: f32,
+}
+
+impl BoneTransform {
+    pub const IDENTITY: BoneTransform = BoneTransform {
+        loc: Location { x: 0.0, y: 0.0 },
+        rot: 0.0,
+        scale: 1.0,
+    };
+
+    pub fn new(loc: Location, rot: f32, scale: f32) -> Self {
+        Self { loc, rot, scale }
+    }
+
+    /// Transforms a location from the space of this transform to the parent space.
+    pub fn apply(&self, loc: Location) -> Location {
+        let (sin, cos) = self.rot.sin_cos();
+        Location {
+            x: (loc.x * cos - loc.y * sin) * self.scale + self.loc.x,
+            y: (loc.x * sin + loc.y * cos) * self.scale + self.loc.y,
+        }
+    }
+
+    /// Combines this transform with a child transform that is relative to it.
+    pub fn then(&self, child: &BoneTransform) -> BoneTransform {
+        BoneTransform {
+            loc: self.apply(child.loc),
+            rot: self.rot + child.rot,
+            scale: self.scale * child.scale,
+        }
+    }
+
+    /// Linear interpolation. Rotation is interpolated along the shortest arc.
+    fn lerp(&self, other: &BoneTransform, t: f32) -> BoneTransform {
+        let rot_diff = (other.rot - self.rot + PI).rem_euclid(2.0 * PI) - PI;
+        BoneTransform {
+            loc: Location {
+                x: self.loc.x + (other.loc.x - self.loc.x) * t,
+                y: self.loc.y + (other.loc.y - self.loc.y) * t,
+            },
+            rot: self.rot + rot_diff * t,
+            scale: self.scale + (other.scale - self.scale) * t,
+        }
+    }
+}
+
+// ---------------------------------------------------------- //
+// ------------------------ Skeleton ------------------------ //
+// ---------------------------------------------------------- //
+
+/// Bone of a [`Skeleton`] with its bind pose.
+/// Parent bones must come before their children in the bone list.
+#[derive(Debug, Clone)]
+pub struct Bone {
+    pub name: String,
+    pub parent: Option<usize>,
+    pub bind_pose: BoneTransform,
+}
+
+/// Sprite attached to a bone. Sprite location, rotation and scale are relative to the bone.
+#[derive(Debug, Clone)]
+pub struct SkeletonSlot {
+    pub(crate) bone: usize,
+    pub(crate) sprite: Sprite,
+    pub(crate) shadow: bool,
+}
+
+impl SkeletonSlot {
+    pub fn new(bone: usize, sprite: Sprite) -> Self {
+        Self {
+            bone,
+            sprite,
+            shadow: false,
+        }
+    }
+
+    /// Slot that is rendered to the shadow pass instead of the color pass.
+    pub fn new_shadow(bone: usize, sprite: Sprite) -> Self {
+        Self {
+            bone,
+            sprite,
+            shadow: true,
+        }
+    }
+
+    pub fn is_shadow(&self) -> bool {
+        self.shadow
+    }
+}
+
+/// Keyframe of a bone. The transform replaces the bind pose of the bone at the given frame.
+#[derive(Debug, Clone, Copy)]
+pub struct BoneKey {
+    pub frame: u32,
+    pub transform: BoneTransform,
+}
+
+/// Keyframes of a single bone, sorted by frame.
+#[derive(Debug, Clone)]
+pub struct BoneTrack {
+    pub bone: usize,
+    pub keys: Vec<BoneKey>,
+}
+
+/// Named animation of a skeleton. Length is in universe frames.
+/// Bones without a track stay in their bind pose.
+///
+/// Looping animations should have matching keys at frame 0 and at the last frame,
+/// since interpolation does not wrap from the last key to the first.
+#[derive(Debug, Clone)]
+pub struct SkeletonAnimation {
+    pub name: String,
+    pub length: u32,
+    pub tracks: Vec<BoneTrack>,
+}
+
+/// Bone hierarchy with sprites attached to the bones, and animations that move the bones.
+///
+/// Skeletons are used through [`super::GfxBundle::new_skeleton`]. The animation is selected with
+/// [`super::GfxRef::new_skeleton_anim`] and posed on the render thread every render frame.
+/// Poses are interpolated between keyframes and between universe frames, so motion stays smooth
+/// even when the render frame rate is higher than the universe frame rate.
+/// Like crossfades, skeleton animations only progress in dynamic gfx, since chunked gfx is cached.
+#[derive(Debug, Clone)]
+pub struct Skeleton {
+    bones: Vec<Bone>,
+    pub(crate) slots: Vec<SkeletonSlot>,
+    animations: Vec<SkeletonAnimation>,
+}
+
+impl Skeleton {
+    /// Creates a new skeleton.
+    ///
+    /// Panics if a parent bone comes after its child, if a slot or track refers to a bone that does not exist,
+    /// or if track keys are not sorted by frame.
+    pub fn new(bones: Vec<Bone>, slots: Vec<SkeletonSlot>, animations: Vec<SkeletonAnimation>) -> Self {
+        for (index, bone) in bones.iter().enumerate() {
+            assert!(
+                bone.parent.is_none_or(|parent| parent < index),
+                "Parent of bone {} must come before it",
+                bone.name
+            );
+        }
+        for slot in &slots {
+            assert!(slot.bone < bones.len(), "Slot refers to a missing bone {}", slot.bone);
+        }
+        for animation in &animations {
+            for track in &animation.tracks {
+                assert!(
+                    track.bone < bones.len(),
+                    "Animation {} refers to a missing bone {}",
+                    animation.name,
+                    track.bone
+                );
+                assert!(
+                    track.keys.windows(2).all(|keys| keys[0].frame <= keys[1].frame),
+                    "Keys of animation {} are not sorted",
+                    animation.name
+                );
+            }
+        }
+
+        Self {
+            bones,
+            slots,
+            animations,
+        }
+    }
+
+    pub fn bones(&self) -> &[Bone] {
+        &self.bones
+    }
+
+    pub fn animations(&self) -> &[SkeletonAnimation] {
+        &self.animations
+    }
+
+    /// Index of the animation with the given name, for use in [`super::GfxRef::new_skeleton_anim`].
+    pub fn animation_index(&self, name: &str) -> Option<u32> {
+        self.animations
+            .iter()
+            .position(|animation| animation.name == name)
+            .map(|index| index as u32)
+    }
+
+    /// Calculates bone transforms relative to the gfx ref location.
+    /// Returns `false` if the skeleton is hidden, which happens after a [`AnimMode::OnceHide`] animation has ended.
+    /// An animation index that is out of range poses the skeleton in its bind pose.
+    pub(crate) fn pose(&self, animation: u32, time: f32, mode: AnimMode, pose: &mut Vec<BoneTransform>) -> bool {
+        pose.clear();
+
+        let animation = self.animations.get(animation as usize);
+        let time = match animation {
+            Some(animation) => match Self::animation_time(time, animation.length as f32, mode) {
+                Some(time) => time,
+                None => return false,
+            },
+            None => 0.0,
+        };
+
+        for (index, bone) in self.bones.iter().enumerate() {
+            let local = animation
+                .and_then(|animation| animation.tracks.iter().find(|track| track.bone == index))
+                .and_then(|track| Self::sample_track(track, time))
+                .unwrap_or(bone.bind_pose);
+
+            let transform = match bone.parent {
+                Some(parent) => pose[parent].then(&local),
+                None => local,
+            };
+            pose.push(transform);
+        }
+
+        true
+    }
+
+    /// Maps time since the animation start to time within the animation. `None` if the animation is hidden.
+    fn animation_time(time: f32, length: f32, mode: AnimMode) -> Option<f32> {
+        if length <= 0.0 {
+            return Some(0.0);
+        }
+
+        match mode {
+            AnimMode::Loop => Some(time.rem_euclid(length)),
+            AnimMode::OnceHold => Some(time.clamp(0.0, length)),
+            AnimMode::OnceHide => (time <= length).then_some(time.max(0.0)),
+            AnimMode::PingPong => {
+                let time = time.rem_euclid(2.0 * length);
+                Some(if time > length { 2.0 * length - time } else { time })
+            }
+        }
+    }
+
+    fn sample_track(track: &BoneTrack, time: f32) -> Option<BoneTransform> {
+        let first = track.keys.first()?;
+        let last = track.keys.last()?;
+        if time <= first.frame as f32 {
+            return Some(first.transform);
+        }
+        if time >= last.frame as f32 {
+            return Some(last.transform);
+        }
+
+        let next_index = track.keys.partition_point(|key| key.frame as f32 <= time);
+        let (prev, next) = (&track.keys[next_index - 1], &track.keys[next_index]);
+        let t = (time - prev.frame as f32) / (next.frame - prev.frame) as f32;
+        Some(prev.transform.lerp(&next.transform, t))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arm_skeleton() -> Skeleton {
+        let bones = vec![
+            Bone {
+                name: "shoulder".to_string(),
+                parent: None,
+                bind_pose: BoneTransform::new(Location::new(1.0, 0.0), 0.0, 1.0),
+            },
+            Bone {
+                name: "hand".to_string(),
+                parent: Some(0),
+                bind_pose: BoneTransform::new(Location::new(1.0, 0.0), 0.0, 1.0),
+            },
+        ];
+        let wave = SkeletonAnimation {
+            name: "wave".to_string(),
+            length: 10,
+            tracks: vec![BoneTrack {
+                bone: 0,
+                keys: vec![
+                    BoneKey {
+                        frame: 0,
+                        transform: BoneTransform::new(Location::new(1.0, 0.0), 0.0, 1.0),
+                    },
+                    BoneKey {
+                        frame: 10,
+                        transform: BoneTransform::new(Location::new(1.0, 0.0), PI / 2.0, 1.0),
+                    },
+                ],
+            }],
+        };
+        Skeleton::new(bones, Vec::new(), vec![wave])
+    }
+
+    fn assert_loc(loc: Location, x: f32, y: f32) {
+        assert!(
+            (loc.x - x).abs() < 1e-4 && (loc.y - y).abs() < 1e-4,
+            "{:?} != ({}, {})",
+            loc,
+            x,
+            y
+        );
+    }
+
+    #[test]
+    fn child_bones_follow_interpolated_parents() {
+        let skeleton = arm_skeleton();
+        let mut pose = Vec::new();
+
+        assert!(skeleton.pose(0, 5.0, AnimMode::Loop, &mut pose));
+        assert_loc(pose[0].loc, 1.0, 0.0);
+        assert!((pose[1].rot - PI / 4.0).abs() < 1e-4);
+        assert_loc(pose[1].loc, 1.0 + (PI / 4.0).cos(), (PI / 4.0).sin());
+
+        assert!(skeleton.pose(0, 15.0, AnimMode::OnceHold, &mut pose));
+        assert_loc(pose[1].loc, 1.0, 1.0);
+
+        assert!(skeleton.pose(5, 5.0, AnimMode::Loop, &mut pose));
+        assert_loc(pose[1].loc, 2.0, 0.0);
+    }
+
+    #[test]
+    fn anim_modes_map_time_to_animation() {
+        assert_eq!(Skeleton::animation_time(12.0, 10.0, AnimMode::Loop), Some(2.0));
+        assert_eq!(Skeleton::animation_time(12.0, 10.0, AnimMode::OnceHold), Some(10.0));
+        assert_eq!(Skeleton::animation_time(12.0, 10.0, AnimMode::OnceHide), None);
+        assert_eq!(Skeleton::animation_time(12.0, 10.0, AnimMode::PingPong), Some(8.0));
+
+        let mut pose = Vec::new();
+        assert!(!arm_skeleton().pose(0, 11.0, AnimMode::OnceHide, &mut pose));
+    }
+}
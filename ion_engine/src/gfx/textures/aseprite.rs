@@ -0,0 +1,659 @@
+use image::RgbaImage;
+
+// ---------------------------------------------------------- //
+// ------------------- Aseprite file format ------------------ //
+// ---------------------------------------------------------- //
+
+// See https://github.com/aseprite/aseprite/blob/main/docs/ase-file-specs.md
+
+const HEADER_MAGIC: u16 = 0xA5E0;
+const FRAME_MAGIC: u16 = 0xF1FA;
+const HEADER_SIZE: usize = 128;
+
+const CHUNK_OLD_PALETTE: u16 = 0x0004;
+const CHUNK_LAYER: u16 = 0x2004;
+const CHUNK_CEL: u16 = 0x2005;
+const CHUNK_TAGS: u16 = 0x2018;
+const CHUNK_PALETTE: u16 = 0x2019;
+const CHUNK_SLICE: u16 = 0x2022;
+
+/// A decoded aseprite file. All frames are flattened from the visible layers.
+#[derive(Debug)]
+pub(crate) struct AsepriteFile {
+    pub width: u32,
+    pub height: u32,
+    pub frames: Vec<AsepriteFrame>,
+    pub tags: Vec<AsepriteTag>,
+    pub slices: Vec<AsepriteSlice>,
+}
+
+#[derive(Debug)]
+pub(crate) struct AsepriteFrame {
+    pub image: RgbaImage,
+    pub duration_ms: u16,
+}
+
+/// Named frame range. Both ends are inclusive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct AsepriteTag {
+    pub name: String,
+    pub from: u16,
+    pub to: u16,
+}
+
+/// Named region of the sprite, using the bounds of its first key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct AsepriteSlice {
+    pub name: String,
+    pub frame: u32,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl AsepriteFile {
+    /// Parses an aseprite file and flattens its frames.
+    ///
+    /// Layers are composited with normal blending, respecting layer and cel opacity and visibility.
+    /// Other blend modes, tilemaps and cel z-indices are not supported and are treated as normal layers.
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader::new(bytes);
+
+        reader.skip(4)?; // File size
+        if reader.u16()? != HEADER_MAGIC {
+            return Err("Invalid aseprite header magic".to_string());
+        }
+        let frame_count = reader.u16()?;
+        let width = reader.u16()? as u32;
+        let height = reader.u16()? as u32;
+        let color_depth = reader.u16()?;
+        let flags = reader.u32()?;
+        reader.skip(2 + 4 + 4)?; // Speed and reserved
+        let transparent_index = reader.u8()?;
+        reader.pos = HEADER_SIZE;
+
+        if frame_count == 0 {
+            return Err("Aseprite file has no frames".to_string());
+        }
+        if !matches!(color_depth, 8 | 16 | 32) {
+            return Err(format!("Unsupported aseprite color depth: {}", color_depth));
+        }
+        let layer_opacity_valid = flags & 1 != 0;
+
+        let mut layers: Vec<Layer> = Vec::new();
+        let mut palette: Vec<[u8; 4]> = Vec::new();
+        let mut cels: Vec<Vec<(usize, Cel)>> = Vec::new();
+        let mut durations = Vec::new();
+        let mut tags = Vec::new();
+        let mut slices = Vec::new();
+
+        for frame_index in 0..frame_count as usize {
+            let frame_start = reader.pos;
+            let frame_size = reader.u32()? as usize;
+            if reader.u16()? != FRAME_MAGIC {
+                return Err(format!("Invalid aseprite frame magic in frame {}", frame_index));
+            }
+            let old_chunk_count = reader.u16()?;
+            durations.push(reader.u16()?);
+            reader.skip(2)?;
+            let new_chunk_count = reader.u32()?;
+            let chunk_count = if new_chunk_count == 0 {
+                old_chunk_count as u32
+            } else {
+                new_chunk_count
+            };
+
+            let mut frame_cels = Vec::new();
+            for _ in 0..chunk_count {
+                let chunk_start = reader.pos;
+                let chunk_size = reader.u32()? as usize;
+                let chunk_type = reader.u16()?;
+                let mut chunk = Reader::new(reader.slice(chunk_start + 6, chunk_start + chunk_size)?);
+
+                match chunk_type {
+                    CHUNK_LAYER => layers.push(Layer::parse(&mut chunk, &layers, layer_opacity_valid)?),
+                    CHUNK_CEL => frame_cels.push(Cel::parse(&mut chunk, color_depth, &palette, transparent_index)?),
+                    CHUNK_PALETTE => Self::parse_palette(&mut chunk, &mut palette)?,
+                    CHUNK_OLD_PALETTE if palette.is_empty() => Self::parse_old_palette(&mut chunk, &mut palette)?,
+                    CHUNK_TAGS => tags = Self::parse_tags(&mut chunk)?,
+                    CHUNK_SLICE => slices.push(Self::parse_slice(&mut chunk)?),
+                    _ => {}
+                }
+
+                reader.pos = chunk_start + chunk_size;
+            }
+
+            cels.push(frame_cels);
+            reader.pos = frame_start + frame_size;
+        }
+
+        let frames: Vec<_> = (0..cels.len())
+            .map(|frame_index| {
+                let mut image = RgbaImage::new(width, height);
+                for (layer_index, layer) in layers.iter().enumerate() {
+                    if !layer.visible || layer.is_group {
+                        continue;
+                    }
+                    let Some(cel) = Self::find_cel(&cels, frame_index, layer_index) else {
+                        continue;
+                    };
+                    let opacity = cel.opacity as u32 * layer.opacity as u32 / 255;
+                    Self::blend_cel(&mut image, cel, opacity);
+                }
+                AsepriteFrame {
+                    image,
+                    duration_ms: durations[frame_index],
+                }
+            })
+            .collect();
+
+        // Drop tags that refer to frames outside of the file
+        tags.retain(|tag: &AsepriteTag| tag.from <= tag.to && (tag.to as usize) < frames.len());
+
+        Ok(Self {
+            width,
+            height,
+            frames,
+            tags,
+            slices,
+        })
+    }
+
+    /// Shortest and longest duration of the given frames, in milliseconds.
+    pub fn duration_range_ms(&self, from: u16, to: u16) -> (u16, u16) {
+        let durations = self.frames[from as usize..=to as usize]
+            .iter()
+            .map(|frame| frame.duration_ms);
+        (durations.clone().min().unwrap_or(0), durations.max().unwrap_or(0))
+    }
+
+    /// Returns the given frames as a horizontal strip, in the layout used for animated textures.
+    pub fn frame_strip(&self, from: u16, to: u16) -> RgbaImage {
+        let frame_count = (to - from + 1) as u32;
+        let mut strip = RgbaImage::new(self.width * frame_count, self.height);
+        for (i, frame) in self.frames[from as usize..=to as usize].iter().enumerate() {
+            image::imageops::replace(&mut strip, &frame.image, (i as u32 * self.width) as i64, 0);
+        }
+        strip
+    }
+
+    /// Returns the region of a slice cropped from the frame of its first key.
+    pub fn slice_image(&self, slice: &AsepriteSlice) -> RgbaImage {
+        let frame = &self.frames[(slice.frame as usize).min(self.frames.len() - 1)];
+        image::imageops::crop_imm(
+            &frame.image,
+            slice.x.max(0) as u32,
+            slice.y.max(0) as u32,
+            slice.width,
+            slice.height,
+        )
+        .to_image()
+    }
+
+    fn find_cel(cels: &[Vec<(usize, Cel)>], frame_index: usize, layer_index: usize) -> Option<&Cel> {
+        let (_, cel) = cels.get(frame_index)?.iter().find(|(layer, _)| *layer == layer_index)?;
+        match cel.linked_frame {
+            Some(linked_frame) if linked_frame < frame_index => Self::find_cel(cels, linked_frame, layer_index),
+            Some(_) => None,
+            None => Some(cel),
+        }
+    }
+
+    fn blend_cel(image: &mut RgbaImage, cel: &Cel, opacity: u32) {
+        for y in 0..cel.height {
+            for x in 0..cel.width {
+                let dst_x = cel.x + x as i32;
+                let dst_y = cel.y + y as i32;
+                if dst_x < 0 || dst_y < 0 || dst_x >= image.width() as i32 || dst_y >= image.height() as i32 {
+                    continue;
+                }
+
+                let i = ((y * cel.width + x) * 4) as usize;
+                let src = &cel.pixels[i..i + 4];
+                let src_a = src[3] as u32 * opacity / 255;
+                if src_a == 0 {
+                    continue;
+                }
+
+                let dst = image.get_pixel_mut(dst_x as u32, dst_y as u32);
+                let dst_a = dst[3] as u32;
+                let out_a = src_a + dst_a * (255 - src_a) / 255;
+                for c in 0..3 {
+                    dst[c] = ((src[c] as u32 * src_a + dst[c] as u32 * dst_a * (255 - src_a) / 255) / out_a) as u8;
+                }
+                dst[3] = out_a as u8;
+            }
+        }
+    }
+
+    fn parse_palette(chunk: &mut Reader, palette: &mut Vec<[u8; 4]>) -> Result<(), String> {
+        let size = chunk.u32()? as usize;
+        let first = chunk.u32()? as usize;
+        let last = chunk.u32()? as usize;
+        chunk.skip(8)?;
+
+        palette.resize(size.max(palette.len()), [0, 0, 0, 0]);
+        for i in first..=last {
+            let flags = chunk.u16()?;
+            let color = [chunk.u8()?, chunk.u8()?, chunk.u8()?, chunk.u8()?];
+            if flags & 1 != 0 {
+                chunk.string()?;
+            }
+            if let Some(entry) = palette.get_mut(i) {
+                *entry = color;
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_old_palette(chunk: &mut Reader, palette: &mut Vec<[u8; 4]>) -> Result<(), String> {
+        let packet_count = chunk.u16()?;
+        let mut index = 0;
+        for _ in 0..packet_count {
+            index += chunk.u8()? as usize;
+            let count = match chunk.u8()? {
+                0 => 256,
+                count => count as usize,
+            };
+            palette.resize((index + count).max(palette.len()), [0, 0, 0, 0]);
+            for _ in 0..count {
+                palette[index] = [chunk.u8()?, chunk.u8()?, chunk.u8()?, 255];
+                index += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_tags(chunk: &mut Reader) -> Result<Vec<AsepriteTag>, String> {
+        let count = chunk.u16()?;
+        chunk.skip(8)?;
+        (0..count)
+            .map(|_| {
+                let from = chunk.u16()?;
+                let to = chunk.u16()?;
+                chunk.skip(1 + 2 + 6 + 3 + 1)?; // Direction, repeat, reserved, color
+                let name = chunk.string()?;
+                Ok(AsepriteTag { name, from, to })
+            })
+            .collect()
+    }
+
+    fn parse_slice(chunk: &mut Reader) -> Result<AsepriteSlice, String> {
+        let key_count = chunk.u32()?;
+        chunk.skip(8)?; // Flags and reserved
+        let name = chunk.string()?;
+        if key_count == 0 {
+            return Err(format!("Aseprite slice {} has no keys", name));
+        }
+        Ok(AsepriteSlice {
+            name,
+            frame: chunk.u32()?,
+            x: chunk.i32()?,
+            y: chunk.i32()?,
+            width: chunk.u32()?,
+            height: chunk.u32()?,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Layer {
+    visible: bool,
+    is_group: bool,
+    child_level: u16,
+    opacity: u8,
+}
+
+impl Layer {
+    fn parse(chunk: &mut Reader, prev_layers: &[Layer], opacity_valid: bool) -> Result<Self, String> {
+        let flags = chunk.u16()?;
+        let layer_type = chunk.u16()?;
+        let child_level = chunk.u16()?;
+        chunk.skip(2 + 2 + 2)?; // Default width, default height, blend mode
+        let opacity = chunk.u8()?;
+
+        // Parent group is the closest previous layer on a lower level. Its visibility already includes its own parents.
+        let parent_visible = prev_layers
+            .iter()
+            .rev()
+            .find(|prev| prev.child_level < child_level)
+            .map(|parent| parent.visible)
+            .unwrap_or(true);
+
+        Ok(Self {
+            visible: flags & 1 != 0 && parent_visible,
+            is_group: layer_type == 1,
+            child_level,
+            opacity: if opacity_valid { opacity } else { 255 },
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Cel {
+    x: i32,
+    y: i32,
+    opacity: u8,
+    width: u32,
+    height: u32,
+    /// RGBA pixels
+    pixels: Vec<u8>,
+    linked_frame: Option<usize>,
+}
+
+impl Cel {
+    fn parse(
+        chunk: &mut Reader,
+        color_depth: u16,
+        palette: &[[u8; 4]],
+        transparent_index: u8,
+    ) -> Result<(usize, Self), String> {
+        let layer_index = chunk.u16()? as usize;
+        let x = chunk.i16()? as i32;
+        let y = chunk.i16()? as i32;
+        let opacity = chunk.u8()?;
+        let cel_type = chunk.u16()?;
+        chunk.skip(2 + 5)?; // Z-index and reserved
+
+        let mut cel = Self {
+            x,
+            y,
+            opacity,
+            width: 0,
+            height: 0,
+            pixels: Vec::new(),
+            linked_frame: None,
+        };
+
+        match cel_type {
+            0 | 2 => {
+                cel.width = chunk.u16()? as u32;
+                cel.height = chunk.u16()? as u32;
+                let data = chunk.rest();
+                let raw = if cel_type == 2 {
+                    miniz_oxide::inflate::decompress_to_vec_zlib(data)
+                        .map_err(|err| format!("Failed to decompress aseprite cel: {:?}", err))?
+                } else {
+                    data.to_vec()
+                };
+                cel.pixels = Self::to_rgba(&raw, cel.width * cel.height, color_depth, palette, transparent_index)?;
+            }
+            1 => cel.linked_frame = Some(chunk.u16()? as usize),
+            // Tilemap cels are not supported
+            _ => {}
+        }
+
+        Ok((layer_index, cel))
+    }
+
+    fn to_rgba(
+        raw: &[u8],
+        pixel_count: u32,
+        color_depth: u16,
+        palette: &[[u8; 4]],
+        transparent_index: u8,
+    ) -> Result<Vec<u8>, String> {
+        let bytes_per_pixel = (color_depth / 8) as usize;
+        if raw.len() < pixel_count as usize * bytes_per_pixel {
+            return Err("Aseprite cel data is truncated".to_string());
+        }
+
+        Ok(match color_depth {
+            32 => raw[..pixel_count as usize * 4].to_vec(),
+            16 => raw
+                .chunks_exact(2)
+                .take(pixel_count as usize)
+                .flat_map(|p| [p[0], p[0], p[0], p[1]])
+                .collect(),
+            _ => raw
+                .iter()
+                .take(pixel_count as usize)
+                .flat_map(|index| {
+                    if *index == transparent_index {
+                        [0, 0, 0, 0]
+                    } else {
+                        palette.get(*index as usize).copied().unwrap_or([0, 0, 0, 0])
+                    }
+                })
+                .collect(),
+        })
+    }
+}
+
+// ---------------------------------------------------------- //
+// ---------------------- Byte reader ----------------------- //
+// ---------------------------------------------------------- //
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let slice = self.slice(self.pos, self.pos + count)?;
+        self.pos += count;
+        Ok(slice)
+    }
+
+    fn slice(&self, start: usize, end: usize) -> Result<&'a [u8], String> {
+        self.bytes
+            .get(start..end)
+            .ok_or_else(|| "Unexpected end of aseprite data".to_string())
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.bytes[self.pos.min(self.bytes.len())..];
+        self.pos = self.bytes.len();
+        rest
+    }
+
+    fn skip(&mut self, count: usize) -> Result<(), String> {
+        self.take(count).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn i16(&mut self) -> Result<i16, String> {
+        Ok(i16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.u16()? as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(chunk_type: u16, data: &[u8]) -> Vec<u8> {
+        let mut bytes = ((data.len() + 6) as u32).to_le_bytes().to_vec();
+        bytes.extend(chunk_type.to_le_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    fn frame(chunks: &[Vec<u8>]) -> Vec<u8> {
+        timed_frame(100, chunks)
+    }
+
+    fn timed_frame(duration_ms: u16, chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body: Vec<u8> = chunks.concat();
+        let mut bytes = ((body.len() + 16) as u32).to_le_bytes().to_vec();
+        bytes.extend(FRAME_MAGIC.to_le_bytes());
+        bytes.extend((chunks.len() as u16).to_le_bytes());
+        bytes.extend(duration_ms.to_le_bytes());
+        bytes.extend([0; 2]);
+        bytes.extend((chunks.len() as u32).to_le_bytes());
+        bytes.extend(body);
+        bytes
+    }
+
+    fn file(width: u16, height: u16, frames: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = vec![0; HEADER_SIZE];
+        bytes[4..6].copy_from_slice(&HEADER_MAGIC.to_le_bytes());
+        bytes[6..8].copy_from_slice(&(frames.len() as u16).to_le_bytes());
+        bytes[8..10].copy_from_slice(&width.to_le_bytes());
+        bytes[10..12].copy_from_slice(&height.to_le_bytes());
+        bytes[12..14].copy_from_slice(&32u16.to_le_bytes());
+        bytes[14..18].copy_from_slice(&1u32.to_le_bytes());
+        bytes.extend(frames.concat());
+        bytes
+    }
+
+    fn string(value: &str) -> Vec<u8> {
+        let mut bytes = (value.len() as u16).to_le_bytes().to_vec();
+        bytes.extend(value.as_bytes());
+        bytes
+    }
+
+    fn layer(visible: bool, opacity: u8) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend((visible as u16).to_le_bytes());
+        data.extend([0; 10]);
+        data.push(opacity);
+        data.extend([0; 3]);
+        data.extend(string("layer"));
+        chunk(CHUNK_LAYER, &data)
+    }
+
+    fn cel(layer: u16, x: i16, y: i16, cel_type: u16, payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend(layer.to_le_bytes());
+        data.extend(x.to_le_bytes());
+        data.extend(y.to_le_bytes());
+        data.push(255);
+        data.extend(cel_type.to_le_bytes());
+        data.extend([0; 7]);
+        data.extend(payload);
+        chunk(CHUNK_CEL, &data)
+    }
+
+    fn image_payload(width: u16, height: u16, pixels: &[u8]) -> Vec<u8> {
+        let mut data = width.to_le_bytes().to_vec();
+        data.extend(height.to_le_bytes());
+        data.extend(pixels);
+        data
+    }
+
+    #[test]
+    fn parses_frames_with_raw_compressed_and_linked_cels() {
+        let red = [255, 0, 0, 255];
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&[red, red].concat(), 6);
+
+        let bytes = file(
+            2,
+            2,
+            &[
+                frame(&[layer(true, 255), cel(0, 1, 1, 0, &image_payload(1, 1, &red))]),
+                frame(&[cel(0, 0, 0, 2, &[image_payload(2, 1, &[]), compressed].concat())]),
+                frame(&[cel(0, 0, 0, 1, &1u16.to_le_bytes())]),
+            ],
+        );
+
+        let file = AsepriteFile::parse(&bytes).unwrap();
+        assert_eq!((file.width, file.height, file.frames.len()), (2, 2, 3));
+        assert_eq!(file.frames[0].image.get_pixel(1, 1).0, red);
+        assert_eq!(file.frames[0].image.get_pixel(0, 0).0, [0, 0, 0, 0]);
+        assert_eq!(file.frames[1].image.get_pixel(1, 0).0, red);
+        assert_eq!(file.frames[2].image, file.frames[1].image);
+        assert_eq!(file.frames[0].duration_ms, 100);
+    }
+
+    #[test]
+    fn hidden_layers_are_skipped_and_opacity_is_applied() {
+        let white = [255, 255, 255, 255];
+        let bytes = file(
+            1,
+            1,
+            &[frame(&[
+                layer(false, 255),
+                layer(true, 0),
+                layer(true, 255),
+                cel(0, 0, 0, 0, &image_payload(1, 1, &white)),
+                cel(1, 0, 0, 0, &image_payload(1, 1, &white)),
+            ])],
+        );
+
+        let file = AsepriteFile::parse(&bytes).unwrap();
+        assert_eq!(file.frames[0].image.get_pixel(0, 0).0, [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn parses_tags_and_slices() {
+        let mut tags = 2u16.to_le_bytes().to_vec();
+        tags.extend([0; 8]);
+        for (name, from, to) in [("walk", 0u16, 1u16), ("broken", 1, 5)] {
+            tags.extend(from.to_le_bytes());
+            tags.extend(to.to_le_bytes());
+            tags.extend([0; 13]);
+            tags.extend(string(name));
+        }
+
+        let mut slice = 1u32.to_le_bytes().to_vec();
+        slice.extend([0; 8]);
+        slice.extend(string("icon"));
+        slice.extend(0u32.to_le_bytes());
+        slice.extend(1i32.to_le_bytes());
+        slice.extend(0i32.to_le_bytes());
+        slice.extend(2u32.to_le_bytes());
+        slice.extend(3u32.to_le_bytes());
+
+        let bytes = file(
+            4,
+            4,
+            &[frame(&[chunk(CHUNK_TAGS, &tags), chunk(CHUNK_SLICE, &slice)]), frame(&[])],
+        );
+
+        let file = AsepriteFile::parse(&bytes).unwrap();
+        assert_eq!(
+            file.tags,
+            vec![AsepriteTag {
+                name: "walk".to_string(),
+                from: 0,
+                to: 1
+            }]
+        );
+        assert_eq!(file.frame_strip(0, 1).dimensions(), (8, 4));
+        assert_eq!(file.slices[0].name, "icon");
+        assert_eq!(file.slice_image(&file.slices[0]).dimensions(), (2, 3));
+    }
+
+    #[test]
+    fn files_without_frames_are_rejected() {
+        assert!(AsepriteFile::parse(&file(4, 4, &[])).is_err());
+    }
+
+    #[test]
+    fn frame_durations_are_kept() {
+        let bytes = file(
+            1,
+            1,
+            &[timed_frame(100, &[]), timed_frame(100, &[]), timed_frame(250, &[])],
+        );
+        let file = AsepriteFile::parse(&bytes).unwrap();
+        assert_eq!(file.duration_range_ms(0, 1), (100, 100));
+        assert_eq!(file.duration_range_ms(0, 2), (100, 250));
+    }
+}
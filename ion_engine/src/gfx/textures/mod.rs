@@ -2,7 +2,9 @@ use std::ops::Range;
 
 use super::gfx_config::Resolution;
 
+mod aseprite;
 pub mod texture_assets;
+pub mod texture_atlas;
 pub(crate) mod texture_loader;
 
 pub(crate) struct Texture {
@@ -50,6 +52,73 @@ impl Texture {
         }
     }
 
+    /// Creates an empty multisampled render target. Multisampled textures can't be sampled with filtering,
+    /// so they are resolved to a regular texture, or read with `textureLoad`.
+    pub fn new_multisampled(
+        device: &wgpu::Device,
+        resolution: Resolution,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        label: &str,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: resolution.width,
+                height: resolution.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            view_formats: &[format],
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self {
+            texture,
+            texture_view: view,
+            texture_format: format,
+        }
+    }
+
+    /// Creates an empty texture that can be written to later with `queue.write_texture`.
+    pub fn new_writable(
+        device: &wgpu::Device,
+        dimensions: (u32, u32),
+        format: wgpu::TextureFormat,
+        mipmap_count: u32,
+        label: &str,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: dimensions.0,
+                height: dimensions.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: mipmap_count,
+            sample_count: 1,
+            view_formats: &[format],
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::RENDER_ATTACHMENT,
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self {
+            texture,
+            texture_view: view,
+            texture_format: format,
+        }
+    }
+
     pub fn new_from_raw_data(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
@@ -119,7 +188,7 @@ pub(crate) struct TextureId {
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 #[repr(u8)]
-pub(crate) enum TextureLayout {
+pub enum TextureLayout {
     Square = 0,
     Isometric = 1,
     IsometricHex = 2,
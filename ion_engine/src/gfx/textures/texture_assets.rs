@@ -1,9 +1,17 @@
-use std::{num::NonZeroU32, ops::Range};
+use std::{collections::BTreeSet, num::NonZeroU32, ops::Range, path::PathBuf};
+
+use ion_common::Map;
 
 use crate::{
     WASM_COMPATIBLE_RENDERING,
+    core::FrameId,
+    files::{
+        Files,
+        asset_error::{AssetDiagnostics, AssetError},
+        mods::MOD_TEXTURE_DIR,
+    },
     gfx::{
-        GfxBundle, GfxRef, Sprite, SpriteTypeId,
+        AnimMode, Color, GfxBundle, GfxCrossfade, GfxRef, Sprite, SpriteTypeId,
         renderer::{
             gpu_data_types::{InstanceLight, InstanceSprite},
             render_camera::RenderCamera,
@@ -11,12 +19,26 @@ use crate::{
     },
 };
 
-use super::{Texture, TextureLayout, texture_loader::TextureLoader};
+use super::{
+    Texture, TextureId, TextureLayout,
+    texture_atlas::{AtlasInsertError, AtlasPacker, RuntimeTexture},
+    texture_loader::{MIPMAP_COUNT, TextureLoader},
+};
+
+/// Number of colors in a palette. Palette index is stored in a single color channel.
+const PALETTE_SIZE: u32 = 256;
 
 /// A collection of texture assets.
 /// Any sprites that need to be rendered need to be included here and then loaded to the GPU.
 /// This is done by first calling the `include_sprite_bundle` function for all the assets,
 /// and then submitting this struct to the `load_texture_assets` function of the `Renderer`.
+///
+/// If textures need to be added after loading, a dynamic atlas can be reserved with `reserve_dynamic_atlas`.
+///
+/// Palettes for indexed color sprites are included with `include_palette`. All palettes are stored as rows
+/// of a single small texture, so variants of a sprite don't need their own textures.
+///
+/// Textures of enabled mods are loaded in place of the base textures with `include_mods`.
 pub struct TextureAssets {
     bind_group_layout: Option<wgpu::BindGroupLayout>,
     bind_group: Option<wgpu::BindGroup>,
@@ -24,7 +46,15 @@ pub struct TextureAssets {
 
     gfx_bundles: Vec<GfxBundle>,
     texture_sheets: Vec<Texture>,
+    texture_ids: Map<String, TextureId>,
+
+    palettes: Vec<Vec<Color>>,
+    mod_texture_dirs: Vec<PathBuf>,
 
+    dynamic_atlas_reserved: bool,
+    dynamic_atlas: Option<DynamicAtlas>,
+
+    diagnostics: Option<AssetDiagnostics>,
     assets_ready: bool,
 }
 
@@ -37,17 +67,241 @@ impl TextureAssets {
 
             gfx_bundles: Vec::new(),
             texture_sheets: Vec::new(),
+            texture_ids: Map::default(),
+
+            palettes: Vec::new(),
+            mod_texture_dirs: Vec::new(),
+
+            dynamic_atlas_reserved: false,
+            dynamic_atlas: None,
+
+            diagnostics: None,
             assets_ready: false,
         }
     }
 
     /// Add a sprite bundle to the texture assets.
     /// Returns the reference to the sprite bundle.
-    pub fn include_gfx_bundle(&mut self, sprite_bundle: GfxBundle) -> u32 {
+    /// If the assets are already loaded, texture IDs are filled in immediately,
+    /// so all textures of the bundle must already be loaded or inserted to the dynamic atlas.
+    pub fn include_gfx_bundle(&mut self, mut sprite_bundle: GfxBundle) -> u32 {
+        if self.assets_ready {
+            let missing = Self::fill_texture_ids(&mut sprite_bundle, &self.texture_ids);
+            self.report_missing_textures(missing);
+        }
         self.gfx_bundles.push(sprite_bundle);
         self.gfx_bundles.len() as u32 - 1
     }
 
+    /// Add a palette for indexed color sprites. Palette index of a sprite pixel selects a color from the palette,
+    /// and indices beyond the palette are black. Returns the palette index for `GfxRef::with_palette`.
+    pub fn include_palette(&mut self, colors: &[Color]) -> u32 {
+        assert!(
+            !self.assets_ready,
+            "Palettes must be included before loading texture assets"
+        );
+        assert!(
+            colors.len() <= PALETTE_SIZE as usize,
+            "Palette can have at most {} colors",
+            PALETTE_SIZE
+        );
+        self.palettes.push(colors.to_vec());
+        self.palettes.len() as u32 - 1
+    }
+
+    /// Loads the textures of the enabled mods, see [`Files::list_mods`]. Mod textures replace the base textures of
+    /// the same path, and can add textures that gfx bundles refer to.
+    pub fn include_mods(&mut self, files: &Files) {
+        assert!(
+            !self.assets_ready,
+            "Mods must be included before loading texture assets"
+        );
+        self.mod_texture_dirs = files
+            .enabled_mod_dirs()
+            .into_iter()
+            .map(|mod_dir| mod_dir.join(MOD_TEXTURE_DIR))
+            .collect();
+    }
+
+    /// Reserves an extra texture sheet pair for inserting textures at runtime.
+    /// The sheets are as large as the regular texture sheets, so this should only be used when needed.
+    pub fn reserve_dynamic_atlas(&mut self) {
+        assert!(
+            !self.assets_ready,
+            "Dynamic atlas must be reserved before loading texture assets"
+        );
+        self.dynamic_atlas_reserved = true;
+    }
+
+    /// Inserts a texture to the dynamic atlas.
+    /// Mipmaps of the atlas are regenerated on the next render frame.
+    pub(crate) fn insert_runtime_texture(
+        &mut self,
+        queue: &wgpu::Queue,
+        texture: RuntimeTexture,
+    ) -> Result<(), AtlasInsertError> {
+        let Some(atlas) = self.dynamic_atlas.as_mut() else {
+            return Err(AtlasInsertError::AtlasNotReserved);
+        };
+
+        if self.texture_ids.contains_key(&texture.name) {
+            return Err(AtlasInsertError::AlreadyExists(texture.name));
+        }
+
+        let (width, height) = texture.dimensions;
+        let expected_len = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|len| len.checked_mul(4));
+        if expected_len.is_none_or(|expected_len| {
+            texture.color.len() != expected_len
+                || texture
+                    .normal_height
+                    .as_ref()
+                    .is_some_and(|normal_height| normal_height.len() != expected_len)
+        }) {
+            return Err(AtlasInsertError::InvalidDimensions(texture.name));
+        }
+
+        let Some((x, y)) = atlas.packer.allocate(width, height) else {
+            if let Some(diagnostics) = self.diagnostics.as_ref() {
+                diagnostics.report(AssetError::AtlasOverflow {
+                    name: texture.name.clone(),
+                    dimensions: texture.dimensions,
+                });
+            }
+            return Err(AtlasInsertError::AtlasFull(texture.name));
+        };
+
+        let sheet_index = atlas.sheet_index;
+        let sheet_c = &self.texture_sheets[sheet_index as usize];
+        Self::write_region(queue, sheet_c, (x, y), texture.dimensions, &texture.color);
+        if let Some(normal_height) = texture.normal_height.as_ref() {
+            let sheet_nh = &self.texture_sheets[sheet_index as usize + 1];
+            Self::write_region(queue, sheet_nh, (x, y), texture.dimensions, normal_height);
+        }
+
+        let size = atlas.size as f32;
+        let texture_id = TextureId {
+            tex_coords: [x as f32 / size, y as f32 / size],
+            tex_coords_sizes: [width as f32 / size, height as f32 / size],
+            tex_sheet_indices: [
+                sheet_index,
+                if texture.normal_height.is_some() {
+                    sheet_index + 1
+                } else {
+                    u32::MAX
+                },
+            ],
+            layout: texture.layout,
+            frame_count: 1,
+        };
+
+        atlas.mipmaps_dirty = true;
+        self.texture_ids.insert(texture.name, texture_id);
+        Ok(())
+    }
+
+    /// Regenerates the mipmaps of the dynamic atlas if textures were inserted since the last call.
+    pub(crate) fn flush_dynamic_atlas(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if let Some(atlas) = self.dynamic_atlas.as_mut()
+            && atlas.mipmaps_dirty
+        {
+            let sheets = &self.texture_sheets[atlas.sheet_index as usize..atlas.sheet_index as usize + 2];
+            for (i, sheet) in sheets.iter().enumerate() {
+                TextureLoader::generate_mipmaps(device, queue, &sheet.texture, MIPMAP_COUNT, i == 0);
+            }
+            atlas.mipmaps_dirty = false;
+        }
+    }
+
+    fn write_region(queue: &wgpu::Queue, sheet: &Texture, origin: (u32, u32), dimensions: (u32, u32), data: &[u8]) {
+        queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                aspect: wgpu::TextureAspect::All,
+                texture: &sheet.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: origin.0,
+                    y: origin.1,
+                    z: 0,
+                },
+            },
+            data,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * dimensions.0),
+                rows_per_image: Some(dimensions.1),
+            },
+            wgpu::Extent3d {
+                width: dimensions.0,
+                height: dimensions.1,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    pub(crate) fn set_diagnostics(&mut self, diagnostics: AssetDiagnostics) {
+        self.diagnostics = Some(diagnostics);
+    }
+
+    fn report_missing_textures(&self, missing: BTreeSet<String>) {
+        if let Some(diagnostics) = self.diagnostics.as_ref() {
+            for name in missing {
+                diagnostics.report(AssetError::MissingTexture { name });
+            }
+        }
+    }
+
+    /// Fills texture IDs of all sprites in the bundle. Returns names of textures that were not found.
+    fn fill_texture_ids(bundle: &mut GfxBundle, texture_ids: &Map<String, TextureId>) -> BTreeSet<String> {
+        let mut missing = BTreeSet::new();
+        let mut fill_ids = |sprite: &mut Sprite| {
+            if let Some(texture_id) = texture_ids.get(&sprite.texture) {
+                sprite.texture_id = Some(*texture_id);
+            } else {
+                sprite.type_id = SpriteTypeId::Missing;
+                missing.insert(sprite.texture.clone());
+            }
+
+            if let Some(mask_name) = &sprite.texture_mask {
+                if let Some(texture_id) = texture_ids.get(mask_name) {
+                    sprite.texture_mask_id = Some(*texture_id);
+                } else {
+                    sprite.type_id = SpriteTypeId::Missing;
+                    missing.insert(mask_name.clone());
+                }
+            }
+        };
+
+        for sprite in &mut bundle.sprites {
+            fill_ids(sprite);
+        }
+
+        // Register texture IDs for shadows
+        for shadow in &mut bundle.shadows {
+            fill_ids(shadow);
+        }
+
+        // Register texture IDs for lights
+        for light in &mut bundle.lights {
+            fill_ids(&mut light.sprite);
+        }
+
+        // Register texture IDs for distortions
+        for distortion in &mut bundle.distortions {
+            fill_ids(distortion);
+        }
+
+        // Register texture IDs for skeleton slots
+        if let Some(skeleton) = &mut bundle.skeleton {
+            for slot in &mut skeleton.slots {
+                fill_ids(&mut slot.sprite);
+            }
+        }
+
+        missing
+    }
+
     pub(crate) fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
         self.bind_group_layout
             .as_ref()
@@ -70,6 +324,73 @@ impl TextureAssets {
         self.assets_ready
     }
 
+    /// Extracts the instances of a single gfx ref.
+    /// During a crossfade both bundles are extracted with complementary fades.
+    #[allow(clippy::too_many_arguments)]
+    fn extract_gfx_ref(
+        &self,
+        camera: Option<&RenderCamera>,
+        gfx_ref: &GfxRef,
+        frame: FrameId,
+        frame_offset: f32,
+        sprites: &mut Vec<(InstanceSprite, TextureLayout, u8)>,
+        shadows: &mut Vec<(InstanceSprite, TextureLayout)>,
+        lights: &mut Vec<(InstanceLight, TextureLayout)>,
+        distortions: &mut Vec<(InstanceSprite, TextureLayout)>,
+    ) {
+        let sprite_bundle = self.gfx_bundles.get(gfx_ref.id as usize).unwrap();
+
+        match gfx_ref.crossfade {
+            Some(crossfade) if crossfade.progress(frame) < 1.0 => {
+                let progress = crossfade.progress(frame);
+                let from_ref = GfxRef {
+                    id: crossfade.from_id,
+                    anim_start: crossfade.from_anim_start,
+                    anim_mode: AnimMode::Loop,
+                    crossfade: None,
+                    ..gfx_ref.clone()
+                };
+                let from_bundle = self.gfx_bundles.get(crossfade.from_id as usize).unwrap();
+
+                let fade_in = GfxCrossfade::fade_bits(progress, false);
+                let fade_out = GfxCrossfade::fade_bits(progress, true);
+                sprite_bundle.extract_for_render(
+                    camera,
+                    gfx_ref,
+                    gfx_ref.anim_time(frame, frame_offset),
+                    fade_in,
+                    progress >= 0.5,
+                    sprites,
+                    shadows,
+                    lights,
+                    distortions,
+                );
+                from_bundle.extract_for_render(
+                    camera,
+                    &from_ref,
+                    from_ref.anim_time(frame, frame_offset),
+                    fade_out,
+                    progress < 0.5,
+                    sprites,
+                    shadows,
+                    lights,
+                    distortions,
+                );
+            }
+            _ => sprite_bundle.extract_for_render(
+                camera,
+                gfx_ref,
+                gfx_ref.anim_time(frame, frame_offset),
+                0,
+                true,
+                sprites,
+                shadows,
+                lights,
+                distortions,
+            ),
+        }
+    }
+
     /// Convert a list of sprite references to a list of renderable instances.
     /// Instances are sorted and grouped by layer and then by texture layout.
     /// Returns a vec of instances for writing to GPU and a vec of ranges for `render_pass.draw_indexed` call.
@@ -77,28 +398,35 @@ impl TextureAssets {
         &self,
         gfx_refs: &[GfxRef],
         camera: &RenderCamera,
+        frame: FrameId,
+        frame_offset: f32,
     ) -> (
         (Vec<InstanceSprite>, Vec<DrawCall>),
         (Vec<InstanceSprite>, Vec<DrawCall>),
         (Vec<InstanceLight>, Vec<DrawCall>),
+        (Vec<InstanceSprite>, Vec<DrawCall>),
     ) {
         let mut instances_color = Vec::new();
         let mut instances_shadow = Vec::new();
         let mut instances_light = Vec::new();
+        let mut instances_distortion = Vec::new();
         let mut draw_calls_color = Vec::new();
         let mut draw_calls_shadow = Vec::new();
         let mut draw_calls_light = Vec::new();
+        let mut draw_calls_distortion = Vec::new();
 
         // TODO: If any of the sprites contain transparency, this needs to be handled differently
 
         for gfx_ref in gfx_refs {
-            let sprite_bundle = self.gfx_bundles.get(gfx_ref.id as usize).unwrap();
-            sprite_bundle.extract_for_render(
+            self.extract_gfx_ref(
                 Some(camera),
                 gfx_ref,
+                frame,
+                frame_offset,
                 &mut instances_color,
                 &mut instances_shadow,
                 &mut instances_light,
+                &mut instances_distortion,
             );
         }
 
@@ -108,6 +436,7 @@ impl TextureAssets {
         // No layers here, just sort by layout
         instances_shadow.sort_by_key(|(_, layout)| *layout);
         instances_light.sort_by_key(|(_, layout)| *layout);
+        instances_distortion.sort_by_key(|(_, layout)| *layout);
 
         if !instances_color.is_empty() {
             let mut current_layer = instances_color[0].2;
@@ -182,6 +511,30 @@ impl TextureAssets {
             });
         }
 
+        // Build draw calls for distortions (no layers, only layout)
+        if !instances_distortion.is_empty() {
+            let mut current_layout = instances_distortion[0].1;
+            let mut start_index = 0;
+
+            for (i, (_, layout)) in instances_distortion.iter().enumerate().skip(1) {
+                if *layout != current_layout {
+                    draw_calls_distortion.push(DrawCall {
+                        layer: 0, // Distortions don't use layers
+                        layout: current_layout,
+                        draw_range: start_index..i as u32,
+                    });
+                    current_layout = *layout;
+                    start_index = i as u32;
+                }
+            }
+
+            draw_calls_distortion.push(DrawCall {
+                layer: 0, // Distortions don't use layers
+                layout: current_layout,
+                draw_range: start_index..instances_distortion.len() as u32,
+            });
+        }
+
         (
             (
                 instances_color.into_iter().map(|(instance, _, _)| instance).collect(),
@@ -195,6 +548,10 @@ impl TextureAssets {
                 instances_light.into_iter().map(|(instance, _)| instance).collect(),
                 draw_calls_light,
             ),
+            (
+                instances_distortion.into_iter().map(|(instance, _)| instance).collect(),
+                draw_calls_distortion,
+            ),
         )
     }
 
@@ -204,6 +561,8 @@ impl TextureAssets {
     pub(crate) fn refs_to_draw_calls_wasm(
         &self,
         gfx_refs: &[GfxRef],
+        frame: FrameId,
+        frame_offset: f32,
     ) -> (
         (Vec<InstanceSprite>, Vec<DrawCallWasm>),
         (Vec<InstanceSprite>, Vec<DrawCallWasm>),
@@ -218,13 +577,15 @@ impl TextureAssets {
 
         // Collect instances
         for gfx_ref in gfx_refs {
-            let sprite_bundle = self.gfx_bundles.get(gfx_ref.id as usize).unwrap();
-            sprite_bundle.extract_for_render(
+            self.extract_gfx_ref(
                 None,
                 gfx_ref,
+                frame,
+                frame_offset,
                 &mut instances_color,
                 &mut instances_shadow,
                 &mut instances_light,
+                &mut Vec::new(), // Distortions are not supported on wasm
             );
         }
 
@@ -354,41 +715,56 @@ impl TextureAssets {
         )
     }
 
-    pub(crate) fn take_finished_loader(&mut self, device: &wgpu::Device, texture_loader: TextureLoader) {
-        let (texture_sheets, texture_ids) = texture_loader.finish();
-        self.texture_sheets.extend(texture_sheets);
-
-        let fill_ids = |sprite: &mut Sprite| {
-            if let Some(texture_id) = texture_ids.get(&sprite.texture) {
-                sprite.texture_id = Some(*texture_id);
-            } else {
-                sprite.type_id = SpriteTypeId::Missing;
+    /// Pixel data of the palette texture. Each palette is one row, padded with black.
+    fn palette_pixels(&self) -> Vec<u8> {
+        let rows = self.palettes.len().max(1);
+        let mut pixels = vec![0; rows * PALETTE_SIZE as usize * 4];
+        for (row, palette) in self.palettes.iter().enumerate() {
+            for (i, color) in palette.iter().enumerate() {
+                let offset = (row * PALETTE_SIZE as usize + i) * 4;
+                pixels[offset..offset + 4].copy_from_slice(&[color.r, color.g, color.b, 255]);
             }
+        }
+        pixels
+    }
 
-            if let Some(mask_name) = &sprite.texture_mask {
-                if let Some(texture_id) = texture_ids.get(mask_name) {
-                    sprite.texture_mask_id = Some(*texture_id);
-                } else {
-                    sprite.type_id = SpriteTypeId::Missing;
-                }
-            }
-        };
+    pub(crate) fn take_finished_loader(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture_loader: TextureLoader,
+    ) {
+        let texture_sheet_max_size = texture_loader.texture_sheet_max_size();
+        let (texture_sheets, texture_ids) = texture_loader.finish();
+        self.texture_sheets.extend(texture_sheets);
+        self.texture_ids = texture_ids;
 
         // Register texture IDs for all sprites in all bundles
+        let mut missing = BTreeSet::new();
         for bundle in &mut self.gfx_bundles {
-            for sprite in &mut bundle.sprites {
-                fill_ids(sprite);
-            }
-
-            // Register texture IDs for shadows
-            for shadow in &mut bundle.shadows {
-                fill_ids(shadow);
+            missing.extend(Self::fill_texture_ids(bundle, &self.texture_ids));
+        }
+        self.report_missing_textures(missing);
+
+        // Dynamic atlas sheets are appended after the loaded sheets, so they are part of the same bind group
+        if self.dynamic_atlas_reserved {
+            let sheet_index = self.texture_sheets.len() as u32;
+            for label in ["texture_sheet_dynamic_c", "texture_sheet_dynamic_nh"] {
+                self.texture_sheets.push(Texture::new_writable(
+                    device,
+                    (texture_sheet_max_size, texture_sheet_max_size),
+                    wgpu::TextureFormat::Rgba8UnormSrgb,
+                    MIPMAP_COUNT,
+                    label,
+                ));
             }
 
-            // Register texture IDs for lights
-            for light in &mut bundle.lights {
-                fill_ids(&mut light.sprite);
-            }
+            self.dynamic_atlas = Some(DynamicAtlas {
+                packer: AtlasPacker::new(texture_sheet_max_size),
+                sheet_index,
+                size: texture_sheet_max_size,
+                mipmaps_dirty: false,
+            });
         }
 
         let sampler_descriptor = wgpu::SamplerDescriptor {
@@ -403,6 +779,26 @@ impl TextureAssets {
 
         let sampler = device.create_sampler(&sampler_descriptor);
 
+        let palette_texture = Texture::new_from_raw_data(
+            device,
+            queue,
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            &self.palette_pixels(),
+            (PALETTE_SIZE, self.palettes.len().max(1) as u32),
+            1,
+            "texture_palettes",
+        );
+        let palette_layout_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+
         if WASM_COMPATIBLE_RENDERING {
             let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 entries: &[
@@ -432,6 +828,7 @@ impl TextureAssets {
                         ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                         count: None,
                     },
+                    palette_layout_entry(3),
                 ],
                 label: Some("texture_assets_bind_group_layout_wasm"),
             });
@@ -455,6 +852,10 @@ impl TextureAssets {
                                 binding: 2,
                                 resource: wgpu::BindingResource::Sampler(&sampler),
                             },
+                            wgpu::BindGroupEntry {
+                                binding: 3,
+                                resource: wgpu::BindingResource::TextureView(&palette_texture.texture_view),
+                            },
                         ],
                         label: Some(&format!("texture_assets_bind_group_wasm_{}", i / 2)),
                     })
@@ -482,6 +883,7 @@ impl TextureAssets {
                         ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                         count: None,
                     },
+                    palette_layout_entry(2),
                 ],
                 label: Some("texture_assets_bind_group_layout"),
             });
@@ -503,6 +905,10 @@ impl TextureAssets {
                         binding: 1,
                         resource: wgpu::BindingResource::Sampler(&sampler),
                     },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(&palette_texture.texture_view),
+                    },
                 ],
                 label: Some("texture_assets_bind_group"),
             });
@@ -514,6 +920,10 @@ impl TextureAssets {
         self.assets_ready = true;
     }
 
+    pub(crate) fn mod_texture_dirs(&self) -> Vec<PathBuf> {
+        self.mod_texture_dirs.clone()
+    }
+
     pub(crate) fn required_textures(&self) -> Vec<String> {
         let mut textures: Vec<_> = self
             .gfx_bundles
@@ -533,6 +943,7 @@ impl TextureAssets {
                             .iter()
                             .filter_map(|light| light.sprite.texture_mask.clone()),
                     )
+                    .chain(bundle.distortions.iter().map(|distortion| distortion.texture.clone()))
             })
             .collect();
 
@@ -555,6 +966,14 @@ impl TextureAssets {
     }
 }
 
+/// Runtime state of the reserved dynamic atlas sheet pair.
+struct DynamicAtlas {
+    packer: AtlasPacker,
+    sheet_index: u32,
+    size: u32,
+    mipmaps_dirty: bool,
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct DrawCall {
     pub layer: u8,
@@ -0,0 +1,110 @@
+use super::TextureLayout;
+
+// ---------------------------------------------------------- //
+// --------------------- Runtime textures ------------------- //
+// ---------------------------------------------------------- //
+
+/// A texture that is inserted to the dynamic atlas after texture assets have been loaded.
+/// Used for content that is not known when calling `load_texture_assets`, for example mod content or user avatars.
+///
+/// Pixel data is tightly packed RGBA8 in sRGB color space, same as the png assets.
+#[derive(Debug, Clone)]
+pub struct RuntimeTexture {
+    /// Name used to refer to the texture from sprites.
+    pub name: String,
+    pub layout: TextureLayout,
+    pub dimensions: (u32, u32),
+    pub color: Vec<u8>,
+    /// Normal map in RGB channels and height map in the alpha channel. Optional.
+    pub normal_height: Option<Vec<u8>>,
+}
+
+/// Error type for inserting runtime textures to the dynamic atlas.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AtlasInsertError {
+    /// Texture assets are not loaded, or the dynamic atlas was not reserved for them.
+    AtlasNotReserved,
+
+    /// A texture with the same name already exists.
+    AlreadyExists(String),
+
+    /// Pixel data does not match the given dimensions.
+    InvalidDimensions(String),
+
+    /// There is no space left in the dynamic atlas.
+    AtlasFull(String),
+}
+
+// ---------------------------------------------------------- //
+// ---------------------- Atlas packer ---------------------- //
+// ---------------------------------------------------------- //
+
+/// Simple shelf packer for the dynamic atlas.
+/// Regions are placed left to right on rows, with a one pixel gap like in the loaded texture sheets.
+/// Space is never reclaimed.
+#[derive(Debug)]
+pub(crate) struct AtlasPacker {
+    size: u32,
+    cur_x: u32,
+    cur_y: u32,
+    row_height: u32,
+}
+
+impl AtlasPacker {
+    pub fn new(size: u32) -> Self {
+        Self {
+            size,
+            cur_x: 0,
+            cur_y: 0,
+            row_height: 0,
+        }
+    }
+
+    /// Reserves a region of the given size and returns its top left corner in pixels.
+    /// Returns `None` if the region does not fit to the atlas anymore.
+    pub fn allocate(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        if width == 0 || height == 0 || width > self.size || height > self.size {
+            return None;
+        }
+
+        let (mut x, mut y) = (self.cur_x, self.cur_y);
+        let mut row_height = self.row_height;
+        if x + width > self.size {
+            x = 0;
+            y += row_height + 1;
+            row_height = 0;
+        }
+        if y + height > self.size {
+            return None;
+        }
+
+        self.cur_x = x + width + 1;
+        self.cur_y = y;
+        self.row_height = row_height.max(height);
+        Some((x, y))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packer_fills_rows_with_gaps() {
+        let mut packer = AtlasPacker::new(64);
+        assert_eq!(packer.allocate(20, 10), Some((0, 0)));
+        assert_eq!(packer.allocate(20, 16), Some((21, 0)));
+        assert_eq!(packer.allocate(20, 10), Some((42, 0)));
+        assert_eq!(packer.allocate(20, 10), Some((0, 17)));
+    }
+
+    #[test]
+    fn packer_rejects_regions_that_do_not_fit() {
+        let mut packer = AtlasPacker::new(32);
+        assert_eq!(packer.allocate(33, 1), None);
+        assert_eq!(packer.allocate(0, 1), None);
+        assert_eq!(packer.allocate(32, 20), Some((0, 0)));
+        assert_eq!(packer.allocate(32, 12), None);
+        assert_eq!(packer.allocate(32, 11), Some((0, 21)));
+    }
+}
@@ -1,23 +1,37 @@
-use std::{collections::VecDeque, ffi::OsStr, fs::File, path::PathBuf};
 use std::{
-    io::{self, Read},
-    sync::mpsc::{self, Receiver, Sender},
+    collections::VecDeque,
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+use std::{
+    io,
+    sync::{
+        Arc,
+        mpsc::{self, Receiver, Sender},
+    },
 };
 
-use image::{GenericImageView, RgbaImage};
+use image::{DynamicImage, GenericImageView, RgbaImage};
 
-use ion_common::{Map, log_info};
+use ion_common::{Map, Set, log_info};
 
 use crate::build_shader;
 use crate::core::Constants;
-use crate::files::file_helpers::{list_files, load_resource};
-use crate::gfx::renderer::gpu_data_types::SHADER_SCALE;
+use crate::files::{
+    asset_archive::{AssetArchive, AssetSource},
+    asset_error::{AssetDiagnostics, AssetError},
+    file_helpers::list_files,
+};
+use crate::gfx::renderer::gpu_data_types::SHADER_MIPMAP;
 use crate::util::concurrency::{JoinHandle, spawn_thread_with_handle};
 
-use super::{Texture, TextureId, TextureLayout};
+use super::{Texture, TextureId, TextureLayout, aseprite::AsepriteFile};
 
 const DEBUG_SAVE_TEXTURES: bool = false;
-const MIPMAP_COUNT: u32 = 10;
+pub(super) const MIPMAP_COUNT: u32 = 10;
+const TEXTURE_FILE_TYPES: [&str; 3] = ["png", "aseprite", "ase"];
+/// Enough bytes of a texture file to read its dimensions.
+const TEXTURE_HEADER_LEN: usize = 24;
 
 pub struct TextureLoader {
     texture_sheet_max_size: u32,
@@ -26,25 +40,36 @@ pub struct TextureLoader {
     loaded_textures: Vec<Texture>,
     loaded_texture_ids: Map<String, TextureId>,
 
-    details_thread: Option<JoinHandle<VecDeque<SingleTextureDetails>>>,
+    asset_source: Option<Arc<AssetSource>>,
+    details_thread: Option<JoinHandle<(AssetSource, VecDeque<SingleTextureDetails>)>>,
     loader_thread: Option<JoinHandle<(Vec<RgbaImage>, Map<String, TextureId>, VecDeque<SingleTextureDetails>)>>,
     // Progress tracking
     total_textures: usize,
     progress: f32,
     progress_sender: Sender<f32>,
     progress_receiver: Receiver<f32>,
+
+    diagnostics: AssetDiagnostics,
 }
 
 impl TextureLoader {
-    pub(crate) fn new(constants: &Constants, textures: Vec<String>, texture_sheet_max_size: u32) -> Self {
+    pub(crate) fn new(
+        constants: &Constants,
+        textures: Vec<String>,
+        mod_texture_dirs: Vec<PathBuf>,
+        texture_sheet_max_size: u32,
+        diagnostics: AssetDiagnostics,
+    ) -> Self {
         log_info!("Loading texture assets: {} textures", textures.len());
         log_info!("Using texture_sheet_max_size of: {}", texture_sheet_max_size);
 
         let constants = constants.clone();
-        let asset_path = constants.gfx.asset_path.clone();
         let total_textures = textures.len();
+        let details_diagnostics = diagnostics.clone();
         let details_thread = spawn_thread_with_handle(Some("gen_texture_details"), move || {
-            Self::gen_texture_details(&constants, asset_path, &textures)
+            let source = Self::open_asset_source(&constants, mod_texture_dirs, &details_diagnostics);
+            let details = Self::gen_texture_details(&constants, &source, &textures, &details_diagnostics);
+            (source, details)
         });
 
         let (progress_sender, progress_receiver) = mpsc::channel();
@@ -56,15 +81,22 @@ impl TextureLoader {
             loaded_textures: Vec::new(),
             loaded_texture_ids: Map::default(),
 
+            asset_source: None,
             details_thread: Some(details_thread),
             loader_thread: None,
             total_textures,
             progress: 0.0,
             progress_sender,
             progress_receiver,
+
+            diagnostics,
         }
     }
 
+    pub(crate) fn texture_sheet_max_size(&self) -> u32 {
+        self.texture_sheet_max_size
+    }
+
     pub(crate) fn finish(self) -> (Vec<Texture>, Map<String, TextureId>) {
         assert!(self.details_thread.is_none(), "Details thread must be finished");
         assert!(self.loader_thread.is_none(), "Loader thread must be finished");
@@ -87,8 +119,21 @@ impl TextureLoader {
 
         // Check if the details thread has finished
         if let Some(details_thread) = self.details_thread.as_mut() {
-            if let Some(details) = details_thread.try_join() {
+            if let Some((source, mut details)) = details_thread.try_join() {
+                // Textures that don't fit to an empty sheet could never be loaded
+                details.retain(|details| {
+                    let fits = details.fits_in_sheet(self.texture_sheet_max_size, 0, 0);
+                    if !fits {
+                        self.diagnostics.report(AssetError::AtlasOverflow {
+                            name: details.name.clone(),
+                            dimensions: details.dimensions,
+                        });
+                    }
+                    fits
+                });
+
                 self.details_thread = None;
+                self.asset_source = Some(Arc::new(source));
                 self.texture_details = Some(details);
                 self.total_textures = self.texture_details.as_ref().unwrap().len();
             }
@@ -97,7 +142,8 @@ impl TextureLoader {
         // Check if the loader thread has finished
         if let Some(loader_thread) = self.loader_thread.as_mut() {
             if let Some((image_sheets, texture_ids, texture_details)) = loader_thread.try_join() {
-                image_sheets.into_iter().for_each(|image| {
+                // Sheets come in pairs of color and normal-height sheets
+                image_sheets.into_iter().enumerate().for_each(|(i, image)| {
                     let texture_name = format!("texture_sheet_{}", self.loaded_textures.len());
                     let texture = Texture::new_from_raw_data(
                         device,
@@ -109,7 +155,7 @@ impl TextureLoader {
                         &texture_name,
                     );
 
-                    Self::generate_mipmaps(device, queue, &texture.texture, MIPMAP_COUNT);
+                    Self::generate_mipmaps(device, queue, &texture.texture, MIPMAP_COUNT, i % 2 == 0);
 
                     self.loaded_textures.push(texture);
 
@@ -137,15 +183,19 @@ impl TextureLoader {
             let loaded_textures_len = self.loaded_textures.len() as u32;
             let total_textures = self.total_textures;
             let progress_sender = self.progress_sender.clone();
+            let diagnostics = self.diagnostics.clone();
+            let source = self.asset_source.clone().expect("Asset source must be open");
 
             if !texture_details.is_empty() {
                 self.loader_thread = Some(spawn_thread_with_handle(Some("gen_texture_sheet"), move || {
                     Self::gen_texture_sheet(
+                        &source,
                         texture_details,
                         texture_sheet_max_size,
                         loaded_textures_len,
                         total_textures,
                         progress_sender,
+                        &diagnostics,
                     )
                 }));
             }
@@ -154,13 +204,52 @@ impl TextureLoader {
         false
     }
 
+    /// Opens the asset archives of the constants. Archives that can't be opened are reported and skipped.
+    fn open_asset_source(
+        constants: &Constants,
+        mod_texture_dirs: Vec<PathBuf>,
+        diagnostics: &AssetDiagnostics,
+    ) -> AssetSource {
+        let archives = constants
+            .gfx
+            .asset_archives
+            .iter()
+            .filter_map(|path| match AssetArchive::open(path) {
+                Ok(archive) => Some(archive),
+                Err(err) => {
+                    diagnostics.report(AssetError::MissingFile {
+                        path: path.clone(),
+                        reason: err.to_string(),
+                    });
+                    None
+                }
+            })
+            .collect();
+        AssetSource::new(constants.gfx.asset_path.clone(), archives, mod_texture_dirs)
+    }
+
     fn gen_texture_details(
         constants: &Constants,
-        asset_path: PathBuf,
+        source: &AssetSource,
         asset_names: &[String],
+        diagnostics: &AssetDiagnostics,
     ) -> VecDeque<SingleTextureDetails> {
-        let mut separate_texture_paths: Vec<_> = Self::list_files_with_dimensions(&asset_path)
-            .expect("Texture assets must be accessible")
+        let asset_files = match Self::list_files_with_dimensions(source) {
+            Ok(asset_files) => asset_files,
+            Err(err) => {
+                diagnostics.report(AssetError::MissingFile {
+                    path: source.asset_path().to_path_buf(),
+                    reason: err.to_string(),
+                });
+                return VecDeque::new();
+            }
+        };
+
+        let (aseprite_paths, png_paths): (Vec<_>, Vec<_>) = asset_files
+            .into_iter()
+            .partition(|(path, _)| Self::is_aseprite_file(path));
+
+        let mut separate_texture_paths: Vec<_> = png_paths
             .into_iter()
             .filter(|(path, _)| {
                 asset_names.iter().any(|texture| {
@@ -183,27 +272,36 @@ impl TextureLoader {
             let file_name = path.file_stem().unwrap().to_str().unwrap().to_owned();
             let file_name_parts: Vec<_> = file_name.split('.').collect();
             let name = file_name_parts[0].to_owned();
-            let type_tag = file_name_parts[1];
+            let type_tag = file_name_parts.get(1).copied().unwrap_or_default();
 
-            match type_tag {
-                "c" => {
+            match (type_tag, texture_sources.get_mut(&name)) {
+                ("c", _) => {
                     texture_sources.insert(name, (path, None, None, dimensions));
                 }
-                "n" => {
-                    texture_sources.get_mut(&name).unwrap().1 = Some(path);
+                ("n", Some(sources)) => {
+                    sources.1 = Some(path);
                 }
-                "h" => {
-                    texture_sources.get_mut(&name).unwrap().2 = Some(path);
+                ("h", Some(sources)) => {
+                    sources.2 = Some(path);
+                }
+                ("n" | "h", None) => {
+                    diagnostics.report(AssetError::MissingFile {
+                        path,
+                        reason: format!("Color texture for {} not found", name),
+                    });
                 }
                 _ => {
-                    panic!("Invalid texture type tag: {}", name);
+                    diagnostics.report(AssetError::UnsupportedFormat {
+                        path,
+                        reason: format!("Invalid texture type tag {:?}", type_tag),
+                    });
                 }
             }
         }
 
         let mut texture_details: Vec<SingleTextureDetails> = texture_sources
             .into_iter()
-            .map(|(_, (path_c, path_n, path_h, dimensions))| {
+            .filter_map(|(_, (path_c, path_n, path_h, dimensions))| {
                 let file_name = path_c.file_stem().unwrap().to_str().unwrap().to_owned();
                 let file_name_parts: Vec<_> = file_name.split('.').collect();
 
@@ -212,32 +310,26 @@ impl TextureLoader {
                     .get(4)
                     .map(|anim_text| *anim_text == "anim")
                     .unwrap_or(false);
-                let sub_images = file_name_parts.get(3).map(|dimension_text| {
-                    let mut parts = dimension_text.split('x');
-                    let x: u32 = parts.next().unwrap().parse().unwrap();
-                    let y: u32 = parts.next().unwrap().parse().unwrap();
-                    (x, y)
-                });
-                let layout = match file_name_parts[2] {
-                    "ov" => TextureLayout::Overlay,
-                    "sq" => TextureLayout::Square,
-                    "iso" => {
-                        let unit_height = ((dimensions.0 / sub_images.unwrap_or((1, 1)).0) as f32
-                            * constants.gfx.camera_angle_deg.to_radians().cos())
-                        .round() as u32;
-
-                        #[allow(clippy::comparison_chain)]
-                        if unit_height == dimensions.1 {
-                            TextureLayout::Isometric
-                        } else if unit_height < dimensions.1 {
-                            TextureLayout::IsometricHex
-                        } else {
-                            panic!("Invalid texture dimensions (y too small) for {}", name)
-                        }
+                let sub_images = match file_name_parts.get(3).map(|text| Self::parse_sub_images(text)) {
+                    Some(Ok(sub_images)) => Some(sub_images),
+                    Some(Err(reason)) => {
+                        diagnostics.report(AssetError::UnsupportedFormat { path: path_c, reason });
+                        return None;
                     }
-                    _ => panic!("Invalid texture layout tag for {}", name),
+                    None => None,
                 };
-                SingleTextureDetails {
+                let layout = match Self::parse_layout(
+                    constants,
+                    file_name_parts.get(2).copied().unwrap_or_default(),
+                    (dimensions.0 / sub_images.unwrap_or((1, 1)).0, dimensions.1),
+                ) {
+                    Ok(layout) => layout,
+                    Err(reason) => {
+                        diagnostics.report(AssetError::UnsupportedFormat { path: path_c, reason });
+                        return None;
+                    }
+                };
+                Some(SingleTextureDetails {
                     name,
                     path_c,
                     path_n,
@@ -246,10 +338,23 @@ impl TextureLoader {
                     sub_images,
                     is_anim,
                     layout,
-                }
+                    image_c: None,
+                })
             })
             .collect();
 
+        for (path, _) in aseprite_paths {
+            let file_name = path.file_stem().unwrap().to_str().unwrap().to_owned();
+            let name = file_name.split('.').next().unwrap();
+            let is_required = asset_names
+                .iter()
+                .any(|texture| texture == name || texture.starts_with(&format!("{}_", name)));
+
+            if is_required {
+                texture_details.extend(Self::gen_aseprite_details(constants, source, &path, diagnostics));
+            }
+        }
+
         texture_details.sort_by_key(|details| {
             let (x_count, _) = details.sub_images.unwrap_or((1, 1));
             details.dimensions.0 / x_count
@@ -262,12 +367,164 @@ impl TextureLoader {
         VecDeque::from(texture_details)
     }
 
+    /// Generates texture details for an aseprite file named `name.<layout>.aseprite`.
+    /// The file produces following textures:
+    /// - `name`: All frames of the file, animated if there are multiple frames.
+    /// - `name_<tag>`: Frames of each tag as an animation.
+    /// - `name_<slice>`: Region of each slice as a static texture.
+    ///
+    /// Animation speed is set by the sprite as with other textures, so the frames of each animation are expected to
+    /// have the same duration. Animations with varying frame durations are reported as unsupported.
+    fn gen_aseprite_details(
+        constants: &Constants,
+        source: &AssetSource,
+        path: &Path,
+        diagnostics: &AssetDiagnostics,
+    ) -> Vec<SingleTextureDetails> {
+        let file_name = path.file_stem().unwrap().to_str().unwrap().to_owned();
+        let file_name_parts: Vec<_> = file_name.split('.').collect();
+        let name = file_name_parts[0];
+        let layout_tag = file_name_parts.get(1).copied().unwrap_or("sq");
+
+        let file = match source.load(path) {
+            Ok(bytes) => AsepriteFile::parse(&bytes).map_err(|reason| AssetError::DecodeFailed {
+                path: path.to_path_buf(),
+                reason,
+            }),
+            Err(err) => Err(AssetError::MissingFile {
+                path: path.to_path_buf(),
+                reason: err.to_string(),
+            }),
+        };
+        let file = match file {
+            Ok(file) => file,
+            Err(err) => {
+                diagnostics.report(err);
+                return Vec::new();
+            }
+        };
+
+        let layout = match Self::parse_layout(constants, layout_tag, (file.width, file.height)) {
+            Ok(layout) => layout,
+            Err(reason) => {
+                diagnostics.report(AssetError::UnsupportedFormat {
+                    path: path.to_path_buf(),
+                    reason,
+                });
+                return Vec::new();
+            }
+        };
+
+        let animation_details = |texture_name: String, from: u16, to: u16| {
+            let (shortest_ms, longest_ms) = file.duration_range_ms(from, to);
+            if shortest_ms != longest_ms {
+                diagnostics.report(AssetError::UnsupportedFormat {
+                    path: path.to_path_buf(),
+                    reason: format!(
+                        "Frame durations of {} vary from {} to {} ms, but animations play at the frame rate of the sprite",
+                        texture_name, shortest_ms, longest_ms
+                    ),
+                });
+            }
+            let frame_count = (to - from + 1) as u32;
+            SingleTextureDetails {
+                name: texture_name,
+                path_c: path.to_path_buf(),
+                path_n: None,
+                path_h: None,
+                dimensions: (file.width * frame_count, file.height),
+                sub_images: (frame_count > 1).then_some((frame_count, 1)),
+                is_anim: frame_count > 1,
+                layout,
+                image_c: Some(DynamicImage::ImageRgba8(file.frame_strip(from, to))),
+            }
+        };
+
+        let mut details = vec![animation_details(name.to_string(), 0, file.frames.len() as u16 - 1)];
+
+        for tag in &file.tags {
+            details.push(animation_details(format!("{}_{}", name, tag.name), tag.from, tag.to));
+        }
+
+        for slice in &file.slices {
+            let image = file.slice_image(slice);
+            let layout = match Self::parse_layout(constants, layout_tag, image.dimensions()) {
+                Ok(layout) => layout,
+                Err(reason) => {
+                    diagnostics.report(AssetError::UnsupportedFormat {
+                        path: path.to_path_buf(),
+                        reason: format!("Slice {}: {}", slice.name, reason),
+                    });
+                    continue;
+                }
+            };
+            details.push(SingleTextureDetails {
+                name: format!("{}_{}", name, slice.name),
+                path_c: path.to_path_buf(),
+                path_n: None,
+                path_h: None,
+                dimensions: image.dimensions(),
+                sub_images: None,
+                is_anim: false,
+                layout,
+                image_c: Some(DynamicImage::ImageRgba8(image)),
+            });
+        }
+
+        details
+    }
+
+    /// Parses the layout tag of a texture file name.
+    /// Isometric layout is resolved from the dimensions of a single frame.
+    fn parse_layout(
+        constants: &Constants,
+        layout_tag: &str,
+        frame_dimensions: (u32, u32),
+    ) -> Result<TextureLayout, String> {
+        match layout_tag {
+            "ov" => Ok(TextureLayout::Overlay),
+            "sq" => Ok(TextureLayout::Square),
+            "iso" => {
+                let unit_height =
+                    (frame_dimensions.0 as f32 * constants.gfx.camera_angle_deg.to_radians().cos()).round() as u32;
+
+                #[allow(clippy::comparison_chain)]
+                if unit_height == frame_dimensions.1 {
+                    Ok(TextureLayout::Isometric)
+                } else if unit_height < frame_dimensions.1 {
+                    Ok(TextureLayout::IsometricHex)
+                } else {
+                    Err("Invalid texture dimensions (y too small) for isometric layout".to_string())
+                }
+            }
+            _ => Err(format!("Invalid texture layout tag {:?}", layout_tag)),
+        }
+    }
+
+    /// Parses the sub image tag of a texture file name, for example `4x2`.
+    fn parse_sub_images(text: &str) -> Result<(u32, u32), String> {
+        let invalid = || format!("Invalid sub image tag {:?}", text);
+        let (x, y) = text.split_once('x').ok_or_else(invalid)?;
+        match (x.parse(), y.parse()) {
+            (Ok(x), Ok(y)) if x > 0 && y > 0 => Ok((x, y)),
+            _ => Err(invalid()),
+        }
+    }
+
+    fn is_aseprite_file(path: &Path) -> bool {
+        path.extension()
+            .map(|ext| ext == "aseprite" || ext == "ase")
+            .unwrap_or(false)
+    }
+
     fn gen_texture_sheet(
+        source: &AssetSource,
         mut texture_details: VecDeque<SingleTextureDetails>,
         texture_sheet_max_size: u32,
         texture_sheet_index: u32,
         total_textures: usize,
         progress_sender: Sender<f32>,
+        diagnostics: &AssetDiagnostics,
     ) -> (Vec<RgbaImage>, Map<String, TextureId>, VecDeque<SingleTextureDetails>) {
         let mut sheet_c = RgbaImage::new(texture_sheet_max_size, texture_sheet_max_size);
         let mut sheet_nh = RgbaImage::new(texture_sheet_max_size, texture_sheet_max_size);
@@ -278,37 +535,63 @@ impl TextureLoader {
         let mut cur_y = 0;
 
         let next_exists_and_fits = |texture_details: &VecDeque<SingleTextureDetails>, cur_x: u32, cur_y: u32| {
-            if let Some(details) = texture_details.front() {
-                let (x_count, y_count) = details.sub_images.unwrap_or((1, 1));
-                let width = details.dimensions.0 / x_count;
-                let height = details.dimensions.1 / y_count;
-                let rows_available = (texture_sheet_max_size - cur_y) / (height + 1); // +1 for spacing between rows
-                let cols_available = (texture_sheet_max_size - cur_x) / (width + 1); // +1 for spacing between columns
-                let cur_row_cols_used = (cur_x + width) / (width + 1);
-
-                x_count * y_count <= rows_available * cols_available - cur_row_cols_used
-            } else {
-                false
-            }
+            texture_details
+                .front()
+                .is_some_and(|details| details.fits_in_sheet(texture_sheet_max_size, cur_x, cur_y))
+        };
+
+        let load_image = |path: &PathBuf| {
+            let bytes = source.load(path).map_err(|err| AssetError::MissingFile {
+                path: path.clone(),
+                reason: err.to_string(),
+            })?;
+            image::load_from_memory(&bytes).map_err(|err| AssetError::DecodeFailed {
+                path: path.clone(),
+                reason: err.to_string(),
+            })
         };
 
         while next_exists_and_fits(&texture_details, cur_x, cur_y) {
-            let details = texture_details.pop_front().unwrap();
+            let mut details = texture_details.pop_front().unwrap();
             let (x_sub, y_sub) = details.sub_images.unwrap_or((1, 1));
             let width = details.dimensions.0 / x_sub;
             let height = details.dimensions.1 / y_sub;
             let x_images = if details.is_anim { 1 } else { x_sub };
 
-            let img_c = image::load_from_memory(&load_resource(&details.path_c).expect("Failed to load color texture"))
-                .expect("Failed to parse color texture");
-            let img_n = details.path_n.as_ref().map(|path| {
-                image::load_from_memory(&load_resource(&path).expect("Failed to load normal texture"))
-                    .expect("Failed to parse normal texture")
-            });
-            let img_h = details.path_h.as_ref().map(|path| {
-                image::load_from_memory(&load_resource(&path).expect("Failed to load height texture"))
-                    .expect("Failed to parse height texture")
-            });
+            let images = (|| {
+                let img_c = match details.image_c.take() {
+                    Some(image) => image,
+                    None => load_image(&details.path_c)?,
+                };
+                let img_n = details.path_n.as_ref().map(load_image).transpose()?;
+                let img_h = details.path_h.as_ref().map(load_image).transpose()?;
+
+                for (img, path) in [(&img_n, &details.path_n), (&img_h, &details.path_h)] {
+                    if let (Some(img), Some(path)) = (img, path)
+                        && img.dimensions() != img_c.dimensions()
+                    {
+                        return Err(AssetError::UnsupportedFormat {
+                            path: path.clone(),
+                            reason: "Dimensions differ from the color texture".to_string(),
+                        });
+                    }
+                }
+                if img_c.dimensions() != details.dimensions {
+                    return Err(AssetError::DecodeFailed {
+                        path: details.path_c.clone(),
+                        reason: "Decoded dimensions differ from the file header".to_string(),
+                    });
+                }
+                Ok((img_c, img_n, img_h))
+            })();
+
+            let (img_c, img_n, img_h) = match images {
+                Ok(images) => images,
+                Err(err) => {
+                    diagnostics.report(err);
+                    continue;
+                }
+            };
 
             let mut cur_src_x = 0;
             let mut cur_src_y = 0;
@@ -385,29 +668,62 @@ impl TextureLoader {
         (vec![sheet_c, sheet_nh], texture_ids, texture_details)
     }
 
-    /// Reads the first 24 bytes of a PNG and parses out (width, height)
-    #[cfg(not(target_arch = "wasm32"))]
-    fn read_png_dimensions(path: &PathBuf) -> io::Result<(u32, u32)> {
-        let mut file = File::open(path)?;
-        let mut header = [0u8; 24];
-        file.read_exact(&mut header)?;
+    /// Parses (width, height) from the start of a texture file.
+    fn parse_dimensions(path: &Path, header: &[u8]) -> io::Result<(u32, u32)> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Truncated texture header");
+        if Self::is_aseprite_file(path) {
+            // Bytes 8..10 = width, Bytes 10..12 = height, little-endian u16
+            let header = header.get(..12).ok_or_else(invalid)?;
+            let width = u16::from_le_bytes([header[8], header[9]]) as u32;
+            let height = u16::from_le_bytes([header[10], header[11]]) as u32;
+            Ok((width, height))
+        } else {
+            // Bytes 16..20 = width, Bytes 20..24 = height, big-endian u32
+            let header = header.get(..24).ok_or_else(invalid)?;
+            let width = u32::from_be_bytes([header[16], header[17], header[18], header[19]]);
+            let height = u32::from_be_bytes([header[20], header[21], header[22], header[23]]);
+            Ok((width, height))
+        }
+    }
 
-        // Bytes 16..20 = width, Bytes 20..24 = height, big-endian u32
-        let width = u32::from_be_bytes([header[16], header[17], header[18], header[19]]);
-        let height = u32::from_be_bytes([header[20], header[21], header[22], header[23]]);
+    /// Lists the textures of the mods, the asset archives and the asset directory.
+    /// The directory is optional if there are archives, as shipped games may only have those.
+    fn list_files_with_dimensions(source: &AssetSource) -> io::Result<Vec<(PathBuf, (u32, u32))>> {
+        let override_files = source.override_files(&TEXTURE_FILE_TYPES);
+        let loose_files = match Self::list_loose_files_with_dimensions(source) {
+            Ok(loose_files) => loose_files,
+            Err(_) if source.has_archives() => Vec::new(),
+            Err(err) => return Err(err),
+        };
 
-        Ok((width, height))
+        let override_set: Set<&PathBuf> = override_files.iter().collect();
+        let mut results: Vec<_> = loose_files
+            .into_iter()
+            .filter(|(path, _)| !override_set.contains(path))
+            .collect();
+        for path in &override_files {
+            let dimensions = source
+                .load_prefix(path, TEXTURE_HEADER_LEN)
+                .and_then(|header| Self::parse_dimensions(path, &header));
+            if let Ok(dimensions) = dimensions {
+                results.push((path.clone(), dimensions));
+            }
+        }
+        Ok(results)
     }
 
-    fn list_files_with_dimensions(path: &PathBuf) -> io::Result<Vec<(PathBuf, (u32, u32))>> {
+    fn list_loose_files_with_dimensions(source: &AssetSource) -> io::Result<Vec<(PathBuf, (u32, u32))>> {
         #[cfg(not(target_arch = "wasm32"))]
         {
-            let texture_file_types = vec![OsStr::new("png")];
-            let files = list_files(path, Some(texture_file_types.as_slice()))?;
+            let texture_file_types = TEXTURE_FILE_TYPES.map(OsStr::new);
+            let files = list_files(source.asset_path(), Some(texture_file_types.as_slice()))?;
 
             let mut results = Vec::new();
             for (file_path, _) in files {
-                if let Ok(dimensions) = Self::read_png_dimensions(&file_path) {
+                let dimensions = source
+                    .load_prefix(&file_path, TEXTURE_HEADER_LEN)
+                    .and_then(|header| Self::parse_dimensions(&file_path, &header));
+                if let Ok(dimensions) = dimensions {
                     results.push((file_path, dimensions));
                 }
             }
@@ -469,12 +785,20 @@ impl TextureLoader {
         }
     }
 
-    fn generate_mipmaps(device: &wgpu::Device, queue: &wgpu::Queue, texture: &wgpu::Texture, mipmap_count: u32) {
+    /// Renders each mip level by box filtering the previous one.
+    /// Color sheets are `alpha_weighted`, so that transparent texels don't darken sprite edges when zoomed out.
+    pub(super) fn generate_mipmaps(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+        mipmap_count: u32,
+        alpha_weighted: bool,
+    ) {
         let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("mipmap_encoder"),
         });
 
-        let shader = build_shader!(device, SHADER_SCALE);
+        let shader = build_shader!(device, SHADER_MIPMAP);
         let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("mipmap_blit_pipeline"),
             layout: None,
@@ -486,7 +810,7 @@ impl TextureLoader {
             },
             fragment: Some(wgpu::FragmentState {
                 module: &shader,
-                entry_point: Some("fs_main"),
+                entry_point: Some(if alpha_weighted { "fs_color" } else { "fs_main" }),
                 compilation_options: Default::default(),
                 targets: &[Some(wgpu::TextureFormat::Rgba8UnormSrgb.into())],
             }),
@@ -502,17 +826,6 @@ impl TextureLoader {
 
         let bind_group_layout = pipeline.get_bind_group_layout(0);
 
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            label: Some("mipmap_sampler"),
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Linear,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
-        });
-
         let views = (0..mipmap_count)
             .map(|mip| {
                 texture.create_view(&wgpu::TextureViewDescriptor {
@@ -532,16 +845,10 @@ impl TextureLoader {
         for target_mip in 1..mipmap_count as usize {
             let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
                 layout: &bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&views[target_mip - 1]),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&sampler),
-                    },
-                ],
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&views[target_mip - 1]),
+                }],
                 label: None,
             });
 
@@ -559,7 +866,7 @@ impl TextureLoader {
             });
             render_pass.set_pipeline(&pipeline);
             render_pass.set_bind_group(0, &bind_group, &[]);
-            render_pass.draw(0..6, 0..1);
+            render_pass.draw(0..3, 0..1);
         }
 
         queue.submit(std::iter::once(encoder.finish()));
@@ -576,10 +883,60 @@ struct SingleTextureDetails {
     sub_images: Option<(u32, u32)>,
     is_anim: bool,
     layout: TextureLayout,
+    /// Already decoded color image, used instead of loading `path_c`.
+    image_c: Option<DynamicImage>,
 }
 
 impl SingleTextureDetails {
     fn has_n_or_h(&self) -> bool {
         self.path_n.is_some() || self.path_h.is_some()
     }
+
+    /// Returns true if all sub images of the texture fit to a sheet, starting from the given position.
+    fn fits_in_sheet(&self, sheet_size: u32, cur_x: u32, cur_y: u32) -> bool {
+        let (x_count, y_count) = self.sub_images.unwrap_or((1, 1));
+        let width = self.dimensions.0 / x_count;
+        let height = self.dimensions.1 / y_count;
+        let rows_available = (sheet_size - cur_y) / (height + 1); // +1 for spacing between rows
+        let cols_available = (sheet_size - cur_x) / (width + 1); // +1 for spacing between columns
+        let cur_row_cols_used = (cur_x + width) / (width + 1);
+
+        x_count * y_count <= (rows_available * cols_available).saturating_sub(cur_row_cols_used)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn details(dimensions: (u32, u32), sub_images: Option<(u32, u32)>) -> SingleTextureDetails {
+        SingleTextureDetails {
+            name: "test".to_string(),
+            path_c: PathBuf::new(),
+            path_n: None,
+            path_h: None,
+            dimensions,
+            sub_images,
+            is_anim: false,
+            layout: TextureLayout::Square,
+            image_c: None,
+        }
+    }
+
+    #[test]
+    fn sub_image_tags_are_validated() {
+        assert_eq!(TextureLoader::parse_sub_images("4x2"), Ok((4, 2)));
+        assert!(TextureLoader::parse_sub_images("4").is_err());
+        assert!(TextureLoader::parse_sub_images("0x2").is_err());
+        assert!(TextureLoader::parse_sub_images("ax2").is_err());
+    }
+
+    #[test]
+    fn oversized_textures_do_not_fit_to_empty_sheet() {
+        assert!(details((63, 63), None).fits_in_sheet(64, 0, 0));
+        assert!(!details((64, 64), None).fits_in_sheet(64, 0, 0));
+        assert!(details((60, 15), Some((4, 1))).fits_in_sheet(64, 0, 0));
+        assert!(!details((160, 40), Some((4, 1))).fits_in_sheet(64, 0, 0));
+        assert!(!details((63, 63), None).fits_in_sheet(64, 10, 0));
+    }
 }
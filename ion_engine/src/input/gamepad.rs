@@ -0,0 +1,492 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use ion_common::{Instant, Map};
+
+use crate::core::world::CommandType;
+
+use super::InputEvent;
+
+/// Identifier of a connected gamepad. Ids of disconnected gamepads can be reused by gamepads connected later.
+pub type GamepadId = u32;
+
+/// Axis values closer to zero than this are reported as zero, so that worn sticks don't drift.
+pub const GAMEPAD_AXIS_DEAD_ZONE: f32 = 0.15;
+
+/// Gamepad buttons, named by their position on the pad.
+/// Declared in the order of the standard gamepad mapping, so that the discriminant is the button index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GamepadButton {
+    South = 0,
+    East = 1,
+    West = 2,
+    North = 3,
+    LeftBumper = 4,
+    RightBumper = 5,
+    LeftTrigger = 6,
+    RightTrigger = 7,
+    Select = 8,
+    Start = 9,
+    LeftStick = 10,
+    RightStick = 11,
+    DPadUp = 12,
+    DPadDown = 13,
+    DPadLeft = 14,
+    DPadRight = 15,
+    Mode = 16,
+}
+
+impl GamepadButton {
+    pub const ALL: [GamepadButton; 17] = [
+        GamepadButton::South,
+        GamepadButton::East,
+        GamepadButton::West,
+        GamepadButton::North,
+        GamepadButton::LeftBumper,
+        GamepadButton::RightBumper,
+        GamepadButton::LeftTrigger,
+        GamepadButton::RightTrigger,
+        GamepadButton::Select,
+        GamepadButton::Start,
+        GamepadButton::LeftStick,
+        GamepadButton::RightStick,
+        GamepadButton::DPadUp,
+        GamepadButton::DPadDown,
+        GamepadButton::DPadLeft,
+        GamepadButton::DPadRight,
+        GamepadButton::Mode,
+    ];
+}
+
+/// Gamepad stick axes in range -1.0..1.0. Negative values are left and up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GamepadAxis {
+    LeftStickX = 0,
+    LeftStickY = 1,
+    RightStickX = 2,
+    RightStickY = 3,
+}
+
+impl GamepadAxis {
+    pub const ALL: [GamepadAxis; 4] =
+        [GamepadAxis::LeftStickX, GamepadAxis::LeftStickY, GamepadAxis::RightStickX, GamepadAxis::RightStickY];
+}
+
+/// Force feedback of a gamepad for a duration. Magnitudes are in range 0.0..1.0.
+/// The strong motor rumbles at a low frequency and the weak motor at a high frequency.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rumble {
+    pub strong: f32,
+    pub weak: f32,
+    pub duration: Duration,
+}
+
+impl Rumble {
+    pub fn new(strong: f32, weak: f32, duration: Duration) -> Self {
+        Self {
+            strong: strong.clamp(0.0, 1.0),
+            weak: weak.clamp(0.0, 1.0),
+            duration,
+        }
+    }
+
+    /// No rumble for the duration, for pauses between the steps of a pattern.
+    pub fn pause(duration: Duration) -> Self {
+        Self::new(0.0, 0.0, duration)
+    }
+}
+
+/// Request to play a rumble pattern on a gamepad, sent from input states to the poller.
+pub(crate) type RumbleRequest = (GamepadId, Vec<Rumble>);
+
+/// State of a single gamepad as read from the platform.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct PolledGamepad {
+    pub buttons: [bool; GamepadButton::ALL.len()],
+    pub axes: [f32; GamepadAxis::ALL.len()],
+}
+
+/// Turns gamepad states read from the platform into input events, by comparing them to the previous poll.
+/// Also plays the rumble patterns of the gamepads one step at a time.
+///
+/// Gamepads are read from the browser gamepad api on wasm, and from XInput on Windows.
+/// Other native platforms don't have a gamepad backend yet, so no gamepads are ever connected there.
+#[derive(Debug, Default)]
+pub(crate) struct GamepadPoller {
+    previous: Map<GamepadId, PolledGamepad>,
+    rumbles: Map<GamepadId, RumbleState>,
+}
+
+#[derive(Debug)]
+struct RumbleState {
+    steps: VecDeque<Rumble>,
+    next_step_at: Option<Instant>,
+}
+
+impl GamepadPoller {
+    pub(crate) fn poll<C: CommandType>(&mut self) -> Vec<InputEvent<C>> {
+        let events = self.update(read_gamepads());
+        for (id, rumble) in self.update_rumbles(Instant::now()) {
+            play_rumble(id, rumble);
+        }
+        events
+    }
+
+    /// Replaces the rumble pattern of the gamepad. An empty pattern stops the rumble.
+    pub(crate) fn set_rumble(&mut self, id: GamepadId, pattern: Vec<Rumble>) {
+        self.rumbles.insert(
+            id,
+            RumbleState {
+                steps: pattern.into(),
+                next_step_at: None,
+            },
+        );
+    }
+
+    /// Returns the rumbles to start on each gamepad, or None if the rumble should stop.
+    fn update_rumbles(&mut self, now: Instant) -> Vec<(GamepadId, Option<Rumble>)> {
+        let mut changes = Vec::new();
+        self.rumbles.retain(|id, state| {
+            if !self.previous.contains_key(id) {
+                return false;
+            }
+            if state.next_step_at.is_some_and(|at| now.duration_since(at).is_none()) {
+                return true;
+            }
+
+            match state.steps.pop_front() {
+                Some(step) => {
+                    changes.push((*id, Some(step)));
+                    state.next_step_at = Some(state.next_step_at.unwrap_or(now) + step.duration);
+                    true
+                }
+                None => {
+                    changes.push((*id, None));
+                    false
+                }
+            }
+        });
+        changes
+    }
+
+    fn update<C: CommandType>(&mut self, current: Map<GamepadId, PolledGamepad>) -> Vec<InputEvent<C>> {
+        let mut events = Vec::new();
+
+        for id in self.previous.keys().filter(|id| !current.contains_key(id)) {
+            events.push(InputEvent::GamepadDisconnected(*id));
+        }
+
+        for (id, gamepad) in &current {
+            let previous = match self.previous.get(id) {
+                Some(previous) => *previous,
+                None => {
+                    events.push(InputEvent::GamepadConnected(*id));
+                    PolledGamepad::default()
+                }
+            };
+
+            for button in GamepadButton::ALL {
+                let pressed = gamepad.buttons[button as usize];
+                if pressed != previous.buttons[button as usize] {
+                    events.push(if pressed {
+                        InputEvent::GamepadButtonPressed(*id, button)
+                    } else {
+                        InputEvent::GamepadButtonReleased(*id, button)
+                    });
+                }
+            }
+
+            for axis in GamepadAxis::ALL {
+                let value = apply_dead_zone(gamepad.axes[axis as usize]);
+                if value != apply_dead_zone(previous.axes[axis as usize]) {
+                    events.push(InputEvent::GamepadAxisChange(*id, axis, value));
+                }
+            }
+        }
+
+        self.previous = current;
+        events
+    }
+}
+
+fn apply_dead_zone(value: f32) -> f32 {
+    if value.abs() < GAMEPAD_AXIS_DEAD_ZONE {
+        0.0
+    } else {
+        value.clamp(-1.0, 1.0)
+    }
+}
+
+/// Reads the gamepads that use the standard mapping. Other mappings have no fixed button layout.
+#[cfg(target_arch = "wasm32")]
+fn read_gamepads() -> Map<GamepadId, PolledGamepad> {
+    use ion_common::wasm_bindgen::JsCast;
+    use ion_common::web_sys;
+
+    let mut gamepads = Map::default();
+    let Some(Ok(list)) = web_sys::window().map(|window| window.navigator().get_gamepads()) else {
+        return gamepads;
+    };
+
+    for value in list.iter() {
+        let Ok(gamepad) = value.dyn_into::<web_sys::Gamepad>() else {
+            continue;
+        };
+        if !gamepad.connected() || gamepad.mapping() != web_sys::GamepadMappingType::Standard {
+            continue;
+        }
+
+        let mut polled = PolledGamepad::default();
+        let buttons = gamepad.buttons();
+        for (i, pressed) in polled.buttons.iter_mut().enumerate() {
+            if let Ok(button) = buttons.get(i as u32).dyn_into::<web_sys::GamepadButton>() {
+                *pressed = button.pressed();
+            }
+        }
+        let axes = gamepad.axes();
+        for (i, value) in polled.axes.iter_mut().enumerate() {
+            *value = axes.get(i as u32).as_f64().unwrap_or(0.0) as f32;
+        }
+
+        gamepads.insert(gamepad.index(), polled);
+    }
+
+    gamepads
+}
+
+/// Reads the XInput gamepads, which are the Xbox compatible ones. XInput has a fixed button layout, but doesn't
+/// report the mode button.
+#[cfg(target_os = "windows")]
+fn read_gamepads() -> Map<GamepadId, PolledGamepad> {
+    use windows::Win32::UI::Input::XboxController::{XINPUT_STATE, XInputGetState, XUSER_MAX_COUNT};
+
+    let mut gamepads = Map::default();
+    for id in 0..XUSER_MAX_COUNT {
+        let mut state = XINPUT_STATE::default();
+        // Anything but ERROR_SUCCESS means that no gamepad is connected to the slot
+        if unsafe { XInputGetState(id, &mut state) } == 0 {
+            gamepads.insert(id, xinput_gamepad(&state.Gamepad));
+        }
+    }
+    gamepads
+}
+
+#[cfg(target_os = "windows")]
+fn xinput_gamepad(state: &windows::Win32::UI::Input::XboxController::XINPUT_GAMEPAD) -> PolledGamepad {
+    use windows::Win32::UI::Input::XboxController::*;
+
+    let button_flags = [
+        (GamepadButton::South, XINPUT_GAMEPAD_A),
+        (GamepadButton::East, XINPUT_GAMEPAD_B),
+        (GamepadButton::West, XINPUT_GAMEPAD_X),
+        (GamepadButton::North, XINPUT_GAMEPAD_Y),
+        (GamepadButton::LeftBumper, XINPUT_GAMEPAD_LEFT_SHOULDER),
+        (GamepadButton::RightBumper, XINPUT_GAMEPAD_RIGHT_SHOULDER),
+        (GamepadButton::Select, XINPUT_GAMEPAD_BACK),
+        (GamepadButton::Start, XINPUT_GAMEPAD_START),
+        (GamepadButton::LeftStick, XINPUT_GAMEPAD_LEFT_THUMB),
+        (GamepadButton::RightStick, XINPUT_GAMEPAD_RIGHT_THUMB),
+        (GamepadButton::DPadUp, XINPUT_GAMEPAD_DPAD_UP),
+        (GamepadButton::DPadDown, XINPUT_GAMEPAD_DPAD_DOWN),
+        (GamepadButton::DPadLeft, XINPUT_GAMEPAD_DPAD_LEFT),
+        (GamepadButton::DPadRight, XINPUT_GAMEPAD_DPAD_RIGHT),
+    ];
+
+    let mut polled = PolledGamepad::default();
+    for (button, flag) in button_flags {
+        polled.buttons[button as usize] = state.wButtons.contains(flag);
+    }
+    // Triggers are analog on XInput, and count as pressed past the threshold that XInput suggests
+    let trigger_threshold = XINPUT_GAMEPAD_TRIGGER_THRESHOLD.0 as u8;
+    polled.buttons[GamepadButton::LeftTrigger as usize] = state.bLeftTrigger > trigger_threshold;
+    polled.buttons[GamepadButton::RightTrigger as usize] = state.bRightTrigger > trigger_threshold;
+
+    // Up is positive on XInput
+    let stick = |value: i16| (value as f32 / i16::MAX as f32).clamp(-1.0, 1.0);
+    polled.axes[GamepadAxis::LeftStickX as usize] = stick(state.sThumbLX);
+    polled.axes[GamepadAxis::LeftStickY as usize] = -stick(state.sThumbLY);
+    polled.axes[GamepadAxis::RightStickX as usize] = stick(state.sThumbRX);
+    polled.axes[GamepadAxis::RightStickY as usize] = -stick(state.sThumbRY);
+    polled
+}
+
+#[cfg(not(any(target_arch = "wasm32", target_os = "windows")))]
+fn read_gamepads() -> Map<GamepadId, PolledGamepad> {
+    Map::default()
+}
+
+/// Plays the rumble with the vibration actuator of the browser gamepad api, if the browser supports it.
+/// The api is called dynamically, as its bindings are still unstable.
+#[cfg(target_arch = "wasm32")]
+fn play_rumble(id: GamepadId, rumble: Option<Rumble>) {
+    use ion_common::js_sys::{Array, Function, Object, Reflect};
+    use ion_common::wasm_bindgen::{JsCast, JsValue};
+    use ion_common::web_sys;
+
+    let Some(Ok(list)) = web_sys::window().map(|window| window.navigator().get_gamepads()) else {
+        return;
+    };
+    let gamepad = list.get(id);
+    let Ok(actuator) = Reflect::get(&gamepad, &JsValue::from_str("vibrationActuator")) else {
+        return;
+    };
+    if actuator.is_undefined() || actuator.is_null() {
+        return;
+    }
+
+    let call = |method: &str, args: &[JsValue]| {
+        if let Ok(function) = Reflect::get(&actuator, &JsValue::from_str(method))
+            && let Ok(function) = function.dyn_into::<Function>()
+        {
+            let _ = function.apply(&actuator, &args.iter().collect::<Array>());
+        }
+    };
+
+    match rumble {
+        Some(rumble) => {
+            let params = Object::new();
+            let _ = Reflect::set(
+                &params,
+                &JsValue::from_str("duration"),
+                &JsValue::from_f64(rumble.duration.as_secs_f64() * 1000.0),
+            );
+            let _ = Reflect::set(
+                &params,
+                &JsValue::from_str("strongMagnitude"),
+                &JsValue::from_f64(rumble.strong as f64),
+            );
+            let _ = Reflect::set(
+                &params,
+                &JsValue::from_str("weakMagnitude"),
+                &JsValue::from_f64(rumble.weak as f64),
+            );
+            call("playEffect", &[JsValue::from_str("dual-rumble"), params.into()]);
+        }
+        None => call("reset", &[]),
+    }
+}
+
+/// Plays the rumble with the motors of the XInput gamepad. The left motor of XInput is the strong one.
+/// XInput rumbles until told otherwise, so the rumble is stopped by the poller when it's over.
+#[cfg(target_os = "windows")]
+fn play_rumble(id: GamepadId, rumble: Option<Rumble>) {
+    use windows::Win32::UI::Input::XboxController::{XINPUT_VIBRATION, XInputSetState};
+
+    let rumble = rumble.unwrap_or(Rumble::pause(Duration::ZERO));
+    let vibration = XINPUT_VIBRATION {
+        wLeftMotorSpeed: (rumble.strong * u16::MAX as f32) as u16,
+        wRightMotorSpeed: (rumble.weak * u16::MAX as f32) as u16,
+    };
+    unsafe {
+        XInputSetState(id, &vibration);
+    }
+}
+
+#[cfg(not(any(target_arch = "wasm32", target_os = "windows")))]
+fn play_rumble(_id: GamepadId, _rumble: Option<Rumble>) {}
+
+#[cfg(test)]
+mod tests {
+    use bincode::{Decode, Encode};
+
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Encode, Decode)]
+    enum TestCommand {
+        Test,
+    }
+    impl CommandType for TestCommand {}
+
+    fn gamepads(gamepads: &[(GamepadId, PolledGamepad)]) -> Map<GamepadId, PolledGamepad> {
+        gamepads.iter().copied().collect()
+    }
+
+    #[test]
+    fn button_order_matches_discriminants() {
+        for (i, button) in GamepadButton::ALL.iter().enumerate() {
+            assert_eq!(*button as usize, i);
+        }
+        for (i, axis) in GamepadAxis::ALL.iter().enumerate() {
+            assert_eq!(*axis as usize, i);
+        }
+    }
+
+    #[test]
+    fn poller_reports_changes_since_previous_poll() {
+        let mut poller = GamepadPoller::default();
+        let mut gamepad = PolledGamepad::default();
+        gamepad.buttons[GamepadButton::South as usize] = true;
+
+        let events = poller.update::<TestCommand>(gamepads(&[(0, gamepad)]));
+        assert!(matches!(
+            events[..],
+            [InputEvent::GamepadConnected(0), InputEvent::GamepadButtonPressed(0, GamepadButton::South)]
+        ));
+
+        // Stick movement inside the dead zone is not reported
+        gamepad.axes[GamepadAxis::LeftStickX as usize] = 0.1;
+        assert!(poller.update::<TestCommand>(gamepads(&[(0, gamepad)])).is_empty());
+
+        gamepad.buttons[GamepadButton::South as usize] = false;
+        gamepad.axes[GamepadAxis::LeftStickX as usize] = -0.5;
+        let events = poller.update::<TestCommand>(gamepads(&[(0, gamepad)]));
+        assert!(matches!(
+            events[..],
+            [
+                InputEvent::GamepadButtonReleased(0, GamepadButton::South),
+                InputEvent::GamepadAxisChange(0, GamepadAxis::LeftStickX, -0.5)
+            ]
+        ));
+
+        let events = poller.update::<TestCommand>(gamepads(&[]));
+        assert!(matches!(events[..], [InputEvent::GamepadDisconnected(0)]));
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn xinput_state_is_mapped_to_standard_layout() {
+        use windows::Win32::UI::Input::XboxController::*;
+
+        let state = XINPUT_GAMEPAD {
+            wButtons: XINPUT_GAMEPAD_A | XINPUT_GAMEPAD_DPAD_LEFT,
+            bLeftTrigger: 255,
+            bRightTrigger: 10,
+            sThumbLX: i16::MIN,
+            sThumbLY: i16::MAX,
+            sThumbRX: 0,
+            sThumbRY: 0,
+        };
+        let polled = xinput_gamepad(&state);
+        let pressed: Vec<_> = GamepadButton::ALL
+            .into_iter()
+            .filter(|button| polled.buttons[*button as usize])
+            .collect();
+        assert_eq!(
+            pressed,
+            vec![GamepadButton::South, GamepadButton::LeftTrigger, GamepadButton::DPadLeft]
+        );
+        assert_eq!(polled.axes, [-1.0, -1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn rumble_patterns_are_played_step_by_step() {
+        let mut poller = GamepadPoller::default();
+        poller.update::<TestCommand>(gamepads(&[(0, PolledGamepad::default())]));
+
+        let buzz = Rumble::new(1.0, 0.5, Duration::from_millis(100));
+        let pause = Rumble::pause(Duration::from_millis(50));
+        poller.set_rumble(0, vec![buzz, pause, buzz]);
+        // Rumbles of gamepads that are not connected are dropped
+        poller.set_rumble(1, vec![buzz]);
+
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        assert_eq!(poller.update_rumbles(at(0)), vec![(0, Some(buzz))]);
+        assert!(poller.update_rumbles(at(90)).is_empty());
+        assert_eq!(poller.update_rumbles(at(110)), vec![(0, Some(pause))]);
+        // Steps are timed from the start of the pattern, not from when they were polled
+        assert_eq!(poller.update_rumbles(at(150)), vec![(0, Some(buzz))]);
+        assert_eq!(poller.update_rumbles(at(250)), vec![(0, None)]);
+        assert!(poller.update_rumbles(at(300)).is_empty());
+    }
+}
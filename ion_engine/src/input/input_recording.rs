@@ -0,0 +1,294 @@
+use std::time::Duration;
+
+use ion_common::Instant;
+use ion_common::bincode::{Decode, Encode};
+use winit::event::{MouseButton, TouchPhase};
+
+use crate::core::coordinates::{Location, Position};
+use crate::core::world::CommandType;
+
+use super::{
+    InputEvent, TimedInputEvent,
+    gamepad::{GamepadAxis, GamepadButton, GamepadId},
+    input_state::SUPPORTED_KEYS,
+};
+
+/// Input events of consecutive frames of an input state, that can be replayed into an input state later.
+///
+/// Recordings of the universe input state contain the input of each universe frame, so replaying one from the same
+/// starting state reproduces the same actions. This makes recordings usable as bug reports and gameplay tests.
+/// Recordings can be stored with bincode. The timing of events within each frame is kept, relative to the time the
+/// frame was handled.
+///
+/// Binds and bind contexts are not recorded, as they are set by the game and not by the player.
+/// Events of keys that input states don't track are not recorded either.
+#[derive(Debug, Clone, Default, PartialEq, Encode, Decode)]
+pub struct InputRecording {
+    /// Events of each frame, with the time in microseconds between sending the event and handling the frame.
+    frames: Vec<Vec<(u32, RecordedEvent)>>,
+}
+
+impl InputRecording {
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+}
+
+/// Input event in a form that can be encoded. Keys, buttons and axes are stored as indices.
+#[derive(Debug, Clone, Copy, PartialEq, Encode, Decode)]
+enum RecordedEvent {
+    KeyPressed(u16, Option<char>),
+    KeyReleased(u16, Option<char>),
+    MouseButtonPressed(u16),
+    MouseButtonReleased(u16),
+    MouseScrollChange(f32),
+    CursorLocPosChange(Location, Position),
+    CameraMoved(f32, f32),
+    GamepadConnected(GamepadId),
+    GamepadDisconnected(GamepadId),
+    GamepadButtonPressed(GamepadId, u8),
+    GamepadButtonReleased(GamepadId, u8),
+    GamepadAxisChange(GamepadId, u8, f32),
+    MouseMotion(f32, f32),
+    Touch(u64, u8, Position, f32),
+}
+
+/// Mouse buttons are stored in this order, followed by the extra buttons by their number.
+const NAMED_MOUSE_BUTTONS: [MouseButton; 5] =
+    [MouseButton::Left, MouseButton::Right, MouseButton::Middle, MouseButton::Back, MouseButton::Forward];
+
+const TOUCH_PHASES: [TouchPhase; 4] =
+    [TouchPhase::Started, TouchPhase::Moved, TouchPhase::Ended, TouchPhase::Cancelled];
+
+fn mouse_button_index(button: MouseButton) -> Option<u16> {
+    match button {
+        MouseButton::Other(number) => number.checked_add(NAMED_MOUSE_BUTTONS.len() as u16),
+        named => NAMED_MOUSE_BUTTONS
+            .iter()
+            .position(|recorded| *recorded == named)
+            .map(|index| index as u16),
+    }
+}
+
+fn mouse_button_from_index(index: u16) -> MouseButton {
+    NAMED_MOUSE_BUTTONS
+        .get(index as usize)
+        .copied()
+        .unwrap_or_else(|| MouseButton::Other(index - NAMED_MOUSE_BUTTONS.len() as u16))
+}
+
+impl RecordedEvent {
+    fn from_event<C: CommandType>(event: &InputEvent<C>) -> Option<Self> {
+        let key_index = |key| SUPPORTED_KEYS.iter().position(|supported| *supported == key);
+
+        Some(match *event {
+            InputEvent::KeyPressed(key, char) => Self::KeyPressed(key_index(key)? as u16, char),
+            InputEvent::KeyReleased(key, char) => Self::KeyReleased(key_index(key)? as u16, char),
+            InputEvent::MouseButtonPressed(button) => Self::MouseButtonPressed(mouse_button_index(button)?),
+            InputEvent::MouseButtonReleased(button) => Self::MouseButtonReleased(mouse_button_index(button)?),
+            InputEvent::MouseScrollChange(delta) => Self::MouseScrollChange(delta),
+            InputEvent::MouseMotion(x, y) => Self::MouseMotion(x, y),
+            InputEvent::CursorLocPosChange(loc, pos) => Self::CursorLocPosChange(loc, pos),
+            InputEvent::CameraMoved(x, y) => Self::CameraMoved(x, y),
+            InputEvent::SetKeyBind(_)
+            | InputEvent::RemoveKeyBind(_)
+            | InputEvent::SetAxisBind(_)
+            | InputEvent::RemoveAxisBind(_)
+            | InputEvent::PushBindContext(..)
+            | InputEvent::PopBindContext => return None,
+            InputEvent::GamepadConnected(id) => Self::GamepadConnected(id),
+            InputEvent::GamepadDisconnected(id) => Self::GamepadDisconnected(id),
+            InputEvent::GamepadButtonPressed(id, button) => Self::GamepadButtonPressed(id, button as u8),
+            InputEvent::GamepadButtonReleased(id, button) => Self::GamepadButtonReleased(id, button as u8),
+            InputEvent::GamepadAxisChange(id, axis, value) => Self::GamepadAxisChange(id, axis as u8, value),
+            InputEvent::Touch(id, phase, pos, aspect_ratio) => {
+                let phase_index = TOUCH_PHASES.iter().position(|recorded| *recorded == phase)?;
+                Self::Touch(id, phase_index as u8, pos, aspect_ratio)
+            }
+        })
+    }
+
+    /// Returns None for indices that are out of range, which only happens with corrupted recordings.
+    fn to_event<C: CommandType>(self) -> Option<InputEvent<C>> {
+        let key = |index: u16| SUPPORTED_KEYS.get(index as usize).copied();
+        let gamepad_button = |index: u8| GamepadButton::ALL.get(index as usize).copied();
+        let gamepad_axis = |index: u8| GamepadAxis::ALL.get(index as usize).copied();
+
+        Some(match self {
+            Self::KeyPressed(index, char) => InputEvent::KeyPressed(key(index)?, char),
+            Self::KeyReleased(index, char) => InputEvent::KeyReleased(key(index)?, char),
+            Self::MouseButtonPressed(index) => InputEvent::MouseButtonPressed(mouse_button_from_index(index)),
+            Self::MouseButtonReleased(index) => InputEvent::MouseButtonReleased(mouse_button_from_index(index)),
+            Self::MouseScrollChange(delta) => InputEvent::MouseScrollChange(delta),
+            Self::MouseMotion(x, y) => InputEvent::MouseMotion(x, y),
+            Self::CursorLocPosChange(loc, pos) => InputEvent::CursorLocPosChange(loc, pos),
+            Self::CameraMoved(x, y) => InputEvent::CameraMoved(x, y),
+            Self::GamepadConnected(id) => InputEvent::GamepadConnected(id),
+            Self::GamepadDisconnected(id) => InputEvent::GamepadDisconnected(id),
+            Self::GamepadButtonPressed(id, index) => InputEvent::GamepadButtonPressed(id, gamepad_button(index)?),
+            Self::GamepadButtonReleased(id, index) => InputEvent::GamepadButtonReleased(id, gamepad_button(index)?),
+            Self::GamepadAxisChange(id, index, value) => InputEvent::GamepadAxisChange(id, gamepad_axis(index)?, value),
+            Self::Touch(id, index, pos, aspect_ratio) => {
+                InputEvent::Touch(id, *TOUCH_PHASES.get(index as usize)?, pos, aspect_ratio)
+            }
+        })
+    }
+}
+
+/// Records or replays the events an input state receives, one frame at a time.
+#[derive(Debug, Default)]
+pub(crate) enum InputRecorder {
+    #[default]
+    Idle,
+    Recording(InputRecording),
+    Replaying {
+        recording: InputRecording,
+        next_frame: usize,
+    },
+}
+
+impl InputRecorder {
+    /// Takes the events received during a frame handled at `now` and returns the events the input state should
+    /// handle. While replaying, received input is replaced by the recorded input, but binds are still applied.
+    pub(crate) fn process_frame<C: CommandType>(
+        &mut self,
+        events: Vec<TimedInputEvent<C>>,
+        now: Instant,
+    ) -> Vec<TimedInputEvent<C>> {
+        match self {
+            InputRecorder::Idle => events,
+            InputRecorder::Recording(recording) => {
+                let frame = events.iter().filter_map(|timed| {
+                    let age = now.duration_since(timed.time).unwrap_or_default();
+                    let age_micros = age.as_micros().min(u32::MAX as u128) as u32;
+                    Some((age_micros, RecordedEvent::from_event(&timed.event)?))
+                });
+                recording.frames.push(frame.collect());
+                events
+            }
+            InputRecorder::Replaying { recording, next_frame } => {
+                let mut events: Vec<_> = events
+                    .into_iter()
+                    .filter(|timed| timed.event.is_binding_change())
+                    .collect();
+                events.extend(
+                    recording.frames[*next_frame]
+                        .iter()
+                        .filter_map(|(age_micros, recorded)| {
+                            Some(TimedInputEvent {
+                                time: now
+                                    .checked_sub(Duration::from_micros(*age_micros as u64))
+                                    .unwrap_or(now),
+                                event: recorded.to_event()?,
+                            })
+                        }),
+                );
+
+                *next_frame += 1;
+                if *next_frame >= recording.frames.len() {
+                    *self = InputRecorder::Idle;
+                }
+                events
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bincode::{Decode, Encode};
+    use winit::keyboard::KeyCode;
+
+    use super::*;
+    use crate::input::KeyBind;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Encode, Decode)]
+    enum TestCommand {
+        Test,
+    }
+    impl CommandType for TestCommand {}
+
+    fn sent_at(time: Instant, events: Vec<InputEvent<TestCommand>>) -> Vec<TimedInputEvent<TestCommand>> {
+        events
+            .into_iter()
+            .map(|event| TimedInputEvent { time, event })
+            .collect()
+    }
+
+    fn untimed(events: Vec<TimedInputEvent<TestCommand>>) -> Vec<InputEvent<TestCommand>> {
+        events.into_iter().map(|timed| timed.event).collect()
+    }
+
+    #[test]
+    fn recorded_frames_are_replayed_in_order() {
+        let start = Instant::now();
+        let mut recorder = InputRecorder::Recording(InputRecording::default());
+        recorder.process_frame(
+            sent_at(
+                start,
+                vec![
+                    InputEvent::KeyPressed(KeyCode::KeyA, Some('a')),
+                    InputEvent::SetKeyBind(KeyBind::new(TestCommand::Test, Some(KeyCode::KeyA), None, None)),
+                    InputEvent::MouseButtonPressed(MouseButton::Back),
+                ],
+            ),
+            start + Duration::from_millis(5),
+        );
+        recorder.process_frame::<TestCommand>(vec![], start);
+        recorder.process_frame(
+            sent_at(start, vec![InputEvent::GamepadButtonPressed(1, GamepadButton::North)]),
+            start,
+        );
+        let InputRecorder::Recording(recording) = recorder else {
+            unreachable!()
+        };
+
+        // Key binds are left out
+        assert_eq!(recording.frame_count(), 3);
+        assert_eq!(recording.frames[0].len(), 2);
+
+        let bytes = ion_common::bincode::encode_to_vec(&recording, ion_common::bincode::config::standard()).unwrap();
+        let (decoded, _): (InputRecording, _) =
+            ion_common::bincode::decode_from_slice(&bytes, ion_common::bincode::config::standard()).unwrap();
+        assert_eq!(decoded, recording);
+
+        let mut recorder = InputRecorder::Replaying {
+            recording: decoded,
+            next_frame: 0,
+        };
+        // Received input is replaced, but key binds go through
+        let replay_time = start + Duration::from_secs(1);
+        let events = recorder.process_frame(
+            sent_at(
+                replay_time,
+                vec![InputEvent::KeyPressed(KeyCode::KeyB, None), InputEvent::RemoveKeyBind(TestCommand::Test)],
+            ),
+            replay_time,
+        );
+        // Replayed events were sent as long before the frame as the recorded ones
+        assert_eq!(
+            events[1]
+                .time
+                .checked_add(Duration::from_millis(5))
+                .unwrap()
+                .duration_since(replay_time),
+            Some(Duration::ZERO)
+        );
+        assert!(matches!(
+            untimed(events)[..],
+            [
+                InputEvent::RemoveKeyBind(TestCommand::Test),
+                InputEvent::KeyPressed(KeyCode::KeyA, Some('a')),
+                InputEvent::MouseButtonPressed(MouseButton::Back)
+            ]
+        ));
+        assert!(recorder.process_frame::<TestCommand>(vec![], replay_time).is_empty());
+        let events = recorder.process_frame::<TestCommand>(vec![], replay_time);
+        assert!(matches!(
+            untimed(events)[..],
+            [InputEvent::GamepadButtonPressed(1, GamepadButton::North)]
+        ));
+
+        assert!(matches!(recorder, InputRecorder::Idle));
+    }
+}
@@ -1,274 +1,479 @@
+use std::cell::{Cell, RefCell};
+use std::f32::consts::{PI, TAU};
 use std::sync::mpsc::{Receiver, Sender};
+use std::time::Duration;
 
-use ion_common::Map;
-use winit::event::MouseButton;
+use ion_common::{Instant, Map};
+use winit::event::{MouseButton, TouchPhase};
 use winit::keyboard::KeyCode;
 
 use crate::{
     core::coordinates::{Location, Position},
     gfx::renderer::render_camera::RenderCamera,
-    input::{CommandType, KeyBind},
+    input::{
+        AxisBind, AxisSource, BindResult, CommandType, KeyBind, MODIFIER_KEYS, TimedInputEvent,
+        gamepad::{GamepadAxis, GamepadButton, GamepadId, Rumble, RumbleRequest},
+        input_recording::{InputRecorder, InputRecording},
+        key_bind_config::KeyBindings,
+        key_display::{CapturedInput, is_character_key, key_display_name},
+    },
 };
 
 use super::InputEvent;
 
+/// Keys whose state is tracked. Events of other keys are ignored.
+pub(crate) const SUPPORTED_KEYS: [KeyCode; 193] = [
+    KeyCode::Backslash,
+    KeyCode::BracketLeft,
+    KeyCode::BracketRight,
+    KeyCode::Comma,
+    KeyCode::Digit0,
+    KeyCode::Digit1,
+    KeyCode::Digit2,
+    KeyCode::Digit3,
+    KeyCode::Digit4,
+    KeyCode::Digit5,
+    KeyCode::Digit6,
+    KeyCode::Digit7,
+    KeyCode::Digit8,
+    KeyCode::Digit9,
+    KeyCode::Equal,
+    KeyCode::IntlBackslash,
+    KeyCode::IntlRo,
+    KeyCode::IntlYen,
+    KeyCode::KeyA,
+    KeyCode::KeyB,
+    KeyCode::KeyC,
+    KeyCode::KeyD,
+    KeyCode::KeyE,
+    KeyCode::KeyF,
+    KeyCode::KeyG,
+    KeyCode::KeyH,
+    KeyCode::KeyI,
+    KeyCode::KeyJ,
+    KeyCode::KeyK,
+    KeyCode::KeyL,
+    KeyCode::KeyM,
+    KeyCode::KeyN,
+    KeyCode::KeyO,
+    KeyCode::KeyP,
+    KeyCode::KeyQ,
+    KeyCode::KeyR,
+    KeyCode::KeyS,
+    KeyCode::KeyT,
+    KeyCode::KeyU,
+    KeyCode::KeyV,
+    KeyCode::KeyW,
+    KeyCode::KeyX,
+    KeyCode::KeyY,
+    KeyCode::KeyZ,
+    KeyCode::Minus,
+    KeyCode::Period,
+    KeyCode::Quote,
+    KeyCode::Semicolon,
+    KeyCode::Slash,
+    KeyCode::AltLeft,
+    KeyCode::AltRight,
+    KeyCode::Backspace,
+    KeyCode::CapsLock,
+    KeyCode::ContextMenu,
+    KeyCode::ControlLeft,
+    KeyCode::ControlRight,
+    KeyCode::Enter,
+    KeyCode::SuperLeft,
+    KeyCode::SuperRight,
+    KeyCode::ShiftLeft,
+    KeyCode::ShiftRight,
+    KeyCode::Space,
+    KeyCode::Tab,
+    KeyCode::Convert,
+    KeyCode::KanaMode,
+    KeyCode::Lang1,
+    KeyCode::Lang2,
+    KeyCode::Lang3,
+    KeyCode::Lang4,
+    KeyCode::Lang5,
+    KeyCode::NonConvert,
+    KeyCode::Delete,
+    KeyCode::End,
+    KeyCode::Help,
+    KeyCode::Home,
+    KeyCode::Insert,
+    KeyCode::PageDown,
+    KeyCode::PageUp,
+    KeyCode::ArrowDown,
+    KeyCode::ArrowLeft,
+    KeyCode::ArrowRight,
+    KeyCode::ArrowUp,
+    KeyCode::NumLock,
+    KeyCode::Numpad0,
+    KeyCode::Numpad1,
+    KeyCode::Numpad2,
+    KeyCode::Numpad3,
+    KeyCode::Numpad4,
+    KeyCode::Numpad5,
+    KeyCode::Numpad6,
+    KeyCode::Numpad7,
+    KeyCode::Numpad8,
+    KeyCode::Numpad9,
+    KeyCode::NumpadAdd,
+    KeyCode::NumpadBackspace,
+    KeyCode::NumpadClear,
+    KeyCode::NumpadClearEntry,
+    KeyCode::NumpadComma,
+    KeyCode::NumpadDecimal,
+    KeyCode::NumpadDivide,
+    KeyCode::NumpadEnter,
+    KeyCode::NumpadEqual,
+    KeyCode::NumpadHash,
+    KeyCode::NumpadMemoryAdd,
+    KeyCode::NumpadMemoryClear,
+    KeyCode::NumpadMemoryRecall,
+    KeyCode::NumpadMemoryStore,
+    KeyCode::NumpadMemorySubtract,
+    KeyCode::NumpadMultiply,
+    KeyCode::NumpadParenLeft,
+    KeyCode::NumpadParenRight,
+    KeyCode::NumpadStar,
+    KeyCode::NumpadSubtract,
+    KeyCode::Escape,
+    KeyCode::Fn,
+    KeyCode::FnLock,
+    KeyCode::PrintScreen,
+    KeyCode::ScrollLock,
+    KeyCode::Pause,
+    KeyCode::BrowserBack,
+    KeyCode::BrowserFavorites,
+    KeyCode::BrowserForward,
+    KeyCode::BrowserHome,
+    KeyCode::BrowserRefresh,
+    KeyCode::BrowserSearch,
+    KeyCode::BrowserStop,
+    KeyCode::Eject,
+    KeyCode::LaunchApp1,
+    KeyCode::LaunchApp2,
+    KeyCode::LaunchMail,
+    KeyCode::MediaPlayPause,
+    KeyCode::MediaSelect,
+    KeyCode::MediaStop,
+    KeyCode::MediaTrackNext,
+    KeyCode::MediaTrackPrevious,
+    KeyCode::Power,
+    KeyCode::Sleep,
+    KeyCode::AudioVolumeDown,
+    KeyCode::AudioVolumeMute,
+    KeyCode::AudioVolumeUp,
+    KeyCode::WakeUp,
+    KeyCode::Meta,
+    KeyCode::Hyper,
+    KeyCode::Turbo,
+    KeyCode::Abort,
+    KeyCode::Resume,
+    KeyCode::Suspend,
+    KeyCode::Again,
+    KeyCode::Copy,
+    KeyCode::Cut,
+    KeyCode::Find,
+    KeyCode::Open,
+    KeyCode::Paste,
+    KeyCode::Props,
+    KeyCode::Select,
+    KeyCode::Undo,
+    KeyCode::Hiragana,
+    KeyCode::Katakana,
+    KeyCode::F1,
+    KeyCode::F2,
+    KeyCode::F3,
+    KeyCode::F4,
+    KeyCode::F5,
+    KeyCode::F6,
+    KeyCode::F7,
+    KeyCode::F8,
+    KeyCode::F9,
+    KeyCode::F10,
+    KeyCode::F11,
+    KeyCode::F12,
+    KeyCode::F13,
+    KeyCode::F14,
+    KeyCode::F15,
+    KeyCode::F16,
+    KeyCode::F17,
+    KeyCode::F18,
+    KeyCode::F19,
+    KeyCode::F20,
+    KeyCode::F21,
+    KeyCode::F22,
+    KeyCode::F23,
+    KeyCode::F24,
+    KeyCode::F25,
+    KeyCode::F26,
+    KeyCode::F27,
+    KeyCode::F28,
+    KeyCode::F29,
+    KeyCode::F30,
+    KeyCode::F31,
+    KeyCode::F32,
+    KeyCode::F33,
+    KeyCode::F34,
+    KeyCode::F35,
+];
+
+/// Default time within which consecutive presses of a mouse button count as a multi-click.
+pub const DEFAULT_MULTI_CLICK_INTERVAL: Duration = Duration::from_millis(400);
+
+/// Default time a command must be held down so that it counts as held instead of tapped.
+pub const DEFAULT_HOLD_THRESHOLD: Duration = Duration::from_millis(250);
+
+/// Timing of repeated presses while a key is held down.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyRepeat {
+    /// Time from the press to the first repeat.
+    pub delay: Duration,
+    /// Time between consecutive repeats.
+    pub interval: Duration,
+}
+
+impl Default for KeyRepeat {
+    fn default() -> Self {
+        Self {
+            delay: Duration::from_millis(500),
+            interval: Duration::from_millis(50),
+        }
+    }
+}
+
+/// Two-finger gesture on a touch screen during a frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TouchGesture {
+    /// Movement of the point between the fingers, in the units of `Position`.
+    pub pan: (f32, f32),
+    /// Ratio of the distance between the fingers to the distance on the previous frame.
+    /// Above one when the fingers spread apart, which usually zooms in.
+    pub zoom: f32,
+    /// Change of the angle between the fingers in radians. Clockwise on the screen is positive.
+    pub rotation: f32,
+}
+
+impl TouchGesture {
+    pub const NONE: Self = Self {
+        pan: (0.0, 0.0),
+        zoom: 1.0,
+        rotation: 0.0,
+    };
+}
+
+impl KeyRepeat {
+    /// Number of repeats that have happened when a key has been held down for the given time.
+    fn repeats_within(&self, held_for: Duration) -> u32 {
+        match held_for.checked_sub(self.delay) {
+            Some(after_delay) => {
+                let interval = self.interval.max(Duration::from_millis(1));
+                (after_delay.as_nanos() / interval.as_nanos()) as u32 + 1
+            }
+            None => 0,
+        }
+    }
+}
+
 pub struct InputState<C: CommandType> {
-    event_senders: [Sender<InputEvent<C>>; 2],
-    event_receiver: Receiver<InputEvent<C>>,
-    events_in_frame: Vec<InputEvent<C>>,
+    event_senders: [Sender<TimedInputEvent<C>>; 2],
+    event_receiver: Receiver<TimedInputEvent<C>>,
+    events_in_frame: Vec<TimedInputEvent<C>>,
 
     bindings: Map<C, KeyBind<C>>,
+    axis_bindings: Map<C, AxisBind<C>>,
+    bind_contexts: Vec<BindContextLayer>,
 
     key_states: Map<KeyCode, KeyState>,
+    /// Characters that character keys produce without modifiers, as learned from key presses.
+    key_chars: Map<KeyCode, char>,
     mouse_button_states: Map<MouseButton, KeyState>,
+    mouse_clicks: Map<MouseButton, ClickState>,
+    multi_click_interval: Cell<Duration>,
+    hold_threshold: Cell<Duration>,
+    key_repeat: Cell<KeyRepeat>,
+    text_repeat: Cell<KeyRepeat>,
+    reserved_keys: RefCell<Vec<KeyCode>>,
     mouse_scroll_state: f32,
+    mouse_motion_state: (f32, f32),
     cursor_location_state: Location,
     cursor_position_state: Position,
+    cursor_position_delta: (f32, f32),
+    gamepads: Map<GamepadId, GamepadState>,
+    rumble_sender: Option<Sender<RumbleRequest>>,
+    touches: Map<u64, Position>,
+    touch_aspect_ratio: f32,
+    touch_gesture: TouchGesture,
+
+    recorder: RefCell<InputRecorder>,
+    /// Time when the events of the current frame were handled. Hold times are measured against this.
+    frame_time: Instant,
+    /// Counts key and mouse button presses, so that the order of presses is known even within a frame.
+    press_count: u64,
 }
 
 impl<C: CommandType> InputState<C> {
-    pub(crate) fn new(event_receiver: Receiver<InputEvent<C>>, event_senders: [Sender<InputEvent<C>>; 2]) -> Self {
+    pub(crate) fn new(
+        event_receiver: Receiver<TimedInputEvent<C>>,
+        event_senders: [Sender<TimedInputEvent<C>>; 2],
+    ) -> Self {
         Self {
             event_senders,
             event_receiver,
             events_in_frame: Vec::new(),
 
             bindings: Map::default(),
-
-            key_states: [
-                (KeyCode::Backslash, KeyState::default()),
-                (KeyCode::BracketLeft, KeyState::default()),
-                (KeyCode::BracketRight, KeyState::default()),
-                (KeyCode::Comma, KeyState::default()),
-                (KeyCode::Digit0, KeyState::default()),
-                (KeyCode::Digit1, KeyState::default()),
-                (KeyCode::Digit2, KeyState::default()),
-                (KeyCode::Digit3, KeyState::default()),
-                (KeyCode::Digit4, KeyState::default()),
-                (KeyCode::Digit5, KeyState::default()),
-                (KeyCode::Digit6, KeyState::default()),
-                (KeyCode::Digit7, KeyState::default()),
-                (KeyCode::Digit8, KeyState::default()),
-                (KeyCode::Digit9, KeyState::default()),
-                (KeyCode::Equal, KeyState::default()),
-                (KeyCode::IntlBackslash, KeyState::default()),
-                (KeyCode::IntlRo, KeyState::default()),
-                (KeyCode::IntlYen, KeyState::default()),
-                (KeyCode::KeyA, KeyState::default()),
-                (KeyCode::KeyB, KeyState::default()),
-                (KeyCode::KeyC, KeyState::default()),
-                (KeyCode::KeyD, KeyState::default()),
-                (KeyCode::KeyE, KeyState::default()),
-                (KeyCode::KeyF, KeyState::default()),
-                (KeyCode::KeyG, KeyState::default()),
-                (KeyCode::KeyH, KeyState::default()),
-                (KeyCode::KeyI, KeyState::default()),
-                (KeyCode::KeyJ, KeyState::default()),
-                (KeyCode::KeyK, KeyState::default()),
-                (KeyCode::KeyL, KeyState::default()),
-                (KeyCode::KeyM, KeyState::default()),
-                (KeyCode::KeyN, KeyState::default()),
-                (KeyCode::KeyO, KeyState::default()),
-                (KeyCode::KeyP, KeyState::default()),
-                (KeyCode::KeyQ, KeyState::default()),
-                (KeyCode::KeyR, KeyState::default()),
-                (KeyCode::KeyS, KeyState::default()),
-                (KeyCode::KeyT, KeyState::default()),
-                (KeyCode::KeyU, KeyState::default()),
-                (KeyCode::KeyV, KeyState::default()),
-                (KeyCode::KeyW, KeyState::default()),
-                (KeyCode::KeyX, KeyState::default()),
-                (KeyCode::KeyY, KeyState::default()),
-                (KeyCode::KeyZ, KeyState::default()),
-                (KeyCode::Minus, KeyState::default()),
-                (KeyCode::Period, KeyState::default()),
-                (KeyCode::Quote, KeyState::default()),
-                (KeyCode::Semicolon, KeyState::default()),
-                (KeyCode::Slash, KeyState::default()),
-                (KeyCode::AltLeft, KeyState::default()),
-                (KeyCode::AltRight, KeyState::default()),
-                (KeyCode::Backspace, KeyState::default()),
-                (KeyCode::CapsLock, KeyState::default()),
-                (KeyCode::ContextMenu, KeyState::default()),
-                (KeyCode::ControlLeft, KeyState::default()),
-                (KeyCode::ControlRight, KeyState::default()),
-                (KeyCode::Enter, KeyState::default()),
-                (KeyCode::SuperLeft, KeyState::default()),
-                (KeyCode::SuperRight, KeyState::default()),
-                (KeyCode::ShiftLeft, KeyState::default()),
-                (KeyCode::ShiftRight, KeyState::default()),
-                (KeyCode::Space, KeyState::default()),
-                (KeyCode::Tab, KeyState::default()),
-                (KeyCode::Convert, KeyState::default()),
-                (KeyCode::KanaMode, KeyState::default()),
-                (KeyCode::Lang1, KeyState::default()),
-                (KeyCode::Lang2, KeyState::default()),
-                (KeyCode::Lang3, KeyState::default()),
-                (KeyCode::Lang4, KeyState::default()),
-                (KeyCode::Lang5, KeyState::default()),
-                (KeyCode::NonConvert, KeyState::default()),
-                (KeyCode::Delete, KeyState::default()),
-                (KeyCode::End, KeyState::default()),
-                (KeyCode::Help, KeyState::default()),
-                (KeyCode::Home, KeyState::default()),
-                (KeyCode::Insert, KeyState::default()),
-                (KeyCode::PageDown, KeyState::default()),
-                (KeyCode::PageUp, KeyState::default()),
-                (KeyCode::ArrowDown, KeyState::default()),
-                (KeyCode::ArrowLeft, KeyState::default()),
-                (KeyCode::ArrowRight, KeyState::default()),
-                (KeyCode::ArrowUp, KeyState::default()),
-                (KeyCode::NumLock, KeyState::default()),
-                (KeyCode::Numpad0, KeyState::default()),
-                (KeyCode::Numpad1, KeyState::default()),
-                (KeyCode::Numpad2, KeyState::default()),
-                (KeyCode::Numpad3, KeyState::default()),
-                (KeyCode::Numpad4, KeyState::default()),
-                (KeyCode::Numpad5, KeyState::default()),
-                (KeyCode::Numpad6, KeyState::default()),
-                (KeyCode::Numpad7, KeyState::default()),
-                (KeyCode::Numpad8, KeyState::default()),
-                (KeyCode::Numpad9, KeyState::default()),
-                (KeyCode::NumpadAdd, KeyState::default()),
-                (KeyCode::NumpadBackspace, KeyState::default()),
-                (KeyCode::NumpadClear, KeyState::default()),
-                (KeyCode::NumpadClearEntry, KeyState::default()),
-                (KeyCode::NumpadComma, KeyState::default()),
-                (KeyCode::NumpadDecimal, KeyState::default()),
-                (KeyCode::NumpadDivide, KeyState::default()),
-                (KeyCode::NumpadEnter, KeyState::default()),
-                (KeyCode::NumpadEqual, KeyState::default()),
-                (KeyCode::NumpadHash, KeyState::default()),
-                (KeyCode::NumpadMemoryAdd, KeyState::default()),
-                (KeyCode::NumpadMemoryClear, KeyState::default()),
-                (KeyCode::NumpadMemoryRecall, KeyState::default()),
-                (KeyCode::NumpadMemoryStore, KeyState::default()),
-                (KeyCode::NumpadMemorySubtract, KeyState::default()),
-                (KeyCode::NumpadMultiply, KeyState::default()),
-                (KeyCode::NumpadParenLeft, KeyState::default()),
-                (KeyCode::NumpadParenRight, KeyState::default()),
-                (KeyCode::NumpadStar, KeyState::default()),
-                (KeyCode::NumpadSubtract, KeyState::default()),
-                (KeyCode::Escape, KeyState::default()),
-                (KeyCode::Fn, KeyState::default()),
-                (KeyCode::FnLock, KeyState::default()),
-                (KeyCode::PrintScreen, KeyState::default()),
-                (KeyCode::ScrollLock, KeyState::default()),
-                (KeyCode::Pause, KeyState::default()),
-                (KeyCode::BrowserBack, KeyState::default()),
-                (KeyCode::BrowserFavorites, KeyState::default()),
-                (KeyCode::BrowserForward, KeyState::default()),
-                (KeyCode::BrowserHome, KeyState::default()),
-                (KeyCode::BrowserRefresh, KeyState::default()),
-                (KeyCode::BrowserSearch, KeyState::default()),
-                (KeyCode::BrowserStop, KeyState::default()),
-                (KeyCode::Eject, KeyState::default()),
-                (KeyCode::LaunchApp1, KeyState::default()),
-                (KeyCode::LaunchApp2, KeyState::default()),
-                (KeyCode::LaunchMail, KeyState::default()),
-                (KeyCode::MediaPlayPause, KeyState::default()),
-                (KeyCode::MediaSelect, KeyState::default()),
-                (KeyCode::MediaStop, KeyState::default()),
-                (KeyCode::MediaTrackNext, KeyState::default()),
-                (KeyCode::MediaTrackPrevious, KeyState::default()),
-                (KeyCode::Power, KeyState::default()),
-                (KeyCode::Sleep, KeyState::default()),
-                (KeyCode::AudioVolumeDown, KeyState::default()),
-                (KeyCode::AudioVolumeMute, KeyState::default()),
-                (KeyCode::AudioVolumeUp, KeyState::default()),
-                (KeyCode::WakeUp, KeyState::default()),
-                (KeyCode::Meta, KeyState::default()),
-                (KeyCode::Hyper, KeyState::default()),
-                (KeyCode::Turbo, KeyState::default()),
-                (KeyCode::Abort, KeyState::default()),
-                (KeyCode::Resume, KeyState::default()),
-                (KeyCode::Suspend, KeyState::default()),
-                (KeyCode::Again, KeyState::default()),
-                (KeyCode::Copy, KeyState::default()),
-                (KeyCode::Cut, KeyState::default()),
-                (KeyCode::Find, KeyState::default()),
-                (KeyCode::Open, KeyState::default()),
-                (KeyCode::Paste, KeyState::default()),
-                (KeyCode::Props, KeyState::default()),
-                (KeyCode::Select, KeyState::default()),
-                (KeyCode::Undo, KeyState::default()),
-                (KeyCode::Hiragana, KeyState::default()),
-                (KeyCode::Katakana, KeyState::default()),
-                (KeyCode::F1, KeyState::default()),
-                (KeyCode::F2, KeyState::default()),
-                (KeyCode::F3, KeyState::default()),
-                (KeyCode::F4, KeyState::default()),
-                (KeyCode::F5, KeyState::default()),
-                (KeyCode::F6, KeyState::default()),
-                (KeyCode::F7, KeyState::default()),
-                (KeyCode::F8, KeyState::default()),
-                (KeyCode::F9, KeyState::default()),
-                (KeyCode::F10, KeyState::default()),
-                (KeyCode::F11, KeyState::default()),
-                (KeyCode::F12, KeyState::default()),
-                (KeyCode::F13, KeyState::default()),
-                (KeyCode::F14, KeyState::default()),
-                (KeyCode::F15, KeyState::default()),
-                (KeyCode::F16, KeyState::default()),
-                (KeyCode::F17, KeyState::default()),
-                (KeyCode::F18, KeyState::default()),
-                (KeyCode::F19, KeyState::default()),
-                (KeyCode::F20, KeyState::default()),
-                (KeyCode::F21, KeyState::default()),
-                (KeyCode::F22, KeyState::default()),
-                (KeyCode::F23, KeyState::default()),
-                (KeyCode::F24, KeyState::default()),
-                (KeyCode::F25, KeyState::default()),
-                (KeyCode::F26, KeyState::default()),
-                (KeyCode::F27, KeyState::default()),
-                (KeyCode::F28, KeyState::default()),
-                (KeyCode::F29, KeyState::default()),
-                (KeyCode::F30, KeyState::default()),
-                (KeyCode::F31, KeyState::default()),
-                (KeyCode::F32, KeyState::default()),
-                (KeyCode::F33, KeyState::default()),
-                (KeyCode::F34, KeyState::default()),
-                (KeyCode::F35, KeyState::default()),
-            ]
-            .into_iter()
-            .collect(),
+            axis_bindings: Map::default(),
+            bind_contexts: Vec::new(),
+
+            key_states: SUPPORTED_KEYS
+                .into_iter()
+                .map(|key| (key, KeyState::default()))
+                .collect(),
+            key_chars: Map::default(),
             mouse_button_states: [
                 (MouseButton::Left, KeyState::default()),
                 (MouseButton::Right, KeyState::default()),
                 (MouseButton::Middle, KeyState::default()),
+                (MouseButton::Back, KeyState::default()),
+                (MouseButton::Forward, KeyState::default()),
             ]
             .into_iter()
             .collect(),
+            mouse_clicks: Map::default(),
+            multi_click_interval: Cell::new(DEFAULT_MULTI_CLICK_INTERVAL),
+            hold_threshold: Cell::new(DEFAULT_HOLD_THRESHOLD),
+            key_repeat: Cell::new(KeyRepeat::default()),
+            text_repeat: Cell::new(KeyRepeat::default()),
+            reserved_keys: RefCell::new(Vec::new()),
             mouse_scroll_state: 0.0,
+            mouse_motion_state: (0.0, 0.0),
             cursor_location_state: Location { x: 0.0, y: 0.0 },
             cursor_position_state: Position { x: 0.0, y: 0.0 },
+            cursor_position_delta: (0.0, 0.0),
+            gamepads: Map::default(),
+            rumble_sender: None,
+            touches: Map::default(),
+            touch_aspect_ratio: 1.0,
+            touch_gesture: TouchGesture::NONE,
+
+            recorder: RefCell::new(InputRecorder::Idle),
+            frame_time: Instant::now(),
+            press_count: 0,
         }
     }
 
+    pub(crate) fn with_rumble_sender(mut self, rumble_sender: Sender<RumbleRequest>) -> Self {
+        self.rumble_sender = Some(rumble_sender);
+        self
+    }
+
     fn command_state(&self, command: C) -> KeyState {
         let key_bind = self.bindings.get(&command).expect("Command must be bound to a key");
-        let key_1_state = key_bind.key_1.map(|key| self.key_state(key));
-        let key_2_state = key_bind.key_2.map(|key| self.key_state(key));
+        let key_states = key_bind.keys.iter().map(|key| self.key_state(*key));
         let button_state = key_bind.mouse_button.map(|button| self.mouse_button_state(button));
 
-        [key_1_state, key_2_state, button_state]
-            .into_iter()
-            .filter_map(|state| state)
+        let combination_state = key_states
+            .chain(button_state)
             .copied()
             .reduce(|acc, state| acc.combine(&state))
+            .map(|state| {
+                if self.modifiers_allow(key_bind)
+                    && !self.is_shadowed(command, |other| other.shares_input_with(key_bind))
+                {
+                    state
+                } else {
+                    KeyState::default()
+                }
+            });
+        let gamepad_state = key_bind
+            .gamepad_button
+            .filter(|_| !self.is_shadowed(command, |other| other.shares_input_with(key_bind)))
+            .map(|button| self.gamepad_button_state(button));
+
+        [combination_state, gamepad_state]
+            .into_iter()
+            .flatten()
+            .reduce(|acc, state| acc.either(&state))
             .expect("At least one binding must exist")
     }
 
+    /// Whether the command is disabled by the bind context stack. Commands are disabled if their context is not in
+    /// the stack, or if a context above it is exclusive or has a bind that shares input with the command.
+    /// All commands are enabled while the stack is empty.
+    fn is_shadowed(&self, command: C, shares_input: impl Fn(&KeyBind<C>) -> bool) -> bool {
+        if self.bind_contexts.is_empty() {
+            return false;
+        }
+
+        let context = command.bind_context();
+        let Some(index) = self.bind_contexts.iter().rposition(|layer| layer.context == context) else {
+            return true;
+        };
+
+        self.bind_contexts[index + 1..].iter().any(|layer| {
+            layer.exclusive
+                || self
+                    .bindings
+                    .values()
+                    .any(|bind| bind.command.bind_context() == layer.context && shares_input(bind))
+        })
+    }
+
+    /// Whether the held modifiers satisfy the modifier rules of the bind.
+    fn modifiers_allow(&self, key_bind: &KeyBind<C>) -> bool {
+        if key_bind.exact_modifiers
+            && MODIFIER_KEYS
+                .iter()
+                .any(|key| !key_bind.keys.contains(key) && self.key_state(*key).active)
+        {
+            return false;
+        }
+
+        if key_bind.modifiers_first {
+            let trigger_state = match key_bind.mouse_button {
+                Some(button) => Some(self.mouse_button_state(button)),
+                None => key_bind.trigger_key().map(|key| self.key_state(key)),
+            };
+            if let Some(trigger_state) = trigger_state {
+                return key_bind
+                    .modifier_keys()
+                    .all(|key| self.key_state(key).pressed_at < trigger_state.pressed_at);
+            }
+        }
+
+        true
+    }
+
+    /// State of the button combined over all connected gamepads.
+    fn gamepad_button_state(&self, button: GamepadButton) -> KeyState {
+        self.gamepads
+            .values()
+            .map(|gamepad| gamepad.buttons[button as usize])
+            .reduce(|acc, state| acc.either(&state))
+            .unwrap_or_default()
+    }
+
     fn key_state(&self, key: KeyCode) -> &KeyState {
         self.key_states
             .get(&key)
             .unwrap_or_else(|| panic!("Unsupported key: {key:?}"))
     }
 
+    /// Extra buttons are tracked from their first press on, so unknown buttons are released.
     fn mouse_button_state(&self, button: MouseButton) -> &KeyState {
-        self.mouse_button_states
-            .get(&button)
-            .unwrap_or_else(|| panic!("Unsupported button: {button:?}"))
+        const RELEASED: KeyState = KeyState {
+            active: false,
+            active_for: 0,
+            active_char: None,
+            just_pressed: false,
+            just_released: false,
+            pressed_at: 0,
+            pressed_time: None,
+            held_for: Duration::ZERO,
+            previous_held_for: Duration::ZERO,
+        };
+        self.mouse_button_states.get(&button).unwrap_or(&RELEASED)
     }
 
     pub fn is_command_active(&self, command: C) -> bool {
@@ -284,6 +489,41 @@ impl<C: CommandType> InputState<C> {
         self.command_state(command).just_released
     }
 
+    /// Whether the command was released during this frame after being held for less than the hold threshold.
+    pub fn is_command_tapped(&self, command: C) -> bool {
+        let key_state = self.command_state(command);
+        key_state.just_released && key_state.held_for < self.hold_threshold.get()
+    }
+
+    /// Whether the command has been held down for at least the hold threshold.
+    pub fn is_command_held(&self, command: C) -> bool {
+        let key_state = self.command_state(command);
+        key_state.active && key_state.held_for >= self.hold_threshold.get()
+    }
+
+    /// Whether the command was released during this frame after being held for at least the hold threshold.
+    pub fn is_command_released_after_hold(&self, command: C) -> bool {
+        let key_state = self.command_state(command);
+        key_state.just_released && key_state.held_for >= self.hold_threshold.get()
+    }
+
+    /// How long the command has been held down, or how long it was held if it was released during this frame.
+    /// Zero for commands that are not active.
+    pub fn command_held_for(&self, command: C) -> Duration {
+        let key_state = self.command_state(command);
+        if key_state.active || key_state.just_released {
+            key_state.held_for
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    /// Sets the time a command must be held down so that it counts as held instead of tapped.
+    /// Applies only to this input state.
+    pub fn set_hold_threshold(&self, threshold: Duration) {
+        self.hold_threshold.set(threshold);
+    }
+
     pub fn is_key_active(&self, key: KeyCode) -> bool {
         let key_state = self.key_state(key);
         key_state.active || (key_state.just_pressed && key_state.just_released)
@@ -297,8 +537,65 @@ impl<C: CommandType> InputState<C> {
         self.key_state(key).just_released
     }
 
+    /// Whether the key repeated during this frame, according to the key repeat timing.
     pub fn is_key_repeating(&self, key: KeyCode) -> bool {
-        self.key_state(key).repeating()
+        self.key_state(key).repeats(self.key_repeat.get()) > 0
+    }
+
+    /// Sets the repeat timing of `is_key_repeating`. Applies only to this input state.
+    pub fn set_key_repeat(&self, repeat: KeyRepeat) {
+        self.key_repeat.set(repeat);
+    }
+
+    /// Sets the repeat timing of the characters of held keys in `text_input`, separately from `is_key_repeating`.
+    /// Applies only to this input state.
+    pub fn set_text_repeat(&self, repeat: KeyRepeat) {
+        self.text_repeat.set(repeat);
+    }
+
+    /// Sets the keys that the game handles itself, for example to open a console, so that they can't be bound to
+    /// commands or captured for binding. No keys are reserved by default. Applies only to this input state.
+    pub fn set_reserved_keys(&self, keys: &[KeyCode]) {
+        *self.reserved_keys.borrow_mut() = keys.to_vec();
+    }
+
+    fn find_reserved_key(&self, mut keys: impl Iterator<Item = KeyCode>) -> Option<KeyCode> {
+        let reserved_keys = self.reserved_keys.borrow();
+        keys.find(|key| reserved_keys.contains(key))
+    }
+
+    /// Name of the key for showing to players. Character keys are named by the character they produce on the
+    /// keyboard layout of the player, for example "Ö", once the key has been pressed. Until then, and for other keys,
+    /// the name is the same as `key_display::key_display_name`.
+    pub fn key_display_name(&self, key: KeyCode) -> String {
+        match self.key_chars.get(&key) {
+            Some(char) => char.to_uppercase().collect(),
+            None => key_display_name(key),
+        }
+    }
+
+    /// First key or button pressed during this frame, for rebinding screens that bind whatever is pressed next.
+    /// Modifier keys are not captured alone, but held modifiers are included with the captured key.
+    /// Reserved keys are skipped, since they can't be bound.
+    pub fn capture_input(&self) -> Option<CapturedInput> {
+        self.events_in_frame.iter().find_map(|timed| match timed.event {
+            InputEvent::KeyPressed(key, _)
+                if !MODIFIER_KEYS.contains(&key) && !self.reserved_keys.borrow().contains(&key) =>
+            {
+                let mut keys: Vec<_> = MODIFIER_KEYS
+                    .into_iter()
+                    .filter(|key| self.is_key_active(*key))
+                    .collect();
+                keys.sort_by_key(|key| self.key_state(*key).pressed_at);
+                keys.push(key);
+                Some(CapturedInput::Keys(keys))
+            }
+            InputEvent::MouseButtonPressed(button) => Some(CapturedInput::MouseButton(button)),
+            InputEvent::GamepadButtonPressed(id, button) if self.gamepads.contains_key(&id) => {
+                Some(CapturedInput::GamepadButton(button))
+            }
+            _ => None,
+        })
     }
 
     pub fn is_button_active(&self, button: MouseButton) -> bool {
@@ -314,49 +611,219 @@ impl<C: CommandType> InputState<C> {
         self.mouse_button_state(button).just_released
     }
 
+    /// Whether the button was pressed during this frame as the second click of a double click.
+    pub fn is_button_double_clicked(&self, button: MouseButton) -> bool {
+        self.button_click_count(button) == 2
+    }
+
+    /// Number of consecutive clicks of the button, if it was pressed during this frame, otherwise zero.
+    /// Presses count as consecutive if each follows the previous one within the multi-click interval.
+    pub fn button_click_count(&self, button: MouseButton) -> u32 {
+        match self.mouse_clicks.get(&button) {
+            Some(clicks) if self.mouse_button_state(button).just_pressed => clicks.count,
+            _ => 0,
+        }
+    }
+
+    /// Sets the time within which consecutive presses of a mouse button count as a multi-click.
+    /// Applies only to this input state.
+    pub fn set_multi_click_interval(&self, interval: Duration) {
+        self.multi_click_interval.set(interval);
+    }
+
+    fn register_click(&mut self, button: MouseButton, now: Instant) {
+        let interval = self.multi_click_interval.get();
+        let clicks = self.mouse_clicks.entry(button).or_insert(ClickState {
+            count: 0,
+            last_pressed: now,
+        });
+
+        let consecutive = clicks.count > 0 && now.duration_since(clicks.last_pressed).is_some_and(|d| d <= interval);
+        clicks.count = if consecutive { clicks.count + 1 } else { 1 };
+        clicks.last_pressed = now;
+    }
+
+    /// Ids of the connected gamepads, in ascending order.
+    pub fn connected_gamepads(&self) -> Vec<GamepadId> {
+        let mut ids: Vec<_> = self.gamepads.keys().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Gamepads connected during this frame.
+    pub fn just_connected_gamepads(&self) -> Vec<GamepadId> {
+        self.events_in_frame
+            .iter()
+            .filter_map(|timed| match &timed.event {
+                InputEvent::GamepadConnected(id) => Some(*id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Gamepads disconnected during this frame.
+    pub fn just_disconnected_gamepads(&self) -> Vec<GamepadId> {
+        self.events_in_frame
+            .iter()
+            .filter_map(|timed| match &timed.event {
+                InputEvent::GamepadDisconnected(id) => Some(*id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Whether the button is held on any of the connected gamepads.
+    pub fn is_gamepad_button_active(&self, button: GamepadButton) -> bool {
+        let button_state = self.gamepad_button_state(button);
+        button_state.active || (button_state.just_pressed && button_state.just_released)
+    }
+
+    pub fn is_gamepad_button_just_pressed(&self, button: GamepadButton) -> bool {
+        self.gamepad_button_state(button).just_pressed
+    }
+
+    pub fn is_gamepad_button_just_released(&self, button: GamepadButton) -> bool {
+        self.gamepad_button_state(button).just_released
+    }
+
+    /// Axis value of the gamepad whose stick is furthest from the center. Zero if no gamepads are connected.
+    pub fn gamepad_axis(&self, axis: GamepadAxis) -> f32 {
+        self.gamepads
+            .values()
+            .map(|gamepad| gamepad.axes[axis as usize])
+            .fold(0.0, |acc, value| if value.abs() > acc.abs() { value } else { acc })
+    }
+
+    /// Axis value of a single gamepad. Zero if the gamepad is not connected.
+    pub fn gamepad_axis_of(&self, gamepad: GamepadId, axis: GamepadAxis) -> f32 {
+        self.gamepads
+            .get(&gamepad)
+            .map(|gamepad| gamepad.axes[axis as usize])
+            .unwrap_or(0.0)
+    }
+
+    /// Plays the steps of the rumble pattern one after another on the gamepad, replacing any pattern that is playing.
+    /// Does nothing on platforms and gamepads that don't support rumble.
+    pub fn rumble(&self, gamepad: GamepadId, pattern: Vec<Rumble>) {
+        if let Some(sender) = &self.rumble_sender {
+            sender.send((gamepad, pattern)).unwrap();
+        }
+    }
+
+    pub fn stop_rumble(&self, gamepad: GamepadId) {
+        self.rumble(gamepad, Vec::new());
+    }
+
+    /// Value of the axis command in range -1.0..1.0, from the source of its binding that is furthest from zero.
+    pub fn axis_value(&self, command: C) -> f32 {
+        let axis_bind = self
+            .axis_bindings
+            .get(&command)
+            .expect("Command must be bound to an axis");
+        let axis_keys: Vec<_> = axis_bind.keys().collect();
+        if self.is_shadowed(command, |bind| bind.keys.iter().any(|key| axis_keys.contains(key))) {
+            return 0.0;
+        }
+
+        let value = axis_bind
+            .sources
+            .iter()
+            .map(|source| match *source {
+                AxisSource::Keys(negative, positive) => {
+                    let pressed = |key| if self.is_key_active(key) { 1.0 } else { 0.0 };
+                    pressed(positive) - pressed(negative)
+                }
+                AxisSource::MouseX(sensitivity) => self.cursor_position_delta.0 * sensitivity,
+                AxisSource::MouseY(sensitivity) => self.cursor_position_delta.1 * sensitivity,
+                AxisSource::Scroll(sensitivity) => self.mouse_scroll_state * sensitivity,
+                AxisSource::GamepadStick(axis) => self.gamepad_axis(axis),
+            })
+            .fold(0.0_f32, |acc, value| if value.abs() > acc.abs() { value } else { acc });
+        value.clamp(-1.0, 1.0)
+    }
+
     pub fn mouse_scroll_delta(&self) -> f32 {
         self.mouse_scroll_state
     }
 
+    /// Relative motion of the mouse during the frame, in physical pixels without cursor acceleration.
+    /// Unlike the cursor location, this keeps changing when the cursor is locked or against the window border.
+    pub fn mouse_motion(&self) -> (f32, f32) {
+        self.mouse_motion_state
+    }
+
+    /// Pinch, rotate and pan of two fingers during this frame. Gestures are detected from the two touches with the
+    /// lowest ids, and are reported only while the same two fingers stay on the screen.
+    pub fn touch_gesture(&self) -> TouchGesture {
+        self.touch_gesture
+    }
+
+    /// Number of fingers on the screen.
+    pub fn touch_count(&self) -> usize {
+        self.touches.len()
+    }
+
+    /// Ids, center, distance and angle of the two touches with the lowest ids. Measured with the aspect ratio of the
+    /// window, so that the distance and angle don't depend on the shape of the window.
+    fn two_finger_pose(&self) -> Option<([u64; 2], Position, f32, f32)> {
+        let mut ids: Vec<_> = self.touches.keys().copied().collect();
+        ids.sort_unstable();
+        let [first, second] = *ids.get(..2)? else {
+            return None;
+        };
+
+        let (a, b) = (self.touches[&first], self.touches[&second]);
+        let center = Position::new((a.x + b.x) / 2.0, (a.y + b.y) / 2.0);
+        let dx = (b.x - a.x) * self.touch_aspect_ratio;
+        let dy = b.y - a.y;
+        Some(([first, second], center, dx.hypot(dy), dy.atan2(dx)))
+    }
+
+    /// Events handled during this frame, in the order they were sent. Events sent by the game, like key binds, are
+    /// included too. Each event has the time it was sent, so timing within a frame can be examined.
+    pub fn frame_events(&self) -> &[TimedInputEvent<C>] {
+        &self.events_in_frame
+    }
+
     pub fn cursor_location(&self) -> Location {
         self.cursor_location_state
     }
 
     pub fn text_input(&self) -> Option<String> {
         // Collects all char inputs to string, but only allocates if necessary
-        let mut string: Option<String> = self.events_in_frame.iter().fold(None, |prev, event| match event {
-            InputEvent::KeyPressed(key_code, char) => {
-                if let Some(char) = char {
-                    match prev {
-                        Some(mut string) => {
-                            string.push(*char);
-                            Some(string)
+        let mut string: Option<String> = self
+            .events_in_frame
+            .iter()
+            .fold(None, |prev, timed| match &timed.event {
+                InputEvent::KeyPressed(key_code, char) => {
+                    if let Some(char) = char {
+                        match prev {
+                            Some(mut string) => {
+                                string.push(*char);
+                                Some(string)
+                            }
+                            None => Some(String::from(*char)),
                         }
-                        None => Some(String::from(*char)),
-                    }
-                } else if *key_code == KeyCode::Space {
-                    match prev {
-                        Some(mut string) => {
-                            string.push(' ');
-                            Some(string)
+                    } else if *key_code == KeyCode::Space {
+                        match prev {
+                            Some(mut string) => {
+                                string.push(' ');
+                                Some(string)
+                            }
+                            None => Some(String::from(' ')),
                         }
-                        None => Some(String::from(' ')),
+                    } else {
+                        prev
                     }
-                } else {
-                    prev
                 }
-            }
-            _ => prev,
-        });
+                _ => prev,
+            });
 
+        let text_repeat = self.text_repeat.get();
         for key_state in self.key_states.values() {
-            if key_state.active && key_state.active_for > 60 && key_state.active_for % 4 == 0 {
-                if let Some(char) = key_state.active_char {
-                    if let Some(string) = &mut string {
-                        string.push(char);
-                    } else {
-                        string = Some(String::from(char));
-                    }
+            if let Some(char) = key_state.active_char {
+                for _ in 0..key_state.repeats(text_repeat) {
+                    string.get_or_insert_with(String::new).push(char);
                 }
             }
         }
@@ -364,15 +831,111 @@ impl<C: CommandType> InputState<C> {
         string
     }
 
-    pub fn set_key_bind(&self, key_bind: KeyBind<C>) {
+    /// Binds a command to the given input, unless it conflicts with bindings of other commands in the same context
+    /// or uses a reserved key. Conflicts are checked against bindings that have been applied so far;
+    /// binds set during the current frame take effect on the next frame.
+    pub fn set_key_bind(&self, key_bind: KeyBind<C>) -> BindResult<C> {
+        if let Some(key) = self.find_reserved_key(key_bind.keys().iter().copied()) {
+            return BindResult::Reserved(key);
+        }
+
+        let conflicts = self.key_bind_conflicts(&key_bind);
+        if !conflicts.is_empty() {
+            return BindResult::Conflicts(conflicts);
+        }
+
+        self.send_key_bind(key_bind);
+        BindResult::Bound
+    }
+
+    /// Binds a command to the given input, removing the bindings of conflicting commands.
+    /// Reserved keys still can't be bound.
+    pub fn force_key_bind(&self, key_bind: KeyBind<C>) -> BindResult<C> {
+        if let Some(key) = self.find_reserved_key(key_bind.keys().iter().copied()) {
+            return BindResult::Reserved(key);
+        }
+
+        for command in self.key_bind_conflicts(&key_bind) {
+            self.remove_key_bind(command);
+        }
+
+        self.send_key_bind(key_bind);
+        BindResult::Bound
+    }
+
+    /// Returns the commands, other than the bound command itself, whose bindings collide with the given one.
+    pub fn key_bind_conflicts(&self, key_bind: &KeyBind<C>) -> Vec<C> {
+        self.bindings
+            .values()
+            .filter(|existing| existing.command != key_bind.command && existing.collides_with(key_bind))
+            .map(|existing| existing.command)
+            .collect()
+    }
+
+    fn send_key_bind(&self, key_bind: KeyBind<C>) {
         assert!(
-            key_bind.key_1.is_some() || key_bind.key_2.is_some() || key_bind.mouse_button.is_some(),
-            "At least one key or mouse button must be bound"
+            key_bind.has_keyboard_or_mouse() || key_bind.gamepad_button.is_some(),
+            "At least one key, mouse button or gamepad button must be bound"
         );
 
-        let event = InputEvent::SetKeyBind(key_bind);
-        for sender in self.event_senders.iter() {
-            sender.send(event.clone()).unwrap();
+        self.send_event(InputEvent::SetKeyBind(key_bind));
+    }
+
+    /// Binds an axis command to the given sources, replacing its previous binding.
+    /// Axes don't conflict with each other or with key binds, but reserved keys still can't be bound.
+    pub fn set_axis_bind(&self, axis_bind: AxisBind<C>) -> BindResult<C> {
+        if let Some(key) = self.find_reserved_key(axis_bind.keys()) {
+            return BindResult::Reserved(key);
+        }
+
+        self.send_event(InputEvent::SetAxisBind(axis_bind));
+        BindResult::Bound
+    }
+
+    pub fn check_axis_bind(&self, command: C) -> Option<AxisBind<C>> {
+        self.axis_bindings.get(&command).cloned()
+    }
+
+    pub fn remove_axis_bind(&self, command: C) {
+        self.send_event(InputEvent::RemoveAxisBind(command));
+    }
+
+    /// Activates a bind context on top of the currently active ones. Commands of lower contexts stay active,
+    /// unless a bind of this context uses the same input. Takes effect on the next frame.
+    ///
+    /// While no contexts have been pushed, commands of all contexts are active.
+    pub fn push_bind_context(&self, context: u32) {
+        self.send_event(InputEvent::PushBindContext(context, false));
+    }
+
+    /// Activates a bind context that disables the commands of all lower contexts, for example for text entry.
+    /// Takes effect on the next frame.
+    pub fn push_exclusive_bind_context(&self, context: u32) {
+        self.send_event(InputEvent::PushBindContext(context, true));
+    }
+
+    /// Deactivates the most recently pushed bind context. Takes effect on the next frame.
+    pub fn pop_bind_context(&self) {
+        self.send_event(InputEvent::PopBindContext);
+    }
+
+    /// Active bind contexts, from the bottom of the stack to the top.
+    pub fn bind_contexts(&self) -> Vec<u32> {
+        self.bind_contexts.iter().map(|layer| layer.context).collect()
+    }
+
+    /// Key binds of all bound commands. Binds set during the current frame are included from the next frame on.
+    pub fn key_bindings(&self) -> KeyBindings<C> {
+        KeyBindings {
+            binds: self.bindings.values().cloned().collect(),
+        }
+    }
+
+    /// Binds every command of the bindings, for example after importing them from a config.
+    /// Conflicting binds of other commands are removed, and binds that use reserved keys are skipped.
+    pub fn set_key_bindings(&self, bindings: KeyBindings<C>) {
+        for key_bind in bindings.binds {
+            self.force_key_bind(key_bind);
         }
     }
 
@@ -381,24 +944,98 @@ impl<C: CommandType> InputState<C> {
     }
 
     pub fn remove_key_bind(&self, command: C) {
-        let event = InputEvent::RemoveKeyBind(command);
-        for sender in self.event_senders.iter() {
-            sender.send(event.clone()).unwrap();
+        self.send_event(InputEvent::RemoveKeyBind(command));
+    }
+
+    /// Starts recording the input events of this state from the next frame on, replacing any ongoing recording
+    /// or replay. Recording the universe input state captures the input of each universe frame.
+    pub fn start_input_recording(&self) {
+        *self.recorder.borrow_mut() = InputRecorder::Recording(InputRecording::default());
+    }
+
+    /// Stops the recording and returns it, or None if nothing was being recorded.
+    pub fn stop_input_recording(&self) -> Option<InputRecording> {
+        match self.recorder.take() {
+            InputRecorder::Recording(recording) => Some(recording),
+            other => {
+                self.recorder.replace(other);
+                None
+            }
         }
     }
 
-    pub(crate) fn handle_camera_movement(&mut self, camera: &RenderCamera) {
-        let event = InputEvent::CameraMoved(camera.last_real_change_x(), camera.last_real_change_y());
+    /// Replays a recording from the next frame on, one recorded frame per frame.
+    /// Input from the window is ignored until the replay has finished.
+    pub fn replay_input_recording(&self, recording: InputRecording) {
+        if recording.frame_count() > 0 {
+            *self.recorder.borrow_mut() = InputRecorder::Replaying {
+                recording,
+                next_frame: 0,
+            };
+        }
+    }
+
+    pub fn is_replaying_input(&self) -> bool {
+        matches!(*self.recorder.borrow(), InputRecorder::Replaying { .. })
+    }
+
+    /// Sends a synthesized event to both input states, as if it came from the window.
+    pub(crate) fn send_event(&self, event: InputEvent<C>) {
+        let event = TimedInputEvent::from(event);
         for sender in self.event_senders.iter() {
             sender.send(event.clone()).unwrap();
         }
     }
 
+    pub(crate) fn handle_camera_movement(&mut self, camera: &RenderCamera) {
+        self.send_event(InputEvent::CameraMoved(
+            camera.last_real_change_x(),
+            camera.last_real_change_y(),
+        ));
+    }
+
     pub(crate) fn handle_received_input_events(&mut self) {
-        let events: Vec<_> = self.event_receiver.try_iter().collect();
+        self.handle_received_input_events_at(Instant::now());
+    }
+
+    fn handle_received_input_events_at(&mut self, now: Instant) {
+        self.frame_time = now;
+        let pose_before = self.two_finger_pose();
+        let events = self
+            .recorder
+            .get_mut()
+            .process_frame(self.event_receiver.try_iter().collect(), now);
         for event in events {
             self.handle_input_event(event);
         }
+
+        self.touch_gesture = match (pose_before, self.two_finger_pose()) {
+            (Some((ids, center, distance, angle)), Some((new_ids, new_center, new_distance, new_angle)))
+                if ids == new_ids && distance > 0.0 =>
+            {
+                let rotation = new_angle - angle;
+                TouchGesture {
+                    pan: (new_center.x - center.x, new_center.y - center.y),
+                    zoom: new_distance / distance,
+                    rotation: (rotation + PI).rem_euclid(TAU) - PI,
+                }
+            }
+            _ => TouchGesture::NONE,
+        };
+
+        let gamepad_button_states = self
+            .gamepads
+            .values_mut()
+            .flat_map(|gamepad| gamepad.buttons.iter_mut());
+        for state in self
+            .key_states
+            .values_mut()
+            .chain(self.mouse_button_states.values_mut())
+            .chain(gamepad_button_states)
+            .filter(|state| state.active)
+        {
+            state.update_held_for(now);
+        }
     }
 
     pub(crate) fn clear_one_frame_statuses(&mut self) {
@@ -423,53 +1060,71 @@ impl<C: CommandType> InputState<C> {
             }
         }
 
+        for gamepad in self.gamepads.values_mut() {
+            for button_state in &mut gamepad.buttons {
+                button_state.just_pressed = false;
+                button_state.just_released = false;
+
+                if button_state.active {
+                    button_state.active_for += 1;
+                } else {
+                    button_state.active_for = 0;
+                }
+            }
+        }
+
         self.events_in_frame.clear();
         self.mouse_scroll_state = 0.0;
+        self.mouse_motion_state = (0.0, 0.0);
+        self.cursor_position_delta = (0.0, 0.0);
     }
 
-    fn handle_input_event(&mut self, input_event: InputEvent<C>) {
-        self.events_in_frame.push(input_event.clone());
-        match input_event {
+    fn handle_input_event(&mut self, timed_event: TimedInputEvent<C>) {
+        self.events_in_frame.push(timed_event.clone());
+        match timed_event.event {
             InputEvent::KeyPressed(key, char) => {
+                // Characters typed with modifiers are not the ones printed on the key
+                if let Some(char) = char
+                    && is_character_key(key)
+                    && !MODIFIER_KEYS.iter().any(|modifier| self.key_state(*modifier).active)
+                {
+                    self.key_chars.insert(key, char);
+                }
+
+                self.press_count += 1;
                 self.key_states.entry(key).and_modify(|state| {
-                    state.just_pressed = true;
-                    state.active = true;
+                    state.press(self.frame_time);
                     state.active_char = char;
+                    state.pressed_at = self.press_count;
                 });
             }
             InputEvent::KeyReleased(key, char) => {
                 self.key_states.entry(key).and_modify(|state| {
-                    state.just_released = true;
-                    state.active = false;
+                    state.release(self.frame_time);
                     state.active_char = char;
                 });
             }
             InputEvent::MouseButtonPressed(mouse_button) => {
-                match mouse_button {
-                    MouseButton::Other(_) => {}
-                    normal_button => {
-                        self.mouse_button_states.entry(normal_button).and_modify(|state| {
-                            state.just_pressed = true;
-                            state.active = true;
-                        });
-                    }
-                };
+                self.register_click(mouse_button, Instant::now());
+                self.press_count += 1;
+                let state = self.mouse_button_states.entry(mouse_button).or_default();
+                state.press(self.frame_time);
+                state.pressed_at = self.press_count;
             }
             InputEvent::MouseButtonReleased(mouse_button) => {
-                match mouse_button {
-                    MouseButton::Other(_) => {}
-                    normal_button => {
-                        self.mouse_button_states.entry(normal_button).and_modify(|state| {
-                            state.just_released = true;
-                            state.active = false;
-                        });
-                    }
-                };
+                let state = self.mouse_button_states.entry(mouse_button).or_default();
+                state.release(self.frame_time);
             }
             InputEvent::MouseScrollChange(delta) => {
                 self.mouse_scroll_state = delta;
             }
+            InputEvent::MouseMotion(x, y) => {
+                self.mouse_motion_state.0 += x;
+                self.mouse_motion_state.1 += y;
+            }
             InputEvent::CursorLocPosChange(loc, pos) => {
+                self.cursor_position_delta.0 += pos.x - self.cursor_position_state.x;
+                self.cursor_position_delta.1 += pos.y - self.cursor_position_state.y;
                 self.cursor_location_state = loc;
                 self.cursor_position_state = pos;
             }
@@ -483,6 +1138,50 @@ impl<C: CommandType> InputState<C> {
             InputEvent::RemoveKeyBind(command) => {
                 self.bindings.remove(&command);
             }
+            InputEvent::SetAxisBind(axis_bind) => {
+                self.axis_bindings.insert(axis_bind.command, axis_bind);
+            }
+            InputEvent::RemoveAxisBind(command) => {
+                self.axis_bindings.remove(&command);
+            }
+            InputEvent::PushBindContext(context, exclusive) => {
+                self.bind_contexts.push(BindContextLayer { context, exclusive });
+            }
+            InputEvent::PopBindContext => {
+                self.bind_contexts.pop();
+            }
+            InputEvent::GamepadConnected(id) => {
+                self.gamepads.insert(id, GamepadState::default());
+            }
+            InputEvent::GamepadDisconnected(id) => {
+                self.gamepads.remove(&id);
+            }
+            InputEvent::GamepadButtonPressed(id, button) => {
+                if let Some(gamepad) = self.gamepads.get_mut(&id) {
+                    gamepad.buttons[button as usize].press(self.frame_time);
+                }
+            }
+            InputEvent::GamepadButtonReleased(id, button) => {
+                if let Some(gamepad) = self.gamepads.get_mut(&id) {
+                    gamepad.buttons[button as usize].release(self.frame_time);
+                }
+            }
+            InputEvent::GamepadAxisChange(id, axis, value) => {
+                if let Some(gamepad) = self.gamepads.get_mut(&id) {
+                    gamepad.axes[axis as usize] = value;
+                }
+            }
+            InputEvent::Touch(id, phase, pos, aspect_ratio) => {
+                self.touch_aspect_ratio = aspect_ratio;
+                match phase {
+                    TouchPhase::Started | TouchPhase::Moved => {
+                        self.touches.insert(id, pos);
+                    }
+                    TouchPhase::Ended | TouchPhase::Cancelled => {
+                        self.touches.remove(&id);
+                    }
+                }
+            }
         }
     }
 }
@@ -494,11 +1193,44 @@ struct KeyState {
     pub active_char: Option<char>,
     pub just_pressed: bool,
     pub just_released: bool,
+    /// Press count of the input state when this was last pressed.
+    pub pressed_at: u64,
+    pub pressed_time: Option<Instant>,
+    /// Time held down so far, or the total time held if released.
+    pub held_for: Duration,
+    /// Time held down as of the previous frame.
+    pub previous_held_for: Duration,
 }
 
 impl KeyState {
-    pub fn repeating(&self) -> bool {
-        self.active && self.active_for > 60 && self.active_for % 4 == 0
+    pub fn press(&mut self, now: Instant) {
+        self.just_pressed = true;
+        self.active = true;
+        self.pressed_time = Some(now);
+        self.held_for = Duration::ZERO;
+        self.previous_held_for = Duration::ZERO;
+    }
+
+    pub fn release(&mut self, now: Instant) {
+        self.just_released = true;
+        self.active = false;
+        self.update_held_for(now);
+    }
+
+    fn update_held_for(&mut self, now: Instant) {
+        if let Some(pressed_time) = self.pressed_time {
+            self.previous_held_for = self.held_for;
+            self.held_for = now.duration_since(pressed_time).unwrap_or_default();
+        }
+    }
+
+    /// Number of times the key repeated during this frame.
+    pub fn repeats(&self, repeat: KeyRepeat) -> u32 {
+        if self.active {
+            repeat.repeats_within(self.held_for) - repeat.repeats_within(self.previous_held_for)
+        } else {
+            0
+        }
     }
 
     pub fn combine(&self, other: &KeyState) -> KeyState {
@@ -509,22 +1241,71 @@ impl KeyState {
             active_char: None,
             just_pressed: active && (self.just_pressed || other.just_pressed),
             just_released: !active && (self.just_released || other.just_released),
+            pressed_at: self.pressed_at.max(other.pressed_at),
+            pressed_time: None,
+            // Chords are held for as long as all of their inputs are
+            held_for: self.held_for.min(other.held_for),
+            previous_held_for: self.previous_held_for.min(other.previous_held_for),
+        }
+    }
+
+    /// State of alternative inputs, where any one of them activates the command.
+    pub fn either(&self, other: &KeyState) -> KeyState {
+        let active = self.active || other.active;
+        KeyState {
+            active,
+            active_for: self.active_for.max(other.active_for),
+            active_char: None,
+            just_pressed: self.just_pressed || other.just_pressed,
+            just_released: !active && (self.just_released || other.just_released),
+            pressed_at: self.pressed_at.max(other.pressed_at),
+            pressed_time: None,
+            held_for: match (self.active, other.active) {
+                (true, false) => self.held_for,
+                (false, true) => other.held_for,
+                _ => self.held_for.max(other.held_for),
+            },
+            previous_held_for: match (self.active, other.active) {
+                (true, false) => self.previous_held_for,
+                (false, true) => other.previous_held_for,
+                _ => self.previous_held_for.max(other.previous_held_for),
+            },
         }
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+struct ClickState {
+    count: u32,
+    last_pressed: Instant,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BindContextLayer {
+    context: u32,
+    exclusive: bool,
+}
+
+#[derive(Debug, Default)]
+struct GamepadState {
+    buttons: [KeyState; GamepadButton::ALL.len()],
+    axes: [f32; GamepadAxis::ALL.len()],
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::mpsc;
+    use std::time::Duration;
 
     use bincode::{Decode, Encode};
+    use ion_common::Instant;
     use winit::event::MouseButton;
     use winit::keyboard::KeyCode;
 
     use crate::core::coordinates::{Location, Position};
-    use crate::input::CommandType;
+    use crate::input::{BindResult, CommandType};
 
-    use super::InputState;
+    use super::{InputState, KeyRepeat};
 
     #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Encode, Decode, Hash)]
     pub enum TestCommand {
@@ -543,7 +1324,7 @@ mod tests {
         let (e_in, e_out) = mpsc::channel();
         let mut handler = InputState::<TestCommand>::new(e_out, [e_in.clone(), e_in.clone()]);
 
-        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyA, Some('A')))
+        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyA, Some('A')).into())
             .unwrap();
 
         handler.handle_received_input_events();
@@ -558,12 +1339,12 @@ mod tests {
         let (e_in, e_out) = mpsc::channel();
         let mut handler = InputState::<TestCommand>::new(e_out, [e_in.clone(), e_in.clone()]);
 
-        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyA, Some('A')))
+        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyA, Some('A')).into())
             .unwrap();
 
         handler.clear_one_frame_statuses();
 
-        e_in.send(crate::input::InputEvent::KeyReleased(KeyCode::KeyA, Some('A')))
+        e_in.send(crate::input::InputEvent::KeyReleased(KeyCode::KeyA, Some('A')).into())
             .unwrap();
 
         handler.handle_received_input_events();
@@ -578,10 +1359,10 @@ mod tests {
         let (e_in, e_out) = mpsc::channel();
         let mut handler = InputState::<TestCommand>::new(e_out, [e_in.clone(), e_in.clone()]);
 
-        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyA, Some('A')))
+        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyA, Some('A')).into())
             .unwrap();
 
-        e_in.send(crate::input::InputEvent::KeyReleased(KeyCode::KeyA, Some('A')))
+        e_in.send(crate::input::InputEvent::KeyReleased(KeyCode::KeyA, Some('A')).into())
             .unwrap();
 
         handler.handle_received_input_events();
@@ -598,7 +1379,7 @@ mod tests {
         let mut handler = InputState::<TestCommand>::new(e_out, [e_in.clone(), e_in.clone()]);
 
         // Test mouse button press
-        e_in.send(crate::input::InputEvent::MouseButtonPressed(MouseButton::Left))
+        e_in.send(crate::input::InputEvent::MouseButtonPressed(MouseButton::Left).into())
             .unwrap();
 
         handler.handle_received_input_events();
@@ -616,7 +1397,7 @@ mod tests {
         assert!(!handler.is_button_just_released(MouseButton::Left));
 
         // Test release (following the pattern of the working key test)
-        e_in.send(crate::input::InputEvent::MouseButtonReleased(MouseButton::Left))
+        e_in.send(crate::input::InputEvent::MouseButtonReleased(MouseButton::Left).into())
             .unwrap();
 
         handler.handle_received_input_events();
@@ -644,7 +1425,7 @@ mod tests {
         assert!(!handler.is_button_just_pressed(MouseButton::Left));
 
         // Send mouse button press
-        e_in.send(crate::input::InputEvent::MouseButtonPressed(MouseButton::Left))
+        e_in.send(crate::input::InputEvent::MouseButtonPressed(MouseButton::Left).into())
             .unwrap();
 
         handler.handle_received_input_events();
@@ -666,7 +1447,8 @@ mod tests {
         let mut handler = InputState::<TestCommand>::new(e_out, [e_in.clone(), e_in.clone()]);
 
         // Test scroll event
-        e_in.send(crate::input::InputEvent::MouseScrollChange(2.5)).unwrap();
+        e_in.send(crate::input::InputEvent::MouseScrollChange(2.5).into())
+            .unwrap();
 
         handler.handle_received_input_events();
 
@@ -687,7 +1469,7 @@ mod tests {
         let new_position = Position { x: 100.0, y: 200.0 };
 
         // Test cursor location update
-        e_in.send(crate::input::InputEvent::CursorLocPosChange(new_location, new_position))
+        e_in.send(crate::input::InputEvent::CursorLocPosChange(new_location, new_position).into())
             .unwrap();
 
         handler.handle_received_input_events();
@@ -704,11 +1486,8 @@ mod tests {
         let initial_position = Position { x: 50.0, y: 75.0 };
 
         // Set initial position
-        e_in.send(crate::input::InputEvent::CursorLocPosChange(
-            initial_location,
-            initial_position,
-        ))
-        .unwrap();
+        e_in.send(crate::input::InputEvent::CursorLocPosChange(initial_location, initial_position).into())
+            .unwrap();
 
         handler.handle_received_input_events();
 
@@ -716,7 +1495,7 @@ mod tests {
         let camera_x_change = 10.0;
         let camera_y_change = -15.0;
 
-        e_in.send(crate::input::InputEvent::CameraMoved(camera_x_change, camera_y_change))
+        e_in.send(crate::input::InputEvent::CameraMoved(camera_x_change, camera_y_change).into())
             .unwrap();
 
         handler.handle_received_input_events();
@@ -739,9 +1518,11 @@ mod tests {
         // Create a key binding
         let keybind = KeyBind {
             command: TestCommand::Test,
-            key_1: Some(KeyCode::KeyW),
-            key_2: None,
+            keys: vec![KeyCode::KeyW],
             mouse_button: None,
+            gamepad_button: None,
+            exact_modifiers: false,
+            modifiers_first: false,
         };
 
         // Set the key binding
@@ -758,15 +1539,15 @@ mod tests {
         let mut handler = InputState::<TestCommand>::new(e_out, [e_in.clone(), e_in.clone()]);
 
         // Send character events
-        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyH, Some('h')))
+        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyH, Some('h')).into())
             .unwrap();
-        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyE, Some('e')))
+        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyE, Some('e')).into())
             .unwrap();
-        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyL, Some('l')))
+        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyL, Some('l')).into())
             .unwrap();
-        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyL, Some('l')))
+        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyL, Some('l')).into())
             .unwrap();
-        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyO, Some('o')))
+        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyO, Some('o')).into())
             .unwrap();
 
         handler.handle_received_input_events();
@@ -787,15 +1568,15 @@ mod tests {
         let mut handler = InputState::<TestCommand>::new(e_out, [e_in.clone(), e_in.clone()]);
 
         // Send text with space
-        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyH, Some('h')))
+        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyH, Some('h')).into())
             .unwrap();
-        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyI, Some('i')))
+        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyI, Some('i')).into())
             .unwrap();
-        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::Space, None))
+        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::Space, None).into())
             .unwrap();
-        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyB, Some('b')))
+        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyB, Some('b')).into())
             .unwrap();
-        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyE, Some('e')))
+        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyE, Some('e')).into())
             .unwrap();
 
         handler.handle_received_input_events();
@@ -810,19 +1591,47 @@ mod tests {
         let mut handler = InputState::<TestCommand>::new(e_out, [e_in.clone(), e_in.clone()]);
 
         // Press a key
-        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyA, Some('a')))
+        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyA, Some('a')).into())
             .unwrap();
 
-        handler.handle_received_input_events();
+        let start = Instant::now();
+        handler.set_key_repeat(KeyRepeat {
+            delay: Duration::from_millis(300),
+            interval: Duration::from_millis(100),
+        });
+        handler.handle_received_input_events_at(start);
 
-        // Simulate holding the key for many frames (key must remain active)
-        // After the first frame, active_for starts at 0 and increments each frame
-        for _ in 0..64 {
+        // Repeats happen when the delay passes and then on every interval, regardless of frame rate
+        let mut repeating_at = Vec::new();
+        for at_ms in (20..=520).step_by(20) {
             handler.clear_one_frame_statuses();
+            handler.handle_received_input_events_at(start + Duration::from_millis(at_ms));
+            if handler.is_key_repeating(KeyCode::KeyA) {
+                repeating_at.push(at_ms);
+            }
         }
+        assert_eq!(repeating_at, vec![300, 400, 500]);
+    }
+
+    #[test]
+    fn text_input_repeats_separately_from_keys() {
+        let (e_in, e_out) = mpsc::channel();
+        let mut handler = InputState::<TestCommand>::new(e_out, [e_in.clone(), e_in.clone()]);
+        handler.set_text_repeat(KeyRepeat {
+            delay: Duration::from_millis(100),
+            interval: Duration::from_millis(10),
+        });
+
+        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyA, Some('a')).into())
+            .unwrap();
+        let start = Instant::now();
+        handler.handle_received_input_events_at(start);
 
-        // At this point, active_for should be 64, which is > 60 and 64 % 4 == 0
-        assert!(handler.is_key_repeating(KeyCode::KeyA));
+        // A slow frame gets every repeat that happened during it
+        handler.clear_one_frame_statuses();
+        handler.handle_received_input_events_at(start + Duration::from_millis(125));
+        assert_eq!(handler.text_input(), Some("aaa".to_string()));
+        assert!(!handler.is_key_repeating(KeyCode::KeyA));
     }
 
     #[test]
@@ -831,7 +1640,7 @@ mod tests {
         let mut handler = InputState::<TestCommand>::new(e_out, [e_in.clone(), e_in.clone()]);
 
         // Press a key
-        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyA, Some('a')))
+        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyA, Some('a')).into())
             .unwrap();
 
         handler.handle_received_input_events();
@@ -846,11 +1655,11 @@ mod tests {
         let mut handler = InputState::<TestCommand>::new(e_out, [e_in.clone(), e_in.clone()]);
 
         // Press multiple keys
-        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyA, Some('a')))
+        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyA, Some('a')).into())
             .unwrap();
-        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyB, Some('b')))
+        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyB, Some('b')).into())
             .unwrap();
-        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyC, Some('c')))
+        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyC, Some('c')).into())
             .unwrap();
 
         handler.handle_received_input_events();
@@ -866,19 +1675,29 @@ mod tests {
     }
 
     #[test]
-    fn mouse_other_button_ignored() {
+    fn extra_mouse_buttons_are_tracked() {
         let (e_in, e_out) = mpsc::channel();
         let mut handler = InputState::<TestCommand>::new(e_out, [e_in.clone(), e_in.clone()]);
 
-        // Send other mouse button event (should be ignored)
-        e_in.send(crate::input::InputEvent::MouseButtonPressed(MouseButton::Other(5)))
-            .unwrap();
+        // Buttons that have never been pressed are released
+        assert!(!handler.is_button_active(MouseButton::Other(5)));
 
+        e_in.send(crate::input::InputEvent::MouseButtonPressed(MouseButton::Other(5)).into())
+            .unwrap();
+        e_in.send(crate::input::InputEvent::MouseButtonPressed(MouseButton::Back).into())
+            .unwrap();
         handler.handle_received_input_events();
 
-        // Other mouse button should not panic or cause issues
-        // No way to directly test this, but the handler should still function normally
-        assert_eq!(handler.mouse_scroll_delta(), 0.0); // Smoke test
+        assert!(handler.is_button_just_pressed(MouseButton::Other(5)));
+        assert!(handler.is_button_active(MouseButton::Back));
+        assert!(!handler.is_button_active(MouseButton::Other(6)));
+
+        handler.clear_one_frame_statuses();
+        e_in.send(crate::input::InputEvent::MouseButtonReleased(MouseButton::Other(5)).into())
+            .unwrap();
+        handler.handle_received_input_events();
+        assert!(handler.is_button_just_released(MouseButton::Other(5)));
+        assert!(!handler.is_button_active(MouseButton::Other(5)));
     }
 
     #[test]
@@ -887,11 +1706,12 @@ mod tests {
         let mut handler = InputState::<TestCommand>::new(e_out, [e_in.clone(), e_in.clone()]);
 
         // Send multiple events
-        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyA, Some('a')))
+        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyA, Some('a')).into())
             .unwrap();
-        e_in.send(crate::input::InputEvent::MouseButtonPressed(MouseButton::Left))
+        e_in.send(crate::input::InputEvent::MouseButtonPressed(MouseButton::Left).into())
+            .unwrap();
+        e_in.send(crate::input::InputEvent::MouseScrollChange(1.5).into())
             .unwrap();
-        e_in.send(crate::input::InputEvent::MouseScrollChange(1.5)).unwrap();
 
         handler.handle_received_input_events();
 
@@ -907,7 +1727,7 @@ mod tests {
         let mut handler = InputState::<TestCommand>::new(e_out, [e_in.clone(), e_in.clone()]);
 
         // Send key release without previous press
-        e_in.send(crate::input::InputEvent::KeyReleased(KeyCode::KeyA, Some('a')))
+        e_in.send(crate::input::InputEvent::KeyReleased(KeyCode::KeyA, Some('a')).into())
             .unwrap();
 
         handler.handle_received_input_events();
@@ -924,11 +1744,11 @@ mod tests {
         let mut handler = InputState::<TestCommand>::new(e_out, [e_in.clone(), e_in.clone()]);
 
         // Send non-character events
-        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::Enter, None))
+        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::Enter, None).into())
             .unwrap();
-        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::Tab, None))
+        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::Tab, None).into())
             .unwrap();
-        e_in.send(crate::input::InputEvent::MouseButtonPressed(MouseButton::Left))
+        e_in.send(crate::input::InputEvent::MouseButtonPressed(MouseButton::Left).into())
             .unwrap();
 
         handler.handle_received_input_events();
@@ -943,7 +1763,7 @@ mod tests {
         let mut handler = InputState::<TestCommand>::new(e_out, [e_in.clone(), e_in.clone()]);
 
         // Press a key
-        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyA, Some('a')))
+        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyA, Some('a')).into())
             .unwrap();
 
         handler.handle_received_input_events();
@@ -983,18 +1803,20 @@ mod tests {
         // Create and set a key binding
         let keybind = KeyBind {
             command: ExtendedTestCommand::MoveUp,
-            key_1: Some(KeyCode::KeyW),
-            key_2: None,
+            keys: vec![KeyCode::KeyW],
             mouse_button: None,
+            gamepad_button: None,
+            exact_modifiers: false,
+            modifiers_first: false,
         };
 
-        e_in.send(crate::input::InputEvent::SetKeyBind(keybind.clone()))
+        e_in.send(crate::input::InputEvent::SetKeyBind(keybind.clone()).into())
             .unwrap();
 
         handler.handle_received_input_events();
 
         // Press the bound key
-        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyW, Some('w')))
+        e_in.send(crate::input::InputEvent::KeyPressed(KeyCode::KeyW, Some('w')).into())
             .unwrap();
 
         handler.handle_received_input_events();
@@ -1015,18 +1837,20 @@ mod tests {
         // Create and set a mouse button binding
         let keybind = KeyBind {
             command: ExtendedTestCommand::Attack,
-            key_1: None,
-            key_2: None,
+            keys: Vec::new(),
             mouse_button: Some(MouseButton::Left),
+            gamepad_button: None,
+            exact_modifiers: false,
+            modifiers_first: false,
         };
 
-        e_in.send(crate::input::InputEvent::SetKeyBind(keybind.clone()))
+        e_in.send(crate::input::InputEvent::SetKeyBind(keybind.clone()).into())
             .unwrap();
 
         handler.handle_received_input_events();
 
         // Press the bound mouse button
-        e_in.send(crate::input::InputEvent::MouseButtonPressed(MouseButton::Left))
+        e_in.send(crate::input::InputEvent::MouseButtonPressed(MouseButton::Left).into())
             .unwrap();
 
         handler.handle_received_input_events();
@@ -1046,18 +1870,20 @@ mod tests {
         // Create and set a key binding
         let keybind = KeyBind {
             command: ExtendedTestCommand::MoveUp,
-            key_1: Some(KeyCode::KeyW),
-            key_2: None,
+            keys: vec![KeyCode::KeyW],
             mouse_button: None,
+            gamepad_button: None,
+            exact_modifiers: false,
+            modifiers_first: false,
         };
 
-        e_in.send(crate::input::InputEvent::SetKeyBind(keybind.clone()))
+        e_in.send(crate::input::InputEvent::SetKeyBind(keybind.clone()).into())
             .unwrap();
 
         handler.handle_received_input_events();
 
         // Remove the binding
-        e_in.send(crate::input::InputEvent::RemoveKeyBind(ExtendedTestCommand::MoveUp))
+        e_in.send(crate::input::InputEvent::RemoveKeyBind(ExtendedTestCommand::MoveUp).into())
             .unwrap();
 
         handler.handle_received_input_events();
@@ -1066,20 +1892,87 @@ mod tests {
         assert_eq!(handler.check_key_bind(ExtendedTestCommand::MoveUp), None);
     }
 
+    #[test]
+    fn conflicting_key_bind_is_not_set() {
+        let (e_in, e_out) = mpsc::channel();
+        let mut handler = InputState::<ExtendedTestCommand>::new(e_out, [e_in.clone(), e_in.clone()]);
+
+        use crate::input::KeyBind;
+
+        let move_up = KeyBind::new(ExtendedTestCommand::MoveUp, Some(KeyCode::KeyW), None, None);
+        assert_eq!(handler.set_key_bind(move_up.clone()), BindResult::Bound);
+        handler.handle_received_input_events();
+
+        // Rebinding the same command is not a conflict
+        assert_eq!(handler.set_key_bind(move_up.clone()), BindResult::Bound);
+
+        let move_down = KeyBind::new(ExtendedTestCommand::MoveDown, Some(KeyCode::KeyW), None, None);
+        assert_eq!(
+            handler.set_key_bind(move_down.clone()),
+            BindResult::Conflicts(vec![ExtendedTestCommand::MoveUp])
+        );
+        handler.handle_received_input_events();
+        assert_eq!(handler.check_key_bind(ExtendedTestCommand::MoveDown), None);
+
+        // Combination with a different second key does not conflict
+        let combo = KeyBind::new(
+            ExtendedTestCommand::MoveDown,
+            Some(KeyCode::KeyW),
+            Some(KeyCode::ShiftLeft),
+            None,
+        );
+        assert_eq!(handler.set_key_bind(combo), BindResult::Bound);
+
+        assert_eq!(handler.force_key_bind(move_down.clone()), BindResult::Bound);
+        handler.handle_received_input_events();
+        assert_eq!(handler.check_key_bind(ExtendedTestCommand::MoveUp), None);
+        assert_eq!(handler.check_key_bind(ExtendedTestCommand::MoveDown), Some(move_down));
+    }
+
+    #[test]
+    fn reserved_keys_can_not_be_bound() {
+        let (e_in, e_out) = mpsc::channel();
+        let mut handler = InputState::<ExtendedTestCommand>::new(e_out, [e_in.clone(), e_in.clone()]);
+
+        use crate::input::KeyBind;
+
+        handler.set_reserved_keys(&[KeyCode::F12]);
+        let key_bind = KeyBind::new(
+            ExtendedTestCommand::Attack,
+            Some(KeyCode::ShiftLeft),
+            Some(KeyCode::F12),
+            None,
+        );
+        assert_eq!(
+            handler.set_key_bind(key_bind.clone()),
+            BindResult::Reserved(KeyCode::F12)
+        );
+        assert_eq!(
+            handler.force_key_bind(key_bind.clone()),
+            BindResult::Reserved(KeyCode::F12)
+        );
+        handler.handle_received_input_events();
+        assert_eq!(handler.check_key_bind(ExtendedTestCommand::Attack), None);
+
+        // Nothing is reserved unless the game asks for it
+        handler.set_reserved_keys(&[]);
+        assert_eq!(handler.set_key_bind(key_bind), BindResult::Bound);
+    }
+
     #[test]
     fn mouse_button_is_active_on_frame_it_was_released() {
         let (e_in, e_out) = mpsc::channel();
         let mut handler = InputState::<TestCommand>::new(e_out, [e_in.clone(), e_in.clone()]);
 
         // Send mouse button press (but don't process events yet)
-        e_in.send(crate::input::InputEvent::MouseButtonPressed(MouseButton::Left))
+        e_in.send(crate::input::InputEvent::MouseButtonPressed(MouseButton::Left).into())
             .unwrap();
 
         // Clear frame status (this resets just_pressed/just_released flags)
         handler.clear_one_frame_statuses();
 
         // Send mouse button release
-        e_in.send(crate::input::InputEvent::MouseButtonReleased(MouseButton::Left))
+        e_in.send(crate::input::InputEvent::MouseButtonReleased(MouseButton::Left).into())
             .unwrap();
 
         // Process both events
@@ -1092,4 +1985,481 @@ mod tests {
         assert!(handler.is_button_just_pressed(MouseButton::Left));
         assert!(handler.is_button_just_released(MouseButton::Left));
     }
+
+    #[test]
+    fn gamepad_button_is_alternative_to_keys() {
+        use crate::input::{
+            InputEvent, KeyBind,
+            gamepad::{GamepadAxis, GamepadButton},
+        };
+
+        let (e_in, e_out) = mpsc::channel();
+        let mut handler = InputState::<ExtendedTestCommand>::new(e_out, [e_in.clone(), e_in.clone()]);
+
+        let attack = KeyBind::new(ExtendedTestCommand::Attack, Some(KeyCode::Space), None, None)
+            .with_gamepad_button(GamepadButton::South);
+        assert_eq!(handler.set_key_bind(attack), BindResult::Bound);
+        handler.handle_received_input_events();
+
+        // Buttons of gamepads that are not connected are ignored
+        e_in.send(InputEvent::GamepadButtonPressed(0, GamepadButton::South).into())
+            .unwrap();
+        handler.handle_received_input_events();
+        assert!(!handler.is_command_active(ExtendedTestCommand::Attack));
+
+        e_in.send(InputEvent::GamepadConnected(0).into()).unwrap();
+        e_in.send(InputEvent::GamepadButtonPressed(0, GamepadButton::South).into())
+            .unwrap();
+        e_in.send(InputEvent::GamepadAxisChange(0, GamepadAxis::LeftStickX, -0.5).into())
+            .unwrap();
+        handler.handle_received_input_events();
+
+        assert_eq!(handler.just_connected_gamepads(), vec![0]);
+        assert!(handler.is_command_active(ExtendedTestCommand::Attack));
+        assert!(handler.is_command_just_actived(ExtendedTestCommand::Attack));
+        assert_eq!(handler.gamepad_axis(GamepadAxis::LeftStickX), -0.5);
+
+        // Releasing the key does not release the command while the gamepad button is held
+        handler.clear_one_frame_statuses();
+        e_in.send(InputEvent::KeyPressed(KeyCode::Space, None).into()).unwrap();
+        e_in.send(InputEvent::KeyReleased(KeyCode::Space, None).into()).unwrap();
+        handler.handle_received_input_events();
+        assert!(handler.is_command_active(ExtendedTestCommand::Attack));
+        assert!(!handler.is_command_just_released(ExtendedTestCommand::Attack));
+
+        handler.clear_one_frame_statuses();
+        e_in.send(InputEvent::GamepadDisconnected(0).into()).unwrap();
+        handler.handle_received_input_events();
+        assert!(!handler.is_command_active(ExtendedTestCommand::Attack));
+        assert_eq!(handler.connected_gamepads(), Vec::new());
+        assert_eq!(handler.gamepad_axis(GamepadAxis::LeftStickX), 0.0);
+    }
+
+    #[test]
+    fn gamepad_binds_collide_only_on_same_button() {
+        use crate::input::{KeyBind, gamepad::GamepadButton};
+
+        let (e_in, e_out) = mpsc::channel();
+        let mut handler = InputState::<ExtendedTestCommand>::new(e_out, [e_in.clone(), e_in.clone()]);
+
+        let move_up = KeyBind::new_gamepad(ExtendedTestCommand::MoveUp, GamepadButton::DPadUp);
+        assert_eq!(handler.set_key_bind(move_up), BindResult::Bound);
+        handler.handle_received_input_events();
+
+        let move_down = KeyBind::new_gamepad(ExtendedTestCommand::MoveDown, GamepadButton::DPadDown);
+        assert_eq!(handler.set_key_bind(move_down), BindResult::Bound);
+
+        let attack = KeyBind::new(ExtendedTestCommand::Attack, Some(KeyCode::KeyW), None, None)
+            .with_gamepad_button(GamepadButton::DPadUp);
+        assert_eq!(
+            handler.set_key_bind(attack),
+            BindResult::Conflicts(vec![ExtendedTestCommand::MoveUp])
+        );
+    }
+
+    #[test]
+    fn axis_value_comes_from_strongest_source() {
+        use crate::input::{AxisBind, AxisSource, InputEvent, gamepad::GamepadAxis};
+
+        let (e_in, e_out) = mpsc::channel();
+        let mut handler = InputState::<ExtendedTestCommand>::new(e_out, [e_in.clone(), e_in.clone()]);
+        handler.set_reserved_keys(&[KeyCode::F12]);
+
+        let move_x = AxisBind::new(
+            ExtendedTestCommand::MoveUp,
+            AxisSource::Keys(KeyCode::KeyA, KeyCode::KeyD),
+        )
+        .with_source(AxisSource::GamepadStick(GamepadAxis::LeftStickX))
+        .with_source(AxisSource::MouseX(4.0));
+        assert_eq!(handler.set_axis_bind(move_x), BindResult::Bound);
+        assert_eq!(
+            handler.set_axis_bind(AxisBind::new(
+                ExtendedTestCommand::MoveDown,
+                AxisSource::Keys(KeyCode::F12, KeyCode::KeyS)
+            )),
+            BindResult::Reserved(KeyCode::F12)
+        );
+        handler.handle_received_input_events();
+        assert_eq!(handler.axis_value(ExtendedTestCommand::MoveUp), 0.0);
+
+        e_in.send(InputEvent::GamepadConnected(0).into()).unwrap();
+        e_in.send(InputEvent::GamepadAxisChange(0, GamepadAxis::LeftStickX, 0.5).into())
+            .unwrap();
+        e_in.send(InputEvent::KeyPressed(KeyCode::KeyA, None).into()).unwrap();
+        handler.handle_received_input_events();
+        assert_eq!(handler.axis_value(ExtendedTestCommand::MoveUp), -1.0);
+
+        // Both keys cancel out, so the stick wins
+        handler.clear_one_frame_statuses();
+        e_in.send(InputEvent::KeyPressed(KeyCode::KeyD, None).into()).unwrap();
+        handler.handle_received_input_events();
+        assert_eq!(handler.axis_value(ExtendedTestCommand::MoveUp), 0.5);
+
+        // Mouse movement is scaled by the sensitivity and clamped
+        handler.clear_one_frame_statuses();
+        e_in.send(InputEvent::CursorLocPosChange(Location { x: 0.0, y: 0.0 }, Position { x: -0.25, y: 0.0 }).into())
+            .unwrap();
+        e_in.send(InputEvent::CursorLocPosChange(Location { x: 0.0, y: 0.0 }, Position { x: -0.5, y: 0.0 }).into())
+            .unwrap();
+        handler.handle_received_input_events();
+        assert_eq!(handler.axis_value(ExtendedTestCommand::MoveUp), -1.0);
+
+        handler.clear_one_frame_statuses();
+        assert_eq!(handler.axis_value(ExtendedTestCommand::MoveUp), 0.5);
+    }
+
+    #[test]
+    fn key_chords_respect_modifier_rules() {
+        use crate::input::{InputEvent, KeyBind};
+
+        let (e_in, e_out) = mpsc::channel();
+        let mut handler = InputState::<ExtendedTestCommand>::new(e_out, [e_in.clone(), e_in.clone()]);
+
+        let save = KeyBind::new_chord(
+            ExtendedTestCommand::Combo,
+            &[KeyCode::ControlLeft, KeyCode::ShiftLeft, KeyCode::KeyS],
+            None,
+        )
+        .with_modifiers_first();
+        let move_down =
+            KeyBind::new(ExtendedTestCommand::MoveDown, Some(KeyCode::KeyS), None, None).with_exact_modifiers();
+        assert_eq!(handler.set_key_bind(save), BindResult::Bound);
+        assert_eq!(handler.set_key_bind(move_down), BindResult::Bound);
+        handler.handle_received_input_events();
+
+        e_in.send(InputEvent::KeyPressed(KeyCode::ControlLeft, None).into())
+            .unwrap();
+        e_in.send(InputEvent::KeyPressed(KeyCode::ShiftLeft, None).into())
+            .unwrap();
+        e_in.send(InputEvent::KeyPressed(KeyCode::KeyS, None).into()).unwrap();
+        handler.handle_received_input_events();
+        assert!(handler.is_command_just_actived(ExtendedTestCommand::Combo));
+        assert!(!handler.is_command_active(ExtendedTestCommand::MoveDown));
+
+        // Releasing the modifiers leaves only S held
+        handler.clear_one_frame_statuses();
+        e_in.send(InputEvent::KeyReleased(KeyCode::ControlLeft, None).into())
+            .unwrap();
+        e_in.send(InputEvent::KeyReleased(KeyCode::ShiftLeft, None).into())
+            .unwrap();
+        handler.handle_received_input_events();
+        handler.clear_one_frame_statuses();
+        assert!(!handler.is_command_active(ExtendedTestCommand::Combo));
+        assert!(handler.is_command_active(ExtendedTestCommand::MoveDown));
+
+        // Pressing the modifiers after S does not execute the chord
+        e_in.send(InputEvent::KeyPressed(KeyCode::ControlLeft, None).into())
+            .unwrap();
+        e_in.send(InputEvent::KeyPressed(KeyCode::ShiftLeft, None).into())
+            .unwrap();
+        handler.handle_received_input_events();
+        assert!(!handler.is_command_active(ExtendedTestCommand::Combo));
+        assert!(!handler.is_command_active(ExtendedTestCommand::MoveDown));
+    }
+
+    #[test]
+    fn chords_collide_regardless_of_key_order() {
+        use crate::input::KeyBind;
+
+        let bind_1 = KeyBind::new_chord(
+            ExtendedTestCommand::Combo,
+            &[KeyCode::ControlLeft, KeyCode::ShiftLeft, KeyCode::KeyS],
+            None,
+        );
+        let bind_2 = KeyBind::new_chord(
+            ExtendedTestCommand::Attack,
+            &[KeyCode::KeyS, KeyCode::ControlLeft, KeyCode::ShiftLeft, KeyCode::KeyS],
+            None,
+        );
+        let bind_3 = KeyBind::new_chord(
+            ExtendedTestCommand::Attack,
+            &[KeyCode::ControlLeft, KeyCode::KeyS],
+            None,
+        );
+
+        assert_eq!(bind_2.keys().len(), 3);
+        assert!(bind_1.collides_with(&bind_2));
+        assert!(!bind_1.collides_with(&bind_3));
+    }
+
+    #[test]
+    fn bind_context_stack_shadows_lower_contexts() {
+        use crate::input::{InputEvent, KeyBind};
+
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Encode, Decode)]
+        enum LayeredCommand {
+            Jump,
+            Pause,
+            Honk,
+            Confirm,
+        }
+        impl CommandType for LayeredCommand {
+            fn bind_context(&self) -> u32 {
+                match self {
+                    LayeredCommand::Jump | LayeredCommand::Pause => 0,
+                    LayeredCommand::Honk => 1,
+                    LayeredCommand::Confirm => 2,
+                }
+            }
+        }
+
+        // Context changes are sent to both input states, so the other one needs its own channel
+        let (e_in, e_out) = mpsc::channel();
+        let (other_in, _other_out) = mpsc::channel();
+        let mut handler = InputState::<LayeredCommand>::new(e_out, [e_in.clone(), other_in]);
+
+        let bind = |command, key| KeyBind::new(command, Some(key), None, None);
+        handler.set_key_bind(bind(LayeredCommand::Jump, KeyCode::Space));
+        handler.set_key_bind(bind(LayeredCommand::Pause, KeyCode::Escape));
+        handler.set_key_bind(bind(LayeredCommand::Honk, KeyCode::Space));
+        handler.set_key_bind(bind(LayeredCommand::Confirm, KeyCode::Enter));
+        handler.push_bind_context(0);
+        handler.push_bind_context(1);
+        e_in.send(InputEvent::KeyPressed(KeyCode::Space, None).into()).unwrap();
+        e_in.send(InputEvent::KeyPressed(KeyCode::Escape, None).into()).unwrap();
+        e_in.send(InputEvent::KeyPressed(KeyCode::Enter, None).into()).unwrap();
+        handler.handle_received_input_events();
+
+        // Space is taken by the upper context, but escape still falls through to the lower one
+        assert_eq!(handler.bind_contexts(), vec![0, 1]);
+        assert!(handler.is_command_active(LayeredCommand::Honk));
+        assert!(!handler.is_command_active(LayeredCommand::Jump));
+        assert!(handler.is_command_active(LayeredCommand::Pause));
+        assert!(!handler.is_command_active(LayeredCommand::Confirm));
+
+        handler.push_exclusive_bind_context(2);
+        handler.handle_received_input_events();
+        assert!(handler.is_command_active(LayeredCommand::Confirm));
+        assert!(!handler.is_command_active(LayeredCommand::Honk));
+        assert!(!handler.is_command_active(LayeredCommand::Pause));
+
+        handler.pop_bind_context();
+        handler.pop_bind_context();
+        handler.handle_received_input_events();
+        assert!(handler.is_command_active(LayeredCommand::Jump));
+        assert!(!handler.is_command_active(LayeredCommand::Honk));
+    }
+
+    #[test]
+    fn mouse_motion_accumulates_during_frame() {
+        let (e_in, e_out) = mpsc::channel();
+        let mut handler = InputState::<TestCommand>::new(e_out, [e_in.clone(), e_in.clone()]);
+
+        e_in.send(crate::input::InputEvent::MouseMotion(3.0, -1.0).into())
+            .unwrap();
+        e_in.send(crate::input::InputEvent::MouseMotion(2.0, -4.0).into())
+            .unwrap();
+        handler.handle_received_input_events();
+        assert_eq!(handler.mouse_motion(), (5.0, -5.0));
+
+        handler.clear_one_frame_statuses();
+        assert_eq!(handler.mouse_motion(), (0.0, 0.0));
+    }
+
+    #[test]
+    fn consecutive_clicks_within_interval_are_counted() {
+        let (e_in, e_out) = mpsc::channel();
+        let mut handler = InputState::<TestCommand>::new(e_out, [e_in.clone(), e_in.clone()]);
+        handler.set_multi_click_interval(Duration::from_millis(300));
+
+        let start = Instant::now();
+        let press = |handler: &mut InputState<TestCommand>, at_ms: u64| {
+            handler.clear_one_frame_statuses();
+            handler.register_click(MouseButton::Left, start + Duration::from_millis(at_ms));
+            handler
+                .mouse_button_states
+                .get_mut(&MouseButton::Left)
+                .unwrap()
+                .just_pressed = true;
+        };
+
+        press(&mut handler, 0);
+        assert_eq!(handler.button_click_count(MouseButton::Left), 1);
+        press(&mut handler, 200);
+        assert!(handler.is_button_double_clicked(MouseButton::Left));
+        press(&mut handler, 450);
+        assert_eq!(handler.button_click_count(MouseButton::Left), 3);
+        assert!(!handler.is_button_double_clicked(MouseButton::Left));
+
+        // Too slow to continue the sequence
+        press(&mut handler, 1000);
+        assert_eq!(handler.button_click_count(MouseButton::Left), 1);
+
+        // Count is only reported on the frame of the press
+        handler.clear_one_frame_statuses();
+        assert_eq!(handler.button_click_count(MouseButton::Left), 0);
+    }
+
+    #[test]
+    fn frame_events_keep_their_order_and_times() {
+        use crate::input::{InputEvent, TimedInputEvent};
+
+        let (e_in, e_out) = mpsc::channel();
+        let mut handler = InputState::<TestCommand>::new(e_out, [e_in.clone(), e_in.clone()]);
+
+        let start = Instant::now();
+        let sent_at = |ms, event| TimedInputEvent {
+            time: start + Duration::from_millis(ms),
+            event,
+        };
+        e_in.send(sent_at(0, InputEvent::KeyPressed(KeyCode::KeyA, None)))
+            .unwrap();
+        e_in.send(sent_at(8, InputEvent::KeyReleased(KeyCode::KeyA, None)))
+            .unwrap();
+        handler.handle_received_input_events();
+
+        let events = handler.frame_events();
+        assert!(matches!(
+            events[..],
+            [
+                TimedInputEvent {
+                    event: InputEvent::KeyPressed(KeyCode::KeyA, None),
+                    ..
+                },
+                TimedInputEvent {
+                    event: InputEvent::KeyReleased(KeyCode::KeyA, None),
+                    ..
+                }
+            ]
+        ));
+        assert_eq!(
+            events[1].time.duration_since(events[0].time),
+            Some(Duration::from_millis(8))
+        );
+
+        handler.clear_one_frame_statuses();
+        assert!(handler.frame_events().is_empty());
+    }
+
+    #[test]
+    fn two_finger_gestures_are_detected() {
+        use std::f32::consts::FRAC_PI_2;
+
+        use winit::event::TouchPhase;
+
+        use crate::input::InputEvent;
+
+        let (e_in, e_out) = mpsc::channel();
+        let mut handler = InputState::<TestCommand>::new(e_out, [e_in.clone(), e_in.clone()]);
+        let touch = |id, phase, x, y| InputEvent::Touch(id, phase, Position::new(x, y), 2.0);
+
+        e_in.send(touch(1, TouchPhase::Started, 0.4, 0.5).into()).unwrap();
+        handler.handle_received_input_events();
+        assert_eq!(handler.touch_gesture(), super::TouchGesture::NONE);
+
+        e_in.send(touch(2, TouchPhase::Started, 0.6, 0.5).into()).unwrap();
+        handler.handle_received_input_events();
+        assert_eq!(handler.touch_gesture(), super::TouchGesture::NONE);
+        assert_eq!(handler.touch_count(), 2);
+
+        // Spreading the fingers apart zooms and moving both pans
+        e_in.send(touch(1, TouchPhase::Moved, 0.3, 0.6).into()).unwrap();
+        e_in.send(touch(2, TouchPhase::Moved, 0.7, 0.6).into()).unwrap();
+        handler.handle_received_input_events();
+        let gesture = handler.touch_gesture();
+        assert!((gesture.zoom - 2.0).abs() < 1e-5);
+        assert!((gesture.pan.0).abs() < 1e-5 && (gesture.pan.1 - 0.1).abs() < 1e-5);
+        assert!(gesture.rotation.abs() < 1e-5);
+
+        // Turning the second finger below the first one is a clockwise quarter turn.
+        // The window is twice as wide as it is high, so the fingers stay as far apart.
+        e_in.send(touch(1, TouchPhase::Moved, 0.5, 0.2).into()).unwrap();
+        e_in.send(touch(2, TouchPhase::Moved, 0.5, 1.0).into()).unwrap();
+        handler.handle_received_input_events();
+        let gesture = handler.touch_gesture();
+        assert!((gesture.zoom - 1.0).abs() < 1e-5);
+        assert!((gesture.rotation - FRAC_PI_2).abs() < 1e-5);
+
+        e_in.send(touch(2, TouchPhase::Ended, 0.5, 1.0).into()).unwrap();
+        handler.handle_received_input_events();
+        assert_eq!(handler.touch_gesture(), super::TouchGesture::NONE);
+        assert_eq!(handler.touch_count(), 1);
+    }
+
+    #[test]
+    fn keys_are_named_by_layout_and_captured() {
+        use crate::input::{InputEvent, key_display::CapturedInput};
+
+        let (e_in, e_out) = mpsc::channel();
+        let mut handler = InputState::<TestCommand>::new(e_out, [e_in.clone(), e_in.clone()]);
+        assert_eq!(handler.key_display_name(KeyCode::Semicolon), ";");
+
+        // Nordic layouts have Ö where US layouts have a semicolon
+        e_in.send(InputEvent::KeyPressed(KeyCode::Semicolon, Some('ö')).into())
+            .unwrap();
+        handler.handle_received_input_events();
+        assert_eq!(handler.key_display_name(KeyCode::Semicolon), "Ö");
+        assert_eq!(
+            handler.capture_input(),
+            Some(CapturedInput::Keys(vec![KeyCode::Semicolon]))
+        );
+
+        // Shifted characters are not learned, and held modifiers are captured with the key
+        handler.clear_one_frame_statuses();
+        e_in.send(InputEvent::KeyReleased(KeyCode::Semicolon, None).into())
+            .unwrap();
+        e_in.send(InputEvent::KeyPressed(KeyCode::ShiftLeft, None).into())
+            .unwrap();
+        handler.handle_received_input_events();
+        assert_eq!(handler.capture_input(), None);
+
+        handler.clear_one_frame_statuses();
+        e_in.send(InputEvent::KeyPressed(KeyCode::Digit1, Some('!')).into())
+            .unwrap();
+        handler.handle_received_input_events();
+        assert_eq!(handler.key_display_name(KeyCode::Digit1), "1");
+        assert_eq!(
+            handler.capture_input(),
+            Some(CapturedInput::Keys(vec![KeyCode::ShiftLeft, KeyCode::Digit1]))
+        );
+    }
+
+    #[test]
+    fn short_presses_are_taps_and_long_presses_are_holds() {
+        use crate::input::{InputEvent, KeyBind};
+
+        let (e_in, e_out) = mpsc::channel();
+        let mut handler = InputState::<ExtendedTestCommand>::new(e_out, [e_in.clone(), e_in.clone()]);
+        handler.set_hold_threshold(Duration::from_millis(200));
+        handler.set_key_bind(KeyBind::new(
+            ExtendedTestCommand::Attack,
+            Some(KeyCode::Space),
+            None,
+            None,
+        ));
+
+        let start = Instant::now();
+        let frame = |handler: &mut InputState<ExtendedTestCommand>, at_ms: u64, event: Option<InputEvent<_>>| {
+            handler.clear_one_frame_statuses();
+            if let Some(event) = event {
+                e_in.send(event.into()).unwrap();
+            }
+            handler.handle_received_input_events_at(start + Duration::from_millis(at_ms));
+        };
+
+        frame(&mut handler, 0, Some(InputEvent::KeyPressed(KeyCode::Space, None)));
+        frame(&mut handler, 100, None);
+        assert!(!handler.is_command_held(ExtendedTestCommand::Attack));
+        assert_eq!(
+            handler.command_held_for(ExtendedTestCommand::Attack),
+            Duration::from_millis(100)
+        );
+        frame(&mut handler, 150, Some(InputEvent::KeyReleased(KeyCode::Space, None)));
+        assert!(handler.is_command_tapped(ExtendedTestCommand::Attack));
+        assert!(!handler.is_command_released_after_hold(ExtendedTestCommand::Attack));
+
+        frame(&mut handler, 1000, Some(InputEvent::KeyPressed(KeyCode::Space, None)));
+        frame(&mut handler, 1300, None);
+        assert!(handler.is_command_held(ExtendedTestCommand::Attack));
+        frame(&mut handler, 1400, Some(InputEvent::KeyReleased(KeyCode::Space, None)));
+        assert!(handler.is_command_released_after_hold(ExtendedTestCommand::Attack));
+        assert!(!handler.is_command_tapped(ExtendedTestCommand::Attack));
+        assert_eq!(
+            handler.command_held_for(ExtendedTestCommand::Attack),
+            Duration::from_millis(400)
+        );
+
+        frame(&mut handler, 1500, None);
+        assert_eq!(handler.command_held_for(ExtendedTestCommand::Attack), Duration::ZERO);
+    }
 }
@@ -0,0 +1,213 @@
+use std::collections::BTreeMap;
+
+use winit::event::MouseButton;
+use winit::keyboard::KeyCode;
+
+use crate::core::world::CommandType;
+use crate::util::config::{Config, ConfigParseError};
+
+use super::{KeyBind, gamepad::GamepadButton, input_state::SUPPORTED_KEYS};
+
+/// Key bindings of all bound commands, for storing them with `Files::export_config` and restoring them later.
+///
+/// The command type must implement `Config`, which can be derived for enums without fields.
+/// Each bind is stored as its own section, for example:
+///
+/// ```text
+/// count = 1
+///
+/// [0]
+/// command = "Save"
+/// keys = "ControlLeft+KeyS"
+/// exact_modifiers = false
+/// modifiers_first = true
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyBindings<C: CommandType> {
+    pub binds: Vec<KeyBind<C>>,
+}
+
+impl<C: CommandType> Default for KeyBindings<C> {
+    fn default() -> Self {
+        Self { binds: Vec::new() }
+    }
+}
+
+/// Name of the key as used in configs, for example `KeyA` or `ShiftLeft`.
+pub fn key_code_name(key: KeyCode) -> String {
+    format!("{key:?}")
+}
+
+/// Key with the given config name. Only keys that input states track have names.
+pub fn key_code_from_name(name: &str) -> Option<KeyCode> {
+    SUPPORTED_KEYS.iter().copied().find(|key| key_code_name(*key) == name)
+}
+
+/// Name of the mouse button as used in configs, for example `Left` or `Other(4)`.
+pub fn mouse_button_name(button: MouseButton) -> String {
+    format!("{button:?}")
+}
+
+pub fn mouse_button_from_name(name: &str) -> Option<MouseButton> {
+    match name {
+        "Left" => Some(MouseButton::Left),
+        "Right" => Some(MouseButton::Right),
+        "Middle" => Some(MouseButton::Middle),
+        "Back" => Some(MouseButton::Back),
+        "Forward" => Some(MouseButton::Forward),
+        _ => name
+            .strip_prefix("Other(")
+            .and_then(|value| value.strip_suffix(')'))
+            .and_then(|value| value.parse().ok())
+            .map(MouseButton::Other),
+    }
+}
+
+/// Name of the gamepad button as used in configs, for example `South` or `DPadUp`.
+pub fn gamepad_button_name(button: GamepadButton) -> String {
+    format!("{button:?}")
+}
+
+pub fn gamepad_button_from_name(name: &str) -> Option<GamepadButton> {
+    GamepadButton::ALL
+        .iter()
+        .copied()
+        .find(|button| gamepad_button_name(*button) == name)
+}
+
+fn field_path(path: &str, field: &str) -> String {
+    if path.is_empty() {
+        field.to_owned()
+    } else {
+        format!("{path}.{field}")
+    }
+}
+
+/// Decodes a named value, or None if the field doesn't exist.
+fn decode_named<T>(
+    path: &str,
+    table: &BTreeMap<String, String>,
+    from_name: impl Fn(&str) -> Option<T>,
+) -> Result<Option<T>, ConfigParseError> {
+    if !table.contains_key(path) {
+        return Ok(None);
+    }
+
+    let name = String::decode_kv_table(path, table)?;
+    from_name(&name)
+        .map(Some)
+        .ok_or_else(|| ConfigParseError::InvalidFieldType(format!("{path} = {name}")))
+}
+
+impl<C: CommandType + Config> Config for KeyBind<C> {
+    fn encode_kv_table(&self, path: &str, table: &mut BTreeMap<String, String>) {
+        self.command.encode_kv_table(&field_path(path, "command"), table);
+
+        let keys: Vec<_> = self.keys.iter().map(|key| key_code_name(*key)).collect();
+        keys.join("+").encode_kv_table(&field_path(path, "keys"), table);
+
+        if let Some(button) = self.mouse_button {
+            mouse_button_name(button).encode_kv_table(&field_path(path, "mouse_button"), table);
+        }
+        if let Some(button) = self.gamepad_button {
+            gamepad_button_name(button).encode_kv_table(&field_path(path, "gamepad_button"), table);
+        }
+
+        self.exact_modifiers
+            .encode_kv_table(&field_path(path, "exact_modifiers"), table);
+        self.modifiers_first
+            .encode_kv_table(&field_path(path, "modifiers_first"), table);
+    }
+
+    fn decode_kv_table(path: &str, table: &BTreeMap<String, String>) -> Result<Self, ConfigParseError> {
+        let command = C::decode_kv_table(&field_path(path, "command"), table)?;
+
+        let keys_path = field_path(path, "keys");
+        let keys_string = String::decode_kv_table(&keys_path, table)?;
+        let keys = keys_string
+            .split('+')
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                key_code_from_name(name)
+                    .ok_or_else(|| ConfigParseError::InvalidFieldType(format!("{keys_path} = {name}")))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut key_bind = KeyBind::new_chord(
+            command,
+            &keys,
+            decode_named(&field_path(path, "mouse_button"), table, mouse_button_from_name)?,
+        );
+        key_bind.gamepad_button = decode_named(&field_path(path, "gamepad_button"), table, gamepad_button_from_name)?;
+        key_bind.exact_modifiers = bool::decode_kv_table(&field_path(path, "exact_modifiers"), table)?;
+        key_bind.modifiers_first = bool::decode_kv_table(&field_path(path, "modifiers_first"), table)?;
+
+        if !key_bind.has_keyboard_or_mouse() && key_bind.gamepad_button.is_none() {
+            return Err(ConfigParseError::MissingData(format!("No input bound in {path}")));
+        }
+
+        Ok(key_bind)
+    }
+}
+
+impl<C: CommandType + Config> Config for KeyBindings<C> {
+    fn encode_kv_table(&self, path: &str, table: &mut BTreeMap<String, String>) {
+        (self.binds.len() as u32).encode_kv_table(&field_path(path, "count"), table);
+        for (i, bind) in self.binds.iter().enumerate() {
+            bind.encode_kv_table(&field_path(path, &i.to_string()), table);
+        }
+    }
+
+    fn decode_kv_table(path: &str, table: &BTreeMap<String, String>) -> Result<Self, ConfigParseError> {
+        let count = u32::decode_kv_table(&field_path(path, "count"), table)?;
+        let binds = (0..count)
+            .map(|i| KeyBind::decode_kv_table(&field_path(path, &i.to_string()), table))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self { binds })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bincode::{Decode, Encode};
+    use derive_engine::Config;
+
+    use super::*;
+    use crate::util::config::{config_from_string, config_to_string};
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Encode, Decode, Config)]
+    enum TestCommand {
+        Save,
+        Jump,
+        Attack,
+    }
+    impl CommandType for TestCommand {}
+
+    #[test]
+    fn key_bindings_survive_config_round_trip() {
+        let bindings = KeyBindings {
+            binds: vec![
+                KeyBind::new_chord(TestCommand::Save, &[KeyCode::ControlLeft, KeyCode::KeyS], None)
+                    .with_modifiers_first(),
+                KeyBind::new(TestCommand::Jump, Some(KeyCode::Space), None, None)
+                    .with_gamepad_button(GamepadButton::South)
+                    .with_exact_modifiers(),
+                KeyBind::new(TestCommand::Attack, None, None, Some(MouseButton::Other(4))),
+            ],
+        };
+
+        let encoded = config_to_string(&bindings);
+        assert!(encoded.contains("keys = \"ControlLeft+KeyS\""));
+
+        let decoded: KeyBindings<TestCommand> = config_from_string(&encoded).unwrap();
+        assert_eq!(decoded, bindings);
+    }
+
+    #[test]
+    fn unknown_key_names_are_rejected() {
+        let encoded = "count = 1\n[0]\ncommand = \"Jump\"\nkeys = \"KeyA+NoSuchKey\"\nexact_modifiers = false\nmodifiers_first = false\n";
+        let decoded: Result<KeyBindings<TestCommand>, _> = config_from_string(encoded);
+        assert!(matches!(decoded, Err(ConfigParseError::InvalidFieldType(_))));
+    }
+}
@@ -0,0 +1,135 @@
+use winit::event::MouseButton;
+use winit::keyboard::KeyCode;
+
+use super::gamepad::GamepadButton;
+
+/// Input captured for rebinding, see `InputState::capture_input`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CapturedInput {
+    /// Modifier keys that were held, followed by the pressed key. Can be bound with `KeyBind::new_chord`.
+    Keys(Vec<KeyCode>),
+    MouseButton(MouseButton),
+    GamepadButton(GamepadButton),
+}
+
+/// Whether the key produces a character that depends on the keyboard layout.
+/// The display names of these keys are learned from the characters they produce.
+pub(crate) fn is_character_key(key: KeyCode) -> bool {
+    let name = format!("{key:?}");
+    name.starts_with("Key")
+        || name.starts_with("Digit")
+        || matches!(
+            key,
+            KeyCode::Backquote
+                | KeyCode::Backslash
+                | KeyCode::BracketLeft
+                | KeyCode::BracketRight
+                | KeyCode::Comma
+                | KeyCode::Equal
+                | KeyCode::IntlBackslash
+                | KeyCode::IntlRo
+                | KeyCode::IntlYen
+                | KeyCode::Minus
+                | KeyCode::Period
+                | KeyCode::Quote
+                | KeyCode::Semicolon
+                | KeyCode::Slash
+        )
+}
+
+/// Name of the key for showing to players, for example "A", "Num 5" or "Right Shift".
+///
+/// Character keys are named by their position on a US layout. Use `InputState::key_display_name` to name them by
+/// the layout of the player instead.
+pub fn key_display_name(key: KeyCode) -> String {
+    let name = match key {
+        KeyCode::Backquote => "`",
+        KeyCode::Backslash | KeyCode::IntlBackslash => "\\",
+        KeyCode::BracketLeft => "[",
+        KeyCode::BracketRight => "]",
+        KeyCode::Comma => ",",
+        KeyCode::Equal => "=",
+        KeyCode::Minus => "-",
+        KeyCode::Period => ".",
+        KeyCode::Quote => "'",
+        KeyCode::Semicolon => ";",
+        KeyCode::Slash | KeyCode::IntlRo => "/",
+        KeyCode::IntlYen => "¥",
+        KeyCode::AltLeft => "Left Alt",
+        KeyCode::AltRight => "Right Alt",
+        KeyCode::ControlLeft => "Left Ctrl",
+        KeyCode::ControlRight => "Right Ctrl",
+        KeyCode::ShiftLeft => "Left Shift",
+        KeyCode::ShiftRight => "Right Shift",
+        KeyCode::SuperLeft => "Left Super",
+        KeyCode::SuperRight => "Right Super",
+        KeyCode::Backspace => "Backspace",
+        KeyCode::CapsLock => "Caps Lock",
+        KeyCode::ContextMenu => "Menu",
+        KeyCode::Enter => "Enter",
+        KeyCode::Space => "Space",
+        KeyCode::Tab => "Tab",
+        KeyCode::Delete => "Delete",
+        KeyCode::End => "End",
+        KeyCode::Home => "Home",
+        KeyCode::Insert => "Insert",
+        KeyCode::PageDown => "Page Down",
+        KeyCode::PageUp => "Page Up",
+        KeyCode::ArrowDown => "Down",
+        KeyCode::ArrowLeft => "Left",
+        KeyCode::ArrowRight => "Right",
+        KeyCode::ArrowUp => "Up",
+        KeyCode::NumLock => "Num Lock",
+        KeyCode::NumpadAdd => "Num +",
+        KeyCode::NumpadDecimal => "Num .",
+        KeyCode::NumpadDivide => "Num /",
+        KeyCode::NumpadEnter => "Num Enter",
+        KeyCode::NumpadMultiply => "Num *",
+        KeyCode::NumpadSubtract => "Num -",
+        KeyCode::Escape => "Esc",
+        KeyCode::PrintScreen => "Print Screen",
+        KeyCode::ScrollLock => "Scroll Lock",
+        KeyCode::Pause => "Pause",
+        _ => {
+            let name = format!("{key:?}");
+            return if let Some(digit) = name.strip_prefix("Numpad").filter(|rest| rest.len() == 1) {
+                format!("Num {digit}")
+            } else if let Some(rest) = name.strip_prefix("Key").or_else(|| name.strip_prefix("Digit")) {
+                rest.to_owned()
+            } else {
+                name
+            };
+        }
+    };
+    name.to_owned()
+}
+
+/// Name of the mouse button for showing to players, for example "Left Mouse" or "Mouse 6".
+pub fn mouse_button_display_name(button: MouseButton) -> String {
+    match button {
+        MouseButton::Left => "Left Mouse".to_owned(),
+        MouseButton::Right => "Right Mouse".to_owned(),
+        MouseButton::Middle => "Middle Mouse".to_owned(),
+        MouseButton::Back => "Mouse Back".to_owned(),
+        MouseButton::Forward => "Mouse Forward".to_owned(),
+        MouseButton::Other(number) => format!("Mouse {number}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_have_readable_names() {
+        assert_eq!(key_display_name(KeyCode::KeyA), "A");
+        assert_eq!(key_display_name(KeyCode::Digit1), "1");
+        assert_eq!(key_display_name(KeyCode::Numpad5), "Num 5");
+        assert_eq!(key_display_name(KeyCode::ShiftRight), "Right Shift");
+        assert_eq!(key_display_name(KeyCode::F11), "F11");
+
+        assert!(is_character_key(KeyCode::Semicolon));
+        assert!(is_character_key(KeyCode::KeyQ));
+        assert!(!is_character_key(KeyCode::Numpad1));
+    }
+}
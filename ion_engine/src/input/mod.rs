@@ -1,66 +1,327 @@
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
 use std::sync::mpsc;
 use std::sync::mpsc::Sender;
 
-use winit::event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent};
+use ion_common::Instant;
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, TouchPhase, WindowEvent};
 use winit::keyboard::PhysicalKey::Code;
 use winit::keyboard::{Key, KeyCode};
 
 use crate::core::coordinates::{Location, Position};
 use crate::core::world::CommandType;
+use crate::input::gamepad::{GamepadAxis, GamepadButton, GamepadId, GamepadPoller, RumbleRequest};
 use crate::input::input_state::InputState;
 use std::fmt::Debug;
 
+pub mod gamepad;
+pub mod input_recording;
 pub mod input_state;
+pub mod key_bind_config;
+pub mod key_display;
+pub mod virtual_controls;
+
+/// Modifier keys. Binds can require that no other modifiers are held, or that modifiers are pressed before the
+/// trigger key of the bind.
+pub const MODIFIER_KEYS: [KeyCode; 8] = [
+    KeyCode::ShiftLeft,
+    KeyCode::ShiftRight,
+    KeyCode::ControlLeft,
+    KeyCode::ControlRight,
+    KeyCode::AltLeft,
+    KeyCode::AltRight,
+    KeyCode::SuperLeft,
+    KeyCode::SuperRight,
+];
 
 /// A key binding for a command.
-/// Binds a command to a key or a combination of keys.
-/// If key_2 is None, the command is executed if key_1 is pressed.
-/// If key_2 is Some, the command is only executed if both keys are pressed.
+/// Binds a command to a key or a chord of any number of keys, optionally together with a mouse button.
+/// The command is executed while all keys and the mouse button of the bind are pressed.
+/// A gamepad button is an alternative to the keys and the mouse button: pressing either one executes the command.
+///
+/// By default other held keys don't matter, so a bind to S is also executed by Ctrl+S.
+/// This can be restricted with `with_exact_modifiers` and `with_modifiers_first`.
 #[derive(Debug, Clone, PartialEq)]
 pub struct KeyBind<C: CommandType> {
     command: C,
-    key_1: Option<KeyCode>,
-    key_2: Option<KeyCode>,
+    keys: Vec<KeyCode>,
     mouse_button: Option<MouseButton>,
+    gamepad_button: Option<GamepadButton>,
+    exact_modifiers: bool,
+    modifiers_first: bool,
 }
 
+impl<C: CommandType> KeyBind<C> {
+    pub fn new(command: C, key_1: Option<KeyCode>, key_2: Option<KeyCode>, mouse_button: Option<MouseButton>) -> Self {
+        let keys: Vec<_> = key_1.into_iter().chain(key_2).collect();
+        Self::new_chord(command, &keys, mouse_button)
+    }
+
+    /// Binds a command to a chord of keys, optionally together with a mouse button. Duplicate keys are ignored.
+    pub fn new_chord(command: C, keys: &[KeyCode], mouse_button: Option<MouseButton>) -> Self {
+        let mut unique_keys = Vec::with_capacity(keys.len());
+        for key in keys {
+            if !unique_keys.contains(key) {
+                unique_keys.push(*key);
+            }
+        }
+
+        Self {
+            command,
+            keys: unique_keys,
+            mouse_button,
+            gamepad_button: None,
+            exact_modifiers: false,
+            modifiers_first: false,
+        }
+    }
+
+    /// Binds a command only to a gamepad button.
+    pub fn new_gamepad(command: C, gamepad_button: GamepadButton) -> Self {
+        Self {
+            command,
+            keys: Vec::new(),
+            mouse_button: None,
+            gamepad_button: Some(gamepad_button),
+            exact_modifiers: false,
+            modifiers_first: false,
+        }
+    }
+
+    /// Adds a gamepad button that executes the command in addition to the keys and the mouse button.
+    pub fn with_gamepad_button(mut self, gamepad_button: GamepadButton) -> Self {
+        self.gamepad_button = Some(gamepad_button);
+        self
+    }
+
+    /// Executes the command only if no modifier keys other than the ones in the bind are held.
+    /// This way a bind to S is not executed by Ctrl+S.
+    pub fn with_exact_modifiers(mut self) -> Self {
+        self.exact_modifiers = true;
+        self
+    }
+
+    /// Executes the command only if the modifier keys of the bind were pressed before its trigger.
+    /// The trigger is the mouse button of the bind, or if there is none, the last key that is not a modifier.
+    /// This way pressing S and then Ctrl does not execute a bind to Ctrl+S.
+    pub fn with_modifiers_first(mut self) -> Self {
+        self.modifiers_first = true;
+        self
+    }
+
+    pub fn command(&self) -> C {
+        self.command
+    }
+
+    pub fn keys(&self) -> &[KeyCode] {
+        &self.keys
+    }
+
+    pub fn mouse_button(&self) -> Option<MouseButton> {
+        self.mouse_button
+    }
+
+    /// Returns true if both binds are triggered by the same input combination or gamepad button in the same context.
+    /// Order of the keys does not matter.
+    pub fn collides_with(&self, other: &KeyBind<C>) -> bool {
+        let same_keys = self.keys.len() == other.keys.len() && self.keys.iter().all(|key| other.keys.contains(key));
+        let same_combination = self.has_keyboard_or_mouse() && same_keys && self.mouse_button == other.mouse_button;
+        let same_gamepad_button = self.gamepad_button.is_some() && self.gamepad_button == other.gamepad_button;
+
+        self.command.bind_context() == other.command.bind_context() && (same_combination || same_gamepad_button)
+    }
+
+    fn has_keyboard_or_mouse(&self) -> bool {
+        !self.keys.is_empty() || self.mouse_button.is_some()
+    }
+
+    /// Whether the binds have any key, mouse button or gamepad button in common.
+    fn shares_input_with(&self, other: &KeyBind<C>) -> bool {
+        self.keys.iter().any(|key| other.keys.contains(key))
+            || (self.mouse_button.is_some() && self.mouse_button == other.mouse_button)
+            || (self.gamepad_button.is_some() && self.gamepad_button == other.gamepad_button)
+    }
+
+    fn modifier_keys(&self) -> impl Iterator<Item = KeyCode> {
+        self.keys.iter().copied().filter(|key| MODIFIER_KEYS.contains(key))
+    }
+
+    fn trigger_key(&self) -> Option<KeyCode> {
+        self.keys.iter().rev().copied().find(|key| !MODIFIER_KEYS.contains(key))
+    }
+}
+
+/// Input that drives an axis, in range -1.0..1.0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AxisSource {
+    /// Negative and positive key. Holding both keys cancels out.
+    Keys(KeyCode, KeyCode),
+    /// Horizontal cursor movement during the frame in window widths, multiplied by the sensitivity.
+    MouseX(f32),
+    /// Vertical cursor movement during the frame in window heights, multiplied by the sensitivity. Down is positive.
+    MouseY(f32),
+    /// Mouse scroll during the frame, multiplied by the sensitivity.
+    Scroll(f32),
+    /// Stick axis of any connected gamepad.
+    GamepadStick(GamepadAxis),
+}
+
+/// An axis binding for a command.
+/// Binds a command to one or more axis sources. The value of the axis is the value of the source
+/// that is furthest from zero, so for example keys and a gamepad stick can both move the player.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AxisBind<C: CommandType> {
+    command: C,
+    sources: Vec<AxisSource>,
+}
+
+impl<C: CommandType> AxisBind<C> {
+    pub fn new(command: C, source: AxisSource) -> Self {
+        Self {
+            command,
+            sources: vec![source],
+        }
+    }
+
+    /// Adds a source that drives the axis in addition to the existing ones.
+    pub fn with_source(mut self, source: AxisSource) -> Self {
+        self.sources.push(source);
+        self
+    }
+
+    pub fn command(&self) -> C {
+        self.command
+    }
+
+    pub fn sources(&self) -> &[AxisSource] {
+        &self.sources
+    }
+
+    fn keys(&self) -> impl Iterator<Item = KeyCode> {
+        self.sources.iter().flat_map(|source| match source {
+            AxisSource::Keys(negative, positive) => vec![*negative, *positive],
+            _ => Vec::new(),
+        })
+    }
+}
+
+/// How the OS cursor is held in the window, set with `Renderer::set_cursor_grab`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorGrab {
+    /// The cursor moves freely in and out of the window.
+    None,
+    /// The cursor can't leave the window, for example for edge scrolling. Not supported on macOS and web.
+    Confined,
+    /// The cursor stays in place, and only relative mouse motion is reported, for example for drag rotating
+    /// or aiming. Not supported on Windows and X11.
+    Locked,
+}
+
+/// Result of setting a key binding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BindResult<C: CommandType> {
+    /// The binding was set.
+    Bound,
+
+    /// The binding was not set, because other commands in the same context are bound to the same input.
+    /// The binding can still be set with `force_key_bind`, which removes the conflicting bindings.
+    Conflicts(Vec<C>),
+
+    /// The binding was not set, because the key is reserved by the game, see `InputState::set_reserved_keys`.
+    Reserved(KeyCode),
+}
+
+/// Input received by input states. Key binds and bind contexts are sent as events as well, so that they
+/// take effect in order with the input.
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
-pub(crate) enum InputEvent<C: CommandType> {
+pub enum InputEvent<C: CommandType> {
     KeyPressed(KeyCode, Option<char>),
     KeyReleased(KeyCode, Option<char>),
     MouseButtonPressed(MouseButton),
     MouseButtonReleased(MouseButton),
     MouseScrollChange(f32),
+    MouseMotion(f32, f32),
     CursorLocPosChange(Location, Position),
     CameraMoved(f32, f32),
     SetKeyBind(KeyBind<C>),
     RemoveKeyBind(C),
+    SetAxisBind(AxisBind<C>),
+    RemoveAxisBind(C),
+    PushBindContext(u32, bool),
+    PopBindContext,
+    GamepadConnected(GamepadId),
+    GamepadDisconnected(GamepadId),
+    GamepadButtonPressed(GamepadId, GamepadButton),
+    GamepadButtonReleased(GamepadId, GamepadButton),
+    GamepadAxisChange(GamepadId, GamepadAxis, f32),
+    /// Touch id, phase, position and the width to height ratio of the window.
+    Touch(u64, TouchPhase, Position, f32),
+}
+
+/// Input event together with the time it was sent to input states.
+#[derive(Debug, Clone)]
+pub struct TimedInputEvent<C: CommandType> {
+    pub time: Instant,
+    pub event: InputEvent<C>,
+}
+
+impl<C: CommandType> From<InputEvent<C>> for TimedInputEvent<C> {
+    /// Stamps the event with the current time.
+    fn from(event: InputEvent<C>) -> Self {
+        Self {
+            time: Instant::now(),
+            event,
+        }
+    }
+}
+
+impl<C: CommandType> InputEvent<C> {
+    /// Whether the event changes bindings or bind contexts. These come from the game and not from the player.
+    pub(crate) fn is_binding_change(&self) -> bool {
+        matches!(
+            self,
+            InputEvent::SetKeyBind(_)
+                | InputEvent::RemoveKeyBind(_)
+                | InputEvent::SetAxisBind(_)
+                | InputEvent::RemoveAxisBind(_)
+                | InputEvent::PushBindContext(..)
+                | InputEvent::PopBindContext
+        )
+    }
 }
 
 pub struct Input<C: CommandType> {
-    input_event_sender_ui: Sender<InputEvent<C>>,
-    input_event_sender_universe: Sender<InputEvent<C>>,
+    input_event_sender_ui: Sender<TimedInputEvent<C>>,
+    input_event_sender_universe: Sender<TimedInputEvent<C>>,
 
     input_state_ui: Cell<Option<InputState<C>>>,
     input_state_universe: Cell<Option<InputState<C>>>,
+
+    gamepad_poller: RefCell<GamepadPoller>,
+    rumble_receiver: mpsc::Receiver<RumbleRequest>,
 }
 
 #[allow(clippy::new_without_default)]
 impl<C: CommandType> Input<C> {
     pub fn new() -> Input<C> {
-        let (input_event_sender_ui, input_event_receiver_ui) = mpsc::channel::<InputEvent<C>>();
-        let (input_event_sender_universe, input_event_receiver_universe) = mpsc::channel::<InputEvent<C>>();
-
-        let input_state_ui = Cell::new(Some(InputState::new(
-            input_event_receiver_ui,
-            [input_event_sender_ui.clone(), input_event_sender_universe.clone()],
-        )));
-        let input_state_universe = Cell::new(Some(InputState::new(
-            input_event_receiver_universe,
-            [input_event_sender_ui.clone(), input_event_sender_universe.clone()],
-        )));
+        let (input_event_sender_ui, input_event_receiver_ui) = mpsc::channel::<TimedInputEvent<C>>();
+        let (input_event_sender_universe, input_event_receiver_universe) = mpsc::channel::<TimedInputEvent<C>>();
+        let (rumble_sender, rumble_receiver) = mpsc::channel();
+
+        let input_state_ui = Cell::new(Some(
+            InputState::new(
+                input_event_receiver_ui,
+                [input_event_sender_ui.clone(), input_event_sender_universe.clone()],
+            )
+            .with_rumble_sender(rumble_sender.clone()),
+        ));
+        let input_state_universe = Cell::new(Some(
+            InputState::new(
+                input_event_receiver_universe,
+                [input_event_sender_ui.clone(), input_event_sender_universe.clone()],
+            )
+            .with_rumble_sender(rumble_sender),
+        ));
 
         Self {
             input_event_sender_ui,
@@ -68,6 +329,9 @@ impl<C: CommandType> Input<C> {
 
             input_state_ui,
             input_state_universe,
+
+            gamepad_poller: RefCell::new(GamepadPoller::default()),
+            rumble_receiver,
         }
     }
 
@@ -83,6 +347,13 @@ impl<C: CommandType> Input<C> {
             .expect("Only one input state for universe exists. This should be called only once.")
     }
 
+    /// Sends the event to both input states with the same timestamp.
+    fn send_to_states(&self, event: InputEvent<C>) {
+        let event = TimedInputEvent::from(event);
+        self.input_event_sender_ui.send(event.clone()).unwrap();
+        self.input_event_sender_universe.send(event).unwrap();
+    }
+
     pub(crate) fn handle_keyboard_event(&self, event: &WindowEvent) {
         if let WindowEvent::KeyboardInput {
             event, is_synthetic, ..
@@ -96,23 +367,12 @@ impl<C: CommandType> Input<C> {
                 match event.state {
                     ElementState::Pressed => {
                         if let Code(keycode) = event.physical_key {
-                            self.input_event_sender_ui
-                                .send(InputEvent::KeyPressed(keycode, char_opt))
-                                .unwrap();
-
-                            self.input_event_sender_universe
-                                .send(InputEvent::KeyPressed(keycode, char_opt))
-                                .unwrap();
+                            self.send_to_states(InputEvent::KeyPressed(keycode, char_opt));
                         }
                     }
                     ElementState::Released => {
                         if let Code(keycode) = event.physical_key {
-                            self.input_event_sender_ui
-                                .send(InputEvent::KeyReleased(keycode, char_opt))
-                                .unwrap();
-                            self.input_event_sender_universe
-                                .send(InputEvent::KeyReleased(keycode, char_opt))
-                                .unwrap();
+                            self.send_to_states(InputEvent::KeyReleased(keycode, char_opt));
                         }
                     }
                 }
@@ -124,21 +384,10 @@ impl<C: CommandType> Input<C> {
         if let WindowEvent::MouseInput { state, button, .. } = event {
             match state {
                 ElementState::Pressed => {
-                    self.input_event_sender_ui
-                        .send(InputEvent::MouseButtonPressed(*button))
-                        .unwrap();
-                    self.input_event_sender_universe
-                        .send(InputEvent::MouseButtonPressed(*button))
-                        .unwrap();
+                    self.send_to_states(InputEvent::MouseButtonPressed(*button));
                 }
                 ElementState::Released => {
-                    self.input_event_sender_ui
-                        .send(InputEvent::MouseButtonReleased(*button))
-                        .unwrap();
-
-                    self.input_event_sender_universe
-                        .send(InputEvent::MouseButtonReleased(*button))
-                        .unwrap();
+                    self.send_to_states(InputEvent::MouseButtonReleased(*button));
                 }
             }
         }
@@ -151,23 +400,37 @@ impl<C: CommandType> Input<C> {
                 MouseScrollDelta::PixelDelta(value) => value.y as f32 / 1000.,
             };
 
-            self.input_event_sender_ui
-                .send(InputEvent::MouseScrollChange(delta))
-                .unwrap();
-            self.input_event_sender_universe
-                .send(InputEvent::MouseScrollChange(delta))
-                .unwrap();
+            self.send_to_states(InputEvent::MouseScrollChange(delta));
         }
     }
 
-    pub(crate) fn handle_mouse_move_event(&self, loc: Location, pos: Position) {
-        self.input_event_sender_ui
-            .send(InputEvent::CursorLocPosChange(loc, pos))
-            .unwrap();
+    /// Handles relative motion of the mouse device, in physical pixels without cursor acceleration.
+    /// Motion is reported also when the cursor is locked or against the border of the window.
+    pub(crate) fn handle_mouse_motion_event(&self, delta: (f64, f64)) {
+        let event = InputEvent::MouseMotion(delta.0 as f32, delta.1 as f32);
+        self.send_to_states(event);
+    }
 
-        self.input_event_sender_universe
-            .send(InputEvent::CursorLocPosChange(loc, pos))
-            .unwrap();
+    /// Sends the gamepad changes since the previous poll. Gamepads don't emit window events, so this is polled every frame.
+    /// Rumble patterns requested by input states are played from here as well.
+    pub(crate) fn poll_gamepads(&self) {
+        let mut gamepad_poller = self.gamepad_poller.borrow_mut();
+        for (id, pattern) in self.rumble_receiver.try_iter() {
+            gamepad_poller.set_rumble(id, pattern);
+        }
+        for event in gamepad_poller.poll() {
+            self.send_to_states(event);
+        }
+    }
+
+    /// Handles a touch on the window. The aspect ratio of the window is needed for measuring gestures.
+    pub(crate) fn handle_touch_event(&self, id: u64, phase: TouchPhase, pos: Position, aspect_ratio: f32) {
+        let event = InputEvent::Touch(id, phase, pos, aspect_ratio);
+        self.send_to_states(event);
+    }
+
+    pub(crate) fn handle_mouse_move_event(&self, loc: Location, pos: Position) {
+        self.send_to_states(InputEvent::CursorLocPosChange(loc, pos));
     }
 }
 
@@ -0,0 +1,327 @@
+use egui::{Align2, Color32, FontId, Id, LayerId, Order, Pos2, Rect, Stroke, TouchPhase, Vec2};
+use winit::keyboard::KeyCode;
+
+use crate::core::world::CommandType;
+
+use super::{
+    InputEvent,
+    gamepad::{GamepadAxis, GamepadId},
+    input_state::InputState,
+};
+
+/// Gamepad id of the virtual joystick. Its stick is reported as the left stick of this gamepad.
+pub const VIRTUAL_GAMEPAD_ID: GamepadId = GamepadId::MAX;
+
+const MARGIN: f32 = 24.0;
+const JOYSTICK_RADIUS: f32 = 64.0;
+const BUTTON_RADIUS: f32 = 36.0;
+const BUTTON_SPACING: f32 = 16.0;
+
+/// Stick deflection at which the direction keys of the joystick are pressed.
+const JOYSTICK_KEY_THRESHOLD: f32 = 0.5;
+
+/// On-screen joystick and buttons for touch screens that have no keyboard, like mobile browsers.
+///
+/// Touches on the controls are turned into key presses, so commands bound to keys work as is.
+/// The joystick also reports its exact position as the left stick of the [`VIRTUAL_GAMEPAD_ID`] gamepad.
+/// The joystick is drawn to the bottom left corner, and buttons from right to left along the bottom right corner.
+///
+/// The controls are shown by calling `show` every frame with the UI input state. Like key binds,
+/// events reach the input states on the next frame.
+pub struct VirtualControls {
+    joystick: Option<VirtualJoystick>,
+    buttons: Vec<VirtualButton>,
+    connected: bool,
+}
+
+struct VirtualJoystick {
+    /// Keys for up, down, left and right
+    keys: [KeyCode; 4],
+    touch: Option<u64>,
+    value: Vec2,
+}
+
+struct VirtualButton {
+    label: String,
+    key: KeyCode,
+    touch: Option<u64>,
+}
+
+#[allow(clippy::new_without_default)]
+impl VirtualControls {
+    pub fn new() -> Self {
+        Self {
+            joystick: None,
+            buttons: Vec::new(),
+            connected: false,
+        }
+    }
+
+    /// Adds a joystick that presses the given keys for up, down, left and right when pushed far enough.
+    pub fn with_joystick(mut self, keys: [KeyCode; 4]) -> Self {
+        self.joystick = Some(VirtualJoystick {
+            keys,
+            touch: None,
+            value: Vec2::ZERO,
+        });
+        self
+    }
+
+    /// Adds a button that holds down the key while touched.
+    pub fn with_button(mut self, label: &str, key: KeyCode) -> Self {
+        self.buttons.push(VirtualButton {
+            label: label.to_string(),
+            key,
+            touch: None,
+        });
+        self
+    }
+
+    /// Handles the touches of this frame and draws the controls on top of the rest of the UI.
+    pub fn show<C: CommandType>(&mut self, ctx: &egui::Context, input_state: &InputState<C>) {
+        let screen_rect = ctx.screen_rect();
+        let touches: Vec<_> = ctx.input(|input| {
+            input
+                .events
+                .iter()
+                .filter_map(|event| match event {
+                    egui::Event::Touch { id, phase, pos, .. } => Some((id.0, *phase, *pos)),
+                    _ => None,
+                })
+                .collect()
+        });
+
+        let mut events = Vec::new();
+        for (id, phase, pos) in touches {
+            self.handle_touch(screen_rect, id, phase, pos, &mut events);
+        }
+        for event in events {
+            input_state.send_event(event);
+        }
+
+        self.paint(ctx, screen_rect);
+    }
+
+    fn joystick_center(screen_rect: Rect) -> Pos2 {
+        Pos2::new(
+            screen_rect.left() + MARGIN + JOYSTICK_RADIUS,
+            screen_rect.bottom() - MARGIN - JOYSTICK_RADIUS,
+        )
+    }
+
+    fn button_center(screen_rect: Rect, index: usize) -> Pos2 {
+        Pos2::new(
+            screen_rect.right() - MARGIN - BUTTON_RADIUS - index as f32 * (2.0 * BUTTON_RADIUS + BUTTON_SPACING),
+            screen_rect.bottom() - MARGIN - BUTTON_RADIUS,
+        )
+    }
+
+    fn handle_touch<C: CommandType>(
+        &mut self,
+        screen_rect: Rect,
+        id: u64,
+        phase: TouchPhase,
+        pos: Pos2,
+        events: &mut Vec<InputEvent<C>>,
+    ) {
+        if !self.connected {
+            self.connected = true;
+            if self.joystick.is_some() {
+                events.push(InputEvent::GamepadConnected(VIRTUAL_GAMEPAD_ID));
+            }
+        }
+
+        let touch_ended = matches!(phase, TouchPhase::End | TouchPhase::Cancel);
+
+        if let Some(joystick) = &mut self.joystick {
+            let center = Self::joystick_center(screen_rect);
+            let grabbed = match phase {
+                TouchPhase::Start => joystick.touch.is_none() && pos.distance(center) <= JOYSTICK_RADIUS,
+                _ => joystick.touch == Some(id),
+            };
+
+            if grabbed {
+                let value = if touch_ended {
+                    joystick.touch = None;
+                    Vec2::ZERO
+                } else {
+                    joystick.touch = Some(id);
+                    let offset = (pos - center) / JOYSTICK_RADIUS;
+                    offset / offset.length().max(1.0)
+                };
+                joystick.set_value(value, events);
+            }
+        }
+
+        for (i, button) in self.buttons.iter_mut().enumerate() {
+            let on_button = pos.distance(Self::button_center(screen_rect, i)) <= BUTTON_RADIUS;
+            match phase {
+                TouchPhase::Start if button.touch.is_none() && on_button => {
+                    button.touch = Some(id);
+                    events.push(InputEvent::KeyPressed(button.key, None));
+                }
+                _ if button.touch == Some(id) && (touch_ended || !on_button) => {
+                    // Sliding off the button releases it
+                    button.touch = None;
+                    events.push(InputEvent::KeyReleased(button.key, None));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn paint(&self, ctx: &egui::Context, screen_rect: Rect) {
+        let painter = ctx.layer_painter(LayerId::new(Order::Foreground, Id::new("virtual_controls")));
+        let idle = Color32::from_white_alpha(40);
+        let active = Color32::from_white_alpha(110);
+        let stroke = Stroke::new(2.0, Color32::from_white_alpha(120));
+
+        if let Some(joystick) = &self.joystick {
+            let center = Self::joystick_center(screen_rect);
+            painter.circle(center, JOYSTICK_RADIUS, idle, stroke);
+            let knob_color = if joystick.touch.is_some() { active } else { idle };
+            painter.circle(
+                center + joystick.value * JOYSTICK_RADIUS,
+                JOYSTICK_RADIUS / 2.5,
+                knob_color,
+                stroke,
+            );
+        }
+
+        for (i, button) in self.buttons.iter().enumerate() {
+            let center = Self::button_center(screen_rect, i);
+            let color = if button.touch.is_some() { active } else { idle };
+            painter.circle(center, BUTTON_RADIUS, color, stroke);
+            painter.text(
+                center,
+                Align2::CENTER_CENTER,
+                &button.label,
+                FontId::proportional(BUTTON_RADIUS / 2.0),
+                Color32::WHITE,
+            );
+        }
+    }
+}
+
+impl VirtualJoystick {
+    fn set_value<C: CommandType>(&mut self, value: Vec2, events: &mut Vec<InputEvent<C>>) {
+        let previous = self.value;
+        self.value = value;
+
+        if value.x != previous.x {
+            events.push(InputEvent::GamepadAxisChange(
+                VIRTUAL_GAMEPAD_ID,
+                GamepadAxis::LeftStickX,
+                value.x,
+            ));
+        }
+        if value.y != previous.y {
+            events.push(InputEvent::GamepadAxisChange(
+                VIRTUAL_GAMEPAD_ID,
+                GamepadAxis::LeftStickY,
+                value.y,
+            ));
+        }
+
+        let [up, down, left, right] = self.keys;
+        for (key, was_pressed, pressed) in [
+            (up, -previous.y, -value.y),
+            (down, previous.y, value.y),
+            (left, -previous.x, -value.x),
+            (right, previous.x, value.x),
+        ]
+        .map(|(key, previous, current)| {
+            (
+                key,
+                previous >= JOYSTICK_KEY_THRESHOLD,
+                current >= JOYSTICK_KEY_THRESHOLD,
+            )
+        }) {
+            if pressed && !was_pressed {
+                events.push(InputEvent::KeyPressed(key, None));
+            } else if was_pressed && !pressed {
+                events.push(InputEvent::KeyReleased(key, None));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bincode::{Decode, Encode};
+
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Encode, Decode)]
+    enum TestCommand {
+        Test,
+    }
+    impl CommandType for TestCommand {}
+
+    const SCREEN: Rect = Rect {
+        min: Pos2::new(0.0, 0.0),
+        max: Pos2::new(800.0, 600.0),
+    };
+
+    fn touch(controls: &mut VirtualControls, id: u64, phase: TouchPhase, pos: Pos2) -> Vec<InputEvent<TestCommand>> {
+        let mut events = Vec::new();
+        controls.handle_touch(SCREEN, id, phase, pos, &mut events);
+        events
+    }
+
+    #[test]
+    fn joystick_presses_direction_keys() {
+        let mut controls =
+            VirtualControls::new().with_joystick([KeyCode::KeyW, KeyCode::KeyS, KeyCode::KeyA, KeyCode::KeyD]);
+        let center = VirtualControls::joystick_center(SCREEN);
+
+        let events = touch(&mut controls, 1, TouchPhase::Start, center);
+        assert!(matches!(events[..], [InputEvent::GamepadConnected(VIRTUAL_GAMEPAD_ID)]));
+
+        // Pushing up past the edge is clamped to full deflection
+        let events = touch(
+            &mut controls,
+            1,
+            TouchPhase::Move,
+            center - Vec2::new(0.0, 2.0 * JOYSTICK_RADIUS),
+        );
+        assert!(matches!(
+            events[..],
+            [
+                InputEvent::GamepadAxisChange(VIRTUAL_GAMEPAD_ID, GamepadAxis::LeftStickY, -1.0),
+                InputEvent::KeyPressed(KeyCode::KeyW, None)
+            ]
+        ));
+
+        let events = touch(&mut controls, 1, TouchPhase::End, center);
+        assert!(matches!(
+            events[..],
+            [
+                InputEvent::GamepadAxisChange(VIRTUAL_GAMEPAD_ID, GamepadAxis::LeftStickY, 0.0),
+                InputEvent::KeyReleased(KeyCode::KeyW, None)
+            ]
+        ));
+    }
+
+    #[test]
+    fn buttons_follow_their_own_touches() {
+        let mut controls = VirtualControls::new()
+            .with_button("A", KeyCode::Space)
+            .with_button("B", KeyCode::KeyE);
+        let button_a = VirtualControls::button_center(SCREEN, 0);
+        let button_b = VirtualControls::button_center(SCREEN, 1);
+
+        let events = touch(&mut controls, 1, TouchPhase::Start, button_a);
+        assert!(matches!(events[..], [InputEvent::KeyPressed(KeyCode::Space, None)]));
+        let events = touch(&mut controls, 2, TouchPhase::Start, button_b);
+        assert!(matches!(events[..], [InputEvent::KeyPressed(KeyCode::KeyE, None)]));
+
+        // Touches that don't start on the controls are ignored
+        assert!(touch(&mut controls, 3, TouchPhase::Start, Pos2::new(400.0, 100.0)).is_empty());
+
+        let events = touch(&mut controls, 1, TouchPhase::Move, Pos2::new(400.0, 100.0));
+        assert!(matches!(events[..], [InputEvent::KeyReleased(KeyCode::Space, None)]));
+        let events = touch(&mut controls, 2, TouchPhase::End, button_b);
+        assert!(matches!(events[..], [InputEvent::KeyReleased(KeyCode::KeyE, None)]));
+    }
+}
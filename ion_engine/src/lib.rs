@@ -1,22 +1,27 @@
 //! # Ion Engine
 //!
 //! The Ion Engine is a game engine for creating 2.5D games.
-//! See the [crate::run] function for the entry point to the engine and documentation.
+//! See the [crate::run] function for the entry point to the engine and documentation,
+//! and [crate::run_headless] for running dedicated servers.
 
 use core::{
-    Constants, RenderFrameProps,
+    Constants, FrameId, HeadlessFrameProps, RenderFrameProps,
     application::run_render_loop,
+    autosave::Autosave,
     coordinates::ChunkLocation,
     universe::{Universe, UniverseDataType},
     world::{ActionType, UiDataType, WorldId, WorldType},
 };
 use gfx::*;
-use input::Input;
-use ion_common::Instant;
+use input::{Input, input_state::InputState};
+use ion_common::net::NetworkPlayerInfo;
 #[cfg(not(target_arch = "wasm32"))]
 use ion_common::util::native_spin_sleep;
+use ion_common::util::signals;
+use ion_common::{Instant, Map, PlayerId, log_error, log_info};
+use std::collections::BTreeMap;
 use std::sync::{
-    Arc,
+    Arc, MutexGuard,
     atomic::{AtomicBool, Ordering},
     mpsc,
 };
@@ -24,8 +29,8 @@ use std::time::Duration;
 use util::concurrency::spawn_thread;
 
 use crate::{
-    core::{UniverseFrameProps, application::ApplicationEvent, world::CommandType},
-    files::Files,
+    core::{PlayerMove, UniverseFrameProps, application::ApplicationEvent, world::CommandType},
+    files::{Files, asset_error::AssetError, file_paths, file_watcher::spawn_file_watcher},
     net::{Network, NetworkEvent},
 };
 
@@ -40,6 +45,9 @@ pub mod input;
 pub mod net;
 pub mod util;
 
+/// Time given for the shutdown notice to reach clients before the process exits.
+const SHUTDOWN_NOTICE_GRACE: Duration = Duration::from_millis(500);
+
 /// Entry point to the Ion game engine.
 ///
 /// This function initializes and runs the game engine with separate universe simulation and rendering threads.
@@ -145,11 +153,30 @@ pub mod util;
 /// the universe and render threads run independently with precise timing control. On web,
 /// the threads are synchronized and rely on browser vsync for frame pacing.
 /// Additionally, the WASM platform has some limitations compared to native:
-/// - No multiplayer support, since it is not possible to send UDP packets from WASM.
+/// - Multiplayer only as a client. Browsers can't send UDP packets, so they join over WebSocket, which the server must
+///   have enabled with [`Network::mp_start_ws_bridge`](net::Network::mp_start_ws_bridge). Hosting a server and the
+///   server browser are not supported.
 /// - No fully independent render / universe threads. Instead they run in sync.
 /// - No native file system access
 /// - No debug tools or debug rendering
 ///
+/// ## Shutdown signals
+///
+/// On unix platforms, SIGINT and SIGTERM are handled by the engine. Connected multiplayer clients are notified,
+/// the universe is written to the save set with [`Universe::set_shutdown_save`], and the process exits.
+/// If the final save fails, the exit code is nonzero.
+///
+/// ## Autosaves
+///
+/// With [`Constants::autosave`] set, the running universe is saved periodically between universe frames, and written
+/// in the background. See [`core::AutosaveConstants`].
+///
+/// ## Hot reload
+///
+/// With [`Constants::file_watch_interval`] set, changes to the files of the asset, config and mod directories are
+/// reported with [`ApplicationEvent::FileChanged`](core::application::ApplicationEvent::FileChanged), so that the game
+/// can reload them. Not available on wasm.
+///
 /// ## Major missing features
 /// - Multiplayer support (Old version in place but does not work yet)
 pub fn run<F, U, W, C, A, D>(constants: Constants, mut on_render_frame: F)
@@ -163,15 +190,28 @@ where
 {
     let (app_event_sender, app_event_receiver) = mpsc::channel::<ApplicationEvent>();
     let (network_event_sender, network_event_receiver) = mpsc::channel::<NetworkEvent>();
+    let (asset_error_sender, asset_error_receiver) = mpsc::channel::<AssetError>();
     let (gfx_data_sender, gfx_data_receiver) = mpsc::sync_channel::<(GfxFrameData, D)>(0);
 
     let engine_running = Arc::new(AtomicBool::new(true));
 
     let files: Files = Files::new(&constants);
+    files.set_app_event_sender(app_event_sender.clone());
     let input: Input<W::CommandType> = Input::new();
-    let network: Arc<Network<W>> = Arc::new(Network::new(&constants, network_event_sender.clone()));
+
+    if let Some(interval) = constants.file_watch_interval
+        && cfg!(not(target_arch = "wasm32"))
+    {
+        let mut watched_dirs = vec![constants.gfx.asset_path.clone(), file_paths::config_dir(constants.app_name)];
+        watched_dirs.extend(files.enabled_mod_dirs());
+        spawn_file_watcher(watched_dirs, interval, app_event_sender.clone(), engine_running.clone());
+    }
+
+    let network: Arc<Network<W>> = Arc::new(Network::new(&constants, files.clone(), network_event_sender.clone()));
     let universe: Arc<Universe<W>> = Arc::new(Universe::new());
 
+    signals::register_shutdown_signals();
+
     // ---------------------------------------------------------- //
     // -------------------- Universe loop ----------------------- //
     // ---------------------------------------------------------- //
@@ -185,53 +225,38 @@ where
         let universe = universe.clone();
 
         let engine_running = engine_running.clone();
+        let files = files.clone();
+        let app_event_sender = app_event_sender.clone();
 
         let mut input_state = input.input_state_universe();
+        let mut autosave = Autosave::new(constants.autosave.clone());
 
         move || {
             while engine_running.load(Ordering::Relaxed) {
+                if signals::shutdown_signal_received() {
+                    shutdown_on_signal(&universe, &network, &files);
+                }
+
                 if universe.is_running() {
                     let mut worlds_data_lock = universe.lock_worlds_data();
                     let universe_data_lock = universe.lock_universe_data();
                     let universe_data = universe_data_lock.as_ref().unwrap();
 
-                    let sync_results = {
-                        input_state.handle_received_input_events();
-
-                        let (stateful_actions, stateless_actions) =
-                            universe.build_actions(&mut worlds_data_lock, &input_state);
-
-                        input_state.clear_one_frame_statuses();
-
-                        network.mp_sync_actions(
-                            universe_data.active_player(),
-                            stateful_actions,
-                            stateless_actions,
-                            &universe,
-                            &mut worlds_data_lock,
-                        )
-                    };
-
-                    if let Some(sync_results) = sync_results {
-                        // TODO: Multithreaded universe frame execution
-                        for world in worlds_data_lock.values_mut() {
-                            let frame_props = UniverseFrameProps {
-                                universe_data,
-                                players_joining: &sync_results.players_joined,
-                                players_leaving: &sync_results.players_left,
-                                actions: sync_results.actions.get(&world.id()).unwrap(),
-                            };
-                            world.execute_on_universe_frame(frame_props);
-                        }
-
-                        universe.next_frame();
+                    let sync_result = execute_synced_frame(
+                        &universe,
+                        &network,
+                        &mut input_state,
+                        &mut worlds_data_lock,
+                        universe_data,
+                    );
 
+                    if let Some(is_at_sync) = sync_result {
                         if let Some(active_world_id) = universe.active_world_id() {
                             if prev_frame_active_world_id != Some(active_world_id) {
                                 prev_frame_render_chunks.clear();
                             }
 
-                            if sync_results.is_at_sync {
+                            if is_at_sync {
                                 let active_world = worlds_data_lock.get_mut(&active_world_id).unwrap();
                                 let (global_data, sprite_data, debug_data) = {
                                     active_world.build_render_data(universe.active_frame(), &prev_frame_render_chunks)
@@ -285,6 +310,11 @@ where
                     //TODO: Sleep here for a bit to avoid busy-waiting
                 }
 
+                // Between frames the universe is unlocked, so it can be autosaved as it is
+                if let Some(event) = autosave.update(&universe, &files) {
+                    app_event_sender.send(event).ok();
+                }
+
                 universe_frame_duration = universe_frame_last.elapsed();
                 universe_frame_last = Instant::now();
             }
@@ -303,91 +333,374 @@ where
 
     let mut input_state = input.input_state_ui();
 
-    run_render_loop(constants, input, app_event_sender, move |renderer| {
-        // --------------------- Sync universe thread --------------------- //
+    run_render_loop(
+        constants,
+        input,
+        app_event_sender,
+        asset_error_sender,
+        move |renderer| {
+            // --------------------- Sync universe thread --------------------- //
 
-        if universe.is_running() {
-            let universe_frame_time = universe.universe_frame_time();
-            universe_frame_time_accumulated += render_frame_duration;
+            if universe.is_running() {
+                let universe_frame_time = universe.paced_frame_time();
+                universe_frame_time_accumulated += render_frame_duration;
 
-            if WASM_COMPATIBLE_RENDERING {
-                if let Ok(gfx_data) = gfx_data_receiver.try_recv() {
-                    latest_gfx_data = Some(gfx_data);
-                }
-                universe_frame_time_accumulated = Duration::ZERO;
-            } else {
-                while universe_frame_time_accumulated >= universe_frame_time {
+                if WASM_COMPATIBLE_RENDERING {
                     if let Ok(gfx_data) = gfx_data_receiver.try_recv() {
                         latest_gfx_data = Some(gfx_data);
                     }
-                    universe_frame_time_accumulated -= universe_frame_time;
+                    universe_frame_time_accumulated = Duration::ZERO;
+                } else {
+                    while universe_frame_time_accumulated >= universe_frame_time {
+                        if let Ok(gfx_data) = gfx_data_receiver.try_recv() {
+                            latest_gfx_data = Some(gfx_data);
+                        }
+                        universe_frame_time_accumulated -= universe_frame_time;
+                    }
+                }
+
+                if let Some(gfx_data) = &mut latest_gfx_data {
+                    gfx_data.0.timing_data.render_frame_duration = render_frame_duration;
+                    gfx_data.0.timing_data.render_frame_offset =
+                        universe_frame_time_accumulated.as_micros() as f32 / universe_frame_time.as_micros() as f32;
                 }
             }
 
+            let (gfx_data, ui_data) = match &latest_gfx_data {
+                Some((gfx_data, ui_data)) => (Some(gfx_data), Some(ui_data)),
+                None => (None, None),
+            };
+
+            renderer.pre_render(gfx_data);
+
+            // ----------------- Run game logic for render loop ----------------- //
+
+            input_state.handle_camera_movement(&renderer.camera());
+            input_state.handle_received_input_events();
+
+            let ui_ctx = renderer.ui_begin_pass();
+
+            on_render_frame(RenderFrameProps {
+                engine_running: engine_running.clone(),
+
+                renderer: renderer,
+                universe: &universe,
+                files: &files,
+
+                gfx_data: gfx_data,
+                ui_input_state: &input_state,
+                ui_data: ui_data,
+                ui_ctx: &ui_ctx,
+
+                app_events: &app_event_receiver,
+                network_events: &network_event_receiver,
+                asset_errors: &asset_error_receiver,
+            });
+
+            input_state.clear_one_frame_statuses();
+
+            // ------------------------ Execute the render ---------------------- //
+
+            renderer.render(gfx_data);
+
             if let Some(gfx_data) = &mut latest_gfx_data {
-                gfx_data.0.timing_data.render_frame_duration = render_frame_duration;
-                gfx_data.0.timing_data.render_frame_offset =
-                    universe_frame_time_accumulated.as_micros() as f32 / universe_frame_time.as_micros() as f32;
+                gfx_data.0.timing_data.render_data_use_count += 1;
             }
-        }
 
-        let (gfx_data, ui_data) = match &latest_gfx_data {
-            Some((gfx_data, ui_data)) => (Some(gfx_data), Some(ui_data)),
-            None => (None, None),
-        };
+            renderer.post_render();
+
+            // Can't really sleep accurately on web platform, so we just skip it.
+            // On web frame pacing should be done by vsync.
+            #[cfg(not(target_arch = "wasm32"))]
+            if let Some(frame_time_cap) = renderer.config().frame_time_cap() {
+                if let Some(target_sleep_time) = Instant::now()
+                    .duration_since(render_frame_last)
+                    .and_then(|d| frame_time_cap.checked_sub(d))
+                {
+                    native_spin_sleep(target_sleep_time);
+                }
+            }
 
-        renderer.pre_render(gfx_data);
+            render_frame_duration = render_frame_last.elapsed();
+            render_frame_last = Instant::now();
 
-        // ----------------- Run game logic for render loop ----------------- //
+            engine_running.load(Ordering::Relaxed)
+        },
+    )
+}
 
-        input_state.handle_camera_movement(&renderer.camera());
-        input_state.handle_received_input_events();
+/// Entry point to the Ion game engine for dedicated servers.
+///
+/// Runs the universe on the calling thread without a window, renderer or input, so that the same game crate can be
+/// shipped as a headless server. The universe runs at the universe speed, see [`Universe::set_universe_speed`].
+///
+/// `on_server_frame` is called once for each universe frame, and also while the universe is not running.
+/// It receives a [`HeadlessFrameProps`], which gives access to the network for starting the multiplayer server.
+/// The engine shuts down when `engine_running` is set to false, or on a shutdown signal (see [`run`]).
+/// Connected clients are notified before the server stops.
+///
+/// Only supported on native platforms.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn run_headless<F, U, W, C, A, D>(constants: Constants, mut on_server_frame: F)
+where
+    F: FnMut(HeadlessFrameProps<W>),
+    U: UniverseDataType<WorldType = W>,
+    W: WorldType<ActionType = A, UiDataType = D, UniverseDataType = U>,
+    C: CommandType,
+    A: ActionType,
+    D: UiDataType,
+{
+    util::init_os();
 
-        let ui_ctx = renderer.ui_begin_pass();
+    log_info!("Starting up {} as a headless server", constants.app_name);
 
-        on_render_frame(RenderFrameProps {
-            engine_running: engine_running.clone(),
+    let (network_event_sender, network_event_receiver) = mpsc::channel::<NetworkEvent>();
 
-            renderer: renderer,
-            universe: &universe,
-            files: &files,
+    let engine_running = Arc::new(AtomicBool::new(true));
+
+    let files: Files = Files::new(&constants);
+    let input: Input<W::CommandType> = Input::new();
+    let network: Network<W> = Network::new(&constants, files.clone(), network_event_sender);
+    let universe: Universe<W> = Universe::new();
+
+    signals::register_shutdown_signals();
+
+    let mut input_state = input.input_state_universe();
+    // Without application events, finished autosaves are only logged
+    let mut autosave = Autosave::new(constants.autosave.clone());
 
-            gfx_data: gfx_data,
-            ui_input_state: &input_state,
-            ui_data: ui_data,
-            ui_ctx: &ui_ctx,
+    while engine_running.load(Ordering::Relaxed) {
+        let frame_start = Instant::now();
+
+        if signals::shutdown_signal_received() {
+            shutdown_on_signal(&universe, &network, &files);
+        }
 
-            app_events: &app_event_receiver,
+        if universe.is_running() {
+            let mut worlds_data_lock = universe.lock_worlds_data();
+            let universe_data_lock = universe.lock_universe_data();
+            let universe_data = universe_data_lock.as_ref().unwrap();
+
+            let sync_result = execute_synced_frame(
+                &universe,
+                &network,
+                &mut input_state,
+                &mut worlds_data_lock,
+                universe_data,
+            );
+
+            if sync_result.is_none() {
+                // Command syncing failed, unloading universe
+                drop(worlds_data_lock);
+                universe.pause();
+                universe.unload_universe();
+            }
+        } else {
+            universe.clear_actions();
+        }
+
+        autosave.update(&universe, &files);
+
+        on_server_frame(HeadlessFrameProps {
+            engine_running: engine_running.clone(),
+            universe: &universe,
+            network: &network,
+            files: &files,
             network_events: &network_event_receiver,
         });
 
-        input_state.clear_one_frame_statuses();
+        if let Some(sleep_time) = Instant::now()
+            .duration_since(frame_start)
+            .and_then(|elapsed| universe.paced_frame_time().checked_sub(elapsed))
+        {
+            native_spin_sleep(sleep_time);
+        }
+    }
+
+    log_info!("Shutting down {}", constants.app_name);
 
-        // ------------------------ Execute the render ---------------------- //
+    network.mp_broadcast_shutdown("Server is shutting down");
+    std::thread::sleep(SHUTDOWN_NOTICE_GRACE);
+    network.mp_stop_client_server();
 
-        renderer.render(gfx_data);
+    ion_common::flush_logs();
+    util::uninit_os();
+}
+
+/// Syncs the actions of the active universe frame over the network and executes it, along with any earlier frames
+/// that must be executed again first. Returns whether the universe is at sync, or none if syncing failed.
+fn execute_synced_frame<W: WorldType>(
+    universe: &Universe<W>,
+    network: &Network<W>,
+    input_state: &mut InputState<W::CommandType>,
+    worlds_data_lock: &mut MutexGuard<Map<WorldId, W>>,
+    universe_data: &W::UniverseDataType,
+) -> Option<bool> {
+    input_state.handle_received_input_events();
+
+    let (stateful_actions, stateless_actions) = universe.build_actions(worlds_data_lock, input_state);
+
+    input_state.clear_one_frame_statuses();
+
+    // Replays take the actions from the recording instead of the players
+    if universe.is_playing_replay() {
+        let Some(replay_frame) = universe.next_replay_frame() else {
+            universe.pause();
+            return Some(true);
+        };
+        execute_universe_frame(
+            worlds_data_lock,
+            universe_data,
+            replay_frame.frame,
+            &replay_frame.players_joined,
+            &replay_frame.players_left,
+            &replay_frame.players_moved,
+            &replay_frame.actions,
+        );
+        universe.next_frame();
+        return Some(true);
+    }
+
+    let sync_results = network.mp_sync_actions(
+        universe_data.active_player(),
+        stateful_actions,
+        stateless_actions,
+        universe,
+        worlds_data_lock,
+    )?;
+
+    // Rollback clients execute again the frames that were executed with mispredicted actions
+    for resimulated in &sync_results.resimulated_frames {
+        network.mp_store_rollback_snapshots(resimulated.frame, worlds_data_lock);
+        execute_universe_frame(
+            worlds_data_lock,
+            universe_data,
+            resimulated.frame,
+            &resimulated.players_joined,
+            &resimulated.players_left,
+            &resimulated.players_moved,
+            &resimulated.actions,
+        );
+        universe.record_replay_frame(
+            resimulated.frame,
+            &resimulated.players_joined,
+            &resimulated.players_left,
+            &resimulated.players_moved,
+            &resimulated.actions,
+        );
+    }
+
+    // Snapshot clients take the state from the server instead of executing stateful actions
+    if let Some(server_snapshot) = &sync_results.server_snapshot {
+        for world in worlds_data_lock.values_mut() {
+            let previous = server_snapshot.previous.get(&world.id());
+            let next = server_snapshot.next.get(&world.id());
+            if let (Some(previous), Some(next)) = (previous, next) {
+                world.apply_server_snapshot(previous, next, server_snapshot.t);
+            }
+        }
+    }
+
+    network.mp_store_rollback_snapshots(universe.active_frame(), worlds_data_lock);
+    execute_universe_frame(
+        worlds_data_lock,
+        universe_data,
+        universe.active_frame(),
+        &sync_results.players_joined,
+        &sync_results.players_left,
+        &sync_results.players_moved,
+        &sync_results.actions,
+    );
+    universe.record_replay_frame(
+        universe.active_frame(),
+        &sync_results.players_joined,
+        &sync_results.players_left,
+        &sync_results.players_moved,
+        &sync_results.actions,
+    );
+    network.mp_send_state_snapshots(universe.active_frame(), worlds_data_lock);
+    network.mp_check_world_checksums(universe.active_frame(), worlds_data_lock);
+    network.mp_process_voice();
+
+    universe.next_frame();
+
+    Some(sync_results.is_at_sync)
+}
+
+/// Executes a single universe frame on all worlds, followed by expiring the objects whose time has come.
+/// Players moving between worlds join and leave only the worlds they move to and from.
+fn execute_universe_frame<W: WorldType>(
+    worlds: &mut Map<WorldId, W>,
+    universe_data: &W::UniverseDataType,
+    frame: FrameId,
+    players_joining: &[NetworkPlayerInfo],
+    players_leaving: &[PlayerId],
+    players_moved: &[PlayerMove],
+    actions: &Map<WorldId, BTreeMap<PlayerId, Vec<W::ActionType>>>,
+) {
+    // TODO: Multithreaded universe frame execution
+    for world in worlds.values_mut() {
+        let world_id = world.id();
+        let players_joining: Vec<_> = players_joining
+            .iter()
+            .cloned()
+            .chain(
+                players_moved
+                    .iter()
+                    .filter(|moved| moved.joined_worlds.contains(&world_id))
+                    .map(|moved| moved.player_info.clone()),
+            )
+            .collect();
+        let players_leaving: Vec<_> = players_leaving
+            .iter()
+            .copied()
+            .chain(
+                players_moved
+                    .iter()
+                    .filter(|moved| moved.left_worlds.contains(&world_id))
+                    .map(|moved| moved.player_info.id),
+            )
+            .collect();
+        let frame_props = UniverseFrameProps {
+            universe_data,
+            players_joining: &players_joining,
+            players_leaving: &players_leaving,
+            actions: actions.get(&world_id).unwrap(),
+        };
+        world.execute_on_universe_frame(frame_props);
 
-        if let Some(gfx_data) = &mut latest_gfx_data {
-            gfx_data.0.timing_data.render_data_use_count += 1;
+        if let Some(expiry_queue) = world.expiry_queue() {
+            let expired = expiry_queue.drain_expired(frame);
+            if !expired.is_empty() {
+                world.on_objects_expired(frame, &expired);
+            }
         }
+    }
+}
 
-        renderer.post_render();
-
-        // Can't really sleep accurately on web platform, so we just skip it.
-        // On web frame pacing should be done by vsync.
-        #[cfg(not(target_arch = "wasm32"))]
-        if let Some(frame_time_cap) = renderer.config().frame_time_cap() {
-            if let Some(target_sleep_time) = Instant::now()
-                .duration_since(render_frame_last)
-                .and_then(|d| frame_time_cap.checked_sub(d))
-            {
-                native_spin_sleep(target_sleep_time);
+/// Handles a received shutdown signal. Notifies multiplayer clients, writes the shutdown save and exits the process.
+/// Exits with a nonzero code if the save fails.
+fn shutdown_on_signal<W: WorldType>(universe: &Universe<W>, network: &Network<W>, files: &Files) -> ! {
+    log_info!("Shutdown signal received");
+
+    network.mp_broadcast_shutdown("Server is shutting down");
+    std::thread::sleep(SHUTDOWN_NOTICE_GRACE);
+
+    let mut exit_code = 0;
+    if let Some(save_name) = universe.shutdown_save() {
+        universe.pause();
+        if let Some(save_files) = universe.save_files() {
+            log_info!("Writing shutdown save {}", save_name);
+            if let Err(err) = files.export_save(&save_name, save_files, universe.save_metadata()) {
+                log_error!("Failed to write shutdown save {}: {}", save_name, err);
+                exit_code = 1;
             }
         }
+    }
 
-        render_frame_duration = render_frame_last.elapsed();
-        render_frame_last = Instant::now();
+    network.mp_stop_client_server();
 
-        engine_running.load(Ordering::Relaxed)
-    })
+    ion_common::flush_logs();
+    util::uninit_os();
+    std::process::exit(exit_code)
 }
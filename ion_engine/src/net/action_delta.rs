@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+
+use bincode::{Decode, Encode};
+use ion_common::PlayerId;
+
+use crate::core::FrameId;
+use crate::core::world::{ActionType, WorldId};
+
+use super::rollback::FrameActions;
+
+/// Runs of identical consecutive actions, with the number of repeats.
+type ActionRuns<C> = Vec<(C, u32)>;
+
+// ---------------------------------------------------------- //
+// ------------------ Delta encoded actions ----------------- //
+// ---------------------------------------------------------- //
+
+/// Actions of a frame, encoded as the difference to a frame the receiver already has.
+///
+/// Most players do nothing on most frames, so only the players whose actions differ from the base frame are sent.
+/// Repeated actions are run-length encoded, and bincode packs the remaining integers as varints.
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub(super) struct ActionDelta<C: ActionType> {
+    /// Frame the delta is against. `None` means the delta is against a frame without any worlds or players.
+    pub base_frame: Option<FrameId>,
+    /// All worlds of the frame, if they differ from the worlds of the base frame.
+    worlds: Option<Vec<WorldId>>,
+    changed: Vec<(WorldId, PlayerId, ActionRuns<C>)>,
+    removed: Vec<(WorldId, PlayerId)>,
+}
+
+impl<C: ActionType> ActionDelta<C> {
+    pub(super) fn encode(actions: &FrameActions<C>, base: Option<(FrameId, &FrameActions<C>)>) -> Self {
+        let base_actions = base.map(|(_, base_actions)| base_actions);
+        let base_entry = |world_id: &WorldId, player_id: &PlayerId| {
+            base_actions
+                .and_then(|base_actions| base_actions.get(world_id))
+                .and_then(|players| players.get(player_id))
+        };
+
+        let worlds = Some(world_ids(actions)).filter(|worlds| base_actions.map(world_ids).as_ref() != Some(worlds));
+
+        let mut changed = Vec::new();
+        for (world_id, players) in sorted(actions) {
+            for (player_id, player_actions) in players {
+                if base_entry(world_id, player_id) != Some(player_actions) {
+                    changed.push((*world_id, *player_id, run_length_encode(player_actions)));
+                }
+            }
+        }
+
+        let mut removed = Vec::new();
+        for (world_id, players) in base_actions.map(sorted).unwrap_or_default() {
+            for player_id in players.keys() {
+                let still_present = actions
+                    .get(world_id)
+                    .is_some_and(|players| players.contains_key(player_id));
+                if !still_present {
+                    removed.push((*world_id, *player_id));
+                }
+            }
+        }
+
+        Self {
+            base_frame: base.map(|(base_frame, _)| base_frame),
+            worlds,
+            changed,
+            removed,
+        }
+    }
+
+    /// Rebuilds the actions of the frame from the actions of the base frame.
+    pub(super) fn decode(&self, base: Option<&FrameActions<C>>) -> FrameActions<C> {
+        let mut actions = base.cloned().unwrap_or_default();
+        for (world_id, player_id) in &self.removed {
+            if let Some(players) = actions.get_mut(world_id) {
+                players.remove(player_id);
+            }
+        }
+        if let Some(worlds) = &self.worlds {
+            actions.retain(|world_id, _| worlds.contains(world_id));
+            for world_id in worlds {
+                actions.entry(*world_id).or_default();
+            }
+        }
+        for (world_id, player_id, runs) in &self.changed {
+            actions
+                .entry(*world_id)
+                .or_default()
+                .insert(*player_id, run_length_decode(runs));
+        }
+        actions
+    }
+}
+
+fn sorted<C>(actions: &FrameActions<C>) -> Vec<(&WorldId, &BTreeMap<PlayerId, Vec<C>>)> {
+    let mut worlds: Vec<_> = actions.iter().collect();
+    worlds.sort_by_key(|(world_id, _)| **world_id);
+    worlds
+}
+
+fn world_ids<C>(actions: &FrameActions<C>) -> Vec<WorldId> {
+    sorted(actions).into_iter().map(|(world_id, _)| *world_id).collect()
+}
+
+fn run_length_encode<C: ActionType>(actions: &[C]) -> ActionRuns<C> {
+    let mut runs: ActionRuns<C> = Vec::new();
+    for action in actions {
+        match runs.last_mut() {
+            Some((last, count)) if last == action => *count += 1,
+            _ => runs.push((action.clone(), 1)),
+        }
+    }
+    runs
+}
+
+fn run_length_decode<C: ActionType>(runs: &[(C, u32)]) -> Vec<C> {
+    runs.iter()
+        .flat_map(|(action, count)| std::iter::repeat_n(action.clone(), *count as usize))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use bincode::config;
+    use ion_common::Map;
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Encode, Decode)]
+    enum TestAction {
+        Move(i32, i32),
+        Jump,
+    }
+
+    impl ActionType for TestAction {
+        fn is_stateful(&self) -> bool {
+            true
+        }
+    }
+
+    fn frame(players: &[(PlayerId, Vec<TestAction>)]) -> FrameActions<TestAction> {
+        let mut actions = Map::default();
+        for world_id in 0..2 {
+            let world: &mut BTreeMap<_, _> = actions.entry(world_id).or_default();
+            for (player_id, player_actions) in players {
+                world.insert(*player_id, player_actions.clone());
+            }
+        }
+        actions
+    }
+
+    #[test]
+    fn deltas_reproduce_the_frame() {
+        let mut players: Vec<_> = (0..64).map(|player_id| (player_id, Vec::new())).collect();
+        let base = frame(&players);
+
+        players[3].1 = vec![TestAction::Jump, TestAction::Jump, TestAction::Jump, TestAction::Move(1, -1)];
+        players.remove(10);
+        players.push((100, vec![TestAction::Move(5, 5)]));
+        let next = frame(&players);
+
+        let full = ActionDelta::encode(&next, None);
+        assert_eq!(full.decode(None), next);
+
+        let delta = ActionDelta::encode(&next, Some((7, &base)));
+        assert_eq!(delta.base_frame, Some(7));
+        assert_eq!(delta.decode(Some(&base)), next);
+
+        let full_size = bincode::encode_to_vec(&full, config::standard()).unwrap().len();
+        let delta_size = bincode::encode_to_vec(&delta, config::standard()).unwrap().len();
+        assert!(delta_size * 10 < full_size);
+    }
+}
@@ -0,0 +1,160 @@
+use std::time::Duration;
+
+use bincode::{Decode, Encode, config};
+use ion_common::Instant;
+
+use crate::core::FrameId;
+
+// Size of a single chunk of the join data. Each chunk is sent as one reliable message.
+const CHUNK_SIZE: usize = 32 * 1024;
+// Chunks the server sends per request, so that a large state doesn't fill the send buffer of the socket
+pub(super) const CHUNK_WINDOW: u32 = 8;
+// Time without new chunks after which the client requests the missing chunks again
+pub(super) const CHUNK_STALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+// ---------------------------------------------------------- //
+// ------------------ Join data transfer -------------------- //
+// ---------------------------------------------------------- //
+
+/// State of the universe sent to a joining client.
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub(super) struct JoinUniverseData {
+    pub universe_data: Vec<u8>,
+    pub worlds_data: Vec<Vec<u8>>,
+    pub active_frame: FrameId,
+}
+
+/// Join data encoded and split into chunks by the server, kept until the client has joined.
+pub(super) struct OutgoingJoinData {
+    total_size: u64,
+    chunks: Vec<Vec<u8>>,
+}
+
+impl OutgoingJoinData {
+    pub(super) fn new(data: &JoinUniverseData) -> Self {
+        let bytes = bincode::encode_to_vec(data, config::standard()).unwrap();
+        Self {
+            total_size: bytes.len() as u64,
+            chunks: bytes.chunks(CHUNK_SIZE).map(|chunk| chunk.to_vec()).collect(),
+        }
+    }
+
+    pub(super) fn total_size(&self) -> u64 {
+        self.total_size
+    }
+
+    pub(super) fn chunk_count(&self) -> u32 {
+        self.chunks.len() as u32
+    }
+
+    /// Chunks of the window starting from the given chunk.
+    pub(super) fn window(&self, from_chunk: u32) -> impl Iterator<Item = (u32, &Vec<u8>)> {
+        self.chunks
+            .iter()
+            .enumerate()
+            .skip(from_chunk as usize)
+            .take(CHUNK_WINDOW as usize)
+            .map(|(index, chunk)| (index as u32, chunk))
+    }
+}
+
+/// Chunks of the join data received by the client so far.
+///
+/// The client requests the next window once the previous one has arrived, and requests the missing chunks again if
+/// nothing arrives for a while, so an interrupted transfer resumes where it left off.
+pub(super) struct IncomingJoinData {
+    total_size: u64,
+    chunks: Vec<Option<Vec<u8>>>,
+    requested_until: u32,
+    last_received: Instant,
+}
+
+impl IncomingJoinData {
+    pub(super) fn new(total_size: u64, chunk_count: u32) -> Self {
+        Self {
+            total_size,
+            chunks: vec![None; chunk_count as usize],
+            requested_until: CHUNK_WINDOW.min(chunk_count),
+            last_received: Instant::now(),
+        }
+    }
+
+    pub(super) fn insert(&mut self, chunk: u32, data: Vec<u8>) {
+        if let Some(slot) = self.chunks.get_mut(chunk as usize)
+            && slot.is_none()
+        {
+            *slot = Some(data);
+            self.last_received = Instant::now();
+        }
+    }
+
+    pub(super) fn received_size(&self) -> u64 {
+        self.chunks.iter().flatten().map(|chunk| chunk.len() as u64).sum()
+    }
+
+    pub(super) fn total_size(&self) -> u64 {
+        self.total_size
+    }
+
+    /// Chunk to request the next window from, if one should be requested now.
+    pub(super) fn next_request(&mut self) -> Option<u32> {
+        let first_missing = self.chunks.iter().position(Option::is_none)? as u32;
+        if first_missing >= self.requested_until {
+            self.requested_until = (first_missing + CHUNK_WINDOW).min(self.chunks.len() as u32);
+            Some(first_missing)
+        } else if self.last_received + CHUNK_STALL_TIMEOUT < Instant::now() {
+            self.last_received = Instant::now();
+            Some(first_missing)
+        } else {
+            None
+        }
+    }
+
+    /// Decodes the join data once all chunks have arrived.
+    pub(super) fn complete(&self) -> Option<Result<JoinUniverseData, String>> {
+        if self.chunks.iter().any(Option::is_none) {
+            return None;
+        }
+        let bytes: Vec<u8> = self.chunks.iter().flatten().flatten().copied().collect();
+        Some(
+            bincode::decode_from_slice(&bytes, config::standard())
+                .map(|(data, _)| data)
+                .map_err(|err| format!("Invalid universe data: {}", err)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn join_data_is_transferred_in_windows() {
+        let data = JoinUniverseData {
+            universe_data: (0..100_000).map(|i| i as u8).collect(),
+            worlds_data: vec![vec![1; CHUNK_SIZE * 12]],
+            active_frame: 42,
+        };
+        let outgoing = OutgoingJoinData::new(&data);
+        assert_eq!(outgoing.chunk_count(), 16);
+
+        let mut incoming = IncomingJoinData::new(outgoing.total_size(), outgoing.chunk_count());
+        for (index, chunk) in outgoing.window(0) {
+            // One chunk of the first window is lost
+            if index != 3 {
+                incoming.insert(index, chunk.clone());
+            }
+        }
+        assert_eq!(incoming.next_request(), None);
+        assert!(incoming.complete().is_none());
+
+        incoming.insert(3, outgoing.window(3).next().unwrap().1.clone());
+        assert_eq!(incoming.next_request(), Some(CHUNK_WINDOW));
+        for (index, chunk) in outgoing.window(CHUNK_WINDOW) {
+            incoming.insert(index, chunk.clone());
+        }
+        assert_eq!(incoming.next_request(), None);
+        assert_eq!(incoming.received_size(), outgoing.total_size());
+        assert_eq!(incoming.complete(), Some(Ok(data)));
+    }
+}
@@ -1,32 +1,63 @@
 use std::sync::mpsc::Sender;
 use std::{
     collections::BTreeMap,
+    io,
     net::SocketAddr,
     sync::{Mutex, MutexGuard, RwLock},
 };
 
 // Re-export these to allow mp-common to stay as private module.
+pub use ion_common::net::tls::TlsCertificate;
+pub use ion_common::net::transport::PacketTransport;
+pub use ion_common::net::udp_network_socket::TrafficStats;
+use ion_common::net::udp_network_socket::dual_stack_bind_addr;
+pub use ion_common::net::{LeaderboardEntry, MatchAssignment, MatchCriteria, StatSubmission};
 use ion_common::net::{NetworkPlayerInfo, NetworkServerInfo};
 use ion_common::{Map, PlayerId};
-pub use mp_common::NetworkEvent;
+pub use mp_auth::{JoinAuthenticator, SharedSecretAuth, SignedTokenAuth};
+pub use mp_common::{
+    ChatMessage, ChatTarget, ConnectionStats, NetSyncMode, NetworkEvent, NetworkStats, PlayerSyncStatus, TeamId,
+    WsBridgeTls,
+};
+pub use quality::ConnectionQuality;
+pub use rcon::{AdminCommand, RconClient};
+pub use relay::RelayTransport;
+pub use stats::StatsClient;
 
 use crate::core::{
-    Constants,
+    Constants, FrameId, NetworkTimeouts,
     universe::Universe,
     world::{WorldId, WorldType},
 };
+use crate::files::Files;
 
 use self::{
     mp_browser::MpBrowser,
     mp_client::MpClient,
     mp_common::{ActionSyncResult, MpInstance},
     mp_server::MpServer,
+    voice::VoiceChat,
 };
 
+mod action_delta;
+mod join_transfer;
+mod mp_auth;
 pub mod mp_browser;
 mod mp_client;
 mod mp_common;
 mod mp_server;
+mod quality;
+mod rcon;
+mod relay;
+mod rollback;
+mod snapshot;
+mod stats;
+mod time_sync;
+mod voice;
+mod world_routes;
+
+/// Creates a new transport for each multiplayer session, see [`Network::mp_set_transport`].
+pub type TransportFactory = Box<dyn Fn() -> Box<dyn PacketTransport> + Send + Sync>;
 
 /// Network capabilities of the Nawi engine.
 /// Main feature is multiplayer. This works as standalone in LAN environments.
@@ -35,28 +66,42 @@ mod mp_server;
 pub struct Network<W: WorldType> {
     network_bind_addr: SocketAddr,
     network_host_addr: SocketAddr,
+    network_timeouts: NetworkTimeouts,
+    app_version: String,
     network_event_sender: Sender<NetworkEvent>,
+    files: Files,
 
     mp_instance: RwLock<Option<MpInstance<W>>>,
     mp_browser_instance: Mutex<Option<MpBrowser>>,
+    transport_factory: Mutex<Option<TransportFactory>>,
+    voice: Mutex<VoiceChat>,
 }
 
 impl<W: WorldType> Network<W> {
-    pub(crate) fn new(constants: &Constants, network_event_sender: Sender<NetworkEvent>) -> Self {
+    pub(crate) fn new(constants: &Constants, files: Files, network_event_sender: Sender<NetworkEvent>) -> Self {
         Self {
             network_bind_addr: constants
                 .net
                 .as_ref()
                 .map(|c| c.bind_addr)
-                .unwrap_or(SocketAddr::from(([0, 0, 0, 0], 0))),
+                .unwrap_or(dual_stack_bind_addr(0)),
             network_host_addr: constants
                 .net
                 .as_ref()
                 .map(|c| c.host_addr)
                 .unwrap_or(SocketAddr::from(([0, 0, 0, 0], 0))),
+            network_timeouts: constants.net.as_ref().map(|c| c.timeouts).unwrap_or_default(),
+            app_version: constants
+                .net
+                .as_ref()
+                .map(|c| c.app_version.clone())
+                .unwrap_or_default(),
             mp_instance: RwLock::new(None),
             mp_browser_instance: Mutex::new(None),
+            transport_factory: Mutex::new(None),
+            voice: Mutex::new(VoiceChat::new()),
             network_event_sender,
+            files,
         }
     }
 
@@ -66,35 +111,179 @@ impl<W: WorldType> Network<W> {
 
     // ------------- Multiplayer Client and Server -------------- //
 
-    pub fn mp_start_server(&self, server_info: NetworkServerInfo, player_info: Option<NetworkPlayerInfo>) {
+    /// Starts a server. Without an authenticator, anyone can join and players have no identity.
+    pub fn mp_start_server(
+        &self,
+        server_info: NetworkServerInfo,
+        player_info: Option<NetworkPlayerInfo>,
+        sync_mode: NetSyncMode,
+        authenticator: Option<Box<dyn JoinAuthenticator>>,
+    ) {
+        #[cfg(target_arch = "wasm32")]
+        panic!("Hosting a server is not supported on wasm");
+
         self.verify_start_conditions();
         *self.mp_instance.write().unwrap() = Some(MpInstance::Server(MpServer::new(
             self.network_bind_addr,
             self.network_host_addr,
+            self.network_timeouts,
+            self.app_version.clone(),
             server_info,
             player_info,
+            sync_mode,
+            authenticator,
+            self.custom_transport(),
+            self.files.clone(),
             self.network_event_sender.clone(),
         )));
     }
 
-    pub fn mp_start_client(&self, server_info: NetworkServerInfo, player_info: NetworkPlayerInfo) {
+    /// Lets browser builds join the running server. Browsers can't send udp, so they connect over WebSocket to the
+    /// same port number over TCP, which must be reachable too. Does nothing if not running a server.
+    ///
+    /// Pages served over https can only open secure WebSockets, so public servers should encrypt the bridge, either
+    /// with a certificate of their own or behind a proxy that terminates TLS, see [`WsBridgeTls`]. Auth tokens and
+    /// admin commands are refused from unencrypted browsers, as they could be read on the way. Fails if the
+    /// certificate can't be loaded.
+    pub fn mp_start_ws_bridge(&self, tls: WsBridgeTls) -> io::Result<()> {
+        match &*self.mp_instance.read().unwrap() {
+            Some(MpInstance::Server(instance)) => instance.start_ws_bridge(self.network_bind_addr.ip(), tls),
+            _ => Ok(()),
+        }
+    }
+
+    /// Joins the server. The sync mode must match the one the server was started with.
+    /// Browser builds join over WebSocket, which the server must have enabled with [`Network::mp_start_ws_bridge`].
+    /// The auth token is checked by the authenticator of the server, see [`JoinAuthenticator`].
+    pub fn mp_start_client(
+        &self,
+        server_info: NetworkServerInfo,
+        player_info: NetworkPlayerInfo,
+        sync_mode: NetSyncMode,
+        auth_token: Option<Vec<u8>>,
+    ) {
         self.verify_start_conditions();
         *self.mp_instance.write().unwrap() = Some(MpInstance::Client(MpClient::new(
             self.network_bind_addr,
             self.network_host_addr,
+            self.network_timeouts,
+            self.app_version.clone(),
             server_info,
             player_info,
+            sync_mode,
+            auth_token,
+            self.custom_transport(),
             self.network_event_sender.clone(),
         )));
     }
 
+    /// Makes servers and clients started after this send their messages over transports created by the factory,
+    /// for example on top of Steam Networking Sockets. None goes back to the built-in udp transport.
+    ///
+    /// Custom transports skip the host services, so NAT punching and global server addresses are up to the transport.
+    pub fn mp_set_transport(&self, factory: Option<TransportFactory>) {
+        *self.transport_factory.lock().unwrap() = factory;
+    }
+
     pub fn mp_stop_client_server(&self) {
         *self.mp_instance.write().unwrap() = None;
     }
 
+    /// Notifies all clients that the server is shutting down. Does nothing if not running a server.
+    pub fn mp_broadcast_shutdown(&self, reason: &str) {
+        if let Some(MpInstance::Server(instance)) = &*self.mp_instance.read().unwrap() {
+            instance.broadcast_shutdown(reason);
+        }
+    }
+
+    /// Sends a chat message to other players, delivered as [`NetworkEvent::Chat`]. On a server without an own
+    /// player, the message is sent from the server itself. Does nothing if not in multiplayer.
+    pub fn mp_send_chat(&self, target: ChatTarget, message: &str) {
+        match &*self.mp_instance.read().unwrap() {
+            Some(MpInstance::Server(instance)) => instance.send_chat(target, message),
+            Some(MpInstance::Client(instance)) => instance.send_chat(target, message),
+            None => {}
+        }
+    }
+
+    /// Puts the player into a team for team chat, or removes it from its team. Does nothing if not running a server.
+    pub fn mp_set_player_team(&self, player_id: PlayerId, team: Option<TeamId>) {
+        if let Some(MpInstance::Server(instance)) = &*self.mp_instance.read().unwrap() {
+            instance.set_player_team(player_id, team);
+        }
+    }
+
+    /// Moves the player to the world, or back into all worlds with none, which is where players start. The server
+    /// picks the frame of the move, on which the player leaves its old worlds and joins the new one, see
+    /// [`UniverseFrameProps`](crate::core::UniverseFrameProps). From then on the player only acts in its world.
+    /// Does nothing if not running a server.
+    pub fn mp_move_player(&self, player_id: PlayerId, world: Option<WorldId>) {
+        if let Some(MpInstance::Server(instance)) = &*self.mp_instance.read().unwrap() {
+            instance.move_player(player_id, world);
+        }
+    }
+
+    /// Syncs only the worlds the player occupies or observes to it, or all worlds with none, which is the default.
+    /// The player's client drops the worlds taken away from it, and receives the state of the worlds added to it.
+    /// Best set when the player starts joining, see [`NetworkEvent::PlayerJoinStart`], so that the join transfer
+    /// only carries those worlds. Does nothing if not running a server.
+    pub fn mp_set_player_worlds(&self, player_id: PlayerId, worlds: Option<&[WorldId]>) {
+        if let Some(MpInstance::Server(instance)) = &*self.mp_instance.read().unwrap() {
+            instance.set_player_worlds(player_id, worlds);
+        }
+    }
+
+    /// Enables the remote console with the password, or disables it with none. Disabled by default.
+    /// Does nothing if not running a server. See [`RconClient`].
+    pub fn mp_set_admin_password(&self, password: Option<&str>) {
+        if let Some(MpInstance::Server(instance)) = &*self.mp_instance.read().unwrap() {
+            instance.set_admin_password(password);
+        }
+    }
+
+    /// Sets how many players may wait in queue when the server is full. Queued players are told their position with
+    /// [`NetworkEvent::OwnJoinQueued`], and start joining in order as others leave. Joins are denied when the queue is
+    /// full, and with the default of zero whenever the server is full. Does nothing if not running a server.
+    pub fn mp_set_max_queued_players(&self, max_queued_players: usize) {
+        if let Some(MpInstance::Server(instance)) = &*self.mp_instance.read().unwrap() {
+            instance.set_max_queued_players(max_queued_players);
+        }
+    }
+
+    // ----------------------- Voice Chat ---------------------- //
+
+    /// Opens the default microphone and speakers for voice chat. Voice is only sent while transmitting,
+    /// see [`Network::mp_set_voice_transmit`].
+    #[cfg(feature = "voice")]
+    pub fn mp_start_voice(&self) {
+        self.voice.lock().unwrap().start();
+    }
+
+    #[cfg(feature = "voice")]
+    pub fn mp_stop_voice(&self) {
+        self.voice.lock().unwrap().stop();
+    }
+
+    /// Whether own voice is sent to other players, for example while a push-to-talk key is held.
+    pub fn mp_set_voice_transmit(&self, transmit: bool) {
+        self.voice.lock().unwrap().set_transmitting(transmit);
+    }
+
+    /// Sets the volume of the voice of the player, where 1.0 is the volume it was recorded at.
+    pub fn mp_set_player_volume(&self, player_id: PlayerId, volume: f32) {
+        self.voice.lock().unwrap().set_volume(player_id, volume);
+    }
+
+    pub fn mp_set_player_muted(&self, player_id: PlayerId, muted: bool) {
+        self.voice.lock().unwrap().set_muted(player_id, muted);
+    }
+
     // -------------------- Server Browser -------------------- //
 
     pub fn mp_start_server_browser(&self) {
+        #[cfg(target_arch = "wasm32")]
+        panic!("Server browser is not supported on wasm");
+
         self.verify_start_conditions();
         *self.mp_browser_instance.lock().unwrap() = Some(MpBrowser::new(self));
     }
@@ -109,6 +298,17 @@ impl<W: WorldType> Network<W> {
 
     // ------------------- Helper Functions ------------------- //
 
+    /// Round trip times and traffic of the multiplayer connections, for example for a connection quality display.
+    /// Empty if not in multiplayer.
+    pub fn stats(&self) -> NetworkStats {
+        let connections = match &*self.mp_instance.read().unwrap() {
+            Some(MpInstance::Server(instance)) => instance.connection_stats(),
+            Some(MpInstance::Client(instance)) => vec![instance.connection_stats()],
+            None => Vec::new(),
+        };
+        NetworkStats { connections }
+    }
+
     pub fn is_mp_on(&self) -> bool {
         self.mp_instance.read().unwrap().is_some()
     }
@@ -122,9 +322,6 @@ impl<W: WorldType> Network<W> {
     // ---------------------------------------------------------- //
 
     pub(crate) fn verify_start_conditions(&self) {
-        #[cfg(target_arch = "wasm32")]
-        panic!("Multiplayer is not supported on wasm");
-
         assert!(
             self.mp_instance.read().unwrap().is_none(),
             "Can't start a network system while mp is active"
@@ -136,6 +333,10 @@ impl<W: WorldType> Network<W> {
         );
     }
 
+    fn custom_transport(&self) -> Option<Box<dyn PacketTransport>> {
+        self.transport_factory.lock().unwrap().as_ref().map(|factory| factory())
+    }
+
     /// Processes all network events that have been received.
     /// Mainly used to run network events in cases where universe does not yet exist,
     /// such as when joining as a client.
@@ -151,6 +352,7 @@ impl<W: WorldType> Network<W> {
     /// Returns none if sync fails (connection to server is lost).
     /// If not at sync, it means this client is falling behind, and should loop frames as fast as possible.
     /// Also reports any joining or leaving players on that frame.
+    /// Rollback clients may also return earlier frames that must be executed again, see [`NetSyncMode::Rollback`].
     /// If playing offline, simply builds the action map out of own global and local actions.
     pub(crate) fn mp_sync_actions(
         &self,
@@ -164,7 +366,7 @@ impl<W: WorldType> Network<W> {
             Some(mp_instance) => {
                 let mut sync_result = match mp_instance {
                     MpInstance::Server(instance) => instance.sync_actions(own_global_actions, universe, worlds_lock),
-                    MpInstance::Client(instance) => instance.sync_actions(own_global_actions, universe),
+                    MpInstance::Client(instance) => instance.sync_actions(own_global_actions, universe, worlds_lock),
                 };
 
                 if let Some(player) = own_player {
@@ -214,10 +416,70 @@ impl<W: WorldType> Network<W> {
                 Some(ActionSyncResult {
                     players_joined,
                     players_left: Vec::new(),
+                    players_moved: Vec::new(),
                     actions: all_actions,
                     is_at_sync: true,
+                    resimulated_frames: Vec::new(),
+                    server_snapshot: None,
                 })
             }
         }
     }
+
+    /// Stores snapshots of the worlds before executing a frame, if a rollback client predicted its actions.
+    /// Does nothing otherwise.
+    pub(crate) fn mp_store_rollback_snapshots(&self, frame: FrameId, worlds: &Map<WorldId, W>) {
+        if let Some(MpInstance::Client(instance)) = &*self.mp_instance.read().unwrap() {
+            instance.store_rollback_snapshots(frame, worlds);
+        }
+    }
+
+    /// Sends captured own voice, and plays the voice received from other players. Does nothing if not in multiplayer.
+    pub(crate) fn mp_process_voice(&self) {
+        let mut voice = self.voice.lock().unwrap();
+        match &*self.mp_instance.read().unwrap() {
+            Some(MpInstance::Server(instance)) => {
+                instance
+                    .take_received_voice()
+                    .into_iter()
+                    .for_each(|(id, packet)| voice.receive(id, packet));
+                voice
+                    .captured_packets()
+                    .into_iter()
+                    .for_each(|packet| instance.send_voice(packet));
+            }
+            Some(MpInstance::Client(instance)) => {
+                instance
+                    .take_received_voice()
+                    .into_iter()
+                    .for_each(|(id, packet)| voice.receive(id, packet));
+                voice
+                    .captured_packets()
+                    .into_iter()
+                    .for_each(|packet| instance.send_voice(packet));
+            }
+            None => return,
+        }
+        voice.play();
+    }
+
+    /// Compares the worlds between the server and clients after executing a frame, to detect desyncs.
+    /// Reported as [`NetworkEvent::Desync`]. Does nothing if not in multiplayer.
+    pub(crate) fn mp_check_world_checksums(&self, frame: FrameId, worlds: &Map<WorldId, W>) {
+        match &*self.mp_instance.read().unwrap() {
+            Some(MpInstance::Server(instance)) => instance.store_world_checksums(frame, worlds),
+            Some(MpInstance::Client(instance)) => instance.send_world_checksums(frame, worlds),
+            None => {}
+        }
+    }
+
+    /// Streams snapshots of the worlds to clients after executing a frame, if running a server in snapshot mode.
+    /// Also sends the worlds newly routed to players, see [`Network::mp_set_player_worlds`].
+    /// Does nothing if not running a server.
+    pub(crate) fn mp_send_state_snapshots(&self, frame: FrameId, worlds: &Map<WorldId, W>) {
+        if let Some(MpInstance::Server(instance)) = &*self.mp_instance.read().unwrap() {
+            instance.send_state_snapshots(frame, worlds);
+            instance.send_added_worlds(frame, worlds);
+        }
+    }
 }
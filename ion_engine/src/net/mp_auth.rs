@@ -0,0 +1,160 @@
+use std::time::Duration;
+
+use bincode::{Decode, Encode, config};
+use blake2::digest::Mac;
+use blake2::{Blake2s256, Blake2sMac256, Digest};
+
+use ion_common::net::{NetworkPlayerInfo, secret_matches};
+use ion_common::{DateTime, PlayerIdentity};
+
+// ---------------------------------------------------------- //
+// ------------------- Join authentication ------------------ //
+// ---------------------------------------------------------- //
+
+/// Verifies players joining a server, and tells who they are across sessions.
+///
+/// The server calls this for every join request with the token the client was started with.
+/// Implementations can also deny players by their identity, for example to keep bans across sessions.
+pub trait JoinAuthenticator: Send + Sync {
+    /// Returns the persistent identity of the joining player, or the reason the join is denied.
+    fn authenticate(&self, player_info: &NetworkPlayerInfo, token: Option<&[u8]>) -> Result<PlayerIdentity, String>;
+}
+
+/// Allows players who know the secret of the server. Identities are the player names, so they are only as
+/// trustworthy as the players who know the secret.
+pub struct SharedSecretAuth {
+    secret: Vec<u8>,
+}
+
+impl SharedSecretAuth {
+    pub fn new(secret: &str) -> Self {
+        Self {
+            secret: secret.as_bytes().to_vec(),
+        }
+    }
+
+    /// Token for clients joining a server that uses this secret.
+    pub fn token(secret: &str) -> Vec<u8> {
+        secret.as_bytes().to_vec()
+    }
+
+    /// Whether the token is the secret.
+    pub(crate) fn verify(&self, token: Option<&[u8]>) -> bool {
+        secret_matches(token.unwrap_or_default(), &self.secret)
+    }
+}
+
+impl JoinAuthenticator for SharedSecretAuth {
+    fn authenticate(&self, player_info: &NetworkPlayerInfo, token: Option<&[u8]>) -> Result<PlayerIdentity, String> {
+        if self.verify(token) {
+            Ok(player_info.name.clone())
+        } else {
+            Err("Wrong password".to_owned())
+        }
+    }
+}
+
+/// Allows players with a token signed by someone who knows the key, such as an account service of the game.
+/// Identities come from the tokens, so they stay the same whatever name or address the player joins with.
+pub struct SignedTokenAuth {
+    key: [u8; 32],
+}
+
+#[derive(Debug, Clone, Encode, Decode)]
+struct SignedToken {
+    identity: PlayerIdentity,
+    expires_at_ms: u64,
+    mac: [u8; 32],
+}
+
+impl SignedTokenAuth {
+    pub fn new(key: &[u8]) -> Self {
+        Self { key: derive_key(key) }
+    }
+
+    /// Issues a token for the identity, valid for the given duration.
+    pub fn issue(&self, identity: &str, valid_for: Duration) -> Vec<u8> {
+        let expires_at_ms = DateTime::now().as_unix_timestamp_ms() + valid_for.as_millis() as u64;
+        let token = SignedToken {
+            identity: identity.to_owned(),
+            expires_at_ms,
+            mac: self.sign(identity, expires_at_ms).finalize().into_bytes().into(),
+        };
+        bincode::encode_to_vec(token, config::standard()).unwrap()
+    }
+
+    fn sign(&self, identity: &str, expires_at_ms: u64) -> Blake2sMac256 {
+        Blake2sMac256::new_from_slice(&self.key)
+            .unwrap()
+            .chain_update(expires_at_ms.to_le_bytes())
+            .chain_update(identity.as_bytes())
+    }
+}
+
+impl JoinAuthenticator for SignedTokenAuth {
+    fn authenticate(&self, _player_info: &NetworkPlayerInfo, token: Option<&[u8]>) -> Result<PlayerIdentity, String> {
+        let token = token.ok_or_else(|| "Missing identity token".to_owned())?;
+        let (token, _): (SignedToken, usize) =
+            bincode::decode_from_slice(token, config::standard()).map_err(|_| "Malformed identity token".to_owned())?;
+
+        self.sign(&token.identity, token.expires_at_ms)
+            .verify_slice(&token.mac)
+            .map_err(|_| "Invalid identity token".to_owned())?;
+        if token.expires_at_ms < DateTime::now().as_unix_timestamp_ms() {
+            return Err("Expired identity token".to_owned());
+        }
+        Ok(token.identity)
+    }
+}
+
+/// Turns a secret of any length into a mac key.
+fn derive_key(secret: &[u8]) -> [u8; 32] {
+    Blake2s256::digest(secret).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player() -> NetworkPlayerInfo {
+        NetworkPlayerInfo {
+            id: 1,
+            name: "player".to_owned(),
+            addr: "127.0.0.1:1234".parse().unwrap(),
+            identity: None,
+        }
+    }
+
+    #[test]
+    fn shared_secret_is_verified() {
+        let auth = SharedSecretAuth::new("hunter2");
+        assert_eq!(
+            auth.authenticate(&player(), Some(&SharedSecretAuth::token("hunter2"))),
+            Ok("player".to_owned())
+        );
+        assert!(
+            auth.authenticate(&player(), Some(&SharedSecretAuth::token("hunter3")))
+                .is_err()
+        );
+        assert!(auth.authenticate(&player(), None).is_err());
+    }
+
+    #[test]
+    fn signed_tokens_are_verified() {
+        let auth = SignedTokenAuth::new(b"server key");
+        let token = auth.issue("account-42", Duration::from_secs(60));
+        assert_eq!(auth.authenticate(&player(), Some(&token)), Ok("account-42".to_owned()));
+
+        let other_key = SignedTokenAuth::new(b"other key");
+        assert!(other_key.authenticate(&player(), Some(&token)).is_err());
+
+        let mut forged = token.clone();
+        let last = forged.len() - 1;
+        forged[last] ^= 1;
+        assert!(auth.authenticate(&player(), Some(&forged)).is_err());
+
+        let expired = auth.issue("account-42", Duration::ZERO);
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(auth.authenticate(&player(), Some(&expired)).is_err());
+    }
+}
@@ -1,22 +1,128 @@
+use std::cmp::Ordering;
 use std::{net::SocketAddr, time::Duration};
 
-use ion_common::log_info;
 use ion_common::net::udp_network_socket::UdpNetworkSocket;
-use ion_common::net::{SysMessage, UdpMessage};
+use ion_common::net::{MatchAssignment, MatchCriteria, SysMessage, UdpMessage};
+use ion_common::{Instant, Map, log_info};
 
 use crate::core::world::WorldType;
-use crate::net::NetworkServerInfo;
+use crate::net::{NetworkPlayerInfo, NetworkServerInfo};
 
 use super::Network;
 
+// Servers that haven't answered a refresh for this long are dropped from the lists
+const SERVER_STALE_AFTER: Duration = Duration::from_secs(60);
+
+// ---------------------------------------------------------- //
+// ------------------ Server list queries ------------------- //
+// ---------------------------------------------------------- //
+
+/// Server found by the browser, see [`MpBrowser::servers`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerListing {
+    pub info: NetworkServerInfo,
+    /// Round trip time of the latest ping. None until the server has answered one.
+    pub latency: Option<Duration>,
+    pub is_local: bool,
+}
+
+/// Conditions a server must meet to be listed. The default lists all servers.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ServerFilter {
+    /// Only servers with a name containing this, ignoring case.
+    pub name_contains: Option<String>,
+    /// Only servers that have, or don't have, a password.
+    pub has_password: Option<bool>,
+    pub hide_full: bool,
+    pub hide_empty: bool,
+    /// Only servers that have answered a ping this fast.
+    pub max_latency: Option<Duration>,
+    /// Only servers of this region, see [`MpBrowser::regions`].
+    pub region: Option<String>,
+}
+
+impl ServerFilter {
+    pub fn matches(&self, server: &ServerListing) -> bool {
+        let info = &server.info;
+        self.name_contains
+            .as_ref()
+            .is_none_or(|name| info.name.to_lowercase().contains(&name.to_lowercase()))
+            && self
+                .has_password
+                .is_none_or(|has_password| info.has_password == has_password)
+            && !(self.hide_full && info.cur_player_count >= info.max_player_count)
+            && !(self.hide_empty && info.cur_player_count == 0)
+            && self
+                .max_latency
+                .is_none_or(|max_latency| server.latency.is_some_and(|latency| latency <= max_latency))
+            && self
+                .region
+                .as_ref()
+                .is_none_or(|region| info.region.as_ref() == Some(region))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ServerSort {
+    /// Fastest first. Servers that haven't answered a ping are last.
+    #[default]
+    Latency,
+    /// Most players first.
+    PlayerCount,
+    Name,
+    /// Grouped by region, by name within each. Servers without a region are last.
+    Region,
+}
+
+impl ServerSort {
+    pub fn compare(&self, a: &ServerListing, b: &ServerListing) -> Ordering {
+        match self {
+            Self::Latency => match (a.latency, b.latency) {
+                (Some(a), Some(b)) => a.cmp(&b),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            },
+            Self::PlayerCount => b.info.cur_player_count.cmp(&a.info.cur_player_count),
+            Self::Name => a.info.name.to_lowercase().cmp(&b.info.name.to_lowercase()),
+            Self::Region => match (&a.info.region, &b.info.region) {
+                (Some(a_region), Some(b_region)) => a_region.cmp(b_region),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            }
+            .then_with(|| Self::Name.compare(a, b)),
+        }
+    }
+}
+
+// ---------------------------------------------------------- //
+// --------------------- Server browser --------------------- //
+// ---------------------------------------------------------- //
+
+/// Finds servers on the local network and from the host, and pings them.
+///
+/// Requesting the server info again refreshes the lists in place, so that servers that answer are updated.
+/// Servers are pinged when first found, and [`MpBrowser::refresh`] drops servers that have stopped answering.
+///
+/// Hosts of different regions may share their server lists, so any of them lists all the servers. The nearest one can
+/// be found with [`MpBrowser::request_host_info`], [`MpBrowser::ping_hosts`] and [`MpBrowser::nearest_host`].
 pub struct MpBrowser {
     host_addr: SocketAddr,
+    host_region: Option<String>,
+    host_peers: Vec<SocketAddr>,
     own_global_addr_resp: Option<SocketAddr>,
     own_local_addr_resp: Option<SocketAddr>,
     udp_socket: UdpNetworkSocket<UdpMessage<()>>,
 
     global_servers: Vec<NetworkServerInfo>,
     local_servers: Vec<NetworkServerInfo>,
+    last_seen: Map<SocketAddr, Instant>,
+    pings_sent: Map<SocketAddr, Instant>,
+    latencies: Map<SocketAddr, Duration>,
+
+    match_queue_position: Option<u32>,
+    match_assignment: Option<MatchAssignment>,
 }
 
 impl MpBrowser {
@@ -34,11 +140,18 @@ impl MpBrowser {
 
         Self {
             host_addr: network.network_host_addr,
+            host_region: None,
+            host_peers: Vec::new(),
             own_global_addr_resp: None,
             own_local_addr_resp: None,
             udp_socket,
             global_servers: Vec::new(),
             local_servers: Vec::new(),
+            last_seen: Map::default(),
+            pings_sent: Map::default(),
+            latencies: Map::default(),
+            match_queue_position: None,
+            match_assignment: None,
         }
     }
 
@@ -61,6 +174,107 @@ impl MpBrowser {
         self.udp_socket.send(self.host_addr, msg, Duration::from_secs(15));
     }
 
+    /// Asks the host for its region and the other hosts it shares the server list with.
+    pub fn request_host_info(&self) {
+        let msg = UdpMessage::SysMessage(SysMessage::HostInfoReq);
+        self.udp_socket.send(self.host_addr, msg, Duration::from_secs(15));
+    }
+
+    /// Region of the host, once it has answered [`MpBrowser::request_host_info`]. None also if the host has no region.
+    pub fn host_region(&mut self) -> Option<&str> {
+        self.handle_network_events();
+        self.host_region.as_deref()
+    }
+
+    /// The host and the other hosts it shares the server list with.
+    pub fn hosts(&mut self) -> Vec<SocketAddr> {
+        self.handle_network_events();
+        self.host_addrs()
+    }
+
+    /// Measures the latency to the host and its peers, see [`MpBrowser::nearest_host`].
+    pub fn ping_hosts(&mut self) {
+        for host in self.hosts() {
+            self.ping(host);
+        }
+    }
+
+    /// The host with the lowest latency, among those that have answered a ping.
+    pub fn nearest_host(&mut self) -> Option<SocketAddr> {
+        self.hosts()
+            .into_iter()
+            .filter_map(|host| Some((self.latencies.get(&host).copied()?, host)))
+            .min()
+            .map(|(_, host)| host)
+    }
+
+    /// Sends the further requests of the browser to another host, for example the [`MpBrowser::nearest_host`].
+    pub fn set_host_addr(&mut self, host_addr: SocketAddr) {
+        if host_addr != self.host_addr {
+            self.host_addr = host_addr;
+            self.host_region = None;
+            self.request_host_info();
+        }
+    }
+
+    /// Requests the server info from the host and the local network, and pings the servers already listed.
+    pub fn refresh(&mut self) {
+        self.handle_network_events();
+        self.remove_stale_servers();
+        self.request_global_server_info();
+        self.request_local_server_info();
+        self.ping_servers();
+    }
+
+    /// Measures the latency to every listed server again.
+    pub fn ping_servers(&mut self) {
+        let addrs: Vec<_> = self
+            .global_servers
+            .iter()
+            .chain(&self.local_servers)
+            .map(|server| server.addr)
+            .collect();
+        for addr in addrs {
+            self.ping(addr);
+        }
+    }
+
+    /// Servers that match the filter from both lists, in the given order.
+    pub fn servers(&mut self, filter: &ServerFilter, sort: ServerSort) -> Vec<ServerListing> {
+        self.handle_network_events();
+        let global = self.global_servers.iter().map(|info| (info, false));
+        let local = self.local_servers.iter().map(|info| (info, true));
+        let mut servers: Vec<_> = global
+            .chain(local)
+            .map(|(info, is_local)| ServerListing {
+                info: info.clone(),
+                latency: self.latencies.get(&info.addr).copied(),
+                is_local,
+            })
+            .filter(|server| filter.matches(server))
+            .collect();
+        servers.sort_by(|a, b| sort.compare(a, b));
+        servers
+    }
+
+    /// Regions of the listed servers, sorted, for grouping or filtering them with [`ServerFilter::region`].
+    pub fn regions(&mut self) -> Vec<String> {
+        self.handle_network_events();
+        let mut regions: Vec<_> = self
+            .global_servers
+            .iter()
+            .chain(&self.local_servers)
+            .filter_map(|info| info.region.clone())
+            .collect();
+        regions.sort();
+        regions.dedup();
+        regions
+    }
+
+    pub fn latency_of(&self, addr: SocketAddr) -> Option<Duration> {
+        self.latencies.get(&addr).copied()
+    }
+
     pub fn global_servers(&mut self) -> &[NetworkServerInfo] {
         self.handle_network_events();
         &self.global_servers
@@ -71,6 +285,38 @@ impl MpBrowser {
         &self.local_servers
     }
 
+    /// Queues the player for a match with other players that have the same criteria, see [`MpBrowser::take_match`].
+    ///
+    /// The host drops players that have been queued for a while, so the request should be sent again now and then
+    /// while waiting. The browser should be dropped before hosting or joining the match, so that its address is free.
+    pub fn request_match(&mut self, player_info: NetworkPlayerInfo, criteria: MatchCriteria) {
+        log_info!("Requesting match: {:?}", criteria);
+        let msg = UdpMessage::SysMessage(SysMessage::MatchReq {
+            player: player_info,
+            criteria,
+        });
+        self.udp_socket.send(self.host_addr, msg, Duration::from_secs(15));
+        self.match_assignment = None;
+    }
+
+    pub fn cancel_match(&mut self) {
+        let msg = UdpMessage::SysMessage(SysMessage::MatchCancel);
+        self.udp_socket.send(self.host_addr, msg, Duration::from_secs(15));
+        self.match_queue_position = None;
+    }
+
+    /// Position in the matchmaking queue, while waiting for a match.
+    pub fn match_queue_position(&mut self) -> Option<u32> {
+        self.handle_network_events();
+        self.match_queue_position
+    }
+
+    /// Returns the match the host has found, once.
+    pub fn take_match(&mut self) -> Option<MatchAssignment> {
+        self.handle_network_events();
+        self.match_assignment.take()
+    }
+
     pub fn own_global_addr(&self) -> Option<SocketAddr> {
         self.own_global_addr_resp
     }
@@ -79,6 +325,60 @@ impl MpBrowser {
         self.own_local_addr_resp
     }
 
+    fn host_addrs(&self) -> Vec<SocketAddr> {
+        let mut hosts = vec![self.host_addr];
+        hosts.extend(self.host_peers.iter().filter(|peer| **peer != self.host_addr));
+        hosts
+    }
+
+    fn ping(&mut self, addr: SocketAddr) {
+        // Unreliable, as a resent ping would not measure the latency
+        self.udp_socket
+            .send(addr, UdpMessage::SysMessage(SysMessage::Ping), Duration::ZERO);
+        self.pings_sent.insert(addr, Instant::now());
+    }
+
+    /// Adds the server to the list, or updates it if already listed. New servers are pinged right away.
+    fn upsert_server(&mut self, server: NetworkServerInfo, is_local: bool) {
+        let servers = if is_local {
+            &mut self.local_servers
+        } else {
+            &mut self.global_servers
+        };
+        let addr = server.addr;
+        match servers.iter_mut().find(|listed| listed.addr == addr) {
+            Some(listed) => *listed = server,
+            None => {
+                servers.push(server);
+                self.ping(addr);
+            }
+        }
+        self.last_seen.insert(addr, Instant::now());
+    }
+
+    fn remove_stale_servers(&mut self) {
+        let now = Instant::now();
+        let last_seen = &self.last_seen;
+        let is_fresh = |server: &NetworkServerInfo| {
+            last_seen
+                .get(&server.addr)
+                .is_some_and(|seen| *seen + SERVER_STALE_AFTER > now)
+        };
+        self.global_servers.retain(is_fresh);
+        self.local_servers.retain(is_fresh);
+
+        let listed: Vec<_> = self
+            .global_servers
+            .iter()
+            .chain(&self.local_servers)
+            .map(|server| server.addr)
+            .chain(self.host_addrs())
+            .collect();
+        self.last_seen.retain(|addr, _| listed.contains(addr));
+        self.latencies.retain(|addr, _| listed.contains(addr));
+        self.pings_sent.retain(|addr, _| listed.contains(addr));
+    }
+
     fn handle_network_events(&mut self) {
         for (from_addr, msg) in self.udp_socket.try_recv_all() {
             match msg {
@@ -91,10 +391,12 @@ impl MpBrowser {
                             self.own_local_addr_resp = Some(addr);
                         }
                     }
-                    SysMessage::ServerInfoResGlobal { mut servers } => {
+                    SysMessage::ServerInfoResGlobal { servers } => {
                         log_info!("Received ServerInfoResGlobal for {} servers", servers.len());
                         if from_addr == self.host_addr {
-                            self.global_servers.append(&mut servers);
+                            for server in servers {
+                                self.upsert_server(server, false);
+                            }
                         }
                     }
                     SysMessage::ServerInfoResLocal { server } => {
@@ -104,7 +406,25 @@ impl MpBrowser {
                             UdpMessage::SysMessage(SysMessage::SocketInfoReq),
                             Duration::from_secs(5),
                         );
-                        self.local_servers.push(server);
+                        self.upsert_server(server, true);
+                    }
+                    SysMessage::HostInfoRes { region, peers } if from_addr == self.host_addr => {
+                        log_info!("Received HostInfoRes, region {:?} with peers {:?}", region, peers);
+                        self.host_region = region;
+                        self.host_peers = peers;
+                    }
+                    SysMessage::MatchQueued { position } if from_addr == self.host_addr => {
+                        self.match_queue_position = Some(position);
+                    }
+                    SysMessage::MatchFound { assignment } if from_addr == self.host_addr => {
+                        log_info!("Received MatchFound: {:?}", assignment);
+                        self.match_queue_position = None;
+                        self.match_assignment = Some(assignment);
+                    }
+                    SysMessage::Pong => {
+                        if let Some(sent_at) = self.pings_sent.remove(&from_addr) {
+                            self.latencies.insert(from_addr, Instant::now() - sent_at);
+                        }
                     }
                     _ => {}
                 },
@@ -115,3 +435,77 @@ impl MpBrowser {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listing(name: &str, players: u32, has_password: bool, latency_ms: Option<u64>) -> ServerListing {
+        ServerListing {
+            info: NetworkServerInfo {
+                id: 0,
+                name: name.to_owned(),
+                addr: SocketAddr::from(([127, 0, 0, 1], 1000 + players as u16)),
+                alt_addr: None,
+                is_global: true,
+                has_password,
+                description: String::new(),
+                cur_player_count: players,
+                max_player_count: 4,
+                region: None,
+                ws_host: None,
+                network_key: None,
+            },
+            latency: latency_ms.map(Duration::from_millis),
+            is_local: false,
+        }
+    }
+
+    #[test]
+    fn servers_are_filtered_and_sorted() {
+        let mut servers = vec![
+            listing("Alpha", 4, false, Some(80)),
+            listing("beta", 2, true, None),
+            listing("Gamma", 0, false, Some(20)),
+            listing("Alphabet", 1, false, Some(40)),
+        ];
+        servers[0].info.region = Some("us".to_owned());
+        servers[2].info.region = Some("eu".to_owned());
+        servers[3].info.region = Some("us".to_owned());
+        let names = |filter: &ServerFilter, sort: ServerSort| {
+            let mut listed: Vec<_> = servers.iter().filter(|server| filter.matches(server)).collect();
+            listed.sort_by(|a, b| sort.compare(a, b));
+            listed.iter().map(|server| server.info.name.clone()).collect::<Vec<_>>()
+        };
+
+        let all = ServerFilter::default();
+        assert_eq!(names(&all, ServerSort::Latency), ["Gamma", "Alphabet", "Alpha", "beta"]);
+        assert_eq!(
+            names(&all, ServerSort::PlayerCount),
+            ["Alpha", "beta", "Alphabet", "Gamma"]
+        );
+        assert_eq!(names(&all, ServerSort::Name), ["Alpha", "Alphabet", "beta", "Gamma"]);
+        assert_eq!(names(&all, ServerSort::Region), ["Gamma", "Alpha", "Alphabet", "beta"]);
+
+        let filter = ServerFilter {
+            name_contains: Some("ALPHA".to_owned()),
+            hide_full: true,
+            ..Default::default()
+        };
+        assert_eq!(names(&filter, ServerSort::Name), ["Alphabet"]);
+
+        let filter = ServerFilter {
+            has_password: Some(false),
+            hide_empty: true,
+            max_latency: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        assert_eq!(names(&filter, ServerSort::Name), ["Alphabet"]);
+
+        let filter = ServerFilter {
+            region: Some("us".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(names(&filter, ServerSort::Name), ["Alpha", "Alphabet"]);
+    }
+}
@@ -2,41 +2,67 @@ use std::sync::mpsc::Sender;
 use std::{
     net::SocketAddr,
     sync::{
-        Mutex, RwLock,
-        atomic::{AtomicBool, Ordering},
+        Mutex, MutexGuard, RwLock,
+        atomic::{AtomicBool, AtomicU32, Ordering},
     },
     time::Duration,
 };
 
+use ion_common::net::transport::{NetworkTransport, PacketTransport};
+#[cfg(not(target_arch = "wasm32"))]
 use ion_common::net::udp_network_socket::UdpNetworkSocket;
+#[cfg(target_arch = "wasm32")]
+use ion_common::net::ws_network_socket::WsNetworkSocket;
 use ion_common::net::{SysMessage, UdpMessage};
 use ion_common::util::native_spin_sleep;
 use ion_common::{Instant, log_info};
 use ion_common::{Map, log_warn};
 
 use crate::core::{
+    DEFAULT_UPS, FrameId, NetworkTimeouts, PlayerMove,
     universe::{Universe, UniverseDataType},
     world::{WorldId, WorldType},
 };
 use crate::net::{NetworkPlayerInfo, NetworkServerInfo, PlayerId};
 use crate::util::concurrency::AtomicInstant;
 
-use super::mp_common::{ActionSyncResult, MpActionBuffer, MpMessage, NetworkEvent};
+use super::join_transfer::IncomingJoinData;
+use super::mp_common::{
+    ActionSyncResult, CHECKSUM_INTERVAL, ChatTarget, ConnectionStats, MpActionBuffer, MpMessage, NetSyncMode,
+    NetworkEvent, PROTOCOL_VERSION, ResimulatedFrame, world_checksums,
+};
+use super::quality::QualityMonitor;
+use super::rollback::{FrameActions, RollbackBuffer};
+use super::snapshot::SnapshotBuffer;
+use super::time_sync::TimeSync;
+use super::voice::VoicePacket;
 
 pub const FRAME_LATENCY_SAFETY_MULTIPLIER: u32 = 5;
 pub const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
-pub const JOIN_TIMEOUT: Duration = Duration::from_secs(30);
+// Clients further behind the server than this run frames without rendering, closer ones catch up by pacing their ticks
+const MAX_PACED_FRAMES: FrameId = 10;
+// Time to wait for the server to answer at its preferred address before trying the other one too
+const FALLBACK_ADDR_DELAY: Duration = Duration::from_millis(250);
 
 // ---------------------------------------------------------- //
 // ------------------- Multiplayer Client ------------------- //
 // ---------------------------------------------------------- //
 
 pub struct MpClient<W: WorldType> {
-    server_addr: SocketAddr,
+    server_addr: RwLock<SocketAddr>,
+    fallback_addr: Option<SocketAddr>,
+    server_answered: AtomicBool,
+    fallback_contacted: AtomicBool,
+    network_host_addr: SocketAddr,
+    nat_punch: bool,
+    timeouts: NetworkTimeouts,
+    app_version: String,
     server_info: RwLock<NetworkServerInfo>,
     player_info: NetworkPlayerInfo,
+    auth_token: Option<Vec<u8>>,
+    sync_mode: NetSyncMode,
 
-    udp_socket: UdpNetworkSocket<UdpMessage<MpMessage<W::ActionType>>>,
+    transport: Box<dyn NetworkTransport<UdpMessage<MpMessage<W::ActionType>>>>,
 
     server_player: RwLock<Option<NetworkPlayerInfo>>,
     client_players: RwLock<Map<PlayerId, NetworkPlayerInfo>>,
@@ -45,71 +71,149 @@ pub struct MpClient<W: WorldType> {
     join_request_sent: AtomicBool,
     join_started_at: AtomicInstant,
     join_synced_up: AtomicBool,
+    join_data: Mutex<Option<IncomingJoinData>>,
+    join_data_loaded: AtomicBool,
+    contact_attempts: AtomicU32,
+    last_contact_at: AtomicInstant,
+
+    last_server_msg: AtomicInstant,
+    last_keepalive_at: AtomicInstant,
+    timed_out: AtomicBool,
 
     latency_duration: Mutex<Duration>,
+    quality_monitor: Mutex<QualityMonitor>,
+    time_sync: Mutex<TimeSync>,
     action_holder: Mutex<MpActionBuffer<W::ActionType>>,
+    rollback: Option<Mutex<RollbackBuffer<W::ActionType>>>,
+    snapshots: Option<Mutex<SnapshotBuffer>>,
+    voice_received: Mutex<Vec<(PlayerId, VoicePacket)>>,
+    added_worlds: Mutex<Vec<(FrameId, WorldId, Vec<u8>)>>,
     network_event_sender: Sender<NetworkEvent>,
 }
 
 impl<W: WorldType> MpClient<W> {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         network_bind_addr: SocketAddr,
         network_host_addr: SocketAddr,
+        timeouts: NetworkTimeouts,
+        app_version: String,
         server_info: NetworkServerInfo,
         mut player_info: NetworkPlayerInfo,
+        sync_mode: NetSyncMode,
+        auth_token: Option<Vec<u8>>,
+        custom_transport: Option<Box<dyn PacketTransport>>,
         network_event_sender: Sender<NetworkEvent>,
     ) -> Self {
         log_info!("Starting MpClient: {:?}", &server_info);
 
-        let udp_socket = UdpNetworkSocket::new(network_bind_addr);
-        let join_request_sent = if server_info.is_global {
-            // Start nat punch process to open route to server
-            udp_socket.send(
-                network_host_addr,
-                UdpMessage::SysMessage(SysMessage::NatPunchRelay { to: server_info.addr }),
-                Duration::from_secs(30),
-            );
-            udp_socket.send(
-                server_info.addr,
-                UdpMessage::SysMessage(SysMessage::NatPunchPing),
-                Duration::from_secs(30),
-            );
-            AtomicBool::new(false)
+        let (transport, is_udp, is_dual_stack): (Box<dyn NetworkTransport<_>>, _, _) = match custom_transport {
+            Some(custom_transport) => (Box::new(custom_transport), false, false),
+            // Browsers can't send udp, so browser builds connect to the WebSocket bridge of the server instead
+            #[cfg(target_arch = "wasm32")]
+            None => (
+                Box::new(WsNetworkSocket::connect(
+                    server_info.addr,
+                    server_info.ws_host.as_deref(),
+                )),
+                false,
+                false,
+            ),
+            #[cfg(not(target_arch = "wasm32"))]
+            None => {
+                let udp_socket = UdpNetworkSocket::new_encrypted(network_bind_addr);
+                if let Some(network_key) = server_info.network_key {
+                    for addr in [Some(server_info.addr), server_info.alt_addr].into_iter().flatten() {
+                        udp_socket.pin_key(addr, network_key);
+                    }
+                }
+                let is_dual_stack = udp_socket.is_dual_stack();
+                (Box::new(udp_socket), true, is_dual_stack)
+            }
+        };
+        let (server_addr, fallback_addr) = if is_udp {
+            server_addrs_for(&server_info, is_dual_stack)
         } else {
-            // No need for nat punching, just request to join
-            player_info.addr.set_ip(udp_socket.local_ip_addr().unwrap());
-            udp_socket.send(
-                server_info.addr,
-                UdpMessage::MpMessage(MpMessage::JoinReq {
-                    player_info: player_info.clone(),
-                }),
-                Duration::from_secs(30),
-            );
-            AtomicBool::new(true)
+            (server_info.addr, None)
         };
 
-        Self {
-            server_addr: server_info.addr,
+        // Nat punching goes through the host services, which only work over udp
+        let nat_punch = server_info.is_global && is_udp;
+        if !nat_punch && let Some(local_ip) = transport.local_ip_addr() {
+            player_info.addr.set_ip(local_ip);
+        }
+
+        let client = Self {
+            server_addr: RwLock::new(server_addr),
+            fallback_addr,
+            server_answered: AtomicBool::new(false),
+            fallback_contacted: AtomicBool::new(false),
+            network_host_addr,
+            nat_punch,
+            timeouts,
+            app_version,
             server_info: RwLock::new(server_info),
             player_info,
-            udp_socket,
+            auth_token,
+            sync_mode,
+            transport,
             server_player: RwLock::new(None),
             client_players: RwLock::new(Map::default()),
             client_players_joining: RwLock::new(Map::default()),
-            join_request_sent,
+            join_request_sent: AtomicBool::new(!nat_punch),
             join_started_at: AtomicInstant::new(Instant::now()),
             join_synced_up: AtomicBool::new(false),
+            join_data: Mutex::new(None),
+            join_data_loaded: AtomicBool::new(false),
+            contact_attempts: AtomicU32::new(0),
+            last_contact_at: AtomicInstant::new(Instant::now()),
+            last_server_msg: AtomicInstant::new(Instant::now()),
+            last_keepalive_at: AtomicInstant::new(Instant::now()),
+            timed_out: AtomicBool::new(false),
             latency_duration: Mutex::new(Duration::from_millis(100)),
+            quality_monitor: Mutex::new(QualityMonitor::default()),
+            time_sync: Mutex::new(TimeSync::new(matches!(sync_mode, NetSyncMode::Rollback { .. }))),
             action_holder: Mutex::new(MpActionBuffer::new()),
+            rollback: match sync_mode {
+                NetSyncMode::Rollback { max_frames } => Some(Mutex::new(RollbackBuffer::new(max_frames))),
+                _ => None,
+            },
+            snapshots: match sync_mode {
+                NetSyncMode::Snapshot { interval } => Some(Mutex::new(SnapshotBuffer::new(interval as u64))),
+                _ => None,
+            },
+            voice_received: Mutex::new(Vec::new()),
+            added_worlds: Mutex::new(Vec::new()),
             network_event_sender,
-        }
+        };
+        client.contact_server(server_addr);
+        client
     }
 
     pub(crate) fn sync_join_process(&self, universe: &Universe<W>) {
         let mut received_actions = self.action_holder.lock().unwrap();
         self.process_network_events(universe, &mut received_actions);
 
-        if self.join_started_at.load(Ordering::Relaxed) + JOIN_TIMEOUT < Instant::now() {
+        // Try the other address family too if the server doesn't answer at the preferred one
+        if let Some(fallback_addr) = self.fallback_addr
+            && !self.server_answered.load(Ordering::Acquire)
+            && self.join_started_at.load(Ordering::Relaxed) + FALLBACK_ADDR_DELAY < Instant::now()
+            && !self.fallback_contacted.swap(true, Ordering::AcqRel)
+        {
+            log_info!("No answer from {:?}, trying {:?}", self.server_addr(), fallback_addr);
+            self.contact_server(fallback_addr);
+        }
+
+        self.check_timeouts();
+
+        // Resume the transfer if chunks were lost
+        if let Some(join_data) = &mut *self.join_data.lock().unwrap()
+            && let Some(from_chunk) = join_data.next_request()
+        {
+            self.request_join_data(from_chunk);
+        }
+
+        if self.join_started_at.load(Ordering::Relaxed) + self.timeouts.join_timeout < Instant::now() {
             self.network_event_sender
                 .send(NetworkEvent::OwnJoinDataRecvFailure {
                     reason: "Timeout downloading map data".to_owned(),
@@ -122,41 +226,71 @@ impl<W: WorldType> MpClient<W> {
         &self,
         own_global_actions: Map<WorldId, Vec<W::ActionType>>,
         universe: &Universe<W>,
+        worlds_lock: &mut MutexGuard<Map<WorldId, W>>,
     ) -> Option<ActionSyncResult<W>> {
         let mut actions = self.action_holder.lock().unwrap();
         self.process_network_events(universe, &mut actions);
+        self.check_timeouts();
+        self.check_and_send_time_sync();
 
         {
             let active_frame = universe.active_frame();
-            let send_for_frame = {
+            if active_frame.is_multiple_of(DEFAULT_UPS) {
+                self.check_and_report_connection_quality();
+            }
+
+            let send_for_frame = if self.rollback.is_some() {
+                // Own actions are executed right away, and the server moves them forward if they arrive late
+                active_frame
+            } else {
                 let rtt = *self.latency_duration.lock().unwrap() * FRAME_LATENCY_SAFETY_MULTIPLIER;
                 active_frame + (rtt.as_micros() / universe.universe_frame_time().as_micros() + 1).max(2) as u64
             };
             let action_msg = UdpMessage::MpMessage(MpMessage::ActionsFromClient {
                 for_frame: send_for_frame,
-                actions: own_global_actions,
+                actions: own_global_actions.clone(),
+                acked_frame: actions.latest_frame(),
             });
 
-            self.udp_socket
-                .send(self.server_addr, action_msg, Duration::from_secs(5));
+            self.transport
+                .send(self.server_addr(), action_msg, Duration::from_secs(5));
 
-            // Receive combined actions from server
-            let wait_start = Instant::now();
+            let resimulated_frames = self.rollback_mispredicted_frames(active_frame, &actions, worlds_lock);
+
+            // Predict actions if rollback is enabled, otherwise receive combined actions from server
             let mut frame_actions = actions.export_actions(active_frame);
-            while frame_actions.is_none() && wait_start + COMMAND_TIMEOUT > Instant::now() {
+            if frame_actions.is_none()
+                && self.join_synced_up.load(Ordering::Acquire)
+                && let Some(rollback) = &self.rollback
+            {
+                let mut rollback = rollback.lock().unwrap();
+                if rollback.can_predict() {
+                    frame_actions = self.predict_actions(&mut rollback, active_frame, own_global_actions, &actions);
+                }
+            }
+
+            let wait_start = Instant::now();
+            while !self.is_frame_ready(active_frame, frame_actions.as_ref(), worlds_lock)
+                && wait_start + COMMAND_TIMEOUT > Instant::now()
+            {
                 native_spin_sleep(Duration::from_millis(1));
                 self.process_network_events(universe, &mut actions);
                 frame_actions = actions.export_actions(active_frame);
             }
-            let is_at_sync = !actions.contains_frame(active_frame + 1);
+            if !self.is_frame_ready(active_frame, frame_actions.as_ref(), worlds_lock) {
+                frame_actions = None;
+            }
+            let frames_behind = actions
+                .latest_frame()
+                .map_or(0, |latest_frame| latest_frame.saturating_sub(active_frame));
 
             // Check if just caught up with server after join
-            if is_at_sync {
+            if frames_behind == 0 {
                 if !self.join_synced_up.load(Ordering::Acquire) {
                     self.join_synced_up.store(true, Ordering::Release);
                     self.network_event_sender.send(NetworkEvent::OwnJoinSuccess).ok();
-                    self.udp_socket.send(
-                        self.server_addr,
+                    self.transport.send(
+                        self.server_addr(),
                         UdpMessage::MpMessage(MpMessage::JoinComplete {
                             player_info: self.player_info.clone(),
                         }),
@@ -164,7 +298,7 @@ impl<W: WorldType> MpClient<W> {
                     );
                 }
             } else if !self.join_synced_up.load(Ordering::Acquire)
-                && self.join_started_at.load(Ordering::Relaxed) + JOIN_TIMEOUT < Instant::now()
+                && self.join_started_at.load(Ordering::Relaxed) + self.timeouts.join_timeout < Instant::now()
             {
                 self.network_event_sender
                     .send(NetworkEvent::OwnJoinFailure {
@@ -173,35 +307,38 @@ impl<W: WorldType> MpClient<W> {
                     .ok();
             }
 
+            let is_at_sync = self.join_synced_up.load(Ordering::Acquire) && frames_behind <= MAX_PACED_FRAMES;
+            let pacing = if self.join_synced_up.load(Ordering::Acquire) {
+                self.time_sync.lock().unwrap().pacing()
+            } else {
+                1.0
+            };
+            universe.set_frame_pacing(pacing);
+
             match frame_actions {
-                Some(frame_actions) => {
-                    let players_left = actions.players_left_on_frame(active_frame);
-                    let players_joined = actions
-                        .players_joined_on_frame(active_frame)
-                        .into_iter()
-                        .map(|player_id| {
-                            if player_id != self.player_info.id {
-                                self.client_players
-                                    .read()
-                                    .unwrap()
-                                    .iter()
-                                    .find(|player| player.1.id == player_id)
-                                    .expect("Must have info for joining player")
-                                    .1
-                                    .clone()
-                            } else {
-                                self.player_info.clone()
-                            }
-                        })
-                        .collect();
+                Some(mut frame_actions) => {
+                    self.update_routed_worlds(active_frame, &frame_actions, worlds_lock);
+                    let (players_joined, players_left) = self.players_changed_on_frame(active_frame, &actions);
+                    let players_moved = self.players_moved_on_frame(active_frame, &actions);
+
+                    // Only the server executes stateful actions in snapshot mode, clients get the resulting state
+                    let server_snapshot = self.snapshots.as_ref().and_then(|snapshots| {
+                        for player_actions in frame_actions.values_mut() {
+                            player_actions.values_mut().for_each(Vec::clear);
+                        }
+                        snapshots.lock().unwrap().interpolate(active_frame)
+                    });
 
                     actions.delete_actions(active_frame.saturating_sub(20000));
 
                     Some(ActionSyncResult {
                         players_joined,
                         players_left,
+                        players_moved,
                         actions: frame_actions,
                         is_at_sync,
+                        resimulated_frames,
+                        server_snapshot,
                     })
                 }
                 _ => {
@@ -213,23 +350,405 @@ impl<W: WorldType> MpClient<W> {
         }
     }
 
+    /// Whether the actions of the frame have arrived, along with the state of any world newly routed to this client.
+    fn is_frame_ready(
+        &self,
+        frame: FrameId,
+        frame_actions: Option<&FrameActions<W::ActionType>>,
+        worlds: &Map<WorldId, W>,
+    ) -> bool {
+        let added_worlds = self.added_worlds.lock().unwrap();
+        frame_actions.is_some_and(|frame_actions| {
+            frame_actions.keys().all(|world_id| {
+                worlds.contains_key(world_id)
+                    || added_worlds
+                        .iter()
+                        .any(|(added_frame, added_id, _)| *added_frame == frame && added_id == world_id)
+            })
+        })
+    }
+
+    /// Adds the worlds the server started syncing to this client on the frame, and drops the ones it stopped syncing.
+    /// See [`Network::mp_set_player_worlds`](super::Network::mp_set_player_worlds).
+    fn update_routed_worlds(
+        &self,
+        frame: FrameId,
+        frame_actions: &FrameActions<W::ActionType>,
+        worlds: &mut MutexGuard<Map<WorldId, W>>,
+    ) {
+        let mut added_worlds = self.added_worlds.lock().unwrap();
+        added_worlds.retain(|(added_frame, _, _)| *added_frame >= frame);
+        for (_, world_id, data) in added_worlds.extract_if(.., |(added_frame, _, _)| *added_frame == frame) {
+            match W::from_bytes(&data, Some(self.player_info.clone())) {
+                Some(world) => {
+                    log_info!("World {} added on frame {}", world_id, frame);
+                    worlds.insert(world_id, world);
+                }
+                None => {
+                    log_warn!("Failed to decode added world {}", world_id);
+                }
+            }
+        }
+        worlds.retain(|world_id, _| frame_actions.contains_key(world_id));
+    }
+
+    fn server_addr(&self) -> SocketAddr {
+        *self.server_addr.read().unwrap()
+    }
+
+    /// Starts nat punching to the server address, or requests to join right away if not needed.
+    fn contact_server(&self, addr: SocketAddr) {
+        // Unanswered handshakes are sent again by check_timeouts, so resends don't need to outlive the retry interval
+        let timeout = self.timeouts.join_retry_interval;
+        if self.nat_punch {
+            // Start nat punch process to open route to server
+            self.transport.send(
+                self.network_host_addr,
+                UdpMessage::SysMessage(SysMessage::NatPunchRelay { to: addr }),
+                timeout,
+            );
+            self.transport
+                .send(addr, UdpMessage::SysMessage(SysMessage::NatPunchPing), timeout);
+        } else {
+            self.transport.send(addr, self.join_req(), timeout);
+        }
+        self.last_contact_at.store(Instant::now(), Ordering::Relaxed);
+    }
+
+    /// Asks the server for its frame every now and then, to keep the tick pacing in time with the server.
+    fn check_and_send_time_sync(&self) {
+        if let Some(client_time) = self.time_sync.lock().unwrap().request(Instant::now()) {
+            // Unreliable, as a late answer would only skew the estimate
+            self.transport.send(
+                self.server_addr(),
+                UdpMessage::MpMessage(MpMessage::TimeSyncReq { client_time }),
+                Duration::ZERO,
+            );
+        }
+    }
+
+    /// Sends keepalives and join handshake retries, and reports a server or join that has timed out.
+    fn check_timeouts(&self) {
+        let now = Instant::now();
+        if self.last_keepalive_at.load(Ordering::Relaxed) + self.timeouts.keepalive_interval < now {
+            // Unreliable, as the next keepalive is sent before a resend would be
+            self.transport.send(
+                self.server_addr(),
+                UdpMessage::MpMessage(MpMessage::Keepalive),
+                Duration::ZERO,
+            );
+            self.last_keepalive_at.store(now, Ordering::Relaxed);
+        }
+
+        if self.timed_out.load(Ordering::Acquire) {
+            return;
+        }
+        if !self.server_answered.load(Ordering::Acquire) {
+            if self.last_contact_at.load(Ordering::Relaxed) + self.timeouts.join_retry_interval < now {
+                if self.contact_attempts.fetch_add(1, Ordering::AcqRel) < self.timeouts.join_retries {
+                    log_info!("No answer from {:?}, sending join handshake again", self.server_addr());
+                    self.contact_server(self.server_addr());
+                    if let Some(fallback_addr) = self.fallback_addr
+                        && self.fallback_contacted.load(Ordering::Acquire)
+                    {
+                        self.contact_server(fallback_addr);
+                    }
+                } else {
+                    log_warn!("Server {:?} did not answer the join handshake", self.server_addr());
+                    self.timed_out.store(true, Ordering::Release);
+                    self.network_event_sender.send(NetworkEvent::OwnJoinTimedOut).ok();
+                }
+            }
+        } else if !self.join_synced_up.load(Ordering::Acquire)
+            && self.join_started_at.load(Ordering::Relaxed) + self.timeouts.join_timeout < now
+        {
+            log_warn!("Join to {:?} timed out", self.server_addr());
+            self.timed_out.store(true, Ordering::Release);
+            self.network_event_sender.send(NetworkEvent::OwnJoinTimedOut).ok();
+        } else if self.last_server_msg.load(Ordering::Relaxed) + self.timeouts.disconnect_timeout < now {
+            log_warn!("Server {:?} timed out", self.server_addr());
+            self.timed_out.store(true, Ordering::Release);
+            self.network_event_sender.send(NetworkEvent::ServerTimedOut).ok();
+        }
+    }
+
+    fn join_req(&self) -> UdpMessage<MpMessage<W::ActionType>> {
+        UdpMessage::MpMessage(MpMessage::JoinReq {
+            protocol_version: PROTOCOL_VERSION,
+            app_version: self.app_version.clone(),
+            player_info: self.player_info.clone(),
+            auth_token: self.auth_token.clone(),
+        })
+    }
+
+    fn request_join_data(&self, from_chunk: u32) {
+        self.transport.send(
+            self.server_addr(),
+            UdpMessage::MpMessage(MpMessage::JoinReqUniverseData {
+                player_info: self.player_info.clone(),
+                from_chunk,
+            }),
+            Duration::from_secs(5),
+        );
+    }
+
+    fn receive_join_data_chunk(
+        &self,
+        universe: &Universe<W>,
+        total_size: u64,
+        chunk_count: u32,
+        chunk: u32,
+        data: Vec<u8>,
+    ) {
+        let mut join_data_lock = self.join_data.lock().unwrap();
+        let join_data = join_data_lock.get_or_insert_with(|| IncomingJoinData::new(total_size, chunk_count));
+        join_data.insert(chunk, data);
+        self.network_event_sender
+            .send(NetworkEvent::OwnJoinDataProgress {
+                received: join_data.received_size(),
+                total: join_data.total_size(),
+            })
+            .ok();
+
+        match join_data.complete() {
+            Some(Ok(join_data)) => {
+                log_info!("Received universe data for frame: {:?}", join_data.active_frame);
+                join_data_lock.take();
+                self.join_data_loaded.store(true, Ordering::Release);
+                universe.load_universe(
+                    UniverseDataType::from_bytes(
+                        join_data.universe_data.as_slice(),
+                        Some(self.server_info.read().unwrap().clone()),
+                        Some(self.player_info.clone()),
+                    ),
+                    join_data
+                        .worlds_data
+                        .into_iter()
+                        .map(|world_data| WorldType::from_bytes(&world_data, Some(self.player_info.clone())).unwrap())
+                        .collect(),
+                    Some(join_data.active_frame),
+                );
+
+                self.network_event_sender
+                    .send(NetworkEvent::OwnJoinDataRecvSuccess)
+                    .ok();
+            }
+            Some(Err(reason)) => {
+                log_warn!("Failed to decode universe data: {}", reason);
+                join_data_lock.take();
+                self.join_data_loaded.store(true, Ordering::Release);
+                self.network_event_sender
+                    .send(NetworkEvent::OwnJoinDataRecvFailure { reason })
+                    .ok();
+            }
+            None => {
+                if let Some(from_chunk) = join_data.next_request() {
+                    self.request_join_data(from_chunk);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn store_rollback_snapshots(&self, frame: FrameId, worlds: &Map<WorldId, W>) {
+        if let Some(rollback) = &self.rollback {
+            rollback.lock().unwrap().store_snapshots(frame, || {
+                worlds.values().map(|world| (world.id(), world.snapshot())).collect()
+            });
+        }
+    }
+
+    /// Sends the checksums of the worlds after the frame to the server, which compares them to its own.
+    /// Only in lockstep mode, as rollback and snapshot clients may differ from the server for a while by design.
+    pub(crate) fn send_world_checksums(&self, frame: FrameId, worlds: &Map<WorldId, W>) {
+        if self.sync_mode == NetSyncMode::Lockstep && frame.is_multiple_of(CHECKSUM_INTERVAL) {
+            let msg = UdpMessage::MpMessage(MpMessage::WorldChecksums {
+                frame,
+                checksums: world_checksums(worlds),
+            });
+            self.transport.send(self.server_addr(), msg, Duration::from_secs(15));
+        }
+    }
+
+    fn check_and_report_connection_quality(&self) {
+        let stats = self.connection_stats();
+        if let Some(quality) = self.quality_monitor.lock().unwrap().update(stats.rtt, stats.traffic) {
+            log_info!("Connection to server is now {:?}", quality);
+            self.network_event_sender
+                .send(NetworkEvent::ConnectionQualityChanged {
+                    player: stats.player,
+                    quality,
+                })
+                .ok();
+        }
+    }
+
+    pub(crate) fn connection_stats(&self) -> ConnectionStats {
+        ConnectionStats {
+            addr: self.server_addr(),
+            player: self.server_player.read().unwrap().clone(),
+            rtt: self.transport.latency_of(self.server_addr()).map(|latency| latency * 2),
+            traffic: self.transport.traffic_stats_of(self.server_addr()).unwrap_or_default(),
+        }
+    }
+
+    /// Sends the chat message to the server, which passes it on to the target players.
+    pub(crate) fn send_chat(&self, target: ChatTarget, message: &str) {
+        self.transport.send(
+            self.server_addr(),
+            UdpMessage::MpMessage(MpMessage::ChatFromClient {
+                target,
+                message: message.to_owned(),
+            }),
+            Duration::from_secs(15),
+        );
+    }
+
+    /// Sends own voice to the server, which passes it on to the other players.
+    pub(crate) fn send_voice(&self, packet: VoicePacket) {
+        let msg = UdpMessage::MpMessage(MpMessage::VoiceFromClient { packet });
+        self.transport.send(self.server_addr(), msg, Duration::ZERO);
+    }
+
+    /// Voice of other players received since the last call.
+    pub(crate) fn take_received_voice(&self) -> Vec<(PlayerId, VoicePacket)> {
+        std::mem::take(&mut *self.voice_received.lock().unwrap())
+    }
+
+    /// Checks the predicted frames against the actions received from the server.
+    /// On a misprediction, restores the worlds to the snapshot before the mispredicted frame and returns the frames
+    /// that must be executed again before the active frame.
+    fn rollback_mispredicted_frames(
+        &self,
+        active_frame: FrameId,
+        actions: &MpActionBuffer<W::ActionType>,
+        worlds_lock: &mut MutexGuard<Map<WorldId, W>>,
+    ) -> Vec<ResimulatedFrame<W>> {
+        let Some(rollback) = &self.rollback else {
+            return Vec::new();
+        };
+        let mut rollback = rollback.lock().unwrap();
+        let Some(from_frame) = rollback.first_misprediction(|frame| actions.export_actions(frame)) else {
+            return Vec::new();
+        };
+
+        let (snapshots, mut own_actions) = rollback.rollback(from_frame);
+        for world in worlds_lock.values_mut() {
+            world.restore_snapshot(snapshots.get(&world.id()).expect("Snapshot must exist for every world"));
+        }
+
+        (from_frame..active_frame)
+            .map(|frame| {
+                let frame_actions = match actions.export_actions(frame) {
+                    Some(frame_actions) => frame_actions,
+                    None => {
+                        let own_actions = own_actions.remove(&frame).unwrap_or_default();
+                        self.predict_actions(&mut rollback, frame, own_actions, actions)
+                            .expect("Previous frames must have been confirmed before predicting")
+                    }
+                };
+                let (players_joined, players_left) = self.players_changed_on_frame(frame, actions);
+                ResimulatedFrame {
+                    frame,
+                    players_joined,
+                    players_left,
+                    players_moved: self.players_moved_on_frame(frame, actions),
+                    actions: frame_actions,
+                }
+            })
+            .collect()
+    }
+
+    /// Predicts the actions of a frame from the latest frame received from the server.
+    /// Returns none if nothing has been received from the server within the rollback window.
+    fn predict_actions(
+        &self,
+        rollback: &mut RollbackBuffer<W::ActionType>,
+        frame: FrameId,
+        own_actions: Map<WorldId, Vec<W::ActionType>>,
+        actions: &MpActionBuffer<W::ActionType>,
+    ) -> Option<FrameActions<W::ActionType>> {
+        let latest_confirmed = (frame.saturating_sub(rollback.max_frames() as u64 + 1)..frame)
+            .rev()
+            .find_map(|frame| actions.export_actions(frame))?;
+        Some(rollback.predict(frame, self.player_info.id, own_actions, &latest_confirmed))
+    }
+
+    /// Returns players that moved between worlds on the frame. Frames that have not been received from the server
+    /// are predicted to have no moves.
+    fn players_moved_on_frame(&self, frame: FrameId, actions: &MpActionBuffer<W::ActionType>) -> Vec<PlayerMove> {
+        actions
+            .players_moved_on_frame(frame)
+            .into_iter()
+            .filter_map(|(player_id, left_worlds, joined_worlds)| {
+                let player_info = if player_id == self.player_info.id {
+                    self.player_info.clone()
+                } else if let Some(server_player) = &*self.server_player.read().unwrap()
+                    && server_player.id == player_id
+                {
+                    server_player.clone()
+                } else {
+                    self.client_players.read().unwrap().get(&player_id)?.clone()
+                };
+                Some(PlayerMove {
+                    player_info,
+                    left_worlds,
+                    joined_worlds,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns players that joined and left on the frame. Frames that have not been received from the server
+    /// are predicted to have no changes.
+    fn players_changed_on_frame(
+        &self,
+        frame: FrameId,
+        actions: &MpActionBuffer<W::ActionType>,
+    ) -> (Vec<NetworkPlayerInfo>, Vec<PlayerId>) {
+        let players_left = actions.players_left_on_frame(frame);
+        let players_joined = actions
+            .players_joined_on_frame(frame)
+            .into_iter()
+            .map(|player_id| {
+                if player_id != self.player_info.id {
+                    self.client_players
+                        .read()
+                        .unwrap()
+                        .iter()
+                        .find(|player| player.1.id == player_id)
+                        .expect("Must have info for joining player")
+                        .1
+                        .clone()
+                } else {
+                    self.player_info.clone()
+                }
+            })
+            .collect();
+        (players_joined, players_left)
+    }
+
     fn process_network_events(&self, universe: &Universe<W>, action_holder: &mut MpActionBuffer<W::ActionType>) {
-        for (from_addr, msg) in self.udp_socket.try_recv_all() {
+        for (from_addr, msg) in self.transport.try_recv_all() {
+            // Stay with the address the server answers at first
+            if !self.server_answered.load(Ordering::Acquire) {
+                if Some(from_addr) == self.fallback_addr && from_addr != self.server_addr() {
+                    log_info!("Server answered at {:?}", from_addr);
+                    *self.server_addr.write().unwrap() = from_addr;
+                }
+                if from_addr == self.server_addr() {
+                    self.server_answered.store(true, Ordering::Release);
+                }
+            }
             match msg {
                 UdpMessage::SysMessage(msg) => {
                     if msg == SysMessage::NatPunchPing {
-                        if from_addr == self.server_addr {
+                        if from_addr == self.server_addr() {
                             log_info!("Received NatPunchPing");
                             if !self.join_request_sent.load(Ordering::Acquire) {
                                 self.join_request_sent.store(true, Ordering::Release);
-                                log_info!("Sending JoinReq to {:?}", self.server_addr);
-                                self.udp_socket.send(
-                                    self.server_addr,
-                                    UdpMessage::MpMessage(MpMessage::JoinReq {
-                                        player_info: self.player_info.clone(),
-                                    }),
-                                    Duration::from_secs(30),
-                                );
+                                log_info!("Sending JoinReq to {:?}", self.server_addr());
+                                self.transport
+                                    .send(self.server_addr(), self.join_req(), Duration::from_secs(30));
                             }
                         } else {
                             log_info!("Got SystemMessage from non-server addr: {:?}", from_addr);
@@ -237,10 +756,29 @@ impl<W: WorldType> MpClient<W> {
                     }
                 }
                 UdpMessage::MpMessage(msg) => {
-                    if from_addr == self.server_addr {
+                    if from_addr == self.server_addr() {
+                        self.last_server_msg.store(Instant::now(), Ordering::Relaxed);
                         match msg {
                             MpMessage::ActionsFromServer { for_frame, actions } => {
-                                action_holder.import_batch_actions(for_frame, &actions);
+                                let base = actions.base_frame.map(|frame| action_holder.export_actions(frame));
+                                match base {
+                                    Some(None) => {
+                                        log_warn!("Missing base frame for actions of frame {}", for_frame);
+                                    }
+                                    _ => {
+                                        let frame_actions = actions.decode(base.flatten().as_ref());
+                                        action_holder.import_batch_actions(for_frame, &frame_actions);
+                                    }
+                                }
+                            }
+                            MpMessage::WorldAdded { frame, world_id, data } => {
+                                log_info!("Received WorldAdded for world {} on frame {}", world_id, frame);
+                                self.added_worlds.lock().unwrap().push((frame, world_id, data));
+                            }
+                            MpMessage::StateSnapshot { frame, worlds } => {
+                                if let Some(snapshots) = &self.snapshots {
+                                    snapshots.lock().unwrap().insert(frame, worlds);
+                                }
                             }
                             MpMessage::JoinRes {
                                 accepted,
@@ -251,13 +789,7 @@ impl<W: WorldType> MpClient<W> {
                             } => {
                                 if accepted {
                                     log_info!("Received JoinRes accepted");
-                                    self.udp_socket.send(
-                                        from_addr,
-                                        UdpMessage::MpMessage(MpMessage::JoinReqUniverseData {
-                                            player_info: self.player_info.clone(),
-                                        }),
-                                        Duration::from_secs(5),
-                                    );
+                                    self.request_join_data(0);
 
                                     self.network_event_sender.send(NetworkEvent::OwnJoinAllowed).ok();
                                     *self.server_player.write().unwrap() = server_player;
@@ -279,6 +811,29 @@ impl<W: WorldType> MpClient<W> {
                                         .ok();
                                 }
                             }
+                            MpMessage::IncompatibleVersion {
+                                protocol_version,
+                                app_version,
+                            } => {
+                                log_warn!("Server runs version {} with protocol {}", app_version, protocol_version);
+                                // The join can't succeed, so it doesn't time out either
+                                self.timed_out.store(true, Ordering::Release);
+                                let reason = format!(
+                                    "Server runs version {} with protocol {}, but this client runs version {} with protocol {}",
+                                    app_version, protocol_version, self.app_version, PROTOCOL_VERSION
+                                );
+                                self.network_event_sender
+                                    .send(NetworkEvent::IncompatibleVersion { reason })
+                                    .ok();
+                            }
+                            MpMessage::JoinQueued { position } => {
+                                log_info!("Received JoinQueued at position {}", position);
+                                // Waiting in the queue doesn't count towards the join timeout
+                                self.join_started_at.store(Instant::now(), Ordering::Relaxed);
+                                self.network_event_sender
+                                    .send(NetworkEvent::OwnJoinQueued { position })
+                                    .ok();
+                            }
                             MpMessage::PlayerJoinStart { player_info } => {
                                 log_info!("Received PlayerJoinStart: {:?}", player_info);
                                 self.server_info.write().unwrap().cur_player_count += 1;
@@ -317,36 +872,60 @@ impl<W: WorldType> MpClient<W> {
                             MpMessage::LatencyUpdate { latency } => {
                                 *self.latency_duration.lock().unwrap() = latency;
                             }
-                            MpMessage::JoinResUniverseData {
-                                universe_data,
-                                worlds_data,
-                                active_frame,
+                            MpMessage::TimeSyncRes {
+                                client_time,
+                                server_frame,
                             } => {
-                                log_info!("Received JoinResUniverseData for frame: {:?}", active_frame);
-                                universe.load_universe(
-                                    UniverseDataType::from_bytes(
-                                        universe_data.as_slice(),
-                                        Some(self.server_info.read().unwrap().clone()),
-                                        Some(self.player_info.clone()),
-                                    ),
-                                    worlds_data
-                                        .into_iter()
-                                        .map(|world_data| {
-                                            WorldType::from_bytes(&world_data, Some(self.player_info.clone())).unwrap()
-                                        })
-                                        .collect(),
-                                    Some(active_frame),
+                                self.time_sync.lock().unwrap().on_response(
+                                    client_time,
+                                    server_frame,
+                                    universe.active_frame(),
+                                    universe.universe_frame_time(),
+                                    Instant::now(),
                                 );
-
+                            }
+                            MpMessage::PlayerSyncStatus { statuses } => {
                                 self.network_event_sender
-                                    .send(NetworkEvent::OwnJoinDataRecvSuccess)
+                                    .send(NetworkEvent::PlayerSyncStatus { statuses })
                                     .ok();
                             }
+                            MpMessage::JoinResUniverseData {
+                                total_size,
+                                chunk_count,
+                                chunk,
+                                data,
+                            } if !self.join_data_loaded.load(Ordering::Acquire) => {
+                                self.receive_join_data_chunk(universe, total_size, chunk_count, chunk, data);
+                            }
                             MpMessage::PlayerLeft { player_info } => {
                                 log_info!("Received PlayerLeft: {:?}", player_info);
                                 self.server_info.write().unwrap().cur_player_count -= 1;
                                 self.client_players.write().unwrap().remove(&player_info.id);
                             }
+                            MpMessage::ServerShutdown { reason } => {
+                                log_info!("Received ServerShutdown: {}", reason);
+                                self.network_event_sender
+                                    .send(NetworkEvent::ServerShutdown { reason })
+                                    .ok();
+                            }
+                            MpMessage::Desync { frame } => {
+                                log_warn!("Received Desync for frame {}", frame);
+                                let msg = NetworkEvent::Desync {
+                                    frame,
+                                    player: self.player_info.id,
+                                };
+                                self.network_event_sender.send(msg).ok();
+                            }
+                            MpMessage::Kicked { reason } => {
+                                log_info!("Received Kicked: {}", reason);
+                                self.network_event_sender.send(NetworkEvent::OwnKicked { reason }).ok();
+                            }
+                            MpMessage::Voice { player_id, packet } => {
+                                self.voice_received.lock().unwrap().push((player_id, packet));
+                            }
+                            MpMessage::Chat { message } => {
+                                self.network_event_sender.send(NetworkEvent::Chat { message }).ok();
+                            }
                             _ => {}
                         }
                     } else {
@@ -360,8 +939,8 @@ impl<W: WorldType> MpClient<W> {
 
 impl<W: WorldType> Drop for MpClient<W> {
     fn drop(&mut self) {
-        self.udp_socket.send(
-            self.server_addr,
+        self.transport.send(
+            self.server_addr(),
             UdpMessage::MpMessage(MpMessage::Leaving {
                 player_info: self.player_info.clone(),
             }),
@@ -369,3 +948,21 @@ impl<W: WorldType> Drop for MpClient<W> {
         );
     }
 }
+
+/// Address to contact the server at first, and the one to try too if the server does not answer there.
+/// Dual stack clients prefer IPv6 and fall back to IPv4, others only use IPv4.
+fn server_addrs_for(server_info: &NetworkServerInfo, is_dual_stack: bool) -> (SocketAddr, Option<SocketAddr>) {
+    let Some(alt_addr) = server_info.alt_addr else {
+        return (server_info.addr, None);
+    };
+    let (ipv6_addr, ipv4_addr) = if server_info.addr.is_ipv6() {
+        (server_info.addr, alt_addr)
+    } else {
+        (alt_addr, server_info.addr)
+    };
+    if is_dual_stack {
+        (ipv6_addr, Some(ipv4_addr))
+    } else {
+        (ipv4_addr, None)
+    }
+}
@@ -1,17 +1,29 @@
 use std::collections::HashSet;
 use std::fmt::Debug;
+use std::net::IpAddr;
 use std::sync::MutexGuard;
 use std::{collections::BTreeMap, net::SocketAddr, time::Duration};
 
 use bincode::{Decode, Encode};
 
 use ion_common::net::NetworkPlayerInfo;
+use ion_common::net::tls::TlsCertificate;
+use ion_common::net::udp_network_socket::TrafficStats;
 use ion_common::{Instant, Map, PlayerId};
 
-use crate::core::FrameId;
 use crate::core::world::{ActionType, WorldId, WorldType};
+use crate::core::{DEFAULT_UPS, FrameId, PlayerMove};
 
-use super::{mp_client::MpClient, mp_server::MpServer};
+use super::{
+    action_delta::ActionDelta, mp_client::MpClient, mp_server::MpServer, quality::ConnectionQuality,
+    rcon::AdminCommand, snapshot::SnapshotInterpolation, voice::VoicePacket,
+};
+
+// Bumped whenever the multiplayer messages change, so that different engine versions refuse to play together
+pub(super) const PROTOCOL_VERSION: u32 = 1;
+
+// Frames between world checksums that are compared to detect desyncs
+pub(super) const CHECKSUM_INTERVAL: FrameId = DEFAULT_UPS;
 
 // ---------------------------------------------------------- //
 // ----------------- Common network types ------------------- //
@@ -20,30 +32,194 @@ use super::{mp_client::MpClient, mp_server::MpServer};
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum NetworkEvent {
     OwnJoinAllowed,
-    OwnJoinDenied { reason: String },
+    OwnJoinDenied {
+        reason: String,
+    },
+    OwnJoinQueued {
+        position: u32,
+    },
 
+    OwnJoinDataProgress {
+        received: u64,
+        total: u64,
+    },
     OwnJoinDataRecvSuccess,
-    OwnJoinDataRecvFailure { reason: String },
+    OwnJoinDataRecvFailure {
+        reason: String,
+    },
 
     OwnJoinSuccess,
-    OwnJoinFailure { reason: String },
+    OwnJoinFailure {
+        reason: String,
+    },
+    OwnJoinTimedOut,
+    IncompatibleVersion {
+        reason: String,
+    },
 
     ServerActionsNotReceived,
+    ServerTimedOut,
+    ConnectionQualityChanged {
+        player: Option<NetworkPlayerInfo>,
+        quality: ConnectionQuality,
+    },
 
-    PlayerJoinStart { player_info: NetworkPlayerInfo },
-    PlayerJoinSuccess { player_info: NetworkPlayerInfo },
-    PlayerJoinFailure { player_info: NetworkPlayerInfo },
-    PlayerLeft { player_info: NetworkPlayerInfo },
+    PlayerJoinStart {
+        player_info: NetworkPlayerInfo,
+    },
+    PlayerJoinSuccess {
+        player_info: NetworkPlayerInfo,
+    },
+    PlayerJoinFailure {
+        player_info: NetworkPlayerInfo,
+    },
+    PlayerLeft {
+        player_info: NetworkPlayerInfo,
+    },
+    PlayerTimedOut {
+        player_info: NetworkPlayerInfo,
+    },
+    PlayerSyncStatus {
+        statuses: Vec<PlayerSyncStatus>,
+    },
+
+    ServerShutdown {
+        reason: String,
+    },
+    OwnKicked {
+        reason: String,
+    },
+
+    Desync {
+        frame: FrameId,
+        player: PlayerId,
+    },
+
+    Chat {
+        message: ChatMessage,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct ChatMessage {
+    /// Player who sent the message, or none if the server itself sent it.
+    pub from: Option<NetworkPlayerInfo>,
+    pub target: ChatTarget,
+    pub text: String,
+}
+
+pub type TeamId = u32;
+
+/// How well a player keeps up with the server, reported once a second to the server and all clients.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct PlayerSyncStatus {
+    pub player: PlayerId,
+    /// Round trip time between the player and the server. Zero for the own player of the server.
+    pub rtt: Duration,
+    /// Frames since the server last received actions from the player. Keeps growing while the player is stalled.
+    pub frame_lag: FrameId,
+}
+
+/// Who a chat message is sent to. The sender always receives its own message too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum ChatTarget {
+    All,
+    Player(PlayerId),
+    /// Players the server has put into the team, see [`Network::mp_set_player_team`](super::Network::mp_set_player_team).
+    Team(TeamId),
+}
+
+/// Quality of the multiplayer connections, see [`Network::stats`](super::Network::stats).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NetworkStats {
+    /// Connections to all clients on a server, or the connection to the server on a client.
+    pub connections: Vec<ConnectionStats>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionStats {
+    pub addr: SocketAddr,
+    /// Player at the other end, or none for a server without an own player.
+    pub player: Option<NetworkPlayerInfo>,
+    /// Round trip time, estimated from acks. None until the first ack has been received.
+    pub rtt: Option<Duration>,
+    pub traffic: TrafficStats,
 }
 
 #[derive(Debug, Clone)]
 pub(crate) struct ActionSyncResult<W: WorldType> {
     pub players_joined: Vec<NetworkPlayerInfo>,
     pub players_left: Vec<PlayerId>,
+    pub players_moved: Vec<PlayerMove>,
     pub actions: Map<WorldId, BTreeMap<PlayerId, Vec<W::ActionType>>>,
+    /// Whether the frame should be rendered. Clients only slightly behind the server are at sync, as they catch up by
+    /// pacing their ticks instead of running frames without rendering.
     pub is_at_sync: bool,
+    /// Frames to execute again before the active frame, because they were executed with mispredicted actions.
+    /// The worlds have already been restored to their state before the first of these frames.
+    pub resimulated_frames: Vec<ResimulatedFrame<W>>,
+    /// State received from the server, applied to the worlds before the frame by clients in snapshot sync mode.
+    pub server_snapshot: Option<SnapshotInterpolation>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ResimulatedFrame<W: WorldType> {
+    pub frame: FrameId,
+    pub players_joined: Vec<NetworkPlayerInfo>,
+    pub players_left: Vec<PlayerId>,
+    pub players_moved: Vec<PlayerMove>,
+    pub actions: Map<WorldId, BTreeMap<PlayerId, Vec<W::ActionType>>>,
+}
+
+/// How multiplayer clients keep their universe in sync with the server.
+/// The server and all clients must use the same mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NetSyncMode {
+    /// Clients wait for the actions of all players before executing a frame.
+    /// Own actions are delayed by the latency to the server.
+    #[default]
+    Lockstep,
+    /// Clients execute frames right away, predicting that remote players do nothing.
+    /// When the actions from the server differ from the prediction, the worlds are restored from a snapshot and the
+    /// frames since are executed again. Requires worlds to implement [`WorldType::restore_snapshot`].
+    ///
+    /// Best suited for small games, where snapshotting and re-executing frames is cheap.
+    Rollback {
+        /// How many frames a client may run ahead of the server before it waits like in lockstep mode.
+        max_frames: u32,
+    },
+    /// Only the server executes stateful actions. It streams snapshots of the worlds to the clients, which
+    /// interpolate between them. For games that can't guarantee determinism across platforms.
+    ///
+    /// Worlds choose what each player receives with [`WorldType::interest_snapshot`], and clients apply them with
+    /// [`WorldType::apply_server_snapshot`]. Clients still execute frames with their stateless actions.
+    Snapshot {
+        /// How many frames there are between snapshots. Clients show the state this many frames in the past.
+        interval: u32,
+    },
 }
 
+/// How the traffic of browsers connected to the WebSocket bridge is encrypted, see
+/// [`Network::mp_start_ws_bridge`](super::Network::mp_start_ws_bridge).
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum WsBridgeTls {
+    /// Nothing is encrypted. Browsers can only join from pages served over plain http.
+    #[default]
+    None,
+    /// The bridge terminates TLS with the certificate. Browsers connect to the
+    /// [`ws_host`](ion_common::net::NetworkServerInfo::ws_host) of the server, which the certificate has to be for.
+    Certificate(TlsCertificate),
+    /// Proxies at the addresses, such as nginx, terminate TLS in front of the bridge. Browsers coming through them
+    /// with `X-Forwarded-Proto: https` are encrypted on the way.
+    Proxies(Vec<IpAddr>),
+}
+
+/// Checksums of all worlds, see [`WorldType::checksum`].
+pub(super) fn world_checksums<W: WorldType>(worlds: &Map<WorldId, W>) -> Map<WorldId, u64> {
+    worlds.values().map(|world| (world.id(), world.checksum())).collect()
+}
+
+#[allow(clippy::large_enum_variant)]
 pub(crate) enum MpInstance<W: WorldType> {
     Server(MpServer<W>),
     Client(MpClient<W>),
@@ -54,18 +230,48 @@ pub(crate) enum MpMessage<C: ActionType> {
     ActionsFromClient {
         for_frame: FrameId,
         actions: Map<WorldId, Vec<C>>,
+        /// Latest frame the client has received actions for, used as the base of action deltas.
+        acked_frame: Option<FrameId>,
     },
     ActionsFromServer {
         for_frame: FrameId,
-        actions: Map<PlayerId, BTreeMap<WorldId, Vec<C>>>,
+        actions: ActionDelta<C>,
+    },
+    StateSnapshot {
+        frame: FrameId,
+        worlds: Map<WorldId, Vec<u8>>,
+    },
+    /// State of a world before the frame, sent when the world is routed to the player.
+    WorldAdded {
+        frame: FrameId,
+        world_id: WorldId,
+        data: Vec<u8>,
     },
 
     LatencyUpdate {
         latency: Duration,
     },
+    /// Sent over otherwise quiet connections, so that the other end doesn't time out.
+    Keepalive,
+    /// Answered by the server with its active frame, see [`super::time_sync`].
+    TimeSyncReq {
+        client_time: Duration,
+    },
+    TimeSyncRes {
+        client_time: Duration,
+        server_frame: FrameId,
+    },
+    PlayerSyncStatus {
+        statuses: Vec<PlayerSyncStatus>,
+    },
 
     JoinReq {
+        /// Versions are first, so that they can be read even if the rest of the request comes from a different
+        /// engine version.
+        protocol_version: u32,
+        app_version: String,
         player_info: NetworkPlayerInfo,
+        auth_token: Option<Vec<u8>>,
     },
     JoinRes {
         accepted: bool,
@@ -74,13 +280,24 @@ pub(crate) enum MpMessage<C: ActionType> {
         client_players: Map<SocketAddr, NetworkPlayerInfo>,
         client_players_joining: Map<SocketAddr, NetworkPlayerInfo>,
     },
+    JoinQueued {
+        position: u32,
+    },
+    /// Versions of the server, sent instead of a JoinRes when the client runs a different version.
+    IncompatibleVersion {
+        protocol_version: u32,
+        app_version: String,
+    },
     JoinReqUniverseData {
         player_info: NetworkPlayerInfo,
+        /// First chunk of the requested window, see [`super::join_transfer`].
+        from_chunk: u32,
     },
     JoinResUniverseData {
-        universe_data: Vec<u8>,
-        worlds_data: Vec<Vec<u8>>,
-        active_frame: FrameId,
+        total_size: u64,
+        chunk_count: u32,
+        chunk: u32,
+        data: Vec<u8>,
     },
     JoinComplete {
         player_info: NetworkPlayerInfo,
@@ -101,6 +318,46 @@ pub(crate) enum MpMessage<C: ActionType> {
     PlayerLeft {
         player_info: NetworkPlayerInfo,
     },
+
+    ServerShutdown {
+        reason: String,
+    },
+
+    ChatFromClient {
+        target: ChatTarget,
+        message: String,
+    },
+    Chat {
+        message: ChatMessage,
+    },
+
+    Kicked {
+        reason: String,
+    },
+
+    WorldChecksums {
+        frame: FrameId,
+        checksums: Map<WorldId, u64>,
+    },
+    Desync {
+        frame: FrameId,
+    },
+
+    AdminCommand {
+        token: Vec<u8>,
+        command: AdminCommand,
+    },
+    AdminResponse {
+        result: Result<String, String>,
+    },
+
+    VoiceFromClient {
+        packet: VoicePacket,
+    },
+    Voice {
+        player_id: PlayerId,
+        packet: VoicePacket,
+    },
 }
 
 #[allow(clippy::type_complexity)]
@@ -108,6 +365,7 @@ pub(crate) enum MpMessage<C: ActionType> {
 pub(super) struct MpActionBuffer<C: ActionType> {
     players: Map<FrameId, HashSet<PlayerId>>,
     actions: Map<FrameId, Map<WorldId, BTreeMap<PlayerId, Vec<C>>>>,
+    latest_frame: Option<FrameId>,
 }
 
 impl<C: ActionType> MpActionBuffer<C> {
@@ -115,11 +373,12 @@ impl<C: ActionType> MpActionBuffer<C> {
         Self {
             players: Map::default(),
             actions: Map::default(),
+            latest_frame: None,
         }
     }
 
-    pub(super) fn contains_frame(&self, frame: FrameId) -> bool {
-        self.actions.contains_key(&frame)
+    pub(super) fn latest_frame(&self) -> Option<FrameId> {
+        self.latest_frame
     }
 
     pub(super) fn contains_player_for_frame(&self, frame: FrameId, player_id: PlayerId) -> bool {
@@ -129,6 +388,7 @@ impl<C: ActionType> MpActionBuffer<C> {
             .unwrap_or(false)
     }
     pub(super) fn import_actions(&mut self, frame: FrameId, world: WorldId, player_id: PlayerId, actions: &[C]) {
+        self.latest_frame = self.latest_frame.max(Some(frame));
         self.players.entry(frame).or_default().insert(player_id);
         self.actions
             .entry(frame)
@@ -148,7 +408,7 @@ impl<C: ActionType> MpActionBuffer<C> {
         }
     }
 
-    pub(super) fn export_actions(&mut self, frame: FrameId) -> Option<Map<WorldId, BTreeMap<PlayerId, Vec<C>>>> {
+    pub(super) fn export_actions(&self, frame: FrameId) -> Option<Map<WorldId, BTreeMap<PlayerId, Vec<C>>>> {
         self.actions.get(&frame).cloned()
     }
 
@@ -191,6 +451,49 @@ impl<C: ActionType> MpActionBuffer<C> {
         }
     }
 
+    /// Keeps the actions of the player on the frame and later frames only in the world, as it has moved there.
+    pub(super) fn relocate_player(&mut self, from_frame: FrameId, player_id: PlayerId, world: WorldId) {
+        for (_, worlds) in self.actions.iter_mut().filter(|(frame, _)| **frame >= from_frame) {
+            for (_, players) in worlds.iter_mut().filter(|(world_id, _)| **world_id != world) {
+                players.remove(&player_id);
+            }
+        }
+    }
+
+    /// Returns the players that stayed in the universe but left or joined worlds on the frame, with those worlds.
+    /// Worlds that were added or removed on the frame are left out.
+    pub(super) fn players_moved_on_frame(&self, frame: FrameId) -> Vec<(PlayerId, Vec<WorldId>, Vec<WorldId>)> {
+        let prev_frame = frame.max(1) - 1;
+        let (Some(active_worlds), Some(prev_worlds), Some(active_players), Some(prev_players)) = (
+            self.actions.get(&frame),
+            self.actions.get(&prev_frame),
+            self.players.get(&frame),
+            self.players.get(&prev_frame),
+        ) else {
+            return Vec::new();
+        };
+
+        let mut moved = Vec::new();
+        for player_id in active_players.intersection(prev_players) {
+            let mut left = Vec::new();
+            let mut joined = Vec::new();
+            for (world_id, active) in active_worlds {
+                let Some(prev) = prev_worlds.get(world_id) else {
+                    continue;
+                };
+                match (prev.contains_key(player_id), active.contains_key(player_id)) {
+                    (true, false) => left.push(*world_id),
+                    (false, true) => joined.push(*world_id),
+                    _ => {}
+                }
+            }
+            if !left.is_empty() || !joined.is_empty() {
+                moved.push((*player_id, left, joined));
+            }
+        }
+        moved
+    }
+
     pub(super) fn players_left_on_frame(&self, frame: FrameId) -> Vec<PlayerId> {
         let active_frame_players = self.players.get(&frame);
         let prev_frame_players = self.players.get(&(frame.max(1) - 1));
@@ -211,3 +514,37 @@ impl<C: ActionType> Debug for MpActionBuffer<C> {
         f.debug_struct("AllActions").finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Encode, Decode)]
+    struct TestAction(u32);
+
+    impl ActionType for TestAction {
+        fn is_stateful(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn relocated_players_move_between_worlds() {
+        let mut buffer = MpActionBuffer::new();
+        for frame in 10..=12 {
+            for world in [1, 2] {
+                buffer.import_actions(frame, world, 7, &[TestAction(frame as u32)]);
+                buffer.import_actions(frame, world, 8, &[]);
+            }
+        }
+
+        buffer.relocate_player(11, 7, 2);
+        assert!(buffer.export_actions(10).unwrap()[&1].contains_key(&7));
+        assert!(!buffer.export_actions(11).unwrap()[&1].contains_key(&7));
+        assert_eq!(buffer.export_actions(12).unwrap()[&2][&7], vec![TestAction(12)]);
+
+        assert_eq!(buffer.players_moved_on_frame(11), vec![(7, vec![1], vec![])]);
+        assert!(buffer.players_moved_on_frame(12).is_empty());
+        assert!(buffer.players_left_on_frame(11).is_empty());
+    }
+}
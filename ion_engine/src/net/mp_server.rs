@@ -1,84 +1,149 @@
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::io;
 use std::net::{IpAddr, Ipv4Addr};
 use std::sync::mpsc::Sender;
 use std::{
     net::SocketAddr,
-    sync::{Mutex, MutexGuard, atomic::Ordering},
+    sync::{
+        Mutex, MutexGuard, RwLock,
+        atomic::{AtomicUsize, Ordering},
+    },
     time::Duration,
 };
 
+use ion_common::net::transport::{NetworkTransport, PacketTransport};
 use ion_common::net::udp_network_socket::UdpNetworkSocket;
-use ion_common::net::{SysMessage, UdpMessage};
+use ion_common::net::ws_network_socket::WsNetworkListener;
+use ion_common::net::{AuthLockout, SysMessage, UdpMessage};
 use ion_common::{Instant, log_info};
-use ion_common::{Map, log_warn};
+use ion_common::{Map, PlayerId, PlayerIdentity, log_warn};
 
-use crate::core::DEFAULT_UPS;
 use crate::core::universe::UniverseDataType;
+use crate::core::{DEFAULT_UPS, NetworkTimeouts};
+use crate::files::Files;
 use crate::net::{NetworkPlayerInfo, NetworkServerInfo};
 use crate::util::concurrency::AtomicInstant;
 use crate::{
     core::{
-        FrameId,
+        FrameId, PlayerMove,
         universe::Universe,
         world::{WorldId, WorldType},
     },
-    net::mp_common::{MpActionBuffer, MpMessage, NetworkEvent},
+    net::mp_common::{
+        CHECKSUM_INTERVAL, ChatMessage, ChatTarget, ConnectionStats, MpActionBuffer, MpMessage, NetSyncMode,
+        NetworkEvent, PROTOCOL_VERSION, PlayerSyncStatus, TeamId, world_checksums,
+    },
 };
 
-use super::mp_common::ActionSyncResult;
+use super::action_delta::ActionDelta;
+use super::join_transfer::{JoinUniverseData, OutgoingJoinData};
+use super::mp_auth::{JoinAuthenticator, SharedSecretAuth};
+use super::mp_common::{ActionSyncResult, WsBridgeTls};
+use super::quality::QualityMonitor;
+use super::rcon::AdminCommand;
+use super::voice::VoicePacket;
+use super::world_routes::WorldRoutes;
+
+// Global servers send a heartbeat to the host at this interval, and the full server info when it has changed or the
+// publish interval has passed, in case the host has restarted and forgotten the server
+const GLOBAL_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+const GLOBAL_PUBLISH_INTERVAL: Duration = Duration::from_secs(60);
 
-const GLOBAL_PUBLISH_INTERVAL: Duration = Duration::from_secs(20);
+// Longer chat messages are cut short
+const MAX_CHAT_MESSAGE_LEN: usize = 500;
 
-const PLAYER_TIMEOUT: Duration = Duration::from_secs(15);
-const PLAYER_JOIN_TIMEOUT: Duration = Duration::from_secs(60);
+// Own world checksums kept for comparing with the checksums of clients that are behind
+const MAX_STORED_CHECKSUMS: usize = 32;
 
 // ---------------------------------------------------------- //
 // ------------------- Multiplayer Server ------------------- //
 // ---------------------------------------------------------- //
 
 pub struct MpServer<W: WorldType> {
-    udp_socket: UdpNetworkSocket<UdpMessage<MpMessage<W::ActionType>>>,
+    transport: Box<dyn NetworkTransport<UdpMessage<MpMessage<W::ActionType>>>>,
+    #[allow(clippy::type_complexity)]
+    ws_bridge: RwLock<Option<WsNetworkListener<UdpMessage<MpMessage<W::ActionType>>>>>,
     host_addr: SocketAddr,
     server_player: Option<NetworkPlayerInfo>,
     server_info: Mutex<NetworkServerInfo>,
+    sync_mode: NetSyncMode,
+    authenticator: Option<Box<dyn JoinAuthenticator>>,
+    admin_auth: Mutex<Option<SharedSecretAuth>>,
+    admin_lockout: Mutex<AuthLockout>,
+    files: Files,
     client_players: Mutex<Map<SocketAddr, (NetworkPlayerInfo, Instant)>>,
     client_players_joining: Mutex<Map<SocketAddr, (NetworkPlayerInfo, Instant)>>,
+    join_data: Mutex<Map<SocketAddr, OutgoingJoinData>>,
+    join_queue: Mutex<VecDeque<(SocketAddr, NetworkPlayerInfo)>>,
+    max_queued_players: AtomicUsize,
+    timeouts: NetworkTimeouts,
+    app_version: String,
 
     global_publish_last: AtomicInstant,
+    global_heartbeat_last: AtomicInstant,
+    global_published_info: Mutex<Option<NetworkServerInfo>>,
+    keepalive_last: AtomicInstant,
 
     latencies: Mutex<Map<SocketAddr, Duration>>,
     actions: Mutex<MpActionBuffer<W::ActionType>>,
+    acked_frames: Mutex<Map<SocketAddr, FrameId>>,
+    last_action_frames: Mutex<Map<SocketAddr, FrameId>>,
+    quality_monitors: Mutex<Map<SocketAddr, QualityMonitor>>,
+    world_routes: Mutex<WorldRoutes>,
+    player_worlds: Mutex<Map<PlayerId, WorldId>>,
+    pending_moves: Mutex<Vec<(PlayerId, Option<WorldId>)>>,
+    added_worlds: Mutex<Vec<(SocketAddr, WorldId)>>,
+    teams: Mutex<Map<PlayerId, TeamId>>,
+    checksums: Mutex<BTreeMap<FrameId, Map<WorldId, u64>>>,
+    desynced_players: Mutex<HashSet<PlayerId>>,
+    voice_received: Mutex<Vec<(PlayerId, VoicePacket)>>,
 
     network_event_sender: Sender<NetworkEvent>,
 }
 
 impl<W: WorldType> MpServer<W> {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         network_bind_addr: SocketAddr,
         network_host_addr: SocketAddr,
+        timeouts: NetworkTimeouts,
+        app_version: String,
         mut server_info: NetworkServerInfo,
         mut server_player: Option<NetworkPlayerInfo>,
+        sync_mode: NetSyncMode,
+        authenticator: Option<Box<dyn JoinAuthenticator>>,
+        custom_transport: Option<Box<dyn PacketTransport>>,
+        files: Files,
         network_event_sender: Sender<NetworkEvent>,
     ) -> Self {
         log_info!("Starting MpServer: {:?}", &server_info);
-        let udp_socket = UdpNetworkSocket::new(network_bind_addr);
-        if !udp_socket.is_loopback() {
-            if server_info.is_global {
-                server_info.addr.set_ip(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
-                udp_socket.send(
-                    network_host_addr,
-                    UdpMessage::SysMessage(SysMessage::SocketInfoReq),
-                    Duration::from_secs(30),
-                );
-            } else {
-                server_info.addr.set_ip(
-                    udp_socket
-                        .local_ip_addr()
-                        .expect("Local servers must get a valid local ip"),
-                );
+        let transport: Box<dyn NetworkTransport<_>> = match custom_transport {
+            // Custom transports find their own way to the server, so the address is left as given
+            Some(custom_transport) => Box::new(custom_transport),
+            None => {
+                let udp_socket = UdpNetworkSocket::new_encrypted(network_bind_addr);
+                server_info.network_key = Some(udp_socket.public_key());
+                if !udp_socket.is_loopback() {
+                    if server_info.is_global {
+                        server_info.addr.set_ip(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+                        udp_socket.send(
+                            network_host_addr,
+                            UdpMessage::SysMessage(SysMessage::SocketInfoReq),
+                            Duration::from_secs(30),
+                        );
+                    } else {
+                        server_info.addr.set_ip(
+                            udp_socket
+                                .local_ip_addr()
+                                .expect("Local servers must get a valid local ip"),
+                        );
+                    }
+                } else {
+                    server_info.addr = network_bind_addr;
+                }
+                Box::new(udp_socket)
             }
-        } else {
-            server_info.addr = network_bind_addr;
-        }
+        };
 
         server_info.cur_player_count = 0;
 
@@ -88,17 +153,42 @@ impl<W: WorldType> MpServer<W> {
 
         Self {
             host_addr: network_host_addr,
-            udp_socket,
+            transport,
+            ws_bridge: RwLock::new(None),
             server_info: Mutex::new(server_info),
             server_player,
+            sync_mode,
+            authenticator,
+            admin_auth: Mutex::new(None),
+            admin_lockout: Mutex::new(AuthLockout::new()),
+            files,
             client_players: Mutex::new(Map::default()),
             client_players_joining: Mutex::new(Map::default()),
+            join_data: Mutex::new(Map::default()),
+            join_queue: Mutex::new(VecDeque::new()),
+            max_queued_players: AtomicUsize::new(0),
+            timeouts,
+            app_version,
             network_event_sender,
 
-            global_publish_last: AtomicInstant::new(Instant::now() - Duration::from_secs(60)),
+            global_publish_last: AtomicInstant::new(Instant::now()),
+            global_heartbeat_last: AtomicInstant::new(Instant::now() - GLOBAL_HEARTBEAT_INTERVAL),
+            global_published_info: Mutex::new(None),
+            keepalive_last: AtomicInstant::new(Instant::now()),
 
             latencies: Mutex::new(Map::default()),
             actions: Mutex::new(MpActionBuffer::new()),
+            acked_frames: Mutex::new(Map::default()),
+            last_action_frames: Mutex::new(Map::default()),
+            quality_monitors: Mutex::new(Map::default()),
+            world_routes: Mutex::new(WorldRoutes::default()),
+            player_worlds: Mutex::new(Map::default()),
+            pending_moves: Mutex::new(Vec::new()),
+            added_worlds: Mutex::new(Vec::new()),
+            teams: Mutex::new(Map::default()),
+            checksums: Mutex::new(BTreeMap::new()),
+            desynced_players: Mutex::new(HashSet::new()),
+            voice_received: Mutex::new(Vec::new()),
         }
     }
 
@@ -109,6 +199,7 @@ impl<W: WorldType> MpServer<W> {
         worlds_lock: &mut MutexGuard<Map<WorldId, W>>,
     ) -> Option<ActionSyncResult<W>> {
         self.process_network_events(universe, worlds_lock);
+        self.check_join_queue(universe.active_frame());
 
         let mut client_players = self.client_players.lock().unwrap();
         let mut client_players_joining = self.client_players_joining.lock().unwrap();
@@ -125,11 +216,55 @@ impl<W: WorldType> MpServer<W> {
 
         self.check_and_report_latencies(active_frame, &mut latencies);
 
+        self.check_and_report_sync_status(active_frame, &client_players, &latencies);
+
+        self.check_and_report_connection_quality(active_frame, &client_players);
+
+        let world_ids: Vec<_> = worlds_lock.keys().copied().collect();
+        let mut world_routes = self.world_routes.lock().unwrap();
+        for (player_id, added) in world_routes.apply_pending(&world_ids) {
+            let addr = client_players
+                .iter()
+                .chain(client_players_joining.iter())
+                .find(|(_, (player, _))| player.id == player_id)
+                .map(|(addr, _)| *addr);
+            if let Some(addr) = addr {
+                // Deltas against frames with the old routes would not decode to the new worlds
+                self.acked_frames.lock().unwrap().remove(&addr);
+                let mut added_worlds = self.added_worlds.lock().unwrap();
+                added_worlds.extend(added.into_iter().map(|world_id| (addr, world_id)));
+            }
+        }
+
+        self.check_and_send_keepalives(&client_players, &client_players_joining);
+
         {
+            // Players move on the next frame, as it is the first frame that has not been sent to the clients yet
+            let mut player_worlds = self.player_worlds.lock().unwrap();
+            for (player_id, world) in self.pending_moves.lock().unwrap().drain(..) {
+                let is_joined = self.server_player.as_ref().is_some_and(|player| player.id == player_id)
+                    || client_players.values().any(|(player, _)| player.id == player_id);
+                match world {
+                    _ if !is_joined => {
+                        log_warn!("Can't move player {} that has not joined", player_id);
+                    }
+                    Some(world_id) => {
+                        player_worlds.insert(player_id, world_id);
+                        actions.relocate_player(next_frame, player_id, world_id);
+                    }
+                    None => {
+                        player_worlds.remove(&player_id);
+                    }
+                }
+            }
+
             // Add own actions to action holder
             if let Some(own_player) = &self.server_player {
+                let own_world = player_worlds.get(&own_player.id);
                 for (world_id, own_actions) in &own_global_actions {
-                    actions.import_actions(next_frame, *world_id, own_player.id, own_actions);
+                    if own_world.is_none_or(|own_world| own_world == world_id) {
+                        actions.import_actions(next_frame, *world_id, own_player.id, own_actions);
+                    }
                 }
             } else {
                 for world_id in own_global_actions.keys() {
@@ -144,45 +279,62 @@ impl<W: WorldType> MpServer<W> {
 
             // Check if we are missing any actions from any players
             for (_addr, player) in actions.missing_players_for_frame(next_frame, &client_players) {
-                for world_id in own_global_actions.keys() {
-                    actions.import_actions(next_frame, *world_id, player.id, &[]);
+                if !player_worlds.contains_key(&player.id) {
+                    for world_id in own_global_actions.keys() {
+                        actions.import_actions(next_frame, *world_id, player.id, &[]);
+                    }
                 }
             }
-
-            // Send action data for the next frame
-            for addr in client_players.keys() {
-                let msg = UdpMessage::MpMessage(MpMessage::ActionsFromServer {
-                    for_frame: next_frame,
-                    actions: actions.export_actions(next_frame).unwrap(),
-                });
-                self.udp_socket.send(*addr, msg, Duration::from_secs(15));
+            for (player_id, world_id) in &*player_worlds {
+                actions.import_actions(next_frame, *world_id, *player_id, &[]);
             }
+            drop(player_worlds);
 
-            for addr in client_players_joining.keys() {
+            // Send action data for the next frame, as deltas against what each client has received
+            let frame_actions = actions.export_actions(next_frame).unwrap();
+            let acked_frames = self.acked_frames.lock().unwrap();
+            for (addr, (player, _)) in client_players.iter().chain(client_players_joining.iter()) {
+                let routed_actions = world_routes.filter(player.id, &frame_actions);
+                let base = acked_frames.get(addr).and_then(|frame| {
+                    let base_actions = actions.export_actions(*frame)?;
+                    Some((*frame, world_routes.filter(player.id, &base_actions)))
+                });
                 let msg = UdpMessage::MpMessage(MpMessage::ActionsFromServer {
                     for_frame: next_frame,
-                    actions: actions.export_actions(next_frame).unwrap(),
+                    actions: ActionDelta::encode(&routed_actions, base.as_ref().map(|(frame, base)| (*frame, base))),
                 });
-                self.udp_socket.send(*addr, msg, Duration::from_secs(15));
+                self.send(*addr, msg, Duration::from_secs(15));
             }
+            drop(acked_frames);
+            drop(world_routes);
 
             // Prepare ActionSyncResult for active frame
+            let player_info_of = |player_id: PlayerId| {
+                if self.server_player.is_some() && self.server_player.as_ref().unwrap().id == player_id {
+                    self.server_player.as_ref().unwrap().clone()
+                } else {
+                    client_players
+                        .iter()
+                        .find(|(_, (player, _))| player.id == player_id)
+                        .expect("Must have info for joining player")
+                        .1
+                        .0
+                        .clone()
+                }
+            };
             let players_left: Vec<_> = actions.players_left_on_frame(active_frame);
             let players_joined: Vec<_> = actions
                 .players_joined_on_frame(active_frame)
                 .into_iter()
-                .map(|player_id| {
-                    if self.server_player.is_some() && self.server_player.as_ref().unwrap().id == player_id {
-                        self.server_player.as_ref().unwrap().clone()
-                    } else {
-                        client_players
-                            .iter()
-                            .find(|(_, (player, _))| player.id == player_id)
-                            .expect("Must have info for joining player")
-                            .1
-                            .0
-                            .clone()
-                    }
+                .map(player_info_of)
+                .collect();
+            let players_moved: Vec<_> = actions
+                .players_moved_on_frame(active_frame)
+                .into_iter()
+                .map(|(player_id, left_worlds, joined_worlds)| PlayerMove {
+                    player_info: player_info_of(player_id),
+                    left_worlds,
+                    joined_worlds,
                 })
                 .collect();
 
@@ -191,17 +343,283 @@ impl<W: WorldType> MpServer<W> {
             Some(ActionSyncResult {
                 players_joined,
                 players_left,
+                players_moved,
                 actions: actions.export_actions(active_frame).unwrap(),
                 is_at_sync: true,
+                resimulated_frames: Vec::new(),
+                server_snapshot: None,
+            })
+        }
+    }
+
+    /// Sends each client a snapshot of the worlds, filtered by what the player is interested in.
+    /// Only sends on every snapshot interval in snapshot sync mode.
+    pub(crate) fn send_state_snapshots(&self, frame: FrameId, worlds: &Map<WorldId, W>) {
+        let NetSyncMode::Snapshot { interval } = self.sync_mode else {
+            return;
+        };
+        if !frame.is_multiple_of(interval.max(1) as u64) {
+            return;
+        }
+
+        let client_players = self.client_players.lock().unwrap();
+        let client_players_joining = self.client_players_joining.lock().unwrap();
+        let world_routes = self.world_routes.lock().unwrap();
+        for (addr, (player_info, _)) in client_players.iter().chain(client_players_joining.iter()) {
+            let msg = UdpMessage::MpMessage(MpMessage::StateSnapshot {
+                frame,
+                worlds: worlds
+                    .values()
+                    .filter(|world| world_routes.is_routed(player_info.id, world.id()))
+                    .map(|world| (world.id(), world.interest_snapshot(player_info)))
+                    .collect(),
+            });
+            self.send(*addr, msg, Duration::from_secs(1));
+        }
+    }
+
+    /// Stores the checksums of the worlds after the frame, for comparing with the checksums clients send.
+    /// Only in lockstep mode, see [`MpClient::send_world_checksums`](super::mp_client::MpClient::send_world_checksums).
+    pub(crate) fn store_world_checksums(&self, frame: FrameId, worlds: &Map<WorldId, W>) {
+        if self.sync_mode == NetSyncMode::Lockstep && frame.is_multiple_of(CHECKSUM_INTERVAL) {
+            let mut checksums = self.checksums.lock().unwrap();
+            checksums.insert(frame, world_checksums(worlds));
+            if checksums.len() > MAX_STORED_CHECKSUMS {
+                checksums.pop_first();
+            }
+        }
+    }
+
+    /// Notifies all connected and joining clients that the server is shutting down.
+    pub(crate) fn broadcast_shutdown(&self, reason: &str) {
+        let client_players = self.client_players.lock().unwrap();
+        let client_players_joining = self.client_players_joining.lock().unwrap();
+        for addr in client_players.keys().chain(client_players_joining.keys()) {
+            self.send(
+                *addr,
+                UdpMessage::MpMessage(MpMessage::ServerShutdown {
+                    reason: reason.to_string(),
+                }),
+                Duration::from_secs(5),
+            );
+        }
+
+        // Leave the server list right away instead of waiting for the host to notice the missing heartbeats
+        if self.global_published_info.lock().unwrap().is_some() {
+            self.send(
+                self.host_addr,
+                UdpMessage::SysMessage(SysMessage::ServerInfoDelete),
+                Duration::from_secs(5),
+            );
+        }
+    }
+
+    pub(crate) fn set_player_team(&self, player_id: PlayerId, team: Option<TeamId>) {
+        let mut teams = self.teams.lock().unwrap();
+        match team {
+            Some(team) => teams.insert(player_id, team),
+            None => teams.remove(&player_id),
+        };
+    }
+
+    pub(crate) fn move_player(&self, player_id: PlayerId, world: Option<WorldId>) {
+        self.pending_moves.lock().unwrap().push((player_id, world));
+    }
+
+    pub(crate) fn set_player_worlds(&self, player_id: PlayerId, worlds: Option<&[WorldId]>) {
+        let worlds = worlds.map(|worlds| worlds.iter().copied().collect());
+        self.world_routes.lock().unwrap().set(player_id, worlds);
+    }
+
+    /// Sends the state of the worlds newly routed to players after the frame, which is the state they start from
+    /// on the next frame. The actions of the next frame were already sent with the new routes.
+    pub(crate) fn send_added_worlds(&self, frame: FrameId, worlds: &Map<WorldId, W>) {
+        for (addr, world_id) in self.added_worlds.lock().unwrap().drain(..) {
+            if let Some(world) = worlds.get(&world_id) {
+                let msg = UdpMessage::MpMessage(MpMessage::WorldAdded {
+                    frame: frame + 1,
+                    world_id,
+                    data: world.as_bytes(),
+                });
+                self.send(addr, msg, Duration::from_secs(15));
+            }
+        }
+    }
+
+    pub(crate) fn set_admin_password(&self, password: Option<&str>) {
+        *self.admin_auth.lock().unwrap() = password.map(SharedSecretAuth::new);
+    }
+
+    /// Starts accepting browser clients over WebSocket. The bridge listens for TCP on the port of the server,
+    /// which is where browsers connect to.
+    pub(crate) fn start_ws_bridge(&self, bind_ip: IpAddr, tls: WsBridgeTls) -> io::Result<()> {
+        let bind_addr = SocketAddr::new(bind_ip, self.server_info.lock().unwrap().addr.port());
+        let bridge = match tls {
+            WsBridgeTls::None => WsNetworkListener::new(bind_addr)?,
+            WsBridgeTls::Certificate(certificate) => WsNetworkListener::with_tls(bind_addr, certificate.load()?)?,
+            WsBridgeTls::Proxies(tls_proxies) => WsNetworkListener::with_tls_proxies(bind_addr, tls_proxies)?,
+        };
+        log_info!("Started WebSocket bridge on {:?}", bridge.local_addr());
+        *self.ws_bridge.write().unwrap() = Some(bridge);
+        Ok(())
+    }
+
+    pub(crate) fn set_max_queued_players(&self, max_queued_players: usize) {
+        self.max_queued_players.store(max_queued_players, Ordering::Relaxed);
+    }
+
+    /// Sends a chat message from the server player, or from the server itself if it has no player.
+    pub(crate) fn send_chat(&self, target: ChatTarget, message: &str) {
+        self.route_chat(self.server_player.clone(), target, message);
+    }
+
+    /// Connections to all clients that have joined.
+    pub(crate) fn connection_stats(&self) -> Vec<ConnectionStats> {
+        self.client_players
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(addr, (player_info, _))| ConnectionStats {
+                addr: *addr,
+                player: Some(player_info.clone()),
+                rtt: self.transport.latency_of(*addr).map(|latency| latency * 2),
+                traffic: self.transport.traffic_stats_of(*addr).unwrap_or_default(),
             })
+            .collect()
+    }
+
+    /// Sends the voice of the server player to all clients.
+    pub(crate) fn send_voice(&self, packet: VoicePacket) {
+        if let Some(server_player) = &self.server_player {
+            self.relay_voice(server_player.id, packet);
+        }
+    }
+
+    /// Voice of client players received since the last call.
+    pub(crate) fn take_received_voice(&self) -> Vec<(PlayerId, VoicePacket)> {
+        std::mem::take(&mut *self.voice_received.lock().unwrap())
+    }
+
+    /// Passes the voice of the player on to all other clients. Voice is not resent if lost.
+    fn relay_voice(&self, player_id: PlayerId, packet: VoicePacket) {
+        for (addr, (player, _)) in self.client_players.lock().unwrap().iter() {
+            if player.id != player_id {
+                let msg = UdpMessage::MpMessage(MpMessage::Voice {
+                    player_id,
+                    packet: packet.clone(),
+                });
+                self.send(*addr, msg, Duration::ZERO);
+            }
+        }
+    }
+
+    /// Passes the chat message on to the target players, and back to the sender.
+    fn route_chat(&self, from: Option<NetworkPlayerInfo>, target: ChatTarget, text: &str) {
+        let message = ChatMessage {
+            from,
+            target,
+            text: text.chars().take(MAX_CHAT_MESSAGE_LEN).collect(),
+        };
+        let teams = self.teams.lock().unwrap();
+        let is_recipient = |player: &NetworkPlayerInfo| {
+            message.from.as_ref().is_some_and(|from| from.id == player.id)
+                || match target {
+                    ChatTarget::All => true,
+                    ChatTarget::Player(player_id) => player.id == player_id,
+                    ChatTarget::Team(team) => teams.get(&player.id) == Some(&team),
+                }
+        };
+
+        for (addr, (player, _)) in self.client_players.lock().unwrap().iter() {
+            if is_recipient(player) {
+                let msg = UdpMessage::MpMessage(MpMessage::Chat {
+                    message: message.clone(),
+                });
+                self.send(*addr, msg, Duration::from_secs(15));
+            }
+        }
+        if self.server_player.as_ref().is_none_or(is_recipient) {
+            self.network_event_sender.send(NetworkEvent::Chat { message }).ok();
         }
     }
 
+    /// Carries out an admin command received over the remote console, or passes it on to the game.
+    fn execute_admin_command(
+        &self,
+        command: AdminCommand,
+        universe: &Universe<W>,
+        worlds_lock: &mut MutexGuard<Map<WorldId, W>>,
+    ) -> Result<String, String> {
+        log_info!("Executing admin command: {:?}", command);
+        match command {
+            AdminCommand::Kick { player_id, reason } => {
+                let addr = self
+                    .client_players
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .find(|(_, (player, _))| player.id == player_id)
+                    .map(|(addr, _)| *addr)
+                    .ok_or_else(|| format!("No player with id {}", player_id))?;
+                let msg = UdpMessage::MpMessage(MpMessage::Kicked { reason });
+                self.send(addr, msg, Duration::from_secs(5));
+                let player_info = self.remove_client_player(addr).unwrap();
+                Ok(format!("Kicked {}", player_info.name))
+            }
+            AdminCommand::Broadcast { message } => {
+                self.route_chat(None, ChatTarget::All, &message);
+                Ok("Message sent".to_owned())
+            }
+            AdminCommand::Save { save_name } => {
+                let universe_data = universe.lock_universe_data();
+                let universe_data = universe_data.as_ref().ok_or_else(|| "No universe loaded".to_owned())?;
+                self.files
+                    .export_save(
+                        &save_name,
+                        Universe::build_save_files(universe_data, worlds_lock),
+                        universe_data.save_metadata(),
+                    )
+                    .map(|_| format!("Saved {}", save_name))
+                    .map_err(|err| format!("Failed to save {}: {}", save_name, err))
+            }
+            command => universe
+                .lock_universe_data()
+                .as_ref()
+                .ok_or_else(|| "No universe loaded".to_owned())?
+                .on_admin_command(&command, worlds_lock),
+        }
+    }
+
+    /// Removes a player that has left the game, and tells the other players about it.
+    fn remove_client_player(&self, addr: SocketAddr) -> Option<NetworkPlayerInfo> {
+        self.latencies.lock().unwrap().remove(&addr);
+        self.acked_frames.lock().unwrap().remove(&addr);
+        self.last_action_frames.lock().unwrap().remove(&addr);
+        let mut client_players = self.client_players.lock().unwrap();
+        let (player_info, _) = client_players.remove(&addr)?;
+        self.world_routes.lock().unwrap().remove(player_info.id);
+        self.player_worlds.lock().unwrap().remove(&player_info.id);
+        self.teams.lock().unwrap().remove(&player_info.id);
+        self.desynced_players.lock().unwrap().remove(&player_info.id);
+        for addr in client_players.keys() {
+            let msg = UdpMessage::MpMessage(MpMessage::PlayerLeft {
+                player_info: player_info.clone(),
+            });
+            self.send(*addr, msg, Duration::from_secs(15));
+        }
+        self.network_event_sender
+            .send(NetworkEvent::PlayerLeft {
+                player_info: player_info.clone(),
+            })
+            .ok();
+        Some(player_info)
+    }
+
     fn check_and_report_latencies(&self, active_frame: FrameId, latencies: &mut MutexGuard<Map<SocketAddr, Duration>>) {
         if active_frame % DEFAULT_UPS == 0 {
             for (addr, latency) in &mut **latencies {
-                *latency = self.udp_socket.latency_of(*addr).unwrap_or(Duration::from_millis(100));
-                self.udp_socket.send(
+                *latency = self.transport.latency_of(*addr).unwrap_or(Duration::from_millis(100));
+                self.send(
                     *addr,
                     UdpMessage::MpMessage(MpMessage::LatencyUpdate { latency: *latency }),
                     Duration::from_secs(15),
@@ -210,6 +628,86 @@ impl<W: WorldType> MpServer<W> {
         }
     }
 
+    fn check_and_report_sync_status(
+        &self,
+        active_frame: FrameId,
+        client_players: &MutexGuard<Map<SocketAddr, (NetworkPlayerInfo, Instant)>>,
+        latencies: &MutexGuard<Map<SocketAddr, Duration>>,
+    ) {
+        if active_frame.is_multiple_of(DEFAULT_UPS) {
+            let last_action_frames = self.last_action_frames.lock().unwrap();
+            let own_status = self.server_player.as_ref().map(|player| PlayerSyncStatus {
+                player: player.id,
+                rtt: Duration::ZERO,
+                frame_lag: 0,
+            });
+            let statuses: Vec<_> = own_status
+                .into_iter()
+                .chain(client_players.iter().map(|(addr, (player, _))| {
+                    PlayerSyncStatus {
+                        player: player.id,
+                        rtt: latencies.get(addr).map_or(Duration::ZERO, |latency| *latency * 2),
+                        frame_lag: last_action_frames
+                            .get(addr)
+                            .map_or(0, |frame| active_frame.saturating_sub(*frame)),
+                    }
+                }))
+                .collect();
+            for addr in client_players.keys() {
+                let msg = UdpMessage::MpMessage(MpMessage::PlayerSyncStatus {
+                    statuses: statuses.clone(),
+                });
+                self.send(*addr, msg, Duration::from_secs(1));
+            }
+            self.network_event_sender
+                .send(NetworkEvent::PlayerSyncStatus { statuses })
+                .ok();
+        }
+    }
+
+    fn check_and_report_connection_quality(
+        &self,
+        active_frame: FrameId,
+        client_players: &MutexGuard<Map<SocketAddr, (NetworkPlayerInfo, Instant)>>,
+    ) {
+        if active_frame.is_multiple_of(DEFAULT_UPS) {
+            let mut quality_monitors = self.quality_monitors.lock().unwrap();
+            quality_monitors.retain(|addr, _| client_players.contains_key(addr));
+            for (addr, (player, _)) in &**client_players {
+                let rtt = self.transport.latency_of(*addr).map(|latency| latency * 2);
+                let traffic = self.transport.traffic_stats_of(*addr).unwrap_or_default();
+                if let Some(quality) = quality_monitors.entry(*addr).or_default().update(rtt, traffic) {
+                    log_info!("Connection to {:?} is now {:?}", player, quality);
+                    self.network_event_sender
+                        .send(NetworkEvent::ConnectionQualityChanged {
+                            player: Some(player.clone()),
+                            quality,
+                        })
+                        .ok();
+                }
+            }
+        }
+    }
+
+    fn check_and_send_keepalives(
+        &self,
+        client_players: &MutexGuard<Map<SocketAddr, (NetworkPlayerInfo, Instant)>>,
+        client_players_joining: &MutexGuard<Map<SocketAddr, (NetworkPlayerInfo, Instant)>>,
+    ) {
+        if self.keepalive_last.load(Ordering::Relaxed) + self.timeouts.keepalive_interval < Instant::now() {
+            let join_queue = self.join_queue.lock().unwrap();
+            let addrs = client_players
+                .keys()
+                .chain(client_players_joining.keys())
+                .chain(join_queue.iter().map(|(addr, _)| addr));
+            for addr in addrs {
+                // Unreliable, as the next keepalive is sent before a resend would be
+                self.send(*addr, UdpMessage::MpMessage(MpMessage::Keepalive), Duration::ZERO);
+            }
+            self.keepalive_last.store(Instant::now(), Ordering::Relaxed);
+        }
+    }
+
     fn check_and_publish_server_info(
         &self,
         client_players: &MutexGuard<Map<SocketAddr, (NetworkPlayerInfo, Instant)>>,
@@ -220,18 +718,33 @@ impl<W: WorldType> MpServer<W> {
         let cur_player_count = client_players.len() + client_players_joining.len() + self_player_count;
         server_info.cur_player_count = cur_player_count as u32;
 
+        let now = Instant::now();
         if server_info.is_global
-            && self.global_publish_last.load(Ordering::Relaxed) + GLOBAL_PUBLISH_INTERVAL < Instant::now()
+            && self.global_heartbeat_last.load(Ordering::Relaxed) + GLOBAL_HEARTBEAT_INTERVAL < now
             && server_info.addr.port() != 0
         {
-            self.udp_socket.send(
-                self.host_addr,
-                UdpMessage::SysMessage(SysMessage::ServerInfoPost {
-                    server: (*server_info).clone(),
-                }),
-                Duration::from_secs(5),
-            );
-            self.global_publish_last.store(Instant::now(), Ordering::Relaxed);
+            let mut published_info = self.global_published_info.lock().unwrap();
+            if published_info.as_ref() != Some(&*server_info)
+                || self.global_publish_last.load(Ordering::Relaxed) + GLOBAL_PUBLISH_INTERVAL < now
+            {
+                self.send(
+                    self.host_addr,
+                    UdpMessage::SysMessage(SysMessage::ServerInfoPost {
+                        server: (*server_info).clone(),
+                    }),
+                    Duration::from_secs(5),
+                );
+                *published_info = Some((*server_info).clone());
+                self.global_publish_last.store(now, Ordering::Relaxed);
+            } else {
+                // Unreliable, as the host keeps the server listed over a few lost heartbeats
+                self.send(
+                    self.host_addr,
+                    UdpMessage::SysMessage(SysMessage::ServerHeartbeat),
+                    Duration::ZERO,
+                );
+            }
+            self.global_heartbeat_last.store(now, Ordering::Relaxed);
         }
     }
 
@@ -245,10 +758,21 @@ impl<W: WorldType> MpServer<W> {
 
         let mut dropping_players: Vec<NetworkPlayerInfo> = Vec::new();
         client_players.retain(|_, (player, last_msg)| {
-            let retain = *last_msg + PLAYER_TIMEOUT > now;
+            let retain = *last_msg + self.timeouts.disconnect_timeout > now;
             if !retain {
                 log_warn!("Player timed out: {:?}", player);
+                self.network_event_sender
+                    .send(NetworkEvent::PlayerTimedOut {
+                        player_info: player.clone(),
+                    })
+                    .ok();
                 latencies.remove(&player.addr);
+                self.acked_frames.lock().unwrap().remove(&player.addr);
+                self.last_action_frames.lock().unwrap().remove(&player.addr);
+                self.world_routes.lock().unwrap().remove(player.id);
+                self.player_worlds.lock().unwrap().remove(&player.id);
+                self.teams.lock().unwrap().remove(&player.id);
+                self.desynced_players.lock().unwrap().remove(&player.id);
                 dropping_players.push(player.clone());
                 self.network_event_sender
                     .send(NetworkEvent::PlayerLeft {
@@ -261,9 +785,16 @@ impl<W: WorldType> MpServer<W> {
 
         let mut dropping_joining_players: Vec<NetworkPlayerInfo> = Vec::new();
         client_players_joining.retain(|_, (player, last_msg)| {
-            let retain = *last_msg + PLAYER_JOIN_TIMEOUT > now;
+            let retain = *last_msg + self.timeouts.join_timeout * 2 > now;
             if !retain {
                 log_warn!("Player join timed out: {:?}", player);
+                self.network_event_sender
+                    .send(NetworkEvent::PlayerTimedOut {
+                        player_info: player.clone(),
+                    })
+                    .ok();
+                self.join_data.lock().unwrap().remove(&player.addr);
+                self.world_routes.lock().unwrap().remove(player.id);
                 dropping_joining_players.push(player.clone());
                 self.network_event_sender
                     .send(NetworkEvent::PlayerJoinFailure {
@@ -276,7 +807,7 @@ impl<W: WorldType> MpServer<W> {
 
         for (addr, (_, _)) in &**client_players {
             for player_info in &dropping_players {
-                self.udp_socket.send(
+                self.send(
                     *addr,
                     UdpMessage::MpMessage(MpMessage::PlayerLeft {
                         player_info: player_info.clone(),
@@ -286,7 +817,7 @@ impl<W: WorldType> MpServer<W> {
             }
 
             for player_info in &dropping_joining_players {
-                self.udp_socket.send(
+                self.send(
                     *addr,
                     UdpMessage::MpMessage(MpMessage::PlayerJoinFailure {
                         player_info: player_info.clone(),
@@ -298,7 +829,7 @@ impl<W: WorldType> MpServer<W> {
 
         for (addr, (_, _)) in &**client_players_joining {
             for player_info in &dropping_players {
-                self.udp_socket.send(
+                self.send(
                     *addr,
                     UdpMessage::MpMessage(MpMessage::PlayerLeft {
                         player_info: player_info.clone(),
@@ -308,7 +839,7 @@ impl<W: WorldType> MpServer<W> {
             }
 
             for player_info in &dropping_joining_players {
-                self.udp_socket.send(
+                self.send(
                     *addr,
                     UdpMessage::MpMessage(MpMessage::PlayerJoinFailure {
                         player_info: player_info.clone(),
@@ -319,8 +850,204 @@ impl<W: WorldType> MpServer<W> {
         }
     }
 
+    /// Checks whether the player may join. Returns the verified identity of the player, or the reason for denial.
+    fn verify_join(
+        &self,
+        player_info: &NetworkPlayerInfo,
+        from_addr: SocketAddr,
+        auth_token: Option<&[u8]>,
+    ) -> Result<Option<PlayerIdentity>, String> {
+        if player_info.addr != from_addr {
+            return Err("Player IP does not match msg source IP".to_owned());
+        }
+        if auth_token.is_some() && self.is_unencrypted_browser_client(from_addr) {
+            // The token could have been read on the way
+            return Err("Auth tokens are not accepted over unencrypted WebSocket".to_owned());
+        }
+        let Some(authenticator) = &self.authenticator else {
+            return Ok(None);
+        };
+
+        let identity = authenticator.authenticate(player_info, auth_token)?;
+        let client_players = self.client_players.lock().unwrap();
+        let client_players_joining = self.client_players_joining.lock().unwrap();
+        let already_joined = client_players
+            .iter()
+            .chain(client_players_joining.iter())
+            .any(|(addr, (player, _))| *addr != from_addr && player.identity.as_ref() == Some(&identity));
+        let is_server_player =
+            self.server_player.as_ref().and_then(|player| player.identity.as_ref()) == Some(&identity);
+        if already_joined || is_server_player {
+            return Err("Player is already in the game".to_owned());
+        }
+        Ok(Some(identity))
+    }
+
+    /// Lets the player start joining if the server has room, otherwise queues or denies the join.
+    fn admit_or_queue_join(&self, from_addr: SocketAddr, player_info: NetworkPlayerInfo) {
+        let mut join_queue = self.join_queue.lock().unwrap();
+        if let Some(position) = join_queue.iter().position(|(addr, _)| *addr == from_addr) {
+            // Already waiting in the queue, the request was sent again
+            join_queue[position].1 = player_info;
+            self.send_queue_position(from_addr, position);
+        } else if join_queue.is_empty() && self.has_room_for(from_addr) {
+            drop(join_queue);
+            self.admit_join(from_addr, player_info);
+        } else if join_queue.len() < self.max_queued_players.load(Ordering::Relaxed) {
+            log_info!("Queued JoinReq from {:?} at position {}", from_addr, join_queue.len());
+            join_queue.push_back((from_addr, player_info));
+            self.send_queue_position(from_addr, join_queue.len() - 1);
+        } else {
+            drop(join_queue);
+            self.deny_join(from_addr, "Server is full".to_owned());
+        }
+    }
+
+    /// Whether a player joining from the address fits in `max_player_count`, counting players still joining.
+    fn has_room_for(&self, from_addr: SocketAddr) -> bool {
+        let client_players = self.client_players.lock().unwrap();
+        let client_players_joining = self.client_players_joining.lock().unwrap();
+        let player_count = client_players
+            .keys()
+            .chain(client_players_joining.keys())
+            .filter(|addr| **addr != from_addr)
+            .count()
+            + self.server_player.is_some() as usize;
+        player_count < self.server_info.lock().unwrap().max_player_count as usize
+    }
+
+    /// Admits queued players as others leave, and reminds the rest of their position once a second.
+    fn check_join_queue(&self, active_frame: FrameId) {
+        let mut join_queue = self.join_queue.lock().unwrap();
+        let mut queue_changed = false;
+        while let Some((addr, _)) = join_queue.front()
+            && self.has_room_for(*addr)
+        {
+            let (addr, player_info) = join_queue.pop_front().unwrap();
+            log_info!("Admitting queued player {:?}", player_info);
+            self.admit_join(addr, player_info);
+            queue_changed = true;
+        }
+
+        if queue_changed || active_frame.is_multiple_of(DEFAULT_UPS) {
+            for (position, (addr, _)) in join_queue.iter().enumerate() {
+                self.send_queue_position(*addr, position);
+            }
+        }
+    }
+
+    fn send_queue_position(&self, addr: SocketAddr, position: usize) {
+        let msg = UdpMessage::MpMessage(MpMessage::JoinQueued {
+            position: position as u32,
+        });
+        self.send(addr, msg, Duration::from_secs(5));
+    }
+
+    fn admit_join(&self, from_addr: SocketAddr, player_info: NetworkPlayerInfo) {
+        // Client starts with an empty action buffer, so earlier acks are no longer valid
+        self.acked_frames.lock().unwrap().remove(&from_addr);
+        let client_players = self.client_players.lock().unwrap();
+        let mut client_players_joining = self.client_players_joining.lock().unwrap();
+
+        self.network_event_sender
+            .send(NetworkEvent::PlayerJoinStart {
+                player_info: player_info.clone(),
+            })
+            .unwrap();
+
+        self.send(
+            from_addr,
+            UdpMessage::MpMessage(MpMessage::JoinRes {
+                accepted: true,
+                reason: None,
+                server_player: self.server_player.clone(),
+                client_players: client_players
+                    .clone()
+                    .into_iter()
+                    .map(|(addr, player)| (addr, player.0))
+                    .collect(),
+                client_players_joining: client_players_joining
+                    .clone()
+                    .into_iter()
+                    .map(|(addr, player)| (addr, player.0))
+                    .collect(),
+            }),
+            Duration::from_secs(15),
+        );
+
+        for (addr, (_, _)) in &*client_players {
+            self.send(
+                *addr,
+                UdpMessage::MpMessage(MpMessage::PlayerJoinStart {
+                    player_info: player_info.clone(),
+                }),
+                Duration::from_secs(10),
+            );
+        }
+
+        for (addr, (_, _)) in &*client_players_joining {
+            self.send(
+                *addr,
+                UdpMessage::MpMessage(MpMessage::PlayerJoinStart {
+                    player_info: player_info.clone(),
+                }),
+                Duration::from_secs(10),
+            );
+        }
+
+        client_players_joining.insert(from_addr, (player_info, Instant::now()));
+    }
+
+    fn deny_join(&self, from_addr: SocketAddr, reason: String) {
+        log_info!("Denied JoinReq from {:?}: {}", from_addr, reason);
+        self.send(
+            from_addr,
+            UdpMessage::MpMessage(MpMessage::JoinRes {
+                accepted: false,
+                reason: Some(reason),
+                server_player: None,
+                client_players: Map::default(),
+                client_players_joining: Map::default(),
+            }),
+            Duration::from_secs(15),
+        );
+    }
+
+    /// Sends the message to the client over the WebSocket bridge if it joined from a browser, otherwise over udp.
+    fn send(&self, addr: SocketAddr, msg: UdpMessage<MpMessage<W::ActionType>>, timeout: Duration) {
+        if let Some(bridge) = &*self.ws_bridge.read().unwrap()
+            && bridge.is_connected(addr)
+        {
+            bridge.send(addr, msg);
+        } else {
+            self.transport.send(addr, msg, timeout);
+        }
+    }
+
+    fn is_browser_client(&self, addr: SocketAddr) -> bool {
+        self.ws_bridge
+            .read()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|bridge| bridge.is_connected(addr))
+    }
+
+    /// Browsers are encrypted if the bridge has a certificate, or up to a TLS proxy in front of it if they came
+    /// through one.
+    fn is_unencrypted_browser_client(&self, addr: SocketAddr) -> bool {
+        self.ws_bridge
+            .read()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|bridge| bridge.is_connected(addr) && !bridge.is_secure(addr))
+    }
+
     fn process_network_events(&self, universe: &Universe<W>, worlds_lock: &mut MutexGuard<Map<WorldId, W>>) {
-        for (from_addr, msg) in self.udp_socket.try_recv_all() {
+        let mut received = self.transport.try_recv_all();
+        if let Some(bridge) = &*self.ws_bridge.read().unwrap() {
+            received.extend(bridge.try_recv_all());
+        }
+        for (from_addr, msg) in received {
             match msg {
                 UdpMessage::SysMessage(msg) => match msg {
                     SysMessage::SocketInfoRes { addr } => {
@@ -331,7 +1058,7 @@ impl<W: WorldType> MpServer<W> {
                     }
                     SysMessage::SocketInfoReq {} => {
                         log_info!("Received SocketInfoReq from {:?}", from_addr);
-                        self.udp_socket.send(
+                        self.send(
                             from_addr,
                             UdpMessage::SysMessage(SysMessage::SocketInfoRes { addr: from_addr }),
                             Duration::from_secs(10),
@@ -340,17 +1067,35 @@ impl<W: WorldType> MpServer<W> {
                     SysMessage::NatPunchStart { to } => {
                         log_info!("Received NatPunchStart from {:?}", from_addr);
                         if from_addr == self.host_addr {
-                            self.udp_socket.send(
+                            self.send(
                                 to,
                                 UdpMessage::SysMessage(SysMessage::NatPunchPing),
                                 Duration::from_secs(15),
                             );
                         }
                     }
+                    SysMessage::HostShutdown if from_addr == self.host_addr => {
+                        // Posts the full info again, so that the server is listed once the host is back
+                        log_info!("Host is shutting down");
+                        *self.global_published_info.lock().unwrap() = None;
+                    }
+                    SysMessage::Ping => {
+                        // Unreliable, as a resent ping would not measure the latency
+                        self.send(from_addr, UdpMessage::SysMessage(SysMessage::Pong), Duration::ZERO);
+                    }
+                    SysMessage::ServerAltAddrCheck { cookie } => {
+                        // Comes from the host over the other address family, so it isn't from the host address.
+                        // Unreliable, as the host checks again with the next post.
+                        self.send(
+                            from_addr,
+                            UdpMessage::SysMessage(SysMessage::ServerAltAddrConfirm { cookie }),
+                            Duration::ZERO,
+                        );
+                    }
                     SysMessage::ServerInfoReq {} => {
                         log_info!("Received ServerInfoReq from {:?}", from_addr);
                         let server_info = self.server_info.lock().unwrap();
-                        self.udp_socket.send(
+                        self.send(
                             from_addr,
                             UdpMessage::SysMessage(SysMessage::ServerInfoResLocal {
                                 server: server_info.clone(),
@@ -361,112 +1106,129 @@ impl<W: WorldType> MpServer<W> {
                     _ => {}
                 },
                 UdpMessage::MpMessage(msg) => match msg {
-                    MpMessage::ActionsFromClient { for_frame, actions } => {
+                    MpMessage::ActionsFromClient {
+                        for_frame,
+                        actions,
+                        acked_frame,
+                    } => {
+                        if let Some(acked_frame) = acked_frame {
+                            let mut acked_frames = self.acked_frames.lock().unwrap();
+                            let acked = acked_frames.entry(from_addr).or_default();
+                            *acked = (*acked).max(acked_frame);
+                        }
                         if let Some((player_info, last_msg)) = self.client_players.lock().unwrap().get_mut(&from_addr) {
+                            self.last_action_frames
+                                .lock()
+                                .unwrap()
+                                .insert(from_addr, universe.active_frame());
+                            // Rollback and snapshot clients don't wait for the actions of other players, so late
+                            // actions are moved to the next frame instead of being dropped.
+                            let for_frame = match self.sync_mode {
+                                NetSyncMode::Rollback { .. } | NetSyncMode::Snapshot { .. } => {
+                                    for_frame.max(universe.active_frame() + 1)
+                                }
+                                NetSyncMode::Lockstep => for_frame,
+                            };
                             if for_frame > universe.active_frame() {
+                                // Players that have moved to a world only act in that world
+                                let player_world = self.player_worlds.lock().unwrap().get(&player_info.id).copied();
                                 let mut action_map = self.actions.lock().unwrap();
                                 for (world_id, actions) in actions {
-                                    action_map.import_actions(for_frame, world_id, player_info.id, &actions);
+                                    if player_world.is_none_or(|player_world| player_world == world_id) {
+                                        action_map.import_actions(for_frame, world_id, player_info.id, &actions);
+                                    }
                                 }
                                 *last_msg = Instant::now();
                             }
                         }
                     }
 
-                    MpMessage::JoinReq { player_info } => {
+                    MpMessage::JoinReq {
+                        protocol_version,
+                        app_version,
+                        mut player_info,
+                        auth_token,
+                    } => {
                         log_info!("Received JoinReq for {:?} from {:?}", player_info, from_addr);
-                        let allowed = player_info.addr == from_addr;
-                        if allowed {
-                            let client_players = self.client_players.lock().unwrap();
-                            let mut client_players_joining = self.client_players_joining.lock().unwrap();
-
-                            self.network_event_sender
-                                .send(NetworkEvent::PlayerJoinStart {
-                                    player_info: player_info.clone(),
-                                })
-                                .unwrap();
-
-                            self.udp_socket.send(
+                        if protocol_version != PROTOCOL_VERSION || app_version != self.app_version {
+                            log_info!(
+                                "Denied JoinReq from {:?}: version {} with protocol {} does not match",
                                 from_addr,
-                                UdpMessage::MpMessage(MpMessage::JoinRes {
-                                    accepted: true,
-                                    reason: None,
-                                    server_player: self.server_player.clone(),
-                                    client_players: client_players
-                                        .clone()
-                                        .into_iter()
-                                        .map(|(addr, player)| (addr, player.0))
-                                        .collect(),
-                                    client_players_joining: client_players_joining
-                                        .clone()
-                                        .into_iter()
-                                        .map(|(addr, player)| (addr, player.0))
-                                        .collect(),
-                                }),
-                                Duration::from_secs(15),
-                            );
-
-                            for (addr, (_, _)) in &*client_players {
-                                self.udp_socket.send(
-                                    *addr,
-                                    UdpMessage::MpMessage(MpMessage::PlayerJoinStart {
-                                        player_info: player_info.clone(),
-                                    }),
-                                    Duration::from_secs(10),
-                                );
-                            }
-
-                            for (addr, (_, _)) in &*client_players_joining {
-                                self.udp_socket.send(
-                                    *addr,
-                                    UdpMessage::MpMessage(MpMessage::PlayerJoinStart {
-                                        player_info: player_info.clone(),
-                                    }),
-                                    Duration::from_secs(10),
-                                );
-                            }
-
-                            client_players_joining.insert(from_addr, (player_info, Instant::now()));
-                        } else {
-                            let reason = if player_info.addr != from_addr {
-                                "Player IP does not match msg source IP"
-                            } else {
-                                "Access denied"
-                            };
-
-                            self.udp_socket.send(
-                                from_addr,
-                                UdpMessage::MpMessage(MpMessage::JoinRes {
-                                    accepted: false,
-                                    reason: Some(reason.to_owned()),
-                                    server_player: None,
-                                    client_players: Map::default(),
-                                    client_players_joining: Map::default(),
-                                }),
-                                Duration::from_secs(15),
+                                app_version,
+                                protocol_version
                             );
+                            let msg = UdpMessage::MpMessage(MpMessage::IncompatibleVersion {
+                                protocol_version: PROTOCOL_VERSION,
+                                app_version: self.app_version.clone(),
+                            });
+                            self.send(from_addr, msg, Duration::from_secs(15));
+                            continue;
+                        }
+                        if self.is_browser_client(from_addr) {
+                            // Browsers don't know the address their connection comes from
+                            player_info.addr = from_addr;
+                        } else if player_info.addr.is_ipv4() != from_addr.is_ipv4() {
+                            // Dual stack clients know their address in one family, but may join over the other
+                            player_info.addr = from_addr;
+                        }
+                        let verdict = self.verify_join(&player_info, from_addr, auth_token.as_deref());
+                        if let Ok(identity) = verdict {
+                            player_info.identity = identity;
+                            self.admit_or_queue_join(from_addr, player_info);
+                        } else if let Err(reason) = verdict {
+                            self.deny_join(from_addr, reason);
                         }
                     }
-                    MpMessage::JoinReqUniverseData { .. } => {
-                        log_info!("Received JoinReqGameData from {:?}", from_addr);
-                        if self.client_players_joining.lock().unwrap().contains_key(&from_addr) {
-                            let worlds_data: Vec<_> = worlds_lock.iter().map(|world| world.1.as_bytes()).collect();
-                            let universe_data = universe.lock_universe_data().as_ref().unwrap().as_bytes(worlds_lock);
-                            let msg = UdpMessage::MpMessage(MpMessage::<W::ActionType>::JoinResUniverseData {
-                                universe_data,
-                                worlds_data,
-                                active_frame: universe.active_frame(),
+                    MpMessage::JoinReqUniverseData { from_chunk, .. } => {
+                        log_info!(
+                            "Received JoinReqUniverseData from {:?} from chunk {}",
+                            from_addr,
+                            from_chunk
+                        );
+                        let joining_id = self
+                            .client_players_joining
+                            .lock()
+                            .unwrap()
+                            .get(&from_addr)
+                            .map(|(player_info, _)| player_info.id);
+                        if let Some(player_id) = joining_id {
+                            // The state is captured on the first request, later requests resume the same transfer
+                            let world_routes = self.world_routes.lock().unwrap();
+                            let mut join_data = self.join_data.lock().unwrap();
+                            let join_data = join_data.entry(from_addr).or_insert_with(|| {
+                                OutgoingJoinData::new(&JoinUniverseData {
+                                    universe_data: universe
+                                        .lock_universe_data()
+                                        .as_ref()
+                                        .unwrap()
+                                        .as_bytes(worlds_lock),
+                                    worlds_data: worlds_lock
+                                        .values()
+                                        .filter(|world| world_routes.is_routed(player_id, world.id()))
+                                        .map(|world| world.as_bytes())
+                                        .collect(),
+                                    active_frame: universe.active_frame(),
+                                })
                             });
-                            self.udp_socket.send(from_addr, msg, Duration::from_secs(60));
+                            for (chunk, data) in join_data.window(from_chunk) {
+                                let msg = UdpMessage::MpMessage(MpMessage::<W::ActionType>::JoinResUniverseData {
+                                    total_size: join_data.total_size(),
+                                    chunk_count: join_data.chunk_count(),
+                                    chunk,
+                                    data: data.clone(),
+                                });
+                                self.send(from_addr, msg, Duration::from_secs(30));
+                            }
                         }
                     }
                     MpMessage::JoinComplete { .. } => {
                         log_info!("Received JoinComplete from {:?}", from_addr);
+                        self.join_data.lock().unwrap().remove(&from_addr);
                         let mut client_players = self.client_players.lock().unwrap();
                         let mut client_players_joining = self.client_players_joining.lock().unwrap();
                         if let Some((player_info, _)) = client_players_joining.remove(&from_addr) {
                             for (addr, (_, _)) in &*client_players {
-                                self.udp_socket.send(
+                                self.send(
                                     *addr,
                                     UdpMessage::MpMessage(MpMessage::PlayerJoinSuccess {
                                         player_info: player_info.clone(),
@@ -476,7 +1238,7 @@ impl<W: WorldType> MpServer<W> {
                             }
 
                             for (addr, (_, _)) in &*client_players_joining {
-                                self.udp_socket.send(
+                                self.send(
                                     *addr,
                                     UdpMessage::MpMessage(MpMessage::PlayerJoinStart {
                                         player_info: player_info.clone(),
@@ -486,6 +1248,10 @@ impl<W: WorldType> MpServer<W> {
                             }
 
                             client_players.insert(from_addr, (player_info.clone(), Instant::now()));
+                            self.last_action_frames
+                                .lock()
+                                .unwrap()
+                                .insert(from_addr, universe.active_frame());
 
                             self.latencies
                                 .lock()
@@ -497,21 +1263,105 @@ impl<W: WorldType> MpServer<W> {
                                 .unwrap();
                         }
                     }
-                    MpMessage::Leaving { .. } => {
-                        log_info!("Received Leaving from {:?}", from_addr);
-                        self.latencies.lock().unwrap().remove(&from_addr);
-                        if let Some((player_info, _)) = self.client_players.lock().unwrap().remove(&from_addr) {
-                            for addr in self.client_players.lock().unwrap().keys() {
-                                let msg = UdpMessage::MpMessage(MpMessage::PlayerLeft {
-                                    player_info: player_info.clone(),
-                                });
-                                self.udp_socket.send(*addr, msg, Duration::from_secs(15));
+                    MpMessage::VoiceFromClient { packet } => {
+                        let player_id = self
+                            .client_players
+                            .lock()
+                            .unwrap()
+                            .get(&from_addr)
+                            .map(|(player_info, _)| player_info.id);
+                        if let Some(player_id) = player_id {
+                            self.relay_voice(player_id, packet.clone());
+                            if self.server_player.is_some() {
+                                self.voice_received.lock().unwrap().push((player_id, packet));
                             }
-                            self.network_event_sender
-                                .send(NetworkEvent::PlayerLeft { player_info })
-                                .ok();
                         }
                     }
+                    MpMessage::ChatFromClient { target, message } => {
+                        let from = self
+                            .client_players
+                            .lock()
+                            .unwrap()
+                            .get(&from_addr)
+                            .map(|(player_info, _)| player_info.clone());
+                        if let Some(from) = from {
+                            self.route_chat(Some(from), target, &message);
+                        }
+                    }
+                    MpMessage::WorldChecksums { frame, checksums } => {
+                        let player_info = self
+                            .client_players
+                            .lock()
+                            .unwrap()
+                            .get(&from_addr)
+                            .map(|(player_info, _)| player_info.clone());
+                        let own_checksums = self.checksums.lock().unwrap().get(&frame).cloned();
+                        if let (Some(player_info), Some(own_checksums)) = (player_info, own_checksums) {
+                            let is_desynced = checksums.iter().any(|(world_id, checksum)| {
+                                own_checksums.get(world_id).is_some_and(|own| own != checksum)
+                            });
+                            if is_desynced && self.desynced_players.lock().unwrap().insert(player_info.id) {
+                                log_warn!("Player {:?} desynced on frame {}", player_info, frame);
+                                let msg = UdpMessage::MpMessage(MpMessage::Desync { frame });
+                                self.send(from_addr, msg, Duration::from_secs(15));
+                                self.network_event_sender
+                                    .send(NetworkEvent::Desync {
+                                        frame,
+                                        player: player_info.id,
+                                    })
+                                    .ok();
+                            }
+                        }
+                    }
+                    MpMessage::AdminCommand { token, command } => {
+                        let is_admin = || {
+                            self.admin_auth
+                                .lock()
+                                .unwrap()
+                                .as_ref()
+                                .is_some_and(|auth| auth.verify(Some(&token)))
+                        };
+                        let mut admin_lockout = self.admin_lockout.lock().unwrap();
+                        let result = if self.is_unencrypted_browser_client(from_addr) {
+                            // The password could have been read on the way
+                            log_warn!("Denied admin command over WebSocket from {:?}", from_addr);
+                            Err("Admin commands are not accepted over unencrypted WebSocket".to_owned())
+                        } else if admin_lockout.is_locked_out(from_addr.ip(), Instant::now()) {
+                            log_warn!("Denied admin command from locked out {:?}", from_addr);
+                            Err("Too many failed attempts, try again later".to_owned())
+                        } else if is_admin() {
+                            admin_lockout.clear(from_addr.ip());
+                            self.execute_admin_command(command, universe, worlds_lock)
+                        } else {
+                            log_warn!("Denied admin command from {:?}", from_addr);
+                            if admin_lockout.record_failure(from_addr.ip(), Instant::now()) {
+                                log_warn!("Locked out admin commands from {:?}", from_addr.ip());
+                            }
+                            Err("Not authorized".to_owned())
+                        };
+                        let msg = UdpMessage::MpMessage(MpMessage::AdminResponse { result });
+                        self.send(from_addr, msg, Duration::from_secs(5));
+                    }
+                    MpMessage::Keepalive => {
+                        if let Some((_, last_msg)) = self.client_players.lock().unwrap().get_mut(&from_addr) {
+                            *last_msg = Instant::now();
+                        }
+                    }
+                    MpMessage::TimeSyncReq { client_time }
+                        if self.client_players.lock().unwrap().contains_key(&from_addr)
+                            || self.client_players_joining.lock().unwrap().contains_key(&from_addr) =>
+                    {
+                        let msg = UdpMessage::MpMessage(MpMessage::TimeSyncRes {
+                            client_time,
+                            server_frame: universe.active_frame(),
+                        });
+                        self.send(from_addr, msg, Duration::ZERO);
+                    }
+                    MpMessage::Leaving { .. } => {
+                        log_info!("Received Leaving from {:?}", from_addr);
+                        self.join_queue.lock().unwrap().retain(|(addr, _)| *addr != from_addr);
+                        self.remove_client_player(from_addr);
+                    }
                     _ => {}
                 },
             }
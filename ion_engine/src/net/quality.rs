@@ -0,0 +1,114 @@
+use std::time::Duration;
+
+use ion_common::net::udp_network_socket::TrafficStats;
+
+// Connections get worse once above the entry threshold, but only get better again once below the exit threshold,
+// so that a connection hovering around a threshold doesn't flood the game with events.
+const DEGRADED_RTT: (Duration, Duration) = (Duration::from_millis(250), Duration::from_millis(200));
+const DEGRADED_LOSS: (f32, f32) = (0.05, 0.03);
+const CRITICAL_RTT: (Duration, Duration) = (Duration::from_millis(600), Duration::from_millis(500));
+const CRITICAL_LOSS: (f32, f32) = (0.15, 0.10);
+
+// ---------------------------------------------------------- //
+// ------------------ Connection quality -------------------- //
+// ---------------------------------------------------------- //
+
+/// Quality of a multiplayer connection, see [`NetworkEvent::ConnectionQualityChanged`](super::NetworkEvent).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum ConnectionQuality {
+    #[default]
+    Good,
+    /// Round trip time or packet loss is high enough to be noticeable.
+    Degraded,
+    /// Round trip time or packet loss is high enough that the connection may soon be lost.
+    Critical,
+}
+
+/// Tracks the quality of one connection from its round trip time and the packet loss since the previous update.
+#[derive(Debug, Clone, Default)]
+pub(super) struct QualityMonitor {
+    quality: ConnectionQuality,
+    prev_stats: TrafficStats,
+}
+
+impl QualityMonitor {
+    /// Returns the new quality if it changed.
+    pub(super) fn update(&mut self, rtt: Option<Duration>, stats: TrafficStats) -> Option<ConnectionQuality> {
+        let sent = stats.packets_sent.saturating_sub(self.prev_stats.packets_sent);
+        let resends = stats.resends.saturating_sub(self.prev_stats.resends);
+        let loss = if sent == 0 {
+            0.0
+        } else {
+            (resends as f32 / sent as f32).min(1.0)
+        };
+        let rtt = rtt.unwrap_or_default();
+        self.prev_stats = stats;
+
+        let exceeds = |(rtt_limit, loss_limit): (Duration, f32)| rtt >= rtt_limit || loss >= loss_limit;
+        let is_critical = if self.quality == ConnectionQuality::Critical {
+            exceeds((CRITICAL_RTT.1, CRITICAL_LOSS.1))
+        } else {
+            exceeds((CRITICAL_RTT.0, CRITICAL_LOSS.0))
+        };
+        let is_degraded = if self.quality >= ConnectionQuality::Degraded {
+            exceeds((DEGRADED_RTT.1, DEGRADED_LOSS.1))
+        } else {
+            exceeds((DEGRADED_RTT.0, DEGRADED_LOSS.0))
+        };
+
+        let quality = match (is_critical, is_degraded) {
+            (true, _) => ConnectionQuality::Critical,
+            (false, true) => ConnectionQuality::Degraded,
+            (false, false) => ConnectionQuality::Good,
+        };
+        (quality != self.quality).then(|| {
+            self.quality = quality;
+            quality
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(packets_sent: u64, resends: u64) -> TrafficStats {
+        TrafficStats {
+            packets_sent,
+            resends,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn quality_changes_with_hysteresis() {
+        let mut monitor = QualityMonitor::default();
+        let ms = Duration::from_millis;
+
+        assert_eq!(monitor.update(Some(ms(100)), stats(100, 0)), None);
+        assert_eq!(
+            monitor.update(Some(ms(260)), stats(200, 0)),
+            Some(ConnectionQuality::Degraded)
+        );
+        // Below the entry threshold, but not yet below the exit threshold
+        assert_eq!(monitor.update(Some(ms(220)), stats(300, 0)), None);
+        assert_eq!(
+            monitor.update(Some(ms(150)), stats(400, 0)),
+            Some(ConnectionQuality::Good)
+        );
+
+        // Loss is measured since the previous update, not over the whole connection
+        assert_eq!(
+            monitor.update(Some(ms(100)), stats(500, 20)),
+            Some(ConnectionQuality::Critical)
+        );
+        assert_eq!(
+            monitor.update(Some(ms(100)), stats(600, 24)),
+            Some(ConnectionQuality::Degraded)
+        );
+        assert_eq!(
+            monitor.update(Some(ms(100)), stats(700, 24)),
+            Some(ConnectionQuality::Good)
+        );
+    }
+}
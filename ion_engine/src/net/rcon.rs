@@ -0,0 +1,168 @@
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+use bincode::{Decode, Encode};
+use ion_common::net::UdpMessage;
+use ion_common::net::udp_network_socket::UdpNetworkSocket;
+use ion_common::{Instant, PlayerId};
+
+use crate::core::world::WorldType;
+
+use super::mp_auth::SharedSecretAuth;
+use super::mp_common::MpMessage;
+
+// ---------------------------------------------------------- //
+// --------------------- Remote console --------------------- //
+// ---------------------------------------------------------- //
+
+/// Command sent by a server operator over the remote console, see [`RconClient`].
+///
+/// The server kicks players, broadcasts messages and writes saves itself. Other commands are passed to the game
+/// with [`UniverseDataType::on_admin_command`](crate::core::universe::UniverseDataType::on_admin_command).
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum AdminCommand {
+    Kick {
+        player_id: PlayerId,
+        reason: String,
+    },
+    /// Sends a chat message from the server to all players.
+    Broadcast {
+        message: String,
+    },
+    Save {
+        save_name: String,
+    },
+    SetSetting {
+        key: String,
+        value: String,
+    },
+    Custom {
+        command: String,
+    },
+}
+
+impl FromStr for AdminCommand {
+    type Err = String;
+
+    /// Parses a command typed into a console: `kick <player id> [reason]`, `say <message>`, `save <name>`,
+    /// `set <key> <value>`. Anything else is a custom command.
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let line = line.trim();
+        let (name, args) = line.split_once(' ').unwrap_or((line, ""));
+        let args = args.trim();
+        match name {
+            "kick" => {
+                let (player_id, reason) = args.split_once(' ').unwrap_or((args, "Kicked by admin"));
+                Ok(Self::Kick {
+                    player_id: player_id
+                        .parse()
+                        .map_err(|_| format!("Invalid player id: {}", player_id))?,
+                    reason: reason.trim().to_owned(),
+                })
+            }
+            "say" if !args.is_empty() => Ok(Self::Broadcast {
+                message: args.to_owned(),
+            }),
+            "save" if !args.is_empty() => Ok(Self::Save {
+                save_name: args.to_owned(),
+            }),
+            "set" => {
+                let (key, value) = args
+                    .split_once(' ')
+                    .ok_or_else(|| "Usage: set <key> <value>".to_owned())?;
+                Ok(Self::SetSetting {
+                    key: key.to_owned(),
+                    value: value.trim().to_owned(),
+                })
+            }
+            "say" | "save" => Err(format!("Missing argument for {}", name)),
+            "" => Err("Empty command".to_owned()),
+            _ => Ok(Self::Custom {
+                command: line.to_owned(),
+            }),
+        }
+    }
+}
+
+/// Sends admin commands to a running server, for example a headless one started with [`crate::run_headless`].
+///
+/// The server only accepts commands after an admin password has been set with
+/// [`Network::mp_set_admin_password`](super::Network::mp_set_admin_password).
+pub struct RconClient<W: WorldType> {
+    udp_socket: UdpNetworkSocket<UdpMessage<MpMessage<W::ActionType>>>,
+    server_addr: SocketAddr,
+    token: Vec<u8>,
+}
+
+impl<W: WorldType> RconClient<W> {
+    pub fn new(bind_addr: SocketAddr, server_addr: SocketAddr, password: &str) -> Self {
+        Self {
+            udp_socket: UdpNetworkSocket::new_encrypted(bind_addr),
+            server_addr,
+            token: SharedSecretAuth::token(password),
+        }
+    }
+
+    /// Sends the command and waits for the server to reply. Returns the reply, or why the command failed.
+    pub fn execute(&self, command: AdminCommand, timeout: Duration) -> Result<String, String> {
+        let msg = MpMessage::AdminCommand {
+            token: self.token.clone(),
+            command,
+        };
+        self.udp_socket
+            .send(self.server_addr, UdpMessage::MpMessage(msg), timeout);
+
+        let deadline = Instant::now() + timeout;
+        while let Some(remaining) = deadline.duration_since(Instant::now()) {
+            match self.udp_socket.try_recv_timeout(remaining) {
+                Some((from_addr, UdpMessage::MpMessage(MpMessage::AdminResponse { result })))
+                    if from_addr == self.server_addr =>
+                {
+                    return result;
+                }
+                Some(_) => {}
+                None => break,
+            }
+        }
+        Err("No response from server".to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn console_commands_are_parsed() {
+        assert_eq!(
+            "kick 7 spamming chat".parse(),
+            Ok(AdminCommand::Kick {
+                player_id: 7,
+                reason: "spamming chat".to_owned()
+            })
+        );
+        assert_eq!(
+            "say  Restarting in 5 minutes".parse(),
+            Ok(AdminCommand::Broadcast {
+                message: "Restarting in 5 minutes".to_owned()
+            })
+        );
+        assert_eq!(
+            "set max_players 16".parse(),
+            Ok(AdminCommand::SetSetting {
+                key: "max_players".to_owned(),
+                value: "16".to_owned()
+            })
+        );
+        assert_eq!(
+            "spawn boss".parse(),
+            Ok(AdminCommand::Custom {
+                command: "spawn boss".to_owned()
+            })
+        );
+        assert!("kick someone".parse::<AdminCommand>().is_err());
+        assert!("save".parse::<AdminCommand>().is_err());
+        assert!("".parse::<AdminCommand>().is_err());
+    }
+}
@@ -0,0 +1,199 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use ion_common::net::transport::PacketTransport;
+use ion_common::net::udp_network_socket::{TrafficStats, UdpNetworkSocket};
+use ion_common::net::{SysMessage, UdpMessage};
+use ion_common::{Instant, Map, log_info, log_warn};
+
+// Requests for a relay are sent again this often while data is waiting for the session to open
+const RELAY_OPEN_INTERVAL: Duration = Duration::from_secs(1);
+
+// Reliable data is resent to the host until acked for this long
+const RELAY_RESEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Data waiting for a session beyond this is dropped
+const MAX_PENDING_PAYLOADS: usize = 256;
+
+// ---------------------------------------------------------- //
+// -------------------- Relay transport --------------------- //
+// ---------------------------------------------------------- //
+
+/// Transport that sends everything through the relay service of the host, for peers that can't reach each other
+/// directly even with NAT punching. Set with [`Network::mp_set_transport`](super::Network::mp_set_transport).
+///
+/// Peers are addressed at the address the host sees their relay transport at, see [`RelayTransport::public_addr`].
+/// Sessions open once both peers have asked for one, which the transports do by themselves when one of them sends
+/// something to the other. Data waits until then, and is dropped if the host refuses the relay.
+pub struct RelayTransport {
+    udp_socket: UdpNetworkSocket<UdpMessage<()>>,
+    host_addr: SocketAddr,
+    state: Mutex<RelayState>,
+}
+
+#[derive(Default)]
+struct RelayState {
+    tokens: Map<SocketAddr, u64>,
+    peers: Map<u64, SocketAddr>,
+    pending: Map<SocketAddr, PendingSends>,
+    received: Vec<(SocketAddr, Vec<u8>)>,
+    public_addr: Option<SocketAddr>,
+}
+
+/// Data waiting for the session to the peer to open.
+struct PendingSends {
+    requested_at: Instant,
+    payloads: Vec<(Vec<u8>, bool)>,
+}
+
+impl RelayTransport {
+    pub fn new(bind_addr: SocketAddr, host_addr: SocketAddr) -> Self {
+        Self {
+            udp_socket: UdpNetworkSocket::new_encrypted(bind_addr),
+            host_addr,
+            state: Mutex::new(RelayState::default()),
+        }
+    }
+
+    /// Asks the host which address it sees this transport at, which is the address other peers reach it at.
+    pub fn public_addr(&self, timeout: Duration) -> Option<SocketAddr> {
+        if let Some(addr) = self.state.lock().unwrap().public_addr {
+            return Some(addr);
+        }
+        self.udp_socket.send(
+            self.host_addr,
+            UdpMessage::SysMessage(SysMessage::SocketInfoReq),
+            timeout,
+        );
+
+        let deadline = Instant::now() + timeout;
+        while let Some(remaining) = deadline.duration_since(Instant::now()) {
+            let (from_addr, msg) = self.udp_socket.try_recv_timeout(remaining)?;
+            let mut state = self.state.lock().unwrap();
+            self.process(&mut state, from_addr, msg);
+            if state.public_addr.is_some() {
+                return state.public_addr;
+            }
+        }
+        None
+    }
+
+    fn process(&self, state: &mut RelayState, from_addr: SocketAddr, msg: UdpMessage<()>) {
+        if from_addr != self.host_addr {
+            return;
+        }
+        let UdpMessage::SysMessage(msg) = msg else {
+            return;
+        };
+        match msg {
+            SysMessage::SocketInfoRes { addr } => {
+                state.public_addr = Some(addr);
+            }
+            // The peer wants a relay, which is agreed to by asking for the same
+            SysMessage::RelayOpen { peer } if !state.tokens.contains_key(&peer) => {
+                self.send_open(peer);
+            }
+            SysMessage::RelayOpened { token, peer } => {
+                log_info!("Relay opened to {:?}", peer);
+                if let Some(old_token) = state.tokens.insert(peer, token) {
+                    state.peers.remove(&old_token);
+                }
+                state.peers.insert(token, peer);
+                if let Some(pending) = state.pending.remove(&peer) {
+                    for (payload, reliable) in pending.payloads {
+                        self.send_data(token, payload, reliable);
+                    }
+                }
+            }
+            SysMessage::RelayRefused { peer, reason } => {
+                log_warn!("Relay to {:?} refused: {}", peer, reason);
+                state.pending.remove(&peer);
+            }
+            SysMessage::RelayData { token, data, .. } => {
+                if let Some(peer) = state.peers.get(&token) {
+                    state.received.push((*peer, data));
+                }
+            }
+            SysMessage::RelayClose { token } => {
+                if let Some(peer) = state.peers.remove(&token) {
+                    log_info!("Relay to {:?} closed", peer);
+                    state.tokens.remove(&peer);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn send_open(&self, peer: SocketAddr) {
+        let msg = UdpMessage::SysMessage(SysMessage::RelayOpen { peer });
+        self.udp_socket.send(self.host_addr, msg, RELAY_OPEN_INTERVAL);
+    }
+
+    fn send_data(&self, token: u64, data: Vec<u8>, reliable: bool) {
+        let timeout = if reliable { RELAY_RESEND_TIMEOUT } else { Duration::ZERO };
+        let msg = UdpMessage::SysMessage(SysMessage::RelayData { token, data, reliable });
+        self.udp_socket.send(self.host_addr, msg, timeout);
+    }
+}
+
+impl PacketTransport for RelayTransport {
+    fn send(&self, addr: SocketAddr, payload: Vec<u8>, reliable: bool) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(token) = state.tokens.get(&addr) {
+            self.send_data(*token, payload, reliable);
+            return;
+        }
+
+        let now = Instant::now();
+        let pending = state.pending.entry(addr).or_insert_with(|| {
+            self.send_open(addr);
+            PendingSends {
+                requested_at: now,
+                payloads: Vec::new(),
+            }
+        });
+        if pending.requested_at + RELAY_OPEN_INTERVAL < now {
+            self.send_open(addr);
+            pending.requested_at = now;
+        }
+        if pending.payloads.len() < MAX_PENDING_PAYLOADS {
+            pending.payloads.push((payload, reliable));
+        }
+    }
+
+    fn try_recv_all(&self) -> Vec<(SocketAddr, Vec<u8>)> {
+        let mut state = self.state.lock().unwrap();
+        for (from_addr, msg) in self.udp_socket.try_recv_all() {
+            self.process(&mut state, from_addr, msg);
+        }
+        std::mem::take(&mut state.received)
+    }
+
+    /// Estimated as twice the latency to the host, as the latency from the host to the peer is not known.
+    fn latency_of(&self, addr: SocketAddr) -> Option<Duration> {
+        if !self.state.lock().unwrap().tokens.contains_key(&addr) {
+            return None;
+        }
+        self.udp_socket.latency_of(self.host_addr).map(|latency| latency * 2)
+    }
+
+    /// Traffic to the host, shared by all the peers.
+    fn traffic_stats_of(&self, _addr: SocketAddr) -> Option<TrafficStats> {
+        self.udp_socket.traffic_stats_of(self.host_addr)
+    }
+
+    fn local_ip_addr(&self) -> Option<IpAddr> {
+        self.udp_socket.local_ip_addr()
+    }
+}
+
+impl Drop for RelayTransport {
+    fn drop(&mut self) {
+        let state = self.state.lock().unwrap();
+        for token in state.peers.keys() {
+            let msg = UdpMessage::SysMessage(SysMessage::RelayClose { token: *token });
+            self.udp_socket.send(self.host_addr, msg, Duration::ZERO);
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::BTreeMap;
+
+use ion_common::{Map, PlayerId};
+
+use crate::core::FrameId;
+use crate::core::world::{ActionType, WorldId};
+
+/// Actions of every player in every world for a single frame.
+pub(super) type FrameActions<C> = Map<WorldId, BTreeMap<PlayerId, Vec<C>>>;
+
+/// Snapshots of every world, taken before executing a frame.
+pub(super) type WorldSnapshots = Map<WorldId, Vec<u8>>;
+
+// ---------------------------------------------------------- //
+// -------------------- Rollback buffer --------------------- //
+// ---------------------------------------------------------- //
+
+/// Frame that was executed with predicted actions, because the confirmed actions from the server had not arrived yet.
+struct PredictedFrame<C: ActionType> {
+    actions: FrameActions<C>,
+    own_actions: Map<WorldId, Vec<C>>,
+    snapshots: Option<WorldSnapshots>,
+}
+
+/// Keeps track of the frames a rollback client has predicted, and the world snapshots taken before each of them.
+///
+/// Remote players are predicted to do nothing, while own actions are applied immediately.
+/// When the confirmed actions of a frame differ from the prediction, the worlds are restored to the snapshot
+/// taken before that frame and all frames since are executed again.
+pub(super) struct RollbackBuffer<C: ActionType> {
+    max_frames: u32,
+    predicted: BTreeMap<FrameId, PredictedFrame<C>>,
+}
+
+impl<C: ActionType> RollbackBuffer<C> {
+    pub(super) fn new(max_frames: u32) -> Self {
+        Self {
+            max_frames,
+            predicted: BTreeMap::new(),
+        }
+    }
+
+    pub(super) fn max_frames(&self) -> u32 {
+        self.max_frames
+    }
+
+    /// Whether another frame can be predicted, or if the client must wait for the server to catch up.
+    pub(super) fn can_predict(&self) -> bool {
+        self.predicted.len() < self.max_frames as usize
+    }
+
+    /// Predicts actions of a frame based on the latest confirmed frame: the same players are present,
+    /// and only the own player acts.
+    pub(super) fn predict(
+        &mut self,
+        frame: FrameId,
+        own_player: PlayerId,
+        own_actions: Map<WorldId, Vec<C>>,
+        latest_confirmed: &FrameActions<C>,
+    ) -> FrameActions<C> {
+        let mut actions = latest_confirmed.clone();
+        for player_actions in actions.values_mut() {
+            player_actions.values_mut().for_each(Vec::clear);
+        }
+        for (world_id, world_actions) in &own_actions {
+            actions
+                .entry(*world_id)
+                .or_default()
+                .insert(own_player, world_actions.clone());
+        }
+
+        self.predicted.insert(
+            frame,
+            PredictedFrame {
+                actions: actions.clone(),
+                own_actions,
+                snapshots: None,
+            },
+        );
+        actions
+    }
+
+    /// Stores the world snapshots taken before executing the frame. Snapshots are only taken for predicted frames.
+    pub(super) fn store_snapshots(&mut self, frame: FrameId, take_snapshots: impl FnOnce() -> WorldSnapshots) {
+        if let Some(predicted) = self.predicted.get_mut(&frame) {
+            predicted.snapshots = Some(take_snapshots());
+        }
+    }
+
+    /// Compares the predicted frames against the confirmed actions, oldest first.
+    /// Correctly predicted frames are dropped from the buffer.
+    /// Returns the first frame that was predicted wrong, if any.
+    pub(super) fn first_misprediction(
+        &mut self,
+        confirmed: impl Fn(FrameId) -> Option<FrameActions<C>>,
+    ) -> Option<FrameId> {
+        while let Some((&frame, predicted)) = self.predicted.first_key_value() {
+            match confirmed(frame) {
+                Some(actions) if actions == predicted.actions => {
+                    self.predicted.pop_first();
+                }
+                Some(_) => return Some(frame),
+                None => return None,
+            }
+        }
+        None
+    }
+
+    /// Removes the given frame and all frames after it from the buffer.
+    /// Returns the snapshots taken before the frame, and own actions of the removed frames for predicting them again.
+    pub(super) fn rollback(&mut self, frame: FrameId) -> (WorldSnapshots, BTreeMap<FrameId, Map<WorldId, Vec<C>>>) {
+        let removed = self.predicted.split_off(&frame);
+        let snapshots = removed
+            .get(&frame)
+            .and_then(|predicted| predicted.snapshots.clone())
+            .expect("Predicted frames must have snapshots");
+        let own_actions = removed
+            .into_iter()
+            .map(|(frame, predicted)| (frame, predicted.own_actions))
+            .collect();
+        (snapshots, own_actions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bincode::{Decode, Encode};
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Encode, Decode)]
+    struct TestAction(u32);
+
+    impl ActionType for TestAction {
+        fn is_stateful(&self) -> bool {
+            true
+        }
+    }
+
+    fn confirmed(players: &[(PlayerId, &[u32])]) -> FrameActions<TestAction> {
+        let mut actions = FrameActions::default();
+        for (player, player_actions) in players {
+            actions
+                .entry(0)
+                .or_default()
+                .insert(*player, player_actions.iter().copied().map(TestAction).collect());
+        }
+        actions
+    }
+
+    #[test]
+    fn mispredicted_frames_are_rolled_back() {
+        let own = 1;
+        let remote = 2;
+        let mut buffer = RollbackBuffer::new(3);
+        let latest = confirmed(&[(own, &[]), (remote, &[7])]);
+
+        let mut own_actions = Map::default();
+        own_actions.insert(0, vec![TestAction(1)]);
+        let predicted = buffer.predict(10, own, own_actions, &latest);
+        assert_eq!(predicted, confirmed(&[(own, &[1]), (remote, &[])]));
+        buffer.predict(11, own, Map::default(), &latest);
+        buffer.predict(12, own, Map::default(), &latest);
+        assert!(!buffer.can_predict());
+
+        for frame in 10..=12 {
+            buffer.store_snapshots(frame, || {
+                let mut snapshots = Map::default();
+                snapshots.insert(0, vec![frame as u8]);
+                snapshots
+            });
+        }
+
+        // Frame 10 was predicted correctly, but remote player acted on frame 11
+        let mut server = Map::default();
+        server.insert(10, confirmed(&[(own, &[1]), (remote, &[])]));
+        server.insert(11, confirmed(&[(own, &[]), (remote, &[3])]));
+        assert_eq!(
+            buffer.first_misprediction(|frame| server.get(&frame).cloned()),
+            Some(11)
+        );
+        assert!(!buffer.predicted.contains_key(&10));
+
+        let (snapshots, own_actions) = buffer.rollback(11);
+        assert_eq!(snapshots.get(&0), Some(&vec![11]));
+        assert_eq!(own_actions.keys().copied().collect::<Vec<_>>(), vec![11, 12]);
+        assert!(!buffer.predicted.contains_key(&12));
+        assert!(buffer.can_predict());
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::BTreeMap;
+
+use crate::core::FrameId;
+
+use super::rollback::WorldSnapshots;
+
+// ---------------------------------------------------------- //
+// -------------------- Snapshot buffer --------------------- //
+// ---------------------------------------------------------- //
+
+/// Pair of server snapshots the client is currently between, and how far between them.
+#[derive(Debug, Clone)]
+pub(crate) struct SnapshotInterpolation {
+    pub previous: WorldSnapshots,
+    pub next: WorldSnapshots,
+    /// Position between the snapshots, from 0.0 at the previous to 1.0 at the next.
+    pub t: f32,
+}
+
+/// State snapshots received from the server by a client in snapshot sync mode.
+///
+/// The client shows the state `delay` frames in the past, so that the snapshot after it
+/// has usually arrived and the state can be interpolated between the two.
+pub(super) struct SnapshotBuffer {
+    delay: u64,
+    snapshots: BTreeMap<FrameId, WorldSnapshots>,
+}
+
+impl SnapshotBuffer {
+    pub(super) fn new(delay: u64) -> Self {
+        Self {
+            delay,
+            snapshots: BTreeMap::new(),
+        }
+    }
+
+    pub(super) fn insert(&mut self, frame: FrameId, snapshots: WorldSnapshots) {
+        self.snapshots.insert(frame, snapshots);
+    }
+
+    /// Returns the snapshots to interpolate between for the frame, and drops snapshots that are no longer needed.
+    /// If the next snapshot has not arrived yet, stays at the latest one.
+    pub(super) fn interpolate(&mut self, frame: FrameId) -> Option<SnapshotInterpolation> {
+        let target = frame.checked_sub(self.delay)?;
+
+        let (&previous_frame, previous) = self.snapshots.range(..=target).next_back()?;
+        let interpolation = match self.snapshots.range(target + 1..).next() {
+            Some((&next_frame, next)) => SnapshotInterpolation {
+                previous: previous.clone(),
+                next: next.clone(),
+                t: (target - previous_frame) as f32 / (next_frame - previous_frame) as f32,
+            },
+            None => SnapshotInterpolation {
+                previous: previous.clone(),
+                next: previous.clone(),
+                t: 0.0,
+            },
+        };
+
+        self.snapshots = self.snapshots.split_off(&previous_frame);
+
+        Some(interpolation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ion_common::Map;
+
+    use super::*;
+
+    fn snapshot(byte: u8) -> WorldSnapshots {
+        let mut snapshots = Map::default();
+        snapshots.insert(0, vec![byte]);
+        snapshots
+    }
+
+    #[test]
+    fn snapshots_are_interpolated_with_delay() {
+        let mut buffer = SnapshotBuffer::new(4);
+        buffer.insert(10, snapshot(1));
+        buffer.insert(14, snapshot(2));
+
+        assert!(buffer.interpolate(13).is_none());
+
+        let interpolation = buffer.interpolate(15).unwrap();
+        assert_eq!(interpolation.previous, snapshot(1));
+        assert_eq!(interpolation.next, snapshot(2));
+        assert_eq!(interpolation.t, 0.25);
+
+        // Next snapshot has not arrived yet, so stays at the latest one
+        let interpolation = buffer.interpolate(19).unwrap();
+        assert_eq!(interpolation.previous, snapshot(2));
+        assert_eq!(interpolation.next, snapshot(2));
+        assert_eq!(interpolation.t, 0.0);
+
+        buffer.insert(18, snapshot(3));
+        buffer.insert(22, snapshot(4));
+        buffer.interpolate(26);
+        assert_eq!(buffer.snapshots.keys().copied().collect::<Vec<_>>(), vec![22]);
+    }
+}
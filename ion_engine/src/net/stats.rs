@@ -0,0 +1,87 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use ion_common::Instant;
+use ion_common::net::udp_network_socket::UdpNetworkSocket;
+use ion_common::net::{LeaderboardEntry, StatSubmission, SysMessage, UdpMessage};
+
+// ---------------------------------------------------------- //
+// ---------------------- Stats client ---------------------- //
+// ---------------------------------------------------------- //
+
+/// Submits player stats to the host services and queries the leaderboards kept from them.
+///
+/// Submissions are signed with a key shared with the host, so they should only be made by game servers, see
+/// [`StatSubmission`]. Anyone can query.
+pub struct StatsClient {
+    udp_socket: UdpNetworkSocket<UdpMessage<()>>,
+    host_addr: SocketAddr,
+}
+
+impl StatsClient {
+    pub fn new(bind_addr: SocketAddr, host_addr: SocketAddr) -> Self {
+        Self {
+            udp_socket: UdpNetworkSocket::new_encrypted(bind_addr),
+            host_addr,
+        }
+    }
+
+    pub fn submit(&self, submission: StatSubmission) {
+        let msg = UdpMessage::SysMessage(SysMessage::StatSubmit { submission });
+        self.udp_socket.send(self.host_addr, msg, Duration::from_secs(15));
+    }
+
+    /// Returns the best players of the stat, at most `count` of them.
+    pub fn leaderboard(&self, stat: &str, count: u32, timeout: Duration) -> Result<Vec<LeaderboardEntry>, String> {
+        let msg = SysMessage::LeaderboardReq {
+            stat: stat.to_owned(),
+            count,
+        };
+        self.request(msg, timeout, |msg| match msg {
+            SysMessage::LeaderboardRes {
+                stat: res_stat,
+                entries,
+            } if res_stat == stat => Some(entries),
+            _ => None,
+        })
+    }
+
+    /// Returns every stat of the player with its value.
+    pub fn player_stats(&self, player: &str, timeout: Duration) -> Result<Vec<(String, i64)>, String> {
+        let msg = SysMessage::PlayerStatsReq {
+            player: player.to_owned(),
+        };
+        self.request(msg, timeout, |msg| match msg {
+            SysMessage::PlayerStatsRes {
+                player: res_player,
+                stats,
+            } if res_player == player => Some(stats),
+            _ => None,
+        })
+    }
+
+    /// Sends the request and waits for the host to send the answer picked out by `answer`.
+    fn request<T>(
+        &self,
+        msg: SysMessage,
+        timeout: Duration,
+        answer: impl Fn(SysMessage) -> Option<T>,
+    ) -> Result<T, String> {
+        self.udp_socket
+            .send(self.host_addr, UdpMessage::SysMessage(msg), timeout);
+
+        let deadline = Instant::now() + timeout;
+        while let Some(remaining) = deadline.duration_since(Instant::now()) {
+            match self.udp_socket.try_recv_timeout(remaining) {
+                Some((from_addr, UdpMessage::SysMessage(msg))) if from_addr == self.host_addr => {
+                    if let Some(answer) = answer(msg) {
+                        return Ok(answer);
+                    }
+                }
+                Some(_) => {}
+                None => break,
+            }
+        }
+        Err("No response from host".to_owned())
+    }
+}
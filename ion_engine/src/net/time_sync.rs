@@ -0,0 +1,170 @@
+use std::time::Duration;
+
+use ion_common::Instant;
+
+use crate::core::FrameId;
+
+pub(super) const TIME_SYNC_INTERVAL: Duration = Duration::from_millis(500);
+
+// Frames kept between the client and the server on top of the latency, so that actions arrive before they are needed
+const TARGET_MARGIN_FRAMES: f64 = 1.0;
+
+const OFFSET_SMOOTHING: f64 = 0.2;
+const DRIFT_SMOOTHING: f64 = 0.1;
+
+// Change of tick speed per frame of offset, and per frame per second of drift
+const OFFSET_GAIN: f64 = 0.02;
+const DRIFT_GAIN: f64 = 0.05;
+
+// Ticks are never paced faster or slower than this, so that the change stays unnoticeable
+const MAX_PACING_CHANGE: f64 = 0.1;
+
+// ---------------------------------------------------------- //
+// ----------------------- Time sync ------------------------ //
+// ---------------------------------------------------------- //
+
+/// Estimates how many frames the client is off from where it should be relative to the server, and how fast that
+/// offset drifts, from periodic time sync requests answered by the server.
+///
+/// Lockstep clients aim to stay behind the server by the latency, as they execute frames only once the server has sent
+/// the actions of the frame. Rollback clients aim to stay ahead by the latency, so that their own actions reach the
+/// server in time for the frame they were executed on.
+#[derive(Debug, Clone)]
+pub(super) struct TimeSync {
+    epoch: Instant,
+    runs_ahead: bool,
+    last_request_at: Option<Instant>,
+    last_sample_at: Option<Instant>,
+    /// Frames the client is behind of where it should be, negative when ahead.
+    offset: Option<f64>,
+    /// Change of the offset in frames per second.
+    drift: f64,
+}
+
+impl TimeSync {
+    pub(super) fn new(runs_ahead: bool) -> Self {
+        Self {
+            epoch: Instant::now(),
+            runs_ahead,
+            last_request_at: None,
+            last_sample_at: None,
+            offset: None,
+            drift: 0.0,
+        }
+    }
+
+    /// Returns the client time to send to the server, if it's time for a new request.
+    pub(super) fn request(&mut self, now: Instant) -> Option<Duration> {
+        if self
+            .last_request_at
+            .is_some_and(|last_request_at| last_request_at + TIME_SYNC_INTERVAL > now)
+        {
+            return None;
+        }
+        self.last_request_at = Some(now);
+        now.duration_since(self.epoch)
+    }
+
+    /// Updates the estimates from the answer of the server to the request sent at the client time.
+    pub(super) fn on_response(
+        &mut self,
+        client_time: Duration,
+        server_frame: FrameId,
+        client_frame: FrameId,
+        frame_time: Duration,
+        now: Instant,
+    ) {
+        let Some(rtt) = now
+            .duration_since(self.epoch)
+            .and_then(|elapsed| elapsed.checked_sub(client_time))
+        else {
+            return;
+        };
+
+        let latency_frames = rtt.as_secs_f64() / 2.0 / frame_time.as_secs_f64();
+        let target_lag = if self.runs_ahead {
+            -(latency_frames + TARGET_MARGIN_FRAMES)
+        } else {
+            latency_frames + TARGET_MARGIN_FRAMES
+        };
+        // The server has moved on by the time the answer arrives
+        let lag = server_frame as f64 + latency_frames - client_frame as f64;
+        let sample = lag - target_lag;
+
+        let offset = match self.offset {
+            Some(offset) => offset + OFFSET_SMOOTHING * (sample - offset),
+            None => sample,
+        };
+        if let (Some(prev_offset), Some(elapsed)) = (
+            self.offset,
+            self.last_sample_at
+                .and_then(|last_sample_at| now.duration_since(last_sample_at)),
+        ) && !elapsed.is_zero()
+        {
+            let rate = (offset - prev_offset) / elapsed.as_secs_f64();
+            self.drift += DRIFT_SMOOTHING * (rate - self.drift);
+        }
+        self.offset = Some(offset);
+        self.last_sample_at = Some(now);
+    }
+
+    /// Multiplier for the tick speed of the client, above one when the client should catch up with the server.
+    pub(super) fn pacing(&self) -> f64 {
+        let Some(offset) = self.offset else {
+            return 1.0;
+        };
+        (1.0 + offset * OFFSET_GAIN + self.drift * DRIFT_GAIN).clamp(1.0 - MAX_PACING_CHANGE, 1.0 + MAX_PACING_CHANGE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME_TIME: Duration = Duration::from_millis(16);
+
+    fn respond(time_sync: &mut TimeSync, server_frame: FrameId, client_frame: FrameId, rtt: Duration) {
+        let sent_at = Instant::now();
+        let client_time = sent_at.duration_since(time_sync.epoch).unwrap();
+        time_sync.on_response(client_time, server_frame, client_frame, FRAME_TIME, sent_at + rtt);
+    }
+
+    #[test]
+    fn pacing_follows_the_server() {
+        let rtt = FRAME_TIME * 4;
+
+        let mut time_sync = TimeSync::new(false);
+        assert_eq!(time_sync.pacing(), 1.0);
+
+        // Behind by the latency and the margin is where lockstep clients should be
+        respond(&mut time_sync, 101, 100, rtt);
+        assert!((time_sync.pacing() - 1.0).abs() < 1e-6);
+
+        let mut time_sync = TimeSync::new(false);
+        respond(&mut time_sync, 104, 100, rtt);
+        assert!(time_sync.pacing() > 1.0);
+
+        let mut time_sync = TimeSync::new(false);
+        respond(&mut time_sync, 98, 100, rtt);
+        assert!(time_sync.pacing() < 1.0);
+
+        // Rollback clients run ahead of the server instead
+        let mut time_sync = TimeSync::new(true);
+        respond(&mut time_sync, 95, 100, rtt);
+        assert!((time_sync.pacing() - 1.0).abs() < 1e-6);
+
+        // Far off clients catch up only at the limited pace
+        let mut time_sync = TimeSync::new(false);
+        respond(&mut time_sync, 1000, 100, rtt);
+        assert_eq!(time_sync.pacing(), 1.0 + MAX_PACING_CHANGE);
+    }
+
+    #[test]
+    fn requests_are_sent_at_intervals() {
+        let mut time_sync = TimeSync::new(false);
+        let now = Instant::now();
+        assert!(time_sync.request(now).is_some());
+        assert!(time_sync.request(now + TIME_SYNC_INTERVAL / 2).is_none());
+        assert!(time_sync.request(now + TIME_SYNC_INTERVAL * 2).is_some());
+    }
+}
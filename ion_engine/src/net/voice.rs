@@ -0,0 +1,325 @@
+use std::collections::BTreeMap;
+
+use bincode::{Decode, Encode};
+use ion_common::{Map, PlayerId};
+
+#[cfg(feature = "voice")]
+use self::device::VoiceDevice;
+
+#[cfg(feature = "voice")]
+mod device;
+
+// ---------------------------------------------------------- //
+// ----------------------- Constants ------------------------ //
+// ---------------------------------------------------------- //
+
+// Packets buffered before playback of a player starts, to smooth out uneven arrival
+const JITTER_DELAY_PACKETS: usize = 3;
+// Packets buffered per player at most. Older ones are dropped if playback falls behind.
+const MAX_BUFFERED_PACKETS: usize = 50;
+
+// ---------------------------------------------------------- //
+// ----------------------- Voice chat ----------------------- //
+// ---------------------------------------------------------- //
+
+/// Opus encoded 20 ms of audio from a player. Sent unreliably, so packets may be lost or arrive out of order.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub(crate) struct VoicePacket {
+    pub seq: u32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct VoiceSettings {
+    volume: f32,
+    muted: bool,
+}
+
+impl Default for VoiceSettings {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+            muted: false,
+        }
+    }
+}
+
+/// Voice of other players waiting for playback, and the captured voice of the own player.
+///
+/// Audio devices are only opened with the `voice` feature. Without it, voice from other players is dropped and
+/// nothing is sent, but the settings still work.
+pub(super) struct VoiceChat {
+    settings: Map<PlayerId, VoiceSettings>,
+    streams: Map<PlayerId, JitterBuffer>,
+    transmitting: bool,
+    next_seq: u32,
+    #[cfg(feature = "voice")]
+    device: Option<VoiceDevice>,
+}
+
+impl VoiceChat {
+    pub(super) fn new() -> Self {
+        Self {
+            settings: Map::default(),
+            streams: Map::default(),
+            transmitting: false,
+            next_seq: 0,
+            #[cfg(feature = "voice")]
+            device: None,
+        }
+    }
+
+    #[cfg(feature = "voice")]
+    pub(super) fn start(&mut self) {
+        if self.device.is_none() {
+            self.device = Some(VoiceDevice::new());
+        }
+    }
+
+    #[cfg(feature = "voice")]
+    pub(super) fn stop(&mut self) {
+        self.device = None;
+        self.streams.clear();
+    }
+
+    pub(super) fn set_transmitting(&mut self, transmitting: bool) {
+        self.transmitting = transmitting;
+    }
+
+    pub(super) fn set_volume(&mut self, player_id: PlayerId, volume: f32) {
+        self.settings.entry(player_id).or_default().volume = volume.max(0.0);
+    }
+
+    pub(super) fn set_muted(&mut self, player_id: PlayerId, muted: bool) {
+        self.settings.entry(player_id).or_default().muted = muted;
+        if muted {
+            self.streams.remove(&player_id);
+        }
+    }
+
+    /// Volume the voice of the player is played at, zero if muted.
+    #[cfg_attr(not(feature = "voice"), allow(dead_code))]
+    pub(super) fn volume(&self, player_id: PlayerId) -> f32 {
+        let settings = self.settings.get(&player_id).copied().unwrap_or_default();
+        if settings.muted { 0.0 } else { settings.volume }
+    }
+
+    /// Buffers a packet received from another player for playback.
+    pub(super) fn receive(&mut self, player_id: PlayerId, packet: VoicePacket) {
+        if !self.is_playing() || self.settings.get(&player_id).is_some_and(|settings| settings.muted) {
+            return;
+        }
+        self.streams.entry(player_id).or_default().insert(packet);
+    }
+
+    /// Packets of own voice captured since the last call. Empty unless transmitting.
+    pub(super) fn captured_packets(&mut self) -> Vec<VoicePacket> {
+        #[cfg(feature = "voice")]
+        let captured = self.device.as_ref().map(VoiceDevice::captured).unwrap_or_default();
+        #[cfg(not(feature = "voice"))]
+        let captured: Vec<Vec<u8>> = Vec::new();
+
+        if !self.transmitting {
+            return Vec::new();
+        }
+        captured
+            .into_iter()
+            .map(|data| {
+                self.next_seq = self.next_seq.wrapping_add(1);
+                VoicePacket {
+                    seq: self.next_seq,
+                    data,
+                }
+            })
+            .collect()
+    }
+
+    /// Decodes and mixes the buffered voice of other players into the audio output.
+    pub(super) fn play(&mut self) {
+        #[cfg(feature = "voice")]
+        {
+            let volumes: Map<PlayerId, f32> = self.streams.keys().map(|id| (*id, self.volume(*id))).collect();
+            if let Some(device) = &mut self.device {
+                device.play(&mut self.streams, &volumes);
+            }
+        }
+    }
+
+    fn is_playing(&self) -> bool {
+        #[cfg(feature = "voice")]
+        {
+            self.device.is_some()
+        }
+        #[cfg(not(feature = "voice"))]
+        {
+            false
+        }
+    }
+}
+
+/// Puts voice packets of a player back in order, and holds back playback until a few packets have arrived.
+#[derive(Default)]
+struct JitterBuffer {
+    packets: BTreeMap<u32, Vec<u8>>,
+    next_seq: Option<u32>,
+}
+
+impl JitterBuffer {
+    fn insert(&mut self, packet: VoicePacket) {
+        if self.next_seq.is_some_and(|next_seq| packet.seq < next_seq) {
+            // Arrived too late, the gap was already filled in
+            return;
+        }
+        self.packets.insert(packet.seq, packet.data);
+        if self.packets.len() > MAX_BUFFERED_PACKETS {
+            self.packets.pop_first();
+            self.next_seq = self.packets.first_key_value().map(|(seq, _)| *seq);
+        }
+    }
+
+    /// Returns the next packet to play. The inner value is none if the packet was lost and should be concealed.
+    /// Returns none while buffering, and when the player has stopped talking.
+    #[cfg_attr(not(feature = "voice"), allow(dead_code))]
+    fn pop(&mut self) -> Option<Option<Vec<u8>>> {
+        let &first_seq = self.packets.keys().next()?;
+        let next_seq = match self.next_seq {
+            Some(next_seq) => next_seq,
+            None if self.packets.len() >= JITTER_DELAY_PACKETS => first_seq,
+            None => return None,
+        };
+        self.next_seq = Some(next_seq + 1);
+
+        let packet = self.packets.remove(&next_seq);
+        if self.packets.is_empty() {
+            // Talk spurt is over, buffer again when the player starts talking
+            self.next_seq = None;
+        }
+        Some(packet)
+    }
+}
+
+/// Linear resampler between the rate voice is encoded at and the rate of an audio device. Audio is resampled in
+/// pieces as it arrives or is needed, and the resampler carries the position between them.
+#[cfg_attr(not(feature = "voice"), allow(dead_code))]
+struct Resampler {
+    /// Input samples per output sample.
+    step: f64,
+    /// Position of the next output sample between the previous and the latest input sample.
+    position: f64,
+    previous: f32,
+    latest: f32,
+}
+
+#[cfg_attr(not(feature = "voice"), allow(dead_code))]
+impl Resampler {
+    fn new(input_rate: u32, output_rate: u32) -> Self {
+        Self {
+            step: input_rate as f64 / output_rate as f64,
+            position: 0.0,
+            previous: 0.0,
+            latest: 0.0,
+        }
+    }
+
+    /// Resamples the input, returning the output samples that fall before its last sample.
+    fn push(&mut self, input: &[f32]) -> Vec<f32> {
+        let mut output = Vec::with_capacity((input.len() as f64 / self.step) as usize + 1);
+        for &sample in input {
+            self.previous = self.latest;
+            self.latest = sample;
+            while self.position < 1.0 {
+                output.push(self.interpolate());
+                self.position += self.step;
+            }
+            self.position -= 1.0;
+        }
+        output
+    }
+
+    /// Returns the next output sample, taking as many input samples as it needs from `next_input`.
+    fn pull(&mut self, mut next_input: impl FnMut() -> f32) -> f32 {
+        while self.position >= 1.0 {
+            self.previous = self.latest;
+            self.latest = next_input();
+            self.position -= 1.0;
+        }
+        let sample = self.interpolate();
+        self.position += self.step;
+        sample
+    }
+
+    fn interpolate(&self) -> f32 {
+        self.previous + (self.latest - self.previous) * self.position as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(seq: u32) -> VoicePacket {
+        VoicePacket {
+            seq,
+            data: vec![seq as u8],
+        }
+    }
+
+    #[test]
+    fn jitter_buffer_reorders_and_conceals_lost_packets() {
+        let mut buffer = JitterBuffer::default();
+        buffer.insert(packet(2));
+        buffer.insert(packet(1));
+        assert_eq!(buffer.pop(), None);
+
+        buffer.insert(packet(4));
+        assert_eq!(buffer.pop(), Some(Some(vec![1])));
+        assert_eq!(buffer.pop(), Some(Some(vec![2])));
+
+        // Packet 3 was lost, and arrives after its turn
+        assert_eq!(buffer.pop(), Some(None));
+        buffer.insert(packet(3));
+        assert_eq!(buffer.pop(), Some(Some(vec![4])));
+        assert_eq!(buffer.pop(), None);
+    }
+
+    #[test]
+    fn muted_players_are_silent() {
+        let mut voice = VoiceChat::new();
+        voice.set_volume(1, 0.5);
+        assert_eq!(voice.volume(1), 0.5);
+        assert_eq!(voice.volume(2), 1.0);
+
+        voice.set_muted(1, true);
+        assert_eq!(voice.volume(1), 0.0);
+        voice.set_muted(1, false);
+        assert_eq!(voice.volume(1), 0.5);
+    }
+
+    #[test]
+    fn resampler_converts_between_rates() {
+        // Device capturing at 44.1 kHz, in pieces of 10 ms
+        let mut resampler = Resampler::new(44100, 48000);
+        let mut output = Vec::new();
+        for _ in 0..100 {
+            output.extend(resampler.push(&[0.5; 441]));
+        }
+        assert!((output.len() as i64 - 48000).abs() <= 1, "{}", output.len());
+        // The first output samples fade in from silence
+        assert!(output[2..].iter().all(|sample| (sample - 0.5).abs() < 1e-6));
+
+        // Device playing at 44.1 kHz
+        let mut resampler = Resampler::new(48000, 44100);
+        let mut taken = 0;
+        for _ in 0..44100 {
+            resampler.pull(|| {
+                taken += 1;
+                0.5
+            });
+        }
+        assert!((taken as i64 - 48000).abs() <= 2, "{}", taken);
+
+        // Halfway between two input samples
+        let mut resampler = Resampler::new(1, 2);
+        assert_eq!(resampler.push(&[1.0, 3.0]), vec![0.0, 0.5, 1.0, 2.0]);
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{
+    DefaultStreamConfigError, Device, FromSample, SampleFormat, SampleRate, SizedSample, Stream, StreamConfig,
+    SupportedStreamConfig, SupportedStreamConfigRange, SupportedStreamConfigsError,
+};
+use ion_common::{Map, PlayerId, log_error, log_warn};
+use opus::{Application, Channels, Decoder, Encoder};
+
+use crate::util::concurrency::spawn_thread;
+
+use super::{JitterBuffer, Resampler};
+
+// Rate that voice is encoded at. Audio of devices running at other rates is resampled.
+const SAMPLE_RATE: u32 = 48000;
+// 20 ms of mono audio per packet
+const FRAME_SAMPLES: usize = 960;
+const MAX_PACKET_SIZE: usize = 400;
+// Mixed audio kept queued for the output device, in packets
+const PLAYBACK_QUEUE_PACKETS: usize = 2;
+
+// ---------------------------------------------------------- //
+// ---------------------- Audio devices --------------------- //
+// ---------------------------------------------------------- //
+
+/// Default microphone and speakers of the system, opened for voice chat.
+///
+/// The audio streams live in their own thread, which encodes captured audio as it arrives.
+/// Decoding and mixing happens in `play`, on the thread that processes the network.
+pub(super) struct VoiceDevice {
+    running: Arc<AtomicBool>,
+    captured: Receiver<Vec<u8>>,
+    playback: Arc<Mutex<VecDeque<f32>>>,
+    decoders: Map<PlayerId, Decoder>,
+}
+
+impl VoiceDevice {
+    pub(super) fn new() -> Self {
+        let running = Arc::new(AtomicBool::new(true));
+        let playback = Arc::new(Mutex::new(VecDeque::new()));
+        let (captured_sender, captured_receiver) = mpsc::channel();
+
+        spawn_thread(Some("Voice"), {
+            let running = running.clone();
+            let playback = playback.clone();
+            move || run_audio_streams(&running, playback, captured_sender)
+        });
+
+        Self {
+            running,
+            captured: captured_receiver,
+            playback,
+            decoders: Map::default(),
+        }
+    }
+
+    /// Encoded packets of own voice captured since the last call.
+    pub(super) fn captured(&self) -> Vec<Vec<u8>> {
+        self.captured.try_iter().collect()
+    }
+
+    /// Decodes the voice of other players and mixes it into the output, until enough audio is queued.
+    pub(super) fn play(&mut self, streams: &mut Map<PlayerId, JitterBuffer>, volumes: &Map<PlayerId, f32>) {
+        while self.playback.lock().unwrap().len() < PLAYBACK_QUEUE_PACKETS * FRAME_SAMPLES {
+            let mut mixed = vec![0.0; FRAME_SAMPLES];
+            let mut is_silent = true;
+            for (player_id, stream) in streams.iter_mut() {
+                let Some(packet) = stream.pop() else {
+                    continue;
+                };
+                let decoder = self
+                    .decoders
+                    .entry(*player_id)
+                    .or_insert_with(|| Decoder::new(SAMPLE_RATE, Channels::Mono).unwrap());
+
+                // An empty packet asks the decoder to conceal the lost one
+                let mut samples = vec![0.0; FRAME_SAMPLES];
+                match decoder.decode_float(packet.as_deref().unwrap_or_default(), &mut samples, false) {
+                    Ok(len) => {
+                        let volume = volumes.get(player_id).copied().unwrap_or(1.0);
+                        for (mixed, sample) in mixed.iter_mut().zip(&samples[..len]) {
+                            *mixed += sample * volume;
+                        }
+                        is_silent = false;
+                    }
+                    Err(err) => {
+                        log_warn!("Failed to decode voice of player {}: {}", player_id, err);
+                    }
+                }
+            }
+
+            if is_silent {
+                break;
+            }
+            self.playback
+                .lock()
+                .unwrap()
+                .extend(mixed.into_iter().map(|sample| sample.clamp(-1.0, 1.0)));
+        }
+    }
+}
+
+impl Drop for VoiceDevice {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Opens the default input and output devices, and encodes captured audio until stopped.
+fn run_audio_streams(running: &AtomicBool, playback: Arc<Mutex<VecDeque<f32>>>, captured: Sender<Vec<u8>>) {
+    let host = cpal::default_host();
+    let (Some(input), Some(output)) = (host.default_input_device(), host.default_output_device()) else {
+        log_warn!("No audio devices for voice chat");
+        return;
+    };
+
+    let (sample_sender, sample_receiver) = mpsc::channel::<Vec<f32>>();
+    let streams = build_input_stream(&input, sample_sender).zip(build_output_stream(&output, playback));
+    let Some((input_stream, output_stream)) = streams else {
+        log_warn!("Audio devices don't support voice chat");
+        return;
+    };
+    if let Err(err) = input_stream.play().and(output_stream.play()) {
+        log_error!("Failed to start voice chat audio: {}", err);
+        return;
+    }
+
+    let mut encoder = Encoder::new(SAMPLE_RATE, Channels::Mono, Application::Voip).unwrap();
+    let mut samples: Vec<f32> = Vec::new();
+    let mut packet = vec![0; MAX_PACKET_SIZE];
+    while running.load(Ordering::Relaxed) {
+        if let Ok(mut received) = sample_receiver.recv_timeout(Duration::from_millis(20)) {
+            samples.append(&mut received);
+        }
+        while samples.len() >= FRAME_SAMPLES {
+            let frame: Vec<f32> = samples.drain(..FRAME_SAMPLES).collect();
+            match encoder.encode_float(&frame, &mut packet) {
+                Ok(len) => {
+                    captured.send(packet[..len].to_vec()).ok();
+                }
+                Err(err) => {
+                    log_warn!("Failed to encode voice: {}", err);
+                }
+            }
+        }
+    }
+}
+
+/// Captures mono audio from the device, in whichever sample format and rate it supports.
+fn build_input_stream(device: &Device, samples: Sender<Vec<f32>>) -> Option<Stream> {
+    let config = choose_config(device.default_input_config(), device.supported_input_configs())?;
+    match config.sample_format() {
+        SampleFormat::I8 => build_typed_input_stream::<i8>(device, &config.config(), samples),
+        SampleFormat::I16 => build_typed_input_stream::<i16>(device, &config.config(), samples),
+        SampleFormat::I32 => build_typed_input_stream::<i32>(device, &config.config(), samples),
+        SampleFormat::I64 => build_typed_input_stream::<i64>(device, &config.config(), samples),
+        SampleFormat::U8 => build_typed_input_stream::<u8>(device, &config.config(), samples),
+        SampleFormat::U16 => build_typed_input_stream::<u16>(device, &config.config(), samples),
+        SampleFormat::U32 => build_typed_input_stream::<u32>(device, &config.config(), samples),
+        SampleFormat::U64 => build_typed_input_stream::<u64>(device, &config.config(), samples),
+        SampleFormat::F32 => build_typed_input_stream::<f32>(device, &config.config(), samples),
+        SampleFormat::F64 => build_typed_input_stream::<f64>(device, &config.config(), samples),
+        format => {
+            log_warn!("Unsupported sample format for voice capture: {}", format);
+            None
+        }
+    }
+}
+
+/// Plays the queued mono audio on every channel of the device, in whichever sample format and rate it supports.
+fn build_output_stream(device: &Device, playback: Arc<Mutex<VecDeque<f32>>>) -> Option<Stream> {
+    let config = choose_config(device.default_output_config(), device.supported_output_configs())?;
+    match config.sample_format() {
+        SampleFormat::I8 => build_typed_output_stream::<i8>(device, &config.config(), playback),
+        SampleFormat::I16 => build_typed_output_stream::<i16>(device, &config.config(), playback),
+        SampleFormat::I32 => build_typed_output_stream::<i32>(device, &config.config(), playback),
+        SampleFormat::I64 => build_typed_output_stream::<i64>(device, &config.config(), playback),
+        SampleFormat::U8 => build_typed_output_stream::<u8>(device, &config.config(), playback),
+        SampleFormat::U16 => build_typed_output_stream::<u16>(device, &config.config(), playback),
+        SampleFormat::U32 => build_typed_output_stream::<u32>(device, &config.config(), playback),
+        SampleFormat::U64 => build_typed_output_stream::<u64>(device, &config.config(), playback),
+        SampleFormat::F32 => build_typed_output_stream::<f32>(device, &config.config(), playback),
+        SampleFormat::F64 => build_typed_output_stream::<f64>(device, &config.config(), playback),
+        format => {
+            log_warn!("Unsupported sample format for voice playback: {}", format);
+            None
+        }
+    }
+}
+
+/// The default config of the device, switched to the rate voice is encoded at if the device supports it, so that
+/// audio doesn't have to be resampled.
+fn choose_config(
+    default: Result<SupportedStreamConfig, DefaultStreamConfigError>,
+    supported: Result<impl Iterator<Item = SupportedStreamConfigRange>, SupportedStreamConfigsError>,
+) -> Option<SupportedStreamConfig> {
+    let default = default.ok()?;
+    let at_encoded_rate = supported
+        .into_iter()
+        .flatten()
+        .filter(|range| range.channels() == default.channels() && range.sample_format() == default.sample_format())
+        .find_map(|range| range.try_with_sample_rate(SampleRate(SAMPLE_RATE)));
+    Some(at_encoded_rate.unwrap_or(default))
+}
+
+/// Multiple channels are averaged into mono.
+fn build_typed_input_stream<T>(device: &Device, config: &StreamConfig, samples: Sender<Vec<f32>>) -> Option<Stream>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    let channels = config.channels as usize;
+    let mut resampler = Resampler::new(config.sample_rate.0, SAMPLE_RATE);
+    device
+        .build_input_stream(
+            config,
+            move |data: &[T], _| {
+                let mono: Vec<f32> = data
+                    .chunks(channels)
+                    .map(|frame| frame.iter().map(|sample| sample.to_sample::<f32>()).sum::<f32>() / channels as f32)
+                    .collect();
+                samples.send(resampler.push(&mono)).ok();
+            },
+            |err| {
+                log_error!("Voice capture failed: {}", err);
+            },
+            None,
+        )
+        .ok()
+}
+
+/// Silence is played if nothing is queued.
+fn build_typed_output_stream<T>(
+    device: &Device,
+    config: &StreamConfig,
+    playback: Arc<Mutex<VecDeque<f32>>>,
+) -> Option<Stream>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let channels = config.channels as usize;
+    let mut resampler = Resampler::new(SAMPLE_RATE, config.sample_rate.0);
+    device
+        .build_output_stream(
+            config,
+            move |data: &mut [T], _| {
+                let mut playback = playback.lock().unwrap();
+                for frame in data.chunks_mut(channels) {
+                    let sample = resampler.pull(|| playback.pop_front().unwrap_or(0.0));
+                    frame.fill(T::from_sample(sample));
+                }
+            },
+            |err| {
+                log_error!("Voice playback failed: {}", err);
+            },
+            None,
+        )
+        .ok()
+}
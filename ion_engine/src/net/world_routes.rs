@@ -0,0 +1,109 @@
+use std::collections::HashSet;
+
+use ion_common::{Map, PlayerId};
+
+use crate::core::world::WorldId;
+
+// ---------------------------------------------------------- //
+// ---------------------- World routes ---------------------- //
+// ---------------------------------------------------------- //
+
+/// Worlds each player occupies or observes, which are the only worlds the server syncs to the player.
+/// Players without a route get all worlds.
+///
+/// Changes are queued and applied by the server at the start of a frame, so that a frame is never sent to a player
+/// with only part of a change applied.
+#[derive(Debug, Default)]
+pub(super) struct WorldRoutes {
+    routes: Map<PlayerId, HashSet<WorldId>>,
+    pending: Vec<(PlayerId, Option<HashSet<WorldId>>)>,
+}
+
+impl WorldRoutes {
+    /// Queues a change of the worlds of the player. None routes all worlds to the player.
+    pub(super) fn set(&mut self, player_id: PlayerId, worlds: Option<HashSet<WorldId>>) {
+        self.pending.push((player_id, worlds));
+    }
+
+    /// Applies the queued changes. Returns the players whose routes changed, with the worlds added to them.
+    pub(super) fn apply_pending(&mut self, all_worlds: &[WorldId]) -> Vec<(PlayerId, Vec<WorldId>)> {
+        let mut changed: Vec<(PlayerId, Vec<WorldId>)> = Vec::new();
+        for (player_id, worlds) in std::mem::take(&mut self.pending) {
+            let added = all_worlds
+                .iter()
+                .filter(|world_id| {
+                    !self.is_routed(player_id, **world_id)
+                        && worlds.as_ref().is_none_or(|worlds| worlds.contains(world_id))
+                })
+                .copied();
+            match changed.iter_mut().find(|(changed_id, _)| *changed_id == player_id) {
+                Some((_, changed_added)) => changed_added.extend(added),
+                None => changed.push((player_id, added.collect())),
+            }
+            match worlds {
+                Some(worlds) => self.routes.insert(player_id, worlds),
+                None => self.routes.remove(&player_id),
+            };
+        }
+        for (player_id, added) in &mut changed {
+            added.retain(|world_id| self.is_routed(*player_id, *world_id));
+        }
+        changed
+    }
+
+    pub(super) fn is_routed(&self, player_id: PlayerId, world_id: WorldId) -> bool {
+        self.routes
+            .get(&player_id)
+            .is_none_or(|worlds| worlds.contains(&world_id))
+    }
+
+    /// Leaves out the worlds that are not routed to the player.
+    pub(super) fn filter<T: Clone>(&self, player_id: PlayerId, worlds: &Map<WorldId, T>) -> Map<WorldId, T> {
+        worlds
+            .iter()
+            .filter(|(world_id, _)| self.is_routed(player_id, **world_id))
+            .map(|(world_id, value)| (*world_id, value.clone()))
+            .collect()
+    }
+
+    pub(super) fn remove(&mut self, player_id: PlayerId) {
+        self.routes.remove(&player_id);
+        self.pending.retain(|(pending_id, _)| *pending_id != player_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes_change_at_the_start_of_a_frame() {
+        let all_worlds = [1, 2, 3];
+        let mut routes = WorldRoutes::default();
+        assert!(routes.is_routed(7, 2));
+
+        routes.set(7, Some(HashSet::from([1])));
+        assert!(routes.is_routed(7, 2));
+        assert_eq!(routes.apply_pending(&all_worlds), vec![(7, vec![])]);
+        assert!(routes.is_routed(7, 1));
+        assert!(!routes.is_routed(7, 2));
+        assert!(routes.is_routed(8, 2));
+
+        let mut worlds = Map::default();
+        worlds.insert(1, "a");
+        worlds.insert(2, "b");
+        assert_eq!(routes.filter(7, &worlds).into_keys().collect::<Vec<_>>(), vec![1]);
+
+        // Only the worlds the player did not already have are added
+        routes.set(7, Some(HashSet::from([1, 3])));
+        routes.set(7, Some(HashSet::from([1, 2, 3])));
+        let mut changed = routes.apply_pending(&all_worlds);
+        changed[0].1.sort();
+        assert_eq!(changed, vec![(7, vec![2, 3])]);
+
+        routes.set(7, Some(HashSet::from([1])));
+        routes.remove(7);
+        assert!(routes.apply_pending(&all_worlds).is_empty());
+        assert!(routes.is_routed(7, 2));
+    }
+}
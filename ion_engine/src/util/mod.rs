@@ -1,6 +1,7 @@
 pub mod casting;
 pub mod concurrency;
 pub mod config;
+pub mod system_info;
 
 pub(crate) fn init_os() {
     #[cfg(target_os = "windows")]
@@ -8,6 +9,20 @@ pub(crate) fn init_os() {
         windows::Win32::Media::timeBeginPeriod(1);
     }
 
+    // Crash reports include the system info, so that rendering issues can be triaged from the log alone.
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let default_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if let Some(report) = system_info::crash_report_info() {
+                ion_common::log_error!("Crashed: {}\nSystem info:\n{}", info, report);
+                ion_common::flush_logs();
+                eprintln!("System info:\n{}", report);
+            }
+            default_hook(info);
+        }));
+    }
+
     #[cfg(target_arch = "wasm32")]
     {
         use ion_common::wasm_bindgen;
@@ -34,6 +49,11 @@ pub(crate) fn init_os() {
             let stack = e.stack();
             msg.push_str(&stack);
             msg.push_str("\n\n");
+            if let Some(report) = super::system_info::crash_report_info() {
+                msg.push_str("System info:\n\n");
+                msg.push_str(report);
+                msg.push_str("\n\n");
+            }
             error(msg);
         }
 
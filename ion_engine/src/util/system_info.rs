@@ -0,0 +1,109 @@
+use std::{fmt, sync::OnceLock};
+
+/// Report of the latest collected system info, included in crash reports.
+static SYSTEM_REPORT: OnceLock<String> = OnceLock::new();
+
+// ---------------------------------------------------------- //
+// ---------------------- System info ----------------------- //
+// ---------------------------------------------------------- //
+
+/// Information about the graphics adapter and the host system.
+/// Collected once when the renderer is created and logged at startup.
+/// Useful mostly for triaging user-reported rendering issues.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SystemInfo {
+    pub adapter_name: String,
+    pub adapter_type: String,
+    pub backend: String,
+    pub driver: String,
+    pub driver_info: String,
+    /// Operating system and cpu architecture, for example `linux x86_64`.
+    pub os: String,
+    /// Number of logical cpu cores. `None` if it can't be queried.
+    pub cpu_cores: Option<usize>,
+    /// Total physical memory in bytes. `None` if it can't be queried (non-unix platforms).
+    pub memory_bytes: Option<u64>,
+}
+
+impl SystemInfo {
+    pub(crate) fn collect(adapter_info: &wgpu::AdapterInfo) -> Self {
+        Self {
+            adapter_name: adapter_info.name.clone(),
+            adapter_type: format!("{:?}", adapter_info.device_type),
+            backend: adapter_info.backend.to_string(),
+            driver: adapter_info.driver.clone(),
+            driver_info: adapter_info.driver_info.clone(),
+            os: format!("{} {}", std::env::consts::OS, std::env::consts::ARCH),
+            cpu_cores: std::thread::available_parallelism().ok().map(|cores| cores.get()),
+            memory_bytes: Self::total_memory(),
+        }
+    }
+
+    /// Stores the report so that it is included in crash reports.
+    pub(crate) fn set_as_crash_report_info(&self) {
+        let _ = SYSTEM_REPORT.set(self.to_string());
+    }
+
+    #[cfg(unix)]
+    fn total_memory() -> Option<u64> {
+        let (pages, page_size) = unsafe { (libc::sysconf(libc::_SC_PHYS_PAGES), libc::sysconf(libc::_SC_PAGESIZE)) };
+        (pages > 0 && page_size > 0).then(|| pages as u64 * page_size as u64)
+    }
+
+    #[cfg(not(unix))]
+    fn total_memory() -> Option<u64> {
+        None
+    }
+}
+
+impl fmt::Display for SystemInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unknown = || "unknown".to_string();
+        writeln!(f, "Adapter: {} ({})", self.adapter_name, self.adapter_type)?;
+        writeln!(f, "Backend: {}", self.backend)?;
+        writeln!(f, "Driver: {} {}", self.driver, self.driver_info)?;
+        writeln!(f, "OS: {}", self.os)?;
+        writeln!(
+            f,
+            "CPU cores: {}",
+            self.cpu_cores.map(|cores| cores.to_string()).unwrap_or_else(unknown)
+        )?;
+        write!(
+            f,
+            "Memory: {}",
+            self.memory_bytes
+                .map(|bytes| format!("{} MiB", bytes / (1024 * 1024)))
+                .unwrap_or_else(unknown)
+        )
+    }
+}
+
+/// Returns the system report for crash reports, if the renderer has been created.
+pub(crate) fn crash_report_info() -> Option<&'static str> {
+    SYSTEM_REPORT.get().map(|report| report.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_contains_all_fields() {
+        let info = SystemInfo {
+            adapter_name: "Test GPU".to_string(),
+            adapter_type: "DiscreteGpu".to_string(),
+            backend: "vulkan".to_string(),
+            driver: "test".to_string(),
+            driver_info: "1.2.3".to_string(),
+            os: "linux x86_64".to_string(),
+            cpu_cores: Some(8),
+            memory_bytes: None,
+        };
+
+        let report = info.to_string();
+        assert!(report.contains("Adapter: Test GPU (DiscreteGpu)"));
+        assert!(report.contains("Driver: test 1.2.3"));
+        assert!(report.contains("CPU cores: 8"));
+        assert!(report.ends_with("Memory: unknown"));
+    }
+}
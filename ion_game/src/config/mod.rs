@@ -1,24 +1,52 @@
 use ion_engine::{
-    core::{Constants, GfxConstants},
-    gfx::gfx_config::{GfxConfig, Resolution, VsyncOpts, WindowMode},
+    core::{AutosaveConstants, Constants, GfxConstants, SaveConstants},
+    gfx::{
+        CameraZoom,
+        gfx_config::{
+            AnisotropyOpts, ColorFilterOpts, DisplayAdjustments, GfxAdapterSelection, GfxBackend, GfxConfig, MsaaOpts,
+            Resolution, SupersamplingOpts, VsyncOpts, WindowMode,
+        },
+    },
+    util::config::ConfigFormat,
 };
 use std::path::PathBuf;
+use std::time::Duration;
 
 use crate::state::Props;
 
 pub mod bindings;
 
+// Bumped whenever the saved universe or worlds change, with a migration from the previous version
+const SAVE_VERSION: u32 = 0;
+
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+// Changed files are only watched in debug builds
+const FILE_WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
 pub fn constants() -> Constants {
     Constants {
         app_name: "ION",
         gfx: GfxConstants {
             asset_path: PathBuf::from("ion_game/assets/textures"),
+            asset_archives: Vec::new(),
             camera_angle_deg: 28.995,
             pixels_per_unit: 128.0,
             height_units_total: 8.0,
             height_scaled_zero: 64.0 / 255.0,
+            backend: GfxBackend::Auto,
+            adapter: GfxAdapterSelection::Auto,
         },
         net: None,
+        saves: SaveConstants::new(SAVE_VERSION),
+        config_format: ConfigFormat::Ion,
+        storage: None,
+        autosave: Some(AutosaveConstants {
+            interval: AUTOSAVE_INTERVAL,
+            slots: 3,
+            save_name: "autosave",
+        }),
+        file_watch_interval: cfg!(debug_assertions).then_some(FILE_WATCH_INTERVAL),
     }
 }
 
@@ -28,12 +56,31 @@ pub fn splash_screen_gfx_config(props: &Props) -> GfxConfig {
         window_decorations: false,
         window_transparent: false,
         window_mode: WindowMode::Windowed,
+        fullscreen_monitor: None,
         frame_resolution: Resolution {
             width: (480.0 * dpi_factor) as u32,
             height: (240.0 * dpi_factor) as u32,
         },
+        supersampling: SupersamplingOpts::Off,
         frame_rate_cap: Some(60),
         vsync: VsyncOpts::Off,
+        lod_camera_scale: None,
+        baked_shadows: false,
+        reflections: false,
+        msaa: MsaaOpts::Off,
+        anisotropy: AnisotropyOpts::Off,
+        color_filter: ColorFilterOpts::Off,
+        display: DisplayAdjustments::default(),
+    }
+}
+
+pub fn default_camera_zoom() -> CameraZoom {
+    CameraZoom {
+        min_scale: 5.0,
+        max_scale: 50.0,
+        scroll_step: 1.2,
+        easing: 15.0,
+        zoom_to_cursor: false,
     }
 }
 
@@ -42,11 +89,20 @@ pub fn default_gfx_config() -> GfxConfig {
         window_decorations: true,
         window_transparent: false,
         window_mode: WindowMode::Windowed,
+        fullscreen_monitor: None,
         frame_resolution: Resolution {
             width: 1920,
             height: 1080,
         },
+        supersampling: SupersamplingOpts::Off,
         frame_rate_cap: Some(120),
         vsync: VsyncOpts::On,
+        lod_camera_scale: Some(40.0),
+        baked_shadows: true,
+        reflections: false,
+        msaa: MsaaOpts::Off,
+        anisotropy: AnisotropyOpts::X4,
+        color_filter: ColorFilterOpts::Off,
+        display: DisplayAdjustments::default(),
     }
 }
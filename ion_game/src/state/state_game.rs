@@ -1,7 +1,7 @@
 use std::sync::atomic::Ordering;
 
 use ion_common::log_info;
-use ion_engine::{KeyCode, core::application::ApplicationEvent};
+use ion_engine::{KeyCode, core::application::ApplicationEvent, files::FileTask};
 
 use crate::{
     state::{GlobalState, Props},
@@ -17,6 +17,8 @@ pub struct GameState {
     pub show_debug_ui_lighting: bool,
 
     pub is_paused: bool,
+    /// Save being written in the background.
+    pub save_task: Option<FileTask<()>>,
 }
 
 impl GameState {
@@ -26,6 +28,7 @@ impl GameState {
             show_debug_ui_lighting: false,
 
             is_paused: false,
+            save_task: None,
         }
     }
 
@@ -47,6 +50,11 @@ impl GameState {
                 ApplicationEvent::Suspended => {
                     log_info!("App suspended");
                 }
+                // Already logged by the engine
+                ApplicationEvent::AutosaveCompleted { .. }
+                | ApplicationEvent::AutosaveFailed { .. }
+                | ApplicationEvent::FileChanged(_)
+                | ApplicationEvent::SaveSynced { .. } => {}
             }
         }
 
@@ -2,7 +2,7 @@ use std::sync::atomic::Ordering;
 
 use crate::{
     assets::texture_assets,
-    config::{default_gfx_config, splash_screen_gfx_config},
+    config::{default_camera_zoom, default_gfx_config, splash_screen_gfx_config},
     ui::ui_init::draw_ui_init_screen,
     universe::creator::{UniverseParams, create_universe},
 };
@@ -19,7 +19,9 @@ pub struct InitState {}
 
 impl InitState {
     pub fn new(props: &mut Props) -> Self {
-        props.renderer.load_texture_assets(texture_assets());
+        let mut texture_assets = texture_assets();
+        texture_assets.include_mods(props.files);
+        props.renderer.load_texture_assets(texture_assets);
         props.renderer.set_config(splash_screen_gfx_config(props));
         #[cfg(target_arch = "wasm32")]
         props
@@ -47,6 +49,11 @@ impl InitState {
                 ApplicationEvent::Suspended => {
                     log_info!("App suspended");
                 }
+                // Already logged by the engine
+                ApplicationEvent::AutosaveCompleted { .. }
+                | ApplicationEvent::AutosaveFailed { .. }
+                | ApplicationEvent::FileChanged(_)
+                | ApplicationEvent::SaveSynced { .. } => {}
             }
         }
 
@@ -59,6 +66,7 @@ impl InitState {
             // Normally main menu, for testing we go straight to game state.
 
             props.renderer.set_config(default_gfx_config());
+            props.renderer.set_camera_zoom(default_camera_zoom());
             #[cfg(target_arch = "wasm32")]
             props
                 .renderer
@@ -72,6 +80,7 @@ impl InitState {
                     id: 0,
                     name: "player_main".to_string(),
                     addr: "127.0.0.1:0".parse().unwrap(),
+                    identity: None,
                 }),
             };
             let (universe_data, worlds) = create_universe(universe_params);
@@ -32,6 +32,11 @@ impl MenuState {
                 ApplicationEvent::Suspended => {
                     log_info!("App suspended");
                 }
+                // Already logged by the engine
+                ApplicationEvent::AutosaveCompleted { .. }
+                | ApplicationEvent::AutosaveFailed { .. }
+                | ApplicationEvent::FileChanged(_)
+                | ApplicationEvent::SaveSynced { .. } => {}
             }
         }
 
@@ -40,6 +40,10 @@ pub fn draw_ui_debug_main(props: &mut Props, state: &mut GameState) {
             ));
         }
 
+        ui.collapsing("System", |ui| {
+            ui.label(props.renderer.system_info().to_string());
+        });
+
         ui.add_space(10.0);
 
         if ui.button("Toggle chunk borders").clicked() {
@@ -1,6 +1,6 @@
 use std::sync::atomic::Ordering;
 
-use ion_common::net::NetworkPlayerInfo;
+use ion_common::{log_error, log_info, net::NetworkPlayerInfo};
 use ion_engine::{
     core::{universe::UniverseDataType, world::WorldType},
     egui::{self, Align2},
@@ -24,39 +24,38 @@ pub fn draw_ui_pause(props: &mut Props, state: &mut GameState) {
                     props.universe.unpause();
                 }
 
-                if ui.button("Save").clicked() {
-                    let save_name = "test";
-                    // Acquire unique access to the universe, this also locks the universe thread.
-                    let universe = props.universe.lock_universe_data();
-                    let worlds = props.universe.lock_worlds_data();
-
-                    let mut save_files_bytes: Vec<_> = worlds
-                        .values()
-                        .map(|world| (world.name.clone(), world.as_bytes()))
-                        .collect();
-
-                    save_files_bytes.push((
-                        "universe".to_string(),
-                        universe
-                            .as_ref()
-                            .expect("Universe must exist when saving")
-                            .as_bytes(&worlds),
-                    ));
+                if let Some(result) = state.save_task.as_ref().and_then(|task| task.try_join()) {
+                    state.save_task = None;
+                    match result {
+                        Ok(()) => {
+                            log_info!("Save written");
+                        }
+                        Err(err) => {
+                            log_error!("Exporting save failed: {}", err);
+                        }
+                    }
+                }
 
-                    props
-                        .files
-                        .export_save(save_name, save_files_bytes)
-                        .expect("Exporting save must succeed");
+                if state.save_task.is_some() {
+                    ui.label("Saving...");
+                } else if ui.button("Save").clicked() {
+                    let save_name = "test";
+                    state.save_task = Some(props.universe.export_save_async(props.files, save_name));
                 }
 
                 if ui.button("Load").clicked() {
                     let save_name = "test";
+                    // The save being written would be read half done
+                    if let Some(Err(err)) = state.save_task.take().map(|task| task.join()) {
+                        log_error!("Exporting save failed: {}", err);
+                    }
                     let mut save_files_bytes = props.files.import_save(save_name).expect("Importing save must succeed");
 
                     let player = NetworkPlayerInfo {
                         id: 0,
                         name: "player_main".to_string(),
                         addr: "127.0.0.1:0".parse().unwrap(),
+                        identity: None,
                     };
 
                     let universe = UniverseData::from_bytes(
@@ -20,9 +20,14 @@ pub fn build_stateless_actions(input: &InputState<Command>, is_active_world: boo
     let mut actions = Vec::new();
 
     if is_active_world && input.mouse_scroll_delta() != 0.0 {
-        actions.push(Action::SetCameraScale(
-            (world.camera.scale + input.mouse_scroll_delta() * 3.0).max(0.02),
-        ))
+        // Camera follows the player, so only the scale is used
+        let (_, scale) = world.camera.zoom.scroll(
+            world.camera.loc,
+            world.camera.scale,
+            input.mouse_scroll_delta(),
+            input.cursor_location(),
+        );
+        actions.push(Action::SetCameraScale(scale))
     }
 
     actions
@@ -9,7 +9,7 @@ use ion_engine::core::coordinates::Direction;
 pub fn process_action(_props: &UniverseFrameProps<World>, player_id: PlayerId, action: &Action, world: &mut World) {
     match action {
         Action::SetCameraScale(scale) => {
-            world.camera.scale = (*scale).min(world.camera.max_scale).max(world.camera.min_scale);
+            world.camera.scale = world.camera.zoom.clamp_scale(*scale);
         }
         action @ Action::Move { .. } => {
             Movement::handle_player_movement_action(action, world);
@@ -1,13 +1,13 @@
 use ion_engine::core::coordinates::{ChunkLocation, Location};
+use ion_engine::gfx::CameraZoom;
 
+use crate::config::default_camera_zoom;
 use crate::universe::chunk::Chunks;
 
 pub struct Camera {
     pub loc: Location,
     pub scale: f32,
-
-    pub max_scale: f32,
-    pub min_scale: f32,
+    pub zoom: CameraZoom,
 }
 
 impl Camera {
@@ -25,9 +25,7 @@ impl Default for Camera {
         Self {
             loc: Location { x: 0.0, y: 0.0 },
             scale: 10.0,
-
-            max_scale: 50.0,
-            min_scale: 5.0,
+            zoom: default_camera_zoom(),
         }
     }
 }
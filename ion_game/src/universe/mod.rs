@@ -5,11 +5,13 @@ use ion_common::DateTime;
 use ion_common::Map;
 use ion_common::bincode::config::Configuration;
 use ion_common::bincode::{Decode, Encode};
+use ion_common::log_info;
 use ion_common::net::{NetworkPlayerInfo, NetworkServerInfo};
 
 use crate::APP_VERSION;
 use ion_engine::core::universe::UniverseDataType;
 use ion_engine::core::world::WorldId;
+use ion_engine::files::save_info::SaveMetadata;
 
 use crate::universe::players::{UniversePlayers, UniversePlayersSaveState};
 use crate::universe::world::World;
@@ -68,6 +70,14 @@ impl UniverseDataType for UniverseData {
         let save_state = UniverseSaveState::from_actual(self, worlds);
         bincode::encode_to_vec(save_state, bincode::config::standard()).unwrap()
     }
+
+    fn save_metadata(&self) -> SaveMetadata {
+        SaveMetadata {
+            playtime: self.stats.total_play_time(),
+            app_version: self.app_version.to_string(),
+            thumbnail: None,
+        }
+    }
 }
 
 // ---------------------------------------------------------- //
@@ -81,6 +91,16 @@ pub struct UniverseStats {
     pub last_played: DateTime,
 }
 
+impl UniverseStats {
+    /// Play time of earlier sessions and the current one.
+    pub fn total_play_time(&self) -> Duration {
+        let session = DateTime::now()
+            .duration_since(self.last_start)
+            .unwrap_or(Duration::ZERO);
+        self.play_time + session
+    }
+}
+
 impl Default for UniverseStats {
     fn default() -> Self {
         Self {
@@ -106,7 +126,10 @@ struct UniverseSaveState {
 
 impl UniverseSaveState {
     fn into_actual(self, server: Option<NetworkServerInfo>, player: Option<NetworkPlayerInfo>) -> UniverseData {
-        assert_eq!(APP_VERSION, self.app_version, "Save version and app version must match");
+        // Saves of older formats are migrated by the engine before they get here, see `SaveConstants`
+        if self.app_version != APP_VERSION {
+            log_info!("Loading universe saved by version {}", self.app_version);
+        }
         let stats = UniverseStats {
             play_time: self.stats.play_time,
             last_start: DateTime::now(),
@@ -124,11 +147,8 @@ impl UniverseSaveState {
         }
     }
     fn from_actual(actual: &UniverseData, worlds: &MutexGuard<Map<WorldId, World>>) -> Self {
-        let session = DateTime::now()
-            .duration_since(actual.stats.last_start)
-            .unwrap_or(Duration::ZERO);
         let stats = UniverseStats {
-            play_time: actual.stats.play_time + session,
+            play_time: actual.stats.total_play_time(),
             last_start: actual.stats.last_start,
             last_played: DateTime::now(),
         };
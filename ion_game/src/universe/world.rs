@@ -10,6 +10,7 @@ use ion_engine::core::FrameId;
 use ion_engine::core::UniverseFrameProps;
 use ion_engine::core::coordinates::ChunkLocation;
 use ion_engine::core::world::{WorldId, WorldType};
+use ion_engine::gfx::CameraFollow;
 use ion_engine::gfx::GfxDebugData;
 use ion_engine::gfx::GfxGlobalData;
 use ion_engine::gfx::GfxSpriteData;
@@ -187,6 +188,7 @@ impl WorldType for World {
             frame,
             camera_loc: self.camera.loc,
             camera_scale: self.camera.scale,
+            camera_follow: CameraFollow::Lock,
             lighting_ambient: self.lighting.ambient,
             lighting_sun: self.lighting.sun,
             post_bloom: 0.2,
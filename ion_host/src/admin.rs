@@ -0,0 +1,59 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use ion_common::net::udp_network_socket::UdpNetworkSocket;
+use ion_common::net::{HostAdminCommand, SysMessage, UdpMessage};
+
+/// Sends admin commands to running host services, which accept them when started with an admin password, see
+/// [`Config::admin_password`](crate::config::Config::admin_password).
+///
+/// The commands go over a session that the host authenticates with its network key, so that nobody in the middle can
+/// read the password. The key is the one the host logs when it starts and reports in its status, and stays the same
+/// across restarts with [`Config::network_key_file`](crate::config::Config::network_key_file).
+pub struct HostAdminClient {
+    udp_socket: UdpNetworkSocket<UdpMessage<()>>,
+    host_addr: SocketAddr,
+    token: Vec<u8>,
+}
+
+impl HostAdminClient {
+    pub fn new(
+        bind_addr: SocketAddr,
+        host_addr: SocketAddr,
+        host_key: [u8; 32],
+        password: &str,
+    ) -> Self {
+        let udp_socket = UdpNetworkSocket::new_encrypted(bind_addr);
+        // Pinned, as the password is sent with every command
+        udp_socket.pin_key(host_addr, host_key);
+        Self {
+            udp_socket,
+            host_addr,
+            token: password.as_bytes().to_vec(),
+        }
+    }
+
+    /// Sends the command and waits for the host to reply. Returns the reply, or why the command failed.
+    pub fn execute(&self, command: HostAdminCommand, timeout: Duration) -> Result<String, String> {
+        let msg = SysMessage::HostAdminReq {
+            token: self.token.clone(),
+            command,
+        };
+        self.udp_socket
+            .send(self.host_addr, UdpMessage::SysMessage(msg), timeout);
+
+        let deadline = Instant::now() + timeout;
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            match self.udp_socket.try_recv_timeout(remaining) {
+                Some((from_addr, UdpMessage::SysMessage(SysMessage::HostAdminRes { result })))
+                    if from_addr == self.host_addr =>
+                {
+                    return result;
+                }
+                Some(_) => {}
+                None => break,
+            }
+        }
+        Err("No response from host".to_owned())
+    }
+}
@@ -1,33 +1,322 @@
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
-const SERVER_PING_TIMEOUT: Duration = Duration::from_secs(60);
+use derive_engine::Config;
+use ion_common::LogLevel;
+use ion_common::net::tls::TlsCertificate;
+use ion_common::util::config::{Config as _, config_update_from_string};
+
+// Variables with this prefix override the field of the same name, for example `ION_HOST_ADMIN_PASSWORD`
+const ENV_PREFIX: &str = "ION_HOST_";
+
+const PORT: u16 = 3333;
+const SERVER_PING_TIMEOUT: Duration = Duration::from_secs(30);
 const NAT_PUNCH_RELAY_TIMEOUT: Duration = Duration::from_secs(20);
 const SOCKET_INFO_RESP_TIMEOUT: Duration = Duration::from_secs(20);
 const SERVER_LIST_RESP_TIMOUT: Duration = Duration::from_secs(20);
+const MAX_REQUESTS_PER_SEC: u32 = 20;
+const MAX_REQUEST_BURST: u32 = 50;
+const MAX_FRAMES_PER_SEC: u32 = 500;
+const MAX_FRAME_BURST: u32 = 1000;
+const MAX_SERVERS_PER_IP: usize = 8;
+const MATCH_QUEUE_TIMEOUT: Duration = Duration::from_secs(120);
+const RELAY_MAX_SESSIONS: usize = 256;
+const RELAY_MAX_SESSIONS_PER_IP: usize = 8;
+const RELAY_BYTES_PER_SEC: u32 = 64 * 1024;
+const RELAY_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+const RELAY_PENDING_TIMEOUT: Duration = Duration::from_secs(10);
+const FEDERATION_SYNC_INTERVAL: Duration = Duration::from_secs(5);
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+const LOG_LEVEL: LogLevel = LogLevel::Debug;
 
-#[derive(Debug, Clone, Copy)]
+/// Configuration of the host services, see [`Config::load`].
+///
+/// The config file uses the format of [`ion_common::util::config`], with the field names as keys:
+///
+/// ```text
+/// port = 3333
+/// max_requests_per_sec = 20
+/// stats_file = "/var/lib/ion_host/stats"
+/// network_key_file = "/var/lib/ion_host/network_key"
+/// region = "eu"
+/// region_file = "/etc/ion_host/regions.csv"
+/// federation_peers = [203.0.113.1:3333,198.51.100.1:3333]
+/// status_port = 8080
+/// status_ip = 127.0.0.1
+/// tls_cert_file = "/etc/letsencrypt/live/host.example.com/fullchain.pem"
+/// tls_key_file = "/etc/letsencrypt/live/host.example.com/privkey.pem"
+/// log_level = "Info"
+/// ```
+#[derive(Debug, Clone, Config)]
 pub struct Config {
     pub port: u16,
+    /// Servers that haven't posted their info or sent a heartbeat for this long are removed from the server list.
     pub server_ping_timeout: Duration,
     pub nat_punch_relay_timeout: Duration,
     pub socket_info_resp_timeout: Duration,
     pub server_list_resp_timeout: Duration,
+    /// Requests accepted from each IP address per second, on average.
+    pub max_requests_per_sec: u32,
+    /// Requests accepted from each IP address at once, after it has been quiet for a while.
+    pub max_request_burst: u32,
+    /// Frames the socket takes from each IP address per second, on average, before it acks or answers them. Relayed
+    /// game traffic counts too, so this is far above [`Config::max_requests_per_sec`].
+    pub max_frames_per_sec: u32,
+    /// Frames the socket takes from each IP address at once, after it has been quiet for a while.
+    pub max_frame_burst: u32,
+    /// Servers that can be registered to the server list from each IP address.
+    pub max_servers_per_ip: usize,
+    /// Players are dropped from the matchmaking queue after this long, unless they request a match again.
+    pub match_queue_timeout: Duration,
+    /// Traffic relay sessions open at once, further ones are refused.
+    pub relay_max_sessions: usize,
+    /// Relay sessions each IP address can be in at once, counting the ones it has requested and the other peer hasn't
+    /// agreed to yet.
+    pub relay_max_sessions_per_ip: usize,
+    /// Bytes each relay session may forward per second, on average.
+    pub relay_bytes_per_sec: u32,
+    /// Relay sessions that haven't forwarded anything for this long are closed.
+    pub relay_idle_timeout: Duration,
+    /// Relay requests the other peer hasn't agreed to within this long are dropped.
+    pub relay_pending_timeout: Duration,
+    /// Password for the admin commands. Admin commands are denied without one.
+    pub admin_password: Option<String>,
+    /// File the private key of the host is kept in across restarts, created if missing. Admin clients pin the public
+    /// key, which the host logs when it starts and reports in its status. The key changes with every start without one.
+    pub network_key_file: Option<PathBuf>,
+    /// Key the game servers sign stat submissions with. Stats are ignored without one.
+    pub stats_key: Option<String>,
+    /// File the stats are kept in across restarts.
+    pub stats_file: Option<PathBuf>,
+    /// Region the host serves. Reported to clients and set on the servers registered here, unless
+    /// [`Config::region_file`] has a region for them.
+    pub region: Option<String>,
+    /// CSV file of `network,region` rows, such as `203.0.113.0/24,eu`, that servers are tagged with by their address.
+    /// See [`RegionMap`](crate::services::region_map::RegionMap) for the format.
+    pub region_file: Option<PathBuf>,
+    /// Other hosts the server list is shared with. Each host lists the servers of its peers too, so that clients can
+    /// use the nearest host and still see them all.
+    pub federation_peers: Vec<SocketAddr>,
+    /// How often the servers registered here are sent to the peers.
+    pub federation_sync_interval: Duration,
+    /// Secret shared by the hosts of [`Config::federation_peers`], that they sign their messages to each other with.
+    /// Peers are ignored without one.
+    pub federation_secret: Option<String>,
+    /// How long the host keeps running after a shutdown signal, without accepting new registrations.
+    pub drain_timeout: Duration,
+    /// TCP port of the HTTP status endpoint for load balancers and uptime monitors, see
+    /// [`ServiceStatus`](crate::services::service_status::ServiceStatus). Disabled without one.
+    pub status_port: Option<u16>,
+    /// IP address the status endpoint listens on, such as `127.0.0.1` to keep it private to the machine. Listens on
+    /// all addresses without one.
+    pub status_ip: Option<IpAddr>,
+    /// PEM certificate chain the status endpoint serves https with, together with [`Config::tls_key_file`]. The
+    /// endpoint is plain HTTP without them.
+    pub tls_cert_file: Option<PathBuf>,
+    /// PEM private key of [`Config::tls_cert_file`].
+    pub tls_key_file: Option<PathBuf>,
+    pub log_level: LogLevel,
 }
 
 impl Default for Config {
     fn default() -> Self {
-        let port = std::env::args()
-            .nth(1)
-            .map(|arg| arg.parse())
-            .expect("UDP port must be given as an argument")
-            .expect("Given argument must be a valid port");
-
         Self {
-            port,
+            port: PORT,
             server_ping_timeout: SERVER_PING_TIMEOUT,
             nat_punch_relay_timeout: NAT_PUNCH_RELAY_TIMEOUT,
             socket_info_resp_timeout: SOCKET_INFO_RESP_TIMEOUT,
             server_list_resp_timeout: SERVER_LIST_RESP_TIMOUT,
+            max_requests_per_sec: MAX_REQUESTS_PER_SEC,
+            max_request_burst: MAX_REQUEST_BURST,
+            max_frames_per_sec: MAX_FRAMES_PER_SEC,
+            max_frame_burst: MAX_FRAME_BURST,
+            max_servers_per_ip: MAX_SERVERS_PER_IP,
+            match_queue_timeout: MATCH_QUEUE_TIMEOUT,
+            relay_max_sessions: RELAY_MAX_SESSIONS,
+            relay_max_sessions_per_ip: RELAY_MAX_SESSIONS_PER_IP,
+            relay_bytes_per_sec: RELAY_BYTES_PER_SEC,
+            relay_idle_timeout: RELAY_IDLE_TIMEOUT,
+            relay_pending_timeout: RELAY_PENDING_TIMEOUT,
+            admin_password: None,
+            network_key_file: None,
+            stats_key: None,
+            stats_file: None,
+            region: None,
+            region_file: None,
+            federation_peers: Vec::new(),
+            federation_sync_interval: FEDERATION_SYNC_INTERVAL,
+            federation_secret: None,
+            drain_timeout: DRAIN_TIMEOUT,
+            status_port: None,
+            status_ip: None,
+            tls_cert_file: None,
+            tls_key_file: None,
+            log_level: LOG_LEVEL,
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config from the defaults, a config file, environment variables and command line arguments, each
+    /// overriding the previous.
+    ///
+    /// Arguments are `[port] [--config <file>] [--<field> <value>]...`. The config file can be given in `ION_HOST_CONFIG`
+    /// too, and other `ION_HOST_` variables override the field of the same name, such as `ION_HOST_ADMIN_PASSWORD`.
+    /// Values on the command line and in variables may leave out the quotes around strings and the brackets around lists.
+    pub fn load(
+        args: impl IntoIterator<Item = String>,
+        env: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, String> {
+        let mut overrides = Vec::new();
+        let mut config_file = None;
+
+        let mut args = args.into_iter().peekable();
+        if let Some(port) = args.next_if(|arg| !arg.starts_with("--")) {
+            overrides.push(("port".to_owned(), port));
+        }
+        while let Some(arg) = args.next() {
+            let Some(key) = arg.strip_prefix("--") else {
+                return Err(format!("Unexpected argument {:?}", arg));
+            };
+            let (key, value) = match key.split_once('=') {
+                Some((key, value)) => (key.to_owned(), value.to_owned()),
+                None => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| format!("Missing value for --{}", key))?;
+                    (key.to_owned(), value)
+                }
+            };
+            if key == "config" {
+                config_file = Some(PathBuf::from(value));
+            } else {
+                overrides.push((key.replace('-', "_"), value));
+            }
+        }
+
+        // Variables go before the arguments, so that the arguments override them
+        let mut env_overrides = Vec::new();
+        for (name, value) in env {
+            let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let key = key.to_lowercase();
+            if key == "config" {
+                config_file = config_file.or(Some(PathBuf::from(value)));
+            } else {
+                env_overrides.push((key, value));
+            }
+        }
+        env_overrides.extend(overrides);
+
+        let mut config = Self::default();
+        if let Some(config_file) = config_file {
+            config = config.with_file(&config_file)?;
+        }
+        for (key, value) in env_overrides {
+            config = config.with_override(&key, &value)?;
+        }
+        config.tls_certificate()?;
+        Ok(config)
+    }
+
+    /// The certificate of [`Config::tls_cert_file`] and [`Config::tls_key_file`], or none if TLS is off. Fails if only
+    /// one of them is set.
+    pub fn tls_certificate(&self) -> Result<Option<TlsCertificate>, String> {
+        match (&self.tls_cert_file, &self.tls_key_file) {
+            (Some(cert_file), Some(key_file)) => Ok(Some(TlsCertificate::new(cert_file, key_file))),
+            (None, None) => Ok(None),
+            _ => Err("TLS needs both tls_cert_file and tls_key_file".to_owned()),
         }
     }
+
+    fn with_file(&self, path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read config file {:?}: {}", path, err))?;
+        config_update_from_string(self, &text)
+            .map_err(|err| format!("Invalid config file {:?}: {:?}", path, err))
+    }
+
+    /// Sets the field to the value, trying it also as a string and as a list, as those are the usual omissions.
+    fn with_override(&self, key: &str, value: &str) -> Result<Self, String> {
+        let config = [
+            value.to_owned(),
+            format!("\"{}\"", value),
+            format!("[{}]", value),
+        ]
+        .iter()
+        .find_map(|value| config_update_from_string(self, &format!("{} = {}", key, value)).ok())
+        .ok_or_else(|| format!("Invalid value for {}: {:?}", key, value))?;
+
+        // Unknown keys are ignored by the decoding, but the field of a known one is set
+        let mut table = std::collections::BTreeMap::new();
+        config.encode_kv_table("", &mut table);
+        if !table.contains_key(key) {
+            return Err(format!("Unknown config field {}", key));
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn config_is_loaded_from_file_env_and_args() {
+        let config_file =
+            std::env::temp_dir().join(format!("ion_host_test_{}.conf", std::process::id()));
+        std::fs::write(
+            &config_file,
+            "port = 4000\nmax_requests_per_sec = 5\nregion = \"eu\"\nlog_level = \"Info\"\n",
+        )
+        .unwrap();
+
+        let env = vec![
+            ("ION_HOST_MAX_REQUESTS_PER_SEC".to_owned(), "7".to_owned()),
+            ("ION_HOST_ADMIN_PASSWORD".to_owned(), "secret".to_owned()),
+            (
+                "ION_HOST_FEDERATION_PEERS".to_owned(),
+                "10.0.0.1:3333,10.0.0.2:3333".to_owned(),
+            ),
+            ("OTHER".to_owned(), "ignored".to_owned()),
+        ];
+        let config = Config::load(
+            args(&[
+                "--config",
+                config_file.to_str().unwrap(),
+                "--max-requests-per-sec",
+                "9",
+            ]),
+            env,
+        )
+        .unwrap();
+        std::fs::remove_file(&config_file).unwrap();
+
+        assert_eq!(config.port, 4000);
+        assert_eq!(config.max_requests_per_sec, 9);
+        assert_eq!(config.region.as_deref(), Some("eu"));
+        assert_eq!(config.admin_password.as_deref(), Some("secret"));
+        assert_eq!(config.federation_peers.len(), 2);
+        assert_eq!(config.log_level, LogLevel::Info);
+        assert_eq!(config.max_request_burst, MAX_REQUEST_BURST);
+
+        let config = Config::load(args(&["3400", "--relay_idle_timeout=2.5"]), Vec::new()).unwrap();
+        assert_eq!(config.port, 3400);
+        assert_eq!(config.relay_idle_timeout, Duration::from_millis(2500));
+    }
+
+    #[test]
+    fn invalid_config_is_rejected() {
+        assert!(Config::load(args(&["--port", "not a port"]), Vec::new()).is_err());
+        assert!(Config::load(args(&["--no_such_field", "1"]), Vec::new()).is_err());
+        assert!(Config::load(args(&["--port"]), Vec::new()).is_err());
+        assert!(Config::load(args(&["3333", "4444"]), Vec::new()).is_err());
+        assert!(Config::load(args(&["--tls_cert_file", "/etc/cert.pem"]), Vec::new()).is_err());
+    }
 }
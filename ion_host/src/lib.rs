@@ -1,10 +1,15 @@
+use std::sync::atomic::AtomicBool;
+
 use crate::config::Config;
 use ion_common::log_info;
 
+pub mod admin;
 pub mod config;
 mod services;
+pub mod shutdown;
 
-pub fn run_ion_host(config: Config) -> ! {
+/// Runs the host services until the shutdown flag is set, see [`shutdown::register_shutdown_signals`].
+pub fn run_ion_host(config: Config, shutdown: &AtomicBool) {
     log_info!("Starting up host services");
-    services::run_services(config)
+    services::run_services(config, shutdown)
 }
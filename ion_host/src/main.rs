@@ -4,14 +4,27 @@
 //! Currently, following services are provided:
 //! - SocketInfo: Provides the socket address of the requesting client.
 //! - ServerList: Provides a list of known multiplayer servers.
+//! - Federation: Shares the server list with the hosts of other regions.
 //! - NatPunch: Provides NAT punching protocol for joining multiplayer servers.
+//! - Relay: Forwards game traffic between peers that can't connect directly even with NAT punching.
+//! - Matchmaking: Groups players into matches, and assigns each match a server or a hosting player.
+//! - Stats: Keeps player stats submitted by game servers, and answers leaderboard queries.
+//! - Admin: Lets operators inspect and adjust the other services while they run.
+//! - Status: Answers health checks and status queries over UDP and HTTP.
 
-use ion_common::LogLevel;
 use ion_host::config::Config;
 use ion_host::run_ion_host;
+use ion_host::shutdown::register_shutdown_signals;
 
-/// Entry point for the host
+/// Entry point for the host, see [`Config::load`] for the arguments
 pub fn main() {
-    ion_common::set_logger_on(LogLevel::Debug);
-    run_ion_host(Config::default());
+    let config = match Config::load(std::env::args().skip(1), std::env::vars()) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+    ion_common::set_logger_on(config.log_level);
+    run_ion_host(config, register_shutdown_signals());
 }
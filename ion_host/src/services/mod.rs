@@ -1,30 +1,124 @@
-use std::net::SocketAddr;
+use std::io;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
-use ion_common::log_dbg;
-use ion_common::net::udp_network_socket::UdpNetworkSocket;
-use ion_common::net::{SysMessage, UdpMessage};
+use ion_common::net::rate_limit::{RateLimiter, is_valid_source};
+use ion_common::net::udp_network_socket::{
+    UdpNetworkSocket, dual_stack_bind_addr, generate_private_key,
+};
+use ion_common::net::{HostAdminCommand, PeerMessage, SysMessage, UdpMessage};
+use ion_common::{log_dbg, log_error, log_info, log_warn};
 
 use crate::config::Config;
+use crate::services::service_admin::ServiceAdmin;
+use crate::services::service_federation::ServiceFederation;
+use crate::services::service_matchmaking::ServiceMatchmaking;
 use crate::services::service_nat_punch::ServiceNatPunch;
+use crate::services::service_relay::ServiceRelay;
 use crate::services::service_server_list::ServiceServerList;
 use crate::services::service_socket_info::ServiceSocketInfo;
+use crate::services::service_stats::ServiceStats;
+use crate::services::service_status::ServiceStatus;
+use crate::shutdown::notify_systemd;
 
+pub mod region_map;
+pub mod service_admin;
+pub mod service_federation;
+pub mod service_matchmaking;
 pub mod service_nat_punch;
+pub mod service_relay;
 pub mod service_server_list;
 pub mod service_socket_info;
+pub mod service_stats;
+pub mod service_status;
 
-pub fn run_services(config: Config) -> ! {
-    let udp_socket: Arc<UdpNetworkSocket<UdpMessage<()>>> = Arc::new(UdpNetworkSocket::new(
-        SocketAddr::from(([0, 0, 0, 0], config.port)),
-    ));
+// Longest time the shutdown flag goes unnoticed while no requests arrive
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(250);
 
-    let service_nat_punch = ServiceNatPunch::new(udp_socket.clone(), config);
-    let service_server_list = ServiceServerList::new(udp_socket.clone(), config);
-    let service_socket_info = ServiceSocketInfo::new(udp_socket.clone(), config);
+/// Runs the services until the shutdown flag is set, and then for [`Config::drain_timeout`] more while refusing
+/// new registrations, so that the notices of the shutdown and the answers still in flight get delivered.
+pub fn run_services(config: Config, shutdown: &AtomicBool) {
+    // Dual stack, so that both IPv4 and IPv6 servers and clients can use the same host
+    let udp_socket: Arc<UdpNetworkSocket<UdpMessage<()>>> =
+        Arc::new(UdpNetworkSocket::with_private_key(
+            dual_stack_bind_addr(config.port),
+            load_network_key(&config),
+        ));
+    // Anyone can send to the host, so the socket itself must not answer floods or spoofed sources either
+    udp_socket.limit_sources(config.max_frames_per_sec, config.max_frame_burst);
+
+    let service_nat_punch = ServiceNatPunch::new(udp_socket.clone(), config.clone());
+    let service_server_list = ServiceServerList::new(udp_socket.clone(), config.clone());
+    let service_socket_info = ServiceSocketInfo::new(udp_socket.clone(), config.clone());
+    let service_matchmaking = ServiceMatchmaking::new(udp_socket.clone(), config.clone());
+    let service_stats = ServiceStats::new(udp_socket.clone(), config.clone());
+    let service_relay = ServiceRelay::new(udp_socket.clone(), config.clone());
+    let service_federation = ServiceFederation::new(udp_socket.clone(), config.clone());
+    let service_admin = ServiceAdmin::new(udp_socket.clone(), config.clone());
+    let service_status = ServiceStatus::new(udp_socket.clone(), config.clone());
+
+    // Every request is answered to its source, so spoofed requests could use the host to flood others
+    let request_limiter = RateLimiter::new(config.max_requests_per_sec, config.max_request_burst);
+    // Relays are limited by their target too, so that requests from many addresses can't flood a single target
+    let relay_limiter = RateLimiter::new(config.max_requests_per_sec, config.max_request_burst);
+
+    notify_systemd("READY=1");
+    let mut drain_deadline: Option<Instant> = None;
 
     loop {
-        let (from_addr, udp_message) = udp_socket.recv_blocking();
+        let now = Instant::now();
+        if drain_deadline.is_none() && shutdown.load(Ordering::Relaxed) {
+            log_info!("Shutting down, draining for {:?}", config.drain_timeout);
+            notify_systemd("STOPPING=1");
+            service_server_list.shutdown();
+            service_matchmaking.shutdown();
+            service_relay.shutdown();
+            service_federation.shutdown();
+            service_status.shutdown();
+            drain_deadline = Some(now + config.drain_timeout);
+        }
+        let is_draining = drain_deadline.is_some();
+        if drain_deadline.is_some_and(|deadline| deadline <= now) {
+            break;
+        }
+        if !is_draining && service_federation.is_sync_due(now) {
+            service_federation.sync(now, service_server_list.servers());
+        }
+        if service_stats.is_save_due(now) {
+            service_stats.flush();
+        }
+        if service_status.is_update_due(now) {
+            service_status.update(now, |status| {
+                status.server_count = service_server_list.servers().len() as u32;
+                status.peer_server_count = service_federation.servers().len() as u32;
+                status.queued_player_count = service_matchmaking.queued_player_count() as u32;
+                status.relay_session_count = service_relay.session_count() as u32;
+            });
+        }
+        let Some((from_addr, udp_message)) = udp_socket
+            .try_recv_timeout(config.federation_sync_interval.min(SHUTDOWN_POLL_INTERVAL))
+        else {
+            continue;
+        };
+        if !is_valid_source(from_addr) {
+            log_warn!("Dropped request from invalid address {:?}", from_addr);
+            continue;
+        }
+        // Relayed data is limited by its session instead, as game traffic comes far faster than requests
+        if let UdpMessage::SysMessage(SysMessage::RelayData {
+            token,
+            data,
+            reliable,
+        }) = udp_message
+        {
+            service_relay.handle_relay_data(from_addr, token, data, reliable);
+            continue;
+        }
+        if !request_limiter.allow(from_addr.ip(), Instant::now()) {
+            log_dbg!("Rate limited request from {:?}", from_addr);
+            continue;
+        }
         log_dbg!("Received request from {:?}: {:?}", from_addr, udp_message);
         if let UdpMessage::SysMessage(message) = udp_message {
             match message {
@@ -32,19 +126,147 @@ pub fn run_services(config: Config) -> ! {
                     service_socket_info.handle_socket_info_req(from_addr);
                 }
                 SysMessage::ServerInfoReq => {
-                    service_server_list.handle_server_info_req(from_addr);
+                    service_server_list
+                        .handle_server_info_req(from_addr, service_federation.servers());
                 }
-                SysMessage::ServerInfoPost { server } => {
+                SysMessage::ServerInfoPost { server } if !is_draining => {
                     service_server_list.handle_server_info_post(from_addr, server);
                 }
                 SysMessage::ServerInfoDelete => {
                     service_server_list.handle_server_info_delete(from_addr);
                 }
+                SysMessage::ServerHeartbeat => {
+                    service_server_list.handle_server_heartbeat(from_addr);
+                }
+                SysMessage::ServerAltAddrConfirm { cookie } => {
+                    service_server_list.handle_server_alt_addr_confirm(from_addr, cookie);
+                }
                 SysMessage::NatPunchRelay { to } => {
-                    service_nat_punch.handle_nat_punch_relay(from_addr, to);
+                    if is_valid_source(to) && relay_limiter.allow(to.ip(), Instant::now()) {
+                        if service_server_list.is_listed(to)
+                            || !service_federation.forward_nat_punch_relay(from_addr, to)
+                        {
+                            service_nat_punch.handle_nat_punch_relay(from_addr, to);
+                        }
+                    } else {
+                        log_dbg!("Dropped nat punch relay from {:?} to {:?}", from_addr, to);
+                    }
+                }
+                SysMessage::HostPeerMessage { message } => {
+                    match service_federation.authenticate(from_addr, message) {
+                        Some(PeerMessage::ServerSync { servers }) if !is_draining => {
+                            service_federation.handle_host_server_sync(from_addr, servers);
+                        }
+                        Some(PeerMessage::NatPunchForward { from, to })
+                            if is_valid_source(from) =>
+                        {
+                            service_nat_punch.handle_nat_punch_relay(from, to);
+                        }
+                        Some(PeerMessage::Shutdown) => {
+                            service_federation.handle_host_shutdown(from_addr);
+                        }
+                        _ => {}
+                    }
+                }
+                SysMessage::HostStatusReq => {
+                    service_status.handle_host_status_req(from_addr);
+                }
+                SysMessage::HostInfoReq => {
+                    service_federation.handle_host_info_req(from_addr);
+                }
+                SysMessage::Ping => {
+                    service_socket_info.handle_ping(from_addr);
+                }
+                SysMessage::RelayOpen { peer } if !is_draining => {
+                    if is_valid_source(peer) && relay_limiter.allow(peer.ip(), Instant::now()) {
+                        service_relay.handle_relay_open(from_addr, peer);
+                    } else {
+                        log_dbg!("Dropped relay request from {:?} to {:?}", from_addr, peer);
+                    }
+                }
+                SysMessage::RelayClose { token } => {
+                    service_relay.handle_relay_close(from_addr, token);
+                }
+                SysMessage::MatchReq { player, criteria } if !is_draining => {
+                    let servers = service_server_list.servers();
+                    service_matchmaking.handle_match_req(from_addr, player, criteria, &servers);
+                }
+                SysMessage::MatchCancel => {
+                    service_matchmaking.handle_match_cancel(from_addr);
+                }
+                SysMessage::StatSubmit { submission } => {
+                    service_stats.handle_stat_submit(from_addr, submission);
+                }
+                SysMessage::LeaderboardReq { stat, count } => {
+                    service_stats.handle_leaderboard_req(from_addr, stat, count);
+                }
+                SysMessage::PlayerStatsReq { player } => {
+                    service_stats.handle_player_stats_req(from_addr, player);
+                }
+                SysMessage::HostAdminReq { token, command } => {
+                    service_admin.handle_host_admin_req(from_addr, &token, command, |command| {
+                        match command {
+                            HostAdminCommand::ListServers => {
+                                Ok(service_server_list.describe_servers())
+                            }
+                            HostAdminCommand::EvictServer { addr } => {
+                                service_server_list.evict_server(addr)
+                            }
+                            HostAdminCommand::ListNatPunches => {
+                                Ok(service_nat_punch.describe_sessions())
+                            }
+                            HostAdminCommand::ListRelays => Ok(service_relay.describe_sessions()),
+                            HostAdminCommand::SetLimits {
+                                max_requests_per_sec,
+                                max_request_burst,
+                                max_servers_per_ip,
+                            } => {
+                                request_limiter.set_limits(max_requests_per_sec, max_request_burst);
+                                relay_limiter.set_limits(max_requests_per_sec, max_request_burst);
+                                service_server_list
+                                    .set_max_servers_per_ip(max_servers_per_ip as usize);
+                                Ok("Limits updated".to_owned())
+                            }
+                        }
+                    });
                 }
                 _ => {}
             }
         }
     }
+
+    service_stats.flush();
+    log_info!("Host services stopped");
+}
+
+/// Private key of the host from [`Config::network_key_file`], created there if missing. Without the file, or if it
+/// can't be used, the key is generated for this run only.
+fn load_network_key(config: &Config) -> [u8; 32] {
+    let Some(path) = &config.network_key_file else {
+        return generate_private_key();
+    };
+    match std::fs::read(path) {
+        Ok(bytes) => match bytes.try_into() {
+            Ok(private_key) => return private_key,
+            Err(_) => {
+                log_error!("Invalid network key in {:?}", path);
+            }
+        },
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            let private_key = generate_private_key();
+            match std::fs::write(path, private_key) {
+                Ok(()) => {
+                    log_info!("Created network key in {:?}", path);
+                    return private_key;
+                }
+                Err(err) => {
+                    log_error!("Failed to save network key to {:?}: {}", path, err);
+                }
+            }
+        }
+        Err(err) => {
+            log_error!("Failed to load network key from {:?}: {}", path, err);
+        }
+    }
+    generate_private_key()
 }
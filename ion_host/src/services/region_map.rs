@@ -0,0 +1,135 @@
+use std::{net::IpAddr, path::Path};
+
+use ion_common::Map;
+
+/// Maps IP networks to coarse regions, such as "eu" or "us-east", for tagging the servers in the server list.
+///
+/// Read from CSV rows of `network,region`, where the network is an address with an optional prefix length, such as
+/// `203.0.113.0/24` or `2001:db8::/32`. Further columns are ignored, as is a header row, so that exports of GeoIP
+/// databases can be used once their country or city columns are replaced with regions. Lines starting with `#` are
+/// comments. The most specific network containing an address decides its region.
+#[derive(Debug, Default)]
+pub struct RegionMap {
+    /// Networks by prefix length, longest first. Addresses are stored as IPv6, IPv4 ones mapped.
+    networks: Vec<(u32, Map<u128, String>)>,
+}
+
+impl RegionMap {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let string = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read {:?}: {}", path, err))?;
+        Self::parse(&string)
+    }
+
+    pub fn parse(string: &str) -> Result<Self, String> {
+        let mut map = Self::default();
+        for (i, line) in string.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut columns = line.split(',').map(str::trim);
+            let network = columns.next().unwrap_or_default();
+            let region = columns.next().unwrap_or_default();
+            match parse_network(network) {
+                Some(_) if region.is_empty() => {
+                    return Err(format!("Line {}: missing region", i + 1));
+                }
+                Some((bits, prefix_len)) => map.insert(bits, prefix_len, region),
+                None if i == 0 => continue,
+                None => return Err(format!("Line {}: invalid network {:?}", i + 1, network)),
+            }
+        }
+        Ok(map)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.networks.is_empty()
+    }
+
+    pub fn region_of(&self, ip: IpAddr) -> Option<&str> {
+        let bits = to_bits(ip);
+        self.networks
+            .iter()
+            .find_map(|(prefix_len, networks)| networks.get(&(bits & mask(*prefix_len))))
+            .map(String::as_str)
+    }
+
+    fn insert(&mut self, bits: u128, prefix_len: u32, region: &str) {
+        let index = match self
+            .networks
+            .binary_search_by(|(len, _)| prefix_len.cmp(len))
+        {
+            Ok(index) => index,
+            Err(index) => {
+                self.networks.insert(index, (prefix_len, Map::default()));
+                index
+            }
+        };
+        self.networks[index]
+            .1
+            .insert(bits & mask(prefix_len), region.to_owned());
+    }
+}
+
+/// Parses `address/prefix_len` or a single address, as IPv6 bits and prefix length.
+fn parse_network(network: &str) -> Option<(u128, u32)> {
+    let (addr, prefix_len) = match network.split_once('/') {
+        Some((addr, prefix_len)) => (addr, Some(prefix_len.parse::<u32>().ok()?)),
+        None => (network, None),
+    };
+    let ip: IpAddr = addr.parse().ok()?;
+    let prefix_len = match (ip, prefix_len) {
+        (IpAddr::V4(_), Some(prefix_len)) if prefix_len <= 32 => prefix_len + 96,
+        (IpAddr::V6(_), Some(prefix_len)) if prefix_len <= 128 => prefix_len,
+        (_, None) => 128,
+        _ => return None,
+    };
+    Some((to_bits(ip), prefix_len))
+}
+
+fn to_bits(ip: IpAddr) -> u128 {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped().to_bits(),
+        IpAddr::V6(ip) => ip.to_bits(),
+    }
+}
+
+fn mask(prefix_len: u32) -> u128 {
+    u128::MAX.checked_shl(128 - prefix_len).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn most_specific_network_decides_region() {
+        let map = RegionMap::parse(
+            "network,region,country\n\
+             # Operator overrides\n\
+             203.0.113.0/24,eu,FI\n\
+             203.0.113.128/25,us-east\n\
+             198.51.100.7,asia\n\
+             2001:db8::/32,eu\n",
+        )
+        .unwrap();
+
+        let region_of = |ip: &str| map.region_of(ip.parse().unwrap());
+        assert_eq!(region_of("203.0.113.1"), Some("eu"));
+        assert_eq!(region_of("203.0.113.200"), Some("us-east"));
+        assert_eq!(region_of("::ffff:203.0.113.200"), Some("us-east"));
+        assert_eq!(region_of("198.51.100.7"), Some("asia"));
+        assert_eq!(region_of("198.51.100.8"), None);
+        assert_eq!(region_of("2001:db8:1::1"), Some("eu"));
+        assert_eq!(region_of("2001:db9::1"), None);
+    }
+
+    #[test]
+    fn invalid_rows_are_rejected() {
+        assert!(RegionMap::parse("network,region\n203.0.113.0/33,eu").is_err());
+        assert!(RegionMap::parse("203.0.113.0/24").is_err());
+        assert!(RegionMap::parse("203.0.113.0/24,eu\nnot a network,eu").is_err());
+        assert!(RegionMap::parse("").unwrap().is_empty());
+    }
+}
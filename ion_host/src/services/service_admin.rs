@@ -0,0 +1,66 @@
+use std::{cell::RefCell, net::SocketAddr, sync::Arc};
+
+use ion_common::net::{
+    AuthLockout, HostAdminCommand, SysMessage, UdpMessage, secret_matches,
+    udp_network_socket::UdpNetworkSocket,
+};
+use ion_common::{Instant, log_info, log_warn};
+
+use crate::config::Config;
+
+pub struct ServiceAdmin {
+    socket: Arc<UdpNetworkSocket<UdpMessage<()>>>,
+    config: Config,
+    lockout: RefCell<AuthLockout>,
+}
+
+impl ServiceAdmin {
+    pub fn new(socket: Arc<UdpNetworkSocket<UdpMessage<()>>>, config: Config) -> Self {
+        log_info!("Creating service Admin");
+        Self {
+            socket,
+            config,
+            lockout: RefCell::new(AuthLockout::new()),
+        }
+    }
+
+    /// Executes the command if the token is the admin password, and sends the result back. Commands are only accepted
+    /// over encrypted sessions, and IPs that have sent too many wrong passwords are locked out for a while.
+    pub fn handle_host_admin_req(
+        &self,
+        from_addr: SocketAddr,
+        token: &[u8],
+        command: HostAdminCommand,
+        execute: impl FnOnce(HostAdminCommand) -> Result<String, String>,
+    ) {
+        let mut lockout = self.lockout.borrow_mut();
+        let result = if !self.socket.has_session(from_addr) {
+            // The password could have been read on the way
+            log_warn!("Denied unencrypted admin command from {:?}", from_addr);
+            Err("Admin commands are only accepted over an encrypted session".to_owned())
+        } else if lockout.is_locked_out(from_addr.ip(), Instant::now()) {
+            log_warn!("Denied admin command from locked out {:?}", from_addr);
+            Err("Too many failed attempts, try again later".to_owned())
+        } else if self.verify(token) {
+            lockout.clear(from_addr.ip());
+            log_info!("Admin command from {:?}: {:?}", from_addr, command);
+            execute(command)
+        } else {
+            log_warn!("Denied admin command from {:?}", from_addr);
+            if lockout.record_failure(from_addr.ip(), Instant::now()) {
+                log_warn!("Locked out admin commands from {:?}", from_addr.ip());
+            }
+            Err("Not authorized".to_owned())
+        };
+        let res = UdpMessage::SysMessage(SysMessage::HostAdminRes { result });
+        self.socket
+            .send(from_addr, res, self.config.socket_info_resp_timeout);
+    }
+
+    fn verify(&self, token: &[u8]) -> bool {
+        let Some(password) = &self.config.admin_password else {
+            return false;
+        };
+        secret_matches(token, password.as_bytes())
+    }
+}
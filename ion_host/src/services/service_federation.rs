@@ -0,0 +1,262 @@
+use std::{
+    cell::{Cell, RefCell},
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use ion_common::net::{
+    NetworkServerInfo, PeerMessage, SignedPeerMessage, SysMessage, UdpMessage,
+    udp_network_socket::UdpNetworkSocket,
+};
+use ion_common::{DateTime, Map, log_info, log_warn};
+
+use crate::config::Config;
+
+// Peer messages sent longer ago than this are refused, so the clocks of the hosts must be at most this far apart
+const PEER_MESSAGE_MAX_AGE: Duration = Duration::from_secs(30);
+
+/// Shares the server list with the peer hosts of [`Config::federation_peers`], usually hosts of other regions.
+///
+/// Only the servers registered at each host are sent, so that servers don't keep circulating between hosts after they
+/// are gone. Servers of a peer that stops syncing are dropped after [`Config::server_ping_timeout`].
+///
+/// Messages between the hosts are signed with [`Config::federation_secret`], and each is accepted only once.
+pub struct ServiceFederation {
+    socket: Arc<UdpNetworkSocket<UdpMessage<()>>>,
+    config: Config,
+    peer_servers: RefCell<Map<SocketAddr, (Instant, Vec<NetworkServerInfo>)>>,
+    last_sync: Cell<Option<Instant>>,
+    last_sent_at_ms: Cell<u64>,
+    // Send times of the messages accepted from each peer within the max age, to refuse replays
+    received: RefCell<Map<SocketAddr, Vec<u64>>>,
+}
+
+impl ServiceFederation {
+    pub fn new(socket: Arc<UdpNetworkSocket<UdpMessage<()>>>, config: Config) -> Self {
+        log_info!("Creating service Federation");
+        if !config.federation_peers.is_empty() {
+            if config.federation_secret.is_some() {
+                log_info!(
+                    "Sharing server list of region {:?} with {:?}",
+                    config.region,
+                    config.federation_peers
+                );
+            } else {
+                log_warn!("Federation peers are ignored, as there is no federation secret");
+            }
+        }
+        Self {
+            socket,
+            config,
+            peer_servers: RefCell::new(Map::default()),
+            last_sync: Cell::new(None),
+            last_sent_at_ms: Cell::new(0),
+            received: RefCell::new(Map::default()),
+        }
+    }
+
+    pub fn is_sync_due(&self, now: Instant) -> bool {
+        !self.config.federation_peers.is_empty()
+            && self.config.federation_secret.is_some()
+            && self
+                .last_sync
+                .get()
+                .is_none_or(|last_sync| last_sync + self.config.federation_sync_interval <= now)
+    }
+
+    /// Sends the servers registered here to the peers.
+    pub fn sync(&self, now: Instant, servers: Vec<NetworkServerInfo>) {
+        self.last_sync.set(Some(now));
+        for peer in &self.config.federation_peers {
+            // Unreliable, as the next sync replaces a lost one
+            self.send(
+                *peer,
+                PeerMessage::ServerSync {
+                    servers: servers.clone(),
+                },
+                Duration::ZERO,
+            );
+        }
+    }
+
+    /// Returns the message if it comes from a peer, is signed with the secret and hasn't been accepted before.
+    pub fn authenticate(
+        &self,
+        from_addr: SocketAddr,
+        signed: SignedPeerMessage,
+    ) -> Option<PeerMessage> {
+        let Some(secret) = &self.config.federation_secret else {
+            log_warn!(
+                "Ignored peer message from {:?}, no federation secret",
+                from_addr
+            );
+            return None;
+        };
+        if !self.is_peer(from_addr) {
+            log_warn!("Ignored peer message from {:?}, not a peer", from_addr);
+            return None;
+        }
+        if !signed.verify(secret.as_bytes()) {
+            log_warn!(
+                "Ignored peer message from {:?}, invalid signature",
+                from_addr
+            );
+            return None;
+        }
+
+        let now_ms = DateTime::now().as_unix_timestamp_ms();
+        let max_age_ms = PEER_MESSAGE_MAX_AGE.as_millis() as u64;
+        if now_ms.abs_diff(signed.sent_at_ms) > max_age_ms {
+            log_warn!(
+                "Ignored peer message from {:?}, sent too long ago",
+                from_addr
+            );
+            return None;
+        }
+        let mut received = self.received.borrow_mut();
+        let received = received.entry(from_addr).or_default();
+        received.retain(|sent_at_ms| now_ms.abs_diff(*sent_at_ms) <= max_age_ms);
+        if received.contains(&signed.sent_at_ms) {
+            log_warn!(
+                "Ignored peer message from {:?}, already received",
+                from_addr
+            );
+            return None;
+        }
+        received.push(signed.sent_at_ms);
+        Some(signed.message)
+    }
+
+    pub fn handle_host_server_sync(&self, from_addr: SocketAddr, servers: Vec<NetworkServerInfo>) {
+        self.peer_servers
+            .borrow_mut()
+            .insert(from_addr, (Instant::now(), servers));
+    }
+
+    /// Drops the servers of the peer right away, as it can't relay nat punches to them anymore.
+    pub fn handle_host_shutdown(&self, from_addr: SocketAddr) {
+        if self.peer_servers.borrow_mut().remove(&from_addr).is_some() {
+            log_info!("Peer {:?} is shutting down", from_addr);
+        }
+    }
+
+    /// Tells the peers that the host is going away.
+    pub fn shutdown(&self) {
+        for peer in &self.config.federation_peers {
+            self.send(
+                *peer,
+                PeerMessage::Shutdown,
+                self.config.socket_info_resp_timeout,
+            );
+        }
+    }
+
+    pub fn handle_host_info_req(&self, from_addr: SocketAddr) {
+        let res = UdpMessage::SysMessage(SysMessage::HostInfoRes {
+            region: self.config.region.clone(),
+            peers: self.config.federation_peers.clone(),
+        });
+        self.socket
+            .send(from_addr, res, self.config.socket_info_resp_timeout);
+    }
+
+    /// Passes the nat punch relay on to the peer the server is registered at, as only that host can reach the server
+    /// through its nat. Returns false if the server isn't registered at any peer.
+    pub fn forward_nat_punch_relay(&self, from_addr: SocketAddr, to: SocketAddr) -> bool {
+        let peer = self
+            .peer_servers
+            .borrow()
+            .iter()
+            .find_map(|(peer, (_, servers))| {
+                servers
+                    .iter()
+                    .any(|server| server.addr == to || server.alt_addr == Some(to))
+                    .then_some(*peer)
+            });
+        let Some(peer) = peer else {
+            return false;
+        };
+        self.send(
+            peer,
+            PeerMessage::NatPunchForward {
+                from: from_addr,
+                to,
+            },
+            self.config.nat_punch_relay_timeout,
+        );
+        true
+    }
+
+    fn send(&self, peer: SocketAddr, message: PeerMessage, timeout: Duration) {
+        let Some(secret) = &self.config.federation_secret else {
+            return;
+        };
+        // Send times identify the messages, so each message gets a later one than the previous
+        let sent_at_ms = DateTime::now()
+            .as_unix_timestamp_ms()
+            .max(self.last_sent_at_ms.get() + 1);
+        self.last_sent_at_ms.set(sent_at_ms);
+        let message = SignedPeerMessage::new(secret.as_bytes(), message, sent_at_ms);
+        let msg = UdpMessage::SysMessage(SysMessage::HostPeerMessage { message });
+        self.socket.send(peer, msg, timeout);
+    }
+
+    pub fn is_peer(&self, addr: SocketAddr) -> bool {
+        self.config.federation_peers.iter().any(|peer| {
+            peer.port() == addr.port() && peer.ip().to_canonical() == addr.ip().to_canonical()
+        })
+    }
+
+    /// Servers registered at the peers.
+    pub fn servers(&self) -> Vec<NetworkServerInfo> {
+        let now = Instant::now();
+        let mut peer_servers = self.peer_servers.borrow_mut();
+        peer_servers.retain(|peer, (updated, _)| {
+            let retain = *updated + self.config.server_ping_timeout > now;
+            if !retain {
+                log_info!("Servers of peer {:?} expired", peer);
+            }
+            retain
+        });
+        peer_servers
+            .values()
+            .flat_map(|(_, servers)| servers.iter().cloned())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_signed_peer_messages_are_accepted_once() {
+        let peer = SocketAddr::from(([127, 0, 0, 1], 3399));
+        let config = Config {
+            federation_peers: vec![peer],
+            federation_secret: Some("federation-secret".to_owned()),
+            ..Config::default()
+        };
+        let socket = Arc::new(UdpNetworkSocket::new(SocketAddr::from(([127, 0, 0, 1], 0))));
+        let federation = ServiceFederation::new(socket, config);
+        let now_ms = DateTime::now().as_unix_timestamp_ms();
+
+        let forged = SignedPeerMessage::new(b"wrong-secret", PeerMessage::Shutdown, now_ms);
+        assert_eq!(federation.authenticate(peer, forged), None);
+
+        let signed = SignedPeerMessage::new(b"federation-secret", PeerMessage::Shutdown, now_ms);
+        let other = SocketAddr::from(([127, 0, 0, 1], 3398));
+        assert_eq!(federation.authenticate(other, signed.clone()), None);
+        assert_eq!(
+            federation.authenticate(peer, signed.clone()),
+            Some(PeerMessage::Shutdown)
+        );
+        // Replayed
+        assert_eq!(federation.authenticate(peer, signed), None);
+
+        let old_ms = now_ms - PEER_MESSAGE_MAX_AGE.as_millis() as u64 - 1000;
+        let old = SignedPeerMessage::new(b"federation-secret", PeerMessage::Shutdown, old_ms);
+        assert_eq!(federation.authenticate(peer, old), None);
+    }
+}
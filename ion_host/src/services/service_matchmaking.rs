@@ -0,0 +1,161 @@
+use std::{
+    cell::RefCell,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use ion_common::net::{
+    MatchAssignment, MatchCriteria, NetworkPlayerInfo, NetworkServerInfo, SysMessage, UdpMessage,
+    udp_network_socket::UdpNetworkSocket,
+};
+use ion_common::{Map, log_info};
+
+use crate::config::Config;
+
+// Servers assigned to a match are not assigned again for this long, so that the players have time to join
+const SERVER_RESERVATION: Duration = Duration::from_secs(30);
+
+/// Groups queued players with the same criteria into matches.
+/// Matches go to empty global servers without a password if there are any, and otherwise one of the players hosts.
+pub struct ServiceMatchmaking {
+    socket: Arc<UdpNetworkSocket<UdpMessage<()>>>,
+    config: Config,
+    queues: RefCell<Map<MatchCriteria, Vec<(NetworkPlayerInfo, Instant)>>>,
+    reserved_servers: RefCell<Map<SocketAddr, Instant>>,
+}
+
+impl ServiceMatchmaking {
+    pub fn new(socket: Arc<UdpNetworkSocket<UdpMessage<()>>>, config: Config) -> Self {
+        log_info!("Creating service Matchmaking");
+        Self {
+            socket,
+            config,
+            queues: RefCell::new(Map::default()),
+            reserved_servers: RefCell::new(Map::default()),
+        }
+    }
+
+    pub fn queued_player_count(&self) -> usize {
+        self.queues.borrow().values().map(Vec::len).sum()
+    }
+
+    pub fn handle_match_req(
+        &self,
+        from_addr: SocketAddr,
+        mut player: NetworkPlayerInfo,
+        criteria: MatchCriteria,
+        servers: &[NetworkServerInfo],
+    ) {
+        if criteria.match_size < 2 {
+            return;
+        }
+        self.handle_match_cancel(from_addr);
+
+        // Players are reached at the address the host sees, which is the one their nat has opened
+        player.addr = from_addr;
+        let mut queues = self.queues.borrow_mut();
+        let queue = queues.entry(criteria.clone()).or_default();
+        queue.push((player, Instant::now()));
+
+        let res = UdpMessage::SysMessage(SysMessage::MatchQueued {
+            position: queue.len() as u32,
+        });
+        self.socket
+            .send(from_addr, res, self.config.socket_info_resp_timeout);
+
+        if queue.len() >= criteria.match_size as usize {
+            let players: Vec<_> = queue
+                .drain(..criteria.match_size as usize)
+                .map(|(player, _)| player)
+                .collect();
+            if queue.is_empty() {
+                queues.remove(&criteria);
+            }
+            drop(queues);
+            self.start_match(players, &criteria, servers);
+        }
+    }
+
+    pub fn handle_match_cancel(&self, from_addr: SocketAddr) {
+        let now = Instant::now();
+        self.queues.borrow_mut().retain(|_, queue| {
+            queue.retain(|(player, queued_at)| {
+                player.addr != from_addr && *queued_at + self.config.match_queue_timeout > now
+            });
+            !queue.is_empty()
+        });
+    }
+
+    /// Empties the queues, telling the queued players that the host is going away.
+    pub fn shutdown(&self) {
+        for (_, queue) in self.queues.borrow_mut().drain() {
+            for (player, _) in queue {
+                let msg = UdpMessage::SysMessage(SysMessage::HostShutdown);
+                self.socket
+                    .send(player.addr, msg, self.config.socket_info_resp_timeout);
+            }
+        }
+    }
+
+    fn start_match(
+        &self,
+        players: Vec<NetworkPlayerInfo>,
+        criteria: &MatchCriteria,
+        servers: &[NetworkServerInfo],
+    ) {
+        let now = Instant::now();
+        let mut reserved_servers = self.reserved_servers.borrow_mut();
+        reserved_servers.retain(|_, reserved_at| *reserved_at + SERVER_RESERVATION > now);
+
+        let server = servers.iter().find(|server| {
+            server.is_global
+                && !server.has_password
+                && server.cur_player_count == 0
+                && server.max_player_count >= criteria.match_size
+                && !reserved_servers.contains_key(&server.addr)
+        });
+        let timeout = self.config.socket_info_resp_timeout;
+
+        match server {
+            Some(server) => {
+                log_info!(
+                    "Match of {} players assigned to {:?}",
+                    players.len(),
+                    server.name
+                );
+                reserved_servers.insert(server.addr, now);
+                for player in &players {
+                    let assignment = MatchAssignment::Server {
+                        server: server.clone(),
+                    };
+                    let res = UdpMessage::SysMessage(SysMessage::MatchFound { assignment });
+                    self.socket.send(player.addr, res, timeout);
+                }
+            }
+            None => {
+                // The player who waited the longest hosts
+                let (host, clients) = players.split_first().unwrap();
+                log_info!(
+                    "Match of {} players hosted by {:?}",
+                    players.len(),
+                    host.addr
+                );
+                for client in clients {
+                    let punch =
+                        UdpMessage::SysMessage(SysMessage::NatPunchStart { to: client.addr });
+                    self.socket
+                        .send(host.addr, punch, self.config.nat_punch_relay_timeout);
+                    let assignment = MatchAssignment::Client { host: host.clone() };
+                    let res = UdpMessage::SysMessage(SysMessage::MatchFound { assignment });
+                    self.socket.send(client.addr, res, timeout);
+                }
+                let assignment = MatchAssignment::Host {
+                    players: clients.to_vec(),
+                };
+                let res = UdpMessage::SysMessage(SysMessage::MatchFound { assignment });
+                self.socket.send(host.addr, res, timeout);
+            }
+        }
+    }
+}
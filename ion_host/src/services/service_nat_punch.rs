@@ -1,6 +1,6 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{cell::RefCell, net::SocketAddr, sync::Arc, time::Instant};
 
-use ion_common::log_info;
+use ion_common::{log_info, Map};
 use ion_common::net::{udp_network_socket::UdpNetworkSocket, SysMessage, UdpMessage};
 
 use crate::config::Config;
@@ -8,17 +8,42 @@ use crate::config::Config;
 pub struct ServiceNatPunch {
     socket: Arc<UdpNetworkSocket<UdpMessage<()>>>,
     config: Config,
+    sessions: RefCell<Map<(SocketAddr, SocketAddr), Instant>>,
 }
 
 impl ServiceNatPunch {
     pub fn new(socket: Arc<UdpNetworkSocket<UdpMessage<()>>>, config: Config) -> Self {
         log_info!("Creating service NatPunch");
-        Self { socket, config }
+        Self {
+            socket,
+            config,
+            sessions: RefCell::new(Map::default()),
+        }
     }
 
     pub fn handle_nat_punch_relay(&self, from_addr: SocketAddr, to: SocketAddr) {
+        let now = Instant::now();
+        let mut sessions = self.sessions.borrow_mut();
+        sessions.retain(|_, started| *started + self.config.nat_punch_relay_timeout > now);
+        sessions.insert((from_addr, to), now);
+        drop(sessions);
+
         let res = UdpMessage::SysMessage(SysMessage::NatPunchStart { to: from_addr });
         self.socket
             .send(to, res, self.config.nat_punch_relay_timeout)
     }
+
+    /// Describes the relays that are still being resent for an admin, one per line.
+    pub fn describe_sessions(&self) -> String {
+        let now = Instant::now();
+        let mut sessions = self.sessions.borrow_mut();
+        sessions.retain(|_, started| *started + self.config.nat_punch_relay_timeout > now);
+        sessions
+            .iter()
+            .map(|((from, to), started)| {
+                format!("{} -> {}, started {}s ago", from, to, now.duration_since(*started).as_secs())
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
 }
@@ -0,0 +1,307 @@
+use std::{
+    cell::RefCell,
+    hash::{BuildHasher, RandomState},
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use ion_common::net::{SysMessage, UdpMessage, udp_network_socket::UdpNetworkSocket};
+use ion_common::{Map, log_dbg, log_info};
+
+use crate::config::Config;
+
+// Sessions can relay this many seconds of their bandwidth at once, after being quiet for a while
+const BURST_SECS: f64 = 2.0;
+
+/// Forwards game traffic between two peers that couldn't connect to each other directly.
+///
+/// A session is opened once both peers have asked for it, so that the host never sends traffic to anyone who didn't
+/// want it. The first request is passed on to the other peer, which can then ask too. Relayed data is limited per
+/// session to [`Config::relay_bytes_per_sec`], and data over the limit is dropped.
+///
+/// Each IP address can be in [`Config::relay_max_sessions_per_ip`] sessions at once, so that no one can take up all
+/// of them. Requests count against the requesting address only, as anyone can name any address as the other peer.
+pub struct ServiceRelay {
+    socket: Arc<UdpNetworkSocket<UdpMessage<()>>>,
+    config: Config,
+    sessions: RefCell<Map<u64, RelaySession>>,
+    random_state: RandomState,
+}
+
+struct RelaySession {
+    /// The peer that asked first, and the one it asked for.
+    peers: [SocketAddr; 2],
+    is_open: bool,
+    started: Instant,
+    last_active: Instant,
+    bytes_relayed: u64,
+    bytes_dropped: u64,
+    budget: f64,
+    budget_updated: Instant,
+}
+
+impl ServiceRelay {
+    pub fn new(socket: Arc<UdpNetworkSocket<UdpMessage<()>>>, config: Config) -> Self {
+        log_info!("Creating service Relay");
+        Self {
+            socket,
+            config,
+            sessions: RefCell::new(Map::default()),
+            random_state: RandomState::new(),
+        }
+    }
+
+    pub fn handle_relay_open(&self, from_addr: SocketAddr, peer: SocketAddr) {
+        let now = Instant::now();
+        self.expire_sessions(now);
+        let mut sessions = self.sessions.borrow_mut();
+
+        if peer == from_addr {
+            drop(sessions);
+            self.refuse(from_addr, peer, "Can't relay to self");
+            return;
+        }
+
+        if let Some((&token, session)) = sessions.iter_mut().find(|(_, session)| {
+            session.peers == [from_addr, peer] || session.peers == [peer, from_addr]
+        }) {
+            // The other peer agreeing opens the session, and repeated requests get the answer again
+            if session.peers[1] == from_addr && !session.is_open {
+                log_info!("Relay opened between {:?} and {:?}", peer, from_addr);
+                session.is_open = true;
+                session.last_active = now;
+            }
+            if session.is_open {
+                self.send_opened(token, session.peers);
+            } else {
+                self.send_request(from_addr, peer);
+            }
+            return;
+        }
+
+        if sessions.len() >= self.config.relay_max_sessions {
+            drop(sessions);
+            self.refuse(from_addr, peer, "Relay is full");
+            return;
+        }
+        let sessions_of_ip = sessions
+            .values()
+            .filter(|session| session.is_of_ip(from_addr.ip()))
+            .count();
+        if sessions_of_ip >= self.config.relay_max_sessions_per_ip {
+            drop(sessions);
+            self.refuse(from_addr, peer, "Too many relays from this address");
+            return;
+        }
+
+        let token = self.new_token(&sessions);
+        sessions.insert(
+            token,
+            RelaySession {
+                peers: [from_addr, peer],
+                is_open: false,
+                started: now,
+                last_active: now,
+                bytes_relayed: 0,
+                bytes_dropped: 0,
+                budget: self.config.relay_bytes_per_sec as f64 * BURST_SECS,
+                budget_updated: now,
+            },
+        );
+        self.send_request(from_addr, peer);
+    }
+
+    pub fn handle_relay_data(
+        &self,
+        from_addr: SocketAddr,
+        token: u64,
+        data: Vec<u8>,
+        reliable: bool,
+    ) {
+        let now = Instant::now();
+        let mut sessions = self.sessions.borrow_mut();
+        let Some(session) = sessions.get_mut(&token) else {
+            return;
+        };
+        let Some(to) = session.other_peer(from_addr) else {
+            log_dbg!(
+                "Dropped relay data from {:?}, not in the session",
+                from_addr
+            );
+            return;
+        };
+        if !session.is_open {
+            return;
+        }
+        if session.last_active + self.config.relay_idle_timeout < now {
+            sessions.remove(&token);
+            return;
+        }
+
+        let per_sec = self.config.relay_bytes_per_sec as f64;
+        let elapsed = now
+            .saturating_duration_since(session.budget_updated)
+            .as_secs_f64();
+        session.budget = (session.budget + elapsed * per_sec).min(per_sec * BURST_SECS);
+        session.budget_updated = now;
+        session.last_active = now;
+
+        let len = data.len() as u64;
+        if session.budget < len as f64 {
+            session.bytes_dropped += len;
+            return;
+        }
+        session.budget -= len as f64;
+        session.bytes_relayed += len;
+
+        // Reliable data is resent by the host too, as it was acked to the sender on arrival
+        let timeout = if reliable {
+            self.config.relay_idle_timeout
+        } else {
+            Duration::ZERO
+        };
+        let msg = UdpMessage::SysMessage(SysMessage::RelayData {
+            token,
+            data,
+            reliable,
+        });
+        self.socket.send(to, msg, timeout);
+    }
+
+    pub fn handle_relay_close(&self, from_addr: SocketAddr, token: u64) {
+        let mut sessions = self.sessions.borrow_mut();
+        let Some(to) = sessions
+            .get(&token)
+            .and_then(|session| session.other_peer(from_addr))
+        else {
+            return;
+        };
+        let session = sessions.remove(&token).unwrap();
+        log_info!(
+            "Relay closed between {:?} and {:?} after {} bytes",
+            session.peers[0],
+            session.peers[1],
+            session.bytes_relayed
+        );
+        if session.is_open {
+            let msg = UdpMessage::SysMessage(SysMessage::RelayClose { token });
+            self.socket
+                .send(to, msg, self.config.nat_punch_relay_timeout);
+        }
+    }
+
+    /// Closes every session, telling both peers.
+    pub fn shutdown(&self) {
+        for (token, session) in self.sessions.borrow_mut().drain() {
+            if session.is_open {
+                for peer in session.peers {
+                    let msg = UdpMessage::SysMessage(SysMessage::RelayClose { token });
+                    self.socket
+                        .send(peer, msg, self.config.nat_punch_relay_timeout);
+                }
+            }
+        }
+    }
+
+    pub fn session_count(&self) -> usize {
+        self.expire_sessions(Instant::now());
+        self.sessions.borrow().len()
+    }
+
+    /// Drops sessions that haven't relayed anything for a while, and requests the other peer never agreed to.
+    /// Requests expire sooner, as they hold a place in the relay without anyone having agreed to it.
+    fn expire_sessions(&self, now: Instant) {
+        self.sessions.borrow_mut().retain(|_, session| {
+            let timeout = if session.is_open {
+                self.config.relay_idle_timeout
+            } else {
+                self.config.relay_pending_timeout
+            };
+            let is_active = session.last_active + timeout > now;
+            if !is_active && session.is_open {
+                log_info!(
+                    "Relay expired between {:?} and {:?} after {} bytes",
+                    session.peers[0],
+                    session.peers[1],
+                    session.bytes_relayed
+                );
+            }
+            is_active
+        });
+    }
+
+    pub fn describe_sessions(&self) -> String {
+        let now = Instant::now();
+        self.expire_sessions(now);
+        self.sessions
+            .borrow()
+            .values()
+            .map(|session| {
+                format!(
+                    "{} <-> {}, {}, started {}s ago, {} bytes relayed, {} bytes dropped",
+                    session.peers[0],
+                    session.peers[1],
+                    if session.is_open { "open" } else { "requested" },
+                    now.duration_since(session.started).as_secs(),
+                    session.bytes_relayed,
+                    session.bytes_dropped
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Tokens authorize sending through the session, so they are random instead of counted.
+    fn new_token(&self, sessions: &Map<u64, RelaySession>) -> u64 {
+        (0u64..)
+            .map(|i| self.random_state.hash_one((Instant::now(), i)))
+            .find(|token| !sessions.contains_key(token))
+            .unwrap()
+    }
+
+    fn send_request(&self, from_addr: SocketAddr, peer: SocketAddr) {
+        let msg = UdpMessage::SysMessage(SysMessage::RelayOpen { peer: from_addr });
+        self.socket
+            .send(peer, msg, self.config.nat_punch_relay_timeout);
+    }
+
+    fn send_opened(&self, token: u64, peers: [SocketAddr; 2]) {
+        for (to, peer) in [(peers[0], peers[1]), (peers[1], peers[0])] {
+            let msg = UdpMessage::SysMessage(SysMessage::RelayOpened { token, peer });
+            self.socket
+                .send(to, msg, self.config.nat_punch_relay_timeout);
+        }
+    }
+
+    fn refuse(&self, from_addr: SocketAddr, peer: SocketAddr, reason: &str) {
+        log_dbg!(
+            "Refused relay from {:?} to {:?}: {}",
+            from_addr,
+            peer,
+            reason
+        );
+        let msg = UdpMessage::SysMessage(SysMessage::RelayRefused {
+            peer,
+            reason: reason.to_owned(),
+        });
+        self.socket
+            .send(from_addr, msg, self.config.nat_punch_relay_timeout);
+    }
+}
+
+impl RelaySession {
+    fn other_peer(&self, addr: SocketAddr) -> Option<SocketAddr> {
+        match self.peers {
+            [a, b] if a == addr => Some(b),
+            [a, b] if b == addr => Some(a),
+            _ => None,
+        }
+    }
+
+    /// Whether the session counts against the IP address: open sessions count for both peers, and requests for the
+    /// requesting peer only.
+    fn is_of_ip(&self, ip: IpAddr) -> bool {
+        self.peers[0].ip() == ip || (self.is_open && self.peers[1].ip() == ip)
+    }
+}
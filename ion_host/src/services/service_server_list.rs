@@ -1,55 +1,289 @@
-use std::{cell::RefCell, net::SocketAddr, sync::Arc, time::Instant};
+use std::{
+    cell::{Cell, RefCell},
+    hash::{BuildHasher, RandomState},
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use ion_common::net::{
-    udp_network_socket::UdpNetworkSocket, NetworkServerInfo, SysMessage, UdpMessage,
+    NetworkServerInfo, SysMessage, UdpMessage, rate_limit::is_valid_source,
+    udp_network_socket::UdpNetworkSocket,
 };
-use ion_common::{log_info, Map};
+use ion_common::{Map, log_info, log_warn};
 
 use crate::config::Config;
+use crate::services::region_map::RegionMap;
 
 pub struct ServiceServerList {
     socket: Arc<UdpNetworkSocket<UdpMessage<()>>>,
     config: Config,
     servers: RefCell<Map<SocketAddr, (Instant, NetworkServerInfo)>>,
+    max_servers_per_ip: Cell<usize>,
+    region_map: RegionMap,
+    /// Cookies sent to the alternative addresses of servers, by the alternative address, with the address of the
+    /// server. Only answered cookies prove that the alternative address belongs to the server. Each server has at
+    /// most one check pending.
+    alt_addr_checks: RefCell<Map<SocketAddr, (SocketAddr, u64)>>,
+    random_state: RandomState,
 }
 
 impl ServiceServerList {
     pub fn new(socket: Arc<UdpNetworkSocket<UdpMessage<()>>>, config: Config) -> Self {
         log_info!("Creating service ServerList");
+        let region_map = match &config.region_file {
+            Some(path) => match RegionMap::load(path) {
+                Ok(region_map) if region_map.is_empty() => {
+                    log_warn!("No regions in {:?}", path);
+                    region_map
+                }
+                Ok(region_map) => region_map,
+                Err(err) => {
+                    log_warn!("Servers are not tagged by region: {}", err);
+                    RegionMap::default()
+                }
+            },
+            None => RegionMap::default(),
+        };
         Self {
             socket,
+            max_servers_per_ip: Cell::new(config.max_servers_per_ip),
             config,
             servers: RefCell::new(Map::default()),
+            region_map,
+            alt_addr_checks: RefCell::new(Map::default()),
+            random_state: RandomState::new(),
         }
     }
 
-    pub fn handle_server_info_req(&self, from_addr: SocketAddr) {
-        let now = Instant::now();
-        self.servers
-            .borrow_mut()
-            .retain(|_, (updated, _)| *updated + self.config.server_ping_timeout > now);
-        let server_list: Vec<_> = self
-            .servers
-            .borrow_mut()
-            .iter()
-            .map(|(_, server)| server.1.clone())
-            .collect();
-
-        let res_msg = UdpMessage::SysMessage(SysMessage::ServerInfoResGlobal {
-            servers: server_list,
-        });
+    /// Answers with the servers registered here and the ones registered at the peer hosts.
+    pub fn handle_server_info_req(
+        &self,
+        from_addr: SocketAddr,
+        peer_servers: Vec<NetworkServerInfo>,
+    ) {
+        let mut servers = self.servers();
+        for server in peer_servers {
+            if !servers.iter().any(|listed| listed.addr == server.addr) {
+                servers.push(server);
+            }
+        }
+        let res_msg = UdpMessage::SysMessage(SysMessage::ServerInfoResGlobal { servers });
 
         self.socket
             .send(from_addr, res_msg, self.config.socket_info_resp_timeout)
     }
-    pub fn handle_server_info_post(&self, from_addr: SocketAddr, server: NetworkServerInfo) {
+    pub fn handle_server_info_post(&self, from_addr: SocketAddr, mut server: NetworkServerInfo) {
         if from_addr == server.addr {
-            self.servers
-                .borrow_mut()
-                .insert(from_addr, (Instant::now(), server));
+            let now = Instant::now();
+            self.expire_servers(now);
+            let mut servers = self.servers.borrow_mut();
+
+            let servers_from_ip = servers
+                .keys()
+                .filter(|addr| addr.ip() == from_addr.ip())
+                .count();
+            if !servers.contains_key(&from_addr) && servers_from_ip >= self.max_servers_per_ip.get()
+            {
+                log_warn!(
+                    "Too many servers registered from {:?}, ignoring {:?}",
+                    from_addr.ip(),
+                    server.name
+                );
+                return;
+            }
+            // Anyone could claim any alternative address, so it is listed only once the server answers there
+            let verified_alt_addr = servers
+                .get(&from_addr)
+                .and_then(|(_, listed)| listed.alt_addr);
+            let claimed_alt_addr = server
+                .alt_addr
+                .filter(|alt_addr| is_valid_alt_addr(from_addr, *alt_addr));
+            if claimed_alt_addr != verified_alt_addr {
+                server.alt_addr = None;
+                if let Some(alt_addr) = claimed_alt_addr {
+                    self.check_alt_addr(from_addr, alt_addr);
+                }
+            }
+            server.region = self.region_of(&server);
+            servers.insert(from_addr, (now, server));
+        }
+    }
+    /// Lists the alternative address of the server the cookie was sent for.
+    pub fn handle_server_alt_addr_confirm(&self, from_addr: SocketAddr, cookie: u64) {
+        let mut alt_addr_checks = self.alt_addr_checks.borrow_mut();
+        let Some(&(server_addr, sent_cookie)) = alt_addr_checks.get(&from_addr) else {
+            return;
+        };
+        if cookie != sent_cookie {
+            return;
+        }
+        alt_addr_checks.remove(&from_addr);
+        if let Some((_, server)) = self.servers.borrow_mut().get_mut(&server_addr) {
+            log_info!("Server {:?} is also at {:?}", server.name, from_addr);
+            server.alt_addr = Some(from_addr);
+            server.region = self.region_of(server);
+        }
+    }
+    fn check_alt_addr(&self, server_addr: SocketAddr, alt_addr: SocketAddr) {
+        let cookie = self
+            .random_state
+            .hash_one((Instant::now(), server_addr, alt_addr));
+        {
+            let mut alt_addr_checks = self.alt_addr_checks.borrow_mut();
+            alt_addr_checks
+                .retain(|_, (checked_server_addr, _)| *checked_server_addr != server_addr);
+            alt_addr_checks.insert(alt_addr, (server_addr, cookie));
         }
+        // Unreliable, as the address may not be the server's, and the next post checks it again
+        let msg = UdpMessage::SysMessage(SysMessage::ServerAltAddrCheck { cookie });
+        self.socket.send(alt_addr, msg, Duration::ZERO);
+    }
+    /// Region from the region map, or the region of the host. Dual stack servers are located by either address, as
+    /// region maps often only cover IPv4.
+    fn region_of(&self, server: &NetworkServerInfo) -> Option<String> {
+        [Some(server.addr), server.alt_addr]
+            .into_iter()
+            .flatten()
+            .find_map(|addr| self.region_map.region_of(addr.ip()))
+            .map(str::to_owned)
+            .or_else(|| self.config.region.clone())
     }
     pub fn handle_server_info_delete(&self, from_addr: SocketAddr) {
         self.servers.borrow_mut().remove(&from_addr);
     }
+    pub fn handle_server_heartbeat(&self, from_addr: SocketAddr) {
+        let now = Instant::now();
+        self.expire_servers(now);
+        if let Some((updated, _)) = self.servers.borrow_mut().get_mut(&from_addr) {
+            *updated = now;
+        }
+    }
+
+    pub fn servers(&self) -> Vec<NetworkServerInfo> {
+        self.expire_servers(Instant::now());
+        self.servers
+            .borrow()
+            .values()
+            .map(|(_, server)| server.clone())
+            .collect()
+    }
+
+    /// Tells the registered servers that the host is going away.
+    pub fn shutdown(&self) {
+        for server in self.servers() {
+            let msg = UdpMessage::SysMessage(SysMessage::HostShutdown);
+            self.socket
+                .send(server.addr, msg, self.config.socket_info_resp_timeout);
+        }
+    }
+
+    /// Whether a server is listed at the address, or has proven that the address is its alternative address.
+    pub fn is_listed(&self, addr: SocketAddr) -> bool {
+        self.servers()
+            .iter()
+            .any(|server| server.addr == addr || server.alt_addr == Some(addr))
+    }
+
+    /// Describes the listed servers for an admin, one per line.
+    pub fn describe_servers(&self) -> String {
+        let now = Instant::now();
+        self.expire_servers(now);
+        self.servers
+            .borrow()
+            .iter()
+            .map(|(addr, (updated, server))| {
+                format!(
+                    "{} {:?} {}/{} players, seen {}s ago",
+                    addr,
+                    server.name,
+                    server.cur_player_count,
+                    server.max_player_count,
+                    now.duration_since(*updated).as_secs()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub fn evict_server(&self, addr: SocketAddr) -> Result<String, String> {
+        match self.servers.borrow_mut().remove(&addr) {
+            Some((_, server)) => {
+                log_info!("Evicted server {:?} at {:?}", server.name, addr);
+                Ok(format!("Evicted {:?}", server.name))
+            }
+            None => Err(format!("No server at {}", addr)),
+        }
+    }
+
+    pub fn set_max_servers_per_ip(&self, max_servers_per_ip: usize) {
+        self.max_servers_per_ip.set(max_servers_per_ip);
+    }
+
+    /// Removes the servers that have stopped sending heartbeats.
+    fn expire_servers(&self, now: Instant) {
+        let mut servers = self.servers.borrow_mut();
+        servers.retain(|addr, (updated, _)| {
+            let retain = *updated + self.config.server_ping_timeout > now;
+            if !retain {
+                log_info!("Server {:?} expired", addr);
+            }
+            retain
+        });
+        self.alt_addr_checks
+            .borrow_mut()
+            .retain(|_, (server_addr, _)| servers.contains_key(server_addr));
+    }
+}
+
+/// Whether the alternative address could be the server's in the other address family. The host sends to it, so it has
+/// to be a valid unicast address too.
+fn is_valid_alt_addr(server_addr: SocketAddr, alt_addr: SocketAddr) -> bool {
+    is_valid_source(alt_addr) && alt_addr.is_ipv6() != server_addr.is_ipv6()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server_info(addr: SocketAddr, alt_addr: SocketAddr) -> NetworkServerInfo {
+        NetworkServerInfo {
+            id: 1,
+            name: "server".to_owned(),
+            addr,
+            alt_addr: Some(alt_addr),
+            is_global: true,
+            has_password: false,
+            description: String::new(),
+            cur_player_count: 0,
+            max_player_count: 8,
+            region: None,
+            ws_host: None,
+            network_key: None,
+        }
+    }
+
+    #[test]
+    fn alt_addr_checks_are_limited_to_one_valid_address_per_server() {
+        let socket = Arc::new(UdpNetworkSocket::new(SocketAddr::from(([127, 0, 0, 1], 0))));
+        let server_list = ServiceServerList::new(socket, Config::default());
+        let server_addr = SocketAddr::from(([127, 0, 0, 1], 3400));
+
+        for alt_addr in [
+            SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], 0)),
+            SocketAddr::from(([0xff02, 0, 0, 0, 0, 0, 0, 1], 3400)),
+            SocketAddr::from(([10, 0, 0, 1], 3400)),
+        ] {
+            server_list.handle_server_info_post(server_addr, server_info(server_addr, alt_addr));
+        }
+        assert!(server_list.alt_addr_checks.borrow().is_empty());
+
+        for port in 3400..3410 {
+            let alt_addr = SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], port));
+            server_list.handle_server_info_post(server_addr, server_info(server_addr, alt_addr));
+        }
+        let alt_addr_checks = server_list.alt_addr_checks.borrow();
+        assert_eq!(alt_addr_checks.len(), 1);
+        assert!(alt_addr_checks.contains_key(&SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], 3409))));
+    }
 }
@@ -1,4 +1,4 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
 use ion_common::log_info;
 use ion_common::net::{udp_network_socket::UdpNetworkSocket, SysMessage, UdpMessage};
@@ -21,4 +21,10 @@ impl ServiceSocketInfo {
         self.socket
             .send(from_addr, res, self.config.socket_info_resp_timeout);
     }
+
+    /// Answered once, so that clients can measure their latency to the host.
+    pub fn handle_ping(&self, from_addr: SocketAddr) {
+        self.socket
+            .send(from_addr, UdpMessage::SysMessage(SysMessage::Pong), Duration::ZERO);
+    }
 }
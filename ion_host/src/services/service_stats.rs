@@ -0,0 +1,216 @@
+use std::{
+    cell::{Cell, RefCell},
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use bincode::config;
+use ion_common::net::{
+    LeaderboardEntry, StatSubmission, SysMessage, UdpMessage, udp_network_socket::UdpNetworkSocket,
+};
+use ion_common::{Map, PlayerIdentity, log_error, log_info, log_warn};
+
+use crate::config::Config;
+
+// Longer leaderboards are cut short, so that the answer stays small
+const MAX_LEADERBOARD_ENTRIES: u32 = 100;
+
+// Changed stats are saved at most this often, as the whole file is rewritten each time
+const SAVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Keeps the best value of each stat for each player, and answers leaderboard and player stat queries.
+/// Stats are saved to [`Config::stats_file`] if one is given, every few seconds while they change and when the host
+/// stops.
+pub struct ServiceStats {
+    socket: Arc<UdpNetworkSocket<UdpMessage<()>>>,
+    config: Config,
+    stats: RefCell<Map<String, Map<PlayerIdentity, i64>>>,
+    is_dirty: Cell<bool>,
+    saved: Cell<Instant>,
+}
+
+impl ServiceStats {
+    pub fn new(socket: Arc<UdpNetworkSocket<UdpMessage<()>>>, config: Config) -> Self {
+        log_info!("Creating service Stats");
+        let stats = load_stats(&config);
+        Self {
+            socket,
+            config,
+            stats: RefCell::new(stats),
+            is_dirty: Cell::new(false),
+            saved: Cell::new(Instant::now()),
+        }
+    }
+
+    pub fn handle_stat_submit(&self, from_addr: SocketAddr, submission: StatSubmission) {
+        let Some(key) = &self.config.stats_key else {
+            return;
+        };
+        if !submission.verify(key.as_bytes()) {
+            log_warn!(
+                "Invalid stat submission from {:?}: {:?}",
+                from_addr,
+                submission
+            );
+            return;
+        }
+
+        let mut stats = self.stats.borrow_mut();
+        let value = stats
+            .entry(submission.stat)
+            .or_default()
+            .entry(submission.player)
+            .or_insert(i64::MIN);
+        if submission.value > *value {
+            *value = submission.value;
+            self.is_dirty.set(true);
+        }
+    }
+
+    pub fn handle_leaderboard_req(&self, from_addr: SocketAddr, stat: String, count: u32) {
+        let mut entries: Vec<_> = self
+            .stats
+            .borrow()
+            .get(&stat)
+            .map(|players| {
+                players
+                    .iter()
+                    .map(|(player, value)| (player.clone(), *value))
+                    .collect()
+            })
+            .unwrap_or_default();
+        entries
+            .sort_by(|(player_a, a), (player_b, b)| b.cmp(a).then_with(|| player_a.cmp(player_b)));
+
+        let mut rank = 0;
+        let entries = entries
+            .iter()
+            .enumerate()
+            .take(count.min(MAX_LEADERBOARD_ENTRIES) as usize)
+            .map(|(i, (player, value))| {
+                if i == 0 || entries[i - 1].1 != *value {
+                    rank = i as u32 + 1;
+                }
+                LeaderboardEntry {
+                    rank,
+                    player: player.clone(),
+                    value: *value,
+                }
+            })
+            .collect();
+
+        let res = UdpMessage::SysMessage(SysMessage::LeaderboardRes { stat, entries });
+        self.socket
+            .send(from_addr, res, self.config.server_list_resp_timeout);
+    }
+
+    /// Whether the stats have changed since they were last saved, and it is time to save them again.
+    pub fn is_save_due(&self, now: Instant) -> bool {
+        self.is_dirty.get() && self.saved.get() + SAVE_INTERVAL <= now
+    }
+
+    /// Saves the stats if they have changed, so that nothing is lost when the host stops.
+    pub fn flush(&self) {
+        if self.is_dirty.replace(false) {
+            save_stats(&self.config, &self.stats.borrow());
+            self.saved.set(Instant::now());
+        }
+    }
+
+    pub fn handle_player_stats_req(&self, from_addr: SocketAddr, player: PlayerIdentity) {
+        let mut stats: Vec<_> = self
+            .stats
+            .borrow()
+            .iter()
+            .filter_map(|(stat, players)| players.get(&player).map(|value| (stat.clone(), *value)))
+            .collect();
+        stats.sort();
+
+        let res = UdpMessage::SysMessage(SysMessage::PlayerStatsRes { player, stats });
+        self.socket
+            .send(from_addr, res, self.config.server_list_resp_timeout);
+    }
+}
+
+fn load_stats(config: &Config) -> Map<String, Map<PlayerIdentity, i64>> {
+    let mut stats: Map<String, Map<PlayerIdentity, i64>> = Map::default();
+    let Some(bytes) = config
+        .stats_file
+        .as_ref()
+        .and_then(|path| std::fs::read(path).ok())
+    else {
+        return stats;
+    };
+    match bincode::decode_from_slice::<Vec<(String, PlayerIdentity, i64)>, _>(
+        &bytes,
+        config::standard(),
+    ) {
+        Ok((saved, _)) => {
+            for (stat, player, value) in saved {
+                stats.entry(stat).or_default().insert(player, value);
+            }
+        }
+        Err(err) => {
+            log_error!("Failed to load stats: {}", err);
+        }
+    }
+    stats
+}
+
+fn save_stats(config: &Config, stats: &Map<String, Map<PlayerIdentity, i64>>) {
+    let Some(path) = &config.stats_file else {
+        return;
+    };
+    let saved: Vec<_> = stats
+        .iter()
+        .flat_map(|(stat, players)| {
+            players
+                .iter()
+                .map(move |(player, value)| (stat, player, *value))
+        })
+        .collect();
+    let bytes = bincode::encode_to_vec(saved, config::standard()).unwrap();
+
+    // Written next to the file first, so that a crash while writing doesn't lose the previous stats
+    let tmp_path = path.with_extension("tmp");
+    if let Err(err) =
+        std::fs::write(&tmp_path, bytes).and_then(|_| std::fs::rename(&tmp_path, path))
+    {
+        log_error!("Failed to save stats: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changed_stats_are_saved_after_a_while() {
+        let config = Config {
+            stats_key: Some("stats-key".to_owned()),
+            ..Config::default()
+        };
+        let socket = Arc::new(UdpNetworkSocket::new(SocketAddr::from(([127, 0, 0, 1], 0))));
+        let service = ServiceStats::new(socket, config);
+        let from_addr = SocketAddr::from(([127, 0, 0, 1], 3400));
+        let now = Instant::now();
+
+        service.handle_stat_submit(
+            from_addr,
+            StatSubmission::new(b"stats-key", "kills", "alice", 3),
+        );
+        assert!(!service.is_save_due(now));
+        assert!(service.is_save_due(now + SAVE_INTERVAL));
+
+        service.flush();
+        assert!(!service.is_save_due(now + SAVE_INTERVAL));
+
+        // Lower values change nothing
+        service.handle_stat_submit(
+            from_addr,
+            StatSubmission::new(b"stats-key", "kills", "alice", 2),
+        );
+        assert!(!service.is_save_due(now + SAVE_INTERVAL * 2));
+    }
+}
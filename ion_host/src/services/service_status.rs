@@ -0,0 +1,428 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    io::{self, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use ion_common::net::{
+    HostStatus, HttpResponse, SysMessage, UdpMessage,
+    tls::{TlsServerConfig, TlsStream},
+    udp_network_socket::{UdpNetworkSocket, dual_stack_bind_addr},
+};
+use ion_common::{log_dbg, log_info, log_warn};
+
+use crate::config::Config;
+
+// The services refresh the status this often
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+// Health checks fail if the status hasn't been refreshed for this long, as the services must be stuck
+const STALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Each request has to arrive and be answered within this in total, so slow clients can't hold on to a connection
+const HTTP_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_HTTP_REQUEST_LEN: usize = 4096;
+// Requests are served concurrently, each on its own thread. Connections over these are closed right away, and the
+// limit per IP keeps a single client from taking all of them.
+const MAX_HTTP_CONNECTIONS: usize = 64;
+const MAX_HTTP_CONNECTIONS_PER_IP: usize = 4;
+
+/// Answers status queries over UDP, and over HTTP at [`Config::status_port`] if one is given.
+///
+/// The HTTP endpoint serves `GET /health`, answered with `200 OK` while the host runs fine and with
+/// `503 Service Unavailable` once it is draining or stuck, and `GET /status`, answered with the [`HostStatus`] as JSON.
+/// The endpoint serves https with the certificate of [`Config::tls_cert_file`] if one is given, and plain HTTP
+/// otherwise, in which case it is best kept private with [`Config::status_ip`].
+pub struct ServiceStatus {
+    socket: Arc<UdpNetworkSocket<UdpMessage<()>>>,
+    config: Config,
+    started: Instant,
+    status: Arc<Mutex<SharedStatus>>,
+    http_addr: Option<SocketAddr>,
+    http_closed: Arc<AtomicBool>,
+}
+
+/// Status shared with the HTTP thread.
+struct SharedStatus {
+    updated: Instant,
+    status: HostStatus,
+}
+
+impl ServiceStatus {
+    pub fn new(socket: Arc<UdpNetworkSocket<UdpMessage<()>>>, config: Config) -> Self {
+        log_info!("Creating service Status");
+        let started = Instant::now();
+        log_info!(
+            "Network key of the host is {}",
+            key_to_hex(&socket.public_key())
+        );
+        let status = Arc::new(Mutex::new(SharedStatus {
+            updated: started,
+            status: HostStatus {
+                version: env!("CARGO_PKG_VERSION").to_owned(),
+                uptime_secs: 0,
+                region: config.region.clone(),
+                is_draining: false,
+                server_count: 0,
+                peer_server_count: 0,
+                queued_player_count: 0,
+                relay_session_count: 0,
+                network_key: socket.public_key(),
+            },
+        }));
+        let http_closed = Arc::new(AtomicBool::new(false));
+
+        let http_addr = config.status_port.and_then(|port| {
+            let bind_addr = match config.status_ip {
+                Some(ip) => SocketAddr::new(ip, port),
+                None => dual_stack_bind_addr(port),
+            };
+            let tls = match config.tls_certificate().and_then(|certificate| {
+                certificate
+                    .map(|certificate| certificate.load().map_err(|err| err.to_string()))
+                    .transpose()
+            }) {
+                Ok(tls) => tls,
+                Err(err) => {
+                    log_warn!("Failed to serve status over https: {}", err);
+                    return None;
+                }
+            };
+            let listener = match TcpListener::bind(bind_addr) {
+                Ok(listener) => listener,
+                Err(err) => {
+                    log_warn!("Failed to serve status over HTTP at {}: {}", bind_addr, err);
+                    return None;
+                }
+            };
+            let scheme = if tls.is_some() { "https" } else { "HTTP" };
+            log_info!("Serving status over {} at {}", scheme, bind_addr);
+            let addr = listener.local_addr().ok();
+            let status = status.clone();
+            let http_closed = http_closed.clone();
+            std::thread::Builder::new()
+                .name("host_status_http".to_owned())
+                .spawn(move || serve_http(listener, tls, &status, &http_closed))
+                .unwrap();
+            addr
+        });
+
+        Self {
+            socket,
+            config,
+            started,
+            status,
+            http_addr,
+            http_closed,
+        }
+    }
+
+    pub fn is_update_due(&self, now: Instant) -> bool {
+        self.status.lock().unwrap().updated + UPDATE_INTERVAL <= now
+    }
+
+    /// Refreshes the status with the counts the function sets.
+    pub fn update(&self, now: Instant, update: impl FnOnce(&mut HostStatus)) {
+        let mut shared = self.status.lock().unwrap();
+        shared.updated = now;
+        shared.status.uptime_secs = now.duration_since(self.started).as_secs();
+        update(&mut shared.status);
+    }
+
+    /// Fails the health checks from now on, so that load balancers stop sending clients here.
+    pub fn shutdown(&self) {
+        self.status.lock().unwrap().status.is_draining = true;
+    }
+
+    pub fn handle_host_status_req(&self, from_addr: SocketAddr) {
+        let status = self.status.lock().unwrap().status.clone();
+        let res = UdpMessage::SysMessage(SysMessage::HostStatusRes { status });
+        self.socket
+            .send(from_addr, res, self.config.socket_info_resp_timeout);
+    }
+}
+
+impl Drop for ServiceStatus {
+    fn drop(&mut self) {
+        self.http_closed.store(true, Ordering::Relaxed);
+        // Wakes the HTTP thread up from waiting for connections, so that it sees the flag
+        if let Some(mut addr) = self.http_addr {
+            match addr.ip() {
+                IpAddr::V4(ip) if ip.is_unspecified() => addr.set_ip(Ipv4Addr::LOCALHOST.into()),
+                IpAddr::V6(ip) if ip.is_unspecified() => addr.set_ip(Ipv6Addr::LOCALHOST.into()),
+                _ => {}
+            }
+            let _ = TcpStream::connect_timeout(&addr, HTTP_TIMEOUT);
+        }
+    }
+}
+
+fn serve_http(
+    listener: TcpListener,
+    tls: Option<TlsServerConfig>,
+    status: &Arc<Mutex<SharedStatus>>,
+    closed: &AtomicBool,
+) {
+    // Open connections by the IP of the client
+    let connections: Arc<Mutex<HashMap<IpAddr, usize>>> = Arc::default();
+    for stream in listener.incoming() {
+        if closed.load(Ordering::Relaxed) {
+            break;
+        }
+        let Ok((stream, ip)) = stream.and_then(|stream| {
+            let ip = stream.peer_addr()?.ip();
+            Ok((stream, ip))
+        }) else {
+            continue;
+        };
+        {
+            let mut connections = connections.lock().unwrap();
+            let total = connections.values().sum::<usize>();
+            let from_ip = connections.get(&ip).copied().unwrap_or_default();
+            if total >= MAX_HTTP_CONNECTIONS || from_ip >= MAX_HTTP_CONNECTIONS_PER_IP {
+                log_dbg!("Refused status request from {:?}, too many connections", ip);
+                continue;
+            }
+            connections.insert(ip, from_ip + 1);
+        }
+
+        let release = {
+            let connections = connections.clone();
+            move || {
+                let mut connections = connections.lock().unwrap();
+                if let Some(count) = connections.get_mut(&ip) {
+                    *count -= 1;
+                    if *count == 0 {
+                        connections.remove(&ip);
+                    }
+                }
+            }
+        };
+        let status = status.clone();
+        let tls = tls.clone();
+        let spawned = std::thread::Builder::new()
+            .name("host_status_http_request".to_owned())
+            .spawn({
+                let release = release.clone();
+                move || {
+                    let handled = TlsStream::accept(stream, tls.as_ref())
+                        .and_then(|mut stream| handle_http_request(&mut stream, &status));
+                    if let Err(err) = handled {
+                        log_dbg!("Failed to answer status request: {}", err);
+                    }
+                    release();
+                }
+            });
+        if spawned.is_err() {
+            release();
+        }
+    }
+}
+
+/// The TLS handshake happens on the first read, so it has to be done within the deadline too.
+fn handle_http_request(stream: &mut TlsStream, status: &Mutex<SharedStatus>) -> io::Result<()> {
+    let deadline = Instant::now() + HTTP_TIMEOUT;
+    let remaining = || {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Request took too long",
+            ))
+        } else {
+            Ok(remaining)
+        }
+    };
+
+    // Only the request line matters, but the headers are read too so that the client sees the whole request handled
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n")
+        && request.len() < MAX_HTTP_REQUEST_LEN
+    {
+        stream.tcp_stream().set_read_timeout(Some(remaining()?))?;
+        let len = stream.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..len]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .split_whitespace();
+
+    let (is_healthy, status) = {
+        let shared = status.lock().unwrap();
+        let is_healthy = !shared.status.is_draining && shared.updated.elapsed() < STALL_TIMEOUT;
+        (is_healthy, shared.status.clone())
+    };
+    let health_code = if is_healthy { 200 } else { 503 };
+    let (status_code, content_type, body) = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some("/health")) => (
+            health_code,
+            "text/plain",
+            reason_phrase(health_code).to_owned(),
+        ),
+        (Some("GET"), Some("/status")) => {
+            (health_code, "application/json", status_to_json(&status))
+        }
+        (Some("GET"), _) => (404, "text/plain", reason_phrase(404).to_owned()),
+        _ => (405, "text/plain", reason_phrase(405).to_owned()),
+    };
+
+    let response = HttpResponse {
+        status_code,
+        headers: HashMap::from([
+            ("Content-Type".to_owned(), content_type.to_owned()),
+            ("Content-Length".to_owned(), body.len().to_string()),
+            ("Connection".to_owned(), "close".to_owned()),
+        ]),
+        body: body.into_bytes(),
+    };
+    stream.tcp_stream().set_write_timeout(Some(remaining()?))?;
+    stream.write_all(&http_response_to_bytes(&response))?;
+    stream.flush()
+}
+
+fn http_response_to_bytes(response: &HttpResponse) -> Vec<u8> {
+    let mut response_str = format!(
+        "HTTP/1.1 {} {}\r\n",
+        response.status_code,
+        reason_phrase(response.status_code)
+    );
+    for (key, value) in &response.headers {
+        response_str.push_str(&format!("{}: {}\r\n", key, value));
+    }
+    response_str.push_str("\r\n");
+
+    let mut response_bytes = response_str.into_bytes();
+    response_bytes.extend_from_slice(&response.body);
+    response_bytes
+}
+
+fn reason_phrase(status_code: u16) -> &'static str {
+    match status_code {
+        200 => "OK",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+fn status_to_json(status: &HostStatus) -> String {
+    let region = status
+        .region
+        .as_deref()
+        .map_or("null".to_owned(), json_string);
+    format!(
+        "{{\"version\":{},\"uptime_secs\":{},\"region\":{},\"is_draining\":{},\"server_count\":{},\
+         \"peer_server_count\":{},\"queued_player_count\":{},\"relay_session_count\":{},\"network_key\":\"{}\"}}",
+        json_string(&status.version),
+        status.uptime_secs,
+        region,
+        status.is_draining,
+        status.server_count,
+        status.peer_server_count,
+        status.queued_player_count,
+        status.relay_session_count,
+        key_to_hex(&status.network_key)
+    )
+}
+
+fn key_to_hex(key: &[u8; 32]) -> String {
+    key.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn json_string(string: &str) -> String {
+    let mut json = String::from('"');
+    for c in string.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ion_common::net::tls::TlsClientConfig;
+
+    #[test]
+    fn status_is_written_as_json() {
+        let status = HostStatus {
+            version: "0.1.0".to_owned(),
+            uptime_secs: 12,
+            region: Some("eu \"west\"\n".to_owned()),
+            is_draining: false,
+            server_count: 3,
+            peer_server_count: 2,
+            queued_player_count: 1,
+            relay_session_count: 0,
+            network_key: [0xab; 32],
+        };
+        assert_eq!(
+            status_to_json(&status),
+            "{\"version\":\"0.1.0\",\"uptime_secs\":12,\"region\":\"eu \\\"west\\\"\\u000a\",\"is_draining\":false,\
+             \"server_count\":3,\"peer_server_count\":2,\"queued_player_count\":1,\"relay_session_count\":0,\
+             \"network_key\":\"abababababababababababababababababababababababababababababababab\"}"
+        );
+    }
+
+    #[test]
+    fn status_is_served_over_tls() {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        let dir = std::env::temp_dir().join(format!("ion_host_status_tls_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cert_file = dir.join("cert.pem");
+        let key_file = dir.join("key.pem");
+        std::fs::write(&cert_file, cert.cert.pem()).unwrap();
+        std::fs::write(&key_file, cert.key_pair.serialize_pem()).unwrap();
+
+        let config = Config {
+            status_port: Some(0),
+            status_ip: Some(IpAddr::from([127, 0, 0, 1])),
+            tls_cert_file: Some(cert_file.clone()),
+            tls_key_file: Some(key_file),
+            ..Config::default()
+        };
+        let socket = Arc::new(UdpNetworkSocket::new(SocketAddr::from(([127, 0, 0, 1], 0))));
+        let service = ServiceStatus::new(socket, config);
+        let http_addr = service.http_addr.unwrap();
+
+        let tls = TlsClientConfig::new(Some(&cert_file)).unwrap();
+        let mut stream =
+            TlsStream::connect(TcpStream::connect(http_addr).unwrap(), "localhost", &tls).unwrap();
+        write!(stream, "GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        stream.flush().unwrap();
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        assert!(String::from_utf8_lossy(&response).starts_with("HTTP/1.1 200 OK\r\n"));
+
+        // Plain HTTP isn't answered
+        let mut stream = TcpStream::connect(http_addr).unwrap();
+        write!(stream, "GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        assert!(!String::from_utf8_lossy(&response).contains("200 OK"));
+
+        drop(service);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
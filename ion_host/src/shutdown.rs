@@ -0,0 +1,31 @@
+use ion_common::log_warn;
+
+/// Makes SIGINT and SIGTERM set the returned flag, which stops [`run_ion_host`](crate::run_ion_host) gracefully.
+pub use ion_common::util::signals::register_shutdown_signals;
+
+/// Tells systemd about the state of the service, such as `READY=1` or `STOPPING=1`, if it was started with
+/// `Type=notify`. Does nothing otherwise.
+pub(crate) fn notify_systemd(state: &str) {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+        let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+            return;
+        };
+        let path = path.to_string_lossy();
+        // Names starting with @ are in the abstract namespace
+        let addr = match path.strip_prefix('@') {
+            Some(name) => SocketAddr::from_abstract_name(name.as_bytes()),
+            None => SocketAddr::from_pathname(path.as_ref()),
+        };
+        let result = UnixDatagram::unbound()
+            .and_then(|socket| addr.and_then(|addr| socket.send_to_addr(state.as_bytes(), &addr)));
+        if let Err(err) = result {
+            log_warn!("Failed to notify systemd of {:?}: {}", state, err);
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = state;
+}
@@ -1,30 +1,69 @@
-use std::net::SocketAddr;
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Mutex, MutexGuard, OnceLock};
 use std::thread::{JoinHandle, sleep};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use ion_common::net::udp_network_socket::UdpNetworkSocket;
-use ion_common::net::{NetworkServerInfo, SysMessage, UdpMessage};
+use ion_common::net::{
+    HostAdminCommand, LeaderboardEntry, MatchAssignment, MatchCriteria, NetworkPlayerInfo,
+    NetworkServerInfo, StatSubmission, SysMessage, UdpMessage,
+};
 use ion_common::{self, LogLevel};
+use ion_host::admin::HostAdminClient;
 use ion_host::config::Config;
 use ion_host::run_ion_host;
 
 static TEST_SERVICES: OnceLock<JoinHandle<()>> = OnceLock::new();
 static TEST_LOCK: OnceLock<Mutex<()>> = OnceLock::new();
+static TEST_SHUTDOWN: AtomicBool = AtomicBool::new(false);
 
-fn start_test_services_if_needed() -> SocketAddr {
-    let test_config = Config {
+fn test_config() -> Config {
+    Config {
         port: 3333,
         server_ping_timeout: Duration::from_secs(2),
         nat_punch_relay_timeout: Duration::from_secs(2),
         socket_info_resp_timeout: Duration::from_secs(2),
         server_list_resp_timeout: Duration::from_secs(2),
-    };
+        max_requests_per_sec: 20,
+        max_request_burst: 50,
+        max_frames_per_sec: 500,
+        max_frame_burst: 1000,
+        max_servers_per_ip: 4,
+        match_queue_timeout: Duration::from_secs(2),
+        relay_max_sessions: 4,
+        relay_max_sessions_per_ip: 2,
+        relay_bytes_per_sec: 4096,
+        relay_idle_timeout: Duration::from_secs(2),
+        relay_pending_timeout: Duration::from_secs(1),
+        admin_password: Some("test-admin".to_owned()),
+        network_key_file: None,
+        stats_key: Some("test-stats".to_owned()),
+        stats_file: None,
+        region: None,
+        region_file: None,
+        federation_peers: vec![SocketAddr::from(([127, 0, 0, 1], 3345))],
+        federation_sync_interval: Duration::from_millis(200),
+        federation_secret: Some("test-federation".to_owned()),
+        drain_timeout: Duration::from_millis(500),
+        status_port: Some(3349),
+        status_ip: Some(IpAddr::from([127, 0, 0, 1])),
+        tls_cert_file: None,
+        tls_key_file: None,
+        log_level: LogLevel::Info,
+    }
+}
+
+fn start_test_services_if_needed() -> SocketAddr {
     TEST_SERVICES.get_or_init(move || {
-        std::thread::spawn(move || {
+        let handle = std::thread::spawn(move || {
             ion_common::set_logger_on(LogLevel::Info);
-            run_ion_host(test_config)
-        })
+            run_ion_host(test_config(), &TEST_SHUTDOWN)
+        });
+        // Gives the services time to bind, so that the first requests aren't lost
+        sleep(Duration::from_millis(100));
+        handle
     });
     SocketAddr::from(([127, 0, 0, 1], 3333))
 }
@@ -71,22 +110,30 @@ fn server_info_service_works() {
         id: 23,
         name: "test1".to_string(),
         addr,
+        alt_addr: None,
         is_global: false,
         has_password: false,
         description: "".to_string(),
         cur_player_count: 0,
         max_player_count: 0,
+        region: None,
+        ws_host: None,
+        network_key: None,
     };
 
     let server_2 = NetworkServerInfo {
         id: 24,
         name: "test2".to_string(),
         addr: addr2,
+        alt_addr: None,
         is_global: false,
         has_password: false,
         description: "".to_string(),
         cur_player_count: 0,
         max_player_count: 0,
+        region: None,
+        ws_host: None,
+        network_key: None,
     };
 
     socket.send(
@@ -154,6 +201,101 @@ fn server_info_service_works() {
     }
 }
 
+#[test]
+fn server_alt_addr_is_listed_once_confirmed() {
+    let service_addr = start_test_services_if_needed();
+    let _test_lock = acquire_test_lock();
+
+    // The alternative address is the one in the other address family
+    let addr = SocketAddr::from(([127, 0, 0, 1], 3334));
+    let alt_addr = SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], 3335));
+    let alt_service_addr = SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], service_addr.port()));
+    let socket: UdpNetworkSocket<UdpMessage<()>> = UdpNetworkSocket::new(addr);
+    let alt_socket: UdpNetworkSocket<UdpMessage<()>> = UdpNetworkSocket::new(alt_addr);
+
+    let server = NetworkServerInfo {
+        id: 25,
+        name: "test-dual-stack".to_string(),
+        addr,
+        alt_addr: Some(alt_addr),
+        is_global: false,
+        has_password: false,
+        description: "".to_string(),
+        cur_player_count: 0,
+        max_player_count: 0,
+        region: None,
+        ws_host: None,
+        network_key: None,
+    };
+    let listed_alt_addr = || {
+        socket.send(
+            service_addr,
+            UdpMessage::SysMessage(SysMessage::ServerInfoReq),
+            Duration::from_secs(5),
+        );
+        match socket.try_recv_timeout(Duration::from_secs(1)).unwrap().1 {
+            UdpMessage::SysMessage(SysMessage::ServerInfoResGlobal { servers }) => servers
+                .into_iter()
+                .find(|listed| listed.id == server.id)
+                .map(|listed| listed.alt_addr),
+            msg => panic!("Wrong message type: {:?}", msg),
+        }
+    };
+
+    // Posts over the alternative address are not accepted, as anyone could claim it
+    alt_socket.send(
+        alt_service_addr,
+        UdpMessage::SysMessage(SysMessage::ServerInfoPost {
+            server: server.clone(),
+        }),
+        Duration::from_secs(5),
+    );
+    sleep(Duration::from_millis(10));
+    assert_eq!(listed_alt_addr(), None);
+
+    socket.send(
+        service_addr,
+        UdpMessage::SysMessage(SysMessage::ServerInfoPost {
+            server: server.clone(),
+        }),
+        Duration::from_secs(5),
+    );
+    let cookie = match alt_socket
+        .try_recv_timeout(Duration::from_secs(1))
+        .unwrap()
+        .1
+    {
+        UdpMessage::SysMessage(SysMessage::ServerAltAddrCheck { cookie }) => cookie,
+        msg => panic!("Wrong message type: {:?}", msg),
+    };
+    assert_eq!(listed_alt_addr(), Some(None));
+
+    alt_socket.send(
+        alt_service_addr,
+        UdpMessage::SysMessage(SysMessage::ServerAltAddrConfirm {
+            cookie: cookie.wrapping_add(1),
+        }),
+        Duration::from_secs(5),
+    );
+    sleep(Duration::from_millis(10));
+    assert_eq!(listed_alt_addr(), Some(None));
+
+    alt_socket.send(
+        alt_service_addr,
+        UdpMessage::SysMessage(SysMessage::ServerAltAddrConfirm { cookie }),
+        Duration::from_secs(5),
+    );
+    sleep(Duration::from_millis(10));
+    assert_eq!(listed_alt_addr(), Some(Some(alt_addr)));
+
+    socket.send(
+        service_addr,
+        UdpMessage::SysMessage(SysMessage::ServerInfoDelete),
+        Duration::from_secs(5),
+    );
+    sleep(Duration::from_millis(10));
+}
+
 #[test]
 fn nat_punch_service_works() {
     let service_addr = start_test_services_if_needed();
@@ -181,3 +323,594 @@ fn nat_punch_service_works() {
         UdpMessage::MpMessage(_) => panic!("Wrong message type"),
     }
 }
+
+#[test]
+fn server_heartbeat_keeps_server_listed() {
+    let service_addr = start_test_services_if_needed();
+    let _test_lock = acquire_test_lock();
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], 3336));
+    let socket: UdpNetworkSocket<UdpMessage<()>> = UdpNetworkSocket::new(addr);
+
+    let server = NetworkServerInfo {
+        id: 25,
+        name: "test3".to_string(),
+        addr,
+        alt_addr: None,
+        is_global: false,
+        has_password: false,
+        description: "".to_string(),
+        cur_player_count: 0,
+        max_player_count: 0,
+        region: None,
+        ws_host: None,
+        network_key: None,
+    };
+
+    let is_listed = |socket: &UdpNetworkSocket<UdpMessage<()>>| {
+        socket.send(
+            service_addr,
+            UdpMessage::SysMessage(SysMessage::ServerInfoReq),
+            Duration::from_secs(5),
+        );
+        match socket.try_recv_timeout(Duration::from_secs(1)).unwrap().1 {
+            UdpMessage::SysMessage(SysMessage::ServerInfoResGlobal { servers }) => {
+                servers.contains(&server)
+            }
+            _ => panic!("Wrong message type"),
+        }
+    };
+
+    socket.send(
+        service_addr,
+        UdpMessage::SysMessage(SysMessage::ServerInfoPost {
+            server: server.clone(),
+        }),
+        Duration::from_secs(5),
+    );
+    sleep(Duration::from_millis(1500));
+
+    socket.send(
+        service_addr,
+        UdpMessage::SysMessage(SysMessage::ServerHeartbeat),
+        Duration::from_secs(5),
+    );
+    sleep(Duration::from_millis(1000));
+    assert!(is_listed(&socket));
+
+    // Without heartbeats the server expires
+    sleep(Duration::from_millis(2500));
+    assert!(!is_listed(&socket));
+}
+
+#[test]
+fn admin_commands_require_password() {
+    let service_addr = start_test_services_if_needed();
+    let _test_lock = acquire_test_lock();
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], 3337));
+    let socket: UdpNetworkSocket<UdpMessage<()>> = UdpNetworkSocket::new(addr);
+    let server = NetworkServerInfo {
+        id: 26,
+        name: "test4".to_string(),
+        addr,
+        alt_addr: None,
+        is_global: false,
+        has_password: false,
+        description: "".to_string(),
+        cur_player_count: 0,
+        max_player_count: 0,
+        region: None,
+        ws_host: None,
+        network_key: None,
+    };
+    socket.send(
+        service_addr,
+        UdpMessage::SysMessage(SysMessage::ServerInfoPost { server }),
+        Duration::from_secs(5),
+    );
+    sleep(Duration::from_millis(10));
+
+    // Not accepted without encryption, even with the right password
+    socket.send(
+        service_addr,
+        UdpMessage::SysMessage(SysMessage::HostAdminReq {
+            token: b"test-admin".to_vec(),
+            command: HostAdminCommand::ListServers,
+        }),
+        Duration::from_secs(5),
+    );
+    match socket.try_recv_timeout(Duration::from_secs(1)).unwrap().1 {
+        UdpMessage::SysMessage(SysMessage::HostAdminRes { result }) => assert!(result.is_err()),
+        msg => panic!("Wrong message type: {:?}", msg),
+    }
+
+    // Admins pin the key the host publishes in its status
+    socket.send(
+        service_addr,
+        UdpMessage::SysMessage(SysMessage::HostStatusReq),
+        Duration::from_secs(5),
+    );
+    let host_key = match socket.try_recv_timeout(Duration::from_secs(1)).unwrap().1 {
+        UdpMessage::SysMessage(SysMessage::HostStatusRes { status }) => status.network_key,
+        msg => panic!("Wrong message type: {:?}", msg),
+    };
+
+    let timeout = Duration::from_secs(1);
+    let intruder = HostAdminClient::new(
+        SocketAddr::from(([127, 0, 0, 1], 3339)),
+        service_addr,
+        host_key,
+        "guess",
+    );
+    assert!(
+        intruder
+            .execute(HostAdminCommand::ListServers, timeout)
+            .is_err()
+    );
+
+    let admin = HostAdminClient::new(
+        SocketAddr::from(([127, 0, 0, 1], 3338)),
+        service_addr,
+        host_key,
+        "test-admin",
+    );
+    assert!(
+        admin
+            .execute(HostAdminCommand::ListServers, timeout)
+            .unwrap()
+            .contains("test4")
+    );
+    assert!(
+        admin
+            .execute(HostAdminCommand::EvictServer { addr }, timeout)
+            .is_ok()
+    );
+    assert!(
+        !admin
+            .execute(HostAdminCommand::ListServers, timeout)
+            .unwrap()
+            .contains("test4")
+    );
+    assert!(
+        admin
+            .execute(HostAdminCommand::EvictServer { addr }, timeout)
+            .is_err()
+    );
+}
+
+#[test]
+fn matchmaking_service_works() {
+    let service_addr = start_test_services_if_needed();
+    let _test_lock = acquire_test_lock();
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], 3340));
+    let addr2 = SocketAddr::from(([127, 0, 0, 1], 3341));
+    let socket: UdpNetworkSocket<UdpMessage<()>> = UdpNetworkSocket::new(addr);
+    let socket2: UdpNetworkSocket<UdpMessage<()>> = UdpNetworkSocket::new(addr2);
+
+    let criteria = MatchCriteria {
+        game_mode: "duel".to_string(),
+        region: "eu".to_string(),
+        skill_bucket: 3,
+        match_size: 2,
+    };
+    let player = |id, addr| NetworkPlayerInfo {
+        id,
+        name: format!("player{}", id),
+        addr,
+        identity: None,
+    };
+    let next_match_msg = |socket: &UdpNetworkSocket<UdpMessage<()>>| loop {
+        match socket.try_recv_timeout(Duration::from_secs(1)).unwrap().1 {
+            UdpMessage::SysMessage(SysMessage::NatPunchStart { .. }) => {}
+            UdpMessage::SysMessage(msg) => return msg,
+            UdpMessage::MpMessage(_) => panic!("Wrong message type"),
+        }
+    };
+
+    socket.send(
+        service_addr,
+        UdpMessage::SysMessage(SysMessage::MatchReq {
+            player: player(1, addr),
+            criteria: criteria.clone(),
+        }),
+        Duration::from_secs(5),
+    );
+    assert_eq!(
+        next_match_msg(&socket),
+        SysMessage::MatchQueued { position: 1 }
+    );
+
+    socket2.send(
+        service_addr,
+        UdpMessage::SysMessage(SysMessage::MatchReq {
+            player: player(2, addr2),
+            criteria,
+        }),
+        Duration::from_secs(5),
+    );
+    assert_eq!(
+        next_match_msg(&socket2),
+        SysMessage::MatchQueued { position: 2 }
+    );
+
+    // The player who queued first hosts the match
+    assert_eq!(
+        next_match_msg(&socket),
+        SysMessage::MatchFound {
+            assignment: MatchAssignment::Host {
+                players: vec![player(2, addr2)]
+            }
+        }
+    );
+    assert_eq!(
+        next_match_msg(&socket2),
+        SysMessage::MatchFound {
+            assignment: MatchAssignment::Client {
+                host: player(1, addr)
+            }
+        }
+    );
+}
+
+#[test]
+fn stats_service_works() {
+    let service_addr = start_test_services_if_needed();
+    let _test_lock = acquire_test_lock();
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], 3342));
+    let socket: UdpNetworkSocket<UdpMessage<()>> = UdpNetworkSocket::new(addr);
+
+    let submissions = [
+        StatSubmission::new(b"test-stats", "kills", "alice", 7),
+        StatSubmission::new(b"test-stats", "kills", "bob", 12),
+        StatSubmission::new(b"test-stats", "kills", "carol", 7),
+        // Lower values than the best one are ignored
+        StatSubmission::new(b"test-stats", "kills", "bob", 3),
+        // Submissions signed with another key are ignored
+        StatSubmission::new(b"guess", "kills", "mallory", 1000),
+    ];
+    for submission in submissions {
+        socket.send(
+            service_addr,
+            UdpMessage::SysMessage(SysMessage::StatSubmit { submission }),
+            Duration::from_secs(5),
+        );
+    }
+    sleep(Duration::from_millis(10));
+
+    socket.send(
+        service_addr,
+        UdpMessage::SysMessage(SysMessage::LeaderboardReq {
+            stat: "kills".to_string(),
+            count: 10,
+        }),
+        Duration::from_secs(5),
+    );
+    let entry = |rank, player: &str, value| LeaderboardEntry {
+        rank,
+        player: player.to_string(),
+        value,
+    };
+    match socket.try_recv_timeout(Duration::from_secs(1)).unwrap().1 {
+        UdpMessage::SysMessage(SysMessage::LeaderboardRes { entries, .. }) => assert_eq!(
+            entries,
+            vec![
+                entry(1, "bob", 12),
+                entry(2, "alice", 7),
+                entry(2, "carol", 7)
+            ]
+        ),
+        _ => panic!("Wrong message type"),
+    }
+}
+
+#[test]
+fn relay_service_works() {
+    let service_addr = start_test_services_if_needed();
+    let _test_lock = acquire_test_lock();
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], 3343));
+    let addr2 = SocketAddr::from(([127, 0, 0, 1], 3344));
+    let socket: UdpNetworkSocket<UdpMessage<()>> = UdpNetworkSocket::new(addr);
+    let socket2: UdpNetworkSocket<UdpMessage<()>> = UdpNetworkSocket::new(addr2);
+    let recv = |socket: &UdpNetworkSocket<UdpMessage<()>>| match socket
+        .try_recv_timeout(Duration::from_secs(1))
+        .unwrap()
+        .1
+    {
+        UdpMessage::SysMessage(msg) => msg,
+        UdpMessage::MpMessage(_) => panic!("Wrong message type"),
+    };
+
+    // The request is passed on, and the session opens once the other peer asks too
+    socket.send(
+        service_addr,
+        UdpMessage::SysMessage(SysMessage::RelayOpen { peer: addr2 }),
+        Duration::from_secs(5),
+    );
+    assert_eq!(recv(&socket2), SysMessage::RelayOpen { peer: addr });
+    socket2.send(
+        service_addr,
+        UdpMessage::SysMessage(SysMessage::RelayOpen { peer: addr }),
+        Duration::from_secs(5),
+    );
+    let token = match recv(&socket) {
+        SysMessage::RelayOpened { token, peer } if peer == addr2 => token,
+        msg => panic!("Wrong message: {:?}", msg),
+    };
+    assert_eq!(
+        recv(&socket2),
+        SysMessage::RelayOpened { token, peer: addr }
+    );
+
+    socket.send(
+        service_addr,
+        UdpMessage::SysMessage(SysMessage::RelayData {
+            token,
+            data: vec![1, 2, 3],
+            reliable: true,
+        }),
+        Duration::from_secs(5),
+    );
+    assert_eq!(
+        recv(&socket2),
+        SysMessage::RelayData {
+            token,
+            data: vec![1, 2, 3],
+            reliable: true,
+        }
+    );
+
+    // Data over the bandwidth of the session is dropped
+    socket2.send(
+        service_addr,
+        UdpMessage::SysMessage(SysMessage::RelayData {
+            token,
+            data: vec![0; 10000],
+            reliable: false,
+        }),
+        Duration::ZERO,
+    );
+    assert!(
+        socket
+            .try_recv_timeout(Duration::from_millis(200))
+            .is_none()
+    );
+
+    socket.send(
+        service_addr,
+        UdpMessage::SysMessage(SysMessage::RelayClose { token }),
+        Duration::from_secs(5),
+    );
+    assert_eq!(recv(&socket2), SysMessage::RelayClose { token });
+
+    // Requests nobody agreed to count against the requester too, until they expire
+    let peers = [3390, 3391, 3392].map(|port| SocketAddr::from(([127, 0, 0, 1], port)));
+    for peer in peers {
+        socket.send(
+            service_addr,
+            UdpMessage::SysMessage(SysMessage::RelayOpen { peer }),
+            Duration::from_secs(5),
+        );
+    }
+    assert_eq!(
+        recv(&socket),
+        SysMessage::RelayRefused {
+            peer: peers[2],
+            reason: "Too many relays from this address".to_owned(),
+        }
+    );
+    sleep(Duration::from_millis(1100));
+    socket.send(
+        service_addr,
+        UdpMessage::SysMessage(SysMessage::RelayOpen { peer: peers[2] }),
+        Duration::from_secs(5),
+    );
+    assert!(
+        socket
+            .try_recv_timeout(Duration::from_millis(500))
+            .is_none()
+    );
+}
+
+#[test]
+fn federated_hosts_share_server_list() {
+    let service_addr = start_test_services_if_needed();
+    let _test_lock = acquire_test_lock();
+
+    // Peer host of another region, sharing its server list with the test services
+    let peer_addr = SocketAddr::from(([127, 0, 0, 1], 3345));
+    let peer_config = Config {
+        port: 3345,
+        status_port: None,
+        status_ip: None,
+        tls_cert_file: None,
+        tls_key_file: None,
+        region: Some("eu".to_owned()),
+        federation_peers: vec![service_addr],
+        ..test_config()
+    };
+    std::thread::spawn(move || run_ion_host(peer_config, &TEST_SHUTDOWN));
+    sleep(Duration::from_millis(100));
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], 3346));
+    let socket: UdpNetworkSocket<UdpMessage<()>> = UdpNetworkSocket::new(addr);
+
+    socket.send(
+        peer_addr,
+        UdpMessage::SysMessage(SysMessage::HostInfoReq),
+        Duration::from_secs(5),
+    );
+    match socket.try_recv_timeout(Duration::from_secs(1)).unwrap().1 {
+        UdpMessage::SysMessage(SysMessage::HostInfoRes { region, peers }) => {
+            assert_eq!(region.as_deref(), Some("eu"));
+            assert_eq!(peers, vec![service_addr]);
+        }
+        _ => panic!("Wrong message type"),
+    }
+
+    let server = NetworkServerInfo {
+        id: 25,
+        name: "federated".to_string(),
+        addr,
+        alt_addr: None,
+        is_global: true,
+        has_password: false,
+        description: "".to_string(),
+        cur_player_count: 0,
+        max_player_count: 0,
+        region: None,
+        ws_host: None,
+        network_key: None,
+    };
+    socket.send(
+        peer_addr,
+        UdpMessage::SysMessage(SysMessage::ServerInfoPost {
+            server: server.clone(),
+        }),
+        Duration::from_secs(5),
+    );
+
+    // The server is listed by both hosts, with the region of the host it registered at
+    let listed_at_host = |is_listed: bool| {
+        (0..20).any(|_| {
+            sleep(Duration::from_millis(100));
+            socket.send(
+                service_addr,
+                UdpMessage::SysMessage(SysMessage::ServerInfoReq),
+                Duration::from_secs(5),
+            );
+            match socket.try_recv_timeout(Duration::from_secs(1)).unwrap().1 {
+                UdpMessage::SysMessage(SysMessage::ServerInfoResGlobal { servers }) => {
+                    let listed = servers.iter().find(|listed| listed.addr == addr);
+                    match listed {
+                        Some(listed) => {
+                            assert_eq!(listed.region.as_deref(), Some("eu"));
+                            is_listed
+                        }
+                        None => !is_listed,
+                    }
+                }
+                _ => panic!("Wrong message type"),
+            }
+        })
+    };
+    assert!(listed_at_host(true));
+
+    socket.send(
+        peer_addr,
+        UdpMessage::SysMessage(SysMessage::ServerInfoDelete),
+        Duration::from_secs(5),
+    );
+    assert!(listed_at_host(false));
+}
+
+#[test]
+fn host_shuts_down_gracefully() {
+    let _test_lock = acquire_test_lock();
+
+    // Separate host, so that the other tests can keep using theirs
+    static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+    let host_addr = SocketAddr::from(([127, 0, 0, 1], 3347));
+    let host_config = Config {
+        port: 3347,
+        status_port: None,
+        status_ip: None,
+        tls_cert_file: None,
+        tls_key_file: None,
+        federation_peers: Vec::new(),
+        ..test_config()
+    };
+    let handle = std::thread::spawn(move || run_ion_host(host_config, &SHUTDOWN));
+    sleep(Duration::from_millis(100));
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], 3348));
+    let socket: UdpNetworkSocket<UdpMessage<()>> = UdpNetworkSocket::new(addr);
+    let server = NetworkServerInfo {
+        id: 26,
+        name: "draining".to_string(),
+        addr,
+        alt_addr: None,
+        is_global: true,
+        has_password: false,
+        description: "".to_string(),
+        cur_player_count: 0,
+        max_player_count: 0,
+        region: None,
+        ws_host: None,
+        network_key: None,
+    };
+    socket.send(
+        host_addr,
+        UdpMessage::SysMessage(SysMessage::ServerInfoPost { server }),
+        Duration::from_secs(5),
+    );
+    sleep(Duration::from_millis(100));
+
+    // Registered servers are told about the shutdown
+    SHUTDOWN.store(true, Ordering::Relaxed);
+    match socket.try_recv_timeout(Duration::from_secs(1)).unwrap().1 {
+        UdpMessage::SysMessage(SysMessage::HostShutdown) => {}
+        _ => panic!("Wrong message type"),
+    }
+
+    // Requests are still answered while draining
+    socket.send(
+        host_addr,
+        UdpMessage::SysMessage(SysMessage::ServerInfoReq),
+        Duration::from_secs(5),
+    );
+    match socket.try_recv_timeout(Duration::from_secs(1)).unwrap().1 {
+        UdpMessage::SysMessage(SysMessage::ServerInfoResGlobal { .. }) => {}
+        _ => panic!("Wrong message type"),
+    }
+
+    let started = std::time::Instant::now();
+    handle.join().unwrap();
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn status_service_works() {
+    let service_addr = start_test_services_if_needed();
+    let _test_lock = acquire_test_lock();
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], 3350));
+    let socket: UdpNetworkSocket<UdpMessage<()>> = UdpNetworkSocket::new(addr);
+    socket.send(
+        service_addr,
+        UdpMessage::SysMessage(SysMessage::HostStatusReq),
+        Duration::from_secs(5),
+    );
+    match socket.try_recv_timeout(Duration::from_secs(1)).unwrap().1 {
+        UdpMessage::SysMessage(SysMessage::HostStatusRes { status }) => {
+            assert_eq!(status.version, env!("CARGO_PKG_VERSION"));
+            assert!(!status.is_draining);
+        }
+        _ => panic!("Wrong message type"),
+    }
+
+    let http_get = |path: &str| {
+        let mut stream = TcpStream::connect(("127.0.0.1", 3349)).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+    assert!(http_get("/health").starts_with("HTTP/1.1 200 OK\r\n"));
+    let status = http_get("/status");
+    assert!(status.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(status.contains("\"is_draining\":false"));
+    assert!(http_get("/missing").starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+    // Clients that never finish their request don't hold up the others
+    let _stalled = [
+        TcpStream::connect(("127.0.0.1", 3349)).unwrap(),
+        TcpStream::connect(("127.0.0.1", 3349)).unwrap(),
+    ];
+    let started = Instant::now();
+    assert!(http_get("/health").starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(started.elapsed() < Duration::from_secs(1));
+}